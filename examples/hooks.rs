@@ -0,0 +1,55 @@
+//! Scans the first thousand ports of localhost, printing what the scan
+//! finds through the hooks of the scanner rather than at the end.
+//!
+//! ```sh
+//! cargo run --example hooks
+//! ```
+use async_std::task::block_on;
+use std::{net::IpAddr, time::Duration};
+
+use rustscan::input::{PortRange, ScanOrder};
+use rustscan::port_strategy::PortStrategy;
+use rustscan::scanner::{Hooks, Scanner};
+
+fn main() {
+    let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+    let range = PortRange {
+        ranges: vec![(1, 1000)],
+    };
+    let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Serial);
+    let hooks = Hooks::new()
+        .on_open(|socket, latency| {
+            println!("{socket} is open, answered in {latency:?}");
+            Ok(())
+        })
+        .on_host_complete(|host| {
+            println!(
+                "{} is done, {} of {} ports open",
+                host.ip,
+                host.open.len(),
+                host.sockets
+            );
+            Ok(())
+        })
+        .on_progress(|stats| {
+            println!("{}/{} sockets probed", stats.settled, stats.sockets);
+            Ok(())
+        });
+    let scanner = Scanner::new(
+        &addrs,
+        500,
+        Duration::from_millis(200),
+        1,
+        true,
+        strategy,
+        true,
+        vec![],
+        false,
+    )
+    .with_hooks(hooks);
+
+    let outcome = block_on(scanner.scan());
+    for failure in outcome.hook_failures {
+        eprintln!("{} failed: {}", failure.hook, failure.error);
+    }
+}
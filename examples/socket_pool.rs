@@ -0,0 +1,61 @@
+//! Compares the probes per second of a full range scan of localhost with the
+//! sockets created by each probe and with them taken from the socket pool.
+//! The pool stays behind `--preallocate-sockets` on every platform until
+//! these numbers say it pays off there, Windows included.
+//!
+//! ```sh
+//! cargo run --release --example socket_pool [batch size] [rounds]
+//! ```
+use async_std::task::block_on;
+use std::env;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use rustscan::input::{PortRange, ScanOrder};
+use rustscan::port_strategy::PortStrategy;
+use rustscan::scanner::Scanner;
+
+const PORTS: u16 = 65535;
+
+fn probes_per_second(batch_size: u16, pooled: bool) -> f64 {
+    let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+    let range = PortRange {
+        ranges: vec![(1, PORTS)],
+    };
+    let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Serial);
+    let scanner = Scanner::new(
+        &addrs,
+        batch_size,
+        Duration::from_millis(1000),
+        1,
+        true,
+        strategy,
+        true,
+        vec![],
+        false,
+    );
+    let scanner = if pooled {
+        scanner.with_socket_pool()
+    } else {
+        scanner
+    };
+
+    let started = Instant::now();
+    block_on(scanner.run());
+    f64::from(PORTS) / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let batch_size = args.next().map_or(1000, |arg| arg.parse().unwrap());
+    let rounds: usize = args.next().map_or(3, |arg| arg.parse().unwrap());
+
+    for round in 1..=rounds {
+        let plain = probes_per_second(batch_size, false);
+        let pooled = probes_per_second(batch_size, true);
+        println!(
+            "round {round}: {plain:.0} probes/s, {pooled:.0} probes/s pooled ({:+.1}%)",
+            (pooled / plain - 1.0) * 100.0
+        );
+    }
+}
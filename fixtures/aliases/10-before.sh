@@ -0,0 +1,7 @@
+#!/bin/bash
+#tags = ["aliases"]
+#phase = "pre"
+#call_format = "bash {{script}} {{ip}} {{hostname}}"
+
+# Logs the host and its canonical name to $ALIASES_LOG.
+echo "pre $1 $2" >> "$ALIASES_LOG"
@@ -0,0 +1,6 @@
+#!/bin/bash
+#tags = ["aliases"]
+#call_format = "bash {{script}} {{ip}} {{hostname}}"
+
+# Logs the host and its canonical name to $ALIASES_LOG.
+echo "post $1 $2" >> "$ALIASES_LOG"
@@ -0,0 +1,12 @@
+#!/bin/bash
+#tags = ["pipeline"]
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# Logs to $PIPELINE_DIR/seen.log how many scripts run with it, itself
+# included, then sleeps for a while.
+mkdir -p "$PIPELINE_DIR/running"
+touch "$PIPELINE_DIR/running/$$"
+echo "$1 $(ls "$PIPELINE_DIR/running" | wc -l)" >> "$PIPELINE_DIR/seen.log"
+sleep 0.3
+rm "$PIPELINE_DIR/running/$$"
+echo "scripted $1 $2"
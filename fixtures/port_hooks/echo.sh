@@ -0,0 +1,6 @@
+#!/bin/sh
+# Echoes its arguments into a file of the directory $1 named after the
+# port $3 and prints the path of the file.
+out="$1/$2-$3.txt"
+echo "$@" > "$out"
+echo "$out"
@@ -0,0 +1,9 @@
+#!/bin/bash
+#tags = ["pre_scan"]
+#phase = "pre"
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# Logs the host and its planned port count to $PRE_SCAN_LOG, then exits
+# with $AUDIT_EXIT.
+echo "pre audit $1 $2" >> "$PRE_SCAN_LOG"
+exit "${AUDIT_EXIT:-0}"
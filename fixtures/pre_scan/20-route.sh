@@ -0,0 +1,10 @@
+#!/bin/bash
+#tags = ["pre_scan"]
+#phase = "pre"
+#on_fail = "skip"
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# Logs the host and its planned port count to $PRE_SCAN_LOG, then exits
+# with $ROUTE_EXIT.
+echo "pre route $1 $2" >> "$PRE_SCAN_LOG"
+exit "${ROUTE_EXIT:-0}"
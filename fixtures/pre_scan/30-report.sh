@@ -0,0 +1,6 @@
+#!/bin/bash
+#tags = ["pre_scan"]
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# Logs the host and its open ports to $PRE_SCAN_LOG.
+echo "post report $1 $2" >> "$PRE_SCAN_LOG"
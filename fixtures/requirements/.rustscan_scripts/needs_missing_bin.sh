@@ -0,0 +1,7 @@
+#!/bin/bash
+#tags = ["core_approved", "example",]
+#developer = [ "example", "https://example.org" ]
+#requires_bins = ["python3", "rustscan-missing-binary"]
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+echo $@
@@ -0,0 +1,7 @@
+#!/bin/bash
+#tags = ["core_approved", "example",]
+#developer = [ "example", "https://example.org" ]
+#min_version = "99.0.0"
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+echo $@
@@ -0,0 +1,8 @@
+#!/bin/bash
+#tags = ["core_approved", "example",]
+#developer = [ "example", "https://example.org" ]
+#requires_bins = ["python3"]
+#min_version = "2.0.0"
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+echo $@
@@ -0,0 +1,15 @@
+#!/bin/bash
+#tags = ["retry"]
+#call_format = "bash {{script}} {{ip}}"
+#retries = 2
+#retry_delay = 10
+#no_retry_codes = [3]
+
+# Counts its runs in the file given as $1 and fails with the exit code $2
+# until it ran more than $3 times.
+runs=$(( $(cat "$1" 2>/dev/null || echo 0) + 1 ))
+echo $runs > "$1"
+echo "run $runs"
+if [ "$runs" -le "$3" ]; then
+    exit "$2"
+fi
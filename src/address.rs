@@ -1,7 +1,9 @@
 //! Provides functions to parse input IP addresses, CIDRs or files.
-use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{prelude::*, BufReader};
+use std::io::{self, prelude::*, BufReader};
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::path::Path;
 use std::str::FromStr;
@@ -9,12 +11,625 @@ use std::str::FromStr;
 use cidr_utils::cidr::IpCidr;
 use hickory_resolver::{
     config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
     Resolver,
 };
 use log::debug;
+use serde::{Serialize, Serializer};
 
-use crate::input::Opts;
-use crate::warning;
+use crate::errors::ErrorCode;
+use crate::family::DualStackHost;
+use crate::input::{
+    column, parse_range, FamilyMode, InputError, Opts, PortRange, MAX_HOSTNAME_LEN,
+};
+use crate::previous::PreviousResults;
+use crate::scope::Scope;
+use crate::{verbose, warning};
+
+/// The most addresses an IPv6 CIDR or range may hold without
+/// `--max-ipv6-hosts`, the 4096 of a /116.
+pub const DEFAULT_MAX_IPV6_HOSTS: u64 = 4_096;
+
+/// The most addresses an IPv4 CIDR or range may hold without
+/// `--max-ipv4-hosts`, the 2^32 of a /0: IPv4 targets are only capped on
+/// demand.
+pub const DEFAULT_MAX_IPV4_HOSTS: u64 = 1 << 32;
+
+/// The most addresses a CIDR or range of each family may hold.
+#[derive(Debug, Clone, Copy)]
+struct HostLimits {
+    ipv4: u64,
+    ipv6: u64,
+}
+
+impl HostLimits {
+    const DEFAULT: Self = Self {
+        ipv4: DEFAULT_MAX_IPV4_HOSTS,
+        ipv6: DEFAULT_MAX_IPV6_HOSTS,
+    };
+
+    fn of(input: &Opts) -> Self {
+        Self {
+            ipv4: input.max_ipv4_hosts,
+            ipv6: input.max_ipv6_hosts,
+        }
+    }
+}
+
+/// Resolves hostnames into IP addresses.
+///
+/// The hickory [`Resolver`] implementation first asks the system resolver
+/// and only uses itself as a backup. Tests implement this with stubs.
+pub trait HostResolver {
+    /// Returns every address `host` resolved to, in resolver order.
+    fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError>;
+
+    /// Returns the addresses of `host` scanned with [`FamilyMode::System`],
+    /// the first one it resolved to.
+    fn resolve_system(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+        let mut ips = self.resolve(host)?;
+        ips.truncate(1);
+        Ok(ips)
+    }
+}
+
+impl HostResolver for Resolver {
+    fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+        match format!("{}:{}", &host, 80).to_socket_addrs() {
+            Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+            Err(_) => resolve_ips_from_host(host, self),
+        }
+    }
+
+    /// The first address of the system resolver, or every address of the
+    /// backup one.
+    fn resolve_system(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+        match format!("{}:{}", &host, 80).to_socket_addrs() {
+            Ok(mut addrs) => Ok(addrs.next().map(|addr| addr.ip()).into_iter().collect()),
+            Err(_) => resolve_ips_from_host(host, self),
+        }
+    }
+}
+
+/// Why a hostname didn't resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResolutionError {
+    /// The name doesn't exist, or has no address.
+    NotFound,
+    /// The resolver didn't answer in time.
+    Timeout,
+    /// Anything else, like an unreachable resolver.
+    Failed(String),
+}
+
+impl From<ResolveError> for ResolutionError {
+    fn from(error: ResolveError) -> Self {
+        match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => ResolutionError::NotFound,
+            ResolveErrorKind::Timeout => ResolutionError::Timeout,
+            _ => ResolutionError::Failed(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ResolutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolutionError::NotFound => f.write_str("no such host"),
+            ResolutionError::Timeout => f.write_str("the resolver timed out"),
+            ResolutionError::Failed(error) => f.write_str(error),
+        }
+    }
+}
+
+impl Serialize for ResolutionError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// An input hostname which could not be resolved, and so isn't scanned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unresolved {
+    pub name: String,
+    /// The targets file the name was read from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub error: ResolutionError,
+}
+
+/// A single address to scan, with the hostnames which resolved to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub ip: IpAddr,
+    pub hostnames: Vec<String>,
+    /// Every input token (CIDR, hostname or IP address) which expanded to
+    /// this address, in input order.
+    pub sources: Vec<String>,
+    /// The ports given to this address with `host=ports`, scanned instead of
+    /// the global ones. Overrides of the same address are merged and win over
+    /// the inputs which gave it without ports.
+    pub ports: Option<PortRange>,
+}
+
+/// A step of the filtering of the targets, see [`Targets::stages`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// The target tokens and the lines of targets files, the malformed ones
+    /// removed.
+    Parsed,
+    /// The addresses the targets expanded to, the hostnames which didn't
+    /// resolve removed.
+    Resolved,
+    /// The addresses without the ones given more than once.
+    Deduped,
+    /// With the hosts of `--import`, the ones without open ports removed with
+    /// `--import-ports-only`.
+    Imported,
+    /// The targets `--only-previously-open` found open ports on.
+    PreviouslyOpen,
+    /// The targets `--rescan-filtered` found filtered ports on.
+    PreviouslyFiltered,
+    /// The targets inside the `--scope` allowlist.
+    InScope,
+}
+
+impl Stage {
+    /// What the targets the stage removed are.
+    fn removed(self) -> &'static str {
+        match self {
+            Stage::Parsed => "invalid",
+            Stage::Resolved => "unresolved",
+            Stage::Deduped => "duplicates",
+            Stage::Imported => "without open ports",
+            Stage::PreviouslyOpen => "not previously open",
+            Stage::PreviouslyFiltered => "without filtered ports",
+            Stage::InScope => "out of scope",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Parsed => "parsed",
+            Stage::Resolved => "resolved",
+            Stage::Deduped => "deduped",
+            Stage::Imported => "imported",
+            Stage::PreviouslyOpen => "previously open",
+            Stage::PreviouslyFiltered => "previously filtered",
+            Stage::InScope => "in scope",
+        })
+    }
+}
+
+/// How many targets a stage of the filtering removed, and left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StageCount {
+    pub stage: Stage,
+    pub removed: usize,
+    pub left: usize,
+}
+
+impl fmt::Display for StageCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.left, self.stage)?;
+        if self.removed > 0 {
+            write!(f, " ({} {})", self.removed, self.stage.removed())?;
+        }
+        Ok(())
+    }
+}
+
+/// The addresses parsed out of the user input.
+#[derive(Debug, Default)]
+pub struct Targets {
+    /// Addresses to scan without duplicates, in the order they were first seen.
+    pub hosts: Vec<Target>,
+    /// Dual-stack hostnames whose scanned family is still to be decided
+    /// by racing both families, see [`FamilyMode::Auto`].
+    pub dual_stack: Vec<DualStackHost>,
+    /// How many addresses were dropped because they were already a target.
+    pub duplicates: usize,
+    /// The hostnames which could not be resolved, in input order.
+    pub unresolved: Vec<Unresolved>,
+    /// How many targets every stage of the filtering removed, in order.
+    pub stages: Vec<StageCount>,
+    index: HashMap<IpAddr, usize>,
+    /// The port overrides of the input tokens, applied to every address the
+    /// token expands to.
+    source_ports: HashMap<String, PortRange>,
+}
+
+impl Targets {
+    /// How many targets are left, counting the dual-stack hostnames once.
+    pub fn len(&self) -> usize {
+        self.hosts.len() + self.dual_stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Records that `stage` removed `removed` targets, leaving the current
+    /// ones.
+    pub fn record(&mut self, stage: Stage, removed: usize) {
+        self.stages.push(StageCount {
+            stage,
+            removed,
+            left: self.len(),
+        });
+    }
+
+    /// Returns the addresses to scan.
+    pub fn ips(&self) -> Vec<IpAddr> {
+        self.hosts.iter().map(|target| target.ip).collect()
+    }
+
+    /// Returns the addresses which were given their own ports.
+    pub fn port_overrides(&self) -> HashMap<IpAddr, PortRange> {
+        self.hosts
+            .iter()
+            .filter_map(|target| Some((target.ip, target.ports.clone()?)))
+            .collect()
+    }
+
+    /// Adds an address expanded from the input token `source`, merging
+    /// `source`, its port override and `hostname` into the existing target
+    /// when the address is already known.
+    pub fn insert(&mut self, ip: IpAddr, source: &str, hostname: Option<&str>) {
+        let position = match self.index.get(&ip) {
+            Some(&position) => {
+                self.duplicates += 1;
+                position
+            }
+            None => {
+                self.index.insert(ip, self.hosts.len());
+                self.hosts.push(Target {
+                    ip,
+                    hostnames: Vec::new(),
+                    sources: Vec::new(),
+                    ports: None,
+                });
+                self.hosts.len() - 1
+            }
+        };
+
+        let sources = &mut self.hosts[position].sources;
+        if !sources.iter().any(|known| known == source) {
+            sources.push(source.to_owned());
+        }
+
+        if let Some(range) = self.source_ports.get(source) {
+            let ports = self.hosts[position]
+                .ports
+                .get_or_insert_with(|| PortRange { ranges: Vec::new() });
+            merge_ranges(ports, range);
+        }
+
+        if let Some(hostname) = hostname {
+            let hostnames = &mut self.hosts[position].hostnames;
+            if !hostnames.iter().any(|name| name == hostname) {
+                hostnames.push(hostname.to_owned());
+            }
+        }
+    }
+
+    /// Like [`Targets::insert`], giving the address `ports` on top of the
+    /// ports it already has.
+    pub fn insert_with_ports(&mut self, ip: IpAddr, source: &str, ports: &PortRange) {
+        self.insert(ip, source, None);
+        let target = &mut self.hosts[self.index[&ip]];
+        let known = target
+            .ports
+            .get_or_insert_with(|| PortRange { ranges: Vec::new() });
+        merge_ranges(known, ports);
+    }
+
+    /// Narrows every target down to the ports `previous` found open, in
+    /// place of its own ports. Dual-stack hostnames are scanned on the
+    /// addresses `previous` knows of. With `add_missing`, the hosts of
+    /// `previous` which aren't a target yet are added under `source`.
+    ///
+    /// Returns the targets which had no open ports in `previous` and got
+    /// dropped.
+    pub fn only_previously_open(
+        &mut self,
+        previous: &PreviousResults,
+        source: &str,
+        add_missing: bool,
+    ) -> Vec<String> {
+        let ports = |ip| previous.open_ports(ip);
+        let missing = if add_missing {
+            previous.ips()
+        } else {
+            Vec::new()
+        };
+        self.only_previous(ports, missing, source, Stage::PreviouslyOpen)
+    }
+
+    /// Like [`Targets::only_previously_open`], with the ports `previous`
+    /// found filtered.
+    pub fn only_previously_filtered(
+        &mut self,
+        previous: &PreviousResults,
+        source: &str,
+        add_missing: bool,
+    ) -> Vec<String> {
+        let ports = |ip| previous.filtered_ports(ip);
+        let missing = if add_missing {
+            previous.filtered_ips()
+        } else {
+            Vec::new()
+        };
+        self.only_previous(ports, missing, source, Stage::PreviouslyFiltered)
+    }
+
+    /// Narrows every target down to its `ports`, dropping the ones without,
+    /// and adds the `missing` hosts under `source`.
+    fn only_previous(
+        &mut self,
+        ports: impl Fn(IpAddr) -> Option<Vec<u16>>,
+        missing: Vec<IpAddr>,
+        source: &str,
+        stage: Stage,
+    ) -> Vec<String> {
+        let mut dropped = Vec::new();
+        for host in std::mem::take(&mut self.dual_stack) {
+            let known: Vec<IpAddr> = [host.ipv4, host.ipv6]
+                .iter()
+                .copied()
+                .filter(|ip| ports(*ip).is_some())
+                .collect();
+            if known.is_empty() {
+                dropped.push(host.hostname.clone());
+            }
+            for ip in known {
+                self.insert(ip, &host.hostname, Some(&host.hostname));
+            }
+        }
+
+        self.hosts.retain(|target| {
+            let known = ports(target.ip).is_some();
+            if !known {
+                dropped.push(target.ip.to_string());
+            }
+            known
+        });
+        self.reindex();
+
+        for ip in missing {
+            if !self.index.contains_key(&ip) {
+                self.insert(ip, source, None);
+            }
+        }
+
+        for target in &mut self.hosts {
+            let ports = ports(target.ip).unwrap_or_default();
+            target.ports = Some(PortRange {
+                ranges: ports.into_iter().map(|port| (port, port)).collect(),
+            });
+        }
+        self.record(stage, dropped.len());
+        dropped
+    }
+
+    /// Drops the targets outside `scope`. Either family of a dual-stack
+    /// hostname may be dropped on its own, the other one is then scanned
+    /// without racing.
+    ///
+    /// Returns the dropped targets, with the sources they came from.
+    pub fn retain_in_scope(&mut self, scope: &Scope) -> Vec<Target> {
+        let mut dropped = Vec::new();
+        for host in std::mem::take(&mut self.dual_stack) {
+            let hostnames = [host.hostname.clone()];
+            let allowed = |ip: IpAddr| scope.allows(ip, &hostnames);
+            match (allowed(host.ipv4), allowed(host.ipv6)) {
+                (true, true) => self.dual_stack.push(host),
+                (ipv4, ipv6) => {
+                    for (ip, allowed) in [(host.ipv4, ipv4), (host.ipv6, ipv6)].iter().copied() {
+                        if allowed {
+                            self.insert(ip, &host.hostname, Some(&host.hostname));
+                        } else {
+                            dropped.push(Target {
+                                ip,
+                                hostnames: hostnames.to_vec(),
+                                sources: hostnames.to_vec(),
+                                ports: self.source_ports.get(&host.hostname).cloned(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let (kept, out_of_scope) = std::mem::take(&mut self.hosts)
+            .into_iter()
+            .partition(|target| scope.allows(target.ip, &target.hostnames));
+        self.hosts = kept;
+        self.reindex();
+        dropped.extend::<Vec<Target>>(out_of_scope);
+        self.record(Stage::InScope, dropped.len());
+        dropped
+    }
+
+    fn reindex(&mut self) {
+        self.index = self
+            .hosts
+            .iter()
+            .enumerate()
+            .map(|(position, target)| (target.ip, position))
+            .collect();
+    }
+
+    /// Records that `name`, from the targets `file` if any, didn't resolve.
+    fn add_unresolved(
+        &mut self,
+        name: &str,
+        file: Option<&Path>,
+        error: ResolutionError,
+        input: &Opts,
+    ) {
+        let message = match file {
+            Some(file) => format!("Host {name:?} in {file:?} could not be resolved: {error}."),
+            None => format!("Host {name:?} could not be resolved: {error}."),
+        };
+        warning!(
+            ErrorCode::UnresolvedHost,
+            message,
+            input.greppable,
+            input.accessible,
+            host = name
+        );
+        self.unresolved.push(Unresolved {
+            name: name.to_owned(),
+            file: file.map(|file| file.display().to_string()),
+            error,
+        });
+    }
+
+    fn add(
+        &mut self,
+        address: &str,
+        ports: Option<PortRange>,
+        resolved: Resolved,
+        mode: FamilyMode,
+    ) {
+        if let Some(range) = ports {
+            let known = self
+                .source_ports
+                .entry(address.to_owned())
+                .or_insert_with(|| PortRange { ranges: Vec::new() });
+            merge_ranges(known, &range);
+        }
+
+        match resolved {
+            Resolved::Literal(ips) => {
+                for ip in ips {
+                    self.insert(ip, address, None);
+                }
+            }
+            Resolved::Failed(_) => {}
+            Resolved::Host(ips) => {
+                let ipv4 = ips.iter().find(|ip| ip.is_ipv4()).copied();
+                let ipv6 = ips.iter().find(|ip| ip.is_ipv6()).copied();
+
+                let selected = match (mode, ipv4, ipv6) {
+                    (FamilyMode::Auto, Some(ipv4), Some(ipv6)) => {
+                        self.dual_stack.push(DualStackHost {
+                            hostname: address.to_owned(),
+                            ipv4,
+                            ipv6,
+                        });
+                        vec![]
+                    }
+                    (FamilyMode::Auto | FamilyMode::Both, _, _) => {
+                        ipv4.into_iter().chain(ipv6).collect()
+                    }
+                    (FamilyMode::Ipv4, _, _) => ipv4.into_iter().collect(),
+                    (FamilyMode::Ipv6, _, _) => ipv6.into_iter().collect(),
+                    (FamilyMode::System, _, _) => ips,
+                };
+
+                for ip in selected {
+                    self.insert(ip, address, Some(address));
+                }
+            }
+        }
+    }
+}
+
+/// The addresses from `first` to `last`, of a single family, yielded one
+/// at a time: a CIDR or range is never expanded before its addresses are
+/// added to the targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HostRange {
+    next: u128,
+    last: u128,
+    ipv6: bool,
+    /// Whether `last` was yielded already.
+    done: bool,
+}
+
+impl HostRange {
+    fn new(first: IpAddr, last: IpAddr) -> Self {
+        let (first, last, ipv6) = match (first, last) {
+            (IpAddr::V4(first), IpAddr::V4(last)) => (
+                u128::from(u32::from(first)),
+                u128::from(u32::from(last)),
+                false,
+            ),
+            (IpAddr::V6(first), IpAddr::V6(last)) => (u128::from(first), u128::from(last), true),
+            _ => unreachable!("Ranges are checked to be of a single family."),
+        };
+        Self {
+            next: first,
+            last,
+            ipv6,
+            done: first > last,
+        }
+    }
+
+    fn of(cidr: &IpCidr) -> Self {
+        Self::new(cidr.first_address(), cidr.last_address())
+    }
+}
+
+impl Iterator for HostRange {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        if self.done {
+            return None;
+        }
+        let ip = self.next;
+        if ip == self.last {
+            self.done = true;
+        } else {
+            self.next += 1;
+        }
+        Some(if self.ipv6 {
+            IpAddr::V6(ip.into())
+        } else {
+            IpAddr::V4(
+                u32::try_from(ip)
+                    .expect("IPv4 ranges fit in 32 bits.")
+                    .into(),
+            )
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            return (0, Some(0));
+        }
+        // None when there are more than fit in a usize, like in a /64.
+        let left = (self.last - self.next)
+            .checked_add(1)
+            .and_then(|left| usize::try_from(left).ok());
+        (left.unwrap_or(usize::MAX), left)
+    }
+}
+
+/// Adds the ranges of `other` missing from `ports`.
+fn merge_ranges(ports: &mut PortRange, other: &PortRange) {
+    for range in &other.ranges {
+        if !ports.ranges.contains(range) {
+            ports.ranges.push(*range);
+        }
+    }
+}
+
+/// What an input address turned into.
+enum Resolved {
+    /// An IP address, CIDR or range, scanned as given.
+    Literal(HostRange),
+    /// Every address a hostname resolved to, in resolver order.
+    Host(Vec<IpAddr>),
+    /// A hostname which didn't resolve.
+    Failed(ResolutionError),
+}
 
 /// Parses the string(s) into IP addresses.
 ///
@@ -31,50 +646,234 @@ use crate::warning;
 ///
 /// Finally, any duplicates are removed to avoid excessive scans.
 pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
-    let mut unresolved_addresses: Vec<&str> = Vec::new();
-    let backup_resolver = get_resolver(&input.resolver);
-
-    for address in &input.addresses {
-        let parsed_ips = parse_address(address, &backup_resolver);
-        if !parsed_ips.is_empty() {
-            ips.extend(parsed_ips);
-        } else {
-            unresolved_addresses.push(address);
+    parse_targets(input).ips()
+}
+
+/// Parses the string(s) into [`Targets`], applying the family mode of the
+/// input to every hostname.
+pub fn parse_targets(input: &Opts) -> Targets {
+    parse_targets_with_resolver(input, &get_resolver(&input.resolver))
+}
+
+/// Same as [`parse_targets`], resolving hostnames with `resolver`.
+pub fn parse_targets_with_resolver(input: &Opts, resolver: &dyn HostResolver) -> Targets {
+    let mut targets = Targets::default();
+    let mut unresolved_addresses: Vec<(&str, ResolutionError)> = Vec::new();
+    let mode = input.family_mode();
+    let limits = HostLimits::of(input);
+    // The targets parsed, and the malformed ones.
+    let (mut parsed, mut invalid) = (0, 0);
+
+    for token in input
+        .addresses
+        .iter()
+        .flat_map(|entry| split_targets(entry))
+    {
+        let (address, ports) = match parse_target(token) {
+            Ok(target) => target,
+            Err(e) => {
+                warning!(
+                    ErrorCode::InvalidTarget,
+                    format!("Invalid target: {e}"),
+                    input.greppable,
+                    input.accessible,
+                    host = token
+                );
+                invalid += 1;
+                continue;
+            }
+        };
+
+        let resolved = match resolve_address(address, resolver, mode, limits) {
+            Ok(resolved) => resolved,
+            // A targets file can have a path which is no address or hostname.
+            Err(_) if ports.is_none() && Path::new(address).is_file() => {
+                unresolved_addresses.push((address, ResolutionError::NotFound));
+                continue;
+            }
+            Err(e) => {
+                warning!(
+                    ErrorCode::InvalidTarget,
+                    format!("Invalid target: {e}"),
+                    input.greppable,
+                    input.accessible,
+                    host = token
+                );
+                invalid += 1;
+                continue;
+            }
+        };
+        match resolved {
+            Resolved::Failed(error) if ports.is_some() => {
+                parsed += 1;
+                targets.add_unresolved(address, None, error, input);
+            }
+            // Counted once known not to be a targets file.
+            Resolved::Failed(error) => unresolved_addresses.push((address, error)),
+            resolved => {
+                parsed += 1;
+                targets.add(address, ports, resolved, mode);
+            }
         }
     }
 
     // If we got to this point this can only be a file path or the wrong input.
-    for file_path in unresolved_addresses {
-        let file_path = Path::new(file_path);
+    for (address, error) in unresolved_addresses {
+        let file_path = Path::new(address);
 
         if !file_path.is_file() {
-            warning!(
-                format!("Host {file_path:?} could not be resolved."),
-                input.greppable,
-                input.accessible
-            );
-
+            parsed += 1;
+            targets.add_unresolved(address, None, error, input);
             continue;
         }
 
-        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver) {
-            ips.extend(x);
-        } else {
-            warning!(
-                format!("Host {file_path:?} could not be resolved."),
-                input.greppable,
-                input.accessible
-            );
+        let lines = match TargetLines::open(file_path, input.max_line_length) {
+            Ok(lines) => lines,
+            Err(e) => {
+                parsed += 1;
+                let error = ResolutionError::Failed(e.to_string());
+                targets.add_unresolved(address, None, error, input);
+                continue;
+            }
+        };
+        for (number, line) in lines {
+            let line = match line {
+                // Blank lines and comments, like the header of a checkpoint.
+                Ok(line) if line.trim().is_empty() || line.trim_start().starts_with('#') => {
+                    continue
+                }
+                Ok(line) => line,
+                Err(e) => {
+                    invalid += 1;
+                    warning!(
+                        ErrorCode::InvalidTarget,
+                        format!("Invalid target at line {number} of {file_path:?}: {e}"),
+                        input.greppable,
+                        input.accessible
+                    );
+                    continue;
+                }
+            };
+            let line_target = parse_target_line(&line).and_then(|(address, ports)| {
+                let resolved = resolve_address(address, resolver, mode, limits)?;
+                Ok((address, ports, resolved))
+            });
+            match line_target {
+                Ok((address, _, Resolved::Failed(error))) => {
+                    parsed += 1;
+                    targets.add_unresolved(address, Some(file_path), error, input);
+                }
+                Ok((address, ports, resolved)) => {
+                    parsed += 1;
+                    targets.add(address, ports, resolved, mode);
+                }
+                Err(e) => {
+                    invalid += 1;
+                    warning!(
+                        ErrorCode::InvalidTarget,
+                        format!("Invalid target at line {number} of {file_path:?}: {e}"),
+                        input.greppable,
+                        input.accessible,
+                        host = line.trim()
+                    );
+                }
+            }
         }
     }
 
-    ips.into_iter()
-        .collect::<BTreeSet<_>>()
-        .into_iter()
+    let resolved = targets.len() + targets.duplicates;
+    targets.stages = vec![
+        StageCount {
+            stage: Stage::Parsed,
+            removed: invalid,
+            left: parsed,
+        },
+        StageCount {
+            stage: Stage::Resolved,
+            removed: targets.unresolved.len(),
+            left: resolved,
+        },
+    ];
+    let duplicates = targets.duplicates;
+    targets.record(Stage::Deduped, duplicates);
+
+    if targets.duplicates > 0 {
+        verbose!(
+            format!(
+                "Collapsed {} duplicate target address(es)",
+                targets.duplicates
+            ),
+            input.greppable,
+            input.accessible
+        );
+    }
+
+    targets
+}
+
+/// Splits an address list into target tokens.
+///
+/// Targets are separated by `;`. A segment without a port override can hold
+/// several targets separated by `,`, the commas of a `host=ports` segment
+/// belong to its ports.
+pub(crate) fn split_targets(list: &str) -> Vec<&str> {
+    list.split(';')
+        .flat_map(|segment| {
+            if segment.contains('=') {
+                vec![segment]
+            } else {
+                segment.split(',').collect()
+            }
+        })
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
         .collect()
 }
 
+/// Parses a target token, either a bare address or `host=ports`.
+fn parse_target(token: &str) -> Result<(&str, Option<PortRange>), InputError> {
+    match token.split_once('=') {
+        None => Ok((token, None)),
+        Some((host, ports)) => {
+            let host = host.trim();
+            if host.is_empty() {
+                return Err(InputError::MissingHost {
+                    input: token.to_owned(),
+                });
+            }
+            let ports = ports.trim();
+            let range = parse_range(ports).map_err(|e| e.within(token, offset_of(token, ports)))?;
+            Ok((host, Some(range)))
+        }
+    }
+}
+
+/// Parses a line of a targets file, either a target token or `host ports`.
+fn parse_target_line(line: &str) -> Result<(&str, Option<PortRange>), InputError> {
+    let line = line.trim();
+    if line.contains('=') {
+        return parse_target(line);
+    }
+
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(host), None, _) => Ok((host, None)),
+        (Some(host), Some(ports), None) => {
+            let range = parse_range(ports).map_err(|e| e.within(line, offset_of(line, ports)))?;
+            Ok((host, Some(range)))
+        }
+        (_, _, extra) => Err(InputError::TooManyFields {
+            input: line.to_owned(),
+            position: column(line, extra.map_or(0, |extra| offset_of(line, extra))),
+        }),
+    }
+}
+
+/// The byte offset of `part` in `input`, which it's a slice of.
+fn offset_of(input: &str, part: &str) -> usize {
+    part.as_ptr() as usize - input.as_ptr() as usize
+}
+
 /// Given a string, parse it as a host, IP address, or CIDR.
 ///
 /// This allows us to pass files as hosts or cidr or IPs easily
@@ -89,31 +888,162 @@ pub fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
 /// let ips = parse_address("127.0.0.1", &Resolver::default().unwrap());
 /// ```
 pub fn parse_address(address: &str, resolver: &Resolver) -> Vec<IpAddr> {
-    IpCidr::from_str(address)
-        .map(|cidr| cidr.iter().map(|c| c.address()).collect())
-        .ok()
-        .or_else(|| {
-            format!("{}:{}", &address, 80)
-                .to_socket_addrs()
-                .ok()
-                .map(|mut iter| vec![iter.next().unwrap().ip()])
-        })
-        .unwrap_or_else(|| resolve_ips_from_host(address, resolver))
+    let mut targets = Targets::default();
+    if let Ok(resolved) =
+        resolve_address(address, resolver, FamilyMode::System, HostLimits::DEFAULT)
+    {
+        targets.add(address, None, resolved, FamilyMode::System);
+    }
+    targets.ips()
 }
 
-/// Uses DNS to get the IPS associated with host
-fn resolve_ips_from_host(source: &str, backup_resolver: &Resolver) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
+/// Resolves an IP address, CIDR, address range or hostname, the hostname
+/// with the family `mode`. CIDRs and ranges holding more addresses than the
+/// `limits` of their family are refused before anything gets expanded.
+fn resolve_address(
+    address: &str,
+    resolver: &dyn HostResolver,
+    mode: FamilyMode,
+    limits: HostLimits,
+) -> Result<Resolved, InputError> {
+    if let Some(range) = parse_ip_range(address) {
+        let (first, last) = range?;
+        return expand_range(address, first, last, limits).map(Resolved::Literal);
+    }
 
-    if let Ok(addrs) = source.to_socket_addrs() {
-        for ip in addrs {
-            ips.push(ip.ip());
+    if let Ok(cidr) = IpCidr::from_str(address) {
+        if cidr.is_ipv6() {
+            let host_bits = 128 - u32::from(cidr.network_length());
+            check_ipv6_size(address, 1u128.checked_shl(host_bits), limits.ipv6)?;
+        } else {
+            let host_bits = 32 - u32::from(cidr.network_length());
+            check_ipv4_size(address, 1 << host_bits, limits.ipv4)?;
+        }
+        return Ok(Resolved::Literal(HostRange::of(&cidr)));
+    }
+    // An address with a slash can only be a CIDR, a bad one.
+    if let Some((ip, prefix)) = address.split_once('/') {
+        if let Ok(parsed) = IpAddr::from_str(ip) {
+            let max_prefix = if parsed.is_ipv4() { 32 } else { 128 };
+            return Err(match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= max_prefix => InputError::HostBitsSet {
+                    input: address.to_owned(),
+                    network: format!("{}/{prefix}", network(parsed, prefix)),
+                },
+                _ => InputError::InvalidCidr {
+                    input: address.to_owned(),
+                    position: column(address, ip.len() + 1),
+                    max_prefix,
+                },
+            });
+        }
+    }
+    if address.trim_end_matches('.').len() > MAX_HOSTNAME_LEN {
+        return Err(InputError::HostnameTooLong {
+            input: address.to_owned(),
+        });
+    }
+
+    let resolved = match mode {
+        FamilyMode::System => resolver.resolve_system(address),
+        _ => resolver.resolve(address),
+    };
+    Ok(match resolved {
+        Ok(ips) if ips.is_empty() => Resolved::Failed(ResolutionError::NotFound),
+        Ok(ips) => Resolved::Host(ips),
+        Err(error) => Resolved::Failed(error),
+    })
+}
+
+/// The network of the first `prefix` bits of `ip`.
+fn network(ip: IpAddr, prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(ip) & mask).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(ip) & mask).into())
+        }
+    }
+}
+
+/// Parses a `first-last` address range, None when `address` isn't one.
+fn parse_ip_range(address: &str) -> Option<Result<(IpAddr, IpAddr), InputError>> {
+    let (first, last) = address.split_once('-')?;
+    let first = IpAddr::from_str(first.trim()).ok()?;
+    let last = IpAddr::from_str(last.trim()).ok()?;
+    if first.is_ipv4() != last.is_ipv4() {
+        return Some(Err(InputError::MixedFamilyRange {
+            input: address.to_owned(),
+        }));
+    }
+    if first > last {
+        return Some(Err(InputError::ReversedRange {
+            input: address.to_owned(),
+        }));
+    }
+    Some(Ok((first, last)))
+}
+
+/// The addresses from `first` to `last`, both of the same family, unless
+/// they are more than the `limits` of it.
+fn expand_range(
+    address: &str,
+    first: IpAddr,
+    last: IpAddr,
+    limits: HostLimits,
+) -> Result<HostRange, InputError> {
+    match (first, last) {
+        (IpAddr::V4(first), IpAddr::V4(last)) => {
+            let (first, last) = (u32::from(first), u32::from(last));
+            check_ipv4_size(address, u64::from(last - first) + 1, limits.ipv4)?;
         }
-    } else if let Ok(addrs) = backup_resolver.lookup_ip(source) {
-        ips.extend(addrs.iter());
+        (IpAddr::V6(first), IpAddr::V6(last)) => {
+            let (first, last) = (u128::from(first), u128::from(last));
+            check_ipv6_size(address, (last - first).checked_add(1), limits.ipv6)?;
+        }
+        _ => unreachable!("Ranges are checked to be of a single family."),
     }
+    Ok(HostRange::new(first, last))
+}
 
-    ips
+/// Refuses IPv4 targets holding more than `max` addresses.
+fn check_ipv4_size(address: &str, hosts: u64, max: u64) -> Result<(), InputError> {
+    if hosts <= max {
+        return Ok(());
+    }
+    Err(InputError::TooManyIpv4Hosts {
+        input: address.to_owned(),
+        hosts,
+        max,
+    })
+}
+
+/// Refuses IPv6 targets holding more than `max` addresses, `hosts` being
+/// None when they hold all 2^128 of them.
+fn check_ipv6_size(address: &str, hosts: Option<u128>, max: u64) -> Result<(), InputError> {
+    if hosts.is_some_and(|hosts| hosts <= u128::from(max)) {
+        return Ok(());
+    }
+    Err(InputError::TooManyIpv6Hosts {
+        input: address.to_owned(),
+        hosts,
+        max,
+    })
+}
+
+/// Uses DNS to get the IPS associated with host
+fn resolve_ips_from_host(
+    source: &str,
+    backup_resolver: &Resolver,
+) -> Result<Vec<IpAddr>, ResolutionError> {
+    if let Ok(addrs) = source.to_socket_addrs() {
+        return Ok(addrs.map(|addr| addr.ip()).collect());
+    }
+    let addrs = backup_resolver.lookup_ip(source)?;
+    Ok(addrs.iter().collect())
 }
 
 /// Derive a DNS resolver.
@@ -126,7 +1056,7 @@ fn resolve_ips_from_host(source: &str, backup_resolver: &Resolver) -> Vec<IpAddr
 ///       `/etc/resolv.conf` on *nix).
 ///    2. finally, build a CloudFlare-based resolver (default
 ///       behaviour).
-fn get_resolver(resolver: &Option<String>) -> Resolver {
+pub(crate) fn get_resolver(resolver: &Option<String>) -> Resolver {
     match resolver {
         Some(r) => {
             let mut config = ResolverConfig::new();
@@ -164,32 +1094,839 @@ fn read_resolver_from_file(path: &str) -> Result<Vec<IpAddr>, std::io::Error> {
     Ok(ips)
 }
 
-#[cfg(not(tarpaulin_include))]
-/// Parses an input file of IPs and uses those
-fn read_ips_from_file(
-    ips: &std::path::Path,
-    backup_resolver: &Resolver,
-) -> Result<Vec<IpAddr>, std::io::Error> {
-    let file = File::open(ips)?;
-    let reader = BufReader::new(file);
+/// The lines of a targets file, read one at a time with their number from
+/// 1. A line over the length limit is skipped without being kept.
+struct TargetLines<R> {
+    reader: R,
+    limit: u64,
+    number: usize,
+}
 
-    let mut ips: Vec<IpAddr> = Vec::new();
+impl TargetLines<BufReader<File>> {
+    fn open(path: &Path, limit: u64) -> io::Result<Self> {
+        Ok(TargetLines::new(BufReader::new(File::open(path)?), limit))
+    }
+}
 
-    for address_line in reader.lines() {
-        if let Ok(address) = address_line {
-            ips.extend(parse_address(&address, backup_resolver));
-        } else {
-            debug!("Line in file is not valid");
+impl<R: BufRead> TargetLines<R> {
+    fn new(reader: R, limit: u64) -> Self {
+        Self {
+            reader,
+            limit,
+            number: 0,
         }
     }
 
-    Ok(ips)
+    /// Reads up to the end of the line the reader is in.
+    fn skip_line(&mut self) -> io::Result<()> {
+        loop {
+            let buffer = self.reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                self.reader.consume(end + 1);
+                return Ok(());
+            }
+            let read = buffer.len();
+            self.reader.consume(read);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TargetLines<R> {
+    type Item = (usize, Result<String, InputError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let read = (&mut self.reader)
+            .take(self.limit + 1)
+            .read_until(b'\n', &mut line);
+        match read {
+            Ok(0) => return None,
+            Ok(_) => self.number += 1,
+            Err(e) => {
+                debug!("Stopped reading the targets file: {}", e);
+                return None;
+            }
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if line.len() as u64 > self.limit {
+            let start = String::from_utf8_lossy(&line[..line.len().min(64)]).into_owned();
+            if let Err(e) = self.skip_line() {
+                debug!("Stopped reading the targets file: {}", e);
+            }
+            return Some((
+                self.number,
+                Err(InputError::LineTooLong {
+                    start,
+                    limit: self.limit,
+                }),
+            ));
+        }
+        Some((
+            self.number,
+            String::from_utf8(line).map_err(|_| InputError::NotUtf8),
+        ))
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
-    use super::{get_resolver, parse_addresses, Opts};
-    use std::net::Ipv4Addr;
+    use super::{
+        get_resolver, parse_addresses, parse_target, parse_target_line,
+        parse_targets_with_resolver, resolve_address, split_targets, HostLimits, HostRange,
+        HostResolver, Opts, ResolutionError, Resolved, Stage, TargetLines, Targets, Unresolved,
+        DEFAULT_MAX_IPV4_HOSTS,
+    };
+    use crate::input::{FamilyMode, InputError, PortRange};
+    use crate::previous::PreviousResults;
+    use crate::scope::Scope;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Resolves hostnames from a fixed table instead of DNS. Hostnames under
+    /// slow. time out, the other ones missing from the table don't exist.
+    struct StubResolver(HashMap<&'static str, Vec<IpAddr>>);
+
+    impl HostResolver for StubResolver {
+        fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+            match self.0.get(host) {
+                Some(ips) => Ok(ips.clone()),
+                None if host.starts_with("slow.") => Err(ResolutionError::Timeout),
+                None => Err(ResolutionError::NotFound),
+            }
+        }
+    }
+
+    fn stub_resolver() -> StubResolver {
+        let mut hosts = HashMap::new();
+        hosts.insert("app.internal", vec!["10.0.0.5".parse().unwrap()]);
+        hosts.insert("db.internal", vec!["10.0.0.5".parse().unwrap()]);
+        hosts.insert("web.internal", vec!["10.0.0.7".parse().unwrap()]);
+        StubResolver(hosts)
+    }
+
+    #[test]
+    fn duplicate_targets_are_collapsed() {
+        let opts = Opts {
+            addresses: vec![
+                "web.internal".to_owned(),
+                "fixtures/duplicate_hosts.txt".to_owned(),
+                "10.0.0.7".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let targets = parse_targets_with_resolver(&opts, &stub_resolver());
+
+        let web: IpAddr = "10.0.0.7".parse().unwrap();
+        let app: IpAddr = "10.0.0.5".parse().unwrap();
+        // web.internal and 10.0.0.7 are read before the file contents.
+        assert_eq!(targets.ips(), [web, app]);
+        assert_eq!(targets.duplicates, 4);
+        assert_eq!(targets.hosts[0].hostnames, ["web.internal"]);
+        assert_eq!(targets.hosts[1].hostnames, ["app.internal", "db.internal"]);
+    }
+
+    #[test]
+    fn unresolved_hosts_are_collected() {
+        let opts = Opts {
+            addresses: vec![
+                "app.internal,missing.internal".to_owned(),
+                "broken.internal=80".to_owned(),
+                "fixtures/unresolved_hosts.txt".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let targets = parse_targets_with_resolver(&opts, &stub_resolver());
+
+        let ips: Vec<IpAddr> = ["10.0.0.5", "10.0.0.7", "10.0.0.9"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        assert_eq!(targets.ips(), ips);
+        let file = Some("fixtures/unresolved_hosts.txt".to_owned());
+        assert_eq!(
+            targets.unresolved,
+            [
+                Unresolved {
+                    name: "broken.internal".to_owned(),
+                    file: None,
+                    error: ResolutionError::NotFound,
+                },
+                Unresolved {
+                    name: "missing.internal".to_owned(),
+                    file: None,
+                    error: ResolutionError::NotFound,
+                },
+                Unresolved {
+                    name: "gone.internal".to_owned(),
+                    file: file.clone(),
+                    error: ResolutionError::NotFound,
+                },
+                Unresolved {
+                    name: "slow.internal".to_owned(),
+                    file,
+                    error: ResolutionError::Timeout,
+                },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&targets.unresolved[3]).unwrap(),
+            serde_json::json!({
+                "name": "slow.internal",
+                "file": "fixtures/unresolved_hosts.txt",
+                "error": "the resolver timed out",
+            })
+        );
+    }
+
+    fn dual_stack_targets(mode: FamilyMode) -> Targets {
+        let ips: Vec<IpAddr> = vec![
+            "2001:db8::1".parse().unwrap(),
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        ];
+        let mut targets = Targets::default();
+        targets.add("dual.example", None, Resolved::Host(ips), mode);
+        targets
+    }
+
+    #[test]
+    fn family_mode_selects_host_addresses() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        // Every address kept by the resolver, in its order.
+        assert_eq!(
+            dual_stack_targets(FamilyMode::System).ips(),
+            [v6, v4, "192.0.2.2".parse().unwrap()]
+        );
+        assert_eq!(dual_stack_targets(FamilyMode::Ipv4).ips(), [v4]);
+        assert_eq!(dual_stack_targets(FamilyMode::Ipv6).ips(), [v6]);
+        assert_eq!(dual_stack_targets(FamilyMode::Both).ips(), [v4, v6]);
+
+        let auto = dual_stack_targets(FamilyMode::Auto);
+        assert!(auto.ips().is_empty());
+        assert_eq!(auto.dual_stack.len(), 1);
+        assert_eq!(auto.dual_stack[0].ipv4, v4);
+        assert_eq!(auto.dual_stack[0].ipv6, v6);
+    }
+
+    #[test]
+    fn system_family_scans_the_first_record() {
+        let mut resolver = stub_resolver();
+        let records = ["10.0.0.8", "10.0.0.9", "2001:db8::9"];
+        let records: Vec<IpAddr> = records.iter().map(|ip| ip.parse().unwrap()).collect();
+        resolver.0.insert("multi.internal", records.clone());
+        let mut opts = Opts {
+            addresses: vec!["multi.internal".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(opts.prefer_family, FamilyMode::System);
+        assert_eq!(
+            parse_targets_with_resolver(&opts, &resolver).ips(),
+            [records[0]]
+        );
+
+        opts.prefer_family = FamilyMode::Both;
+        assert_eq!(
+            parse_targets_with_resolver(&opts, &resolver).ips(),
+            [records[0], records[2]]
+        );
+    }
+
+    #[test]
+    fn overlapping_cidrs_list_every_source() {
+        let opts = Opts {
+            addresses: vec![
+                "10.0.0.0/30".to_owned(),
+                "10.0.0.2/31".to_owned(),
+                "10.0.0.3".to_owned(),
+                "app.internal".to_owned(),
+                "10.0.0.4/31".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let targets = parse_targets_with_resolver(&opts, &stub_resolver());
+        let sources: Vec<(String, Vec<String>)> = targets
+            .hosts
+            .iter()
+            .map(|target| (target.ip.to_string(), target.sources.clone()))
+            .collect();
+
+        let expected = [
+            ("10.0.0.0", vec!["10.0.0.0/30"]),
+            ("10.0.0.1", vec!["10.0.0.0/30"]),
+            ("10.0.0.2", vec!["10.0.0.0/30", "10.0.0.2/31"]),
+            ("10.0.0.3", vec!["10.0.0.0/30", "10.0.0.2/31", "10.0.0.3"]),
+            ("10.0.0.5", vec!["app.internal", "10.0.0.4/31"]),
+            ("10.0.0.4", vec!["10.0.0.4/31"]),
+        ];
+        let expected: Vec<(String, Vec<String>)> = expected
+            .iter()
+            .map(|(ip, sources)| {
+                let sources = sources.iter().map(ToString::to_string).collect();
+                (ip.to_string(), sources)
+            })
+            .collect();
+        assert_eq!(sources, expected);
+    }
+
+    fn ranges(ranges: &[(u16, u16)]) -> Option<PortRange> {
+        Some(PortRange {
+            ranges: ranges.to_vec(),
+        })
+    }
+
+    #[test]
+    fn targets_are_split_around_port_overrides() {
+        assert_eq!(
+            split_targets("web1=80,443; db1=5432,5433 ;10.0.0.0/24,10.0.1.1"),
+            ["web1=80,443", "db1=5432,5433", "10.0.0.0/24", "10.0.1.1"]
+        );
+        assert_eq!(split_targets("127.0.0.1,;"), ["127.0.0.1"]);
+
+        assert_eq!(parse_target("10.0.0.1"), Ok(("10.0.0.1", None)));
+        assert_eq!(
+            parse_target("web1=80,1000-1002"),
+            Ok(("web1", ranges(&[(80, 80), (1000, 1002)])))
+        );
+        assert!(parse_target("web1=80,abc").is_err());
+        assert!(parse_target("web1=").is_err());
+        assert!(parse_target("=80").is_err());
+    }
+
+    #[test]
+    fn port_overrides_only_apply_to_their_target() {
+        let opts = Opts {
+            addresses: vec![
+                "web.internal=80,443;10.0.0.4/31".to_owned(),
+                "fixtures/port_overrides.txt".to_owned(),
+                "bad.internal=80,abc".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let targets = parse_targets_with_resolver(&opts, &stub_resolver());
+        let ports: Vec<(String, Option<PortRange>)> = targets
+            .hosts
+            .iter()
+            .map(|target| (target.ip.to_string(), target.ports.clone()))
+            .collect();
+
+        assert_eq!(
+            ports,
+            [
+                ("10.0.0.7".to_owned(), ranges(&[(80, 80), (443, 443)])),
+                // Given plainly by the CIDR, with its own ports by the file.
+                ("10.0.0.4".to_owned(), ranges(&[(22, 22)])),
+                ("10.0.0.5".to_owned(), ranges(&[(5432, 5433), (8080, 8080)])),
+                ("10.0.0.9".to_owned(), None),
+            ]
+        );
+        assert_eq!(targets.port_overrides().len(), 3);
+    }
+
+    #[test]
+    fn dual_stack_hosts_keep_their_ports() {
+        let mut targets = Targets::default();
+        targets.add(
+            "dual.example",
+            ranges(&[(443, 443)]),
+            Resolved::Host(vec![
+                "192.0.2.1".parse().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+            ]),
+            FamilyMode::Auto,
+        );
+        // The family race decision is inserted under the hostname.
+        targets.insert(
+            "2001:db8::1".parse().unwrap(),
+            "dual.example",
+            Some("dual.example"),
+        );
+
+        assert_eq!(targets.hosts[0].ports, ranges(&[(443, 443)]));
+    }
+
+    #[test]
+    fn previous_results_narrow_the_targets() {
+        let previous = PreviousResults::from_json(
+            r#"{"hosts": [
+                {"ip": "10.0.0.5", "ports": [22, 5432]},
+                {"ip": "10.0.0.8", "ports": [80]},
+                {"ip": "10.0.0.9", "ports": []}
+            ]}"#,
+        )
+        .unwrap();
+        let targets = || {
+            let opts = Opts {
+                addresses: vec!["app.internal=8080".to_owned(), "10.0.0.7".to_owned()],
+                ..Default::default()
+            };
+            parse_targets_with_resolver(&opts, &stub_resolver())
+        };
+
+        let mut added = targets();
+        assert_eq!(
+            added.only_previously_open(&previous, "previous.json", true),
+            ["10.0.0.7"]
+        );
+        let ports: Vec<(String, Option<PortRange>)> = added
+            .hosts
+            .iter()
+            .map(|target| (target.ip.to_string(), target.ports.clone()))
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                ("10.0.0.5".to_owned(), ranges(&[(22, 22), (5432, 5432)])),
+                ("10.0.0.8".to_owned(), ranges(&[(80, 80)])),
+            ]
+        );
+        assert_eq!(added.hosts[1].sources, ["previous.json"]);
+
+        let mut given = targets();
+        given.only_previously_open(&previous, "previous.json", false);
+        assert_eq!(given.ips(), ["10.0.0.5".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn filtered_ports_narrow_the_targets() {
+        let previous = PreviousResults::from_json(
+            r#"{"hosts": [
+                {"ip": "10.0.0.5", "ports": [22], "filtered": [22, 3389]},
+                {"ip": "10.0.0.7", "ports": [80]},
+                {"ip": "10.0.0.8", "ports": [], "filtered": [25]}
+            ]}"#,
+        )
+        .unwrap();
+        let opts = Opts {
+            addresses: vec!["app.internal=8080".to_owned(), "10.0.0.7".to_owned()],
+            ..Default::default()
+        };
+        let mut targets = parse_targets_with_resolver(&opts, &stub_resolver());
+
+        // 10.0.0.7 had open ports, not filtered ones.
+        assert_eq!(
+            targets.only_previously_filtered(&previous, "previous.json", true),
+            ["10.0.0.7"]
+        );
+        let ports: Vec<(String, Option<PortRange>)> = targets
+            .hosts
+            .iter()
+            .map(|target| (target.ip.to_string(), target.ports.clone()))
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                ("10.0.0.5".to_owned(), ranges(&[(3389, 3389)])),
+                ("10.0.0.8".to_owned(), ranges(&[(25, 25)])),
+            ]
+        );
+        assert_eq!(
+            targets.stages.last().unwrap().stage,
+            Stage::PreviouslyFiltered
+        );
+    }
+
+    #[test]
+    fn targets_outside_the_scope_are_dropped() {
+        let mut resolver = stub_resolver();
+        resolver.0.insert(
+            "dual.internal",
+            vec!["10.0.0.9".parse().unwrap(), "2001:db8::9".parse().unwrap()],
+        );
+        let scope = Scope::parse("10.0.0.4/31\ninternal.corp\n10.0.0.8/30\n").unwrap();
+        let opts = Opts {
+            addresses: vec![
+                "10.0.0.4/30".to_owned(),
+                "web.internal".to_owned(),
+                "dual.internal".to_owned(),
+            ],
+            prefer_family: FamilyMode::Auto,
+            ..Default::default()
+        };
+        let mut targets = parse_targets_with_resolver(&opts, &resolver);
+
+        let dropped: Vec<(String, Vec<String>)> = targets
+            .retain_in_scope(&scope)
+            .into_iter()
+            .map(|target| (target.ip.to_string(), target.sources))
+            .collect();
+        assert_eq!(
+            dropped,
+            [
+                ("2001:db8::9".to_owned(), vec!["dual.internal".to_owned()]),
+                ("10.0.0.6".to_owned(), vec!["10.0.0.4/30".to_owned()]),
+                // Resolved outside the scope, web.internal matches no suffix.
+                (
+                    "10.0.0.7".to_owned(),
+                    vec!["10.0.0.4/30".to_owned(), "web.internal".to_owned()]
+                ),
+            ]
+        );
+        let kept: Vec<String> = targets.ips().iter().map(IpAddr::to_string).collect();
+        assert_eq!(kept, ["10.0.0.4", "10.0.0.5", "10.0.0.9"]);
+        assert!(targets.dual_stack.is_empty());
+
+        let scope = Scope::parse("0.0.0.0/0\n::/0\n").unwrap();
+        let mut targets = parse_targets_with_resolver(&opts, &resolver);
+        assert!(targets.retain_in_scope(&scope).is_empty());
+        assert_eq!(targets.dual_stack.len(), 1);
+    }
+
+    #[test]
+    fn large_ipv6_cidrs_are_refused() {
+        let resolver = stub_resolver();
+        let error = resolve_address(
+            "2001:db8::/64",
+            &resolver,
+            FamilyMode::System,
+            HostLimits::DEFAULT,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "2001:db8::/64 holds 2^64 = 18446744073709551616 addresses, more than the 4096 \
+             allowed by --max-ipv6-hosts. Use a /116 or longer prefix, a smaller range, or \
+             raise --max-ipv6-hosts."
+        );
+        let ipv6 = |max: u64| HostLimits {
+            ipv6: max,
+            ..HostLimits::DEFAULT
+        };
+        assert!(
+            resolve_address("::/0", &resolver, FamilyMode::System, ipv6(u64::MAX))
+                .err()
+                .unwrap()
+                .to_string()
+                .starts_with("::/0 holds 2^128 addresses")
+        );
+
+        let ips = |address: &str, max: u64| match resolve_address(
+            address,
+            &resolver,
+            FamilyMode::System,
+            ipv6(max),
+        ) {
+            Ok(Resolved::Literal(ips)) => ips,
+            _ => panic!("{:?} was refused", address),
+        };
+        assert_eq!(ips("2001:db8::/116", 4_096).count(), 4_096);
+        assert!(resolve_address(
+            "2001:db8::/112",
+            &resolver,
+            FamilyMode::System,
+            HostLimits::DEFAULT
+        )
+        .is_err());
+        // Raising the limit lets the larger CIDR through.
+        assert_eq!(ips("2001:db8::/112", 65_536).count(), 65_536);
+        // The limit of IPv6 leaves IPv4 alone.
+        assert_eq!(ips("10.0.0.0/16", 1).count(), 65_536);
+    }
+
+    #[test]
+    fn large_ipv4_cidrs_are_refused() {
+        let resolver = stub_resolver();
+        let ipv4 = |max: u64| HostLimits {
+            ipv4: max,
+            ..HostLimits::DEFAULT
+        };
+        let error = resolve_address("0.0.0.0/0", &resolver, FamilyMode::System, ipv4(65_536))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "0.0.0.0/0 holds 2^32 = 4294967296 addresses, more than the 65536 allowed by \
+             --max-ipv4-hosts. Use a /16 or longer prefix, a smaller range, or raise \
+             --max-ipv4-hosts."
+        );
+        let range = resolve_address(
+            "10.0.0.0-10.1.0.0",
+            &resolver,
+            FamilyMode::System,
+            ipv4(65_536),
+        );
+        assert_eq!(
+            range.err(),
+            Some(InputError::TooManyIpv4Hosts {
+                input: "10.0.0.0-10.1.0.0".to_owned(),
+                hosts: 65_537,
+                max: 65_536,
+            })
+        );
+
+        let ips = |address: &str, max: u64| match resolve_address(
+            address,
+            &resolver,
+            FamilyMode::System,
+            ipv4(max),
+        ) {
+            Ok(Resolved::Literal(ips)) => ips,
+            _ => panic!("{:?} was refused", address),
+        };
+        assert_eq!(ips("10.0.0.0/16", 65_536).count(), 65_536);
+        assert!(
+            resolve_address("10.0.0.0/15", &resolver, FamilyMode::System, ipv4(65_536)).is_err()
+        );
+        assert_eq!(ips("10.0.0.0/15", 131_072).count(), 131_072);
+        assert_eq!(ips("10.0.0.7", 1).count(), 1);
+        // Without --max-ipv4-hosts, every IPv4 target is let through.
+        for (cidr, hosts) in [
+            ("10.0.0.0/8", 1 << 24),
+            ("172.16.0.0/12", 1 << 20),
+            ("0.0.0.0/0", 1 << 32),
+        ] {
+            let ips = ips(cidr, DEFAULT_MAX_IPV4_HOSTS);
+            assert_eq!(ips.size_hint(), (hosts, Some(hosts)));
+        }
+    }
+
+    #[test]
+    fn malformed_targets_are_typed_errors() {
+        let resolver = stub_resolver();
+        let resolve = |address: &str| {
+            resolve_address(address, &resolver, FamilyMode::System, HostLimits::DEFAULT)
+        };
+        assert_eq!(
+            resolve("10.0.0.0/-1").err(),
+            Some(InputError::InvalidCidr {
+                input: "10.0.0.0/-1".to_owned(),
+                position: 10,
+                max_prefix: 32,
+            })
+        );
+        assert_eq!(
+            resolve("10.0.0.1/8").err(),
+            Some(InputError::HostBitsSet {
+                input: "10.0.0.1/8".to_owned(),
+                network: "10.0.0.0/8".to_owned(),
+            })
+        );
+        assert!(matches!(
+            resolve("::1/0"),
+            Err(InputError::HostBitsSet { network, .. }) if network == "::/0"
+        ));
+        assert!(matches!(
+            resolve("2001:db8::/129"),
+            Err(InputError::InvalidCidr {
+                max_prefix: 128,
+                ..
+            })
+        ));
+        assert!(matches!(
+            resolve("10.0.0.9-10.0.0.1"),
+            Err(InputError::ReversedRange { .. })
+        ));
+        assert!(matches!(
+            resolve("10.0.0.1-::1"),
+            Err(InputError::MixedFamilyRange { .. })
+        ));
+
+        let long = format!("{}.example", "a".repeat(10_000));
+        let error = resolve(&long).err().unwrap();
+        assert_eq!(error, InputError::HostnameTooLong { input: long });
+        assert!(error
+            .to_string()
+            .ends_with("is 10008 characters long, DNS allows 253."));
+        assert!(error.to_string().len() < 200);
+        assert!(matches!(
+            resolve(&format!("{}.", "a".repeat(253))),
+            Ok(Resolved::Failed(ResolutionError::NotFound))
+        ));
+
+        assert!(matches!(
+            parse_target("=80"),
+            Err(InputError::MissingHost { .. })
+        ));
+        // Positions are in characters, of the whole target.
+        assert_eq!(
+            parse_target("höst=80,x").err(),
+            Some(InputError::InvalidPortRange {
+                input: "höst=80,x".to_owned(),
+                position: 9,
+            })
+        );
+        assert_eq!(
+            parse_target_line("  host 80 extra").err(),
+            Some(InputError::TooManyFields {
+                input: "host 80 extra".to_owned(),
+                position: 9,
+            })
+        );
+        assert!(split_targets(&",".repeat(1_000_000)).is_empty());
+    }
+
+    #[test]
+    fn long_lines_are_skipped_unread() {
+        let bytes = [
+            &b"10.0.0.1\n"[..],
+            &[b'a'; 100],
+            b"\n10.0.0.2\r\n\xff\n10.0.0.3",
+        ]
+        .concat();
+        let lines: Vec<(usize, Result<String, InputError>)> =
+            TargetLines::new(&bytes[..], 16).collect();
+        assert_eq!(
+            lines,
+            [
+                (1, Ok("10.0.0.1".to_owned())),
+                (
+                    2,
+                    Err(InputError::LineTooLong {
+                        start: "a".repeat(17),
+                        limit: 16
+                    })
+                ),
+                (3, Ok("10.0.0.2\r".to_owned())),
+                (4, Err(InputError::NotUtf8)),
+                (5, Ok("10.0.0.3".to_owned())),
+            ]
+        );
+    }
+
+    // Never panics, and gives either the targets or a typed error which can
+    // be shown.
+    #[test]
+    fn target_grammar_never_panics() {
+        let resolver = stub_resolver();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(175);
+        let alphabet = [
+            "10.0.0.1",
+            "10.0.0.2",
+            "::1",
+            "2001:db8::",
+            "app.internal",
+            "/",
+            "/31",
+            "/-1",
+            "/129",
+            "-",
+            "=",
+            ",",
+            ";",
+            " ",
+            "80",
+            "70000",
+            "a",
+            ".",
+            ":",
+            "é",
+            "\0",
+        ];
+        for _ in 0..5_000 {
+            let length = if rng.gen_ratio(1, 50) {
+                rng.gen_range(100..2_000)
+            } else {
+                rng.gen_range(0..8)
+            };
+            let input: String = (0..length)
+                .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                .collect();
+
+            for token in split_targets(&input) {
+                for parsed in [parse_target(token), parse_target_line(token)] {
+                    match parsed {
+                        Ok((address, _)) => {
+                            if let Err(e) = resolve_address(
+                                address,
+                                &resolver,
+                                FamilyMode::System,
+                                HostLimits::DEFAULT,
+                            ) {
+                                assert!(!e.to_string().is_empty());
+                            }
+                        }
+                        Err(e) => assert!(!e.to_string().is_empty()),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn address_ranges_are_expanded() {
+        let opts = Opts {
+            addresses: vec![
+                "2001:db8::fe-2001:db8::101, 2001:db8::1".to_owned(),
+                "10.0.0.254-10.0.1.1".to_owned(),
+                "2001:db8::1-2001:db8::1:0".to_owned(),
+                "10.0.0.2-10.0.0.1".to_owned(),
+                "10.0.0.1-2001:db8::1".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let ips: Vec<String> = parse_targets_with_resolver(&opts, &stub_resolver())
+            .ips()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        // The oversized and malformed ranges are skipped.
+        assert_eq!(
+            ips,
+            [
+                "2001:db8::fe",
+                "2001:db8::ff",
+                "2001:db8::100",
+                "2001:db8::101",
+                "2001:db8::1",
+                "10.0.0.254",
+                "10.0.0.255",
+                "10.0.1.0",
+                "10.0.1.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn host_ranges_are_yielded_one_at_a_time() {
+        let ip = |ip: &str| -> IpAddr { ip.parse().unwrap() };
+        let range = HostRange::new(ip("10.0.0.254"), ip("10.0.1.1"));
+        assert_eq!(range.size_hint(), (4, Some(4)));
+        assert_eq!(
+            range.collect::<Vec<_>>(),
+            [
+                ip("10.0.0.254"),
+                ip("10.0.0.255"),
+                ip("10.0.1.0"),
+                ip("10.0.1.1")
+            ]
+        );
+        // The last addresses of a family end the range without overflowing.
+        let last = HostRange::new(ip("255.255.255.254"), ip("255.255.255.255"));
+        assert_eq!(last.count(), 2);
+        let mut last = HostRange::new(ip("ffff::fffe"), ip("ffff::ffff"));
+        assert_eq!(last.by_ref().count(), 2);
+        assert_eq!(last.next(), None);
+        // Far more than are ever expanded at once.
+        let all = HostRange::of(&"::/0".parse().unwrap());
+        assert_eq!(all.size_hint(), (usize::MAX, None));
+        assert_eq!(
+            HostRange::new(ip("2001:db8::1"), ip("2001:db8::1")).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn family_mode_keeps_literals() {
+        let mut targets = Targets::default();
+        targets.add(
+            "2001:db8::1",
+            None,
+            Resolved::Literal(HostRange::of(&"2001:db8::1".parse().unwrap())),
+            FamilyMode::Ipv4,
+        );
+
+        assert_eq!(targets.ips().len(), 1);
+    }
 
     #[test]
     fn parse_correct_addresses() {
@@ -0,0 +1,162 @@
+//! The canonical names of the hosts, see [`Naming`].
+//!
+//! The targets are merged by address, so a machine given as `web01` on the
+//! command line, as `web01.corp.local` in a targets file and by its address
+//! in an import is a single host, with every name it was given in its
+//! `hostnames`. One of them is picked as the name of the host, the `name` of
+//! the JSON report and `{{hostname}}` of the scripts, by `--prefer-names`:
+//!
+//! - `fqdn`, the default: the first name with a dot,
+//! - `short`: the first name without a dot,
+//! - `ip`: the address itself.
+//!
+//! A host without a name of the preferred kind gets its first name, and a
+//! host without any name its address.
+//!
+//! The aliases file of `--aliases` forces the name of some addresses,
+//! whatever names they were given, as TOML or, with a `.json` extension,
+//! JSON:
+//!
+//! ```toml
+//! "10.0.0.5" = "jump.corp.example"
+//! ```
+use crate::input::PreferNames;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The names forced by the aliases file, by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Aliases {
+    names: BTreeMap<IpAddr, String>,
+}
+
+impl Aliases {
+    /// Reads the aliases file at `path`, JSON or TOML depending on its
+    /// extension.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            Self::from_json(&content)
+        } else {
+            Self::from_toml(&content)
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+}
+
+/// Picks the name of every host.
+#[derive(Debug, Clone, Default)]
+pub struct Naming {
+    prefer: PreferNames,
+    aliases: Aliases,
+}
+
+impl Naming {
+    pub fn new(prefer: PreferNames, aliases: Aliases) -> Self {
+        Self { prefer, aliases }
+    }
+
+    /// The name of `ip`, given as `hostnames` in the order they came in.
+    pub fn name(&self, ip: IpAddr, hostnames: &[String]) -> String {
+        if let Some(name) = self.aliases.names.get(&ip) {
+            return name.clone();
+        }
+        let dotted = |name: &&String| name.trim_end_matches('.').contains('.');
+        let preferred = match self.prefer {
+            PreferNames::Fqdn => hostnames.iter().find(dotted),
+            PreferNames::Short => hostnames.iter().find(|name| !dotted(name)),
+            PreferNames::Ip => return ip.to_string(),
+        };
+        preferred
+            .or_else(|| hostnames.first())
+            .map_or_else(|| ip.to_string(), Clone::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aliases, Naming};
+    use crate::address::Targets;
+    use crate::input::PreferNames;
+    use std::net::IpAddr;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn names_of_different_sources_are_one_host() {
+        let web = ip("10.0.0.5");
+        let mut targets = Targets::default();
+        targets.insert(web, "web01", Some("web01"));
+        targets.insert(web, "hosts.txt", Some("web01.corp.local"));
+        targets.insert(web, "10.0.0.5", None);
+        targets.insert(ip("10.0.0.6"), "10.0.0.6", None);
+        let hosts = &targets.hosts;
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].hostnames, ["web01", "web01.corp.local"]);
+
+        let name = |prefer, index: usize| {
+            Naming::new(prefer, Aliases::default()).name(hosts[index].ip, &hosts[index].hostnames)
+        };
+        assert_eq!(name(PreferNames::Fqdn, 0), "web01.corp.local");
+        assert_eq!(name(PreferNames::Short, 0), "web01");
+        assert_eq!(name(PreferNames::Ip, 0), "10.0.0.5");
+        for prefer in [PreferNames::Fqdn, PreferNames::Short, PreferNames::Ip] {
+            assert_eq!(name(prefer, 1), "10.0.0.6");
+        }
+    }
+
+    #[test]
+    fn first_name_without_one_of_the_preferred_kind() {
+        let naming = |prefer| Naming::new(prefer, Aliases::default());
+        let short = ["db".to_owned(), "db2".to_owned()];
+        let fqdns = ["a.example".to_owned(), "b.example".to_owned()];
+
+        assert_eq!(naming(PreferNames::Fqdn).name(ip("10.0.0.7"), &short), "db");
+        assert_eq!(
+            naming(PreferNames::Short).name(ip("10.0.0.7"), &fqdns),
+            "a.example"
+        );
+        // The root dot doesn't make a name qualified.
+        let rooted = ["db.".to_owned(), "db.example".to_owned()];
+        assert_eq!(
+            naming(PreferNames::Short).name(ip("10.0.0.7"), &rooted),
+            "db."
+        );
+    }
+
+    #[test]
+    fn aliases_force_the_name() {
+        let aliases = Aliases::from_toml(r#""10.0.0.5" = "jump.corp.example""#).unwrap();
+        assert_eq!(
+            Aliases::from_json(r#"{"10.0.0.5": "jump.corp.example"}"#),
+            Ok(aliases.clone())
+        );
+        let hostnames = ["web01".to_owned()];
+        for prefer in [PreferNames::Fqdn, PreferNames::Short, PreferNames::Ip] {
+            let naming = Naming::new(prefer, aliases.clone());
+            assert_eq!(naming.name(ip("10.0.0.5"), &hostnames), "jump.corp.example");
+        }
+        assert_eq!(
+            Naming::new(PreferNames::Fqdn, aliases).name(ip("10.0.0.6"), &hostnames),
+            "web01"
+        );
+
+        assert!(Aliases::from_toml(r#""web01" = "jump.corp.example""#).is_err());
+    }
+}
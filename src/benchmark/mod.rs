@@ -18,6 +18,8 @@
 //! ```
 use std::time::Instant;
 
+pub mod tune;
+
 /// A Benchmark struct to hold NamedTimers with name, start and end Instants,
 #[derive(Debug)]
 pub struct Benchmark {
@@ -38,6 +40,7 @@ impl Benchmark {
     /// formats every element the same way and return
     /// a single String with all the available information
     /// for easy printing
+    #[allow(clippy::unnecessary_unwrap)]
     pub fn summary(&self) -> String {
         let mut summary = String::from("\nRustScan Benchmark Summary");
 
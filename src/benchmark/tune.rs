@@ -0,0 +1,308 @@
+//! Calibrates the scan defaults for this machine and network, see `rustscan tune`.
+//!
+//! The calibration only ever probes localhost and the representative host
+//! it's given, with a bounded amount of connections:
+//! - a few connects in a row measure the round trip times and losses,
+//! - batches of concurrent connects, doubling up to a maximum, find out
+//!   how many connections can be open at once before errors show up.
+//!
+//! The measurements are turned into a batch size, timeout and tries which
+//! are stored in the `[tuned]` profile of the config file.
+use async_std::io;
+use async_std::net::TcpStream;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How many connects in a row measure the round trip times.
+pub const RTT_SAMPLES: usize = 16;
+/// The size of the first concurrent batch, doubled until errors show up.
+pub const FIRST_BATCH: usize = 64;
+/// How long a calibration probe may take.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The timeout and tries of RustScan, kept without answers to go by.
+const DEFAULT_TIMEOUT_MS: u32 = 1_500;
+const DEFAULT_TRIES: u8 = 1;
+const MIN_BATCH_SIZE: usize = 16;
+const MIN_TIMEOUT_MS: u32 = 100;
+const MAX_TIMEOUT_MS: u32 = 5_000;
+
+/// What the calibration found out about a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Measurements {
+    /// The round trip times of the probes which got an answer.
+    pub rtts: Vec<Duration>,
+    /// The probes which timed out.
+    pub lost: usize,
+    /// The largest batch of concurrent connects without errors.
+    pub concurrency: usize,
+    /// How long resolving the host took, None for IP addresses.
+    pub resolver: Option<Duration>,
+}
+
+impl Measurements {
+    /// The `percent`th percentile of the round trip times, nearest rank.
+    pub fn rtt_percentile(&self, percent: usize) -> Option<Duration> {
+        let mut rtts = self.rtts.clone();
+        rtts.sort_unstable();
+        let rank = (percent * rtts.len()).div_ceil(100).max(1);
+        rtts.get(rank - 1).copied()
+    }
+
+    /// The share of the probes which timed out, from 0 to 1.
+    pub fn loss(&self) -> f64 {
+        let probes = self.rtts.len() + self.lost;
+        if probes == 0 {
+            return 0.0;
+        }
+        self.lost as f64 / probes as f64
+    }
+}
+
+/// The defaults recommended for the measured host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recommendation {
+    pub batch_size: u16,
+    /// In milliseconds.
+    pub timeout: u32,
+    pub tries: u8,
+}
+
+/// Turns measurements into scan defaults:
+/// - the batch size leaves a quarter of the concurrency found as headroom,
+/// - the timeout is three times the 99th percentile round trip time,
+///   rounded up to 10 ms, between 100 ms and 5 s,
+/// - a port is tried once without losses, twice with up to 10% of the
+///   probes lost and three times beyond.
+///
+/// Without a single answer the timeout and tries are kept as they are.
+pub fn recommend(measurements: &Measurements) -> Recommendation {
+    let batch_size = (measurements.concurrency * 3 / 4)
+        .max(MIN_BATCH_SIZE)
+        .try_into()
+        .unwrap_or(u16::MAX);
+    let Some(p99) = measurements.rtt_percentile(99) else {
+        return Recommendation {
+            batch_size,
+            timeout: DEFAULT_TIMEOUT_MS,
+            tries: DEFAULT_TRIES,
+        };
+    };
+
+    let timeout = u32::try_from(p99.as_millis().saturating_mul(3)).unwrap_or(u32::MAX);
+    let timeout = timeout
+        .div_ceil(10)
+        .saturating_mul(10)
+        .clamp(MIN_TIMEOUT_MS, MAX_TIMEOUT_MS);
+    let loss = measurements.loss();
+    let tries = if loss == 0.0 {
+        1
+    } else if loss <= 0.1 {
+        2
+    } else {
+        3
+    };
+
+    Recommendation {
+        batch_size,
+        timeout,
+        tries,
+    }
+}
+
+/// Combines the measurements of localhost, bound by this machine, with the
+/// ones of the representative host, bound by the network too.
+pub fn combine(local: &Measurements, remote: Option<&Measurements>) -> Measurements {
+    let Some(remote) = remote else {
+        return local.clone();
+    };
+    Measurements {
+        rtts: remote.rtts.clone(),
+        lost: remote.lost,
+        concurrency: local.concurrency.min(remote.concurrency),
+        resolver: remote.resolver,
+    }
+}
+
+/// Resolves `host` to a socket on `port`, timing the resolution of names.
+pub fn resolve(host: &str, port: u16) -> Result<(SocketAddr, Option<Duration>), String> {
+    if let Ok(ip) = host.parse() {
+        return Ok((SocketAddr::new(ip, port), None));
+    }
+    let start = Instant::now();
+    let socket = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{host} has no address."))?;
+    Ok((socket, Some(start.elapsed())))
+}
+
+/// Measures `socket`, opening at most `max_batch_size` connections at once.
+pub async fn measure(socket: SocketAddr, max_batch_size: usize) -> Measurements {
+    let mut measurements = Measurements::default();
+    for _ in 0..RTT_SAMPLES {
+        let start = Instant::now();
+        match probe(socket).await {
+            Ok(()) => measurements.rtts.push(start.elapsed()),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => measurements.lost += 1,
+            Err(_) => {}
+        }
+    }
+
+    let mut batch = FIRST_BATCH.min(max_batch_size);
+    loop {
+        let mut ftrs: FuturesUnordered<_> = (0..batch).map(|_| probe(socket)).collect();
+        let mut errors = 0;
+        while let Some(result) = ftrs.next().await {
+            errors += usize::from(result.is_err());
+        }
+        // A single error in a hundred is noise, more is the limit.
+        if errors * 100 > batch {
+            break;
+        }
+        measurements.concurrency = batch;
+        if batch >= max_batch_size {
+            break;
+        }
+        batch = (batch * 2).min(max_batch_size);
+    }
+    measurements
+}
+
+/// Connects to `socket`, a refused connection being an answer too.
+async fn probe(socket: SocketAddr) -> io::Result<()> {
+    match io::timeout(PROBE_TIMEOUT, TcpStream::connect(socket)).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the `[tuned]` profile of the config file `content` with
+/// `recommendation`, leaving everything else as it is.
+pub fn write_profile(content: &str, recommendation: &Recommendation) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_profile = false;
+    for line in content.lines() {
+        let header = line.trim();
+        if header.starts_with('[') {
+            in_profile = header == "[tuned]";
+        }
+        if !in_profile {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let mut content = lines.join("\n");
+    if !content.is_empty() {
+        content.push_str("\n\n");
+    }
+    content.push_str(&format!(
+        "[tuned]\nbatch_size = {}\ntimeout = {}\ntries = {}\n",
+        recommendation.batch_size, recommendation.timeout, recommendation.tries
+    ));
+    content
+}
+
+/// Stores `recommendation` in the `[tuned]` profile of the config file at `path`.
+pub fn save_profile(path: &Path, recommendation: &Recommendation) -> std::io::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    fs::write(path, write_profile(&content, recommendation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, recommend, write_profile, Measurements, Recommendation};
+    use std::time::Duration;
+
+    fn measurements(rtts_ms: &[u64], lost: usize, concurrency: usize) -> Measurements {
+        Measurements {
+            rtts: rtts_ms.iter().copied().map(Duration::from_millis).collect(),
+            lost,
+            concurrency,
+            resolver: None,
+        }
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let m = measurements(&[40, 10, 30, 20], 0, 0);
+        assert_eq!(m.rtt_percentile(50), Some(Duration::from_millis(20)));
+        assert_eq!(m.rtt_percentile(99), Some(Duration::from_millis(40)));
+        assert_eq!(m.rtt_percentile(1), Some(Duration::from_millis(10)));
+        assert_eq!(measurements(&[], 3, 0).rtt_percentile(99), None);
+    }
+
+    #[test]
+    fn recommendations_follow_the_measurements() {
+        // 99th percentile at 41 ms, one probe in ten lost.
+        let m = measurements(&[12, 15, 20, 41, 18, 13, 14, 16, 17], 1, 2_048);
+        assert_eq!(
+            recommend(&m),
+            Recommendation {
+                batch_size: 1_536,
+                timeout: 130,
+                tries: 2,
+            }
+        );
+
+        let lossless = measurements(&[1, 1, 2], 0, 4_096);
+        assert_eq!(recommend(&lossless).tries, 1);
+        // Localhost answers far below the shortest timeout.
+        assert_eq!(recommend(&lossless).timeout, 100);
+        assert_eq!(recommend(&measurements(&[2_000], 0, 64)).timeout, 5_000);
+        assert_eq!(recommend(&measurements(&[100], 2, 64)).tries, 3);
+    }
+
+    #[test]
+    fn silent_hosts_keep_the_defaults() {
+        assert_eq!(
+            recommend(&measurements(&[], 16, 0)),
+            Recommendation {
+                batch_size: 16,
+                timeout: 1_500,
+                tries: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn remote_host_bounds_the_local_measurements() {
+        let local = measurements(&[1], 0, 4_096);
+        let remote = measurements(&[30, 35], 1, 512);
+
+        let combined = combine(&local, Some(&remote));
+        assert_eq!(combined.concurrency, 512);
+        assert_eq!(combined.rtts, remote.rtts);
+        assert_eq!(combine(&local, None), local);
+    }
+
+    #[test]
+    fn profile_is_written_over_the_previous_one() {
+        let recommendation = Recommendation {
+            batch_size: 1_536,
+            timeout: 130,
+            tries: 2,
+        };
+        let expected = "[tuned]\nbatch_size = 1536\ntimeout = 130\ntries = 2\n";
+        assert_eq!(write_profile("", &recommendation), expected);
+
+        let content = "# My config\nbatch_size = 100\n\n[tuned]\nbatch_size = 1\ntimeout = 5\n\n[other]\nkey = 1\n";
+        assert_eq!(
+            write_profile(content, &recommendation),
+            format!("# My config\nbatch_size = 100\n\n[other]\nkey = 1\n\n{expected}")
+        );
+    }
+}
@@ -0,0 +1,645 @@
+//! Everything needed to run a scan again elsewhere, in one file, see
+//! `rustscan bundle`.
+//!
+//! `bundle export` writes the effective configuration of a run, once the
+//! configuration file is merged and the default ports are picked, as a TOML
+//! document: the targets, the excluded ports, the ports and their order, the
+//! timing, the scripts and the seed. The files the run reads are embedded
+//! with their contents: the targets files, the `--order-file`, the `--scope`
+//! and the `--notes`. `bundle run` writes them back to a private folder of
+//! the temporary directory, removed once they are read, and scans with the
+//! configuration of the bundle over the command line, which keeps the output
+//! options.
+//!
+//! The format is versioned by `bundle_version`. A bundle of another version,
+//! with a key it doesn't know or a value which doesn't parse is refused with
+//! the line and the column of the culprit.
+//!
+//! The custom scripts are named, not embedded: they are expected in the
+//! scripts folder of whoever runs the bundle, and their absence refuses the
+//! bundle unless `--ignore-missing-scripts`.
+#![allow(clippy::module_name_repetitions)]
+
+use crate::address::split_targets;
+use crate::input::{
+    Fairness, FamilyMode, Opts, OrderFileMode, PortRange, ScanOrder, ScopeMode, ScriptsRequired,
+};
+use crate::port_strategy::DefaultPorts;
+use crate::scanner::{Shard, TimeoutMap};
+use crate::scripts::ScriptFile;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The version of the bundles written.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// A run, ready to be scanned again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bundle {
+    pub bundle_version: u32,
+    /// The RustScan which exported the bundle.
+    pub exported_by: String,
+    /// The file names of the custom scripts the run selected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<String>,
+    pub settings: Settings,
+    #[serde(default, rename = "file", skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<EmbeddedFile>,
+}
+
+/// The effective configuration of a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// The target tokens, one per entry.
+    pub addresses: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ports: Option<Vec<u16>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "text")]
+    pub range: Option<PortRange>,
+    /// The default port set the run fell back on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ports: Option<DefaultPorts>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_ports: Option<Vec<u16>>,
+    #[serde(with = "value_enum")]
+    pub scan_order: ScanOrder,
+    pub spread_window: u16,
+    pub spread_distance: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_file: Option<PathBuf>,
+    #[serde(with = "value_enum")]
+    pub order_file_mode: OrderFileMode,
+    #[serde(with = "value_enum")]
+    pub fairness: Fairness,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub canary_ports: Vec<u16>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub canary_first_pass: bool,
+    pub udp: bool,
+    #[serde(with = "value_enum")]
+    pub family: FamilyMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+    pub strict_resolution: bool,
+    pub max_ipv4_hosts: u64,
+    pub max_ipv6_hosts: u64,
+    pub batch_size: u16,
+    /// In milliseconds.
+    pub timeout: u32,
+    pub tries: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "text")]
+    pub timeout_map: Option<TimeoutMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "text")]
+    pub shard: Option<Shard>,
+    #[serde(with = "value_enum")]
+    pub scripts: ScriptsRequired,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<PathBuf>,
+    #[serde(with = "value_enum")]
+    pub scope_mode: ScopeMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<PathBuf>,
+}
+
+/// A file the run reads, with its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddedFile {
+    /// The path the settings give the file by.
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Just the version, read first so that another version isn't refused for
+/// the keys it changed.
+#[derive(Deserialize)]
+struct Header {
+    bundle_version: toml::Spanned<u32>,
+}
+
+/// Just the paths of the settings, with where they are, to name the one
+/// without its contents.
+#[derive(Deserialize)]
+struct PathsHeader {
+    settings: Paths,
+}
+
+#[derive(Deserialize)]
+struct Paths {
+    #[serde(default)]
+    order_file: Option<toml::Spanned<PathBuf>>,
+    #[serde(default)]
+    scope: Option<toml::Spanned<PathBuf>>,
+    #[serde(default)]
+    notes: Option<toml::Spanned<PathBuf>>,
+}
+
+impl Bundle {
+    /// The bundle of the run of `opts`, which fell back on `default_ports`
+    /// and selected `scripts`. Fails when a file of the run can't be read.
+    pub fn export(
+        opts: &Opts,
+        default_ports: Option<DefaultPorts>,
+        scripts: &[ScriptFile],
+    ) -> Result<Self, String> {
+        let addresses: Vec<String> = opts
+            .addresses
+            .iter()
+            .flat_map(|entry| split_targets(entry))
+            .map(|token| token.trim().to_owned())
+            .filter(|token| !token.is_empty())
+            .collect();
+
+        let mut files: Vec<EmbeddedFile> = Vec::new();
+        let mut embed = |path: &Path| -> Result<(), String> {
+            if files.iter().any(|file| file.path == path) {
+                return Ok(());
+            }
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+            files.push(EmbeddedFile {
+                path: path.to_owned(),
+                contents,
+            });
+            Ok(())
+        };
+        // The tokens which aren't targets are read as targets files.
+        for token in &addresses {
+            let path = Path::new(token);
+            if path.is_file() {
+                embed(path)?;
+            }
+        }
+        for path in [&opts.order_file, &opts.scope, &opts.notes]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            embed(path)?;
+        }
+
+        let scripts = match opts.scripts {
+            ScriptsRequired::Custom => scripts
+                .iter()
+                .filter_map(|script| script.path.as_deref()?.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            bundle_version: BUNDLE_VERSION,
+            exported_by: format!("RustScan {}", env!("CARGO_PKG_VERSION")),
+            scripts,
+            settings: Settings {
+                addresses,
+                ports: opts.ports.clone(),
+                range: opts.range.clone(),
+                default_ports,
+                exclude_ports: opts.exclude_ports.clone(),
+                scan_order: opts.scan_order,
+                spread_window: opts.spread_window,
+                spread_distance: opts.spread_distance,
+                order_file: opts.order_file.clone(),
+                order_file_mode: opts.order_file_mode,
+                fairness: opts.fairness,
+                canary_ports: opts.canary_ports.clone(),
+                canary_first_pass: opts.canary_first_pass,
+                udp: opts.udp,
+                family: opts.family_mode(),
+                resolver: opts.resolver.clone(),
+                strict_resolution: opts.strict_resolution,
+                max_ipv4_hosts: opts.max_ipv4_hosts,
+                max_ipv6_hosts: opts.max_ipv6_hosts,
+                batch_size: opts.batch_size,
+                timeout: opts.timeout,
+                tries: opts.tries,
+                timeout_map: opts.timeout_map.clone(),
+                seed: opts.seed,
+                shard: opts.shard,
+                scripts: opts.scripts,
+                scope: opts.scope.clone(),
+                scope_mode: opts.scope_mode,
+                notes: opts.notes.clone(),
+            },
+            files,
+        })
+    }
+
+    /// The bundle as a TOML document.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Bundles always serialize.")
+    }
+
+    /// Reads a bundle written by [`Bundle::to_toml`], telling where it is
+    /// wrong when it is.
+    pub fn parse(document: &str) -> Result<Self, String> {
+        let header: Header = toml::from_str(document).map_err(|e| e.to_string())?;
+        let version = header.bundle_version;
+        if *version.get_ref() != BUNDLE_VERSION {
+            let (line, column) = location(document, version.span().start);
+            return Err(format!(
+                "line {line}, column {column}: bundle_version {} can't be read, only {BUNDLE_VERSION}",
+                version.get_ref()
+            ));
+        }
+
+        let bundle: Self = toml::from_str(document).map_err(|e| e.to_string())?;
+        // Every path of the settings has to come with its contents.
+        if let Err(path) = bundle.check_files() {
+            let paths: PathsHeader = toml::from_str(document).map_err(|e| e.to_string())?;
+            let settings = paths.settings;
+            let offset = [settings.order_file, settings.scope, settings.notes]
+                .iter()
+                .flatten()
+                .find(|spanned| spanned.get_ref() == path)
+                .map_or(0, |spanned| spanned.span().start);
+            let (line, column) = location(document, offset);
+            return Err(format!(
+                "line {line}, column {column}: {}",
+                missing_file(path)
+            ));
+        }
+        Ok(bundle)
+    }
+
+    /// The custom scripts of the bundle which aren't among `found`.
+    pub fn missing_scripts(&self, found: &[ScriptFile]) -> Vec<&str> {
+        self.scripts
+            .iter()
+            .filter(|name| {
+                !found.iter().any(|script| {
+                    script
+                        .path
+                        .as_deref()
+                        .and_then(Path::file_name)
+                        .is_some_and(|file_name| file_name == name.as_str())
+                })
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Writes the files of the bundle to `dir` and sets the configuration of
+    /// the bundle in `opts`, the settings pointing at the files written.
+    /// Returns the default port set the run fell back on.
+    pub fn apply(&self, opts: &mut Opts, dir: &Path) -> io::Result<Option<DefaultPorts>> {
+        fs::create_dir_all(dir)?;
+        let mut written: HashMap<&Path, PathBuf> = HashMap::new();
+        for (index, file) in self.files.iter().enumerate() {
+            let name = file
+                .path
+                .file_name()
+                .map_or_else(|| "file".into(), |name| name.to_string_lossy());
+            // Prefixed, as two files of the run can have the same name.
+            let path = dir.join(format!("{index}-{name}"));
+            fs::write(&path, &file.contents)?;
+            written.insert(&file.path, path);
+        }
+        if let Err(path) = self.check_files() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                missing_file(path),
+            ));
+        }
+        let extracted =
+            |path: &Option<PathBuf>| path.as_deref().and_then(|path| written.get(path)).cloned();
+
+        let settings = &self.settings;
+        opts.addresses = settings
+            .addresses
+            .iter()
+            .map(|token| match written.get(Path::new(token)) {
+                Some(path) => path.display().to_string(),
+                None => token.clone(),
+            })
+            .collect();
+        opts.ports = settings.ports.clone();
+        opts.range = settings.range.clone();
+        opts.top = false;
+        opts.exclude_ports = settings.exclude_ports.clone();
+        opts.given_ports = None;
+        opts.scan_order = settings.scan_order;
+        opts.spread_window = settings.spread_window;
+        opts.spread_distance = settings.spread_distance;
+        opts.order_file = extracted(&settings.order_file);
+        opts.order_file_mode = settings.order_file_mode;
+        opts.fairness = settings.fairness;
+        opts.canary_ports = settings.canary_ports.clone();
+        opts.canary_first_pass = settings.canary_first_pass;
+        opts.udp = settings.udp;
+        opts.prefer_family = settings.family;
+        opts.ipv4 = false;
+        opts.ipv6 = false;
+        opts.both_families = false;
+        opts.resolver = settings.resolver.clone();
+        opts.strict_resolution = settings.strict_resolution;
+        opts.max_ipv4_hosts = settings.max_ipv4_hosts;
+        opts.max_ipv6_hosts = settings.max_ipv6_hosts;
+        opts.batch_size = settings.batch_size;
+        opts.timeout = settings.timeout;
+        opts.tries = settings.tries;
+        opts.timeout_map = settings.timeout_map.clone();
+        opts.seed = settings.seed;
+        opts.shard = settings.shard;
+        opts.scripts = settings.scripts;
+        opts.scope = extracted(&settings.scope);
+        opts.scope_mode = settings.scope_mode;
+        opts.notes = extracted(&settings.notes);
+        Ok(settings.default_ports)
+    }
+
+    /// Fails with the first path of the settings, targets files left aside,
+    /// which comes without its contents.
+    fn check_files(&self) -> Result<(), &Path> {
+        let settings = &self.settings;
+        let missing = [&settings.order_file, &settings.scope, &settings.notes]
+            .iter()
+            .copied()
+            .flatten()
+            .find(|path| !self.files.iter().any(|file| &file.path == *path));
+        missing.map_or(Ok(()), |path| Err(path.as_path()))
+    }
+}
+
+fn missing_file(path: &Path) -> String {
+    format!(
+        "{} is given in the settings without a [[file]] of its contents",
+        path.display()
+    )
+}
+
+/// The line and the column, from 1, of the byte `offset` of `document`.
+fn location(document: &str, offset: usize) -> (usize, usize) {
+    let before = &document[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+    (line, column)
+}
+
+/// Writes the values which have a textual form, like `--range` and
+/// `--shard`, as their text.
+mod text {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| text.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Writes the values of the command line choices, like `--scan-order`, as
+/// they are given on the command line.
+mod value_enum {
+    use clap::ValueEnum;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: ValueEnum, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let value = value.to_possible_value().expect("Every choice has a name.");
+        serializer.serialize_str(value.get_name())
+    }
+
+    pub fn deserialize<'de, T: ValueEnum, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        T::from_str(&name, true).map_err(|_| {
+            let names: Vec<String> = T::value_variants()
+                .iter()
+                .filter_map(ValueEnum::to_possible_value)
+                .map(|value| value.get_name().to_owned())
+                .collect();
+            de::Error::custom(format!(
+                "unknown value {name:?}, expected one of {}",
+                names.join(", ")
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{location, Bundle};
+    use crate::address::parse_targets;
+    use crate::input::{Opts, PortRange, ScanOrder, ScriptsRequired};
+    use crate::plan::ScanPlan;
+    use crate::port_strategy::{DefaultPorts, OrderFile};
+    use crate::scripts::ScriptFile;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustscan-bundle-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn plan(opts: &Opts, default_ports: Option<DefaultPorts>) -> serde_json::Value {
+        let order_file = opts
+            .order_file
+            .as_ref()
+            .map(|path| OrderFile::read(path).unwrap());
+        let plan = ScanPlan::new(opts, &parse_targets(opts), order_file, default_ports);
+        serde_json::to_value(plan).unwrap()
+    }
+
+    fn script(path: &str) -> ScriptFile {
+        let mut script: ScriptFile = toml::from_str("").unwrap();
+        script.path = Some(PathBuf::from(path));
+        script
+    }
+
+    #[test]
+    fn exported_runs_are_planned_the_same() {
+        let dir = temp_dir("round-trip");
+        let order_file = dir.join("order.txt");
+        fs::write(&order_file, "443\n22\n80\n").unwrap();
+        let scope = dir.join("scope.txt");
+        fs::write(&scope, "127.0.0.0/8\n").unwrap();
+        let notes = dir.join("notes.txt");
+        fs::write(&notes, "127.0.0.1 = The loopback\n").unwrap();
+
+        let opts = Opts {
+            addresses: vec!["127.0.0.1,127.0.0.2;127.0.0.3=22-25".to_owned()],
+            range: Some(PortRange {
+                ranges: vec![(20, 30), (80, 80), (443, 443)],
+            }),
+            exclude_ports: Some(vec![21]),
+            scan_order: ScanOrder::Random,
+            order_file: Some(order_file),
+            canary_ports: vec![22],
+            batch_size: 1234,
+            timeout: 700,
+            tries: 2,
+            timeout_map: Some("lan=127.0.0.0/8:300:2,default:900".parse().unwrap()),
+            seed: Some(42),
+            shard: Some("1/2".parse().unwrap()),
+            scripts: ScriptsRequired::Custom,
+            scope: Some(scope),
+            notes: Some(notes),
+            ..Opts::default()
+        };
+        let scripts = [script("/home/me/.rustscan_scripts/audit.sh")];
+        let exported = Bundle::export(&opts, Some(DefaultPorts::TopTcp), &scripts).unwrap();
+        let document = exported.to_toml();
+        let imported = Bundle::parse(&document).unwrap();
+        assert_eq!(imported, exported);
+        assert_eq!(imported.scripts, ["audit.sh"]);
+        assert_eq!(imported.files.len(), 3);
+
+        let mut run = Opts::default();
+        let default_ports = imported.apply(&mut run, &dir.join("run")).unwrap();
+        assert_eq!(default_ports, Some(DefaultPorts::TopTcp));
+        assert_eq!(
+            plan(&run, default_ports),
+            plan(&opts, Some(DefaultPorts::TopTcp))
+        );
+        assert_eq!(
+            fs::read_to_string(run.scope.unwrap()).unwrap(),
+            "127.0.0.0/8\n"
+        );
+        assert!(run.notes.unwrap().starts_with(dir.join("run")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn targets_files_are_embedded() {
+        let dir = temp_dir("targets");
+        let targets = dir.join("targets.txt");
+        fs::write(&targets, "127.0.0.1\n127.0.0.2\n").unwrap();
+        let opts = Opts {
+            addresses: vec![format!("{},127.0.0.3", targets.display())],
+            ..Opts::default()
+        };
+
+        let bundle = Bundle::parse(&Bundle::export(&opts, None, &[]).unwrap().to_toml()).unwrap();
+        assert_eq!(bundle.files[0].path, targets);
+        let mut run = Opts::default();
+        bundle.apply(&mut run, &dir.join("run")).unwrap();
+        let extracted = Path::new(&run.addresses[0]);
+        assert!(extracted.starts_with(dir.join("run")));
+        assert_eq!(run.addresses[1], "127.0.0.3");
+        let ips: Vec<String> = parse_targets(&run)
+            .hosts
+            .iter()
+            .map(|host| host.ip.to_string())
+            .collect();
+        assert_eq!(ips, ["127.0.0.3", "127.0.0.1", "127.0.0.2"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn wrong_bundles_are_refused_with_their_location() {
+        let document = Bundle::export(&Opts::default(), None, &[])
+            .unwrap()
+            .to_toml();
+
+        let newer = document.replace("bundle_version = 1", "bundle_version = 2");
+        assert_eq!(
+            Bundle::parse(&newer),
+            Err("line 1, column 18: bundle_version 2 can't be read, only 1".to_owned())
+        );
+
+        let unknown_order =
+            document.replace("scan_order = \"serial\"", "scan_order = \"sideways\"");
+        let error = Bundle::parse(&unknown_order).unwrap_err();
+        let line = document
+            .lines()
+            .position(|line| line.starts_with("scan_order"))
+            .unwrap()
+            + 1;
+        assert!(
+            error.contains(&format!("line {line}, column 14")),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("unknown value \"sideways\", expected one of serial, random"),
+            "{}",
+            error
+        );
+
+        let unknown_key = document.replace("[settings]\n", "[settings]\nspeed = 11\n");
+        let error = Bundle::parse(&unknown_key).unwrap_err();
+        assert!(error.contains("unknown field `speed`"), "{}", error);
+
+        let without_file = document.replace("[settings]\n", "[settings]\nscope = \"scope.txt\"\n");
+        let line = without_file
+            .lines()
+            .position(|line| line.starts_with("scope"))
+            .unwrap()
+            + 1;
+        assert_eq!(
+            Bundle::parse(&without_file),
+            Err(format!(
+                "line {line}, column 9: scope.txt is given in the settings without a [[file]] of its contents"
+            ))
+        );
+
+        // Nor are they run, however they were made.
+        let mut bundle = Bundle::parse(&document).unwrap();
+        bundle.settings.scope = Some(PathBuf::from("scope.txt"));
+        let dir = temp_dir("without-file");
+        let error = bundle.apply(&mut Opts::default(), &dir).unwrap_err();
+        assert!(
+            error.to_string().starts_with("scope.txt is given"),
+            "{}",
+            error
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_scripts_are_named() {
+        let opts = Opts {
+            scripts: ScriptsRequired::Custom,
+            ..Opts::default()
+        };
+        let selected = [script("/a/audit.sh"), script("/a/route.sh")];
+        let bundle = Bundle::export(&opts, None, &selected).unwrap();
+        assert_eq!(
+            bundle.missing_scripts(&[script("/b/route.sh")]),
+            ["audit.sh"]
+        );
+        assert!(bundle.missing_scripts(&selected).is_empty());
+    }
+
+    #[test]
+    fn locations_count_from_one() {
+        assert_eq!(location("a = 1\nb = 2\n", 0), (1, 1));
+        assert_eq!(location("a = 1\nb = 2\n", 10), (2, 5));
+    }
+}
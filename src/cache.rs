@@ -0,0 +1,246 @@
+//! Caches the open ports of scanned hosts across runs, see `--cache`.
+//!
+//! Every host gets its own JSON file in the cache directory, keyed on its
+//! address, the protocol and the set of ports scanned. Scanning other ports
+//! of the same host, or the same ports over UDP, never reuses an entry. An
+//! entry which can't be read, was written by another cache version or is
+//! older than `--cache-max-age` is ignored and the host is scanned again.
+use crate::hash::fnv1a;
+use crate::scripts::ScriptRun;
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Bumped whenever the format of the entries changes.
+pub const CACHE_VERSION: u32 = 1;
+
+/// What a cache entry is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub ip: IpAddr,
+    pub udp: bool,
+    /// The hash of the set of ports scanned.
+    pub ports_hash: u64,
+}
+
+impl CacheKey {
+    /// The key of scanning `ports` of `ip`, in whatever order.
+    pub fn new(ip: IpAddr, ports: &[u16], udp: bool) -> Self {
+        let mut ports = ports.to_vec();
+        ports.sort_unstable();
+        ports.dedup();
+        Self {
+            ip,
+            udp,
+            ports_hash: fnv1a(ports.iter().flat_map(|port| port.to_be_bytes())),
+        }
+    }
+
+    fn protocol(&self) -> &'static str {
+        if self.udp {
+            "udp"
+        } else {
+            "tcp"
+        }
+    }
+
+    fn file_name(&self) -> String {
+        let ip = self.ip.to_string().replace(':', "_");
+        format!("{ip}-{}-{:016x}.json", self.protocol(), self.ports_hash)
+    }
+}
+
+/// The cached results of a host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    version: u32,
+    pub ip: IpAddr,
+    protocol: String,
+    ports_hash: String,
+    /// When the ports were scanned, in seconds since the Unix epoch.
+    pub scanned_at: u64,
+    pub open: Vec<u16>,
+    /// The script runs against the host, with `--cache-scripts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scripts: Option<Vec<ScriptRun>>,
+}
+
+impl CacheEntry {
+    /// An entry for the `open` ports of `key` scanned now.
+    pub fn new(key: &CacheKey, open: Vec<u16>) -> Self {
+        let scanned_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            version: CACHE_VERSION,
+            ip: key.ip,
+            protocol: key.protocol().to_owned(),
+            ports_hash: format!("{:016x}", key.ports_hash),
+            scanned_at,
+            open,
+            scripts: None,
+        }
+    }
+
+    /// Caches the script runs of the host along with its ports.
+    #[must_use]
+    pub fn with_scripts(mut self, scripts: Option<Vec<ScriptRun>>) -> Self {
+        self.scripts = scripts;
+        self
+    }
+
+    /// When the ports were scanned, in RFC 3339 format.
+    pub fn scanned_at_rfc3339(&self) -> String {
+        humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(self.scanned_at))
+            .to_string()
+    }
+
+    fn matches(&self, key: &CacheKey) -> bool {
+        self.version == CACHE_VERSION
+            && self.ip == key.ip
+            && self.protocol == key.protocol()
+            && self.ports_hash == format!("{:016x}", key.ports_hash)
+    }
+
+    /// How long ago the ports were scanned, None when it's in the future.
+    fn age(&self, now: SystemTime) -> Option<Duration> {
+        now.duration_since(UNIX_EPOCH + Duration::from_secs(self.scanned_at))
+            .ok()
+    }
+}
+
+/// A directory of cache entries.
+#[derive(Debug, Clone)]
+pub struct ScanCache {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+impl ScanCache {
+    /// A cache in `dir` whose entries are fresh for `max_age`.
+    pub fn new(dir: &Path, max_age: Duration) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            max_age,
+        }
+    }
+
+    /// The fresh entry of `key`, None when there is none or it can't be used.
+    pub fn get(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let path = self.dir.join(key.file_name());
+        let content = fs::read_to_string(&path).ok()?;
+        let entry: CacheEntry = match serde_json::from_str(&content) {
+            Ok(entry) => entry,
+            Err(e) => {
+                debug!(
+                    "Ignoring the corrupted cache entry {}: {}",
+                    path.display(),
+                    e
+                );
+                return None;
+            }
+        };
+
+        let fresh = entry
+            .age(SystemTime::now())
+            .is_some_and(|age| age <= self.max_age);
+        (entry.matches(key) && fresh).then_some(entry)
+    }
+
+    /// Stores `entry` as the one of `key`, replacing the previous one.
+    pub fn put(&self, key: &CacheKey, entry: &CacheEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(key.file_name());
+        // Readers never see a half written entry.
+        let partial = path.with_extension(format!("json.{}", std::process::id()));
+        fs::write(&partial, serde_json::to_string(entry)?)?;
+        fs::rename(&partial, &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheEntry, CacheKey, ScanCache};
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    // A fresh cache directory for every test.
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustscan-cache-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn localhost() -> IpAddr {
+        "127.0.0.1".parse().unwrap()
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = cache_dir("round-trip");
+        let cache = ScanCache::new(&dir, Duration::from_secs(60));
+        let key = CacheKey::new(localhost(), &[443, 22, 80], false);
+
+        assert_eq!(cache.get(&key), None);
+        let entry = CacheEntry::new(&key, vec![22, 80]);
+        cache.put(&key, &entry).unwrap();
+        assert_eq!(cache.get(&key), Some(entry));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn key_depends_on_the_ports_and_protocol() {
+        let key = CacheKey::new(localhost(), &[80, 22], false);
+
+        assert_eq!(key, CacheKey::new(localhost(), &[22, 80, 80], false));
+        assert_ne!(key, CacheKey::new(localhost(), &[22, 80, 443], false));
+        assert_ne!(key, CacheKey::new(localhost(), &[22, 80], true));
+        assert_ne!(
+            key.file_name(),
+            CacheKey::new(localhost(), &[22, 80], true).file_name()
+        );
+    }
+
+    #[test]
+    fn stale_entries_are_ignored() {
+        let dir = cache_dir("stale");
+        let cache = ScanCache::new(&dir, Duration::from_secs(60));
+        let key = CacheKey::new(localhost(), &[80], false);
+
+        let mut entry = CacheEntry::new(&key, vec![80]);
+        entry.scanned_at -= 120;
+        cache.put(&key, &entry).unwrap();
+        assert_eq!(cache.get(&key), None);
+
+        // Entries from the future are just as suspicious.
+        entry.scanned_at += 3_600;
+        cache.put(&key, &entry).unwrap();
+        assert_eq!(cache.get(&key), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unusable_entries_are_ignored() {
+        let dir = cache_dir("unusable");
+        let cache = ScanCache::new(&dir, Duration::from_secs(60));
+        let key = CacheKey::new(localhost(), &[80], false);
+
+        let mut entry = CacheEntry::new(&key, vec![80]);
+        entry.version += 1;
+        cache.put(&key, &entry).unwrap();
+        assert_eq!(cache.get(&key), None);
+
+        std::fs::write(dir.join(key.file_name()), "{\"version\": 1, \"ip\":").unwrap();
+        assert_eq!(cache.get(&key), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
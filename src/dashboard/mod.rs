@@ -0,0 +1,113 @@
+//! The live dashboard of `--tui`.
+//!
+//! The dashboard listens to the [`ScanUpdate`]s of the scanner and steers
+//! it through a [`ScanControl`], the scanner doesn't know it is there. What
+//! the screen shows is worked out by [`view::View`], which the terminal is
+//! only drawn from.
+pub mod view;
+
+#[cfg(unix)]
+mod terminal;
+
+use crate::input::{Opts, OutputFormat};
+use crate::scanner::{ScanControl, ScanUpdate};
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the screen is drawn.
+#[cfg(unix)]
+const FRAME: Duration = Duration::from_millis(100);
+
+/// Checks that the dashboard can have stdout to itself.
+pub fn check(opts: &Opts) -> Result<(), String> {
+    if cfg!(not(unix)) {
+        return Err("The dashboard of --tui needs a Unix terminal.".to_owned());
+    }
+    if opts.format == OutputFormat::Json && opts.output_file.is_none() {
+        return Err(
+            "--tui can't share stdout with the JSON report, write it with --output-file instead."
+                .to_owned(),
+        );
+    }
+    if opts.greppable {
+        return Err("--tui can't share stdout with greppable results, write the JSON report with --output-file instead.".to_owned());
+    }
+    if !io::stdout().is_terminal() {
+        return Err(
+            "--tui needs a terminal on stdout, write the JSON report with --output-file instead."
+                .to_owned(),
+        );
+    }
+    Ok(())
+}
+
+/// The dashboard running on its own thread.
+pub struct Dashboard {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl Dashboard {
+    /// Shows `updates` until [`Dashboard::finish`], the keys pressed go to
+    /// `control`.
+    pub fn start(updates: Receiver<ScanUpdate>, control: ScanControl) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        let thread = thread::spawn(move || run(&updates, &control, &finished));
+        Self { done, thread }
+    }
+
+    /// Draws the last updates and gives the terminal back.
+    pub fn finish(self) -> io::Result<()> {
+        self.done.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("dashboard panicked")))
+    }
+}
+
+#[cfg(unix)]
+fn run(updates: &Receiver<ScanUpdate>, control: &ScanControl, done: &AtomicBool) -> io::Result<()> {
+    use std::time::Instant;
+    use terminal::Screen;
+    use view::{Command, View};
+
+    let (screen, keys) = Screen::enter()?;
+    let mut view = View::default();
+    loop {
+        // Checked first, so the updates sent before the end are all shown.
+        let finished = done.load(Ordering::Relaxed);
+        let now = Instant::now();
+        for update in updates.try_iter() {
+            view.apply(update, now);
+        }
+        for key in keys.try_iter() {
+            match view.press(key) {
+                Some(Command::Pause) => control.set_paused(true),
+                Some(Command::Resume) => control.set_paused(false),
+                Some(Command::Skip(ip)) => control.skip(ip),
+                Some(Command::Quit) => control.stop(),
+                None => {}
+            }
+        }
+
+        let (width, height) = Screen::size();
+        screen.draw(&view.render(width, height, now))?;
+        if finished {
+            return Ok(());
+        }
+        thread::sleep(FRAME);
+    }
+}
+
+#[cfg(not(unix))]
+fn run(_: &Receiver<ScanUpdate>, _: &ScanControl, _: &AtomicBool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the dashboard needs a Unix terminal",
+    ))
+}
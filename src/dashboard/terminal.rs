@@ -0,0 +1,101 @@
+//! The terminal of the dashboard: raw mode through `stty`, the alternate
+//! screen, and the keys pressed.
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// The terminal in raw mode on the alternate screen, set back when dropped.
+pub struct Screen {
+    /// The `stty -g` settings to restore, None when stdin isn't a terminal.
+    saved: Option<String>,
+    keys: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+impl Screen {
+    /// Takes the terminal over, with the keys pressed sent to the receiver.
+    /// Without a terminal on stdin, no key is ever sent.
+    pub fn enter() -> io::Result<(Self, Receiver<u8>)> {
+        let (sender, keys) = mpsc::channel();
+        let saved = stty(&["-g"]).ok();
+        let mut screen = Self { saved, keys: None };
+        if screen.saved.is_some() {
+            // Reads give up after a tenth of a second, so the reader can stop.
+            stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let reading = stop.clone();
+            let reader = thread::spawn(move || {
+                let mut stdin = io::stdin();
+                let mut buffer = [0; 16];
+                while !reading.load(Ordering::Relaxed) {
+                    match stdin.read(&mut buffer) {
+                        Ok(read) => {
+                            for key in &buffer[..read] {
+                                if sender.send(*key).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        Err(_) => return,
+                    }
+                }
+            });
+            screen.keys = Some((stop, reader));
+        }
+
+        // The alternate screen, without a cursor.
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok((screen, keys))
+    }
+
+    /// Draws `lines` over the previous frame.
+    pub fn draw(&self, lines: &[String]) -> io::Result<()> {
+        let mut frame = String::from("\x1b[H");
+        for line in lines {
+            frame.push_str(line);
+            frame.push_str("\x1b[K\r\n");
+        }
+        frame.push_str("\x1b[J");
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(frame.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// The width and height of the terminal.
+    pub fn size() -> (usize, usize) {
+        match terminal_size::terminal_size() {
+            Some((width, height)) => (width.0.into(), height.0.into()),
+            None => (80, 24),
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if let Some((stop, reader)) = self.keys.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = reader.join();
+        }
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        if let Some(saved) = &self.saved {
+            let _ = stty(&[saved.trim()]);
+        }
+    }
+}
+
+/// Runs `stty` on the terminal of stdin.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
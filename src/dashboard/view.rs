@@ -0,0 +1,400 @@
+//! What the dashboard shows, worked out from the updates of the scan and
+//! the keys pressed alone, so that it can be checked without a terminal.
+use crate::scanner::ScanUpdate;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// How many open sockets the feed remembers.
+const FEED_LENGTH: usize = 256;
+/// The probe rate is measured over this much time.
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+const BAR_WIDTH: usize = 20;
+
+/// How far the scan of a host got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostProgress {
+    pub ip: IpAddr,
+    pub sockets: usize,
+    pub probed: usize,
+    pub skipped: usize,
+    /// The open ports, ascending.
+    pub open: Vec<u16>,
+    /// Whether the host was skipped with the keyboard.
+    pub skipping: bool,
+}
+
+impl HostProgress {
+    pub fn is_finished(&self) -> bool {
+        self.probed + self.skipped >= self.sockets
+    }
+
+    fn bar(&self) -> String {
+        let done = (self.probed + self.skipped).min(self.sockets);
+        let filled = match self.sockets {
+            0 => BAR_WIDTH,
+            sockets => done * BAR_WIDTH / sockets,
+        };
+        let status = match (self.skipping, self.sockets) {
+            (true, _) => "skip".to_owned(),
+            (false, 0) => "100%".to_owned(),
+            (false, sockets) => format!("{:>3}%", done * 100 / sockets),
+        };
+        format!(
+            "[{}{}] {status}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled)
+        )
+    }
+}
+
+/// What a key press asks of the scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Skip(IpAddr),
+    /// Stops probing, the results found so far are printed as usual.
+    Quit,
+}
+
+/// The state of the dashboard.
+#[derive(Debug, Default)]
+pub struct View {
+    hosts: Vec<HostProgress>,
+    index: HashMap<IpAddr, usize>,
+    /// The open sockets, with when they were found since the start.
+    feed: VecDeque<(Duration, SocketAddr)>,
+    /// When the probes of the rate window finished.
+    probes: VecDeque<Instant>,
+    batch_size: u16,
+    /// How long the scan should still take, as last forecast.
+    left: Option<Duration>,
+    started: Option<Instant>,
+    /// The host probed last.
+    current: Option<IpAddr>,
+    paused: bool,
+    quitting: bool,
+}
+
+impl View {
+    /// Takes `update` of the scan in, received at `now`.
+    pub fn apply(&mut self, update: ScanUpdate, now: Instant) {
+        match update {
+            ScanUpdate::Started { hosts, batch_size } => {
+                self.started.get_or_insert(now);
+                self.batch_size = batch_size;
+                // A fallback scan adds its hosts to the ones already shown.
+                for (ip, sockets) in hosts {
+                    match self.index.get(&ip) {
+                        Some(position) => self.hosts[*position].sockets += sockets,
+                        None => {
+                            self.index.insert(ip, self.hosts.len());
+                            self.hosts.push(HostProgress {
+                                ip,
+                                sockets,
+                                probed: 0,
+                                skipped: 0,
+                                open: Vec::new(),
+                                skipping: false,
+                            });
+                        }
+                    }
+                }
+            }
+            ScanUpdate::Probed(socket) => {
+                if let Some(host) = self.host_mut(socket.ip()) {
+                    host.probed += 1;
+                }
+                self.current = Some(socket.ip());
+                self.probes.push_back(now);
+                while self
+                    .probes
+                    .front()
+                    .is_some_and(|probe| now.duration_since(*probe) > RATE_WINDOW)
+                {
+                    self.probes.pop_front();
+                }
+            }
+            ScanUpdate::Open(socket) => {
+                if let Some(host) = self.host_mut(socket.ip()) {
+                    if let Err(position) = host.open.binary_search(&socket.port()) {
+                        host.open.insert(position, socket.port());
+                    }
+                }
+                let since = self
+                    .started
+                    .map(|started| now.duration_since(started))
+                    .unwrap_or_default();
+                self.feed.push_back((since, socket));
+                if self.feed.len() > FEED_LENGTH {
+                    self.feed.pop_front();
+                }
+            }
+            ScanUpdate::Skipped(socket) => {
+                if let Some(host) = self.host_mut(socket.ip()) {
+                    host.skipped += 1;
+                }
+            }
+            ScanUpdate::Forecast(forecast) => {
+                self.left = Some(Duration::from_secs(forecast.eta_secs));
+            }
+            ScanUpdate::Served(..) => {}
+        }
+    }
+
+    /// Reacts to `key`, the command for the scan if it asks for one.
+    pub fn press(&mut self, key: u8) -> Option<Command> {
+        match key {
+            b'p' | b' ' => {
+                self.paused = !self.paused;
+                Some(if self.paused {
+                    Command::Pause
+                } else {
+                    Command::Resume
+                })
+            }
+            b's' => {
+                let ip = self.current_host()?;
+                self.host_mut(ip)?.skipping = true;
+                Some(Command::Skip(ip))
+            }
+            // Ctrl-C arrives as a byte in raw mode.
+            b'q' | 0x03 => {
+                self.quitting = true;
+                Some(Command::Quit)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn hosts(&self) -> &[HostProgress] {
+        &self.hosts
+    }
+
+    /// The host being scanned, the one probed last unless it's done.
+    /// Otherwise the first one with probes left.
+    pub fn current_host(&self) -> Option<IpAddr> {
+        let scanning = |host: &&HostProgress| !host.is_finished() && !host.skipping;
+        self.current
+            .and_then(|ip| self.host(ip))
+            .filter(scanning)
+            .or_else(|| self.hosts.iter().find(scanning))
+            .map(|host| host.ip)
+    }
+
+    /// Probes finished per second, over the last two seconds.
+    pub fn rate(&self, now: Instant) -> f64 {
+        let recent = self
+            .probes
+            .iter()
+            .filter(|probe| now.duration_since(**probe) <= RATE_WINDOW)
+            .count();
+        recent as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    /// The lines of the screen, at most `height` of at most `width`
+    /// characters.
+    pub fn render(&self, width: usize, height: usize, now: Instant) -> Vec<String> {
+        let sockets: usize = self.hosts.iter().map(|host| host.sockets).sum();
+        let done: usize = self
+            .hosts
+            .iter()
+            .map(|host| host.probed + host.skipped)
+            .sum();
+        let open: usize = self.hosts.iter().map(|host| host.open.len()).sum();
+        let elapsed = self
+            .started
+            .map(|started| now.duration_since(started))
+            .unwrap_or_default();
+        let state = match (self.quitting, self.paused) {
+            (true, _) => "  QUITTING",
+            (false, true) => "  PAUSED",
+            (false, false) => "",
+        };
+        let left = self
+            .left
+            .map(|left| format!("  ~{} left", clock(left)))
+            .unwrap_or_default();
+        let mut lines = vec![
+            format!(
+                "RustScan  {}  {done}/{sockets} sockets  {open} open  {:.0}/s  batch {}{left}{state}",
+                clock(elapsed),
+                self.rate(now),
+                self.batch_size
+            ),
+            String::new(),
+            format!("  {:<39} {:<27} OPEN", "HOST", "PROGRESS"),
+        ];
+
+        // Header, table header, blank, feed header and keys.
+        let room = height.saturating_sub(lines.len() + 3);
+        let rows = self.hosts.len().min((room / 2).max(1));
+        let current = self
+            .current_host()
+            .and_then(|ip| self.index.get(&ip).copied());
+        let first = current
+            .map(|current| current.saturating_sub(rows / 2))
+            .unwrap_or_default()
+            .min(self.hosts.len() - rows);
+        for (position, host) in self.hosts.iter().enumerate().skip(first).take(rows) {
+            let ports: Vec<String> = host.open.iter().map(ToString::to_string).collect();
+            lines.push(format!(
+                "{} {:<39} {} {}",
+                if Some(position) == current { '>' } else { ' ' },
+                host.ip.to_string(),
+                host.bar(),
+                ports.join(",")
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("OPEN PORTS".to_owned());
+        let feed = room.saturating_sub(rows);
+        let skip = self.feed.len().saturating_sub(feed);
+        for (since, socket) in self.feed.iter().skip(skip) {
+            lines.push(format!("  {} {socket}", clock(*since)));
+        }
+        lines.push("p pause/resume  s skip host  q quit".to_owned());
+
+        lines.truncate(height);
+        for line in &mut lines {
+            if let Some((cut, _)) = line.char_indices().nth(width) {
+                line.truncate(cut);
+            }
+        }
+        lines
+    }
+
+    fn host(&self, ip: IpAddr) -> Option<&HostProgress> {
+        self.index.get(&ip).map(|position| &self.hosts[*position])
+    }
+
+    fn host_mut(&mut self, ip: IpAddr) -> Option<&mut HostProgress> {
+        let position = *self.index.get(&ip)?;
+        self.hosts.get_mut(position)
+    }
+}
+
+/// `mm:ss`, or `h:mm:ss` past an hour.
+fn clock(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds / 3_600 {
+        0 => format!("{:02}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, View};
+    use crate::scanner::{Forecast, ScanUpdate};
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::{Duration, Instant};
+
+    fn socket(ip: &str, port: u16) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), port)
+    }
+
+    /// Two hosts of four sockets, the first one done with port 22 open and
+    /// the second one probed once.
+    fn view(start: Instant) -> View {
+        let mut view = View::default();
+        let updates = vec![
+            ScanUpdate::Started {
+                hosts: vec![
+                    ("10.0.0.1".parse().unwrap(), 4),
+                    ("10.0.0.2".parse().unwrap(), 4),
+                ],
+                batch_size: 500,
+            },
+            ScanUpdate::Probed(socket("10.0.0.1", 80)),
+            ScanUpdate::Probed(socket("10.0.0.1", 22)),
+            ScanUpdate::Open(socket("10.0.0.1", 22)),
+            ScanUpdate::Probed(socket("10.0.0.1", 443)),
+            ScanUpdate::Probed(socket("10.0.0.2", 80)),
+            ScanUpdate::Probed(socket("10.0.0.1", 21)),
+        ];
+        for (second, update) in updates.into_iter().enumerate() {
+            view.apply(update, start + Duration::from_millis(second as u64 * 500));
+        }
+        view
+    }
+
+    #[test]
+    fn progress_follows_the_updates() {
+        let start = Instant::now();
+        let view = view(start);
+
+        let hosts = view.hosts();
+        assert_eq!((hosts[0].probed, hosts[0].open.clone()), (4, vec![22]));
+        assert!(hosts[0].is_finished());
+        assert_eq!((hosts[1].probed, hosts[1].is_finished()), (1, false));
+        // The host probed last is done, the other one is still going.
+        assert_eq!(view.current_host(), Some("10.0.0.2".parse().unwrap()));
+        // Four probes finished in the last two seconds.
+        assert_eq!(view.rate(start + Duration::from_secs(3)), 2.0);
+    }
+
+    #[test]
+    fn keys_steer_the_scan() {
+        let mut view = view(Instant::now());
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert_eq!(view.press(b'p'), Some(Command::Pause));
+        assert_eq!(view.press(b' '), Some(Command::Resume));
+        assert_eq!(view.press(b's'), Some(Command::Skip(second)));
+        assert!(view.hosts()[1].skipping);
+        // Nothing is left to skip.
+        assert_eq!(view.press(b's'), None);
+        assert_eq!(view.press(b'x'), None);
+        assert_eq!(view.press(0x03), Some(Command::Quit));
+
+        let now = Instant::now() + Duration::from_secs(3);
+        view.apply(
+            ScanUpdate::Forecast(Forecast {
+                remaining: 3,
+                eta_secs: 75,
+                ..Forecast::default()
+            }),
+            now,
+        );
+        assert!(view.render(120, 30, now)[0].ends_with("batch 500  ~01:15 left  QUITTING"));
+        view.apply(ScanUpdate::Skipped(socket("10.0.0.2", 22)), now);
+        assert_eq!(view.hosts()[1].skipped, 1);
+    }
+
+    #[test]
+    fn screen_fits_the_terminal() {
+        let start = Instant::now();
+        let mut view = view(start);
+        let now = start + Duration::from_secs(3);
+
+        let lines = view.render(120, 30, now);
+        assert_eq!(
+            lines[0],
+            "RustScan  00:03  5/8 sockets  1 open  2/s  batch 500"
+        );
+        assert_eq!(
+            lines[3],
+            format!("  {:<39} [####################] 100% 22", "10.0.0.1")
+        );
+        assert_eq!(
+            lines[4],
+            format!("> {:<39} [#####---------------]  25% ", "10.0.0.2")
+        );
+        assert_eq!(&lines[6..8], ["OPEN PORTS", "  00:01 10.0.0.1:22"]);
+        assert_eq!(lines.last().unwrap(), "p pause/resume  s skip host  q quit");
+
+        // Small terminals show the current host and the newest open ports.
+        for port in 1..=20 {
+            view.apply(ScanUpdate::Open(socket("10.0.0.2", port)), now);
+        }
+        let lines = view.render(30, 9, now);
+        assert_eq!(lines.len(), 9);
+        assert!(lines.iter().all(|line| line.chars().count() <= 30));
+        assert!(lines[3].starts_with("> 10.0.0.2"), "{:?}", lines);
+        assert_eq!(lines[6], "  00:03 10.0.0.2:19");
+        assert_eq!(lines[7], "  00:03 10.0.0.2:20");
+    }
+}
@@ -0,0 +1,429 @@
+//! The host discovery of `--discovery`, leaving out the hosts which are
+//! down before their ports are scanned.
+//!
+//! Every host is probed with a [`Technique`], by default [`ConnectPing`]: a
+//! connect to the ports of [`DISCOVERY_PORTS`] in turn, the first answer,
+//! connected or refused, telling the host is up. A host none of them gets
+//! an answer from is down.
+//!
+//! The port scan doesn't wait for the discovery to be over. The hosts found
+//! up are handed over in [`Waves`], the ones found since the last wave, and
+//! scanned while the others are still being probed. With
+//! `--discovery-timeout` the discovery stops at the deadline: the hosts
+//! whose probes haven't resolved by then are undetermined, scanned in the
+//! last wave as assumed up, or left out as assumed down with
+//! `--discovery-undetermined dead`, see [`Liveness`].
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use serde_derive::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The ports [`ConnectPing`] connects to, in order.
+pub const DISCOVERY_PORTS: [u16; 2] = [80, 443];
+
+/// Whether a host is up, as its discovery probes tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Alive,
+    Dead,
+}
+
+/// How the hosts are probed.
+pub trait Technique: Send + Sync {
+    /// Probes `ip`, for as long as it takes.
+    fn probe(&self, ip: IpAddr) -> BoxFuture<'_, Verdict>;
+}
+
+/// A connect to every port in turn until one answers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectPing {
+    pub ports: Vec<u16>,
+    /// The timeout of every connect.
+    pub timeout: Duration,
+}
+
+impl Technique for ConnectPing {
+    fn probe(&self, ip: IpAddr) -> BoxFuture<'_, Verdict> {
+        Box::pin(async move {
+            for port in &self.ports {
+                let socket = SocketAddr::new(ip, *port);
+                match io::timeout(self.timeout, TcpStream::connect(socket)).await {
+                    Ok(_) => return Verdict::Alive,
+                    // A refused connection still proves the host is up.
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        return Verdict::Alive
+                    }
+                    Err(_) => {}
+                }
+            }
+            Verdict::Dead
+        })
+    }
+}
+
+/// What the hosts whose probes haven't resolved at the deadline are taken
+/// for, with `--discovery-undetermined`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Undetermined {
+    Alive,
+    Dead,
+}
+
+/// What the discovery made of a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Liveness {
+    Alive,
+    Dead,
+    /// Its probes hadn't resolved at the deadline, it was scanned anyway.
+    AssumedAlive,
+    /// Its probes hadn't resolved at the deadline, it was left out.
+    AssumedDead,
+}
+
+impl Liveness {
+    /// Whether the ports of the host are scanned.
+    pub fn is_scanned(self) -> bool {
+        matches!(self, Liveness::Alive | Liveness::AssumedAlive)
+    }
+}
+
+/// The hosts of a discovery, by what their probes told.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Discovered {
+    pub alive: Vec<IpAddr>,
+    pub dead: Vec<IpAddr>,
+    /// The hosts whose probes hadn't resolved at the deadline, or hadn't
+    /// started, in the order given.
+    pub undetermined: Vec<IpAddr>,
+}
+
+/// Probes `ips` with `technique`, at most `concurrency` at a time, until
+/// every one is resolved or `deadline` is over. `found` gets every host as
+/// soon as it's found up.
+pub async fn discover(
+    technique: &dyn Technique,
+    ips: &[IpAddr],
+    concurrency: usize,
+    deadline: Option<Duration>,
+    mut found: impl FnMut(IpAddr),
+) -> Discovered {
+    let start = Instant::now();
+    let mut discovered = Discovered::default();
+    let mut queue = ips.iter().copied();
+    let mut in_flight = FuturesUnordered::new();
+    let mut launch = |in_flight: &mut FuturesUnordered<_>| {
+        if let Some(ip) = queue.next() {
+            in_flight.push(async move { (ip, technique.probe(ip).await) });
+        }
+    };
+    for _ in 0..concurrency.max(1) {
+        launch(&mut in_flight);
+    }
+
+    loop {
+        let next = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_sub(start.elapsed());
+                match async_std::future::timeout(left, in_flight.next()).await {
+                    Ok(next) => next,
+                    // The deadline is over, the rest is undetermined.
+                    Err(_) => break,
+                }
+            }
+            None => in_flight.next().await,
+        };
+        let Some((ip, verdict)) = next else {
+            break;
+        };
+        match verdict {
+            Verdict::Alive => {
+                found(ip);
+                discovered.alive.push(ip);
+            }
+            Verdict::Dead => discovered.dead.push(ip),
+        }
+        launch(&mut in_flight);
+    }
+
+    let resolved: HashSet<IpAddr> = discovered
+        .alive
+        .iter()
+        .chain(&discovered.dead)
+        .copied()
+        .collect();
+    discovered.undetermined = ips
+        .iter()
+        .copied()
+        .filter(|ip| !resolved.contains(ip))
+        .collect();
+    discovered
+}
+
+#[derive(Debug)]
+enum Message {
+    Alive(IpAddr),
+    Finished(Discovered),
+}
+
+/// The hosts found up, handed over to the port scan as the discovery, run
+/// on its own thread, goes on.
+#[derive(Debug)]
+pub struct Waves {
+    receiver: Receiver<Message>,
+    undetermined: Undetermined,
+    liveness: HashMap<IpAddr, Liveness>,
+    done: bool,
+}
+
+impl Waves {
+    /// Starts the discovery of `ips`, see [`discover`].
+    pub fn start(
+        technique: Arc<dyn Technique>,
+        ips: Vec<IpAddr>,
+        concurrency: usize,
+        deadline: Option<Duration>,
+        undetermined: Undetermined,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let found = sender.clone();
+            let discovered = async_std::task::block_on(discover(
+                technique.as_ref(),
+                &ips,
+                concurrency,
+                deadline,
+                move |ip| {
+                    let _ = found.send(Message::Alive(ip));
+                },
+            ));
+            let _ = sender.send(Message::Finished(discovered));
+        });
+        Self {
+            receiver,
+            undetermined,
+            liveness: HashMap::new(),
+            done: false,
+        }
+    }
+
+    /// The hosts to scan next: the ones found up since the last wave,
+    /// waiting for one when there are none yet, and the undetermined ones
+    /// with the last wave when they are assumed up. None once the discovery
+    /// is over and every host it found up was handed over.
+    pub fn next_wave(&mut self) -> Option<Vec<IpAddr>> {
+        let mut wave = Vec::new();
+        while !self.done {
+            let message = if wave.is_empty() {
+                self.receiver.recv().ok()
+            } else {
+                match self.receiver.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => None,
+                }
+            };
+            match message {
+                Some(Message::Alive(ip)) => {
+                    self.liveness.insert(ip, Liveness::Alive);
+                    wave.push(ip);
+                }
+                Some(Message::Finished(discovered)) => {
+                    for ip in discovered.dead {
+                        self.liveness.insert(ip, Liveness::Dead);
+                    }
+                    let assumed = match self.undetermined {
+                        Undetermined::Alive => Liveness::AssumedAlive,
+                        Undetermined::Dead => Liveness::AssumedDead,
+                    };
+                    for ip in discovered.undetermined {
+                        self.liveness.insert(ip, assumed);
+                        if assumed.is_scanned() {
+                            wave.push(ip);
+                        }
+                    }
+                    self.done = true;
+                }
+                // The discovery thread is gone, nothing more is coming.
+                None => self.done = true,
+            }
+        }
+        Some(wave).filter(|wave| !wave.is_empty())
+    }
+
+    /// What the discovery made of every host so far.
+    pub fn liveness(&self) -> &HashMap<IpAddr, Liveness> {
+        &self.liveness
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, Liveness, Technique, Undetermined, Verdict, Waves};
+    use futures::future::BoxFuture;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Answers every host with its verdict after its delay, never for the
+    /// hosts without one.
+    #[derive(Default)]
+    struct Mock {
+        hosts: HashMap<IpAddr, (u64, Verdict)>,
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+        resolved: Mutex<Vec<IpAddr>>,
+    }
+
+    impl Mock {
+        fn new(hosts: &[(&str, u64, Verdict)]) -> Self {
+            Self {
+                hosts: hosts
+                    .iter()
+                    .map(|(ip, delay, verdict)| (ip.parse().unwrap(), (*delay, *verdict)))
+                    .collect(),
+                ..Self::default()
+            }
+        }
+
+        fn is_resolved(&self, ip: &str) -> bool {
+            self.resolved.lock().unwrap().contains(&ip.parse().unwrap())
+        }
+    }
+
+    impl Technique for Mock {
+        fn probe(&self, ip: IpAddr) -> BoxFuture<'_, Verdict> {
+            Box::pin(async move {
+                let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.most_in_flight.fetch_max(now, Ordering::SeqCst);
+                let (delay, verdict) = self
+                    .hosts
+                    .get(&ip)
+                    .copied()
+                    .unwrap_or((3_600_000, Verdict::Dead));
+                async_std::task::sleep(Duration::from_millis(delay)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                self.resolved.lock().unwrap().push(ip);
+                verdict
+            })
+        }
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn hosts_are_found_as_they_answer() {
+        let mock = Mock::new(&[
+            ("10.0.0.1", 30, Verdict::Alive),
+            ("10.0.0.2", 0, Verdict::Dead),
+            ("10.0.0.3", 100, Verdict::Alive),
+            ("10.0.0.4", 0, Verdict::Alive),
+        ]);
+        let mut found = Vec::new();
+        let all = ips(&["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"]);
+        let discovered =
+            async_std::task::block_on(discover(&mock, &all, 2, None, |ip| found.push(ip)));
+
+        // 10.0.0.4 only starts once 10.0.0.1 is over, and still answers
+        // before 10.0.0.3.
+        assert_eq!(found, ips(&["10.0.0.1", "10.0.0.4", "10.0.0.3"]));
+        assert_eq!(discovered.alive, found);
+        assert_eq!(discovered.dead, ips(&["10.0.0.2"]));
+        assert!(discovered.undetermined.is_empty());
+        assert_eq!(mock.most_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn hosts_unresolved_at_the_deadline_are_undetermined() {
+        // 10.0.0.2 never answers, 10.0.0.4 never gets to start.
+        let mock = Mock::new(&[
+            ("10.0.0.1", 0, Verdict::Alive),
+            ("10.0.0.3", 0, Verdict::Dead),
+        ]);
+        let all = ips(&["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"]);
+        let discovered = async_std::task::block_on(discover(
+            &mock,
+            &all,
+            1,
+            Some(Duration::from_millis(100)),
+            |_| {},
+        ));
+        assert_eq!(discovered.alive, ips(&["10.0.0.1"]));
+        assert!(discovered.dead.is_empty());
+        assert_eq!(
+            discovered.undetermined,
+            ips(&["10.0.0.2", "10.0.0.3", "10.0.0.4"])
+        );
+    }
+
+    #[test]
+    fn hosts_found_up_are_scanned_while_the_others_are_probed() {
+        let mock = Arc::new(Mock::new(&[
+            ("10.0.0.1", 0, Verdict::Alive),
+            ("10.0.0.2", 300, Verdict::Alive),
+            ("10.0.0.3", 0, Verdict::Dead),
+        ]));
+        let all = ips(&["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"]);
+        let mut waves = Waves::start(
+            mock.clone(),
+            all,
+            4,
+            Some(Duration::from_millis(600)),
+            Undetermined::Alive,
+        );
+
+        // The first wave doesn't wait for the slower hosts.
+        assert_eq!(waves.next_wave(), Some(ips(&["10.0.0.1"])));
+        assert!(!mock.is_resolved("10.0.0.2"));
+        assert_eq!(waves.next_wave(), Some(ips(&["10.0.0.2"])));
+        // 10.0.0.4 never answered, it comes last as assumed up.
+        assert_eq!(waves.next_wave(), Some(ips(&["10.0.0.4"])));
+        assert_eq!(waves.next_wave(), None);
+        assert_eq!(waves.next_wave(), None);
+
+        let liveness = waves.liveness();
+        let of = |ip: &str| liveness[&ip.parse::<IpAddr>().unwrap()];
+        assert_eq!(of("10.0.0.1"), Liveness::Alive);
+        assert_eq!(of("10.0.0.2"), Liveness::Alive);
+        assert_eq!(of("10.0.0.3"), Liveness::Dead);
+        assert_eq!(of("10.0.0.4"), Liveness::AssumedAlive);
+    }
+
+    #[test]
+    fn undetermined_hosts_can_be_assumed_down() {
+        let mock = Arc::new(Mock::new(&[("10.0.0.1", 0, Verdict::Alive)]));
+        let mut waves = Waves::start(
+            mock,
+            ips(&["10.0.0.1", "10.0.0.2"]),
+            2,
+            Some(Duration::from_millis(100)),
+            Undetermined::Dead,
+        );
+        assert_eq!(waves.next_wave(), Some(ips(&["10.0.0.1"])));
+        assert_eq!(waves.next_wave(), None);
+        let assumed = waves.liveness()[&"10.0.0.2".parse::<IpAddr>().unwrap()];
+        assert_eq!(assumed, Liveness::AssumedDead);
+        assert!(!assumed.is_scanned());
+    }
+
+    #[test]
+    fn no_wave_without_hosts_up() {
+        let mock = Arc::new(Mock::new(&[("10.0.0.1", 0, Verdict::Dead)]));
+        let mut waves = Waves::start(mock, ips(&["10.0.0.1"]), 1, None, Undetermined::Alive);
+        assert_eq!(waves.next_wave(), None);
+        assert_eq!(waves.liveness().len(), 1);
+    }
+}
@@ -0,0 +1,95 @@
+//! Checks which destination ports a network lets out, see `--egress-check`.
+//!
+//! The scan is turned around: its only host is a listener of the tester,
+//! outside of the network, which accepts connections on every port, and a
+//! port found open is one the network let a connection out to. The ports
+//! refused or timed out are blocked, so a listener missing some ports makes
+//! them look blocked too, `rustscan listen --ports 1-65535` is one which
+//! doesn't. The report says it's an egress check in its `mode`, never to be
+//! mistaken for the results of a scan.
+use crate::report::Mode;
+use crate::schema::SchemaVersion;
+use serde_derive::Serialize;
+use std::net::{IpAddr, SocketAddr};
+
+/// The ports allowed out to the listener, and the ones blocked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EgressReport {
+    /// The version of this document, see [`crate::schema`].
+    pub schema_version: SchemaVersion,
+    pub mode: Mode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// The listener as it was given.
+    pub listener: String,
+    pub address: IpAddr,
+    /// In ascending order, like the blocked ones.
+    pub allowed: Vec<u16>,
+    pub blocked: Vec<u16>,
+}
+
+impl EgressReport {
+    /// Splits the ports `tested` against the listener at `address` by
+    /// whether they are among the `open` sockets.
+    pub fn new(listener: &str, address: IpAddr, tested: &[u16], open: &[SocketAddr]) -> Self {
+        let mut tested = tested.to_vec();
+        tested.sort_unstable();
+        tested.dedup();
+        let (allowed, blocked) = tested.into_iter().partition(|port| {
+            open.iter()
+                .any(|socket| socket.ip() == address && socket.port() == *port)
+        });
+        Self {
+            schema_version: SchemaVersion::default(),
+            mode: Mode::Egress,
+            run_id: None,
+            listener: listener.to_owned(),
+            address,
+            allowed,
+            blocked,
+        }
+    }
+
+    /// The greppable line of the check, `egress ip -> [port,port]` with the
+    /// ports allowed out.
+    pub fn greppable(&self) -> String {
+        let ports: Vec<String> = self.allowed.iter().map(ToString::to_string).collect();
+        format!("egress {} -> [{}]", self.address, ports.join(","))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the egress report.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EgressReport;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn ports_are_split_by_egress() {
+        let open: Vec<SocketAddr> = ["192.0.2.7:443", "192.0.2.7:53", "198.51.100.1:22"]
+            .iter()
+            .map(|socket| socket.parse().unwrap())
+            .collect();
+        let mut report = EgressReport::new(
+            "egress.example",
+            "192.0.2.7".parse().unwrap(),
+            &[443, 22, 53, 80, 443],
+            &open,
+        );
+        assert_eq!(report.allowed, [53, 443]);
+        // Another host answering on 22 lets nothing out to the listener.
+        assert_eq!(report.blocked, [22, 80]);
+        assert_eq!(report.greppable(), "egress 192.0.2.7 -> [53,443]");
+
+        report.run_id = Some("acme-0424".to_owned());
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["mode"], "egress");
+        assert_eq!(json["listener"], "egress.example");
+        assert_eq!(json["run_id"], "acme-0424");
+        assert_eq!(json["blocked"], serde_json::json!([22, 80]));
+        assert!(json.get("hosts").is_none());
+    }
+}
@@ -0,0 +1,227 @@
+//! The warnings and errors RustScan reports, each with a stable code.
+//!
+//! With `--errors-format json` every one of them is printed on stderr as a
+//! single line JSON object, see [`ErrorEvent`], so that programs driving
+//! RustScan don't have to parse the free text.
+use serde_derive::Serialize;
+
+/// The stable code of every warning and error. The serialized names never
+/// change, new codes are only added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    /// A warning without a more specific code.
+    Warning,
+    /// The command line arguments could not be parsed.
+    InvalidArguments,
+    /// The configuration file could not be parsed.
+    InvalidConfig,
+    /// A target token, or a line of a targets file, is malformed.
+    InvalidTarget,
+    /// A host or targets file could not be resolved.
+    UnresolvedHost,
+    /// The `--scope` allowlist could not be read.
+    InvalidScope,
+    /// A target is outside the `--scope` allowlist.
+    OutOfScope,
+    /// None of the targets could be resolved.
+    NoTargets,
+    /// A host has no port left to scan, its exclusions took every one.
+    NoPortsLeft,
+    /// Neither family of a dual-stack hostname answered the race.
+    NoFamilyAnswered,
+    /// The file limit could not be set or raised.
+    UlimitFailed,
+    /// The file limit is too low for the batch size.
+    FileLimitTooLow,
+    /// The batch size was lowered to fit in the file limit.
+    BatchSizeLowered,
+    /// A socket option can't be set on this platform.
+    UnsupportedSocketOption,
+    /// The interface of hosts of `--interface-map` can't be used, or the
+    /// interfaces can't be listed.
+    InterfaceUnusable,
+    /// The bastion of `--ssh-jump` could not be connected to.
+    SshJumpFailed,
+    /// The SOCKS proxy of `--proxy` or `--use-system-proxy` can't be used.
+    ProxyFailed,
+    /// The reflector of `--reflector` could not be reached or served.
+    ReflectorFailed,
+    /// The reflector saw other source ports than the ones bound, a NAT
+    /// rewrites them.
+    SourcePortsRewritten,
+    /// A port of `rustscan listen` could not be bound.
+    ListenFailed,
+    /// Options which don't work together were given.
+    IncompatibleOptions,
+    /// A knock of the port-knocking sequence failed.
+    KnockFailed,
+    /// A host stopped answering its heartbeats during its scan.
+    HostDown,
+    /// A host seems to block the scan after a burst of probes.
+    Throttled,
+    /// A host rejected the scan as administratively prohibited, its other
+    /// ports were skipped.
+    AdminProhibited,
+    /// The network of the scanner seems down, most probes being unreachable.
+    NetworkDown,
+    /// The hosts which answered went silent at once, like when a conntrack
+    /// table is full.
+    ConntrackFull,
+    /// Connections fail on this machine in bursts under load, and are
+    /// retried.
+    BurstErrors,
+    /// The scan was paused, or stopped, outside of `--allowed-window`.
+    OutsideWindow,
+    /// A closure given to the scanner as a hook returned an error or
+    /// panicked.
+    HookFailed,
+    /// The results could not be stored in the cache.
+    CacheWriteFailed,
+    /// The JSON report of `--output-file`, or the page of `--html-report`,
+    /// could not be written.
+    ReportWriteFailed,
+    /// The page of `--serve-report` could not be served.
+    ServeFailed,
+    /// The checkpoint of `--allowed-window` could not be written.
+    CheckpointWriteFailed,
+    /// A file of `--export` could not be written.
+    ExportFailed,
+    /// Events of `--notify` could not be delivered.
+    NotifyFailed,
+    /// An output sink of a library user failed.
+    SinkFailed,
+    /// The profile measured by `rustscan tune` could not be stored.
+    ConfigWriteFailed,
+    /// The previous results of `--only-previously-open` could not be read.
+    InvalidPreviousResults,
+    /// A target had no open ports in the previous results.
+    NotPreviouslyOpen,
+    /// A target had no filtered ports in the report of `--rescan-filtered`.
+    NotPreviouslyFiltered,
+    /// The nmap or masscan output of `--import` could not be read.
+    InvalidImport,
+    /// The notes of `--notes` could not be read.
+    InvalidNotes,
+    /// The aliases of `--aliases` could not be read.
+    InvalidAliases,
+    /// The version rules of `--probe-rules` could not be read.
+    InvalidProbeRules,
+    /// The ports of `--order-file` could not be read.
+    InvalidOrderFile,
+    /// The configuration doesn't have the fingerprint of `--verify-config`.
+    ConfigMismatch,
+    /// Another run holding a `--lock` scans some of the same targets.
+    ScopeLocked,
+    /// The lockfile of `--lock` could not be written.
+    LockFailed,
+    /// The trace of `--trace-file` could not be written.
+    TraceWriteFailed,
+    /// The reports given to `rustscan merge` could not be combined.
+    InvalidReport,
+    /// The key of `--sign-key` or `rustscan verify` could not be read, or
+    /// the one of `rustscan keygen` written.
+    InvalidKey,
+    /// The signature of the report given to `rustscan verify` doesn't
+    /// check out.
+    SignatureMismatch,
+    /// Shards of the split are missing from, or repeated in, a merge.
+    IncompleteShards,
+    /// A bundle of `rustscan bundle` could not be written or read.
+    InvalidBundle,
+    /// Custom scripts of the bundle of `rustscan bundle run` are missing
+    /// from the scripts folder.
+    BundleScriptsMissing,
+    /// A check of `rustscan selftest` failed.
+    SelftestFailed,
+    /// A scanned host has no open ports.
+    NoOpenPorts,
+    /// The scripts could not be initiated.
+    ScriptsFailed,
+    /// A script can't run because of unmet requirements.
+    ScriptUnavailable,
+    /// A script run failed.
+    ScriptFailed,
+}
+
+impl ErrorCode {
+    /// The exit status of RustScan when this error aborts the run.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            // Same as the usage errors reported by the argument parser.
+            ErrorCode::InvalidArguments => 2,
+            // Nothing was wrong with the run, it had nothing to scan.
+            ErrorCode::NoTargets => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// A warning or error with its code and context.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorEvent {
+    pub code: ErrorCode,
+    pub message: String,
+    /// The host, target token or file the error is about.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+impl ErrorEvent {
+    pub fn new(code: ErrorCode, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            host: None,
+            port: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_host(mut self, host: impl ToString) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// The single line JSON object of this event.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Error events always serialize.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorCode, ErrorEvent};
+
+    #[test]
+    fn events_are_single_line_json() {
+        let event = ErrorEvent::new(ErrorCode::KnockFailed, "Knock failed:\nunreachable")
+            .with_host("10.0.0.1")
+            .with_port(7000);
+        assert_eq!(
+            event.to_json(),
+            r#"{"code":"knock-failed","message":"Knock failed:\nunreachable","host":"10.0.0.1","port":7000}"#
+        );
+
+        let event = ErrorEvent::new(ErrorCode::NoTargets, "No IPs could be resolved.");
+        assert_eq!(
+            event.to_json(),
+            r#"{"code":"no-targets","message":"No IPs could be resolved."}"#
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(ErrorCode::InvalidArguments.exit_code(), 2);
+        assert_eq!(ErrorCode::NoTargets.exit_code(), 3);
+        assert_eq!(ErrorCode::InvalidTarget.exit_code(), 1);
+    }
+}
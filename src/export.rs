@@ -0,0 +1,412 @@
+//! Writes the open sockets of a run for other tools, see `--export`.
+//!
+//! Every exporter writes its file once the scan and the scripts are over,
+//! with the open ports of every host in the order of the report:
+//!
+//! - `httpx` writes `scheme://host:port` lines, for httpx, nuclei or ffuf.
+//!   The scheme comes from what the probe pipeline or nmap found out about
+//!   the port, from the port otherwise. Ports found speaking something else
+//!   than HTTP or TLS, like SSH, are left out.
+//! - `hostport` writes `host:port` lines.
+//! - `nmap-targets` writes one host per line, for `nmap -iL`, and the ports
+//!   of every host next to it in `FILE.ports`, for `nmap -p`. The hosts
+//!   start with a `# run-id:` comment, which nmap skips, when the run has an
+//!   id. The other files take no comments, and get none.
+//!
+//! Hosts are written by their hostnames when they were given some, one line
+//! per hostname, by their address otherwise. The ports whose tries didn't
+//! agree are left out with `--export-exclude-flaky`.
+use crate::hints::ProtocolHint;
+use crate::input::PortRange;
+use crate::report::{HostReport, ScanReport};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The ports served over TLS by default, taken to be HTTPS without a hint.
+const HTTPS_PORTS: [u16; 4] = [443, 4443, 8443, 9443];
+
+/// The formats of `--export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exporter {
+    Httpx,
+    HostPort,
+    NmapTargets,
+}
+
+impl Exporter {
+    const ALL: [Exporter; 3] = [Exporter::Httpx, Exporter::HostPort, Exporter::NmapTargets];
+
+    fn name(self) -> &'static str {
+        match self {
+            Exporter::Httpx => "httpx",
+            Exporter::HostPort => "hostport",
+            Exporter::NmapTargets => "nmap-targets",
+        }
+    }
+}
+
+impl fmt::Display for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An exporter of `--export`, with the file it writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub exporter: Exporter,
+    pub path: PathBuf,
+}
+
+impl FromStr for Export {
+    type Err = String;
+
+    /// Parses `name=FILE`, like `httpx=web.txt`.
+    fn from_str(export: &str) -> Result<Self, String> {
+        let names = || {
+            Exporter::ALL
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let (name, path) = export
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=FILE, with NAME one of {}", names()))?;
+        let exporter = Exporter::ALL
+            .iter()
+            .copied()
+            .find(|exporter| exporter.name() == name.trim())
+            .ok_or_else(|| format!("{name:?} is not an exporter, use one of {}", names()))?;
+        if path.trim().is_empty() {
+            return Err(format!("{name} is missing its file"));
+        }
+        Ok(Self {
+            exporter,
+            path: PathBuf::from(path.trim()),
+        })
+    }
+}
+
+impl Export {
+    /// The files of this export with their content, for the open ports of
+    /// the hosts of `report`, but the flaky ones when `exclude_flaky`.
+    pub fn files(&self, report: &ScanReport, exclude_flaky: bool) -> Vec<(PathBuf, String)> {
+        let hosts: Vec<Exported> = report
+            .hosts
+            .iter()
+            .map(|host| {
+                let flaky = if exclude_flaky {
+                    host.flaky_ports()
+                } else {
+                    Vec::new()
+                };
+                Exported {
+                    host,
+                    ports: host
+                        .ports
+                        .iter()
+                        .copied()
+                        .filter(|port| !flaky.contains(port))
+                        .collect(),
+                }
+            })
+            .filter(|exported| !exported.ports.is_empty())
+            .collect();
+        match self.exporter {
+            Exporter::Httpx => vec![(self.path.clone(), httpx(&hosts))],
+            Exporter::HostPort => {
+                let lines = hosts.iter().flat_map(|exported| {
+                    names(exported.host).into_iter().flat_map(move |name| {
+                        exported.ports.iter().map(move |port| {
+                            format!("{}:{port}", authority(&name, exported.host.ip))
+                        })
+                    })
+                });
+                vec![(self.path.clone(), lines_of(lines))]
+            }
+            Exporter::NmapTargets => {
+                let mut ports: Vec<u16> = hosts
+                    .iter()
+                    .flat_map(|exported| exported.ports.clone())
+                    .collect();
+                ports.sort_unstable();
+                ports.dedup();
+                let ports = if ports.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n", PortRange::from_ports(&ports))
+                };
+                let mut ports_path = self.path.clone().into_os_string();
+                ports_path.push(".ports");
+                let header = report.run_id.iter().map(|id| format!("# run-id: {id}"));
+                vec![
+                    (
+                        self.path.clone(),
+                        lines_of(
+                            header.chain(hosts.iter().flat_map(|exported| names(exported.host))),
+                        ),
+                    ),
+                    (PathBuf::from(ports_path), ports),
+                ]
+            }
+        }
+    }
+
+    /// Writes the files of this export.
+    pub fn write(&self, report: &ScanReport, exclude_flaky: bool) -> io::Result<()> {
+        for (path, content) in self.files(report, exclude_flaky) {
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+}
+
+/// A host of the report, with the ports of it which are exported.
+struct Exported<'a> {
+    host: &'a HostReport,
+    ports: Vec<u16>,
+}
+
+/// The `scheme://host:port` lines of the ports which may speak HTTP.
+fn httpx(hosts: &[Exported]) -> String {
+    let lines = hosts.iter().flat_map(|exported| {
+        let host = exported.host;
+        let schemes: Vec<(u16, &str)> = exported
+            .ports
+            .iter()
+            .filter_map(|port| Some((*port, scheme(host, *port)?)))
+            .collect();
+        names(host).into_iter().flat_map(move |name| {
+            let authority = authority(&name, host.ip);
+            schemes
+                .clone()
+                .into_iter()
+                .map(move |(port, scheme)| format!("{scheme}://{authority}:{port}"))
+        })
+    });
+    lines_of(lines)
+}
+
+/// The scheme of `port`, None when it was found speaking something else.
+/// The protocol hint of the probe pipeline goes first, then the service
+/// nmap found, then the port.
+fn scheme(host: &HostReport, port: u16) -> Option<&'static str> {
+    let hint = host
+        .probes
+        .iter()
+        .find(|probe| probe.port == port)
+        .and_then(|probe| probe.service_guess.protocol_hint);
+    match hint {
+        Some(ProtocolHint::Http) => return Some("http"),
+        Some(ProtocolHint::Tls) => return Some("https"),
+        Some(_) => return None,
+        None => {}
+    }
+
+    let service = host
+        .services
+        .iter()
+        .find(|service| service.port == port)
+        .and_then(|service| service.service.as_deref());
+    match service {
+        Some(service) if service == "https" || service.starts_with("ssl/") => Some("https"),
+        Some(service) if service.starts_with("http") => Some("http"),
+        Some(_) => None,
+        None if HTTPS_PORTS.contains(&port) => Some("https"),
+        None => Some("http"),
+    }
+}
+
+/// The hostnames of `host`, its address when it has none.
+fn names(host: &HostReport) -> Vec<String> {
+    if host.hostnames.is_empty() {
+        vec![host.ip.to_string()]
+    } else {
+        host.hostnames.clone()
+    }
+}
+
+/// `name` ready to be followed by a port, IPv6 addresses in brackets.
+fn authority(name: &str, ip: IpAddr) -> String {
+    if ip.is_ipv6() && name.parse::<IpAddr>().is_ok() {
+        format!("[{name}]")
+    } else {
+        name.to_owned()
+    }
+}
+
+fn lines_of(lines: impl Iterator<Item = String>) -> String {
+    lines.map(|line| line + "\n").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Export, Exporter};
+    use crate::address::Target;
+    use crate::hints::ProtocolHint;
+    use crate::input::HostOrder;
+    use crate::probe::{ProbeStep, ServiceGuess};
+    use crate::report::{PortConfidence, PortProbe, ScanReport};
+    use crate::scanner::{Confidence, TryOutcome};
+    use crate::scripts::nmap::PortService;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    fn target(ip: &str, hostnames: &[&str]) -> Target {
+        Target {
+            ip: ip.parse().unwrap(),
+            hostnames: hostnames.iter().map(|name| name.to_string()).collect(),
+            sources: vec![ip.to_owned()],
+            ports: None,
+        }
+    }
+
+    fn probe(port: u16, protocol_hint: ProtocolHint) -> PortProbe {
+        PortProbe {
+            port,
+            service_guess: ServiceGuess {
+                service: protocol_hint.service().map(ToOwned::to_owned),
+                probe: ProbeStep::Banner,
+                evidence: String::new(),
+                protocol_hint: Some(protocol_hint),
+                first_bytes: None,
+            },
+        }
+    }
+
+    fn service(port: u16, name: &str) -> PortService {
+        PortService {
+            port,
+            protocol: "tcp".to_owned(),
+            service: Some(name.to_owned()),
+            product: None,
+            version: None,
+        }
+    }
+
+    // 10.0.0.1 answered TLS on 8000 and SSH on 2222, nmap found HTTPS on
+    // 8001 for web.example and api.example, nothing is known of ::1.
+    fn report() -> ScanReport {
+        let targets = [
+            target("10.0.0.1", &[]),
+            target("10.0.0.2", &["web.example", "api.example"]),
+            target("10.0.0.3", &[]),
+            target("::1", &[]),
+        ];
+        let open: Vec<SocketAddr> = [
+            "10.0.0.1:80",
+            "10.0.0.1:8000",
+            "10.0.0.1:2222",
+            "10.0.0.2:8001",
+            "[::1]:443",
+        ]
+        .iter()
+        .map(|socket| socket.parse().unwrap())
+        .collect();
+        let mut report = ScanReport::new(&targets, &open, HostOrder::Input);
+        report.hosts[0].probes = vec![
+            probe(8000, ProtocolHint::Tls),
+            probe(2222, ProtocolHint::Ssh),
+        ];
+        report.hosts[1].services = vec![service(8001, "ssl/http")];
+        report
+    }
+
+    fn files(export: &str) -> Vec<(PathBuf, String)> {
+        export.parse::<Export>().unwrap().files(&report(), false)
+    }
+
+    #[test]
+    fn every_exporter_writes_its_lines() {
+        assert_eq!(
+            files("httpx=web.txt"),
+            [(
+                PathBuf::from("web.txt"),
+                "http://10.0.0.1:80\nhttps://10.0.0.1:8000\nhttps://web.example:8001\n\
+                 https://api.example:8001\nhttps://[::1]:443\n"
+                    .to_owned()
+            )]
+        );
+        assert_eq!(
+            files("hostport=sockets.txt"),
+            [(
+                PathBuf::from("sockets.txt"),
+                "10.0.0.1:80\n10.0.0.1:2222\n10.0.0.1:8000\nweb.example:8001\n\
+                 api.example:8001\n[::1]:443\n"
+                    .to_owned()
+            )]
+        );
+        assert_eq!(
+            files("nmap-targets=targets.txt"),
+            [
+                (
+                    PathBuf::from("targets.txt"),
+                    "10.0.0.1\nweb.example\napi.example\n::1\n".to_owned()
+                ),
+                (
+                    PathBuf::from("targets.txt.ports"),
+                    "80,443,2222,8000-8001\n".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nmap_targets_are_headed_with_the_run_id() {
+        let mut report = report();
+        report.run_id = Some("acme-0424".to_owned());
+        let export: Export = "nmap-targets=targets.txt".parse().unwrap();
+        let files = export.files(&report, false);
+        assert_eq!(
+            files[0].1,
+            "# run-id: acme-0424\n10.0.0.1\nweb.example\napi.example\n::1\n"
+        );
+        assert_eq!(files[1].1, "80,443,2222,8000-8001\n");
+
+        // Lists for httpx and the like stay bare.
+        let export: Export = "hostport=sockets.txt".parse().unwrap();
+        assert!(!export.files(&report, false)[0].1.contains("acme-0424"));
+    }
+
+    #[test]
+    fn flaky_ports_can_be_left_out() {
+        let mut report = report();
+        report.hosts[3].confidence = vec![PortConfidence {
+            port: 443,
+            label: Confidence::Flaky,
+            tries: vec![TryOutcome::Open, TryOutcome::TimedOut],
+        }];
+        let export: Export = "hostport=sockets.txt".parse().unwrap();
+        assert!(export.files(&report, false)[0].1.contains("[::1]:443"));
+        assert_eq!(
+            export.files(&report, true)[0].1,
+            "10.0.0.1:80\n10.0.0.1:2222\n10.0.0.1:8000\nweb.example:8001\napi.example:8001\n"
+        );
+
+        // A host left without ports is left out with them.
+        let export: Export = "nmap-targets=targets.txt".parse().unwrap();
+        assert_eq!(
+            export.files(&report, true)[0].1,
+            "10.0.0.1\nweb.example\napi.example\n"
+        );
+    }
+
+    #[test]
+    fn exports_are_parsed() {
+        let export: Export = "nmap-targets = out/targets.txt".parse().unwrap();
+        assert_eq!(export.exporter, Exporter::NmapTargets);
+        assert_eq!(export.path, PathBuf::from("out/targets.txt"));
+
+        assert!("httpx".parse::<Export>().unwrap_err().contains("NAME=FILE"));
+        assert!("curl=a.txt"
+            .parse::<Export>()
+            .unwrap_err()
+            .contains("is not an exporter"));
+        assert!("httpx=".parse::<Export>().is_err());
+    }
+}
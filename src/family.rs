@@ -0,0 +1,337 @@
+//! Address family selection for dual-stack hostnames.
+//!
+//! With `--prefer-family auto`, every hostname which resolves to both an IPv4
+//! and an IPv6 address gets a quick connectivity probe on both families. The
+//! family answering first is scanned, and if its first batch then gets no
+//! response at all, not even a refused connection, the scan switches to the
+//! other family, see [`crate::scanner::Fallback`].
+use crate::errors::ErrorCode;
+use crate::warning;
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use futures::stream::FuturesUnordered;
+use log::debug;
+use serde_derive::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Ports which are likely to answer, tried in order for the race probe.
+const LIKELY_PORTS: [u16; 3] = [443, 80, 22];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+impl Family {
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Family::Ipv4,
+            IpAddr::V6(_) => Family::Ipv6,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Family::Ipv4 => Family::Ipv6,
+            Family::Ipv6 => Family::Ipv4,
+        }
+    }
+}
+
+/// Why a family was picked for a dual-stack host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// This family answered the race probe first.
+    FirstResponse,
+    /// Neither family answered the race probe, IPv4 is used.
+    NoResponse,
+    /// The first batch of the first pick got no response, the other family
+    /// was scanned.
+    Fallback,
+}
+
+/// A hostname which resolved to both address families.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DualStackHost {
+    pub hostname: String,
+    pub ipv4: IpAddr,
+    pub ipv6: IpAddr,
+}
+
+impl DualStackHost {
+    fn address(&self, family: Family) -> IpAddr {
+        match family {
+            Family::Ipv4 => self.ipv4,
+            Family::Ipv6 => self.ipv6,
+        }
+    }
+}
+
+/// The outcome of the family selection, as logged and reported in JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FamilyDecision {
+    pub hostname: String,
+    pub family: Family,
+    pub address: IpAddr,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Racing {
+        ipv4: Option<bool>,
+        ipv6: Option<bool>,
+    },
+    Chosen(Family, Reason),
+}
+
+/// The per-host selection state machine.
+///
+/// It starts racing, takes the first family reported as responsive and can
+/// fall back once to the other family after the first batch.
+#[derive(Debug, Clone)]
+pub struct FamilySelection {
+    host: DualStackHost,
+    state: State,
+}
+
+impl FamilySelection {
+    pub fn new(host: DualStackHost) -> Self {
+        Self {
+            host,
+            state: State::Racing {
+                ipv4: None,
+                ipv6: None,
+            },
+        }
+    }
+
+    /// Feeds the outcome of one race probe. Outcomes arriving after a
+    /// family was chosen are ignored.
+    pub fn probe_result(&mut self, family: Family, responded: bool) {
+        let State::Racing { mut ipv4, mut ipv6 } = self.state else {
+            return;
+        };
+
+        if responded {
+            self.state = State::Chosen(family, Reason::FirstResponse);
+            return;
+        }
+
+        match family {
+            Family::Ipv4 => ipv4 = Some(false),
+            Family::Ipv6 => ipv6 = Some(false),
+        }
+        self.state = if ipv4.is_some() && ipv6.is_some() {
+            State::Chosen(Family::Ipv4, Reason::NoResponse)
+        } else {
+            State::Racing { ipv4, ipv6 }
+        };
+    }
+
+    /// Feeds the number of responses the chosen family produced in its
+    /// first batch. Zero responses switch to the other family, once.
+    pub fn first_batch(&mut self, responses: usize) {
+        if let State::Chosen(family, reason) = self.state {
+            if responses == 0 && reason != Reason::Fallback {
+                self.state = State::Chosen(family.other(), Reason::Fallback);
+            }
+        }
+    }
+
+    pub fn is_decided(&self) -> bool {
+        matches!(self.state, State::Chosen(..))
+    }
+
+    /// Returns the address to scan, or None while still racing.
+    pub fn chosen(&self) -> Option<IpAddr> {
+        match self.state {
+            State::Chosen(family, _) => Some(self.host.address(family)),
+            State::Racing { .. } => None,
+        }
+    }
+
+    /// Returns the address of the family which won't be scanned, or None
+    /// while still racing or when both families got scanned after a fallback.
+    pub fn skipped(&self) -> Option<IpAddr> {
+        match self.state {
+            State::Chosen(_, Reason::Fallback) | State::Racing { .. } => None,
+            State::Chosen(family, _) => Some(self.host.address(family.other())),
+        }
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.host.hostname
+    }
+
+    pub fn has_fallen_back(&self) -> bool {
+        matches!(self.state, State::Chosen(_, Reason::Fallback))
+    }
+
+    pub fn decision(&self) -> Option<FamilyDecision> {
+        match self.state {
+            State::Chosen(family, reason) => Some(FamilyDecision {
+                hostname: self.host.hostname.clone(),
+                family,
+                address: self.host.address(family),
+                reason,
+            }),
+            State::Racing { .. } => None,
+        }
+    }
+}
+
+/// Picks the port used for the race probe out of the ports being scanned.
+pub fn probe_port(ports: &[u16]) -> Option<u16> {
+    LIKELY_PORTS
+        .iter()
+        .find(|port| ports.contains(port))
+        .or_else(|| ports.first())
+        .copied()
+}
+
+/// Races one connect per family for every host, at most `batch_size`
+/// probes at a time, and returns the decided selections.
+pub async fn race(
+    hosts: Vec<DualStackHost>,
+    port: u16,
+    timeout: Duration,
+    batch_size: u16,
+    greppable: bool,
+    accessible: bool,
+) -> Vec<FamilySelection> {
+    let mut selections = Vec::with_capacity(hosts.len());
+
+    for chunk in hosts.chunks(std::cmp::max(usize::from(batch_size) / 2, 1)) {
+        let mut chunk_selections: Vec<FamilySelection> =
+            chunk.iter().cloned().map(FamilySelection::new).collect();
+        let mut ftrs = FuturesUnordered::new();
+
+        for (index, host) in chunk.iter().enumerate() {
+            for family in [Family::Ipv4, Family::Ipv6] {
+                let socket = SocketAddr::new(host.address(family), port);
+                ftrs.push(async move { (index, family, responds(socket, timeout).await) });
+            }
+        }
+
+        while let Some((index, family, responded)) = ftrs.next().await {
+            chunk_selections[index].probe_result(family, responded);
+        }
+        selections.extend(chunk_selections);
+    }
+
+    for selection in &selections {
+        if let Some(decision) = selection.decision() {
+            debug!("Family decision {:?}", decision);
+            if decision.reason == Reason::NoResponse {
+                warning!(
+                    ErrorCode::NoFamilyAnswered,
+                    format!(
+                        "Neither family of {} answered, scanning {}",
+                        decision.hostname, decision.address
+                    ),
+                    greppable,
+                    accessible,
+                    host = &decision.hostname
+                );
+            }
+        }
+    }
+
+    selections
+}
+
+/// A refused connection still proves the family is routable.
+async fn responds(socket: SocketAddr, timeout: Duration) -> bool {
+    match io::timeout(timeout, async move { TcpStream::connect(socket).await }).await {
+        Ok(_) => true,
+        Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probe_port, DualStackHost, Family, FamilySelection, Reason};
+
+    fn selection() -> FamilySelection {
+        FamilySelection::new(DualStackHost {
+            hostname: "dual.example".to_owned(),
+            ipv4: "192.0.2.1".parse().unwrap(),
+            ipv6: "2001:db8::1".parse().unwrap(),
+        })
+    }
+
+    #[test]
+    fn first_response_wins() {
+        let mut selection = selection();
+        selection.probe_result(Family::Ipv6, true);
+        selection.probe_result(Family::Ipv4, true);
+
+        let decision = selection.decision().unwrap();
+        assert_eq!(decision.family, Family::Ipv6);
+        assert_eq!(decision.reason, Reason::FirstResponse);
+        assert_eq!(selection.chosen(), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(selection.skipped(), Some("192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn failed_probe_waits_for_the_other_family() {
+        let mut selection = selection();
+        selection.probe_result(Family::Ipv6, false);
+        assert!(!selection.is_decided());
+        assert_eq!(selection.chosen(), None);
+
+        selection.probe_result(Family::Ipv4, true);
+        assert_eq!(selection.decision().unwrap().family, Family::Ipv4);
+    }
+
+    #[test]
+    fn no_response_defaults_to_ipv4() {
+        let mut selection = selection();
+        selection.probe_result(Family::Ipv4, false);
+        selection.probe_result(Family::Ipv6, false);
+
+        let decision = selection.decision().unwrap();
+        assert_eq!(decision.family, Family::Ipv4);
+        assert_eq!(decision.reason, Reason::NoResponse);
+    }
+
+    #[test]
+    fn empty_first_batch_falls_back_once() {
+        let mut selection = selection();
+        selection.probe_result(Family::Ipv4, true);
+        selection.first_batch(0);
+
+        assert!(selection.has_fallen_back());
+        assert_eq!(selection.decision().unwrap().family, Family::Ipv6);
+        // Both families got scanned.
+        assert_eq!(selection.skipped(), None);
+
+        // The fallback is final, even when it doesn't answer either.
+        selection.first_batch(0);
+        assert_eq!(selection.decision().unwrap().family, Family::Ipv6);
+    }
+
+    #[test]
+    fn responsive_first_batch_keeps_choice() {
+        let mut selection = selection();
+        selection.probe_result(Family::Ipv6, true);
+        selection.first_batch(3);
+
+        assert!(!selection.has_fallen_back());
+        assert_eq!(selection.decision().unwrap().family, Family::Ipv6);
+    }
+
+    #[test]
+    fn probe_port_prefers_likely_ports() {
+        assert_eq!(probe_port(&[1, 2, 80, 443]), Some(443));
+        assert_eq!(probe_port(&[8080, 22]), Some(22));
+        assert_eq!(probe_port(&[8080]), Some(8080));
+        assert_eq!(probe_port(&[]), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! The fingerprint of the effective configuration of a run, see
+//! `--verify-config`.
+//!
+//! What shapes a scan is put in a canonical form, serialized as JSON and
+//! hashed, so that every artifact of a run can tell how it was produced and
+//! a runbook can pin the exact scan it expects. Equivalent invocations get
+//! the same hash: targets and excluded ports are sets, given in any order,
+//! and the ports are the ones scanned whether they came from `--ports`,
+//! `--range` or a default.
+use crate::hash::fnv1a;
+use crate::input::{Opts, PortRange};
+use crate::port_strategy::OrderFile;
+use crate::scanner::Shard;
+use serde_derive::Serialize;
+
+/// The canonical configuration of a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanConfig {
+    pub version: String,
+    /// The targets as given, before resolution. Sorted and deduplicated.
+    pub targets: Vec<String>,
+    pub technique: String,
+    /// The ports scanned, like `80-82,443`.
+    pub ports: String,
+    pub exclude_ports: Vec<u16>,
+    /// The --scan-order, or the mode of the --order-file.
+    pub order: String,
+    /// The hash of the ports of the --order-file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_file: Option<String>,
+    pub seed: Option<u64>,
+    pub shard: Option<Shard>,
+    pub batch_size: u16,
+    pub timeout_ms: u32,
+    pub tries: u8,
+    /// The groups of the --timeout-map, sorted.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timeout_map: Vec<String>,
+}
+
+impl ScanConfig {
+    /// The configuration of `opts`, scanning `ports`.
+    pub fn new(opts: &Opts, ports: &PortRange, order_file: Option<&OrderFile>) -> Self {
+        let mut targets: Vec<String> = opts
+            .addresses
+            .iter()
+            .flat_map(|addresses| target_tokens(addresses))
+            .collect();
+        targets.sort();
+        targets.dedup();
+        let mut exclude_ports = opts.excluded_ports();
+        exclude_ports.sort_unstable();
+        exclude_ports.dedup();
+        let mut timeout_map: Vec<String> = opts
+            .timeout_map
+            .iter()
+            .flat_map(|map| map.groups().iter().map(ToString::to_string))
+            .collect();
+        timeout_map.sort();
+        let order = match order_file {
+            Some(_) => format!("order-file-{:?}", opts.order_file_mode),
+            None => format!("{:?}", opts.scan_order),
+        };
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            targets,
+            technique: if opts.udp { "udp" } else { "tcp-connect" }.to_owned(),
+            ports: ports.to_string(),
+            exclude_ports,
+            order: order.to_lowercase(),
+            order_file: order_file.map(|file| format!("{:016x}", order_file_hash(file))),
+            seed: opts.seed,
+            shard: opts.shard,
+            batch_size: opts.batch_size,
+            timeout_ms: opts.timeout,
+            tries: opts.tries.max(1),
+            timeout_map,
+        }
+    }
+
+    /// The single line JSON form the hash is taken of.
+    pub fn canonical(&self) -> String {
+        serde_json::to_string(self).expect("Scan configurations always serialize.")
+    }
+
+    pub fn fingerprint(self) -> Fingerprint {
+        let hash = format!("{:016x}", fnv1a(self.canonical().into_bytes()));
+        Fingerprint { config: self, hash }
+    }
+}
+
+/// A configuration with its hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Fingerprint {
+    pub config: ScanConfig,
+    pub hash: String,
+}
+
+impl Fingerprint {
+    /// Whether `hash` is the one of this configuration, in any case.
+    pub fn matches(&self, hash: &str) -> bool {
+        self.hash.eq_ignore_ascii_case(hash.trim())
+    }
+}
+
+/// The targets of an `--addresses` value, lowercased. Targets with ports of
+/// their own are separated by `;`, the others by `,` too.
+fn target_tokens(addresses: &str) -> Vec<String> {
+    addresses
+        .split(';')
+        .flat_map(|token| {
+            if token.contains('=') {
+                vec![token]
+            } else {
+                token.split(',').collect()
+            }
+        })
+        .map(|token| token.trim().to_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn order_file_hash(file: &OrderFile) -> u64 {
+    let lines: Vec<String> = file
+        .entries()
+        .iter()
+        .map(|(port, weight)| match weight {
+            Some(weight) => format!("{port} {weight}"),
+            None => port.to_string(),
+        })
+        .collect();
+    fnv1a(lines.join("\n").into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScanConfig;
+    use crate::input::{Opts, PortRange};
+    use crate::port_strategy::OrderFile;
+
+    fn hash(addresses: &[&str], exclude_ports: &[u16], ports: &str) -> String {
+        let opts = Opts {
+            addresses: addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            exclude_ports: Some(exclude_ports.to_vec()),
+            ..Opts::default()
+        };
+        let ports = PortRange::from_ports(
+            &ports
+                .split(',')
+                .map(|port| port.parse().unwrap())
+                .collect::<Vec<u16>>(),
+        );
+        ScanConfig::new(&opts, &ports, None).fingerprint().hash
+    }
+
+    #[test]
+    fn equivalent_invocations_hash_the_same() {
+        let expected = hash(
+            &["10.0.0.1,Example.com", "10.0.0.0/24"],
+            &[22, 21],
+            "80,443,81",
+        );
+        assert_eq!(expected.len(), 16);
+        assert_eq!(
+            expected,
+            hash(
+                &["10.0.0.0/24,example.com", " 10.0.0.1"],
+                &[21, 22, 21],
+                "443,80,81"
+            )
+        );
+        assert_eq!(
+            expected,
+            hash(
+                &["10.0.0.1;10.0.0.0/24;example.com"],
+                &[21, 22],
+                "81,443,80"
+            )
+        );
+    }
+
+    #[test]
+    fn different_scans_hash_differently() {
+        let expected = hash(&["10.0.0.1"], &[], "80");
+        assert_ne!(expected, hash(&["10.0.0.2"], &[], "80"));
+        assert_ne!(expected, hash(&["10.0.0.1"], &[22], "80"));
+        assert_ne!(expected, hash(&["10.0.0.1"], &[], "81"));
+        // Targets with ports of their own keep them.
+        assert_ne!(
+            hash(&["web1=80,443;10.0.0.1"], &[], "80"),
+            hash(&["web1=80;10.0.0.1"], &[], "80")
+        );
+
+        let opts = Opts {
+            addresses: vec!["10.0.0.1".to_owned()],
+            ..Opts::default()
+        };
+        let ports = PortRange::from_ports(&[80]);
+        let plain = ScanConfig::new(&opts, &ports, None).fingerprint();
+        let file = OrderFile::parse("80\n").unwrap();
+        let ordered = ScanConfig::new(&opts, &ports, Some(&file)).fingerprint();
+        assert_eq!(ordered.config.order, "order-file-literal");
+        assert_ne!(plain.hash, ordered.hash);
+        assert!(plain.matches(&plain.hash.to_uppercase()));
+    }
+}
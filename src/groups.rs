@@ -0,0 +1,242 @@
+//! Groups the hosts of a report by what they expose, see
+//! `--group-by-fingerprint`.
+//!
+//! Hosts built from the same image open the same ports, and reading them
+//! one by one is noise. The open ports of a host, with the protocol hints of
+//! `--probe-all` when there are some, make its fingerprint: a hash which only
+//! depends on them, so that the same group gets the same fingerprint from
+//! one run to the next and two reports can be diffed by it.
+use crate::hash::fnv1a;
+use crate::hints::ProtocolHint;
+use crate::report::HostReport;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+/// The hosts which share a fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostGroup {
+    /// The hash of the ports and hints, 16 hex digits.
+    pub fingerprint: String,
+    pub ports: Vec<u16>,
+    /// The protocol hints of the ports which got one, by port.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<PortHint>,
+    /// In the order of the report.
+    pub hosts: Vec<IpAddr>,
+    pub count: usize,
+}
+
+/// The protocol hint of an open port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PortHint {
+    pub port: u16,
+    pub hint: ProtocolHint,
+}
+
+impl HostGroup {
+    /// The line of the group in the normal output, its hosts on the next.
+    pub fn summary(&self) -> String {
+        let ports: Vec<String> = self
+            .ports
+            .iter()
+            .map(
+                |port| match self.hints.iter().find(|hint| hint.port == *port) {
+                    Some(hint) => format!("{port}/{}", hint.hint),
+                    None => port.to_string(),
+                },
+            )
+            .collect();
+        let hosts: Vec<String> = self.hosts.iter().map(ToString::to_string).collect();
+        format!(
+            "Fingerprint {}: {} host{} with [{}]\n    {}",
+            self.fingerprint,
+            self.count,
+            if self.count == 1 { "" } else { "s" },
+            ports.join(","),
+            hosts.join(", ")
+        )
+    }
+}
+
+/// The fingerprint of open `ports`, ascending, with their `hints`.
+pub fn fingerprint(ports: &[u16], hints: &[PortHint]) -> String {
+    let mut canonical = String::from("tcp:");
+    for port in ports {
+        let _ = write!(canonical, "{port},");
+    }
+    for hint in hints {
+        let _ = write!(canonical, "{}={};", hint.port, hint.hint);
+    }
+    format!("{:016x}", fnv1a(canonical.bytes()))
+}
+
+/// Groups the scanned `hosts` by fingerprint, the biggest groups first and
+/// the ones of a size in the order their first host came in.
+pub fn group_hosts(hosts: &[HostReport]) -> Vec<HostGroup> {
+    let mut groups: Vec<HostGroup> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for host in hosts.iter().filter(|host| host.scanned) {
+        let mut hints: Vec<PortHint> = host
+            .probes
+            .iter()
+            .filter_map(|probe| {
+                Some(PortHint {
+                    port: probe.port,
+                    hint: probe.service_guess.protocol_hint?,
+                })
+            })
+            .collect();
+        hints.sort_by_key(|hint| hint.port);
+        let fingerprint = fingerprint(&host.ports, &hints);
+        let group = *index.entry(fingerprint.clone()).or_insert_with(|| {
+            groups.push(HostGroup {
+                fingerprint,
+                ports: host.ports.clone(),
+                hints,
+                hosts: Vec::new(),
+                count: 0,
+            });
+            groups.len() - 1
+        });
+        groups[group].hosts.push(host.ip);
+        groups[group].count += 1;
+    }
+    // Stable, ties keep the order of the hosts.
+    groups.sort_by_key(|group| std::cmp::Reverse(group.count));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, group_hosts, PortHint};
+    use crate::address::Target;
+    use crate::hints::ProtocolHint;
+    use crate::probe::{ProbeStep, ServiceGuess};
+    use crate::report::{HostReport, PortProbe, ScanReport, SkipReason};
+    use std::net::IpAddr;
+
+    fn host(ip: &str, ports: &[u16]) -> HostReport {
+        let target = Target {
+            ip: ip.parse().unwrap(),
+            hostnames: Vec::new(),
+            sources: vec![ip.to_owned()],
+            ports: None,
+        };
+        HostReport::new(&target, ports.to_vec())
+    }
+
+    fn hinted(ip: &str, ports: &[u16], port: u16, hint: ProtocolHint) -> HostReport {
+        let mut host = host(ip, ports);
+        host.probes.push(PortProbe {
+            port,
+            service_guess: ServiceGuess {
+                service: hint.service().map(ToOwned::to_owned),
+                probe: ProbeStep::Banner,
+                evidence: String::new(),
+                protocol_hint: Some(hint),
+                first_bytes: None,
+            },
+        });
+        host
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn hosts_with_the_same_ports_are_grouped() {
+        let mut skipped = host("10.0.0.9", &[]);
+        skipped.scanned = false;
+        skipped.skipped_reason = Some(SkipReason::OtherFamily);
+        let hosts = vec![
+            host("10.0.0.1", &[22, 80]),
+            host("10.0.0.2", &[]),
+            host("10.0.0.3", &[22, 80]),
+            host("10.0.0.4", &[3306]),
+            host("10.0.0.5", &[]),
+            host("10.0.0.6", &[22, 80]),
+            skipped,
+        ];
+        let groups = group_hosts(&hosts);
+        let members: Vec<(Vec<u16>, Vec<IpAddr>, usize)> = groups
+            .iter()
+            .map(|group| (group.ports.clone(), group.hosts.clone(), group.count))
+            .collect();
+        assert_eq!(
+            members,
+            [
+                (vec![22, 80], ips(&["10.0.0.1", "10.0.0.3", "10.0.0.6"]), 3),
+                // The hosts without open ports are a group too.
+                (vec![], ips(&["10.0.0.2", "10.0.0.5"]), 2),
+                (vec![3306], ips(&["10.0.0.4"]), 1),
+            ]
+        );
+        assert_eq!(
+            groups[2].summary(),
+            format!(
+                "Fingerprint {}: 1 host with [3306]\n    10.0.0.4",
+                groups[2].fingerprint
+            )
+        );
+
+        // The JSON keeps every host besides the groups.
+        let report = ScanReport {
+            groups,
+            hosts,
+            ..ScanReport::default()
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["hosts"].as_array().unwrap().len(), 7);
+        assert_eq!(json["groups"].as_array().unwrap().len(), 3);
+        assert_eq!(json["groups"][0]["count"], 3);
+        assert_eq!(
+            json["groups"][0]["hosts"],
+            serde_json::json!(["10.0.0.1", "10.0.0.3", "10.0.0.6"])
+        );
+        assert!(json["groups"][0].get("hints").is_none());
+        assert!(
+            serde_json::from_str::<serde_json::Value>(&ScanReport::default().to_json())
+                .unwrap()
+                .get("groups")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn protocol_hints_split_groups() {
+        let hosts = vec![
+            hinted("10.0.0.1", &[22, 8080], 8080, ProtocolHint::Http),
+            hinted("10.0.0.2", &[22, 8080], 8080, ProtocolHint::Tls),
+            hinted("10.0.0.3", &[22, 8080], 8080, ProtocolHint::Http),
+        ];
+        let groups = group_hosts(&hosts);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].hosts, ips(&["10.0.0.1", "10.0.0.3"]));
+        assert_eq!(
+            groups[0].hints,
+            [PortHint {
+                port: 8080,
+                hint: ProtocolHint::Http
+            }]
+        );
+        assert!(groups[1].summary().contains("with [22,8080/tls]"));
+    }
+
+    // The fingerprints are compared across runs, they must not change.
+    #[test]
+    fn fingerprints_are_stable() {
+        assert_eq!(fingerprint(&[22, 80], &[]), fingerprint(&[22, 80], &[]));
+        assert_ne!(fingerprint(&[22, 80], &[]), fingerprint(&[22, 81], &[]));
+        assert_ne!(fingerprint(&[2, 280], &[]), fingerprint(&[22, 80], &[]));
+        assert_eq!(fingerprint(&[], &[]), "c737b0eefd244fde");
+        assert_eq!(fingerprint(&[22, 80], &[]), "adbeea000140211a");
+        let http = PortHint {
+            port: 8080,
+            hint: ProtocolHint::Http,
+        };
+        assert_eq!(fingerprint(&[22, 8080], &[http]), "e2a228012ba99822");
+    }
+}
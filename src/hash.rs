@@ -0,0 +1,22 @@
+//! The stable hash of what's written down or compared across runs, like the
+//! cache keys, the fingerprints of configurations or the shards of a scan.
+
+/// The 64 bit FNV-1a hash of `bytes`, which unlike the std hashers is the
+/// same on every platform and Rust version.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fnv1a;
+
+    #[test]
+    fn hashes_are_the_ones_of_fnv1a() {
+        assert_eq!(fnv1a(Vec::new()), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(*b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(*b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
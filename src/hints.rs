@@ -0,0 +1,301 @@
+//! Protocol hints from the first bytes a port sends.
+//!
+//! A table of signatures, byte prefixes and regular expressions, is matched
+//! against a captured banner or answer in order, the first match wins. Bytes
+//! matching no signature are flagged as binary when they mostly aren't text,
+//! otherwise they get no hint at all.
+//!
+//! The hints are used by the probe pipeline, see [`crate::probe`], and given
+//! to scripts with the `{{hints}}` placeholder, see [`crate::scripts`].
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The protocol a port seems to speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProtocolHint {
+    Ssh,
+    Ftp,
+    Smtp,
+    Pop3,
+    Imap,
+    Http,
+    Tls,
+    Rdp,
+    Mysql,
+    Redis,
+    Vnc,
+    Telnet,
+    /// Bytes of an unknown protocol which isn't text.
+    Binary,
+}
+
+/// Every hint, in the order of the enum.
+const HINTS: [ProtocolHint; 13] = [
+    ProtocolHint::Ssh,
+    ProtocolHint::Ftp,
+    ProtocolHint::Smtp,
+    ProtocolHint::Pop3,
+    ProtocolHint::Imap,
+    ProtocolHint::Http,
+    ProtocolHint::Tls,
+    ProtocolHint::Rdp,
+    ProtocolHint::Mysql,
+    ProtocolHint::Redis,
+    ProtocolHint::Vnc,
+    ProtocolHint::Telnet,
+    ProtocolHint::Binary,
+];
+
+impl ProtocolHint {
+    fn name(self) -> &'static str {
+        match self {
+            ProtocolHint::Ssh => "ssh",
+            ProtocolHint::Ftp => "ftp",
+            ProtocolHint::Smtp => "smtp",
+            ProtocolHint::Pop3 => "pop3",
+            ProtocolHint::Imap => "imap",
+            ProtocolHint::Http => "http",
+            ProtocolHint::Tls => "tls",
+            ProtocolHint::Rdp => "rdp",
+            ProtocolHint::Mysql => "mysql",
+            ProtocolHint::Redis => "redis",
+            ProtocolHint::Vnc => "vnc",
+            ProtocolHint::Telnet => "telnet",
+            ProtocolHint::Binary => "binary",
+        }
+    }
+
+    /// The service the hint gives away, None for binary.
+    pub fn service(self) -> Option<&'static str> {
+        (self != ProtocolHint::Binary).then(|| self.name())
+    }
+}
+
+impl fmt::Display for ProtocolHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ProtocolHint {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        HINTS
+            .iter()
+            .copied()
+            .find(|hint| hint.name() == input)
+            .ok_or_else(|| format!("Unknown protocol hint {input:?}."))
+    }
+}
+
+enum Pattern {
+    Prefix(&'static [u8]),
+    Regex(&'static str),
+}
+
+/// The signatures in matching order. FTP greetings have to come before
+/// SMTP ones, both start with 220.
+const SIGNATURES: [(ProtocolHint, Pattern); 14] = [
+    (
+        ProtocolHint::Ssh,
+        Pattern::Regex(r"^SSH-(?:1\.99|1\.5|2\.0)-[!-~]+"),
+    ),
+    (
+        ProtocolHint::Ftp,
+        Pattern::Regex(r"(?i)^220[ -][^\r\n]*(?:ftp|filezilla)"),
+    ),
+    (ProtocolHint::Smtp, Pattern::Regex(r"^220[ -]")),
+    (ProtocolHint::Pop3, Pattern::Prefix(b"+OK")),
+    (ProtocolHint::Imap, Pattern::Regex(r"^\* (?:OK|PREAUTH)")),
+    (
+        ProtocolHint::Http,
+        Pattern::Regex(r"^HTTP/\d(?:\.\d)? \d{3}"),
+    ),
+    (
+        ProtocolHint::Tls,
+        Pattern::Regex(r"(?s-u)^[\x15\x16]\x03[\x00-\x04]"),
+    ),
+    // A TPKT header around an X.224 Connection Confirm.
+    (
+        ProtocolHint::Rdp,
+        Pattern::Regex(r"(?s-u)^\x03\x00..\x0e\xd0"),
+    ),
+    // The length, sequence 0 and protocol 10 of a greeting.
+    (
+        ProtocolHint::Mysql,
+        Pattern::Regex(r"(?s-u)^...\x00\x0a[0-9][!-~]*\x00"),
+    ),
+    (
+        ProtocolHint::Redis,
+        Pattern::Regex(r"^-(?:ERR|NOAUTH|DENIED) "),
+    ),
+    (ProtocolHint::Vnc, Pattern::Regex(r"^RFB \d{3}\.\d{3}")),
+    // Option negotiation: IAC followed by WILL, WONT, DO or DONT.
+    (
+        ProtocolHint::Telnet,
+        Pattern::Regex(r"(?-u)^\xff[\xfb-\xfe]"),
+    ),
+    (ProtocolHint::Http, Pattern::Prefix(b"HTTP/")),
+    (ProtocolHint::Ssh, Pattern::Prefix(b"SSH-")),
+];
+
+static MATCHERS: Lazy<Vec<(ProtocolHint, Matcher)>> = Lazy::new(|| {
+    SIGNATURES
+        .iter()
+        .map(|(hint, pattern)| {
+            let matcher = match pattern {
+                Pattern::Prefix(prefix) => Matcher::Prefix(prefix),
+                Pattern::Regex(regex) => Matcher::Regex(Regex::new(regex).unwrap()),
+            };
+            (*hint, matcher)
+        })
+        .collect()
+});
+
+enum Matcher {
+    Prefix(&'static [u8]),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, bytes: &[u8]) -> bool {
+        match self {
+            Matcher::Prefix(prefix) => bytes.starts_with(prefix),
+            Matcher::Regex(regex) => regex.is_match(bytes),
+        }
+    }
+}
+
+/// The protocol the first `bytes` of a port give away. None for text
+/// matching no signature, and for nothing at all.
+pub fn hint(bytes: &[u8]) -> Option<ProtocolHint> {
+    if bytes.is_empty() {
+        return None;
+    }
+    if let Some((hint, _)) = MATCHERS.iter().find(|(_, matcher)| matcher.matches(bytes)) {
+        return Some(*hint);
+    }
+
+    // More than a quarter of bytes which can't be text.
+    let binary = bytes
+        .iter()
+        .filter(|byte| !(byte.is_ascii_graphic() || byte.is_ascii_whitespace()))
+        .count();
+    (binary * 4 > bytes.len()).then_some(ProtocolHint::Binary)
+}
+
+/// The `{{hints}}` of a script, `port:hint` pairs separated with commas.
+pub fn placeholder(hints: &[(u16, ProtocolHint)]) -> String {
+    hints
+        .iter()
+        .map(|(port, hint)| format!("{port}:{hint}"))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hint, placeholder, ProtocolHint};
+
+    /// Greetings and answers captured from real servers.
+    const CORPUS: [(&[u8], Option<ProtocolHint>); 27] = [
+        (b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n", Some(ProtocolHint::Ssh)),
+        (b"SSH-2.0-dropbear_2020.81\r\n", Some(ProtocolHint::Ssh)),
+        (b"SSH-1.99-Cisco-1.25\r\n", Some(ProtocolHint::Ssh)),
+        (b"SSH-2.0-\r\n", Some(ProtocolHint::Ssh)),
+        (b"220 (vsFTPd 3.0.3)\r\n", Some(ProtocolHint::Ftp)),
+        (
+            b"220 ProFTPD 1.3.5e Server (Debian) [::ffff:10.0.0.1]\r\n",
+            Some(ProtocolHint::Ftp),
+        ),
+        (b"220-FileZilla Server 0.9.60 beta\r\n", Some(ProtocolHint::Ftp)),
+        (b"220 Microsoft FTP Service\r\n", Some(ProtocolHint::Ftp)),
+        (
+            b"220 mail.example.com ESMTP Postfix (Ubuntu)\r\n",
+            Some(ProtocolHint::Smtp),
+        ),
+        (
+            b"220 mx.google.com ESMTP a1si123456qkb.12 - gsmtp\r\n",
+            Some(ProtocolHint::Smtp),
+        ),
+        (
+            b"220 EXCH01.corp.local Microsoft ESMTP MAIL Service ready at Mon, 1 Jan 2024 10:00:00 +0000\r\n",
+            Some(ProtocolHint::Smtp),
+        ),
+        (b"+OK Dovecot ready.\r\n", Some(ProtocolHint::Pop3)),
+        (
+            b"+OK POP3 server ready <1896.697170952@dbc.mtview.ca.us>\r\n",
+            Some(ProtocolHint::Pop3),
+        ),
+        (
+            b"* OK [CAPABILITY IMAP4rev1 SASL-IR LOGIN-REFERRALS ID ENABLE IDLE LITERAL+ STARTTLS AUTH=PLAIN] Dovecot (Ubuntu) ready.\r\n",
+            Some(ProtocolHint::Imap),
+        ),
+        (
+            b"HTTP/1.1 400 Bad Request\r\nServer: nginx\r\n\r\n",
+            Some(ProtocolHint::Http),
+        ),
+        (b"HTTP/1.0 200 OK\r\n", Some(ProtocolHint::Http)),
+        (
+            b"\x16\x03\x03\x00\x5d\x02\x00\x00\x59\x03\x03",
+            Some(ProtocolHint::Tls),
+        ),
+        (b"\x15\x03\x01\x00\x02\x02\x28", Some(ProtocolHint::Tls)),
+        (
+            b"\x03\x00\x00\x13\x0e\xd0\x00\x00\x12\x34\x00\x02\x1f\x08\x00\x02\x00\x00\x00",
+            Some(ProtocolHint::Rdp),
+        ),
+        (
+            b"\x4a\x00\x00\x00\x0a8.0.36\x00\x08\x00\x00\x00",
+            Some(ProtocolHint::Mysql),
+        ),
+        (
+            b"\x5b\x00\x00\x00\x0a5.5.5-10.11.6-MariaDB-0+deb12u1\x00",
+            Some(ProtocolHint::Mysql),
+        ),
+        (b"-NOAUTH Authentication required.\r\n", Some(ProtocolHint::Redis)),
+        (b"RFB 003.008\n", Some(ProtocolHint::Vnc)),
+        (
+            b"\xff\xfd\x18\xff\xfd\x20\xff\xfd\x23\xff\xfd\x27",
+            Some(ProtocolHint::Telnet),
+        ),
+        (
+            b"\x00\x00\x00\x1c\x9a\x01\x00\x00\x00\x00\x00\x01",
+            Some(ProtocolHint::Binary),
+        ),
+        (b"Welcome to the admin console\r\n", None),
+        (b"", None),
+    ];
+
+    #[test]
+    fn corpus_is_classified() {
+        for (bytes, expected) in CORPUS.iter() {
+            assert_eq!(
+                hint(bytes),
+                *expected,
+                "{:?}",
+                String::from_utf8_lossy(bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn binary_hints_give_no_service() {
+        assert_eq!(ProtocolHint::Ssh.service(), Some("ssh"));
+        assert_eq!(ProtocolHint::Binary.service(), None);
+    }
+
+    #[test]
+    fn hints_are_listed_per_port() {
+        assert_eq!(
+            placeholder(&[(22, ProtocolHint::Ssh), (3389, ProtocolHint::Rdp)]),
+            "22:ssh,3389:rdp"
+        );
+        assert_eq!(placeholder(&[]), "");
+    }
+}
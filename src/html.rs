@@ -0,0 +1,372 @@
+//! The HTML report of `--html-report` and `--serve-report`.
+//!
+//! [`render`] makes a single page out of the results of a run: a table of
+//! the hosts, the open ports of every host with what was found out about
+//! them, a search box narrowing both down, and the JSON report itself. The
+//! page has its style and script inline and loads nothing, so it can be
+//! opened from disk, mailed or served as is.
+//!
+//! [`Server`] serves the page and the JSON report on a local address until
+//! told to stop, the page at `/` and the report at [`JSON_PATH`]. It's meant
+//! for a look at the results from a browser, answering one request at a
+//! time, and doesn't do more HTTP than that.
+use crate::report::{HostReport, ScanReport, SkipReason};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Where the server serves the JSON report.
+pub const JSON_PATH: &str = "/report.json";
+
+/// How long the server sleeps when nothing came in.
+const IDLE: Duration = Duration::from_millis(10);
+
+/// How long reading a request or writing its answer may take.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The most bytes of a request read, the request line and headers.
+const MAX_REQUEST: usize = 8192;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+th{background:#eee}\
+input[type=search]{width:24em;padding:.3em}\
+.dim{color:#777}\
+.flaky{color:#b60}\
+pre{background:#f6f6f6;padding:1em;overflow:auto}";
+
+const SEARCH: &str =
+    "document.getElementById(\"search\").addEventListener(\"input\", function () {\n\
+  var terms = this.value.toLowerCase();\n\
+  document.querySelectorAll(\"[data-search]\").forEach(function (element) {\n\
+    element.hidden = element.dataset.search.indexOf(terms) < 0;\n\
+  });\n\
+});";
+
+/// The page of `report`, linking to its JSON report at `json_link`.
+pub fn render(report: &ScanReport, json_link: &str) -> String {
+    let mut page = String::new();
+    let title = match &report.run_id {
+        Some(run_id) => format!("RustScan report {}", escape(run_id)),
+        None => "RustScan report".to_owned(),
+    };
+    let open_ports: usize = report.hosts.iter().map(|host| host.ports.len()).sum();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n\
+         <p>{} host(s), {open_ports} open port(s). <a href=\"{}\">Raw JSON</a></p>\n\
+         <input id=\"search\" type=\"search\" placeholder=\"Search hosts, names, ports and services\">\n",
+        report.hosts.len(),
+        escape(json_link),
+    );
+
+    page.push_str(
+        "<table id=\"hosts\">\n<thead><tr><th>Host</th><th>Name</th><th>Open ports</th></tr></thead>\n<tbody>\n",
+    );
+    for (index, host) in report.hosts.iter().enumerate() {
+        let ports: Vec<String> = host.ports.iter().map(ToString::to_string).collect();
+        let ports = match (host.scanned, ports.is_empty()) {
+            (false, _) => format!("<span class=\"dim\">{}</span>", skipped(host)),
+            (true, true) => "<span class=\"dim\">none</span>".to_owned(),
+            (true, false) => ports.join(", "),
+        };
+        let _ = writeln!(
+            page,
+            "<tr data-search=\"{}\"><td><a href=\"#host-{index}\">{}</a></td><td>{}</td><td>{ports}</td></tr>",
+            escape(&search_terms(host)),
+            host.ip,
+            escape(host.name.as_deref().unwrap_or_default()),
+        );
+    }
+    page.push_str("</tbody>\n</table>\n");
+
+    for (index, host) in report.hosts.iter().enumerate() {
+        host_section(&mut page, index, host);
+    }
+
+    let _ = write!(
+        page,
+        "<details id=\"raw\">\n<summary>JSON report</summary>\n<pre>{}</pre>\n</details>\n\
+         <script>\n{SEARCH}\n</script>\n</body>\n</html>\n",
+        escape(&report.to_json()),
+    );
+    page
+}
+
+/// The section of a single host, with a row for every open port.
+fn host_section(page: &mut String, index: usize, host: &HostReport) {
+    let _ = writeln!(
+        page,
+        "<section id=\"host-{index}\" data-search=\"{}\">\n<h2>{}</h2>",
+        escape(&search_terms(host)),
+        host.ip,
+    );
+    if !host.hostnames.is_empty() {
+        let _ = writeln!(
+            page,
+            "<p>Known as {}</p>",
+            escape(&host.hostnames.join(", "))
+        );
+    }
+    for note in &host.notes {
+        let _ = writeln!(page, "<p class=\"dim\">{}</p>", escape(note));
+    }
+    if !host.scanned {
+        let _ = writeln!(page, "<p class=\"dim\">{}</p>\n</section>", skipped(host));
+        return;
+    }
+    if host.ports.is_empty() {
+        page.push_str("<p class=\"dim\">No open ports.</p>\n</section>\n");
+        return;
+    }
+
+    page.push_str("<table>\n<tr><th>Port</th><th>Service</th><th>Confidence</th></tr>\n");
+    for &port in &host.ports {
+        let confidence = host
+            .confidence
+            .iter()
+            .find(|confidence| confidence.port == port)
+            .map_or_else(String::new, |confidence| {
+                format!("<span class=\"{0}\">{0}</span>", confidence.label)
+            });
+        let _ = writeln!(
+            page,
+            "<tr><td>{port}</td><td>{}</td><td>{confidence}</td></tr>",
+            escape(&service(host, port)),
+        );
+    }
+    page.push_str("</table>\n</section>\n");
+}
+
+/// What nmap or the probes found the service of `port` to be.
+fn service(host: &HostReport, port: u16) -> String {
+    if let Some(service) = host.services.iter().find(|service| service.port == port) {
+        let found: Vec<&str> = [&service.service, &service.product, &service.version]
+            .iter()
+            .filter_map(|field| field.as_deref())
+            .collect();
+        return found.join(" ");
+    }
+    host.probes
+        .iter()
+        .find(|probe| probe.port == port)
+        .and_then(|probe| probe.service_guess.service.clone())
+        .unwrap_or_default()
+}
+
+fn skipped(host: &HostReport) -> &'static str {
+    match host.skipped_reason {
+        Some(SkipReason::OtherFamily) => "not scanned, the other address family was",
+        Some(SkipReason::PreScanFailed) => "not scanned, a pre-scan script failed",
+        Some(SkipReason::NothingFiltered) => "not rescanned, nothing was filtered",
+        Some(SkipReason::InterfaceUnusable) => "not scanned, its interface can't be used",
+        Some(SkipReason::HostDown) => "not scanned, the discovery found it down",
+        None => "not scanned",
+    }
+}
+
+/// What the search box matches a host on, in lowercase.
+fn search_terms(host: &HostReport) -> String {
+    let mut terms = vec![host.ip.to_string()];
+    terms.extend(host.hostnames.iter().cloned());
+    if let Some(name) = host.name.iter().find(|name| !terms.contains(name)) {
+        terms.push(name.clone());
+    }
+    terms.extend(host.ports.iter().map(ToString::to_string));
+    terms.extend(
+        host.ports
+            .iter()
+            .map(|&port| service(host, port))
+            .filter(|service| !service.is_empty()),
+    );
+    terms.join(" ").to_lowercase()
+}
+
+/// Escapes `text` for the content or an attribute of an element.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serves a page and its JSON report over HTTP.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers every request with `page` or `json` until `stop` is set,
+    /// returning how many were answered.
+    pub fn serve(&self, page: &str, json: &str, stop: &AtomicBool) -> usize {
+        let mut answered = 0;
+        while !stop.load(Ordering::SeqCst) {
+            match self.listener.accept() {
+                // A client gone before its answer was still answered.
+                Ok((stream, _)) => {
+                    let _ = answer(stream, page, json);
+                    answered += 1;
+                }
+                Err(_) => thread::sleep(IDLE),
+            }
+        }
+        answered
+    }
+}
+
+fn answer(mut stream: TcpStream, page: &str, json: &str) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (line.next(), line.next().map(|path| path.split('?').next()));
+    let (status, content_type, body) = match (method, path.flatten()) {
+        (Some("GET"), Some("/" | "/index.html")) => ("200 OK", "text/html; charset=utf-8", page),
+        (Some("GET"), Some(JSON_PATH)) => ("200 OK", "application/json", json),
+        (Some("GET"), _) => ("404 Not Found", "text/plain; charset=utf-8", "Not found\n"),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain; charset=utf-8",
+            "Only GET is served\n",
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, render};
+    use crate::address::Target;
+    use crate::input::HostOrder;
+    use crate::report::{HostReport, PortConfidence, ScanReport, SkipReason};
+    use crate::scanner::{Confidence, TryOutcome};
+    use crate::scripts::nmap::PortService;
+    use std::net::SocketAddr;
+
+    fn target(ip: &str, hostname: Option<&str>) -> Target {
+        Target {
+            ip: ip.parse().unwrap(),
+            hostnames: hostname.into_iter().map(str::to_owned).collect(),
+            sources: vec![hostname.unwrap_or(ip).to_owned()],
+            ports: None,
+        }
+    }
+
+    fn report() -> ScanReport {
+        let targets = [
+            target("192.0.2.1", Some("web01.corp.local")),
+            target("192.0.2.2", None),
+        ];
+        let open: Vec<SocketAddr> = ["192.0.2.1:22", "192.0.2.1:443"]
+            .iter()
+            .map(|socket| socket.parse().unwrap())
+            .collect();
+        let mut report = ScanReport::new(&targets, &open, HostOrder::Input);
+        report.run_id = Some("acme-<1>".to_owned());
+        let host = &mut report.hosts[0];
+        host.name = Some("web01.corp.local".to_owned());
+        host.notes = vec!["Owned by \"ops\"".to_owned()];
+        host.services = vec![PortService {
+            port: 22,
+            protocol: "tcp".to_owned(),
+            service: Some("ssh".to_owned()),
+            product: Some("OpenSSH".to_owned()),
+            version: Some("9.6".to_owned()),
+        }];
+        host.confidence = vec![PortConfidence {
+            port: 443,
+            label: Confidence::Flaky,
+            tries: vec![TryOutcome::TimedOut, TryOutcome::Open],
+        }];
+        report.hosts.push(HostReport::skipped(
+            &target("192.0.2.3", None),
+            SkipReason::PreScanFailed,
+        ));
+        report
+    }
+
+    #[test]
+    fn page_lists_the_hosts_and_their_ports() {
+        let page = render(&report(), "report.json");
+
+        assert!(page.starts_with("<!DOCTYPE html>"), "{}", page);
+        assert!(page.contains("<title>RustScan report acme-&lt;1&gt;</title>"));
+        assert!(page.contains("3 host(s), 2 open port(s). <a href=\"report.json\">Raw JSON</a>"));
+        assert!(page.contains(
+            "<tr data-search=\"192.0.2.1 web01.corp.local 22 443 ssh openssh 9.6\">\
+             <td><a href=\"#host-0\">192.0.2.1</a></td><td>web01.corp.local</td><td>22, 443</td></tr>"
+        ));
+        assert!(page.contains("<td><a href=\"#host-1\">192.0.2.2</a></td><td></td><td><span class=\"dim\">none</span></td>"));
+        assert!(page.contains("<span class=\"dim\">not scanned, a pre-scan script failed</span>"));
+
+        assert!(page.contains("<section id=\"host-0\""));
+        assert!(page.contains("<p>Known as web01.corp.local</p>"));
+        assert!(page.contains("<p class=\"dim\">Owned by &quot;ops&quot;</p>"));
+        assert!(page.contains("<tr><td>22</td><td>ssh OpenSSH 9.6</td><td></td></tr>"));
+        assert!(page
+            .contains("<tr><td>443</td><td></td><td><span class=\"flaky\">flaky</span></td></tr>"));
+        assert!(page.contains("<p class=\"dim\">No open ports.</p>"));
+    }
+
+    #[test]
+    fn page_carries_everything_it_needs() {
+        let report = report();
+        let page = render(&report, "#raw");
+
+        assert!(page.contains(&format!("<pre>{}</pre>", escape(&report.to_json()))));
+        assert!(page.contains("<input id=\"search\" type=\"search\""));
+        assert!(page.contains("<script>\ndocument.getElementById(\"search\")"));
+        for external in ["src=", "<link", "http://", "https://"] {
+            assert!(!page.contains(external), "{}", external);
+        }
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+}
@@ -0,0 +1,514 @@
+//! The hosts and open ports of an nmap or masscan run, read back with
+//! `--import` to be scanned again.
+//!
+//! The format is told from the content: an XML report of `nmap -oX` (which
+//! `masscan -oX` writes too), the JSON of `masscan -oJ` or the list of
+//! `masscan -oL`. Only the addresses and the open ports are read. The hosts
+//! nmap found down are left out, and the entries of a same host are merged,
+//! masscan writing one per open port.
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The output formats `--import` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    NmapXml,
+    MasscanJson,
+    MasscanList,
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ImportFormat::NmapXml => "nmap XML",
+            ImportFormat::MasscanJson => "masscan JSON",
+            ImportFormat::MasscanList => "masscan list",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Imported {
+    pub format: ImportFormat,
+    /// The hosts in the order they first appear.
+    pub hosts: Vec<ImportedHost>,
+}
+
+/// A host of the imported run, with its open ports sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedHost {
+    pub ip: IpAddr,
+    pub tcp: Vec<u16>,
+    pub udp: Vec<u16>,
+}
+
+impl ImportedHost {
+    /// The open ports of the protocol scanned.
+    pub fn ports(&self, udp: bool) -> &[u16] {
+        if udp {
+            &self.udp
+        } else {
+            &self.tcp
+        }
+    }
+}
+
+impl Imported {
+    /// Reads the output file at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    /// Parses the output of nmap or masscan, telling its format from the
+    /// first character.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let (format, hosts) = match content.trim_start().chars().next() {
+            Some('<') => (ImportFormat::NmapXml, parse_nmap_xml(content)?),
+            Some('[') => (ImportFormat::MasscanJson, parse_masscan_json(content)?),
+            _ => (ImportFormat::MasscanList, parse_masscan_list(content)?),
+        };
+        Ok(Self {
+            format,
+            hosts: hosts.finish(),
+        })
+    }
+}
+
+/// The hosts read so far, merged by address.
+#[derive(Default)]
+struct Hosts {
+    hosts: Vec<ImportedHost>,
+    index: HashMap<IpAddr, usize>,
+}
+
+impl Hosts {
+    fn host(&mut self, ip: IpAddr) -> &mut ImportedHost {
+        let hosts = &mut self.hosts;
+        let position = *self.index.entry(ip).or_insert_with(|| {
+            hosts.push(ImportedHost {
+                ip,
+                tcp: Vec::new(),
+                udp: Vec::new(),
+            });
+            hosts.len() - 1
+        });
+        &mut self.hosts[position]
+    }
+
+    /// Adds the open `port` of `ip`, only TCP and UDP ports being kept.
+    fn open(&mut self, ip: IpAddr, protocol: &str, port: u16) {
+        let host = self.host(ip);
+        match protocol {
+            "tcp" => host.tcp.push(port),
+            "udp" => host.udp.push(port),
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> Vec<ImportedHost> {
+        for host in &mut self.hosts {
+            for ports in [&mut host.tcp, &mut host.udp] {
+                ports.sort_unstable();
+                ports.dedup();
+            }
+        }
+        self.hosts
+    }
+}
+
+fn parse_ip(address: &str, line: usize) -> Result<IpAddr, String> {
+    address
+        .parse()
+        .map_err(|_| format!("line {line}: invalid address {address:?}"))
+}
+
+/// A start (or empty) tag or an end tag of an XML document.
+#[derive(Debug)]
+struct Tag<'a> {
+    name: &'a str,
+    attributes: Vec<(&'a str, &'a str)>,
+    end: bool,
+    empty: bool,
+    line: usize,
+}
+
+impl<'a> Tag<'a> {
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// The tags of `xml`, skipping the text, the comments, the processing
+/// instructions and the declarations. Only what nmap writes is supported,
+/// the values of the attributes are kept escaped.
+fn tags(xml: &str) -> Result<Vec<Tag<'_>>, String> {
+    let mut tags = Vec::new();
+    let mut line = 1;
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        line += rest[..start].matches('\n').count();
+        rest = &rest[start..];
+        let skipped = [
+            ("<!--", "-->"),
+            ("<?", "?>"),
+            ("<![CDATA[", "]]>"),
+            ("<!", ">"),
+        ]
+        .iter()
+        .find(|(opening, _)| rest.starts_with(opening));
+        if let Some((opening, closing)) = skipped {
+            let end = rest
+                .find(closing)
+                .ok_or_else(|| format!("line {line}: unterminated {opening}"))?;
+            line += rest[..end].matches('\n').count();
+            rest = &rest[end + closing.len()..];
+            continue;
+        }
+
+        let (tag, length) = parse_tag(rest, line)?;
+        line += rest[..length].matches('\n').count();
+        rest = &rest[length..];
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+/// Parses the tag `xml` starts with, returning it with its length.
+fn parse_tag(xml: &str, line: usize) -> Result<(Tag<'_>, usize), String> {
+    let unterminated = || format!("line {line}: unterminated tag");
+    let end = xml.starts_with("</");
+    let mut position = if end { 2 } else { 1 };
+    let name_length = xml[position..]
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .ok_or_else(unterminated)?;
+    let name = &xml[position..position + name_length];
+    if name.is_empty() {
+        return Err(format!("line {line}: tag without a name"));
+    }
+    position += name_length;
+
+    let mut attributes = Vec::new();
+    loop {
+        let rest = &xml[position..];
+        let trimmed = rest.trim_start();
+        position += rest.len() - trimmed.len();
+        if trimmed.starts_with("/>") && !end {
+            let tag = Tag {
+                name,
+                attributes,
+                end,
+                empty: true,
+                line,
+            };
+            return Ok((tag, position + 2));
+        }
+        if trimmed.starts_with('>') {
+            let tag = Tag {
+                name,
+                attributes,
+                end,
+                empty: false,
+                line,
+            };
+            return Ok((tag, position + 1));
+        }
+        if trimmed.is_empty() {
+            return Err(unterminated());
+        }
+
+        let malformed = || format!("line {line}: malformed attribute in <{name}>");
+        let equals = trimmed.find('=').ok_or_else(malformed)?;
+        let key = trimmed[..equals].trim_end();
+        let value = trimmed[equals + 1..].trim_start();
+        let quote = value
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(malformed)?;
+        let length = value[1..].find(quote).ok_or_else(unterminated)?;
+        if end || key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(malformed());
+        }
+        attributes.push((key, &value[1..1 + length]));
+        position += trimmed.len() - value.len() + length + 2;
+    }
+}
+
+/// The hosts of an `nmap -oX` report which weren't down, with their open
+/// ports. The elements have to be properly nested, so a truncated report is
+/// an error.
+fn parse_nmap_xml(xml: &str) -> Result<Hosts, String> {
+    /// What was read of the host and the port being parsed.
+    #[derive(Default)]
+    struct Host<'a> {
+        up: bool,
+        ip: Option<IpAddr>,
+        open: Vec<(&'a str, u16)>,
+        port: Option<(&'a str, u16)>,
+        port_open: bool,
+    }
+
+    let mut hosts = Hosts::default();
+    let mut open: Vec<(&str, usize)> = Vec::new();
+    let mut host: Option<Host> = None;
+    for tag in tags(xml)? {
+        if tag.end {
+            match open.pop() {
+                Some((name, _)) if name == tag.name => {}
+                Some((name, line)) => {
+                    return Err(format!(
+                        "line {}: </{}> closes <{name}> of line {line}",
+                        tag.line, tag.name
+                    ))
+                }
+                None => return Err(format!("line {}: stray </{}>", tag.line, tag.name)),
+            }
+        } else if open.is_empty() && tag.name != "nmaprun" {
+            return Err(format!(
+                "line {}: not an nmap report, the root element is <{}>",
+                tag.line, tag.name
+            ));
+        }
+        let parent = open.last().map(|(name, _)| *name);
+
+        match (tag.name, tag.end) {
+            ("host", false) => {
+                // masscan writes no status, only the hosts it found up.
+                host = Some(Host {
+                    up: true,
+                    ..Host::default()
+                });
+            }
+            ("status", false) if parent == Some("host") => {
+                if let Some(host) = &mut host {
+                    host.up = tag.attribute("state") != Some("down");
+                }
+            }
+            ("address", false) if parent == Some("host") => {
+                let ipv4_or_ipv6 = matches!(tag.attribute("addrtype"), Some("ipv4" | "ipv6"));
+                if let (Some(host), Some(address), true) =
+                    (&mut host, tag.attribute("addr"), ipv4_or_ipv6)
+                {
+                    host.ip = Some(parse_ip(address, tag.line)?);
+                }
+            }
+            ("port", false) if parent == Some("ports") => {
+                let protocol = tag.attribute("protocol").unwrap_or("tcp");
+                let port = tag
+                    .attribute("portid")
+                    .and_then(|port| port.parse().ok())
+                    .ok_or_else(|| format!("line {}: <port> without a valid portid", tag.line))?;
+                if let Some(host) = &mut host {
+                    host.port = Some((protocol, port));
+                    host.port_open = false;
+                }
+            }
+            ("state", false) if parent == Some("port") => {
+                if let Some(host) = &mut host {
+                    host.port_open = tag.attribute("state") == Some("open");
+                }
+            }
+            ("host", true) => {
+                let host = host.take().unwrap_or_default();
+                if host.up {
+                    let ip = host.ip.ok_or_else(|| {
+                        format!("line {}: <host> without an IPv4 or IPv6 address", tag.line)
+                    })?;
+                    hosts.host(ip);
+                    for (protocol, port) in host.open {
+                        hosts.open(ip, protocol, port);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let port_over = tag.name == "port" && (tag.end || tag.empty);
+        if let (Some(host), true) = (&mut host, port_over) {
+            if let (Some(port), true) = (host.port.take(), host.port_open) {
+                host.open.push(port);
+            }
+        }
+        if !tag.end && !tag.empty {
+            open.push((tag.name, tag.line));
+        }
+    }
+
+    match open.last() {
+        Some((name, line)) => Err(format!("line {line}: <{name}> is never closed")),
+        None if xml.contains("<nmaprun") => Ok(hosts),
+        None => Err(String::from("not an nmap report, there is no <nmaprun>")),
+    }
+}
+
+#[derive(Deserialize)]
+struct MasscanRecord {
+    /// The last record of older masscan versions only has `finished`.
+    ip: Option<IpAddr>,
+    #[serde(default)]
+    ports: Vec<MasscanPort>,
+}
+
+#[derive(Deserialize)]
+struct MasscanPort {
+    port: u16,
+    proto: String,
+    /// Missing from the banner records, which are about open ports too.
+    status: Option<String>,
+}
+
+/// The hosts of a `masscan -oJ` array. The comma some versions leave before
+/// the closing bracket is allowed.
+fn parse_masscan_json(json: &str) -> Result<Hosts, String> {
+    let trimmed = json.trim_end();
+    let json = match trimmed
+        .strip_suffix(']')
+        .map(str::trim_end)
+        .and_then(|records| records.strip_suffix(','))
+    {
+        // Replaced by a space so the lines and columns of errors don't move.
+        Some(records) => format!("{records} {}", &json[records.len() + 1..]),
+        None => json.to_owned(),
+    };
+    let records: Vec<MasscanRecord> = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+
+    let mut hosts = Hosts::default();
+    for record in records {
+        let Some(ip) = record.ip else {
+            continue;
+        };
+        hosts.host(ip);
+        for port in record.ports {
+            if port.status.as_deref().is_none_or(|status| status == "open") {
+                hosts.open(ip, &port.proto, port.port);
+            }
+        }
+    }
+    Ok(hosts)
+}
+
+/// The hosts of a `masscan -oL` list, made of `open tcp 80 10.0.0.1
+/// 1700000000` lines. The banner lines and the comments are skipped.
+fn parse_masscan_list(list: &str) -> Result<Hosts, String> {
+    let mut hosts = Hosts::default();
+    for (number, line) in list.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["open", protocol, port, address, ..] => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("line {line_number}: invalid port {port:?}"))?;
+                hosts.open(parse_ip(address, line_number)?, protocol, port);
+            }
+            ["banner" | "closed", ..] => {}
+            _ => {
+                return Err(format!(
+                    "line {line_number}: expected an nmap XML report, a masscan JSON array \
+                     or 'open <protocol> <port> <address> <timestamp>', got {line:?}"
+                ))
+            }
+        }
+    }
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportFormat, Imported, ImportedHost};
+    use std::path::Path;
+
+    fn host(ip: &str, tcp: &[u16], udp: &[u16]) -> ImportedHost {
+        ImportedHost {
+            ip: ip.parse().unwrap(),
+            tcp: tcp.to_vec(),
+            udp: udp.to_vec(),
+        }
+    }
+
+    #[test]
+    fn nmap_reports_skip_the_hosts_down() {
+        let imported = Imported::read(Path::new("fixtures/import/nmap.xml")).unwrap();
+
+        assert_eq!(imported.format, ImportFormat::NmapXml);
+        // 10.0.0.2 is down, 53/udp of 10.0.0.1 only open|filtered.
+        assert_eq!(
+            imported.hosts,
+            [
+                host("10.0.0.1", &[22, 443], &[]),
+                host("fd00::3", &[], &[53])
+            ]
+        );
+        assert_eq!(imported.hosts[1].ports(true), [53]);
+    }
+
+    #[test]
+    fn masscan_duplicates_are_merged() {
+        let expected = [
+            host("192.168.1.10", &[22, 80], &[]),
+            host("192.168.1.20", &[], &[161]),
+        ];
+        let json = Imported::read(Path::new("fixtures/import/masscan.json")).unwrap();
+        let list = Imported::read(Path::new("fixtures/import/masscan.txt")).unwrap();
+
+        assert_eq!(json.format, ImportFormat::MasscanJson);
+        assert_eq!(json.hosts, expected);
+        assert_eq!(list.format, ImportFormat::MasscanList);
+        assert_eq!(list.hosts, expected);
+
+        // Older versions leave a comma before the bracket.
+        let json = r#"[
+{"ip": "10.0.0.1", "ports": [{"port": 80, "proto": "tcp", "status": "open"}]},
+{"finished": 1},
+]"#;
+        assert_eq!(
+            Imported::parse(json).unwrap().hosts,
+            [host("10.0.0.1", &[80], &[])]
+        );
+    }
+
+    #[test]
+    fn errors_tell_where_they_are() {
+        let truncated = "<?xml version=\"1.0\"?>\n<nmaprun>\n<host>\n<ports>\n";
+        assert_eq!(
+            Imported::parse(truncated).unwrap_err(),
+            "line 4: <ports> is never closed"
+        );
+        let misnested = "<nmaprun>\n<host>\n<ports>\n</host>\n</nmaprun>";
+        assert_eq!(
+            Imported::parse(misnested).unwrap_err(),
+            "line 4: </host> closes <ports> of line 3"
+        );
+        let port = "<nmaprun><host>\n<ports><port portid=\"http\"/></ports></host></nmaprun>";
+        assert_eq!(
+            Imported::parse(port).unwrap_err(),
+            "line 2: <port> without a valid portid"
+        );
+        assert!(Imported::parse("<html></html>")
+            .unwrap_err()
+            .contains("not an nmap report"));
+
+        let json = "[\n{\"ip\": \"10.0.0.300\"}\n]";
+        assert!(Imported::parse(json).unwrap_err().contains("line 2"));
+        let list = "#masscan\nopen tcp 80 10.0.0.1 1700000000\nopen tcp 80\n";
+        assert!(Imported::parse(list).unwrap_err().starts_with("line 3: "));
+        let list = "open tcp 80 web 1700000000";
+        assert_eq!(
+            Imported::parse(list).unwrap_err(),
+            "line 1: invalid address \"web\""
+        );
+    }
+}
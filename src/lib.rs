@@ -42,6 +42,8 @@
 
 pub mod tui;
 
+pub mod errors;
+
 pub mod input;
 
 pub mod scanner;
@@ -54,4 +56,80 @@ pub mod scripts;
 
 pub mod address;
 
+pub mod family;
+
+pub mod discovery;
+
+pub mod report;
+
+pub mod schema;
+
+pub mod signing;
+
+pub mod egress;
+
+pub mod reflector;
+
+pub mod listen;
+
+pub mod groups;
+
+pub mod probe;
+
+pub mod version;
+
+pub mod snmp;
+
+pub mod hints;
+
+pub mod cache;
+
+pub mod previous;
+
+pub mod rescan;
+
+pub mod trace;
+
+pub mod import;
+
+pub mod scope;
+
+pub mod notes;
+
+pub mod alias;
+
+pub mod merge;
+
+pub mod dashboard;
+
+pub mod privileges;
+
+pub mod resources;
+
+pub mod notify;
+
+pub mod fingerprint;
+
+pub mod plan;
+
+pub mod bundle;
+
+pub mod split;
+
+pub mod window;
+
+pub mod private_dir;
+
+pub mod selftest;
+
+pub mod export;
+
+pub mod sink;
+
+pub mod html;
+
+pub mod lock;
+
+pub mod hash;
+
 pub mod generated;
@@ -0,0 +1,404 @@
+//! The listener of `rustscan listen`, something to scan when testing.
+//!
+//! A [`Listener`] binds TCP and UDP ports of an address, reporting each
+//! port it couldn't bind instead of failing, and serves the others until
+//! told to stop: every connection and datagram becomes an [`Event`], gets
+//! the [`Answer`] of the listener and is counted in its [`Summary`]. A TCP
+//! connection is closed once answered, so a scan never waits on it. With
+//! [`Answer::Reflect`] it answers like the [`crate::reflector`], so it
+//! serves the `--reflector` of scans too.
+//!
+//! The integration tests of the crate listen with it in their own process.
+use crate::reflector;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long the listener sleeps when nothing came in.
+const IDLE: Duration = Duration::from_millis(10);
+
+/// How long an answer may take to be written to a connection.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The transports a listener binds ports of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        })
+    }
+}
+
+/// What the listener sends to every connection and datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answer {
+    Nothing,
+    /// Followed by a newline.
+    Banner(String),
+    /// The address it came from, see [`reflector::answer`].
+    Reflect,
+}
+
+impl Answer {
+    fn to(&self, peer: SocketAddr) -> Option<String> {
+        match self {
+            Answer::Nothing => None,
+            Answer::Banner(banner) => Some(format!("{banner}\n")),
+            Answer::Reflect => Some(reflector::answer(peer)),
+        }
+    }
+}
+
+/// A port the listener couldn't bind.
+#[derive(Debug)]
+pub struct BindFailure {
+    pub protocol: Protocol,
+    pub port: u16,
+    pub error: io::Error,
+}
+
+impl fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}: {}", self.protocol, self.port, self.error)
+    }
+}
+
+/// A connection, or a datagram, the listener got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub at: String,
+    pub protocol: Protocol,
+    pub port: u16,
+    pub peer: SocketAddr,
+    /// The size of a datagram.
+    pub bytes: Option<usize>,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}/{} <- {}",
+            self.at, self.protocol, self.port, self.peer
+        )?;
+        match self.bytes {
+            Some(bytes) => write!(f, ", {bytes} bytes"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What connected to every port of the listener, and from where.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    ports: BTreeMap<(Protocol, u16), (u64, BTreeSet<IpAddr>)>,
+}
+
+impl Summary {
+    pub fn record(&mut self, event: &Event) {
+        let (count, peers) = self.ports.entry((event.protocol, event.port)).or_default();
+        *count += 1;
+        peers.insert(event.peer.ip());
+    }
+
+    /// How many connections and datagrams came in.
+    pub fn total(&self) -> u64 {
+        self.ports.values().map(|(count, _)| count).sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ports.is_empty() {
+            return f.write_str("Nothing connected.");
+        }
+        let lines: Vec<String> = self
+            .ports
+            .iter()
+            .map(|((protocol, port), (count, peers))| {
+                let peers: Vec<String> = peers.iter().map(ToString::to_string).collect();
+                let what = match protocol {
+                    Protocol::Tcp => "connection(s)",
+                    Protocol::Udp => "datagram(s)",
+                };
+                format!(
+                    "{protocol}/{port}: {count} {what} from {}",
+                    peers.join(", ")
+                )
+            })
+            .collect();
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+/// The ports bound by a listener.
+#[derive(Debug)]
+pub struct Listener {
+    tcp: Vec<TcpListener>,
+    udp: Vec<UdpSocket>,
+}
+
+impl Listener {
+    /// Binds every one of `ports` of `address` for every one of `protocols`,
+    /// giving the ports which couldn't be bound next to the listener.
+    pub fn bind(
+        address: IpAddr,
+        ports: &[u16],
+        protocols: &[Protocol],
+    ) -> (Self, Vec<BindFailure>) {
+        let mut listener = Self {
+            tcp: Vec::new(),
+            udp: Vec::new(),
+        };
+        let mut failures = Vec::new();
+        for &protocol in protocols {
+            for &port in ports {
+                let socket = SocketAddr::new(address, port);
+                let bound = match protocol {
+                    Protocol::Tcp => TcpListener::bind(socket)
+                        .and_then(|tcp| tcp.set_nonblocking(true).map(|()| tcp))
+                        .map(|tcp| listener.tcp.push(tcp)),
+                    Protocol::Udp => UdpSocket::bind(socket)
+                        .and_then(|udp| udp.set_nonblocking(true).map(|()| udp))
+                        .map(|udp| listener.udp.push(udp)),
+                };
+                if let Err(error) = bound {
+                    failures.push(BindFailure {
+                        protocol,
+                        port,
+                        error,
+                    });
+                }
+            }
+        }
+        (listener, failures)
+    }
+
+    /// The addresses bound, which tell the ports picked for port 0.
+    pub fn local_addrs(&self) -> Vec<(Protocol, SocketAddr)> {
+        let tcp = self.tcp.iter().map(|tcp| (Protocol::Tcp, tcp.local_addr()));
+        let udp = self.udp.iter().map(|udp| (Protocol::Udp, udp.local_addr()));
+        tcp.chain(udp)
+            .filter_map(|(protocol, addr)| Some((protocol, addr.ok()?)))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_empty() && self.udp.is_empty()
+    }
+
+    /// Answers every connection and datagram with `answer` until `stop` is
+    /// set, giving each to `on_event`. The ones already waiting when it's
+    /// set are still answered.
+    pub fn serve(
+        &self,
+        answer: &Answer,
+        stop: &AtomicBool,
+        mut on_event: impl FnMut(&Event),
+    ) -> Summary {
+        let mut summary = Summary::default();
+        let mut buffer = [0; 2048];
+        loop {
+            let stopping = stop.load(Ordering::SeqCst);
+            let mut events = Vec::new();
+            for tcp in &self.tcp {
+                while let Ok((stream, peer)) = tcp.accept() {
+                    let port = tcp.local_addr().map_or(0, |addr| addr.port());
+                    // A peer gone before its answer still connected.
+                    let _ = send(stream, answer.to(peer));
+                    events.push(event(Protocol::Tcp, port, peer, None));
+                }
+            }
+            for udp in &self.udp {
+                while let Ok((bytes, peer)) = udp.recv_from(&mut buffer) {
+                    let port = udp.local_addr().map_or(0, |addr| addr.port());
+                    if let Some(answer) = answer.to(peer) {
+                        let _ = udp.send_to(answer.as_bytes(), peer);
+                    }
+                    events.push(event(Protocol::Udp, port, peer, Some(bytes)));
+                }
+            }
+
+            for event in &events {
+                summary.record(event);
+                on_event(event);
+            }
+            if stopping {
+                return summary;
+            }
+            if events.is_empty() {
+                thread::sleep(IDLE);
+            }
+        }
+    }
+}
+
+fn event(protocol: Protocol, port: u16, peer: SocketAddr, bytes: Option<usize>) -> Event {
+    Event {
+        at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        protocol,
+        port,
+        peer,
+        bytes,
+    }
+}
+
+fn send(mut stream: TcpStream, answer: Option<String>) -> io::Result<()> {
+    if let Some(answer) = answer {
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.write_all(answer.as_bytes())?;
+    }
+    Ok(())
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// A flag set by Ctrl-C, which doesn't end the process anymore. Ctrl-C still
+/// ends it right away where it can't be caught.
+pub fn stop_on_interrupt() -> &'static AtomicBool {
+    #[cfg(unix)]
+    {
+        extern "C" fn interrupted(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        }
+        // SAFETY: the handler only stores to an atomic, which is async
+        // signal safe.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+    &INTERRUPTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Answer, Event, Listener, Protocol, Summary};
+    use crate::reflector;
+    use std::io::Read;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn event(protocol: Protocol, port: u16, peer: &str) -> Event {
+        Event {
+            at: "2024-01-01T00:00:00Z".to_owned(),
+            protocol,
+            port,
+            peer: peer.parse().unwrap(),
+            bytes: None,
+        }
+    }
+
+    #[test]
+    fn summary_counts_per_port() {
+        let mut summary = Summary::default();
+        assert_eq!(summary.to_string(), "Nothing connected.");
+        for event in [
+            event(Protocol::Udp, 53, "192.0.2.1:5000"),
+            event(Protocol::Tcp, 8000, "192.0.2.2:40000"),
+            event(Protocol::Tcp, 8000, "192.0.2.1:40001"),
+            event(Protocol::Tcp, 8000, "192.0.2.1:40002"),
+        ] {
+            summary.record(&event);
+        }
+        assert_eq!(summary.total(), 4);
+        assert_eq!(
+            summary.to_string(),
+            "tcp/8000: 3 connection(s) from 192.0.2.1, 192.0.2.2\n\
+             udp/53: 1 datagram(s) from 192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn taken_ports_are_reported_and_the_others_bound() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let (listener, failures) = Listener::bind(LOCALHOST, &[port, 0], &[Protocol::Tcp]);
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].protocol, Protocol::Tcp);
+        assert_eq!(failures[0].port, port);
+        assert!(failures[0]
+            .to_string()
+            .starts_with(&format!("tcp/{port}: ")));
+        assert_eq!(listener.local_addrs().len(), 1);
+    }
+
+    // Serves `listener` with `answer` while `connect` runs against its
+    // first address, then gives the events.
+    fn serve(listener: &Listener, answer: Answer, connect: impl FnOnce(SocketAddr)) -> Vec<Event> {
+        let stop = AtomicBool::new(false);
+        let (_, address) = listener.local_addrs()[0];
+        let mut events = Vec::new();
+        thread::scope(|scope| {
+            let served =
+                scope.spawn(|| listener.serve(&answer, &stop, |event| events.push(event.clone())));
+            connect(address);
+            stop.store(true, Ordering::SeqCst);
+            assert_eq!(served.join().unwrap().total(), 1);
+        });
+        events
+    }
+
+    #[test]
+    fn connections_get_the_banner() {
+        let (listener, _) = Listener::bind(LOCALHOST, &[0], &[Protocol::Tcp]);
+        let events = serve(
+            &listener,
+            Answer::Banner("SSH-2.0-test".to_owned()),
+            |address| {
+                let mut stream = TcpStream::connect(address).unwrap();
+                let mut banner = String::new();
+                stream.read_to_string(&mut banner).unwrap();
+                assert_eq!(banner, "SSH-2.0-test\n");
+            },
+        );
+        assert_eq!(events[0].protocol, Protocol::Tcp);
+        assert_eq!(events[0].peer.ip(), LOCALHOST);
+    }
+
+    #[test]
+    fn datagrams_get_the_banner() {
+        let (listener, _) = Listener::bind(LOCALHOST, &[0], &[Protocol::Udp]);
+        let events = serve(&listener, Answer::Banner("pong".to_owned()), |address| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            socket.send_to(b"ping", address).unwrap();
+            let mut answer = [0; 16];
+            let (read, _) = socket.recv_from(&mut answer).unwrap();
+            assert_eq!(&answer[..read], b"pong\n");
+        });
+        assert_eq!(events[0].bytes, Some(4));
+        assert!(events[0].to_string().ends_with(", 4 bytes"));
+    }
+
+    #[test]
+    fn listener_serves_the_reflector() {
+        let (listener, _) = Listener::bind(LOCALHOST, &[0], &[Protocol::Tcp]);
+        serve(&listener, Answer::Reflect, |address| {
+            let reflection = reflector::reflect(address, None, Duration::from_secs(2)).unwrap();
+            assert!(reflection.port_kept(), "{}", reflection);
+        });
+    }
+}
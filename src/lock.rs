@@ -0,0 +1,410 @@
+//! The cooperative lock of `--lock`, keeping runs on a shared machine from
+//! scanning the same targets at once.
+//!
+//! Every run taking the lock leaves a lockfile in the lock directory, with
+//! its run id, the fingerprint of its configuration, its PID and the
+//! networks it scans, see [`LockRecord`]. A run then looks at the lockfiles
+//! of the others: one whose networks intersect its own is scanning some of
+//! the same hosts. Lockfiles of processes which are gone, crashed or killed
+//! before removing them, are stale and removed on the way.
+//!
+//! A lockfile is written whole to a temporary file first and linked to its
+//! name, which fails if the name is taken: a lockfile is never seen half
+//! written and two runs never share one. Since every run writes its lockfile
+//! before looking at the others, of two runs starting at the same time at
+//! least one sees the other.
+use cidr_utils::cidr::IpCidr;
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The extension of lockfiles, anything else in the directory is ignored.
+const EXTENSION: &str = "lock";
+
+/// The networks a run scans.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct LockScope {
+    cidrs: Vec<IpCidr>,
+}
+
+impl LockScope {
+    /// The scope of scanning `ips`, resolved from `targets`. The CIDRs of the
+    /// targets are kept as they are, every other address is a network of its
+    /// own.
+    pub fn new(targets: &[String], ips: &[IpAddr]) -> Self {
+        let mut cidrs: Vec<IpCidr> = targets
+            .iter()
+            .filter(|target| target.contains('/'))
+            .filter_map(|target| IpCidr::from_str(target).ok())
+            .collect();
+        let hosts: Vec<IpCidr> = ips
+            .iter()
+            .filter(|ip| !cidrs.iter().any(|cidr| cidr.contains(ip)))
+            .map(|ip| IpCidr::new_host(*ip))
+            .collect();
+        cidrs.extend(hosts);
+        cidrs.sort();
+        cidrs.dedup();
+        Self { cidrs }
+    }
+
+    /// Whether an address is in both scopes.
+    pub fn overlaps(&self, other: &LockScope) -> bool {
+        self.cidrs
+            .iter()
+            .any(|cidr| other.cidrs.iter().any(|other| intersect(cidr, other)))
+    }
+}
+
+/// Two networks are either disjoint or one holds the other, and then its
+/// first address too.
+fn intersect(a: &IpCidr, b: &IpCidr) -> bool {
+    a.contains(&b.first_address()) || b.contains(&a.first_address())
+}
+
+impl TryFrom<Vec<String>> for LockScope {
+    type Error = String;
+
+    fn try_from(cidrs: Vec<String>) -> Result<Self, Self::Error> {
+        let cidrs = cidrs
+            .iter()
+            .map(|cidr| IpCidr::from_str(cidr).map_err(|e| format!("{cidr:?}: {e}")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { cidrs })
+    }
+}
+
+impl From<LockScope> for Vec<String> {
+    fn from(scope: LockScope) -> Self {
+        scope.cidrs.iter().map(|cidr| format!("{cidr:#}")).collect()
+    }
+}
+
+/// The content of a lockfile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockRecord {
+    pub run_id: String,
+    /// The fingerprint of the configuration of the run.
+    pub scope_hash: String,
+    pub pid: u32,
+    pub scope: LockScope,
+}
+
+impl LockRecord {
+    /// The record of this process.
+    pub fn new(run_id: &str, scope_hash: &str, scope: LockScope) -> Self {
+        Self {
+            run_id: run_id.to_owned(),
+            scope_hash: scope_hash.to_owned(),
+            pid: std::process::id(),
+            scope,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!("{}.{EXTENSION}", self.run_id)
+    }
+}
+
+/// The directory holding the lockfiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockDir {
+    path: PathBuf,
+}
+
+impl LockDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The directory shared by every user of the machine, in its temporary
+    /// directory.
+    pub fn shared() -> Self {
+        Self::new(env::temp_dir().join("rustscan-locks"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Takes the lock of `record`. Returns it along with the locks of the
+    /// other runs whose scope overlaps it. Fails when the lockfile can't be
+    /// written, or another run has the same run id.
+    pub fn acquire(&self, record: LockRecord) -> io::Result<(ScanLock, Vec<LockRecord>)> {
+        self.create()?;
+        let path = self.path.join(record.file_name());
+        let written = self.path.join(format!(".{}.tmp", record.run_id));
+        let json = serde_json::to_string(&record).expect("Lock records always serialize.");
+        fs::write(&written, json)?;
+        let linked = fs::hard_link(&written, &path);
+        let _ = fs::remove_file(&written);
+        linked?;
+
+        let lock = ScanLock { path };
+        let overlapping = self
+            .live_locks()?
+            .into_iter()
+            .filter(|other| other.run_id != record.run_id && other.scope.overlaps(&record.scope))
+            .collect();
+        Ok((lock, overlapping))
+    }
+
+    /// The locks of the runs still going, removing the stale ones. Lockfiles
+    /// which can't be read are left alone.
+    pub fn live_locks(&self) -> io::Result<Vec<LockRecord>> {
+        let mut live = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let Some(record) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|json| serde_json::from_str::<LockRecord>(&json).ok())
+            else {
+                continue;
+            };
+            if is_running(record.pid) {
+                live.push(record);
+            } else {
+                // Another user's stale lockfile can't be removed, it's only
+                // left out.
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(live)
+    }
+
+    /// Creates the directory, writable by everyone like the temporary
+    /// directory is, so that the runs of every user share it.
+    fn create(&self) -> io::Result<()> {
+        if self.path.is_dir() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o1777))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the process `pid` is running. Signal 0 only checks it can be
+/// signaled, a process of another user can't, but exists.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // 0 and negative PIDs would stand for process groups.
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // SAFETY: signal 0 is never delivered, only the permission is checked.
+    let checked = unsafe { libc::kill(pid, 0) };
+    checked == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to check, locks are never taken for stale.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// A lock held, removed when dropped.
+#[derive(Debug)]
+pub struct ScanLock {
+    path: PathBuf,
+}
+
+impl ScanLock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScanLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockDir, LockRecord, LockScope};
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn scope(targets: &[&str], ips: &[&str]) -> LockScope {
+        let targets: Vec<String> = targets.iter().map(|target| target.to_string()).collect();
+        let ips: Vec<IpAddr> = ips.iter().map(|ip| ip.parse().unwrap()).collect();
+        LockScope::new(&targets, &ips)
+    }
+
+    fn lock_dir(name: &str) -> LockDir {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("rustscan-lock-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        LockDir::new(path)
+    }
+
+    fn record(run_id: &str, scope: LockScope) -> LockRecord {
+        LockRecord::new(run_id, "0123456789abcdef", scope)
+    }
+
+    #[test]
+    fn scopes_keep_cidrs_and_hosts_outside_them() {
+        let scope = scope(
+            &["10.0.0.0/30", "web.example", "192.0.2.9"],
+            &["10.0.0.1", "10.0.0.2", "192.0.2.9", "198.51.100.7"],
+        );
+        let cidrs: Vec<String> = scope.into();
+        assert_eq!(cidrs, ["10.0.0.0/30", "192.0.2.9/32", "198.51.100.7/32"]);
+    }
+
+    #[test]
+    fn overlaps_of_mixed_cidrs_and_hosts() {
+        let network = scope(&["10.0.0.0/16"], &[]);
+        // A host inside the network.
+        assert!(network.overlaps(&scope(&[], &["10.0.200.1"])));
+        // A network inside it, and one holding it.
+        assert!(network.overlaps(&scope(&["10.0.4.0/24"], &[])));
+        assert!(network.overlaps(&scope(&["10.0.0.0/8"], &[])));
+        assert!(scope(&["10.0.4.0/24"], &[]).overlaps(&network));
+        // Next to it.
+        assert!(!network.overlaps(&scope(&["10.1.0.0/16"], &["10.1.0.1", "192.0.2.1"])));
+        // Hosts against hosts.
+        let hosts = scope(&[], &["192.0.2.1", "2001:db8::1"]);
+        assert!(hosts.overlaps(&scope(&[], &["2001:db8::1"])));
+        assert!(!hosts.overlaps(&scope(&[], &["192.0.2.2"])));
+        // The families never meet.
+        assert!(!scope(&["::/0"], &[]).overlaps(&scope(&["0.0.0.0/0"], &[])));
+        assert!(scope(&["2001:db8::/32"], &[]).overlaps(&hosts));
+        assert!(!LockScope::default().overlaps(&network));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let record = record("run-1", scope(&["10.0.0.0/24"], &["2001:db8::1"]));
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(
+            json.contains(r#""scope":["10.0.0.0/24","2001:db8::1/128"]"#),
+            "{}",
+            json
+        );
+        assert_eq!(serde_json::from_str::<LockRecord>(&json).unwrap(), record);
+        assert!(serde_json::from_str::<LockRecord>(&json.replace("/24", "/33")).is_err());
+    }
+
+    #[test]
+    fn overlapping_locks_are_seen_and_released() {
+        let dir = lock_dir("overlap");
+        let (first, overlapping) = dir
+            .acquire(record("run-1", scope(&["10.0.0.0/24"], &[])))
+            .unwrap();
+        assert!(overlapping.is_empty());
+        assert!(first.path().is_file());
+
+        let (_apart, overlapping) = dir
+            .acquire(record("run-2", scope(&[], &["10.0.1.1"])))
+            .unwrap();
+        assert!(overlapping.is_empty());
+        let (second, overlapping) = dir
+            .acquire(record("run-3", scope(&[], &["10.0.0.7"])))
+            .unwrap();
+        let run_ids: Vec<&str> = overlapping
+            .iter()
+            .map(|lock| lock.run_id.as_str())
+            .collect();
+        assert_eq!(run_ids, ["run-1"]);
+        assert_eq!(overlapping[0].pid, std::process::id());
+
+        // A run id is only used once.
+        assert!(dir.acquire(record("run-3", LockScope::default())).is_err());
+        drop(first);
+        drop(second);
+        let (_third, overlapping) = dir
+            .acquire(record("run-4", scope(&["10.0.0.0/8"], &[])))
+            .unwrap();
+        let run_ids: Vec<&str> = overlapping
+            .iter()
+            .map(|lock| lock.run_id.as_str())
+            .collect();
+        assert_eq!(run_ids, ["run-2"]);
+        let _ = std::fs::remove_dir_all(dir.path());
+    }
+
+    #[test]
+    fn stale_locks_are_removed() {
+        let dir = lock_dir("stale");
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let mut stale = record("crashed", scope(&["10.0.0.0/24"], &[]));
+        stale.pid = dead;
+        std::fs::create_dir_all(dir.path()).unwrap();
+        let stale_path = dir.path().join("crashed.lock");
+        std::fs::write(&stale_path, serde_json::to_string(&stale).unwrap()).unwrap();
+        // Not a lockfile of this version, left alone.
+        let unreadable = dir.path().join("garbage.lock");
+        std::fs::write(&unreadable, "{").unwrap();
+
+        let (_lock, overlapping) = dir
+            .acquire(record("fresh", scope(&[], &["10.0.0.1"])))
+            .unwrap();
+        assert!(overlapping.is_empty(), "{:?}", overlapping);
+        assert!(!stale_path.exists());
+        assert!(unreadable.exists());
+        let live: Vec<String> = dir
+            .live_locks()
+            .unwrap()
+            .into_iter()
+            .map(|lock| lock.run_id)
+            .collect();
+        assert_eq!(live, ["fresh"]);
+        let _ = std::fs::remove_dir_all(dir.path());
+    }
+
+    #[test]
+    fn runs_starting_together_see_each_other() {
+        const RUNS: usize = 8;
+        for round in 0..10 {
+            let dir = lock_dir(&format!("race-{round}"));
+            let barrier = Arc::new(Barrier::new(RUNS));
+            let runs: Vec<_> = (0..RUNS)
+                .map(|run| {
+                    let (dir, barrier) = (dir.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let ip = format!("10.0.0.{run}");
+                        dir.acquire(record(
+                            &format!("run-{run}"),
+                            scope(&["10.0.0.0/28"], &[&ip]),
+                        ))
+                        .unwrap()
+                    })
+                })
+                .collect();
+            let locks: Vec<_> = runs.into_iter().map(|run| run.join().unwrap()).collect();
+            // Whatever the order, at most one run went on thinking it was
+            // alone.
+            let alone = locks
+                .iter()
+                .filter(|(_, overlapping)| overlapping.is_empty())
+                .count();
+            assert!(alone <= 1, "{}", alone);
+            drop(locks);
+            let _ = std::fs::remove_dir_all(dir.path());
+        }
+    }
+}
@@ -2,21 +2,83 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::doc_markdown, clippy::if_not_else, clippy::non_ascii_literal)]
 
+use rustscan::alias::{Aliases, Naming};
+use rustscan::benchmark::tune::{self, Measurements};
 use rustscan::benchmark::{Benchmark, NamedTimer};
-use rustscan::input::{self, Config, Opts, ScriptsRequired};
-use rustscan::port_strategy::PortStrategy;
-use rustscan::scanner::Scanner;
-use rustscan::scripts::{init_scripts, Script, ScriptFile};
-use rustscan::{detail, funny_opening, output, warning};
+use rustscan::bundle::Bundle;
+use rustscan::cache::{CacheEntry, CacheKey, ScanCache};
+use rustscan::dashboard::{self, Dashboard};
+use rustscan::discovery::{ConnectPing, Liveness, Undetermined, Waves, DISCOVERY_PORTS};
+use rustscan::errors::ErrorCode;
+use rustscan::export::Export;
+use rustscan::family::{self, FamilySelection};
+use rustscan::fingerprint::Fingerprint;
+use rustscan::groups::group_hosts;
+use rustscan::hints::ProtocolHint;
+use rustscan::html::{self, Server};
+use rustscan::import::Imported;
+use rustscan::input::{
+    self, Action, BundleAction, BundleArgs, Config, KeygenArgs, ListenArgs, MergeArgs, Opts,
+    OutputFormat, PortRange, ReflectorArgs, ScopeMode, ScriptsRequired, SelftestArgs, TuneArgs,
+    VerifyArgs,
+};
+use rustscan::listen::{self, Answer, Listener};
+use rustscan::lock::{LockDir, LockRecord, LockScope, ScanLock};
+use rustscan::merge;
+use rustscan::notes::Notes;
+use rustscan::notify::{Notifier, WebhookSink};
+use rustscan::plan::ScanPlan;
+use rustscan::port_strategy::{DefaultPorts, OrderFile};
+use rustscan::previous::PreviousResults;
+use rustscan::private_dir::PrivateDir;
+use rustscan::privileges::{self, Host, Platform};
+#[cfg(unix)]
+use rustscan::privileges::{CapabilityReport, Feature};
+use rustscan::probe::{Prober, ServiceGuess};
+use rustscan::reflector;
+use rustscan::report::{
+    self, HostReport, PortDefaults, PortProbe, ScanReport, ScanStats, SkipReason,
+};
+use rustscan::rescan;
+use rustscan::resources::{self, Process, Sampler};
+use rustscan::scanner::{
+    can_bind_device, select, AdaptiveTries, AdminProhibited, BastionNames, Binding, Connectivity,
+    Conntrack, Heartbeat, HostInterface, HostTimeout, Interface, JumpSession, Pacing, ProxyRoute,
+    ProxySource, ScanControl, ScanOutcome, ScanUpdate, Scanner, SocketOptions, SourcePorts,
+    SshJump, Unusable,
+};
+use rustscan::scope::Scope;
+use rustscan::scripts::children;
+use rustscan::scripts::pipeline::{self, Backpressure, Pipeline};
+use rustscan::scripts::port_hooks::{self, HookTarget, PortHookRun};
+use rustscan::scripts::{
+    check_scripts, init_scripts, nmap, run_with_retries, split_phases, OnFail, Phase, RetryPolicy,
+    Script, ScriptFile, ScriptRun,
+};
+use rustscan::selftest;
+use rustscan::signing::{self, PublicKey, SigningKey};
+use rustscan::sink::{Greppable, Html, Json, Normal, ScanEnd, Sinks};
+use rustscan::snmp::{SnmpProber, SNMP_PORT};
+use rustscan::trace::{self, TraceEvent, TraceWriter, Tracer};
+use rustscan::tui::{self, Verbosity};
+use rustscan::version::{self, VersionProber, VersionRule};
+use rustscan::window::{self, Checkpoint, Event, Guard, Origin, SystemClock, Warden};
+use rustscan::{detail, funny_opening, output, verbose, warning};
 
 use colorful::{Color, Colorful};
 use futures::executor::block_on;
-use std::collections::HashMap;
-use std::net::IpAddr;
-use std::string::ToString;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use rustscan::address::parse_addresses;
+use rustscan::address::{
+    parse_targets, parse_targets_with_resolver, Stage, Target, Targets, Unresolved,
+};
+use rustscan::egress::EgressReport;
 
 extern crate colorful;
 extern crate dirs;
@@ -26,6 +88,21 @@ extern crate dirs;
 const DEFAULT_FILE_DESCRIPTORS_LIMIT: u64 = 8000;
 // Safest batch size based on experimentation
 const AVERAGE_BATCH_SIZE: u16 = 3000;
+// File descriptors kept free of probe sockets for everything else, like
+// the scripts and the resolver.
+#[cfg(unix)]
+const RESERVED_FILE_DESCRIPTORS: u64 = 100;
+// The network is considered down once 80% of the last 50 probes were
+// unreachable.
+const NETWORK_DOWN_WINDOW: usize = 50;
+const NETWORK_DOWN_THRESHOLD: f64 = 0.8;
+// The table is considered full once 90% of the last 200 probes of hosts
+// which answered timed out, the batch size is divided by 16 at most.
+const CONNTRACK_WINDOW: usize = 200;
+const CONNTRACK_THRESHOLD: f64 = 0.9;
+const CONNTRACK_MAX_SLOWDOWN: u32 = 16;
+// How many source ports are checked against the --reflector.
+const REFLECTIONS: usize = 3;
 
 #[macro_use]
 extern crate log;
@@ -42,155 +119,3041 @@ fn main() {
     let mut opts: Opts = Opts::read();
     let config = Config::read(opts.config_path.clone());
     opts.merge(&config);
+    let mut default_ports = opts.default_ports(&config);
+
+    // The JSON document is the only thing printed, just like greppable mode
+    // it silences everything else. Written to a file, it leaves stdout be.
+    if opts.format == OutputFormat::Json && opts.output_file.is_none() {
+        opts.greppable = true;
+    }
+
+    tui::set_verbosity(opts.verbosity());
+    tui::set_silence_warnings(opts.silence_warnings);
+    children::CHILDREN.set_limit(opts.max_children.into());
+
+    // The bundle has the last word over the command line and the config file.
+    let mut bundle_dir = None;
+    if let Some(Action::Bundle(BundleArgs {
+        action: BundleAction::Run {
+            file,
+            ignore_missing_scripts,
+        },
+    })) = opts.action.clone()
+    {
+        let (ports, dir) = load_bundle(&mut opts, &file, ignore_missing_scripts);
+        default_ports = ports;
+        bundle_dir = Some(dir);
+    }
 
     debug!("Main() `opts` arguments are {:?}", opts);
 
+    let capabilities = privileges::detect(&Host, Platform::current());
+    debug!("Capabilities {:?}", capabilities);
+    if opts.capabilities {
+        println!("{}", capabilities.render());
+        return;
+    }
+    if opts.schema {
+        print!("{}", opts.schema_version.schema());
+        return;
+    }
+
+    if let Some(Action::Tune(args)) = &opts.action {
+        block_on(tune(&opts, args));
+        return;
+    }
+    if let Some(Action::Merge(args)) = &opts.action {
+        merge_reports(&opts, args);
+        return;
+    }
+    if let Some(Action::Selftest(args)) = &opts.action {
+        block_on(run_selftest(&opts, args));
+        return;
+    }
+    if let Some(Action::Reflector(args)) = &opts.action {
+        run_reflector(&opts, args);
+        return;
+    }
+    if let Some(Action::Listen(args)) = &opts.action {
+        run_listener(&opts, args);
+        return;
+    }
+    if let Some(Action::Bundle(BundleArgs {
+        action: BundleAction::Export { file },
+    })) = &opts.action
+    {
+        export_bundle(&opts, default_ports, file);
+        return;
+    }
+    if let Some(Action::Keygen(args)) = &opts.action {
+        generate_keys(&opts, args);
+        return;
+    }
+    if let Some(Action::Verify(args)) = &opts.action {
+        verify_report(&opts, args);
+        return;
+    }
+    if let Some(listener) = &opts.egress_check {
+        run_egress_check(&opts, listener, &capabilities);
+        return;
+    }
+    if let Some(reflector) = &opts.reflector {
+        verify_source_ports(&opts, reflector);
+    }
+
+    // The arguments after `--` follow the ones of --nmap-args on its argv.
+    if let Some(nmap_args) = &opts.nmap_args {
+        let user_args = [nmap_args.0.as_slice(), &opts.command].concat();
+        if let Err(e) = nmap::validate_args(&user_args) {
+            warning!(
+                ErrorCode::InvalidArguments,
+                e,
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::InvalidArguments.exit_code());
+        }
+    }
+    if opts.tui {
+        if let Err(e) = dashboard::check(&opts) {
+            warning!(
+                ErrorCode::IncompatibleOptions,
+                e,
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::IncompatibleOptions.exit_code());
+        }
+    }
+
     let scripts_to_run: Vec<ScriptFile> = match init_scripts(&opts.scripts) {
         Ok(scripts_to_run) => scripts_to_run,
         Err(e) => {
             warning!(
+                ErrorCode::ScriptsFailed,
                 format!("Initiating scripts failed!\n{e}"),
                 opts.greppable,
                 opts.accessible
             );
-            std::process::exit(1);
+            std::process::exit(ErrorCode::ScriptsFailed.exit_code());
         }
     };
 
     debug!("Scripts initialized {:?}", &scripts_to_run);
 
-    if !opts.greppable && !opts.accessible {
-        print_opening(&opts);
+    let script_checks = check_scripts(scripts_to_run, env::var_os("PATH").as_deref());
+    if opts.list_scripts {
+        for check in &script_checks {
+            let tags = check.script.tags.clone().unwrap_or_default().join(", ");
+            if check.is_ready() {
+                println!("{} [{tags}]: ready", check.name());
+            } else {
+                println!(
+                    "{} [{tags}]: disabled, {}",
+                    check.name(),
+                    check.unmet.join(", ")
+                );
+            }
+        }
+        return;
     }
 
-    let ips: Vec<IpAddr> = parse_addresses(&opts);
+    // Scripts only run when their output can be shown.
+    let runs_scripts = !opts.greppable && !opts.quiet && opts.scripts != ScriptsRequired::None;
+    let mut scripts_to_run: Vec<ScriptFile> = Vec::with_capacity(script_checks.len());
+    for check in script_checks {
+        if check.is_ready() {
+            scripts_to_run.push(check.script);
+            continue;
+        }
+        if !runs_scripts {
+            continue;
+        }
 
-    if ips.is_empty() {
+        let message = format!(
+            "Script {} is disabled, it {}.",
+            check.name(),
+            check.unmet.join(", ")
+        );
+        if opts.strict_scripts {
+            warning!(
+                ErrorCode::ScriptUnavailable,
+                format!("{message}\nAborting scan because of --strict-scripts."),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::ScriptUnavailable.exit_code());
+        }
         warning!(
-            "No IPs could be resolved, aborting scan.",
+            ErrorCode::ScriptUnavailable,
+            message,
             opts.greppable,
             opts.accessible
         );
-        std::process::exit(1);
     }
+    let (pre_scripts, scripts_to_run) = split_phases(scripts_to_run);
+
+    if tui::shows(Verbosity::Normal, opts.greppable) && !opts.accessible {
+        print_opening(&opts);
+    }
+
+    // The bastion resolves the hostnames, its hosts are scanned as it sees them.
+    let bastion_names = (opts.ssh_jump.is_some() && !opts.udp).then(BastionNames::default);
+    let mut targets = match &bastion_names {
+        Some(names) => parse_targets_with_resolver(&opts, names),
+        None => parse_targets(&opts),
+    };
+    let imported = opts
+        .import
+        .as_deref()
+        .map(|path| import_targets(&opts, path, &mut targets));
+    let previous = opts
+        .only_previously_open
+        .as_deref()
+        .map(|path| read_previous(&opts, path, &mut targets));
+    let rescan = opts
+        .rescan_filtered
+        .as_deref()
+        .map(|path| read_rescan(&opts, path, &mut targets));
+    let mut priorities = opts
+        .prioritize_from
+        .as_deref()
+        .map(|path| read_priorities(&opts, path));
+    if let Some(imported) = imported.filter(|imported| !imported.is_empty()) {
+        let priorities = priorities.get_or_insert_with(HashMap::new);
+        for (ip, ports) in imported {
+            priorities.entry(ip).or_default().extend(ports);
+        }
+    }
+    if let Some(path) = opts.scope.as_deref() {
+        check_scope(&opts, &read_scope(&opts, path), path, &mut targets);
+    }
+    let notes = opts.notes.as_deref().map(|path| read_notes(&opts, path));
+    let aliases = opts
+        .aliases
+        .as_deref()
+        .map(|path| read_aliases(&opts, path));
+    let naming = Naming::new(opts.prefer_names, aliases.unwrap_or_default());
+    let version_rules = read_version_rules(&opts);
+    let order_file = opts
+        .order_file
+        .as_deref()
+        .map(|path| read_order_file(&opts, path));
+    // Every file of the bundle is read by now.
+    drop(bundle_dir);
+    let plan = ScanPlan::new(&opts, &targets, order_file, default_ports);
+    let origin = Origin::find(&opts.addresses);
+    let fingerprint = match &origin {
+        Some((path, origin)) => resume_checkpoint(&opts, path, origin, plan.fingerprint.clone()),
+        None => plan.fingerprint.clone(),
+    };
+    if let Some(expected) = &opts.verify_config {
+        verify_config(&opts, &fingerprint, expected);
+    }
+
+    if plan.is_empty() {
+        report_no_targets(&opts, &plan);
+    }
+    if let Some(host) = &opts.explain_ports {
+        explain_ports(&opts, &plan, &targets, host);
+        return;
+    }
+    if !plan.portless.is_empty() {
+        report_portless(&opts, &plan);
+    }
+
+    if opts.dry_run {
+        print_dry_run(&opts, &plan);
+        return;
+    }
+
+    let ssh_jump = connect_ssh_jump(&opts, bastion_names);
+    let proxy = connect_proxy(&opts, &targets, |name| env::var(name).ok());
 
     #[cfg(unix)]
-    let batch_size: u16 = infer_batch_size(&opts, adjust_ulimit_size(&opts));
+    let batch_size: u16 = {
+        let open = open_file_descriptors();
+        let ulimit = adjust_ulimit_size(&opts, open, &capabilities);
+        infer_batch_size(&opts, ulimit.saturating_sub(open))
+    };
 
     #[cfg(not(unix))]
     let batch_size: u16 = AVERAGE_BATCH_SIZE;
 
+    let dual_stack = std::mem::take(&mut targets.dual_stack);
+    let mut family_selections = race_families(&opts, &plan, dual_stack, batch_size);
+    for decision in family_selections
+        .iter()
+        .filter_map(FamilySelection::decision)
+    {
+        targets.insert(
+            decision.address,
+            &decision.hostname,
+            Some(&decision.hostname),
+        );
+    }
+    let mut ips: Vec<IpAddr> = targets.ips();
+    // The hosts whose interface can't be used are left out of the scan.
+    let interfaces = opts
+        .interface_map
+        .as_ref()
+        .map(|_| list_interfaces(&opts))
+        .unwrap_or_default();
+    let interface_selections = select_interfaces(&opts, &interfaces, &ips);
+    // The other address of a dual-stack host is scanned instead of the one
+    // picked, should its first batch get no response.
+    let other_families: HashMap<IpAddr, IpAddr> = family_selections
+        .iter()
+        .filter_map(|selection| Some((selection.chosen()?, selection.skipped()?)))
+        .filter(|(_, other)| !ips.contains(other))
+        .collect();
+    let other_selections = select_interfaces(
+        &opts,
+        &interfaces,
+        &other_families.values().copied().collect::<Vec<IpAddr>>(),
+    );
+    let other_families: HashMap<IpAddr, IpAddr> = other_families
+        .into_iter()
+        .filter(|(_, other)| !other_selections.get(other).is_some_and(Result::is_err))
+        .collect();
+    let interface_selections: HashMap<IpAddr, Result<Binding, Unusable>> = interface_selections
+        .into_iter()
+        .chain(other_selections)
+        .collect();
+
+    // The dashboard follows the scan through its feed, and steers it.
+    let (feed, updates) = if opts.tui {
+        let (feed, updates) = std::sync::mpsc::channel();
+        (Some(feed), Some(updates))
+    } else {
+        (None, None)
+    };
+    // So does the guard of --allowed-window.
+    let (window_feed, window_updates) = if opts.allowed_window.is_empty() {
+        (None, None)
+    } else {
+        let (feed, updates) = std::sync::mpsc::channel();
+        (Some(feed), Some(updates))
+    };
+    let control = ScanControl::default();
+    let sampler = opts
+        .resource_report
+        .then(|| Sampler::start(Process, resources::INTERVAL));
+    let run_id = opts
+        .run_id
+        .clone()
+        .or_else(|| origin.map(|(_, origin)| origin.run_id))
+        .unwrap_or_else(report::new_run_id);
+    let started_at = SystemTime::now();
+    let signing_key = opts
+        .sign_key
+        .as_deref()
+        .map(|path| load_signing_key(&opts, path));
+    let scan_lock = (opts.lock || opts.lock_strict)
+        .then(|| take_lock(&opts, &run_id, &fingerprint, &ips))
+        .flatten();
+    let (trace_writer, tracer) = opts
+        .trace_file
+        .as_deref()
+        .map(|path| start_trace(&opts, path))
+        .unzip();
+    let sinks = output_sinks(&opts, &run_id);
+    // The address of --serve-report is taken before scanning, so that a
+    // scan isn't run for results which can't be served.
+    let report_server = opts
+        .serve_report
+        .map(|address| bind_report_server(&opts, address));
+
+    // The scripts of --pipeline-scripts start as soon as their host is
+    // scanned.
+    let pipeline = (opts.pipeline_scripts && runs_scripts && opts.format != OutputFormat::Json)
+        .then(|| {
+            let names = targets
+                .hosts
+                .iter()
+                .map(|target| (target.ip, naming.name(target.ip, &target.hostnames)))
+                .collect();
+            start_pipeline(
+                ScriptEnv::new(&opts, &scripts_to_run, &run_id, tracer.as_ref()),
+                names,
+                &control,
+                batch_size,
+            )
+        });
+    let pipeline_hooks = pipeline.as_ref().map(Pipeline::hooks);
+
     // Added by wasuaje - 01/26/2024:
     // exclude_ports  is an exclusion port list
     //
     // Added by brendanglancy - 5/19/2024:
     // udp is an option to do a udp scan
-    let scanner = Scanner::new(
-        &ips,
-        batch_size,
-        Duration::from_millis(opts.timeout.into()),
-        opts.tries,
+    let build_scanner = |ips: &[IpAddr], port_overrides: HashMap<IpAddr, PortRange>| {
+        let scanner = plan
+            .scanner(ips, batch_size, port_overrides)
+            .with_knock(opts.knock.clone(), opts.knock_delay)
+            .with_socket_options(SocketOptions {
+                ttl: opts.ttl,
+                nodelay: opts.nodelay,
+            })
+            .with_fairness(opts.fairness)
+            .with_fallbacks(other_families.clone());
+        let scanner = if opts.preallocate_sockets && !opts.udp {
+            scanner.with_socket_pool()
+        } else {
+            scanner
+        };
+        let scanner = match &feed {
+            Some(feed) => scanner
+                .with_feed(feed.clone())
+                .with_control(control.clone()),
+            None => scanner,
+        };
+        let scanner = match &window_feed {
+            Some(feed) => scanner
+                .with_feed(feed.clone())
+                .with_control(control.clone()),
+            None => scanner,
+        };
+        let scanner = match &pipeline_hooks {
+            Some(hooks) => scanner
+                .with_hooks(hooks.clone())
+                .with_control(control.clone()),
+            None => scanner,
+        };
+        let scanner = scanner.with_sinks(sinks.clone());
+        let scanner = match &tracer {
+            Some(tracer) => scanner.with_trace(tracer.clone()),
+            None => scanner,
+        };
+        let scanner = match &opts.timeout_map {
+            Some(timeout_map) => scanner.with_timeout_map(timeout_map.clone()),
+            None => scanner,
+        };
+        let scanner = match opts.adaptive_tries {
+            Some(silent_probes) if !opts.udp => scanner.with_adaptive_tries(AdaptiveTries {
+                silent_probes,
+                timeout: opts
+                    .adaptive_timeout
+                    .map(|timeout| Duration::from_millis(timeout.into())),
+            }),
+            _ => scanner,
+        };
+        let scanner = if opts.ignore_admin_prohibited || opts.udp {
+            scanner
+        } else {
+            scanner.with_admin_prohibited(AdminProhibited::default())
+        };
+        let scanner = match opts.spread_tries {
+            Some(spread) if !opts.udp => scanner.with_spread_tries(spread),
+            _ => scanner,
+        };
+        let scanner = match opts.throttle_window {
+            Some(window) if !opts.udp => scanner.with_pacing(Pacing {
+                window,
+                cooldown: opts.throttle_cooldown,
+                max_slowdown: opts.throttle_max_slowdown,
+            }),
+            _ => scanner,
+        };
+        let scanner = match opts.heartbeat {
+            Some(interval) if !opts.udp => scanner.with_heartbeat(Heartbeat {
+                interval,
+                misses: opts.heartbeat_misses,
+                on_down: opts.host_down,
+                resume_window: opts.resume_window,
+            }),
+            _ => scanner,
+        };
+        let scanner = if opts.pause_on_network_down || opts.connectivity_check.is_some() {
+            scanner.with_connectivity(Connectivity {
+                window: NETWORK_DOWN_WINDOW,
+                threshold: NETWORK_DOWN_THRESHOLD,
+                interval: opts.connectivity_interval,
+                reference: opts.connectivity_check,
+            })
+        } else {
+            scanner
+        };
+        let scanner = if opts.conntrack_backoff && !opts.udp {
+            scanner.with_conntrack(Conntrack {
+                window: CONNTRACK_WINDOW,
+                threshold: CONNTRACK_THRESHOLD,
+                max_slowdown: CONNTRACK_MAX_SLOWDOWN,
+                table: Conntrack::local_table(),
+            })
+        } else {
+            scanner
+        };
+        let scanner = match &ssh_jump {
+            Some(session) => scanner.with_ssh_jump(Arc::clone(session)),
+            None => scanner,
+        };
+        let scanner = match &proxy {
+            Some(route) => scanner.with_proxy(route.clone()),
+            None => scanner,
+        };
+        let scanner = match &priorities {
+            Some(priorities) => scanner.with_priorities(priorities.clone()),
+            None => scanner,
+        };
+        let scanner = if opts.verify_open && !opts.udp {
+            scanner.with_verified_open()
+        } else {
+            scanner
+        };
+        // The open ports are probed on the connection which found them.
+        let scanner = if opts.probe_all && !opts.udp {
+            scanner.with_probes(service_prober(&opts))
+        } else {
+            scanner
+        };
+        if opts.randomize_source_ports {
+            scanner.with_source_ports(SourcePorts::new(opts.seed))
+        } else {
+            scanner
+        }
+    };
+    let scanner = bind_interfaces(
+        build_scanner(&ips, targets.port_overrides()),
+        &interface_selections,
+    );
+    let cache = opts
+        .cache
+        .as_deref()
+        .map(|dir| ScanCache::new(dir, opts.cache_max_age));
+    let (cache_keys, cached) = read_cache(&opts, cache.as_ref(), &scanner, &ips);
+    let scanner = scanner.without_hosts(&cached.keys().copied().collect::<Vec<IpAddr>>());
+    debug!("Scanner finished building: {:?}", scanner);
+    verbose!(
+        format!(
+            "Scanning {} host(s) with a batch size of {batch_size}",
+            ips.len()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+
+    if let Err(e) = scanner.check_socket_options() {
+        warning!(
+            ErrorCode::UnsupportedSocketOption,
+            format!("Unsupported socket option, aborting scan.\n{e}"),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::UnsupportedSocketOption.exit_code());
+    }
+
+    if opts.throttle_window.is_some() && opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "UDP probes time out on every closed port, skipping --throttle-window.",
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    if opts.heartbeat.is_some() && opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "Heartbeats need an open TCP port, skipping --heartbeat.",
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    if (opts.pause_on_network_down || opts.connectivity_check.is_some()) && opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "UDP probes don't tell unreachable networks apart, skipping --pause-on-network-down.",
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    if opts.conntrack_backoff && opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "UDP probes don't tell silence apart from closed ports, skipping --conntrack-backoff.",
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    // The pre phase runs before any probe, a host it fails for is dropped
+    // when the script says so.
+    let (pre_runs, pre_skipped) = if pre_scripts.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        let hosts: Vec<(IpAddr, String)> = targets
+            .hosts
+            .iter()
+            .filter(|target| !cached.contains_key(&target.ip))
+            .filter(|target| {
+                !interface_selections
+                    .get(&target.ip)
+                    .is_some_and(Result::is_err)
+            })
+            .map(|target| (target.ip, naming.name(target.ip, &target.hostnames)))
+            .collect();
+        run_pre_scripts(
+            &opts,
+            &pre_scripts,
+            &scanner,
+            &hosts,
+            &run_id,
+            tracer.as_ref(),
+        )
+    };
+    let scanner = scanner.without_hosts(&pre_skipped);
+    // The hosts found up first are scanned while the others are discovered,
+    // the ones found later in the waves after it.
+    let mut waves = opts.discovery.then(|| {
+        let skipped: HashSet<IpAddr> = cached
+            .keys()
+            .chain(&pre_skipped)
+            .chain(
+                interface_selections
+                    .iter()
+                    .filter(|(_, selected)| selected.is_err())
+                    .map(|(ip, _)| ip),
+            )
+            .copied()
+            .collect();
+        let hosts = ips.iter().filter(|ip| !skipped.contains(ip)).copied();
+        start_discovery(&opts, hosts.collect(), batch_size)
+    });
+    let scanner = match waves.as_mut() {
+        Some(waves) => scanner.with_hosts(&waves.next_wave().unwrap_or_default()),
+        None => scanner,
+    };
+
+    let dashboard = updates.map(|updates| {
+        tui::set_verbosity(Verbosity::Quiet);
+        Dashboard::start(updates, control.clone())
+    });
+    let guard = window_updates.map(|updates| {
+        let origin = Origin::new(&run_id, &fingerprint);
+        start_guard(&opts, &scanner, &control, origin, updates)
+    });
+
+    let mut portscan_bench = NamedTimer::start("Portscan");
+    let mut sockets = scanner.sockets();
+    let mut outcome = block_on(scanner.scan());
+    if let Some(waves) = waves.as_mut() {
+        while let Some(wave) = waves.next_wave() {
+            let wave_scanner = bind_interfaces(
+                build_scanner(&wave, targets.port_overrides()),
+                &interface_selections,
+            );
+            sockets += wave_scanner.sockets();
+            outcome.extend(block_on(wave_scanner.scan()));
+        }
+    }
+    let ScanOutcome {
+        open: mut scan_result,
+        outages,
+        downgrades,
+        throttlings,
+        prohibitions,
+        network_outages,
+        conntrack_backoffs,
+        duplicates,
+        pending_retries,
+        forecast,
+        probes,
+        tries,
+        filtered,
+        fallbacks,
+        ..
+    } = outcome;
+    let unfinished = Some(forecast).filter(|forecast| forecast.remaining > 0);
+    scan_result.extend(cached.values().flat_map(|entry| {
+        entry
+            .open
+            .iter()
+            .map(move |port| SocketAddr::new(entry.ip, *port))
+    }));
+
+    // The dual-stack hosts whose first batch got no response were scanned
+    // on their other family.
+    for fallback in &fallbacks {
+        let Some(selection) = family_selections
+            .iter_mut()
+            .find(|selection| selection.chosen() == Some(fallback.from))
+        else {
+            continue;
+        };
+        selection.first_batch(0);
+        let hostname = selection.hostname().to_owned();
+        targets.insert(fallback.to, &hostname, Some(&hostname));
+        sockets += fallback.sockets as u64;
+        ips.push(fallback.to);
+    }
+    if let Some(guard) = guard {
+        match guard.finish() {
+            Ok(0) => {}
+            Ok(left) => warning!(
+                ErrorCode::OutsideWindow,
+                format!(
+                    "{left} socket(s) were left outside of --allowed-window, resume the scan with -a {}",
+                    opts.checkpoint.display()
+                ),
+                opts.greppable,
+                opts.accessible
+            ),
+            Err(e) => warning!(
+                ErrorCode::CheckpointWriteFailed,
+                format!("The checkpoint {:?} could not be written: {e}", opts.checkpoint),
+                opts.greppable,
+                opts.accessible
+            ),
+        }
+    }
+    if let Some(dashboard) = dashboard {
+        let finished = dashboard.finish();
+        tui::set_verbosity(opts.verbosity());
+        if let Err(e) = finished {
+            warning!(
+                format!("The dashboard failed: {e}"),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    portscan_bench.end();
+    benchmarks.push(portscan_bench);
+    // A critical sink which failed stopped the scan, the run ends with it.
+    if sinks.failed_critically() {
+        report_sink_failures(&opts, &sinks);
+    }
+
+    let mut report = ScanReport::new(&targets.hosts, &scan_result, opts.sort_hosts);
+    report.add_confidence(&tries);
+    report.add_filtered(&filtered);
+    report.schema_version = opts.schema_version;
+    report.run_id = Some(run_id.clone());
+    report.unresolved = std::mem::take(&mut targets.unresolved);
+    report.forecast = unfinished;
+    report.network_outages = network_outages;
+    report.conntrack_backoffs = conntrack_backoffs;
+    for host in &mut report.hosts {
+        if pre_skipped.contains(&host.ip) {
+            host.scanned = false;
+            host.skipped_reason = Some(SkipReason::PreScanFailed);
+        }
+    }
+    if let Some(waves) = &waves {
+        report_discovery(&opts, waves, &mut report);
+    }
+    for host in &mut report.hosts {
+        if let Some(selected) = interface_selections.get(&host.ip) {
+            host.interface = Some(HostInterface::new(selected));
+            if selected.is_err() {
+                host.scanned = false;
+                host.skipped_reason = Some(SkipReason::InterfaceUnusable);
+            }
+        }
+    }
+    for (ip, run) in pre_runs {
+        if let Some(host) = report.hosts.iter_mut().find(|host| host.ip == ip) {
+            host.scripts.push(run);
+        }
+    }
+    if duplicates > 0 {
+        detail!(
+            format!("{duplicates} sockets were given more than once, each was probed once."),
+            opts.greppable,
+            opts.accessible
+        );
+        report.duplicate_sockets = Some(duplicates);
+    }
+    if pending_retries > 0 {
+        detail!(
+            format!(
+                "{pending_retries} sockets still had spread tries to go, they count as closed."
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    report.fingerprint = Some(fingerprint.clone());
+    report.default_ports = default_ports.map(|set| PortDefaults {
+        set,
+        ports: plan.fingerprint.config.ports.clone(),
+    });
+    let open_ports: u64 = report
+        .hosts
+        .iter()
+        .map(|host| host.ports.len() as u64)
+        .sum();
+    if let Some(shard) = opts.shard {
+        report.stats = Some(ScanStats {
+            shards: vec![shard],
+            sockets,
+            open_ports,
+            resources: None,
+        });
+    }
+    for host in &mut report.hosts {
+        host.family = family_selections
+            .iter()
+            .filter_map(FamilySelection::decision)
+            .find(|decision| decision.address == host.ip);
+    }
+    for outage in outages {
+        warning!(
+            ErrorCode::HostDown,
+            format!(
+                "Host {} went down at {}, {} ports unprobed{}.",
+                outage.ip,
+                outage.went_down_at,
+                outage.unprobed_ports,
+                if outage.came_back {
+                    ", it came back"
+                } else {
+                    ""
+                }
+            ),
+            opts.greppable,
+            opts.accessible,
+            host = outage.ip
+        );
+        if let Some(host) = report.hosts.iter_mut().find(|host| host.ip == outage.ip) {
+            host.outages.push(outage);
+        }
+    }
+    for throttling in throttlings {
+        if let Some(host) = report
+            .hosts
+            .iter_mut()
+            .find(|host| host.ip == throttling.ip)
+        {
+            host.throttling = Some(throttling);
+        }
+    }
+    for prohibition in prohibitions {
+        if let Some(host) = report
+            .hosts
+            .iter_mut()
+            .find(|host| host.ip == prohibition.ip)
+        {
+            host.admin_prohibited = Some(prohibition);
+        }
+    }
+    if let Some(previous) = &previous {
+        for host in &mut report.hosts {
+            host.closed_since = previous.closed_since(host.ip, &host.ports);
+        }
+    }
+    if let (Some(previous), Some(path)) = (&rescan, &opts.rescan_filtered) {
+        for host in &mut report.hosts {
+            rescan::combine(previous, host, &run_id);
+        }
+        let unscanned = rescan::unscanned(previous, &path.display().to_string(), &report.hosts);
+        report.hosts.extend(unscanned);
+    }
+    if let Some(timeout_map) = &opts.timeout_map {
+        for host in &mut report.hosts {
+            host.timeout = timeout_map
+                .resolve(host.ip)
+                .map(|group| HostTimeout::new(group, opts.tries));
+        }
+    }
+    for downgrade in downgrades {
+        if let Some(host) = report.hosts.iter_mut().find(|host| host.ip == downgrade.ip) {
+            host.tries_downgrade = Some(downgrade);
+        }
+    }
+    // Only one family of a dual-stack hostname gets scanned, the other
+    // address is reported as skipped.
+    for selection in &family_selections {
+        if let Some(ip) = selection.skipped().filter(|ip| !ips.contains(ip)) {
+            let target = Target {
+                ip,
+                hostnames: vec![selection.hostname().to_owned()],
+                sources: vec![selection.hostname().to_owned()],
+                ports: None,
+            };
+            report
+                .hosts
+                .push(HostReport::skipped(&target, SkipReason::OtherFamily));
+        }
+    }
+
+    for host in &mut report.hosts {
+        host.name = Some(naming.name(host.ip, &host.hostnames));
+    }
+    if let Some(notes) = &notes {
+        attach_notes(&opts, notes, &mut report);
+    }
+
+    for host in &report.hosts {
+        if !host.closed_since.is_empty() {
+            let ports: Vec<String> = host.closed_since.iter().map(ToString::to_string).collect();
+            detail!(
+                format!(
+                    "Closed since the previous scan {} -> [{}]",
+                    host.ip,
+                    ports.join(",")
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+            // The previously open ports aren't missing because of the batch size.
+            continue;
+        }
+        if !host.scanned || !host.ports.is_empty() {
+            continue;
+        }
+        let ip = &host.ip;
+
+        // If we got here it means the scan couldn't find any open ports for the IP.
+
+        let x = format!("Looks like I didn't find any open ports for {:?}. This is usually caused by a high batch size.
+        \n*I used {} batch size, consider lowering it with {} or a comfortable number for your system.
+        \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout.\n",
+        ip,
+        opts.batch_size,
+        "'rustscan -b <batch_size> -a <ip address>'");
+        warning!(
+            ErrorCode::NoOpenPorts,
+            x,
+            opts.greppable,
+            opts.accessible,
+            host = ip
+        );
+    }
+
+    if opts.probe_all {
+        if opts.udp {
+            warning!(
+                ErrorCode::IncompatibleOptions,
+                "Only TCP ports can be probed, skipping --probe-all.",
+                opts.greppable,
+                opts.accessible
+            );
+        } else {
+            probe_services(&opts, &mut report, probes);
+        }
+    }
+    if let Some(rules) = version_rules {
+        if opts.udp {
+            warning!(
+                ErrorCode::IncompatibleOptions,
+                "Only TCP ports can be probed, skipping --probe-versions.",
+                opts.greppable,
+                opts.accessible
+            );
+        } else {
+            probe_versions(&opts, rules, &mut report);
+        }
+    }
+    if opts.snmp_probe {
+        if opts.udp {
+            probe_snmp(&opts, &mut report);
+        } else {
+            warning!(
+                ErrorCode::IncompatibleOptions,
+                "SNMP agents are only found by UDP scans, skipping --snmp-probe.",
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    if !opts.port_hook.is_empty() {
+        for hook in &opts.port_hook {
+            if !hook.hints.is_empty() && !opts.probe_all {
+                warning!(
+                    ErrorCode::IncompatibleOptions,
+                    format!(
+                        "Only --probe-all hints the ports, the port hook {} only runs on its ports.",
+                        hook.name
+                    ),
+                    opts.greppable,
+                    opts.accessible
+                );
+            }
+        }
+        run_port_hooks(&opts, &run_id, &mut report);
+    }
+
+    let mut script_bench = NamedTimer::start("Scripts");
+    let script_env = ScriptEnv::new(&opts, &scripts_to_run, &run_id, tracer.as_ref());
+    let mut pipelined = pipeline
+        .map(|pipeline| finish_pipeline(&opts, pipeline))
+        .unwrap_or_default();
+    for host in &mut report.hosts {
+        let (ip, ports) = (host.ip, host.ports.clone());
+
+        // The hosts of a JSON report are told once nmap added its services.
+        if opts.format == OutputFormat::Json {
+            continue;
+        }
+        if !host.scanned || ports.is_empty() {
+            sinks.host(host);
+            continue;
+        }
+
+        if opts.show_source {
+            detail!(
+                format!("{ip} comes from {}", host.source.join(", ")),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+
+        print_notes(&opts, host);
+
+        for probe in &host.probes {
+            detail!(
+                format!("{ip}:{} looks like {}", probe.port, probe.service_guess),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        if let Some(snmp) = &host.snmp {
+            detail!(
+                format!(
+                    "{ip}:{SNMP_PORT} answers SNMPv{} with the community {:?}: {}",
+                    snmp.version,
+                    snmp.community,
+                    snmp.sys_descr.as_deref().unwrap_or("no sysDescr")
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        for service in &host.services {
+            let found = [&service.service, &service.product, &service.version]
+                .iter()
+                .filter_map(|field| field.as_deref())
+                .collect::<Vec<&str>>()
+                .join(" ");
+            detail!(
+                format!("{ip}:{} runs {found}", service.port),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        for run in &host.port_hooks {
+            print_port_hook_run(&opts, ip, run);
+        }
+
+        // if option scripts is none, no script will be spawned
+        if opts.greppable || opts.quiet || opts.scripts == ScriptsRequired::None {
+            sinks.host(host);
+            continue;
+        }
+        detail!("Starting Script(s)", opts.greppable, opts.accessible);
+
+        let cached_runs = cached
+            .get(&ip)
+            .and_then(|entry| entry.scripts.clone())
+            .filter(|_| opts.cache_scripts);
+        if let Some(runs) = cached_runs {
+            for run in &runs {
+                print_script_run(&opts, ip, run);
+            }
+            host.scripts = runs;
+            sinks.host(host);
+            continue;
+        }
+
+        let input = ScriptInput {
+            ports,
+            hints: host
+                .probes
+                .iter()
+                .filter_map(|probe| Some((probe.port, probe.service_guess.protocol_hint?)))
+                .collect(),
+            name: host.name.clone(),
+        };
+        // The scripts of a host whose ports changed since it was pipelined
+        // run again on the ones it ended up with.
+        match pipelined
+            .remove(&ip)
+            .filter(|scripts| scripts.input == input)
+        {
+            Some(scripts) => {
+                for note in scripts.notes {
+                    tell_script_note(&opts, ip, note, &mut host.scripts);
+                }
+            }
+            None => run_host_scripts(&script_env, ip, &input, &mut |note| {
+                tell_script_note(&opts, ip, note, &mut host.scripts);
+            }),
+        }
+        sinks.host(host);
+    }
+
+    if opts.group_by_fingerprint {
+        report.groups = group_hosts(&report.hosts);
+        if opts.format != OutputFormat::Json && !opts.no_results {
+            for group in &report.groups {
+                output!(group.summary(), opts.greppable, opts.accessible);
+            }
+        }
+    }
+    print_unresolved(&opts, &report.unresolved);
+    verbose!(
+        format!("Configuration fingerprint: {}", fingerprint.hash),
+        opts.greppable,
+        opts.accessible
+    );
+    if let Some(sampler) = sampler {
+        let usage = sampler.finish();
+        detail!(usage.to_string(), opts.greppable, opts.accessible);
+        let stats = report.stats.get_or_insert_with(|| ScanStats {
+            shards: Vec::new(),
+            sockets,
+            open_ports,
+            resources: None,
+        });
+        stats.resources = Some(usage);
+    }
+
+    if opts.format == OutputFormat::Json && !opts.no_results {
+        let default_script = scripts_to_run.iter().find(|script| script.path.is_none());
+        if let (Some(_), Some(script)) = (&opts.nmap_args, default_script) {
+            let retries = script.retry_policy(&opts.script_retry_policy());
+            add_nmap_services(&opts, &retries, &mut report, tracer.as_ref());
+        }
+    }
+    if opts.format == OutputFormat::Json {
+        for host in &report.hosts {
+            sinks.host(host);
+        }
+    }
+    if let Some(key) = &signing_key {
+        let signature = signing::sign(
+            key,
+            &report,
+            &fingerprint.hash,
+            started_at,
+            SystemTime::now(),
+        );
+        report.signature = Some(signature);
+    }
+    sinks.finished(&ScanEnd::of(&report));
+    report_sink_failures(&opts, &sinks);
+    for export in &opts.export {
+        write_export(&opts, export, &report);
+    }
+
+    if let (Some(cache), false) = (&cache, opts.no_cache_write) {
+        write_cache(&opts, cache, &cache_keys, &cached, &report);
+    }
+    // Serving the report doesn't scan anything.
+    drop(scan_lock);
+
+    // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
+    script_bench.end();
+    benchmarks.push(script_bench);
+    rustscan_bench.end();
+    benchmarks.push(rustscan_bench);
+    debug!("Benchmarks raw {:?}", benchmarks);
+    info!("{}", benchmarks.summary());
+
+    if let Some(writer) = trace_writer {
+        finish_trace(&opts, writer);
+    }
+    if let Some(server) = &report_server {
+        serve_report(&opts, server, &report);
+    }
+
+    if opts.strict_resolution && !report.unresolved.is_empty() {
+        std::process::exit(ErrorCode::UnresolvedHost.exit_code());
+    }
+}
+
+/// Lists the targets which were left out of the scan because they didn't
+/// resolve, after the results.
+fn print_unresolved(opts: &Opts, unresolved: &[Unresolved]) {
+    if unresolved.is_empty() {
+        return;
+    }
+    let lines: Vec<String> = unresolved
+        .iter()
+        .map(|host| match &host.file {
+            Some(file) => format!("{} (in {file}): {}", host.name, host.error),
+            None => format!("{}: {}", host.name, host.error),
+        })
+        .collect();
+    detail!(
+        format!(
+            "{} target(s) could not be resolved and were not scanned:\n{}",
+            unresolved.len(),
+            lines.join("\n")
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+}
+
+/// Prints the reports of `args` merged into one JSON document.
+fn merge_reports(opts: &Opts, args: &MergeArgs) {
+    // The merged document is the only thing on stdout, warnings go to stderr.
+    tui::set_verbosity(Verbosity::Quiet);
+
+    let mut reports = Vec::new();
+    for path in &args.files {
+        match merge::read(path) {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                warning!(
+                    ErrorCode::InvalidReport,
+                    format!("Can't read the report {}: {e}", path.display()),
+                    opts.greppable,
+                    opts.accessible,
+                    host = path.display()
+                );
+                std::process::exit(ErrorCode::InvalidReport.exit_code());
+            }
+        }
+    }
+
+    let merged = match merge::merge(reports, opts.sort_hosts) {
+        Ok(merged) => merged,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidReport,
+                format!("Can't merge the reports: {e}"),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::InvalidReport.exit_code());
+        }
+    };
+    for shard in &merged.missing {
+        warning!(
+            ErrorCode::IncompleteShards,
+            format!("Shard {shard} is missing, the merged report doesn't cover the whole scan."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    for shard in &merged.duplicates {
+        warning!(
+            ErrorCode::IncompleteShards,
+            format!("Shard {shard} was given more than once, its stats are counted twice."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&merged.report).expect("JSON values always serialize.")
+    );
+}
+
+/// Writes the key pair of `rustscan keygen`, exiting when it can't.
+fn generate_keys(opts: &Opts, args: &KeygenArgs) {
+    match signing::write_pair(&args.path) {
+        Ok(public) => println!(
+            "Wrote the private key to {} and the public key to {}",
+            args.path.display(),
+            public.display()
+        ),
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidKey,
+                format!("Could not generate the keys, {e}."),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::InvalidKey.exit_code());
+        }
+    }
+}
+
+/// Checks the signature of the report of `rustscan verify`, exiting with
+/// an error when it doesn't match.
+fn verify_report(opts: &Opts, args: &VerifyArgs) {
+    let key = PublicKey::read(&args.pubkey).unwrap_or_else(|e| {
+        warning!(
+            ErrorCode::InvalidKey,
+            format!("Can't load the public key {}, {e}.", args.pubkey.display()),
+            opts.greppable,
+            opts.accessible,
+            host = args.pubkey.display()
+        );
+        std::process::exit(ErrorCode::InvalidKey.exit_code());
+    });
+    let report = std::fs::read_to_string(&args.report).unwrap_or_else(|e| {
+        warning!(
+            ErrorCode::InvalidReport,
+            format!("Can't read the report {}: {e}", args.report.display()),
+            opts.greppable,
+            opts.accessible,
+            host = args.report.display()
+        );
+        std::process::exit(ErrorCode::InvalidReport.exit_code());
+    });
+    match signing::verify(&report, &key) {
+        Ok(signature) => println!(
+            "The report {} is signed by {}, for the configuration {}, scanned from {} to {}.",
+            args.report.display(),
+            signature.public_key,
+            signature.config_fingerprint,
+            signature.started_at,
+            signature.signed_at
+        ),
+        Err(e) => {
+            warning!(
+                ErrorCode::SignatureMismatch,
+                format!("The report {} doesn't verify, {e}.", args.report.display()),
+                opts.greppable,
+                opts.accessible,
+                host = args.report.display()
+            );
+            std::process::exit(ErrorCode::SignatureMismatch.exit_code());
+        }
+    }
+}
+
+/// The private key of `--sign-key`, exiting when it can't be read or there
+/// is no JSON report to sign.
+fn load_signing_key(opts: &Opts, path: &Path) -> SigningKey {
+    if opts.format != OutputFormat::Json && opts.output_file.is_none() {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "--sign-key signs the JSON report, it needs --format json or --output-file.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::IncompatibleOptions.exit_code());
+    }
+    SigningKey::read(path).unwrap_or_else(|e| {
+        warning!(
+            ErrorCode::InvalidKey,
+            format!("Can't load the signing key {}, {e}.", path.display()),
+            opts.greppable,
+            opts.accessible,
+            host = path.display()
+        );
+        std::process::exit(ErrorCode::InvalidKey.exit_code());
+    })
+}
+
+/// Races both address families of every dual-stack host and logs the
+/// family each of them will be scanned on.
+fn race_families(
+    opts: &Opts,
+    plan: &ScanPlan,
+    hosts: Vec<family::DualStackHost>,
+    batch_size: u16,
+) -> Vec<FamilySelection> {
+    if hosts.is_empty() {
+        return Vec::new();
+    }
+
+    let Some(port) = family::probe_port(&plan.ports()) else {
+        return Vec::new();
+    };
+
+    let selections = block_on(family::race(
+        hosts,
+        port,
+        Duration::from_millis(opts.timeout.into()),
+        batch_size,
+        opts.greppable,
+        opts.accessible,
+    ));
+
+    for decision in selections.iter().filter_map(FamilySelection::decision) {
+        info!("Family decision {:?}", decision);
+        detail!(
+            format!(
+                "{} is dual-stack, scanning {} ({:?})",
+                decision.hostname, decision.address, decision.reason
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    selections
+}
+
+/// The probe pipeline of `--probe-all`.
+fn service_prober(opts: &Opts) -> Prober {
+    Prober::new(Duration::from_millis(opts.probe_timeout.into()))
+}
+
+/// Runs the probe pipeline against every open port the scan didn't probe
+/// already, the cached ones or the ones behind a bastion or a proxy, and
+/// folds the service guesses into the report.
+fn probe_services(
+    opts: &Opts,
+    report: &mut ScanReport,
+    mut guesses: HashMap<SocketAddr, ServiceGuess>,
+) {
+    let sockets: Vec<SocketAddr> = report
+        .hosts
+        .iter()
+        .flat_map(|host| {
+            host.ports
+                .iter()
+                .map(move |port| SocketAddr::new(host.ip, *port))
+        })
+        .filter(|socket| !guesses.contains_key(socket))
+        .collect();
+    guesses.extend(block_on(
+        service_prober(opts).probe_all(&sockets, opts.probe_concurrency.into()),
+    ));
+
+    for host in &mut report.hosts {
+        let ip = host.ip;
+        host.probes = host
+            .ports
+            .iter()
+            .filter_map(|&port| {
+                let service_guess = guesses.remove(&SocketAddr::new(ip, port))?;
+                Some(PortProbe {
+                    port,
+                    service_guess,
+                })
+            })
+            .collect();
+    }
+}
+
+/// Runs the version rules against every open port and folds what they found
+/// into the services of the report.
+fn probe_versions(opts: &Opts, rules: Vec<VersionRule>, report: &mut ScanReport) {
+    let sockets: Vec<SocketAddr> = report
+        .hosts
+        .iter()
+        .flat_map(|host| {
+            host.ports
+                .iter()
+                .map(move |port| SocketAddr::new(host.ip, *port))
+        })
+        .collect();
+    let prober = VersionProber::new(rules).with_max_probes(opts.max_rule_probes.into());
+    let mut services = block_on(prober.probe_all(&sockets, opts.probe_concurrency.into()));
+
+    for host in &mut report.hosts {
+        let ip = host.ip;
+        host.services = host
+            .ports
+            .iter()
+            .filter_map(|&port| services.remove(&SocketAddr::new(ip, port)))
+            .collect();
+    }
+}
+
+/// Checks the communities of the SNMP agents of the hosts with 161 open and
+/// folds what answered into the report.
+fn probe_snmp(opts: &Opts, report: &mut ScanReport) {
+    let sockets: Vec<SocketAddr> = report
+        .hosts
+        .iter()
+        .filter(|host| host.ports.contains(&SNMP_PORT))
+        .map(|host| SocketAddr::new(host.ip, SNMP_PORT))
+        .collect();
+    if sockets.is_empty() {
+        return;
+    }
+    let prober = SnmpProber::new(Duration::from_millis(opts.probe_timeout.into()))
+        .with_version(opts.snmp_version)
+        .with_communities(&opts.snmp_community);
+    let mut findings = block_on(prober.probe_all(&sockets, opts.probe_concurrency.into()));
+
+    for host in &mut report.hosts {
+        host.snmp = findings.remove(&SocketAddr::new(host.ip, SNMP_PORT));
+    }
+}
+
+/// Runs the port hooks on the open ports of every host and folds their runs
+/// into the report.
+fn run_port_hooks(opts: &Opts, run_id: &str, report: &mut ScanReport) {
+    let targets: Vec<HookTarget> = report
+        .hosts
+        .iter()
+        .flat_map(|host| {
+            host.ports.iter().map(move |&port| HookTarget {
+                ip: host.ip,
+                port,
+                hint: host
+                    .probes
+                    .iter()
+                    .find(|probe| probe.port == port)
+                    .and_then(|probe| probe.service_guess.protocol_hint),
+            })
+        })
+        .collect();
+    let runs = port_hooks::run_hooks(
+        &opts.port_hook,
+        &targets,
+        opts.port_hook_timeout,
+        opts.port_hook_concurrency.into(),
+        Some(run_id),
+    );
+
+    for (ip, run) in runs {
+        if let Some(host) = report.hosts.iter_mut().find(|host| host.ip == ip) {
+            host.port_hooks.push(run);
+        }
+    }
+}
+
+/// Starts the guard of --allowed-window over the scan of `scanner`, telling
+/// when the windows pause, resume or stop it.
+fn start_guard(
+    opts: &Opts,
+    scanner: &Scanner,
+    control: &ScanControl,
+    origin: Origin,
+    updates: Receiver<ScanUpdate>,
+) -> Guard {
+    let checkpoint = Checkpoint {
+        path: opts.checkpoint.clone(),
+        origin,
+        planned: scanner
+            .hosts()
+            .into_iter()
+            .map(|ip| (ip, scanner.host_ports(ip)))
+            .collect(),
+    };
+    let (greppable, accessible) = (opts.greppable, opts.accessible);
+    let path = opts.checkpoint.clone();
+    let on_event = move |event: Event| {
+        let (verb, reopens, left) = match event {
+            Event::Resumed => {
+                detail!(
+                    "Inside of --allowed-window again, resuming the scan",
+                    greppable,
+                    accessible
+                );
+                return;
+            }
+            Event::Paused { reopens, left } => ("pausing the scan until", reopens, left),
+            Event::Stopped { reopens, left } => {
+                ("stopping the scan, the next window opens", reopens, left)
+            }
+        };
+        let reopens = window::reopening(reopens, SystemTime::now());
+        match left {
+            Ok(left) => warning!(
+                ErrorCode::OutsideWindow,
+                format!(
+                    "Outside of --allowed-window, {verb} {reopens}. The {left} socket(s) left are in {path:?}."
+                ),
+                greppable,
+                accessible
+            ),
+            Err(e) => warning!(
+                ErrorCode::CheckpointWriteFailed,
+                format!("Outside of --allowed-window, {verb} {reopens}. The checkpoint {path:?} could not be written: {e}"),
+                greppable,
+                accessible
+            ),
+        }
+    };
+    Guard::start(
+        Warden::new(opts.allowed_window.clone(), opts.no_wait),
+        SystemClock,
+        control.clone(),
+        checkpoint,
+        updates,
+        window::POLL,
+        on_event,
+    )
+}
+
+/// Runs the scripts of the pre phase against every host of `hosts` in order,
+/// `{{port}}` being the count of ports planned for the host. Gives the runs
+/// and the hosts dropped from the scan by a failed `on_fail = "skip"` script,
+/// which stops the pre phase of the host.
+fn run_pre_scripts(
+    opts: &Opts,
+    scripts: &[ScriptFile],
+    scanner: &Scanner,
+    hosts: &[(IpAddr, String)],
+    run_id: &str,
+    tracer: Option<&Tracer>,
+) -> (Vec<(IpAddr, ScriptRun)>, Vec<IpAddr>) {
+    let default_retries = opts.script_retry_policy();
+    let mut runs = Vec::new();
+    let mut skipped = Vec::new();
+    for (ip, hostname) in hosts {
+        let ip = *ip;
+        let planned = scanner.host_ports(ip).len();
+        for script_f in scripts {
+            let retries = script_f.retry_policy(&default_retries);
+            let script = Script::build(
+                script_f.path.clone(),
+                ip,
+                Vec::new(),
+                script_f.port.clone(),
+                script_f.ports_separator.clone(),
+                script_f.tags.clone(),
+                script_f.call_format.clone(),
+            )
+            .with_run_id(Some(run_id.to_owned()))
+            .with_hostname(Some(hostname.clone()))
+            .with_planned_ports(planned);
+            let mut run = traced_script(tracer, ip, script_f.name(), &retries, || {
+                script.clone().run()
+            });
+            run.phase = Phase::Pre;
+            // The output is shown like the one of the post phase.
+            if !opts.greppable && !opts.quiet {
+                print_script_run(opts, ip, &run);
+            }
+            let failed = !run.succeeded();
+            runs.push((ip, run));
+            if !failed {
+                continue;
+            }
+
+            let skip = script_f.on_fail == OnFail::Skip;
+            warning!(
+                ErrorCode::ScriptFailed,
+                format!(
+                    "Pre-scan script {} failed on {ip}, {}.",
+                    script_f.name(),
+                    if skip {
+                        "skipping the host"
+                    } else {
+                        "scanning anyway"
+                    }
+                ),
+                opts.greppable,
+                opts.accessible,
+                host = ip
+            );
+            if skip {
+                skipped.push(ip);
+                break;
+            }
+        }
+    }
+    (runs, skipped)
+}
+
+/// What the scripts of a host run with. The hosts of `--pipeline-scripts`
+/// keep their results only when they ended up with the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScriptInput {
+    ports: Vec<u16>,
+    hints: Vec<(u16, ProtocolHint)>,
+    name: Option<String>,
+}
+
+/// What the scripts of every host run with.
+struct ScriptEnv {
+    opts: Opts,
+    scripts: Vec<ScriptFile>,
+    retries: RetryPolicy,
+    run_id: String,
+    tracer: Option<Tracer>,
+}
+
+impl ScriptEnv {
+    fn new(opts: &Opts, scripts: &[ScriptFile], run_id: &str, tracer: Option<&Tracer>) -> Self {
+        Self {
+            opts: opts.clone(),
+            scripts: scripts.to_vec(),
+            retries: opts.script_retry_policy(),
+            run_id: run_id.to_owned(),
+            tracer: tracer.cloned(),
+        }
+    }
+}
+
+/// What running the scripts of a host tells as it goes.
+enum ScriptNote {
+    Verbose(String),
+    Detail(String),
+    Ran(ScriptRun),
+}
+
+/// The scripts which ran for a host of `--pipeline-scripts`, told once its
+/// turn in the report comes.
+struct PipelinedScripts {
+    input: ScriptInput,
+    notes: Vec<ScriptNote>,
+}
+
+/// Starts the workers of `--pipeline-scripts`, which run the scripts of
+/// the hosts of `names` as they are scanned.
+fn start_pipeline(
+    env: ScriptEnv,
+    names: HashMap<IpAddr, String>,
+    control: &ScanControl,
+    batch_size: u16,
+) -> Pipeline<PipelinedScripts> {
+    let workers = usize::from(env.opts.script_concurrency);
+    // The scan slows down once half of the workers are busy.
+    let pressure = Backpressure::new(control.clone(), batch_size.into(), workers.div_ceil(2));
+    Pipeline::start(workers, pipeline::QUEUE, pressure, move |ip, ports| {
+        let input = ScriptInput {
+            ports,
+            hints: Vec::new(),
+            name: names.get(&ip).cloned(),
+        };
+        let mut notes = Vec::new();
+        run_host_scripts(&env, ip, &input, &mut |note| notes.push(note));
+        PipelinedScripts { input, notes }
+    })
+}
+
+/// Waits for the scripts of the pipelined hosts.
+fn finish_pipeline(
+    opts: &Opts,
+    pipeline: Pipeline<PipelinedScripts>,
+) -> HashMap<IpAddr, PipelinedScripts> {
+    let (scripts, slowdowns) = pipeline.finish();
+    if slowdowns > 0 {
+        verbose!(
+            format!("The scan slowed down {slowdowns} time(s) while many hosts ran their scripts."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    scripts
+}
+
+/// Runs the scripts of `env` against `ip`, telling `note` how it goes.
+fn run_host_scripts(
+    env: &ScriptEnv,
+    ip: IpAddr,
+    input: &ScriptInput,
+    note: &mut dyn FnMut(ScriptNote),
+) {
+    let opts = &env.opts;
+    let (ports, hints) = (&input.ports, &input.hints);
+    // Run all the scripts we found and parsed based on the script config file tags field.
+    for mut script_f in env.scripts.clone() {
+        if let Some(reason) = script_f.unmet_condition(ports, hints) {
+            note(ScriptNote::Verbose(format!(
+                "Skipping script {} on ip {ip}, the host {reason}.",
+                script_f.name()
+            )));
+            continue;
+        }
+        let retries = script_f.retry_policy(&env.retries);
+
+        // The embedded nmap script runs without a shell when it gets
+        // user arguments, so they can't be reinterpreted.
+        if let (Some(nmap_args), None) = (&opts.nmap_args, &script_f.path) {
+            let mut user_args = nmap_args.0.clone();
+            user_args.extend(opts.command.iter().cloned());
+            let argvs = nmap::argvs(ip, ports, &user_args, None);
+            for argv in &argvs {
+                note(ScriptNote::Detail(format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", argv.join(" "), &ip)));
+            }
+            let run = traced_script(env.tracer.as_ref(), ip, script_f.name(), &retries, || {
+                let outputs = argvs
+                    .iter()
+                    .map(|argv| nmap::run(argv, Some(&env.run_id)))
+                    .collect::<anyhow::Result<Vec<String>>>()?;
+                Ok(outputs.concat())
+            });
+            note(ScriptNote::Ran(run));
+            continue;
+        }
+
+        // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
+        if !opts.command.is_empty() {
+            let user_extra_args = &opts.command.join(" ");
+            debug!("Extra args vec {:?}", user_extra_args);
+            if script_f.call_format.is_some() {
+                let mut call_f = script_f.call_format.unwrap();
+                call_f.push(' ');
+                call_f.push_str(user_extra_args);
+                note(ScriptNote::Detail(format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", call_f, &ip)));
+                debug!("Call format {}", call_f);
+                script_f.call_format = Some(call_f);
+            }
+        }
+
+        // Building the script with the arguments from the ScriptFile, and ip-ports.
+        let name = script_f.name();
+        let script = Script::build(
+            script_f.path,
+            ip,
+            ports.clone(),
+            script_f.port,
+            script_f.ports_separator,
+            script_f.tags,
+            script_f.call_format,
+        )
+        .with_hints(hints.clone())
+        .with_run_id(Some(env.run_id.clone()))
+        .with_hostname(input.name.clone());
+        let run = traced_script(env.tracer.as_ref(), ip, name, &retries, || {
+            script.clone().run()
+        });
+        note(ScriptNote::Ran(run));
+    }
+}
+
+fn tell_script_note(opts: &Opts, ip: IpAddr, note: ScriptNote, runs: &mut Vec<ScriptRun>) {
+    match note {
+        ScriptNote::Verbose(message) => verbose!(message, opts.greppable, opts.accessible),
+        ScriptNote::Detail(message) => detail!(message, opts.greppable, opts.accessible),
+        ScriptNote::Ran(run) => {
+            print_script_run(opts, ip, &run);
+            runs.push(run);
+        }
+    }
+}
+
+/// Runs `script` on `ip` like [`run_with_retries`], recording when it started
+/// and finished in the trace.
+fn traced_script(
+    tracer: Option<&Tracer>,
+    ip: IpAddr,
+    script: String,
+    policy: &RetryPolicy,
+    run: impl FnMut() -> anyhow::Result<String>,
+) -> ScriptRun {
+    let Some(tracer) = tracer else {
+        return run_with_retries(script, policy, run);
+    };
+    tracer.record(TraceEvent::ScriptStarted {
+        script: script.clone(),
+        ip,
+    });
+    let started = Instant::now();
+    let run = run_with_retries(script, policy, run);
+    tracer.record(TraceEvent::ScriptFinished {
+        script: run.script.clone(),
+        ip,
+        succeeded: run.succeeded(),
+        duration_us: trace::micros(started.elapsed()),
+    });
+    run
+}
+
+/// Runs nmap with the user arguments against every host with open ports and
+/// folds the services it found into the report.
+fn add_nmap_services(
+    opts: &Opts,
+    retries: &RetryPolicy,
+    report: &mut ScanReport,
+    tracer: Option<&Tracer>,
+) {
+    let mut user_args = opts.nmap_args.clone().unwrap_or_default().0;
+    user_args.extend(opts.command.iter().cloned());
+    let run_id = report.run_id.clone();
+    // Without a folder of its own, nmap still runs but tells no services.
+    let xml_dir = PrivateDir::create("rustscan-nmap")
+        .map_err(|e| debug!("No folder for the XML of nmap: {}", e))
+        .ok();
+
+    for host in report
+        .hosts
+        .iter_mut()
+        .filter(|host| !host.ports.is_empty())
+    {
+        let xml = xml_dir
+            .as_ref()
+            .map(|dir| nmap::xml_path(dir.path(), host.ip));
+        let argvs = nmap::argvs(host.ip, &host.ports, &user_args, xml.as_deref());
+        // Every run of a split port list overwrites the XML of the previous.
+        let mut services = Vec::new();
+        let run = traced_script(tracer, host.ip, "default".to_owned(), retries, || {
+            services.clear();
+            let mut output = String::new();
+            for argv in &argvs {
+                output.push_str(&nmap::run(argv, run_id.as_deref())?);
+                let Some(xml) = &xml else {
+                    continue;
+                };
+                match std::fs::read_to_string(xml) {
+                    Ok(xml) => services.extend(nmap::parse_services(&xml)),
+                    Err(e) => debug!("Nmap against {} wrote no XML: {}", host.ip, e),
+                }
+            }
+            Ok(output)
+        });
+        match &run.error {
+            // What the version rules found on ports nmap told nothing of stays.
+            None => {
+                host.services
+                    .retain(|ours| !services.iter().any(|service| service.port == ours.port));
+                host.services.extend(services);
+                host.services.sort_by_key(|service| service.port);
+            }
+            Some(e) => debug!("Nmap against {} failed: {}", host.ip, e),
+        }
+        host.scripts.push(run);
+        if let Some(xml) = &xml {
+            let _ = std::fs::remove_file(xml);
+        }
+    }
+}
+
+/// Reads the previous results of `--only-previously-open` and narrows the
+/// targets down to the ports they found open, aborting when they can't be read.
+fn read_previous(opts: &Opts, path: &Path, targets: &mut Targets) -> PreviousResults {
+    let previous = read_previous_results(opts, path);
+    let source = path.display().to_string();
+    for target in targets.only_previously_open(&previous, &source, !opts.targets_from_file_only) {
+        warning!(
+            ErrorCode::NotPreviouslyOpen,
+            format!("{target} had no open ports in {source}, skipping it."),
+            opts.greppable,
+            opts.accessible,
+            host = target
+        );
+    }
+    previous
+}
+
+/// Reads the previous results of `--rescan-filtered` and narrows the targets
+/// down to the ports they found filtered.
+fn read_rescan(opts: &Opts, path: &Path, targets: &mut Targets) -> PreviousResults {
+    let previous = read_previous_results(opts, path);
+    let source = path.display().to_string();
+    for target in targets.only_previously_filtered(&previous, &source, true) {
+        warning!(
+            ErrorCode::NotPreviouslyFiltered,
+            format!("{target} had no filtered ports in {source}, skipping it."),
+            opts.greppable,
+            opts.accessible,
+            host = target
+        );
+    }
+    previous
+}
+
+/// Reads the previous results of `--prioritize-from` into the ports every
+/// host had open, aborting when they can't be read.
+fn read_priorities(opts: &Opts, path: &Path) -> HashMap<IpAddr, Vec<u16>> {
+    let previous = read_previous_results(opts, path);
+    let priorities: HashMap<IpAddr, Vec<u16>> = previous
+        .ips()
+        .into_iter()
+        .filter_map(|ip| Some((ip, previous.open_ports(ip)?)))
+        .collect();
+    verbose!(
+        format!(
+            "Probing first the ports {} found open on {} hosts",
+            path.display(),
+            priorities.len()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    priorities
+}
+
+/// Reads the JSON report at `path`, aborting when it can't be read.
+fn read_previous_results(opts: &Opts, path: &Path) -> PreviousResults {
+    match PreviousResults::read(path) {
+        Ok(previous) => previous,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidPreviousResults,
+                format!("Can't read the previous results {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidPreviousResults.exit_code());
+        }
+    }
+}
+
+/// Aborts the run which has no target left, telling how many every stage of
+/// the filtering removed. The plan is printed too as JSON with `--format
+/// json`, its stages saying the same.
+fn report_no_targets(opts: &Opts, plan: &ScanPlan) -> ! {
+    if opts.format == OutputFormat::Json {
+        println!("{}", plan.to_json());
+    }
+    warning!(
+        ErrorCode::NoTargets,
+        format!("No targets left to scan, aborting scan: {}.", plan.funnel()),
+        opts.greppable,
+        opts.accessible
+    );
+    std::process::exit(ErrorCode::NoTargets.exit_code());
+}
+
+/// Aborts the run which has hosts without a port left to scan, with the
+/// table of where their ports came from.
+fn report_portless(opts: &Opts, plan: &ScanPlan) -> ! {
+    for (ips, resolution) in &plan.portless {
+        let mut hosts: Vec<String> = ips.iter().take(5).map(ToString::to_string).collect();
+        if ips.len() > hosts.len() {
+            hosts.push(format!("{} more", ips.len() - hosts.len()));
+        }
+        let why = match resolution.emptied_by() {
+            Some(source) => format!("{source} excluded the last of them"),
+            None => "no source gives any".to_owned(),
+        };
+        warning!(
+            ErrorCode::NoPortsLeft,
+            format!(
+                "No port left to scan on {}, {why}, aborting scan:\n{}",
+                hosts.join(", "),
+                resolution.table()
+            ),
+            opts.greppable,
+            opts.accessible,
+            host = ips[0]
+        );
+    }
+    std::process::exit(ErrorCode::NoPortsLeft.exit_code());
+}
+
+/// Prints where the ports of `host`, an address, a hostname or a token of
+/// the targets, come from, for `--explain-ports`.
+fn explain_ports(opts: &Opts, plan: &ScanPlan, targets: &Targets, host: &str) {
+    let ip = host.parse::<IpAddr>().ok();
+    let Some(target) = targets.hosts.iter().find(|target| {
+        Some(target.ip) == ip
+            || target.hostnames.iter().any(|name| name == host)
+            || target.sources.iter().any(|source| source == host)
+    }) else {
+        warning!(
+            ErrorCode::InvalidArguments,
+            format!("{host} of --explain-ports is not one of the targets."),
+            opts.greppable,
+            opts.accessible,
+            host = host
+        );
+        std::process::exit(ErrorCode::InvalidArguments.exit_code());
+    };
+    println!("Ports of {}:", target.ip);
+    println!("{}", plan.explain_ports(target).table());
+}
+
+/// Adds the hosts of the `--import` file at `path` to the targets, aborting
+/// when it can't be read. Returns the ports to probe first on every host, none
+/// with `--import-ports-only` where they're the only ones scanned.
+fn import_targets(opts: &Opts, path: &Path, targets: &mut Targets) -> HashMap<IpAddr, Vec<u16>> {
+    let imported = match Imported::read(path) {
+        Ok(imported) => imported,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidImport,
+                format!("Can't import {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidImport.exit_code());
+        }
+    };
+
+    let source = path.display().to_string();
+    let mut priorities = HashMap::new();
+    let mut without_ports = 0;
+    for host in &imported.hosts {
+        let ports = host.ports(opts.udp);
+        if !opts.import_ports_only {
+            targets.insert(host.ip, &source, None);
+            if !ports.is_empty() {
+                priorities.insert(host.ip, ports.to_vec());
+            }
+        } else if ports.is_empty() {
+            without_ports += 1;
+        } else {
+            let ranges = ports.iter().map(|port| (*port, *port)).collect();
+            targets.insert_with_ports(host.ip, &source, &PortRange { ranges });
+        }
+    }
+    targets.record(Stage::Imported, without_ports);
+    verbose!(
+        format!(
+            "Imported {} hosts from the {} output {}",
+            imported.hosts.len() - without_ports,
+            imported.format,
+            path.display()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    if without_ports > 0 {
+        verbose!(
+            format!("Left out {without_ports} imported hosts without open ports"),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    priorities
+}
+
+/// Reads the `--scope` allowlist at `path`, aborting when it can't be read.
+fn read_scope(opts: &Opts, path: &Path) -> Scope {
+    match Scope::read(path) {
+        Ok(scope) => scope,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidScope,
+                format!("Can't read the scope {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidScope.exit_code());
+        }
+    }
+}
+
+/// Drops the targets outside `scope`, or aborts listing them with
+/// `--scope-mode enforce`. Runs before anything gets probed.
+fn check_scope(opts: &Opts, scope: &Scope, path: &Path, targets: &mut Targets) {
+    let out_of_scope = targets.retain_in_scope(scope);
+    if out_of_scope.is_empty() {
+        return;
+    }
+    let describe = |target: &Target| format!("{} (from {})", target.ip, target.sources.join(", "));
+
+    match opts.scope_mode {
+        ScopeMode::Enforce => {
+            let listed: Vec<String> = out_of_scope.iter().map(describe).collect();
+            warning!(
+                ErrorCode::OutOfScope,
+                format!(
+                    "{} target(s) outside the scope {}, aborting scan:\n{}",
+                    out_of_scope.len(),
+                    path.display(),
+                    listed.join("\n")
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::OutOfScope.exit_code());
+        }
+        ScopeMode::Warn => {
+            for target in &out_of_scope {
+                warning!(
+                    ErrorCode::OutOfScope,
+                    format!(
+                        "{} is outside the scope {}, skipping it.",
+                        describe(target),
+                        path.display()
+                    ),
+                    opts.greppable,
+                    opts.accessible,
+                    host = target.ip
+                );
+            }
+        }
+    }
+}
+
+/// Reads the version rules of `--probe-rules`, the built-in ones alone with
+/// `--probe-versions`, aborting when they can't be read. None without either.
+fn read_version_rules(opts: &Opts) -> Option<Vec<VersionRule>> {
+    let Some(path) = opts.probe_rules.as_deref() else {
+        return opts.probe_versions.then(version::default_rules);
+    };
+    match version::read_rules(path) {
+        Ok(rules) => Some(rules),
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidProbeRules,
+                format!("Can't read the version rules {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidProbeRules.exit_code());
+        }
+    }
+}
+
+/// Connects to the bastion of `--ssh-jump`, aborting when it can't be
+/// authenticated with. The hostnames of the targets were given stand-in
+/// addresses by `names`. None without one, or for UDP scans.
+fn connect_ssh_jump(opts: &Opts, names: Option<BastionNames>) -> Option<Arc<JumpSession>> {
+    let destination = opts.ssh_jump.as_deref()?;
+    if opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "UDP probes can't go through an SSH bastion, skipping --ssh-jump.",
+            opts.greppable,
+            opts.accessible
+        );
+        return None;
+    }
+
+    let jump = SshJump::new(destination)
+        .with_max_channels(opts.ssh_max_channels)
+        .with_hostnames(names.map(BastionNames::into_hostnames).unwrap_or_default());
+    let jump = match &opts.ssh_identity {
+        Some(identity) => jump.with_identity(identity.clone()),
+        None => jump,
+    };
+    match jump.connect() {
+        Ok(session) => {
+            verbose!(
+                format!(
+                    "Scanning through {destination}, {} channels at once",
+                    session.max_channels()
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+            Some(Arc::new(session))
+        }
+        Err(e) => {
+            warning!(
+                ErrorCode::SshJumpFailed,
+                format!("Can't scan through the SSH bastion, aborting scan.\n{e}"),
+                opts.greppable,
+                opts.accessible,
+                host = destination
+            );
+            std::process::exit(ErrorCode::SshJumpFailed.exit_code());
+        }
+    }
+}
+
+/// Sets up the SOCKS proxy of `--proxy`, or of the environment `env` reads
+/// with `--use-system-proxy`, aborting when it can't be used. The targets of
+/// `NO_PROXY` are probed straight. None without a proxy, or for UDP scans.
+fn connect_proxy(
+    opts: &Opts,
+    targets: &Targets,
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<ProxyRoute> {
+    let abort = |message: String| -> ! {
+        warning!(
+            ErrorCode::ProxyFailed,
+            format!("Can't scan through the SOCKS proxy, aborting scan.\n{message}"),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::ProxyFailed.exit_code());
+    };
+    let choice = rustscan::scanner::choose(opts.proxy.as_deref(), opts.use_system_proxy, env)
+        .unwrap_or_else(|e| abort(e))?;
+    if opts.udp {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "UDP probes can't go through a SOCKS proxy, scanning straight.",
+            opts.greppable,
+            opts.accessible
+        );
+        return None;
+    }
+    if let Some(variable) = choice.overridden {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            format!("--proxy is given, ignoring the proxy of {variable}."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    let address = choice
+        .proxy
+        .resolve()
+        .unwrap_or_else(|e| abort(format!("{}: {e}", choice.proxy)));
+    // Every port would pass for closed behind a proxy which is down.
+    if let Err(e) =
+        std::net::TcpStream::connect_timeout(&address, Duration::from_millis(opts.timeout.into()))
+    {
+        abort(format!("{} can't be reached: {e}", choice.proxy));
+    }
+    let mut direct: Vec<IpAddr> = targets
+        .hosts
+        .iter()
+        .filter(|target| choice.no_proxy.bypasses(target.ip, &target.hostnames))
+        .map(|target| target.ip)
+        .collect();
+    for host in &targets.dual_stack {
+        let hostnames = [host.hostname.clone()];
+        for ip in [host.ipv4, host.ipv6].iter().copied() {
+            if choice.no_proxy.bypasses(ip, &hostnames) {
+                direct.push(ip);
+            }
+        }
+    }
+
+    let source = match choice.source {
+        ProxySource::Flag => String::from("--proxy"),
+        ProxySource::Environment(variable) => variable.to_owned(),
+    };
+    detail!(
+        format!(
+            "Probing through the SOCKS proxy {} (from {source})",
+            choice.proxy
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    if !direct.is_empty() {
+        detail!(
+            format!("{} targets skip the proxy, as NO_PROXY asks", direct.len()),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    Some(ProxyRoute::new(choice.proxy, address).with_direct(direct))
+}
+
+/// Reads the notes of `--notes` at `path`, aborting when they can't be read.
+fn read_notes(opts: &Opts, path: &Path) -> Notes {
+    match Notes::read(path) {
+        Ok(notes) => notes,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidNotes,
+                format!("Can't read the notes {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidNotes.exit_code());
+        }
+    }
+}
+
+/// Reads the aliases of `--aliases` at `path`, aborting when they can't be
+/// read.
+fn read_aliases(opts: &Opts, path: &Path) -> Aliases {
+    match Aliases::read(path) {
+        Ok(aliases) => aliases,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidAliases,
+                format!("Can't read the aliases {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidAliases.exit_code());
+        }
+    }
+}
+
+/// Takes the lock of `--lock` on the targets of `ips`, warning about the
+/// runs which scan some of them already. With `--lock-strict` the run is
+/// aborted instead, and when the lockfile can't be written.
+fn take_lock(
+    opts: &Opts,
+    run_id: &str,
+    fingerprint: &Fingerprint,
+    ips: &[IpAddr],
+) -> Option<ScanLock> {
+    let dir = LockDir::shared();
+    let scope = LockScope::new(&fingerprint.config.targets, ips);
+    let (lock, overlapping) = match dir.acquire(LockRecord::new(run_id, &fingerprint.hash, scope)) {
+        Ok(acquired) => acquired,
+        Err(e) => {
+            warning!(
+                ErrorCode::LockFailed,
+                format!("Can't write the lockfile in {}: {e}", dir.path().display()),
+                opts.greppable,
+                opts.accessible,
+                host = dir.path().display()
+            );
+            if opts.lock_strict {
+                std::process::exit(ErrorCode::LockFailed.exit_code());
+            }
+            return None;
+        }
+    };
+    if overlapping.is_empty() {
+        return Some(lock);
+    }
+
+    let runs: Vec<String> = overlapping
+        .iter()
+        .map(|other| {
+            let same = if other.scope_hash == fingerprint.hash {
+                ", the same scan"
+            } else {
+                ""
+            };
+            format!("run {} of PID {}{same}", other.run_id, other.pid)
+        })
+        .collect();
+    let message = format!(
+        "Targets of this scan are locked by another run: {}",
+        runs.join("; ")
+    );
+    if opts.lock_strict {
+        warning!(
+            ErrorCode::ScopeLocked,
+            format!("{message}\nAborting scan because of --lock-strict."),
+            opts.greppable,
+            opts.accessible
+        );
+        drop(lock);
+        std::process::exit(ErrorCode::ScopeLocked.exit_code());
+    }
+    warning!(
+        ErrorCode::ScopeLocked,
+        message,
+        opts.greppable,
+        opts.accessible
+    );
+    Some(lock)
+}
+
+/// Starts writing the trace of `--trace-file` to `path`, aborting when it
+/// can't be created.
+/// Starts the discovery of `ips`, with a quarter of the batch size so that
+/// the hosts found up have room to be scanned alongside.
+fn start_discovery(opts: &Opts, ips: Vec<IpAddr>, batch_size: u16) -> Waves {
+    let technique = ConnectPing {
+        ports: DISCOVERY_PORTS.to_vec(),
+        timeout: Duration::from_millis(opts.timeout.into()),
+    };
+    Waves::start(
+        Arc::new(technique),
+        ips,
+        usize::from(batch_size / 4).max(1),
+        opts.discovery_timeout,
+        opts.discovery_undetermined,
+    )
+}
+
+/// Notes what the discovery made of every host, the ones left out being
+/// skipped.
+fn report_discovery(opts: &Opts, waves: &Waves, report: &mut ScanReport) {
+    let mut undetermined = 0;
+    for host in &mut report.hosts {
+        let Some(liveness) = waves.liveness().get(&host.ip).copied() else {
+            continue;
+        };
+        host.discovery = Some(liveness);
+        if !liveness.is_scanned() {
+            host.scanned = false;
+            host.skipped_reason = Some(SkipReason::HostDown);
+        }
+        if matches!(liveness, Liveness::AssumedAlive | Liveness::AssumedDead) {
+            undetermined += 1;
+        }
+    }
+    if undetermined > 0 {
+        detail!(
+            format!(
+                "The discovery couldn't tell about {undetermined} host(s) by --discovery-timeout, they were assumed {}.",
+                match opts.discovery_undetermined {
+                    Undetermined::Alive => "up and scanned",
+                    Undetermined::Dead => "down and left out",
+                }
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+}
+
+/// The interfaces of this machine, for `--interface-map`.
+fn list_interfaces(opts: &Opts) -> Vec<Interface> {
+    Interface::list().unwrap_or_else(|e| {
+        warning!(
+            ErrorCode::InterfaceUnusable,
+            format!("The interfaces of --interface-map can't be listed, aborting scan: {e}"),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::InterfaceUnusable.exit_code());
+    })
+}
+
+/// The sources of the probes of `ips` on the `interfaces` of
+/// `--interface-map`, or why their interface can't be used, telling which
+/// interface every host uses.
+fn select_interfaces(
+    opts: &Opts,
+    interfaces: &[Interface],
+    ips: &[IpAddr],
+) -> HashMap<IpAddr, Result<Binding, Unusable>> {
+    let mut selections = HashMap::new();
+    let Some(map) = &opts.interface_map else {
+        return selections;
+    };
+    let mut devices: HashMap<String, bool> = HashMap::new();
+    let mut unusable: Vec<(Unusable, usize)> = Vec::new();
+    for &ip in ips {
+        match select(map, interfaces, ip) {
+            Some(Ok(mut binding)) => {
+                let device = *devices
+                    .entry(binding.interface.clone())
+                    .or_insert_with(|| can_bind_device(&binding.interface));
+                binding.device = device;
+                verbose!(
+                    format!(
+                        "Host {ip} uses the interface {}, from {}",
+                        binding.interface, binding.source
+                    ),
+                    opts.greppable,
+                    opts.accessible
+                );
+                selections.insert(ip, Ok(binding));
+            }
+            Some(Err(reason)) => {
+                verbose!(
+                    format!("Host {ip} is skipped, {reason}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                match unusable.iter_mut().find(|(known, _)| *known == reason) {
+                    Some((_, hosts)) => *hosts += 1,
+                    None => unusable.push((reason.clone(), 1)),
+                }
+                selections.insert(ip, Err(reason));
+            }
+            None => verbose!(
+                format!("Host {ip} uses the interface of its route, outside of --interface-map"),
+                opts.greppable,
+                opts.accessible
+            ),
+        }
+    }
+    for (reason, hosts) in unusable {
+        warning!(
+            ErrorCode::InterfaceUnusable,
+            format!(
+                "Skipping the {hosts} host(s) going out by {}, {reason}.",
+                reason.interface()
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    let mut unbound: Vec<&str> = devices
+        .iter()
+        .filter(|(_, device)| !**device)
+        .map(|(interface, _)| interface.as_str())
+        .collect();
+    if !unbound.is_empty() {
+        unbound.sort_unstable();
+        detail!(
+            format!(
+                "The probes are bound to the addresses of {} only, binding them to the interfaces themselves takes CAP_NET_RAW on Linux.",
+                unbound.join(", ")
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    selections
+}
+
+/// Binds the probes of `scanner` to the sources of `selections`, leaving out
+/// the hosts whose interface can't be used.
+fn bind_interfaces(
+    scanner: Scanner,
+    selections: &HashMap<IpAddr, Result<Binding, Unusable>>,
+) -> Scanner {
+    if selections.is_empty() {
+        return scanner;
+    }
+    let mut bindings = HashMap::new();
+    let mut skipped = Vec::new();
+    for (ip, selected) in selections {
+        match selected {
+            Ok(binding) => {
+                bindings.insert(*ip, binding.clone());
+            }
+            Err(_) => skipped.push(*ip),
+        }
+    }
+    scanner.with_bindings(bindings).without_hosts(&skipped)
+}
+
+fn start_trace(opts: &Opts, path: &Path) -> (TraceWriter, Tracer) {
+    match TraceWriter::create(path, trace::CAPACITY) {
+        Ok(started) => started,
+        Err(e) => {
+            warning!(
+                ErrorCode::TraceWriteFailed,
+                format!("Can't write the trace to {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::TraceWriteFailed.exit_code());
+        }
+    }
+}
+
+/// Writes the end of the trace, telling about the events which were dropped.
+fn finish_trace(opts: &Opts, writer: TraceWriter) {
+    match writer.finish() {
+        Ok(0) => {}
+        Ok(dropped) => detail!(
+            format!("Dropped {dropped} events of the trace, it couldn't be written fast enough."),
+            opts.greppable,
+            opts.accessible
+        ),
+        Err(e) => warning!(
+            ErrorCode::TraceWriteFailed,
+            format!("Can't write the trace: {e}"),
+            opts.greppable,
+            opts.accessible
+        ),
+    }
+}
+
+/// Aborts unless the configuration of the run has the `expected`
+/// fingerprint of `--verify-config`.
+fn verify_config(opts: &Opts, fingerprint: &Fingerprint, expected: &str) {
+    if fingerprint.matches(expected) {
+        return;
+    }
+    warning!(
+        ErrorCode::ConfigMismatch,
+        format!(
+            "The configuration has the fingerprint {}, not {expected}, aborting.\n{}",
+            fingerprint.hash,
+            fingerprint.config.canonical()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    std::process::exit(ErrorCode::ConfigMismatch.exit_code());
+}
+
+/// The fingerprint of a run resuming the checkpoint at `path`, the one of
+/// the run which left it. Aborts when more than the targets and the ports
+/// changed since.
+fn resume_checkpoint(
+    opts: &Opts,
+    path: &Path,
+    origin: &Origin,
+    fingerprint: Fingerprint,
+) -> Fingerprint {
+    let fingerprint = origin.resume(fingerprint.config);
+    if fingerprint.matches(&origin.fingerprint) {
+        return fingerprint;
+    }
+    warning!(
+        ErrorCode::ConfigMismatch,
+        format!(
+            "The checkpoint {} was left by a run with the fingerprint {}, this one resumes it with {}, aborting.\n{}",
+            path.display(),
+            origin.fingerprint,
+            fingerprint.hash,
+            fingerprint.config.canonical()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    std::process::exit(ErrorCode::ConfigMismatch.exit_code());
+}
+
+/// Reads the ports of `--order-file` at `path`, aborting when they can't be
+/// read.
+fn read_order_file(opts: &Opts, path: &Path) -> OrderFile {
+    match OrderFile::read(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidOrderFile,
+                format!("Can't read the port order {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible,
+                host = path.display()
+            );
+            std::process::exit(ErrorCode::InvalidOrderFile.exit_code());
+        }
+    }
+}
+
+/// Tries the ports of the run against `listener` and reports the ones the
+/// network let out, see `--egress-check`.
+fn run_egress_check(opts: &Opts, listener: &str, capabilities: &CapabilityReport) {
+    // The scanner stays quiet, its open ports are no open ports of a target.
+    let listener_opts = Opts {
+        addresses: vec![listener.to_owned()],
+        greppable: true,
+        udp: false,
+        canary_ports: Vec::new(),
+        shard: None,
+        ..opts.clone()
+    };
+    let targets = parse_targets(&listener_opts);
+    let Some(address) = targets.ips().first().copied() else {
+        warning!(
+            ErrorCode::NoTargets,
+            format!("The egress listener {listener} could not be resolved, aborting check."),
+            opts.greppable,
+            opts.accessible,
+            host = listener
+        );
+        std::process::exit(ErrorCode::NoTargets.exit_code());
+    };
+
+    #[cfg(unix)]
+    let batch_size: u16 = {
+        let open = open_file_descriptors();
+        let ulimit = adjust_ulimit_size(opts, open, capabilities);
+        infer_batch_size(opts, ulimit.saturating_sub(open))
+    };
+    #[cfg(not(unix))]
+    let batch_size: u16 = {
+        let _ = capabilities;
+        AVERAGE_BATCH_SIZE
+    };
+
+    let order_file = opts
+        .order_file
+        .as_deref()
+        .map(|path| read_order_file(opts, path));
+    let plan = ScanPlan::new(&listener_opts, &targets, order_file, None);
+    let scanner = plan
+        .scanner(&[address], batch_size, HashMap::new())
+        .with_socket_options(SocketOptions {
+            ttl: opts.ttl,
+            nodelay: opts.nodelay,
+        });
+    let tested = scanner.host_ports(address);
+    verbose!(
+        format!(
+            "Checking egress on {} ports against {listener} ({address})",
+            tested.len()
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    let open = block_on(scanner.scan()).open;
+
+    let mut report = EgressReport::new(listener, address, &tested, &open);
+    report.schema_version = opts.schema_version;
+    report.run_id = Some(opts.run_id.clone().unwrap_or_else(report::new_run_id));
+    if opts.format == OutputFormat::Json && opts.output_file.is_none() {
+        println!("{}", report.to_json());
+    } else if opts.greppable || opts.quiet {
+        println!("{}", report.greppable());
+    } else {
+        let ports: Vec<String> = report.allowed.iter().map(ToString::to_string).collect();
+        output!(
+            format!(
+                "Egress check against {listener} ({address}): {} of {} ports allowed out\n\
+                 Allowed out: [{}]",
+                report.allowed.len(),
+                tested.len(),
+                ports.join(",")
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    if let Some(path) = &opts.output_file {
+        if let Err(e) = std::fs::write(path, report.to_json() + "\n") {
+            warning!(
+                ErrorCode::ReportWriteFailed,
+                format!("Couldn't write the report to {}: {e}", path.display()),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::ReportWriteFailed.exit_code());
+        }
+    }
+}
+
+/// Attaches the notes of every host of the report, the notes about no host
+/// are only mentioned in verbose mode.
+fn attach_notes(opts: &Opts, notes: &Notes, report: &mut ScanReport) {
+    for host in &mut report.hosts {
+        host.notes = notes.for_host(host.ip, &host.hostnames);
+    }
+    let hosts: Vec<(IpAddr, &[String])> = report
+        .hosts
+        .iter()
+        .map(|host| (host.ip, host.hostnames.as_slice()))
+        .collect();
+    for key in notes.unmatched(&hosts) {
+        verbose!(
+            format!("The note of {key} matches no target, ignoring it."),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+}
+
+/// Prints the notes of `host` dimmed, with the other details on stderr.
+fn print_notes(opts: &Opts, host: &HostReport) {
+    if !tui::shows(Verbosity::Normal, opts.greppable) {
+        return;
+    }
+    for note in &host.notes {
+        let line = format!("{} note: {note}", host.ip);
+        if opts.accessible {
+            eprintln!("{line}");
+        } else {
+            eprintln!("{}", ansi_term::Style::new().dimmed().paint(line));
+        }
+    }
+}
+
+/// Looks every host up in the cache. Returns the cache key of every host and
+/// the fresh entries found, none with `--refresh`.
+fn read_cache(
+    opts: &Opts,
+    cache: Option<&ScanCache>,
+    scanner: &Scanner,
+    ips: &[IpAddr],
+) -> (HashMap<IpAddr, CacheKey>, HashMap<IpAddr, CacheEntry>) {
+    let mut keys = HashMap::new();
+    let mut cached = HashMap::new();
+    let Some(cache) = cache else {
+        return (keys, cached);
+    };
+
+    for ip in ips {
+        let key = CacheKey::new(*ip, &scanner.host_ports(*ip), opts.udp);
+        if let Some(entry) = cache.get(&key).filter(|_| !opts.refresh) {
+            detail!(
+                format!(
+                    "Using the cached scan of {ip} from {}",
+                    entry.scanned_at_rfc3339()
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+            cached.insert(*ip, entry);
+        }
+        keys.insert(*ip, key);
+    }
+    (keys, cached)
+}
+
+/// The outputs of the run: the normal output, the greppable lines or the
+/// JSON report, the report of `--output-file`, the page of `--html-report`
+/// and the webhooks of `--notify`. Failing to write the reports ends the
+/// run.
+fn output_sinks(opts: &Opts, run_id: &str) -> Sinks {
+    let mut sinks = Sinks::new().with_sink(Normal::new(opts.greppable, opts.accessible));
+    let prints_lines = opts.greppable || opts.quiet || opts.scripts == ScriptsRequired::None;
+    if opts.format != OutputFormat::Json && prints_lines && !opts.no_results {
+        let prefix = opts
+            .greppable_prefix
+            .as_ref()
+            .map(|prefix| prefix.replace("{run_id}", run_id));
+        sinks = sinks.with_sink(Greppable::new(prefix, opts.show_empty_hosts));
+    }
+    match &opts.output_file {
+        Some(path) => {
+            sinks = sinks.with_critical_sink(Json::file(path.clone(), opts.output_split));
+        }
+        None if opts.format == OutputFormat::Json && !opts.no_results => {
+            sinks = sinks.with_sink(Json::stdout());
+        }
+        None => {}
+    }
+    if let Some(path) = &opts.html_report {
+        sinks = sinks.with_critical_sink(Html::file(path.clone()));
+    }
+    if !opts.notify.is_empty() {
+        let notifier = Notifier::start(opts.notify.clone(), Some(run_id.to_owned()));
+        sinks = sinks.with_sink(WebhookSink::new(notifier));
+    }
+    sinks
+}
+
+/// Binds the server of `--serve-report` on `address`, aborting when it can't.
+fn bind_report_server(opts: &Opts, address: SocketAddr) -> Server {
+    match Server::bind(address) {
+        Ok(server) => server,
+        Err(e) => {
+            warning!(
+                ErrorCode::ServeFailed,
+                format!("Can't serve the report on {address}: {e}"),
+                opts.greppable,
+                opts.accessible,
+                host = address
+            );
+            std::process::exit(ErrorCode::ServeFailed.exit_code());
+        }
+    }
+}
+
+/// Serves the page of `report` and the report itself until Ctrl-C.
+fn serve_report(opts: &Opts, server: &Server, report: &ScanReport) {
+    let page = html::render(report, html::JSON_PATH);
+    let address = server
+        .local_addr()
+        .map_or_else(|_| "its address".to_owned(), |address| address.to_string());
+    detail!(
+        format!("Serving the report on http://{address}/, Ctrl-C to stop"),
         opts.greppable,
-        PortStrategy::pick(&opts.range, opts.ports, opts.scan_order),
-        opts.accessible,
-        opts.exclude_ports.unwrap_or_default(),
-        opts.udp,
+        opts.accessible
     );
-    debug!("Scanner finished building: {:?}", scanner);
-
-    let mut portscan_bench = NamedTimer::start("Portscan");
-    let scan_result = block_on(scanner.run());
-    portscan_bench.end();
-    benchmarks.push(portscan_bench);
+    server.serve(&page, &report.to_json(), listen::stop_on_interrupt());
+}
 
-    let mut ports_per_ip = HashMap::new();
+/// Warns about the sinks which failed, the run ending with the code of the
+/// first critical one among them.
+fn report_sink_failures(opts: &Opts, sinks: &Sinks) {
+    let failures = sinks.failures();
+    for failure in &failures {
+        warning!(failure.code, failure, opts.greppable, opts.accessible);
+    }
+    if let Some(critical) = failures.iter().find(|failure| failure.critical) {
+        std::process::exit(critical.code.exit_code());
+    }
+}
 
-    for socket in scan_result {
-        ports_per_ip
-            .entry(socket.ip())
-            .or_insert_with(Vec::new)
-            .push(socket.port());
+/// Writes the files of an exporter of `--export`, the run fails without them.
+fn write_export(opts: &Opts, export: &Export, report: &ScanReport) {
+    if let Err(e) = export.write(report, opts.export_exclude_flaky) {
+        warning!(
+            ErrorCode::ExportFailed,
+            format!(
+                "Couldn't write the {} export to {}: {e}",
+                export.exporter,
+                export.path.display()
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::ExportFailed.exit_code());
     }
+}
 
-    for ip in ips {
-        if ports_per_ip.contains_key(&ip) {
+/// Stores the results of the hosts scanned in this run, and the script runs
+/// of cached hosts which had none cached yet.
+fn write_cache(
+    opts: &Opts,
+    cache: &ScanCache,
+    keys: &HashMap<IpAddr, CacheKey>,
+    cached: &HashMap<IpAddr, CacheEntry>,
+    report: &ScanReport,
+) {
+    for host in &report.hosts {
+        let Some(key) = keys.get(&host.ip) else {
+            continue;
+        };
+        // A host dropped before the scan has no ports to remember.
+        if !host.scanned {
+            continue;
+        }
+        // Nobody knows about the ports left unprobed by an outage.
+        if host.outages.iter().any(|outage| outage.unprobed_ports > 0) {
             continue;
         }
 
-        // If we got here it means the IP was not found within the HashMap, this
-        // means the scan couldn't find any open ports for it.
-
-        let x = format!("Looks like I didn't find any open ports for {:?}. This is usually caused by a high batch size.
-        \n*I used {} batch size, consider lowering it with {} or a comfortable number for your system.
-        \n Alternatively, increase the timeout if your ping is high. Rustscan -t 2000 for 2000 milliseconds (2s) timeout.\n",
-        ip,
-        opts.batch_size,
-        "'rustscan -b <batch_size> -a <ip address>'");
-        warning!(x, opts.greppable, opts.accessible);
+        let scripts = (opts.cache_scripts
+            && !host.scripts.is_empty()
+            && host.scripts.iter().all(ScriptRun::succeeded))
+        .then(|| host.scripts.clone());
+        let entry = match cached.get(&host.ip) {
+            Some(entry) if entry.scripts.is_some() || scripts.is_none() => continue,
+            Some(entry) => entry.clone().with_scripts(scripts),
+            None => CacheEntry::new(key, host.ports.clone()).with_scripts(scripts),
+        };
+        if let Err(e) = cache.put(key, &entry) {
+            warning!(
+                ErrorCode::CacheWriteFailed,
+                format!("Could not cache the results of {}: {e}", host.ip),
+                opts.greppable,
+                opts.accessible,
+                host = host.ip
+            );
+        }
     }
+}
 
-    let mut script_bench = NamedTimer::start("Scripts");
-    for (ip, ports) in &ports_per_ip {
-        let vec_str_ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
+/// Prints the output of the last attempt of a script run, after a warning
+/// for every attempt which was retried. The output goes to stderr, unless
+/// `--forward-script-output` counts it with the results.
+fn print_script_run(opts: &Opts, ip: IpAddr, run: &ScriptRun) {
+    for (attempt, retried) in run.retried.iter().enumerate() {
+        warning!(
+            ErrorCode::ScriptFailed,
+            format!(
+                "Script {} failed on attempt {} of {}, retrying: {}",
+                run.script,
+                attempt + 1,
+                run.attempts,
+                retried.error
+            ),
+            opts.greppable,
+            opts.accessible,
+            host = ip
+        );
+        verbose!(retried.output.trim_end(), opts.greppable, opts.accessible);
+    }
 
-        // nmap port style is 80,443. Comma separated with no spaces.
-        let ports_str = vec_str_ports.join(",");
+    match &run.error {
+        None if opts.forward_script_output => println!("{}", run.output.trim_end()),
+        None => detail!(run.output, opts.greppable, opts.accessible),
+        Some(e) => warning!(
+            ErrorCode::ScriptFailed,
+            format!("Error {e}"),
+            opts.greppable,
+            opts.accessible,
+            host = ip
+        ),
+    }
+}
 
-        // if option scripts is none, no script will be spawned
-        if opts.greppable || opts.scripts == ScriptsRequired::None {
-            println!("{} -> [{}]", &ip, ports_str);
-            continue;
+fn print_port_hook_run(opts: &Opts, ip: IpAddr, run: &PortHookRun) {
+    let ran = format!("Port hook {} on {ip}:{}", run.hook, run.port);
+    match (&run.error, run.exit_code) {
+        (Some(e), _) => warning!(
+            ErrorCode::ScriptFailed,
+            format!("{ran} failed: {e}"),
+            opts.greppable,
+            opts.accessible,
+            host = ip
+        ),
+        (None, Some(0)) => {
+            let wrote = run
+                .output_path
+                .as_ref()
+                .map(|path| format!(", wrote {}", path.display()))
+                .unwrap_or_default();
+            detail!(
+                format!("{ran} took {}ms{wrote}", run.duration_ms),
+                opts.greppable,
+                opts.accessible
+            );
         }
-        detail!("Starting Script(s)", opts.greppable, opts.accessible);
-
-        // Run all the scripts we found and parsed based on the script config file tags field.
-        for mut script_f in scripts_to_run.clone() {
-            // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
-            if !opts.command.is_empty() {
-                let user_extra_args = &opts.command.join(" ");
-                debug!("Extra args vec {:?}", user_extra_args);
-                if script_f.call_format.is_some() {
-                    let mut call_f = script_f.call_format.unwrap();
-                    call_f.push(' ');
-                    call_f.push_str(user_extra_args);
-                    output!(
-                        format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", call_f, &ip),
-                        opts.greppable,
-                        opts.accessible
-                    );
-                    debug!("Call format {}", call_f);
-                    script_f.call_format = Some(call_f);
-                }
-            }
+        (None, code) => warning!(
+            ErrorCode::ScriptFailed,
+            format!("{ran} exited with {}", code.unwrap_or(-1)),
+            opts.greppable,
+            opts.accessible,
+            host = ip
+        ),
+    }
+    if !run.output.trim().is_empty() {
+        verbose!(run.output.trim_end(), opts.greppable, opts.accessible);
+    }
+}
 
-            // Building the script with the arguments from the ScriptFile, and ip-ports.
-            let script = Script::build(
-                script_f.path,
-                *ip,
-                ports.clone(),
-                script_f.port,
-                script_f.ports_separator,
-                script_f.tags,
-                script_f.call_format,
-            );
-            match script.run() {
-                Ok(script_result) => {
-                    detail!(script_result.to_string(), opts.greppable, opts.accessible);
-                }
-                Err(e) => {
-                    warning!(&format!("Error {e}"), opts.greppable, opts.accessible);
-                }
+/// Prints the plan of the scan, for `--dry-run`, as JSON with `--format json`.
+fn print_dry_run(opts: &Opts, plan: &ScanPlan) {
+    if opts.format == OutputFormat::Json {
+        println!("{}", plan.to_json());
+        return;
+    }
+    let mut hosts: Vec<String> = plan.hosts.iter().map(|host| host.ip.to_string()).collect();
+    hosts.extend(plan.dual_stack.iter().cloned());
+    println!("Hosts: {}", hosts.join(", "));
+    println!("Protocol: {}", if opts.udp { "UDP" } else { "TCP" });
+    let ports = &plan.fingerprint.config.ports;
+    match plan.default_ports {
+        Some(set) => println!("Ports: {ports} ({set}, the default)"),
+        None => println!("Ports: {ports}"),
+    }
+    if !plan.canary_ports.is_empty() {
+        let ports: Vec<String> = plan.canary_ports.iter().map(ToString::to_string).collect();
+        println!(
+            "Canary ports: {}{}",
+            ports.join(","),
+            if plan.canary_first_pass {
+                " (first pass)"
+            } else {
+                ""
             }
-        }
+        );
     }
-
-    // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
-    script_bench.end();
-    benchmarks.push(script_bench);
-    rustscan_bench.end();
-    benchmarks.push(rustscan_bench);
-    debug!("Benchmarks raw {:?}", benchmarks);
-    info!("{}", benchmarks.summary());
+    let excluded = &plan.fingerprint.config.exclude_ports;
+    if !excluded.is_empty() {
+        println!("Excluded ports: {}", PortRange::from_ports(excluded));
+    }
+    println!("Sockets: {}", plan.sockets);
+    println!("Fingerprint: {}", plan.fingerprint.hash);
 }
 
 /// Prints the opening title of RustScan
@@ -205,7 +3168,7 @@ fn print_opening(opts: &Opts) {
         r#"`-' `-'`-----'`----'  `-'  `----'  `---' `-'  `-'`-' `-'"#,
         r#"The Modern Day Port Scanner."#
     );
-    println!("{}", s.gradient(Color::Green).bold());
+    eprintln!("{}", s.gradient(Color::Green).bold());
     let info = format!(
         "{}\n{}\n{}\n{}",
         r#"________________________________________"#,
@@ -213,7 +3176,7 @@ fn print_opening(opts: &Opts) {
         r#": https://github.com/RustScan/RustScan :"#,
         r#" --------------------------------------"#
     );
-    println!("{}", info.gradient(Color::Yellow).bold());
+    eprintln!("{}", info.gradient(Color::Yellow).bold());
     funny_opening!();
 
     let config_path = opts
@@ -228,11 +3191,428 @@ fn print_opening(opts: &Opts) {
     );
 }
 
+/// Calibrates against localhost and the representative host of `rustscan
+/// tune`, then stores the recommendations in the `[tuned]` profile.
+async fn tune(opts: &Opts, args: &TuneArgs) {
+    let max_batch_size = usize::from(args.max_batch_size);
+    let mut hosts = vec!["127.0.0.1".to_owned()];
+    hosts.extend(args.target.clone());
+
+    let mut sockets = Vec::with_capacity(hosts.len());
+    for host in &hosts {
+        match tune::resolve(host, args.port) {
+            Ok(resolved) => sockets.push(resolved),
+            Err(e) => {
+                warning!(
+                    ErrorCode::UnresolvedHost,
+                    format!("Could not resolve {host}: {e}"),
+                    opts.greppable,
+                    opts.accessible,
+                    host = host
+                );
+                std::process::exit(ErrorCode::UnresolvedHost.exit_code());
+            }
+        }
+    }
+
+    // Localhost is this machine, only the representative host has to be in scope.
+    if let (Some(path), Some(target), Some((socket, resolver))) =
+        (opts.scope.as_deref(), &args.target, sockets.get(1))
+    {
+        let hostnames: Vec<String> = resolver.map(|_| target.clone()).into_iter().collect();
+        if !read_scope(opts, path).allows(socket.ip(), &hostnames) {
+            warning!(
+                ErrorCode::OutOfScope,
+                format!(
+                    "{target} ({}) is outside the scope {}, aborting calibration.",
+                    socket.ip(),
+                    path.display()
+                ),
+                opts.greppable,
+                opts.accessible,
+                host = target
+            );
+            std::process::exit(ErrorCode::OutOfScope.exit_code());
+        }
+    }
+
+    let mut measured: Vec<Measurements> = Vec::with_capacity(hosts.len());
+    for (host, (socket, resolver)) in hosts.iter().zip(sockets) {
+        detail!(
+            format!("Calibrating against {socket}"),
+            opts.greppable,
+            opts.accessible
+        );
+        let mut measurements = tune::measure(socket, max_batch_size).await;
+        measurements.resolver = resolver;
+        print_measurements(host, &measurements);
+        measured.push(measurements);
+    }
+
+    let combined = tune::combine(&measured[0], measured.get(1));
+    let recommendation = tune::recommend(&combined);
+    output!(
+        format!(
+            "Recommended: batch_size = {}, timeout = {} ms, tries = {}",
+            recommendation.batch_size, recommendation.timeout, recommendation.tries
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    if args.dry_run {
+        return;
+    }
+
+    let config_path = opts
+        .config_path
+        .clone()
+        .unwrap_or_else(input::default_config_path);
+    match tune::save_profile(&config_path, &recommendation) {
+        Ok(()) => output!(
+            format!("Saved as the [tuned] profile of {config_path:?}, use it with --profile tuned"),
+            opts.greppable,
+            opts.accessible
+        ),
+        Err(e) => {
+            warning!(
+                ErrorCode::ConfigWriteFailed,
+                format!("Could not write the [tuned] profile to {config_path:?}: {e}"),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::ConfigWriteFailed.exit_code());
+        }
+    }
+}
+
+/// Runs the checks of `rustscan selftest`, exiting with an error when any
+/// of them failed.
+async fn run_selftest(opts: &Opts, args: &SelftestArgs) {
+    let checks = selftest::run(opts, &args.name, usize::from(args.max_batch_size)).await;
+    for check in &checks {
+        println!("{check}");
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        warning!(
+            ErrorCode::SelftestFailed,
+            format!("{failed} of {} checks failed.", checks.len()),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::SelftestFailed.exit_code());
+    }
+    output!(
+        format!("All {} checks passed.", checks.len()),
+        opts.greppable,
+        opts.accessible
+    );
+}
+
+/// Serves the reflector until the process is killed.
+fn run_reflector(opts: &Opts, args: &ReflectorArgs) {
+    let listener = match TcpListener::bind(args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warning!(
+                ErrorCode::ReflectorFailed,
+                format!("The reflector could not listen on {}: {e}", args.listen),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::ReflectorFailed.exit_code());
+        }
+    };
+    let address = listener.local_addr().unwrap_or(args.listen);
+    output!(
+        format!("Reflecting source addresses on {address}"),
+        opts.greppable,
+        opts.accessible
+    );
+    if let Err(e) = reflector::serve(&listener) {
+        warning!(
+            ErrorCode::ReflectorFailed,
+            format!("The reflector stopped: {e}"),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::ReflectorFailed.exit_code());
+    }
+}
+
+/// Writes the bundle of the run of `opts` to `file`, see [`Bundle`].
+fn export_bundle(opts: &Opts, default_ports: Option<DefaultPorts>, file: &Path) {
+    let scripts = match opts.scripts {
+        ScriptsRequired::Custom => init_scripts(&opts.scripts).unwrap_or_else(|e| {
+            warning!(
+                ErrorCode::ScriptsFailed,
+                format!("Initiating scripts failed!\n{e}"),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::ScriptsFailed.exit_code());
+        }),
+        _ => Vec::new(),
+    };
+    let exported = Bundle::export(opts, default_ports, &scripts).and_then(|bundle| {
+        std::fs::write(file, bundle.to_toml())
+            .map_err(|e| format!("Could not write {}: {e}", file.display()))
+    });
+    if let Err(e) = exported {
+        warning!(
+            ErrorCode::InvalidBundle,
+            format!("Could not export the bundle. {e}"),
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::InvalidBundle.exit_code());
+    }
+    println!("Exported the bundle to {}", file.display());
+}
+
+/// Sets the configuration of the bundle `file` in `opts`, exiting when it
+/// can't be read or custom scripts it selected are missing. Returns the
+/// default port set the bundle fell back on, and the folder its files are
+/// written to, removed once dropped.
+fn load_bundle(
+    opts: &mut Opts,
+    file: &Path,
+    ignore_missing_scripts: bool,
+) -> (Option<DefaultPorts>, PrivateDir) {
+    let bundle = std::fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|document| Bundle::parse(&document));
+    let bundle = match bundle {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidBundle,
+                format!("Invalid bundle {}: {e}", file.display()),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::InvalidBundle.exit_code());
+        }
+    };
+
+    if !bundle.scripts.is_empty() {
+        let found = init_scripts(&ScriptsRequired::Custom).unwrap_or_default();
+        let missing = bundle.missing_scripts(&found);
+        if !missing.is_empty() {
+            let message = format!(
+                "The scripts {} of the bundle are missing from the scripts folder",
+                missing.join(", ")
+            );
+            if !ignore_missing_scripts {
+                warning!(
+                    ErrorCode::BundleScriptsMissing,
+                    format!("{message}, run it with --ignore-missing-scripts to scan anyway."),
+                    opts.greppable,
+                    opts.accessible
+                );
+                std::process::exit(ErrorCode::BundleScriptsMissing.exit_code());
+            }
+            warning!(
+                ErrorCode::BundleScriptsMissing,
+                format!("{message}, scanning anyway."),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+
+    let applied = PrivateDir::create("rustscan-bundle").and_then(|dir| {
+        let default_ports = bundle.apply(opts, dir.path())?;
+        Ok((default_ports, dir))
+    });
+    match applied {
+        Ok(applied) => applied,
+        Err(e) => {
+            warning!(
+                ErrorCode::InvalidBundle,
+                format!("Could not write the files of the bundle: {e}"),
+                opts.greppable,
+                opts.accessible
+            );
+            std::process::exit(ErrorCode::InvalidBundle.exit_code());
+        }
+    }
+}
+
+/// Serves the ports of `rustscan listen` until Ctrl-C, then prints what
+/// connected to them.
+fn run_listener(opts: &Opts, args: &ListenArgs) {
+    let ports: Vec<u16> = args
+        .ports
+        .ranges
+        .iter()
+        .flat_map(|&(start, end)| start..=end)
+        .collect();
+    let (listener, failures) = Listener::bind(args.address, &ports, &args.protocol);
+    for failure in &failures {
+        warning!(
+            ErrorCode::ListenFailed,
+            format!("Could not listen on {failure}"),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    if listener.is_empty() {
+        warning!(
+            ErrorCode::ListenFailed,
+            "None of the ports could be listened on, exiting.",
+            opts.greppable,
+            opts.accessible
+        );
+        std::process::exit(ErrorCode::ListenFailed.exit_code());
+    }
+
+    let answer = match (&args.banner, args.reflect) {
+        (_, true) => Answer::Reflect,
+        (Some(banner), false) => Answer::Banner(banner.clone()),
+        (None, false) => Answer::Nothing,
+    };
+    output!(
+        format!(
+            "Listening on {} socket(s) of {}, Ctrl-C to stop",
+            listener.local_addrs().len(),
+            args.address
+        ),
+        opts.greppable,
+        opts.accessible
+    );
+    let summary = listener.serve(&answer, listen::stop_on_interrupt(), |event| {
+        output!(event, opts.greppable, opts.accessible);
+    });
+    output!(summary, opts.greppable, opts.accessible);
+}
+
+/// Connects to the reflector from a few random source ports and warns, or
+/// aborts with --strict-evasion, when a NAT rewrote them.
+fn verify_source_ports(opts: &Opts, reflector: &str) {
+    if !opts.randomize_source_ports {
+        warning!(
+            ErrorCode::IncompatibleOptions,
+            "Only the source ports of --randomize-source-ports are checked by --reflector, skipping it.",
+            opts.greppable,
+            opts.accessible
+        );
+        return;
+    }
+    let fail = |code: ErrorCode, message: String| {
+        let outcome = if opts.strict_evasion {
+            "aborting scan"
+        } else {
+            "scanning anyway"
+        };
+        warning!(
+            code,
+            format!("{message}, {outcome}."),
+            opts.greppable,
+            opts.accessible,
+            host = reflector
+        );
+        if opts.strict_evasion {
+            std::process::exit(code.exit_code());
+        }
+    };
+
+    let address = match reflector::resolve(reflector) {
+        Ok(address) => address,
+        Err(e) => {
+            return fail(
+                ErrorCode::ReflectorFailed,
+                format!("The reflector {reflector} could not be resolved, the source ports are unchecked: {e}"),
+            )
+        }
+    };
+    let ports = SourcePorts::new(None);
+    let timeout = Duration::from_millis(opts.timeout.into());
+    let mut reflections = Vec::new();
+    for _ in 0..REFLECTIONS {
+        match reflector::reflect(address, ports.take(), timeout) {
+            Ok(reflection) => reflections.push(reflection),
+            Err(e) => {
+                return fail(
+                    ErrorCode::ReflectorFailed,
+                    format!("The reflector {reflector} ({address}) could not be reached, the source ports are unchecked: {e}"),
+                )
+            }
+        }
+    }
+
+    let rewritten: Vec<String> = reflections
+        .iter()
+        .filter(|reflection| !reflection.port_kept())
+        .map(ToString::to_string)
+        .collect();
+    if rewritten.is_empty() {
+        verbose!(
+            format!("The reflector {reflector} saw the source ports as they were bound."),
+            opts.greppable,
+            opts.accessible
+        );
+    } else {
+        fail(
+            ErrorCode::SourcePortsRewritten,
+            format!(
+                "A NAT rewrites the source ports before the reflector {reflector}, --randomize-source-ports is lost past it: the {}",
+                rewritten.join(", the ")
+            ),
+        );
+    }
+}
+
+/// Prints what the calibration measured of `host`.
+fn print_measurements(host: &str, measurements: &Measurements) {
+    let millis = |rtt: Option<Duration>| {
+        rtt.map_or_else(
+            || "-".to_owned(),
+            |rtt| format!("{:.1} ms", rtt.as_secs_f64() * 1e3),
+        )
+    };
+    println!("{host}:");
+    println!(
+        "  round trip: p50 {}, p90 {}, p99 {}, {} of {} probes lost",
+        millis(measurements.rtt_percentile(50)),
+        millis(measurements.rtt_percentile(90)),
+        millis(measurements.rtt_percentile(99)),
+        measurements.lost,
+        measurements.rtts.len() + measurements.lost
+    );
+    println!(
+        "  concurrent connects without errors: {}",
+        measurements.concurrency
+    );
+    if let Some(resolver) = measurements.resolver {
+        println!("  resolver: {}", millis(Some(resolver)));
+    }
+}
+
+/// Sets the file limit given with `--ulimit`, or raises the soft limit up to
+/// the hard one when the batch size doesn't fit in it. Returns the soft limit.
+/// A `--ulimit` above the hard limit falls back to it without the privilege
+/// to raise it.
 #[cfg(unix)]
-fn adjust_ulimit_size(opts: &Opts) -> u64 {
+fn adjust_ulimit_size(opts: &Opts, open: u64, capabilities: &CapabilityReport) -> u64 {
     use rlimit::Resource;
 
     if let Some(limit) = opts.ulimit {
+        let limit = match (
+            Resource::NOFILE.get(),
+            capabilities.check(Feature::RaiseFileLimit),
+        ) {
+            (Ok((_, hard)), Err(missing)) if limit > hard => {
+                warning!(
+                    ErrorCode::UlimitFailed,
+                    format!("--ulimit {limit} is above the hard file limit {hard}, {missing}."),
+                    opts.greppable,
+                    opts.accessible
+                );
+                hard
+            }
+            _ => limit,
+        };
         if Resource::NOFILE.set(limit, limit).is_ok() {
             detail!(
                 format!("Automatically increasing ulimit value to {limit}."),
@@ -241,17 +3621,63 @@ fn adjust_ulimit_size(opts: &Opts) -> u64 {
             );
         } else {
             warning!(
+                ErrorCode::UlimitFailed,
                 "ERROR. Failed to set ulimit value.",
                 opts.greppable,
                 opts.accessible
             );
         }
+    } else if let Ok((soft, hard)) = Resource::NOFILE.get() {
+        if let Some(limit) = raise_target(opts.batch_size.into(), open, soft, hard) {
+            match Resource::NOFILE.set(limit, hard) {
+                Ok(()) => detail!(
+                    format!("Automatically increasing ulimit value to {limit}."),
+                    opts.greppable,
+                    opts.accessible
+                ),
+                Err(e) => warning!(
+                    ErrorCode::UlimitFailed,
+                    format!("Failed to raise the file limit from {soft} to {limit}: {e}"),
+                    opts.greppable,
+                    opts.accessible
+                ),
+            }
+        }
     }
 
     let (soft, _) = Resource::NOFILE.get().unwrap();
     soft
 }
 
+/// Returns the soft file limit to ask for so that `batch_size` sockets fit
+/// next to the `open` descriptors, capped to the `hard` limit. None when the
+/// soft limit is already enough or can't be raised.
+#[cfg(unix)]
+fn raise_target(batch_size: u64, open: u64, soft: u64, hard: u64) -> Option<u64> {
+    let needed = batch_size + open + RESERVED_FILE_DESCRIPTORS;
+    if needed <= soft || hard <= soft {
+        return None;
+    }
+    Some(needed.min(hard))
+}
+
+/// Counts the file descriptors the process already holds, like the standard
+/// streams and the ones of the async runtime.
+#[cfg(unix)]
+fn open_file_descriptors() -> u64 {
+    // Conservative guess where the descriptors can't be listed.
+    const UNKNOWN_OPEN_DESCRIPTORS: u64 = 32;
+
+    ["/proc/self/fd", "/dev/fd"]
+        .iter()
+        .find_map(|dir| std::fs::read_dir(dir).ok())
+        // Listing the directory takes a descriptor of its own.
+        .map_or(UNKNOWN_OPEN_DESCRIPTORS, |entries| {
+            entries.count().saturating_sub(1) as u64
+        })
+}
+
+/// Fits the batch size into the `ulimit` file descriptors left for scanning.
 #[cfg(unix)]
 fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
     use std::convert::TryInto;
@@ -260,7 +3686,7 @@ fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
 
     // Adjust the batch size when the ulimit value is lower than the desired batch size
     if ulimit < batch_size {
-        warning!("File limit is lower than default batch size. Consider upping with --ulimit. May cause harm to sensitive servers",
+        warning!(ErrorCode::FileLimitTooLow, "File limit is lower than default batch size. Consider upping with --ulimit. May cause harm to sensitive servers",
             opts.greppable, opts.accessible
         );
 
@@ -271,20 +3697,27 @@ fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
             // ulimit is smaller than aveage batch size
             // user must have very small ulimit
             // decrease batch size to half of ulimit
-            warning!("Your file limit is very small, which negatively impacts RustScan's speed. Use the Docker image, or up the Ulimit with '--ulimit 5000'. ", opts.greppable, opts.accessible);
+            warning!(ErrorCode::FileLimitTooLow, "Your file limit is very small, which negatively impacts RustScan's speed. Use the Docker image, or up the Ulimit with '--ulimit 5000'. ", opts.greppable, opts.accessible);
             info!("Halving batch_size because ulimit is smaller than average batch size");
             batch_size = ulimit / 2;
         } else if ulimit > DEFAULT_FILE_DESCRIPTORS_LIMIT {
             info!("Batch size is now average batch size");
             batch_size = AVERAGE_BATCH_SIZE.into();
         } else {
-            batch_size = ulimit - 100;
+            batch_size = ulimit - RESERVED_FILE_DESCRIPTORS;
         }
+
+        warning!(
+            ErrorCode::BatchSizeLowered,
+            format!("Lowered the batch size from {} to {batch_size} to fit in the {ulimit} available file descriptors.", opts.batch_size),
+            opts.greppable,
+            opts.accessible
+        );
     }
     // When the ulimit is higher than the batch size let the user know that the
     // batch size can be increased unless they specified the ulimit themselves.
-    else if ulimit + 2 > batch_size && (opts.ulimit.is_none()) {
-        detail!(format!("File limit higher than batch size. Can increase speed by increasing batch size '-b {}'.", ulimit - 100),
+    else if ulimit > batch_size + RESERVED_FILE_DESCRIPTORS && (opts.ulimit.is_none()) {
+        detail!(format!("File limit higher than batch size. Can increase speed by increasing batch size '-b {}'.", ulimit - RESERVED_FILE_DESCRIPTORS),
         opts.greppable, opts.accessible);
     }
 
@@ -294,10 +3727,14 @@ fn infer_batch_size(opts: &Opts, ulimit: u64) -> u16 {
 }
 
 #[cfg(test)]
+// Kept the way the tests from before the lint are written.
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     #[cfg(unix)]
-    use super::{adjust_ulimit_size, infer_batch_size};
+    use super::{adjust_ulimit_size, infer_batch_size, raise_target};
     use super::{print_opening, Opts};
+    #[cfg(unix)]
+    use rustscan::privileges::{self, Host, Platform};
 
     #[test]
     #[cfg(unix)]
@@ -336,7 +3773,8 @@ mod tests {
         let mut opts = Opts::default();
         opts.batch_size = 50_000;
         opts.ulimit = Some(2_000);
-        let batch_size = adjust_ulimit_size(&opts);
+        let capabilities = privileges::detect(&Host, Platform::current());
+        let batch_size = adjust_ulimit_size(&opts, 0, &capabilities);
 
         assert!(batch_size == 2_000);
     }
@@ -353,6 +3791,30 @@ mod tests {
         assert!(batch_size == opts.batch_size);
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn soft_limit_raised_up_to_hard_limit() {
+        // The batch fits next to the open descriptors.
+        assert_eq!(raise_target(4_500, 20, 8_000, 8_000), None);
+        assert_eq!(raise_target(4_500, 20, 1_024, 524_288), Some(4_620));
+        assert_eq!(raise_target(4_500, 20, 1_024, 2_048), Some(2_048));
+        // Nothing to raise to.
+        assert_eq!(raise_target(4_500, 20, 1_024, 1_024), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn batch_size_kept_within_available_descriptors() {
+        let opts = Opts {
+            batch_size: 4_500,
+            ..Default::default()
+        };
+        // A 5_000 soft limit with 600 descriptors already open.
+        let batch_size = infer_batch_size(&opts, 5_000 - 600);
+
+        assert_eq!(batch_size, 4_300);
+    }
+
     #[test]
     fn test_print_opening_no_panic() {
         let mut opts = Opts::default();
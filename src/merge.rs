@@ -0,0 +1,317 @@
+//! Combines the JSON reports of `--shard` runs into one, see `rustscan merge`.
+//!
+//! The reports are merged as JSON documents instead of being read back into
+//! a [`crate::report::ScanReport`], so that nothing a shard reported is lost
+//! on the way. Hosts are matched by address: their ports are unioned and
+//! sorted, their lists are unioned in order, and for anything else the first
+//! report mentioning it wins. The `stats` of the shards are summed up.
+use crate::input::HostOrder;
+use crate::scanner::Shard;
+use serde_json::{Map, Value};
+use std::cmp::Reverse;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A merged report, with the shards which were expected but not given and
+/// the ones given more than once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    pub report: Value,
+    pub missing: Vec<Shard>,
+    pub duplicates: Vec<Shard>,
+}
+
+/// Reads the JSON report at `path`, which has to list its hosts.
+pub fn read(path: &Path) -> Result<Value, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let report: Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+    check(&report)?;
+    Ok(report)
+}
+
+/// Checks that `report` looks like the output of `--format json`.
+pub fn check(report: &Value) -> Result<(), String> {
+    let hosts = report
+        .get("hosts")
+        .and_then(Value::as_array)
+        .ok_or("The report has no hosts, is it the output of --format json?")?;
+    for host in hosts {
+        if host
+            .get("ip")
+            .and_then(Value::as_str)
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .is_none()
+        {
+            return Err(format!("A host of the report has no IP address: {host}"));
+        }
+    }
+    shards(report).map(|_| ())
+}
+
+/// The shards of `report`'s stats, none when it has no stats.
+fn shards(report: &Value) -> Result<Vec<Shard>, String> {
+    let Some(shards) = report.pointer("/stats/shards") else {
+        return Ok(Vec::new());
+    };
+    shards
+        .as_array()
+        .ok_or_else(|| format!("Invalid shards {shards}."))?
+        .iter()
+        .map(|shard| {
+            shard
+                .as_str()
+                .ok_or_else(|| format!("Invalid shard {shard}."))?
+                .parse()
+        })
+        .collect()
+}
+
+/// Merges the checked `reports`, hosts come in the order they are first
+/// seen unless sorted by `order`.
+pub fn merge(reports: Vec<Value>, order: HostOrder) -> Result<Merged, String> {
+    let mut hosts: Vec<Map<String, Value>> = Vec::new();
+    let mut given: Vec<Shard> = Vec::new();
+    let mut sockets = 0;
+    let mut open_ports = 0;
+    let mut has_stats = false;
+
+    for report in reports {
+        check(&report)?;
+        if let Some(stats) = report.get("stats") {
+            has_stats = true;
+            given.extend(shards(&report)?);
+            sockets += stats.get("sockets").and_then(Value::as_u64).unwrap_or(0);
+            open_ports += stats.get("open_ports").and_then(Value::as_u64).unwrap_or(0);
+        }
+        let Value::Object(mut report) = report else {
+            unreachable!("Checked reports are objects.");
+        };
+        let Some(Value::Array(report_hosts)) = report.remove("hosts") else {
+            unreachable!("Checked reports have hosts.");
+        };
+        for host in report_hosts {
+            let Value::Object(host) = host else {
+                continue;
+            };
+            match hosts
+                .iter_mut()
+                .find(|known| known.get("ip") == host.get("ip"))
+            {
+                Some(known) => merge_host(known, host),
+                None => hosts.push(host),
+            }
+        }
+    }
+
+    if let Some(first) = given.first() {
+        if let Some(other) = given.iter().find(|shard| shard.count != first.count) {
+            return Err(format!(
+                "The reports are shards of different splits, {first} and {other}."
+            ));
+        }
+    }
+    let (missing, duplicates) = coverage(&given);
+
+    match order {
+        HostOrder::Input => {}
+        HostOrder::Ip => hosts.sort_by_key(ip),
+        HostOrder::OpenCount => hosts.sort_by_key(|host| Reverse(ports(host).len())),
+    }
+
+    let mut merged = Map::new();
+    merged.insert(
+        "hosts".to_owned(),
+        Value::Array(hosts.into_iter().map(Value::Object).collect()),
+    );
+    if has_stats {
+        given.sort_by_key(|shard| shard.index);
+        given.dedup();
+        merged.insert(
+            "stats".to_owned(),
+            serde_json::json!({
+                "shards": given.iter().map(ToString::to_string).collect::<Vec<String>>(),
+                "sockets": sockets,
+                "open_ports": open_ports,
+            }),
+        );
+    }
+    Ok(Merged {
+        report: Value::Object(merged),
+        missing,
+        duplicates,
+    })
+}
+
+/// The shards of the split which aren't `given`, and the ones given twice.
+fn coverage(given: &[Shard]) -> (Vec<Shard>, Vec<Shard>) {
+    let Some(count) = given.first().map(|shard| shard.count) else {
+        return (Vec::new(), Vec::new());
+    };
+    let mut missing = Vec::new();
+    let mut duplicates = Vec::new();
+    for index in 1..=count {
+        let shard = Shard { index, count };
+        match given.iter().filter(|given| **given == shard).count() {
+            0 => missing.push(shard),
+            1 => {}
+            _ => duplicates.push(shard),
+        }
+    }
+    (missing, duplicates)
+}
+
+/// Folds the record of the same host from another report into `into`.
+fn merge_host(into: &mut Map<String, Value>, host: Map<String, Value>) {
+    for (key, value) in host {
+        let Some(known) = into.get_mut(&key) else {
+            into.insert(key, value);
+            continue;
+        };
+        match (key.as_str(), known, value) {
+            ("scanned", known, Value::Bool(scanned)) => {
+                *known = Value::Bool(known.as_bool() == Some(true) || scanned);
+            }
+            ("ports" | "closed_since", Value::Array(known), Value::Array(ports)) => {
+                known.extend(ports);
+                known.sort_by_key(Value::as_u64);
+                known.dedup();
+            }
+            (_, Value::Array(known), Value::Array(values)) => {
+                for value in values {
+                    if !known.contains(&value) {
+                        known.push(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A port open in a shard isn't closed since the previous results.
+    let open = ports(into);
+    if let Some(Value::Array(closed)) = into.get_mut("closed_since") {
+        closed.retain(|port| !open.contains(port));
+        if closed.is_empty() {
+            into.remove("closed_since");
+        }
+    }
+    if into.get("scanned") == Some(&Value::Bool(true)) {
+        into.remove("skipped_reason");
+    }
+}
+
+fn ip(host: &Map<String, Value>) -> Option<IpAddr> {
+    host.get("ip")?.as_str()?.parse().ok()
+}
+
+fn ports(host: &Map<String, Value>) -> Vec<Value> {
+    host.get("ports")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, merge};
+    use crate::input::HostOrder;
+    use crate::scanner::Shard;
+    use serde_json::json;
+
+    fn shard(index: u32, ports: &[u16], sockets: u64) -> serde_json::Value {
+        json!({
+            "hosts": [
+                {
+                    "ip": "10.0.0.9",
+                    "source": ["10.0.0.0/28"],
+                    "ports": ports,
+                    "scanned": true,
+                },
+                {
+                    "ip": "10.0.0.2",
+                    "hostnames": [format!("shard{index}.internal")],
+                    "source": ["10.0.0.0/28"],
+                    "ports": [],
+                    "scanned": true,
+                },
+            ],
+            "stats": {
+                "shards": [format!("{index}/3")],
+                "sockets": sockets,
+                "open_ports": ports.len(),
+            },
+        })
+    }
+
+    #[test]
+    fn shards_are_merged_per_host() {
+        let merged = merge(
+            vec![
+                shard(2, &[443, 22], 100),
+                shard(1, &[80], 101),
+                shard(3, &[], 99),
+            ],
+            HostOrder::Input,
+        )
+        .unwrap();
+
+        assert!(merged.missing.is_empty() && merged.duplicates.is_empty());
+        let report = merged.report;
+        assert_eq!(report["hosts"].as_array().unwrap().len(), 2);
+        assert_eq!(report["hosts"][0]["ip"], "10.0.0.9");
+        assert_eq!(report["hosts"][0]["ports"], json!([22, 80, 443]));
+        assert_eq!(report["hosts"][0]["source"], json!(["10.0.0.0/28"]));
+        assert_eq!(
+            report["hosts"][1]["hostnames"],
+            json!(["shard2.internal", "shard1.internal", "shard3.internal"])
+        );
+        assert_eq!(
+            report["stats"],
+            json!({"shards": ["1/3", "2/3", "3/3"], "sockets": 300, "open_ports": 3})
+        );
+    }
+
+    #[test]
+    fn hosts_are_sorted_by_order() {
+        let merged = merge(vec![shard(1, &[80], 1)], HostOrder::Ip).unwrap();
+        assert_eq!(merged.report["hosts"][0]["ip"], "10.0.0.2");
+    }
+
+    #[test]
+    fn missing_and_duplicate_shards_are_reported() {
+        let merged = merge(
+            vec![shard(1, &[80], 1), shard(1, &[80], 1)],
+            HostOrder::Input,
+        )
+        .unwrap();
+        assert_eq!(
+            merged.missing,
+            [Shard { index: 2, count: 3 }, Shard { index: 3, count: 3 }]
+        );
+        assert_eq!(merged.duplicates, [Shard { index: 1, count: 3 }]);
+        assert_eq!(merged.report["hosts"][0]["ports"], json!([80]));
+    }
+
+    #[test]
+    fn other_splits_and_documents_are_refused() {
+        let mut other = shard(1, &[], 1);
+        other["stats"]["shards"] = json!(["1/2"]);
+        assert!(merge(vec![shard(1, &[], 1), other], HostOrder::Input).is_err());
+
+        assert!(check(&json!({"open": []})).is_err());
+        assert!(check(&json!({"hosts": [{"ports": [80]}]})).is_err());
+        assert!(check(&json!({"hosts": [], "stats": {"shards": ["0/2"]}})).is_err());
+        assert!(check(&json!({"hosts": []})).is_ok());
+    }
+
+    #[test]
+    fn open_ports_are_not_closed_since() {
+        let first = json!({"hosts": [{"ip": "10.0.0.1", "ports": [], "closed_since": [22, 80]}]});
+        let second = json!({"hosts": [{"ip": "10.0.0.1", "ports": [80]}]});
+
+        let merged = merge(vec![first, second], HostOrder::Input).unwrap();
+        assert_eq!(merged.report["hosts"][0]["closed_since"], json!([22]));
+        assert!(merged.report.get("stats").is_none());
+    }
+}
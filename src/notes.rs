@@ -0,0 +1,139 @@
+//! The notes of `--notes`, attached to the hosts they are about.
+//!
+//! The file maps an IP address or a hostname to a free-text note, as TOML
+//! or, with a `.json` extension, JSON:
+//!
+//! ```toml
+//! "10.0.0.5" = "known jump box"
+//! "nas.corp.example" = "customer-owned NAS"
+//! ```
+//!
+//! A hostname note is attached to every address the hostname resolved to.
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Notes {
+    entries: BTreeMap<String, String>,
+}
+
+impl Notes {
+    /// Reads the notes file at `path`, JSON or TOML depending on its extension.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            Self::from_json(&content)
+        } else {
+            Self::from_toml(&content)
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        toml::from_str(content).map_err(|e| e.to_string())
+    }
+
+    /// The notes of `ip`, the note of the address first, then the ones of
+    /// `hostnames` in order.
+    pub fn for_host(&self, ip: IpAddr, hostnames: &[String]) -> Vec<String> {
+        let mut notes: Vec<String> = Vec::new();
+        let matching = self
+            .entries
+            .iter()
+            .filter(|(key, _)| key.parse::<IpAddr>().is_ok_and(|key| key == ip))
+            .chain(hostnames.iter().flat_map(|hostname| {
+                self.entries
+                    .iter()
+                    .filter(move |(key, _)| same_hostname(key, hostname))
+            }));
+        for (_, note) in matching {
+            if !notes.contains(note) {
+                notes.push(note.clone());
+            }
+        }
+        notes
+    }
+
+    /// The keys which match none of `hosts`, given as an address with its
+    /// hostnames.
+    pub fn unmatched(&self, hosts: &[(IpAddr, &[String])]) -> Vec<&str> {
+        self.entries
+            .keys()
+            .filter(|key| {
+                !hosts
+                    .iter()
+                    .any(|(ip, hostnames)| match key.parse::<IpAddr>() {
+                        Ok(key) => key == *ip,
+                        Err(_) => hostnames
+                            .iter()
+                            .any(|hostname| same_hostname(key, hostname)),
+                    })
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Hostnames are compared case insensitively, without the root dot.
+fn same_hostname(key: &str, hostname: &str) -> bool {
+    key.trim_end_matches('.')
+        .eq_ignore_ascii_case(hostname.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notes;
+    use std::net::IpAddr;
+
+    const NOTES: &str = r#"
+        "10.0.0.5" = "known jump box"
+        "NAS.corp.example." = "customer-owned NAS"
+        "printer.corp.example" = "do not scan on weekdays"
+    "#;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn notes_match_by_ip_and_hostname() {
+        let notes = Notes::from_toml(NOTES).unwrap();
+        let nas = ["nas.corp.example".to_owned()];
+
+        assert_eq!(notes.for_host(ip("10.0.0.5"), &[]), ["known jump box"]);
+        assert_eq!(notes.for_host(ip("10.0.0.9"), &nas), ["customer-owned NAS"]);
+        assert_eq!(
+            notes.for_host(ip("10.0.0.5"), &nas),
+            ["known jump box", "customer-owned NAS"]
+        );
+    }
+
+    #[test]
+    fn unknown_hosts_have_no_notes() {
+        let notes = Notes::from_json(r#"{"10.0.0.5": "known jump box"}"#).unwrap();
+        let hostnames = ["jump.corp.example".to_owned()];
+
+        assert!(notes.for_host(ip("10.0.0.6"), &hostnames).is_empty());
+        assert_eq!(
+            notes.unmatched(&[(ip("10.0.0.6"), &hostnames)]),
+            ["10.0.0.5"]
+        );
+        assert_eq!(
+            Notes::from_toml(NOTES).unwrap().unmatched(&[
+                (ip("10.0.0.5"), &[]),
+                (ip("10.0.0.9"), &["nas.corp.example".to_owned()])
+            ]),
+            ["printer.corp.example"]
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! The webhooks of `--notify`, POSTed a small JSON [`Event`] as the scan
+//! goes.
+//!
+//! A [`Notifier`] follows the [`ScanUpdate`]s of the scanner like the
+//! dashboard does, and its events are sent by a thread of their own so a
+//! slow endpoint never holds the scan up. The queue is bounded: once
+//! [`QUEUE`] events wait, the oldest one makes room for the newest and is
+//! counted as dropped. Failed deliveries aren't reported one by one either,
+//! [`Notifier::finish`] sums them up once the scan is over.
+//!
+//! Only plain `http://` URLs are supported, every event being POSTed over a
+//! connection of its own. The payloads are the serialized [`Event`]s, next
+//! to the `time` they happened at, and their fields never change.
+//!
+//! A run gives its webhooks the events of its sinks through a
+//! [`WebhookSink`], one sink among the other outputs, see [`crate::sink`].
+use crate::errors::ErrorCode;
+use crate::scanner::ScanUpdate;
+use crate::sink::{OutputSink, PortEvent, ScanEnd, ScanStart};
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The most events waiting to be sent.
+pub const QUEUE: usize = 1_000;
+
+/// How long a delivery may take to connect, and then to be answered.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the events still queued at the end of the scan get to be sent.
+const GRACE: Duration = Duration::from_secs(10);
+
+/// How often the updates of the scan are checked for its end.
+const POLL: Duration = Duration::from_millis(50);
+
+/// The events a webhook can be given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    OpenPort,
+    HostComplete,
+    ScanComplete,
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "open-port" => Ok(EventKind::OpenPort),
+            "host-complete" => Ok(EventKind::HostComplete),
+            "scan-complete" => Ok(EventKind::ScanComplete),
+            _ => Err(format!(
+                "Unknown event {input:?}, expected open-port, host-complete or scan-complete."
+            )),
+        }
+    }
+}
+
+/// An `http://` URL events are POSTed to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Url {
+    /// The host and port as written, like `[::1]:8080`.
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for Url {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = input.strip_prefix("http://") else {
+            return Err(format!("Only http:// URLs can be notified, not {input:?}."));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            // The colons of an IPv6 address are left alone.
+            Some(colon) if !authority[colon..].contains(']') => {
+                let port = authority[colon + 1..]
+                    .parse()
+                    .map_err(|_| format!("Invalid port in the URL {input:?}."))?;
+                (&authority[..colon], port)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("The URL {input:?} has no host."));
+        }
+        Ok(Self {
+            authority: authority.to_owned(),
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.path)
+    }
+}
+
+/// A webhook of `--notify`, like `open-port=http://hooks.local/scan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub kind: EventKind,
+    pub url: Url,
+}
+
+/// Parses a `--notify` argument, the event and the URL joined by `=`.
+pub fn parse_hook(input: &str) -> Result<Hook, String> {
+    let Some((kind, url)) = input.split_once('=') else {
+        return Err(
+            "Invalid notification. Correct format: 'event=url'. Example: open-port=http://127.0.0.1:8080/."
+                .to_owned(),
+        );
+    };
+    Ok(Hook {
+        kind: kind.parse()?,
+        url: url.parse()?,
+    })
+}
+
+/// A port found open, sent as soon as it is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpenPort {
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+/// A host whose every port had its first probe, or was skipped. A retry may
+/// still find another port of it open after this.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostComplete {
+    pub ip: IpAddr,
+    /// The open ports of the host, in ascending order.
+    pub open_ports: Vec<u16>,
+    /// How many of its ports were left out because the host was skipped.
+    pub skipped: usize,
+}
+
+/// The end of the scan, once the results are in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanComplete {
+    pub hosts: usize,
+    pub open_ports: usize,
+    pub elapsed_ms: u64,
+    /// The events dropped for newer ones while the endpoints were slow.
+    pub dropped_events: u64,
+}
+
+/// An event, tagged with its kind under `event`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    OpenPort(OpenPort),
+    HostComplete(HostComplete),
+    ScanComplete(ScanComplete),
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::OpenPort(_) => EventKind::OpenPort,
+            Event::HostComplete(_) => EventKind::HostComplete,
+            Event::ScanComplete(_) => EventKind::ScanComplete,
+        }
+    }
+}
+
+/// What is POSTed, an event and when it happened.
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    #[serde(flatten)]
+    pub event: &'a Event,
+    /// RFC 3339, to the second.
+    pub time: String,
+    /// The run the event belongs to, see `--run-id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<&'a str>,
+}
+
+impl Payload<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Events always serialize.")
+    }
+}
+
+/// Turns the updates of a scan into the events they make.
+#[derive(Debug, Default)]
+pub struct Events {
+    hosts: HashMap<IpAddr, Progress>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    left: usize,
+    skipped: usize,
+    open: Vec<u16>,
+}
+
+impl Events {
+    pub fn apply(&mut self, update: &ScanUpdate) -> Vec<Event> {
+        match update {
+            ScanUpdate::Started { hosts, .. } => {
+                let mut events = Vec::new();
+                // A fallback scan adds to the hosts of the first one.
+                for (ip, sockets) in hosts {
+                    let host = self.hosts.entry(*ip).or_default();
+                    host.left += sockets;
+                    if host.left == 0 {
+                        events.extend(self.complete(*ip));
+                    }
+                }
+                events
+            }
+            ScanUpdate::Open(socket) => {
+                let host = self.hosts.entry(socket.ip()).or_default();
+                if let Err(position) = host.open.binary_search(&socket.port()) {
+                    host.open.insert(position, socket.port());
+                }
+                vec![Event::OpenPort(OpenPort {
+                    ip: socket.ip(),
+                    port: socket.port(),
+                })]
+            }
+            ScanUpdate::Probed(socket) | ScanUpdate::Skipped(socket) => {
+                let Some(host) = self.hosts.get_mut(&socket.ip()) else {
+                    return Vec::new();
+                };
+                if matches!(update, ScanUpdate::Skipped(_)) {
+                    host.skipped += 1;
+                }
+                host.left = host.left.saturating_sub(1);
+                if host.left == 0 {
+                    self.complete(socket.ip()).into_iter().collect()
+                } else {
+                    Vec::new()
+                }
+            }
+            ScanUpdate::Forecast(_) | ScanUpdate::Served(..) => Vec::new(),
+        }
+    }
+
+    fn complete(&mut self, ip: IpAddr) -> Option<Event> {
+        let host = self.hosts.get_mut(&ip)?;
+        Some(Event::HostComplete(HostComplete {
+            ip,
+            open_ports: host.open.clone(),
+            skipped: std::mem::take(&mut host.skipped),
+        }))
+    }
+}
+
+/// A payload on its way to a webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Delivery {
+    url: Url,
+    body: String,
+}
+
+/// The bounded queue between the scan and the sending thread.
+#[derive(Debug)]
+struct Queue {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    pending: VecDeque<Delivery>,
+    dropped: u64,
+    /// Until when the sending goes on once the scan is over.
+    deadline: Option<Instant>,
+}
+
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queues `delivery`, dropping the oldest one when the queue is full.
+    fn push(&self, delivery: Delivery) {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.len() >= self.capacity {
+            state.pending.pop_front();
+            state.dropped += 1;
+        }
+        state.pending.push_back(delivery);
+        self.ready.notify_one();
+    }
+
+    /// The next delivery to send, None once the queue is closed and empty or
+    /// its deadline is over, the deliveries left then being returned apart.
+    fn pop(&self) -> Result<Delivery, u64> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(deadline) = state.deadline {
+                if Instant::now() >= deadline {
+                    let unsent = state.pending.len() as u64;
+                    state.pending.clear();
+                    return Err(unsent);
+                }
+            }
+            if let Some(delivery) = state.pending.pop_front() {
+                return Ok(delivery);
+            }
+            if state.deadline.is_some() {
+                return Err(0);
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    fn close(&self, deadline: Instant) {
+        self.state.lock().unwrap().deadline = Some(deadline);
+        self.ready.notify_all();
+    }
+
+    fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/// What became of the events, once the scan is over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub sent: u64,
+    /// Dropped for newer events while the queue was full.
+    pub dropped: u64,
+    /// Still queued when the end of the scan gave them no more time.
+    pub unsent: u64,
+    /// The failed deliveries of every URL, with the last error.
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub url: String,
+    pub count: u64,
+    pub error: String,
+}
+
+impl Summary {
+    /// Whether every event was delivered.
+    pub fn is_clean(&self) -> bool {
+        self.dropped == 0 && self.unsent == 0 && self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} event(s) of --notify were sent", self.sent)?;
+        for failure in &self.failures {
+            write!(
+                f,
+                "\n{} failed for {}: {}",
+                failure.count, failure.url, failure.error
+            )?;
+        }
+        if self.dropped > 0 {
+            write!(
+                f,
+                "\n{} dropped while the endpoints were slow",
+                self.dropped
+            )?;
+        }
+        if self.unsent > 0 {
+            write!(f, "\n{} unsent when the scan ended", self.unsent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends the events of a scan to the webhooks of `--notify`.
+#[derive(Debug)]
+pub struct Notifier {
+    hooks: Arc<[Hook]>,
+    run_id: Option<Arc<str>>,
+    queue: Arc<Queue>,
+    feed: Sender<ScanUpdate>,
+    done: Arc<AtomicBool>,
+    listener: JoinHandle<()>,
+    sender: JoinHandle<Summary>,
+    started: Instant,
+}
+
+impl Notifier {
+    /// A notifier whose events say they belong to `run_id`, when given one.
+    pub fn start(hooks: Vec<Hook>, run_id: Option<String>) -> Self {
+        Self::with_capacity(hooks, run_id, QUEUE)
+    }
+
+    /// A notifier queueing at most `capacity` events.
+    pub fn with_capacity(hooks: Vec<Hook>, run_id: Option<String>, capacity: usize) -> Self {
+        let hooks: Arc<[Hook]> = hooks.into();
+        let run_id: Option<Arc<str>> = run_id.map(Into::into);
+        let queue = Arc::new(Queue::new(capacity));
+        let (feed, updates) = mpsc::channel();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let listener = {
+            let (hooks, queue, done) = (hooks.clone(), queue.clone(), done.clone());
+            let run_id = run_id.clone();
+            thread::spawn(move || listen(&updates, &hooks, run_id.as_deref(), &queue, &done))
+        };
+        let sender = {
+            let queue = queue.clone();
+            thread::spawn(move || send_all(&queue))
+        };
+        Self {
+            hooks,
+            run_id,
+            queue,
+            feed,
+            done,
+            listener,
+            sender,
+            started: Instant::now(),
+        }
+    }
+
+    /// The feed to give the scanner, see [`crate::scanner::Scanner::with_feed`].
+    pub fn feed(&self) -> Sender<ScanUpdate> {
+        self.feed.clone()
+    }
+
+    /// Sends the end of the scan, with its `hosts` and `open_ports`, after
+    /// the rest of its events, and waits a little for the ones still queued.
+    pub fn finish(self, hosts: usize, open_ports: usize) -> Summary {
+        // The scan is over, what it sent only has to be drained.
+        self.done.store(true, Ordering::Relaxed);
+        drop(self.feed);
+        let _ = self.listener.join();
+
+        let complete = ScanComplete {
+            hosts,
+            open_ports,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            dropped_events: self.queue.dropped(),
+        };
+        queue_event(
+            &self.hooks,
+            self.run_id.as_deref(),
+            &self.queue,
+            &Event::ScanComplete(complete),
+        );
+        self.queue.close(Instant::now() + GRACE);
+        let mut summary = self.sender.join().unwrap_or_default();
+        summary.dropped = self.queue.dropped();
+        summary
+    }
+}
+
+/// The webhooks of a [`Notifier`] as an output of the run, given the events
+/// of the sinks instead of the updates of the scanner. Failed deliveries
+/// make it fail once, at the end of the run.
+#[derive(Debug)]
+pub struct WebhookSink(Option<Notifier>);
+
+impl WebhookSink {
+    pub fn new(notifier: Notifier) -> Self {
+        Self(Some(notifier))
+    }
+
+    fn forward(&self, update: ScanUpdate) {
+        if let Some(notifier) = &self.0 {
+            let _ = notifier.feed.send(update);
+        }
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn name(&self) -> String {
+        "webhooks".to_owned()
+    }
+
+    fn code(&self) -> ErrorCode {
+        ErrorCode::NotifyFailed
+    }
+
+    fn started(&mut self, start: &ScanStart) -> anyhow::Result<()> {
+        self.forward(ScanUpdate::Started {
+            hosts: start.hosts.clone(),
+            batch_size: start.batch_size,
+        });
+        Ok(())
+    }
+
+    fn port(&mut self, event: &PortEvent) -> anyhow::Result<()> {
+        self.forward(match event {
+            PortEvent::Open { socket, .. } => ScanUpdate::Open(*socket),
+            PortEvent::Probed(socket) => ScanUpdate::Probed(*socket),
+            PortEvent::Skipped(socket) => ScanUpdate::Skipped(*socket),
+            PortEvent::Served(socket, guess) => ScanUpdate::Served(*socket, guess.clone()),
+        });
+        Ok(())
+    }
+
+    fn finished(&mut self, end: &ScanEnd) -> anyhow::Result<()> {
+        let Some(notifier) = self.0.take() else {
+            return Ok(());
+        };
+        let summary = notifier.finish(end.hosts, end.open_ports);
+        if !summary.is_clean() {
+            anyhow::bail!("{summary}");
+        }
+        Ok(())
+    }
+}
+
+/// Queues `event` for every webhook of its kind.
+fn queue_event(hooks: &[Hook], run_id: Option<&str>, queue: &Queue, event: &Event) {
+    let mut body = None;
+    for hook in hooks.iter().filter(|hook| hook.kind == event.kind()) {
+        let body = body.get_or_insert_with(|| {
+            Payload {
+                event,
+                time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                run_id,
+            }
+            .to_json()
+        });
+        queue.push(Delivery {
+            url: hook.url.clone(),
+            body: body.clone(),
+        });
+    }
+}
+
+fn listen(
+    updates: &Receiver<ScanUpdate>,
+    hooks: &[Hook],
+    run_id: Option<&str>,
+    queue: &Queue,
+    done: &AtomicBool,
+) {
+    let mut events = Events::default();
+    loop {
+        // Checked first, so the updates sent before the end are all seen.
+        let finished = done.load(Ordering::Relaxed);
+        match updates.recv_timeout(POLL) {
+            Ok(update) => {
+                for event in events.apply(&update) {
+                    queue_event(hooks, run_id, queue, &event);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if !finished => {}
+            Err(_) => return,
+        }
+    }
+}
+
+fn send_all(queue: &Queue) -> Summary {
+    let mut summary = Summary::default();
+    let mut failures: Vec<Failure> = Vec::new();
+    loop {
+        let delivery = match queue.pop() {
+            Ok(delivery) => delivery,
+            Err(unsent) => {
+                summary.unsent = unsent;
+                summary.failures = failures;
+                return summary;
+            }
+        };
+        match post(&delivery.url, &delivery.body) {
+            Ok(()) => summary.sent += 1,
+            Err(e) => {
+                let url = delivery.url.to_string();
+                match failures.iter_mut().find(|failure| failure.url == url) {
+                    Some(failure) => {
+                        failure.count += 1;
+                        failure.error = e.to_string();
+                    }
+                    None => failures.push(Failure {
+                        url,
+                        count: 1,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// POSTs `body` to `url`, an answer out of the 2xx range being an error.
+fn post(url: &Url, body: &str) -> io::Result<()> {
+    let mut stream = None;
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "the host has no address");
+    for address in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => error = e,
+        }
+    }
+    let mut stream = stream.ok_or(error)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rustscan/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        url.path,
+        url.authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+    )?;
+    stream.flush()?;
+
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1).map(str::parse::<u16>) {
+        Some(Ok(200..=299)) => Ok(()),
+        Some(Ok(_)) => Err(io::Error::other(format!(
+            "the endpoint answered {}",
+            status.trim()
+        ))),
+        _ => Err(io::Error::other("the endpoint didn't answer HTTP")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_hook, Delivery, Event, EventKind, Events, HostComplete, OpenPort, Payload, Queue,
+        ScanComplete, Url,
+    };
+    use crate::scanner::ScanUpdate;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Instant;
+
+    #[test]
+    fn hooks_are_parsed() {
+        let hook = parse_hook("open-port=http://127.0.0.1:8080/events/open").unwrap();
+        assert_eq!(hook.kind, EventKind::OpenPort);
+        assert_eq!(hook.url.to_string(), "http://127.0.0.1:8080/events/open");
+        assert_eq!((hook.url.host.as_str(), hook.url.port), ("127.0.0.1", 8080));
+
+        let hook = parse_hook("scan-complete=http://[::1]").unwrap();
+        assert_eq!((hook.url.host.as_str(), hook.url.port), ("::1", 80));
+        assert_eq!(hook.url.path, "/");
+
+        assert!(parse_hook("http://127.0.0.1/").is_err());
+        assert!(parse_hook("port-closed=http://127.0.0.1/").is_err());
+        assert!(parse_hook("open-port=https://127.0.0.1/")
+            .unwrap_err()
+            .contains("Only http://"));
+        assert!(parse_hook("open-port=http://127.0.0.1:http/").is_err());
+    }
+
+    #[test]
+    fn updates_make_events() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let idle: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut events = Events::default();
+        let mut seen = Vec::new();
+        for update in [
+            ScanUpdate::Started {
+                hosts: vec![(ip, 2), (idle, 0)],
+                batch_size: 10,
+            },
+            ScanUpdate::Probed(SocketAddr::new(ip, 80)),
+            ScanUpdate::Open(SocketAddr::new(ip, 22)),
+            ScanUpdate::Probed(SocketAddr::new(ip, 22)),
+        ] {
+            seen.extend(events.apply(&update));
+        }
+
+        assert_eq!(
+            seen,
+            [
+                Event::HostComplete(HostComplete {
+                    ip: idle,
+                    open_ports: vec![],
+                    skipped: 0,
+                }),
+                Event::OpenPort(OpenPort { ip, port: 22 }),
+                Event::HostComplete(HostComplete {
+                    ip,
+                    open_ports: vec![22],
+                    skipped: 0,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn payloads_keep_their_schema() {
+        let event = Event::OpenPort(OpenPort {
+            ip: "10.0.0.1".parse().unwrap(),
+            port: 443,
+        });
+        let time = "2024-05-01T10:00:00Z".to_owned();
+        assert_eq!(
+            Payload {
+                event: &event,
+                time: time.clone(),
+                run_id: None,
+            }
+            .to_json(),
+            r#"{"event":"open-port","ip":"10.0.0.1","port":443,"time":"2024-05-01T10:00:00Z"}"#
+        );
+
+        let event = Event::ScanComplete(ScanComplete {
+            hosts: 2,
+            open_ports: 3,
+            elapsed_ms: 1_500,
+            dropped_events: 0,
+        });
+        assert_eq!(
+            Payload {
+                event: &event,
+                time,
+                run_id: Some("acme-0424"),
+            }
+            .to_json(),
+            r#"{"event":"scan-complete","hosts":2,"open_ports":3,"elapsed_ms":1500,"dropped_events":0,"time":"2024-05-01T10:00:00Z","run_id":"acme-0424"}"#
+        );
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let queue = Queue::new(2);
+        let url: Url = "http://127.0.0.1/".parse().unwrap();
+        for body in ["1", "2", "3"] {
+            queue.push(Delivery {
+                url: url.clone(),
+                body: body.to_owned(),
+            });
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop().unwrap().body, "2");
+
+        // Closed, what is left still goes before the deadline.
+        queue.close(Instant::now() + std::time::Duration::from_secs(60));
+        assert_eq!(queue.pop().unwrap().body, "3");
+        assert_eq!(queue.pop(), Err(0));
+
+        queue.push(Delivery {
+            url,
+            body: "4".to_owned(),
+        });
+        queue.close(Instant::now());
+        assert_eq!(queue.pop(), Err(1));
+    }
+}
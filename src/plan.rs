@@ -0,0 +1,615 @@
+//! What a run is going to probe, worked out before any probe, see
+//! [`ScanPlan`].
+//!
+//! The plan is made of the options and the parsed targets only: it opens no
+//! socket, so it answers "what exactly will this command do" for
+//! `--dry-run`, and serialized as JSON it can be reviewed, or approved,
+//! before the scan. The scan is then built from it, so that what was planned
+//! is what gets probed. How much of the batch size fits in the file limit,
+//! and which family of a dual-stack hostname answers, are left to the run.
+use crate::address::{StageCount, Target, Targets, Unresolved};
+use crate::fingerprint::{Fingerprint, ScanConfig};
+use crate::input::{Opts, OrderFileMode, PortRange, ScanOrder};
+use crate::port_strategy::{
+    self, DefaultPorts, OrderFile, PortResolution, PortSources, PortStrategy, Spread,
+};
+use crate::scanner::{Canaries, Scanner};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The hosts and ports of a run, with how they are probed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanPlan {
+    /// The canonical configuration of the run, with its hash, see
+    /// [`crate::fingerprint`].
+    pub fingerprint: Fingerprint,
+    /// The default port set the run fell back on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_ports: Option<DefaultPorts>,
+    /// The addresses to scan, each once, in input order.
+    pub hosts: Vec<PlannedHost>,
+    /// The dual-stack hostnames scanned on the family which answers first,
+    /// not counted in the sockets.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dual_stack: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<Unresolved>,
+    /// How many targets every stage of their filtering removed, down to the
+    /// hosts.
+    pub stages: Vec<StageCount>,
+    /// Probed on every host, whatever its ports.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub canary_ports: Vec<u16>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub canary_first_pass: bool,
+    /// How the random orders keep the ports apart.
+    pub spread: Spread,
+    /// How many sockets the hosts add up to, whatever the tries.
+    pub sockets: u64,
+    /// The hosts without a port left to scan, with where their ports came
+    /// from, the hosts of the same ports together.
+    #[serde(skip)]
+    pub portless: Vec<(Vec<IpAddr>, PortResolution)>,
+    #[serde(skip)]
+    port_sources: PortSources,
+    #[serde(skip)]
+    range: Option<PortRange>,
+    #[serde(skip)]
+    ports: Option<Vec<u16>>,
+    #[serde(skip)]
+    scan_order: ScanOrder,
+    #[serde(skip)]
+    order_file: Option<OrderFile>,
+    #[serde(skip)]
+    order_file_mode: OrderFileMode,
+    #[serde(skip)]
+    exclude_ports: Vec<u16>,
+    #[serde(skip)]
+    timeout: Duration,
+    #[serde(skip)]
+    udp: bool,
+    #[serde(skip)]
+    greppable: bool,
+    #[serde(skip)]
+    accessible: bool,
+}
+
+/// An address of the plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedHost {
+    pub ip: IpAddr,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hostnames: Vec<String>,
+    /// Every input token the address was expanded from.
+    pub sources: Vec<String>,
+    /// The ports of the host, like `80-82,443`, given to it with
+    /// `host=ports` or the ones of the run.
+    pub ports: String,
+    /// How many sockets of the host are probed, after the excluded ports,
+    /// the canaries and the shard.
+    pub sockets: u64,
+}
+
+impl ScanPlan {
+    /// Plans the scan of `targets` with `opts`, the ports in the order of
+    /// `order_file` when there is one.
+    pub fn new(
+        opts: &Opts,
+        targets: &Targets,
+        order_file: Option<OrderFile>,
+        default_ports: Option<DefaultPorts>,
+    ) -> Self {
+        let fingerprint =
+            ScanConfig::new(opts, &scanned_ports(opts), order_file.as_ref()).fingerprint();
+        let mut plan = Self {
+            fingerprint,
+            default_ports,
+            hosts: Vec::with_capacity(targets.hosts.len()),
+            dual_stack: targets
+                .dual_stack
+                .iter()
+                .map(|host| host.hostname.clone())
+                .collect(),
+            unresolved: targets.unresolved.clone(),
+            stages: targets.stages.clone(),
+            canary_ports: opts.canary_ports.clone(),
+            canary_first_pass: opts.canary_first_pass,
+            spread: Spread {
+                window: opts.spread_window.into(),
+                distance: opts.spread_distance,
+            },
+            sockets: 0,
+            portless: Vec::new(),
+            port_sources: opts.port_sources(default_ports),
+            range: opts.range.clone(),
+            ports: opts.ports.clone(),
+            scan_order: opts.scan_order,
+            order_file,
+            order_file_mode: opts.order_file_mode,
+            exclude_ports: opts.excluded_ports(),
+            timeout: Duration::from_millis(opts.timeout.into()),
+            udp: opts.udp,
+            greppable: opts.greppable,
+            accessible: opts.accessible,
+        };
+
+        let ips: Vec<IpAddr> = targets.hosts.iter().map(|target| target.ip).collect();
+        let scanner = plan.scanner(&ips, opts.batch_size, targets.port_overrides());
+        // Without a shard, the hosts of the same ports probe as many of them.
+        let mut counted: HashMap<Option<String>, u64> = HashMap::new();
+        for target in &targets.hosts {
+            let sockets = match opts.shard {
+                Some(_) => scanner.host_ports(target.ip).len() as u64,
+                None => *counted
+                    .entry(target.ports.as_ref().map(ToString::to_string))
+                    .or_insert_with(|| scanner.host_ports(target.ip).len() as u64),
+            };
+            plan.hosts.push(plan.host(target, sockets));
+        }
+        // The hosts of the same ports have the same resolution.
+        let mut portless: Vec<(Option<String>, Vec<IpAddr>, PortResolution)> = Vec::new();
+        let mut resolved: HashMap<Option<String>, bool> = HashMap::new();
+        for target in &targets.hosts {
+            let key = target.ports.as_ref().map(ToString::to_string);
+            let empty =
+                *resolved
+                    .entry(key.clone())
+                    .or_insert_with(|| match plan.explain_ports(target) {
+                        resolution if resolution.is_empty() => {
+                            portless.push((key.clone(), Vec::new(), resolution));
+                            true
+                        }
+                        _ => false,
+                    });
+            if let (true, Some((_, ips, _))) = (
+                empty,
+                portless.iter_mut().find(|(ports, _, _)| *ports == key),
+            ) {
+                ips.push(target.ip);
+            }
+        }
+        plan.portless = portless
+            .into_iter()
+            .map(|(_, ips, resolution)| (ips, resolution))
+            .collect();
+        plan.sockets = plan.hosts.iter().map(|host| host.sockets).sum();
+        plan
+    }
+
+    /// The order of `range`, the ports of the run without one, in the order
+    /// of the order file when there is one, of the scan order otherwise.
+    pub fn port_strategy(&self, range: Option<&PortRange>) -> PortStrategy {
+        let (range, ports) = match range {
+            Some(range) => (Some(range.clone()), None),
+            None => (self.range.clone(), self.ports.clone()),
+        };
+        match &self.order_file {
+            Some(file) => PortStrategy::from_file(&range, ports, file, self.order_file_mode),
+            None => PortStrategy::pick_spread(&range, ports, self.scan_order, self.spread),
+        }
+    }
+
+    /// The ports of the run in scan order, without the excluded ones.
+    pub fn ports(&self) -> Vec<u16> {
+        self.port_strategy(None)
+            .order()
+            .into_iter()
+            .filter(|port| !self.exclude_ports.contains(port))
+            .collect()
+    }
+
+    /// The scanner of `ips` as planned, probing up to `batch_size` sockets at
+    /// a time, `port_overrides` giving hosts ports of their own.
+    pub fn scanner(
+        &self,
+        ips: &[IpAddr],
+        batch_size: u16,
+        port_overrides: HashMap<IpAddr, PortRange>,
+    ) -> Scanner {
+        let config = &self.fingerprint.config;
+        let port_overrides = port_overrides
+            .into_iter()
+            .map(|(ip, range)| (ip, self.port_strategy(Some(&range))))
+            .collect();
+        let scanner = Scanner::new(
+            ips,
+            batch_size,
+            self.timeout,
+            config.tries,
+            self.greppable,
+            self.port_strategy(None),
+            self.accessible,
+            self.exclude_ports.clone(),
+            self.udp,
+        )
+        .with_port_overrides(port_overrides);
+        let scanner = if self.canary_ports.is_empty() {
+            scanner
+        } else {
+            scanner.with_canaries(Canaries {
+                ports: self.canary_ports.clone(),
+                first_pass: self.canary_first_pass,
+            })
+        };
+        match config.shard {
+            Some(shard) => scanner.with_shard(shard, config.seed.unwrap_or(0)),
+            None => scanner,
+        }
+    }
+
+    /// Where the ports of `target` come from, every source of the run
+    /// giving or excluding some.
+    pub fn explain_ports(&self, target: &Target) -> PortResolution {
+        port_strategy::resolve(&self.port_sources, target.ports.as_ref())
+    }
+
+    /// Whether no target is left to scan.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.dual_stack.is_empty()
+    }
+
+    /// The targets left after every stage, like `3 parsed (1 invalid) -> 2
+    /// resolved -> 2 deduped`.
+    pub fn funnel(&self) -> String {
+        let stages: Vec<String> = self.stages.iter().map(ToString::to_string).collect();
+        stages.join(" -> ")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Scan plans always serialize.")
+    }
+
+    fn host(&self, target: &Target, sockets: u64) -> PlannedHost {
+        PlannedHost {
+            ip: target.ip,
+            hostnames: target.hostnames.clone(),
+            sources: target.sources.clone(),
+            ports: match &target.ports {
+                Some(range) => range.to_string(),
+                None => self.fingerprint.config.ports.clone(),
+            },
+            sockets,
+        }
+    }
+}
+
+/// The ports given to the run, as ranges.
+pub fn scanned_ports(opts: &Opts) -> PortRange {
+    match &opts.ports {
+        Some(ports) => PortRange::from_ports(ports),
+        None => opts
+            .range
+            .clone()
+            .unwrap_or(PortRange { ranges: Vec::new() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scanned_ports, PlannedHost, ScanPlan};
+    use crate::address::{
+        parse_targets_with_resolver, HostResolver, ResolutionError, Stage, Targets,
+    };
+    use crate::input::{FamilyMode, Opts, PortRange, ScanOrder};
+    use crate::port_strategy::{DefaultPorts, OrderFile, PortSource};
+    use crate::previous::PreviousResults;
+    use crate::scanner::Shard;
+    use crate::scope::Scope;
+    use async_std::task::block_on;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    /// Resolves hostnames from a fixed table, the other ones don't exist.
+    struct Table(HashMap<&'static str, Vec<IpAddr>>);
+
+    impl HostResolver for Table {
+        fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+            self.0.get(host).cloned().ok_or(ResolutionError::NotFound)
+        }
+    }
+
+    fn table() -> Table {
+        let mut hosts = HashMap::new();
+        hosts.insert("app.test", vec!["10.0.0.1".parse().unwrap()]);
+        hosts.insert(
+            "dual.test",
+            vec!["10.0.0.9".parse().unwrap(), "2001:db8::9".parse().unwrap()],
+        );
+        Table(hosts)
+    }
+
+    fn opts(addresses: &[&str], ports: &[u16]) -> Opts {
+        Opts {
+            addresses: addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            ports: Some(ports.to_vec()),
+            batch_size: 100,
+            timeout: 500,
+            tries: 1,
+            ..Opts::default()
+        }
+    }
+
+    fn plan(opts: &Opts) -> ScanPlan {
+        let targets = parse_targets_with_resolver(opts, &table());
+        ScanPlan::new(opts, &targets, None, None)
+    }
+
+    fn sockets(plan: &ScanPlan) -> Vec<(String, u64)> {
+        plan.hosts
+            .iter()
+            .map(|host| (host.ip.to_string(), host.sockets))
+            .collect()
+    }
+
+    #[test]
+    fn targets_are_planned_once_with_their_provenance() {
+        let plan = plan(&opts(&["10.0.0.0/31", "10.0.0.1", "app.test"], &[22, 80]));
+        assert_eq!(
+            plan.hosts,
+            [
+                PlannedHost {
+                    ip: "10.0.0.0".parse().unwrap(),
+                    hostnames: vec![],
+                    sources: vec!["10.0.0.0/31".to_owned()],
+                    ports: "22,80".to_owned(),
+                    sockets: 2,
+                },
+                PlannedHost {
+                    ip: "10.0.0.1".parse().unwrap(),
+                    hostnames: vec!["app.test".to_owned()],
+                    sources: vec![
+                        "10.0.0.0/31".to_owned(),
+                        "10.0.0.1".to_owned(),
+                        "app.test".to_owned()
+                    ],
+                    ports: "22,80".to_owned(),
+                    sockets: 2,
+                },
+            ]
+        );
+        assert_eq!(plan.sockets, 4);
+    }
+
+    #[test]
+    fn hosts_with_ports_of_their_own_keep_them() {
+        let opts = Opts {
+            ports: None,
+            range: Some(PortRange {
+                ranges: vec![(1, 100)],
+            }),
+            exclude_ports: Some(vec![23]),
+            ..opts(&["10.0.0.1=22-23;10.0.0.2"], &[])
+        };
+        let plan = plan(&opts);
+        assert_eq!(plan.hosts[0].ports, "22-23");
+        assert_eq!(plan.hosts[1].ports, "1-100");
+        // The excluded ports are left out of the overrides too.
+        assert_eq!(
+            sockets(&plan),
+            [("10.0.0.1".to_owned(), 1), ("10.0.0.2".to_owned(), 99)]
+        );
+        assert_eq!(plan.sockets, 100);
+    }
+
+    #[test]
+    fn hosts_left_without_ports_are_kept_apart() {
+        let opts = Opts {
+            exclude_ports: Some(vec![80, 443]),
+            ..opts(&["10.0.0.1=22,80;10.0.0.2;10.0.0.3;10.0.0.4=443"], &[80])
+        };
+        let plan = plan(&opts);
+        let portless: Vec<(Vec<String>, Option<PortSource>)> = plan
+            .portless
+            .iter()
+            .map(|(ips, resolution)| {
+                (
+                    ips.iter().map(ToString::to_string).collect(),
+                    resolution.emptied_by(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            portless,
+            [
+                (
+                    vec!["10.0.0.2".to_owned(), "10.0.0.3".to_owned()],
+                    Some(PortSource::ExcludePorts)
+                ),
+                (vec!["10.0.0.4".to_owned()], Some(PortSource::ExcludePorts)),
+            ]
+        );
+        // What the explanation tells is what gets scanned.
+        let targets = parse_targets_with_resolver(&opts, &table());
+        for target in &targets.hosts {
+            let resolution = plan.explain_ports(target);
+            let planned = plan.hosts.iter().find(|host| host.ip == target.ip).unwrap();
+            assert_eq!(resolution.ports.len() as u64, planned.sockets);
+        }
+        assert_eq!(plan.explain_ports(&targets.hosts[0]).ports, [22]);
+    }
+
+    #[test]
+    fn canaries_are_probed_on_every_host() {
+        let opts = Opts {
+            canary_ports: vec![3389, 80],
+            ..opts(&["10.0.0.1=443", "10.0.0.2"], &[80])
+        };
+        let plan = plan(&opts);
+        // 80 is probed once, as a port and a canary.
+        assert_eq!(
+            sockets(&plan),
+            [("10.0.0.1".to_owned(), 3), ("10.0.0.2".to_owned(), 2)]
+        );
+        assert!(!plan.to_json().contains("canary_first_pass"));
+    }
+
+    #[test]
+    fn shards_split_the_sockets() {
+        let whole = opts(&["10.0.0.0/30"], &(1..=500).collect::<Vec<u16>>());
+        assert_eq!(plan(&whole).sockets, 2_000);
+        let shares: Vec<u64> = (1..=3)
+            .map(|index| {
+                let sharded = Opts {
+                    shard: Some(Shard { index, count: 3 }),
+                    ..whole.clone()
+                };
+                let plan = plan(&sharded);
+                assert!(plan.hosts.iter().all(|host| host.sockets < 500));
+                plan.sockets
+            })
+            .collect();
+        assert_eq!(shares.iter().sum::<u64>(), 2_000);
+    }
+
+    #[test]
+    fn unresolved_and_dual_stack_hosts_are_not_counted() {
+        let opts = Opts {
+            prefer_family: FamilyMode::Auto,
+            ..opts(&["dual.test", "missing.test", "10.0.0.1"], &[80])
+        };
+        let plan = plan(&opts);
+        assert_eq!(sockets(&plan), [("10.0.0.1".to_owned(), 1)]);
+        assert_eq!(plan.dual_stack, ["dual.test"]);
+        assert_eq!(plan.unresolved.len(), 1);
+        assert_eq!(plan.unresolved[0].name, "missing.test");
+    }
+
+    #[test]
+    fn the_scanner_probes_what_was_planned() {
+        let opts = Opts {
+            exclude_ports: Some(vec![8080]),
+            scan_order: ScanOrder::Serial,
+            ..opts(&["10.0.0.1=22", "10.0.0.0/30"], &[443, 80, 8080])
+        };
+        let targets = parse_targets_with_resolver(&opts, &table());
+        let plan = ScanPlan::new(&opts, &targets, None, None);
+        assert_eq!(plan.ports(), [443, 80]);
+        let scanner = plan.scanner(&targets.ips(), 10, targets.port_overrides());
+        assert_eq!(scanner.sockets(), plan.sockets);
+        assert_eq!(scanner.host_ports("10.0.0.1".parse().unwrap()), [22]);
+
+        let mut ordered = opts.clone();
+        ordered.order_file = Some("ports.txt".into());
+        let file = OrderFile::parse("80\n8080\n").unwrap();
+        let plan = ScanPlan::new(&ordered, &targets, Some(file), None);
+        assert_eq!(&plan.ports()[..1], [80]);
+        assert_eq!(plan.fingerprint.config.order, "order-file-literal");
+    }
+
+    #[test]
+    fn plans_serialize_for_review() {
+        let udp = Opts {
+            udp: true,
+            ..opts(&["10.0.0.1", "app.test"], &[53])
+        };
+        let targets = parse_targets_with_resolver(&udp, &table());
+        let reviewed = ScanPlan::new(&udp, &targets, None, Some(DefaultPorts::Udp));
+        let json: serde_json::Value = serde_json::from_str(&reviewed.to_json()).unwrap();
+        assert_eq!(json["fingerprint"]["config"]["technique"], "udp");
+        assert_eq!(json["fingerprint"]["hash"], reviewed.fingerprint.hash);
+        assert_eq!(
+            json["hosts"][0]["hostnames"],
+            serde_json::json!(["app.test"])
+        );
+        assert_eq!(json["hosts"][0]["sockets"], 1);
+        assert_eq!(json["sockets"], 1);
+        assert!(json.get("dual_stack").is_none());
+        assert!(json.get("unresolved").is_none());
+        assert!(json.get("default_ports").is_some());
+        // Options the plan is built from stay out of it.
+        assert!(json.get("greppable").is_none());
+
+        // The same scan given differently is the same plan.
+        let again = Opts {
+            udp: true,
+            ..opts(&["app.test", "10.0.0.1"], &[53, 53])
+        };
+        assert_eq!(plan(&again).fingerprint, reviewed.fingerprint);
+    }
+
+    /// The left and removed counts of every stage.
+    fn funnel(targets: &Targets) -> Vec<(Stage, usize, usize)> {
+        targets
+            .stages
+            .iter()
+            .map(|count| (count.stage, count.left, count.removed))
+            .collect()
+    }
+
+    #[test]
+    fn every_stage_counts_what_it_removed() {
+        // Nothing valid.
+        let targets = parse_targets_with_resolver(&opts(&["10.0.0.1=x"], &[80]), &table());
+        assert_eq!(
+            funnel(&targets),
+            [
+                (Stage::Parsed, 0, 1),
+                (Stage::Resolved, 0, 0),
+                (Stage::Deduped, 0, 0)
+            ]
+        );
+        let plan = ScanPlan::new(&opts(&[], &[80]), &targets, None, None);
+        assert!(plan.is_empty());
+        assert_eq!(
+            plan.funnel(),
+            "0 parsed (1 invalid) -> 0 resolved -> 0 deduped"
+        );
+        // Its scanner probes nothing, without panicking.
+        let outcome = block_on(plan.scanner(&[], 10, HashMap::new()).scan());
+        assert!(outcome.open.is_empty());
+
+        // Nothing resolves.
+        let targets = parse_targets_with_resolver(&opts(&["missing.test"], &[80]), &table());
+        assert_eq!(funnel(&targets)[1], (Stage::Resolved, 0, 1));
+
+        // Duplicates are removed, never everything.
+        let opts = opts(&["10.0.0.0/31", "10.0.0.1", "app.test"], &[80]);
+        let targets = parse_targets_with_resolver(&opts, &table());
+        assert_eq!(
+            funnel(&targets),
+            [
+                (Stage::Parsed, 3, 0),
+                (Stage::Resolved, 4, 0),
+                (Stage::Deduped, 2, 2)
+            ]
+        );
+
+        // None was open before.
+        let mut previously = parse_targets_with_resolver(&opts, &table());
+        let previous = PreviousResults::from_json(r#"{"hosts": []}"#).unwrap();
+        previously.only_previously_open(&previous, "previous.json", true);
+        assert_eq!(funnel(&previously)[3], (Stage::PreviouslyOpen, 0, 2));
+
+        // None is in scope.
+        let mut scoped = parse_targets_with_resolver(&opts, &table());
+        scoped.retain_in_scope(&Scope::parse("10.0.0.1\n").unwrap());
+        assert_eq!(funnel(&scoped)[3], (Stage::InScope, 1, 1));
+        scoped.retain_in_scope(&Scope::parse("192.168.0.0/24\n").unwrap());
+        assert_eq!(funnel(&scoped)[4], (Stage::InScope, 0, 1));
+        let plan = ScanPlan::new(&opts, &scoped, None, None);
+        assert!(plan.is_empty());
+        assert!(plan.funnel().ends_with("-> 0 in scope (1 out of scope)"));
+    }
+
+    #[test]
+    fn scanned_ports_come_from_ports_or_range() {
+        assert_eq!(
+            scanned_ports(&opts(&[], &[443, 80, 81])).to_string(),
+            "80-81,443"
+        );
+        let ranged = Opts {
+            ports: None,
+            range: Some(PortRange {
+                ranges: vec![(1, 1024)],
+            }),
+            ..Opts::default()
+        };
+        assert_eq!(scanned_ports(&ranged).to_string(), "1-1024");
+        assert_eq!(scanned_ports(&Opts::default()).to_string(), "");
+    }
+}
@@ -0,0 +1,135 @@
+//! The ports of a run which was given neither `--ports` nor `--range`.
+//!
+//! Every protocol has its own default: a TCP scan covers every port, while
+//! most UDP ports never answer, so a UDP scan sticks to the ports services
+//! are found on. `--top` narrows either down to the most frequently open
+//! ones, the `[ports]` table of the configuration file standing in for the
+//! TCP ones when there is one.
+use super::popularity::{MOST_POPULAR, MOST_POPULAR_UDP, POPULAR, POPULAR_UDP};
+use crate::input::PortRange;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// The default port sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DefaultPorts {
+    /// Every TCP port.
+    AllTcp,
+    /// The 100 most frequently open TCP ports.
+    TopTcp,
+    /// The `[ports]` table of the configuration file.
+    ConfigTop,
+    /// The 100 most frequently open UDP ports.
+    Udp,
+    /// The 20 most frequently open UDP ports.
+    TopUdp,
+}
+
+impl DefaultPorts {
+    /// The set of a UDP run or a TCP one, with or without `--top`.
+    /// `configured` is whether the configuration file lists top ports.
+    pub fn select(udp: bool, top: bool, configured: bool) -> Self {
+        match (udp, top) {
+            (false, false) => DefaultPorts::AllTcp,
+            (false, true) if configured => DefaultPorts::ConfigTop,
+            (false, true) => DefaultPorts::TopTcp,
+            (true, false) => DefaultPorts::Udp,
+            (true, true) => DefaultPorts::TopUdp,
+        }
+    }
+
+    /// Whether the set is the top ports of `--top`.
+    pub fn is_top(self) -> bool {
+        matches!(
+            self,
+            DefaultPorts::TopTcp | DefaultPorts::ConfigTop | DefaultPorts::TopUdp
+        )
+    }
+}
+
+impl fmt::Display for DefaultPorts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DefaultPorts::AllTcp => "every TCP port",
+            DefaultPorts::TopTcp => "the 100 most frequently open TCP ports",
+            DefaultPorts::ConfigTop => "the top ports of the configuration file",
+            DefaultPorts::Udp => "the 100 most frequently open UDP ports",
+            DefaultPorts::TopUdp => "the 20 most frequently open UDP ports",
+        })
+    }
+}
+
+/// Picks the default set of a run and its ports, `configured` being the top
+/// ports of the configuration file.
+pub fn default_ports(
+    udp: bool,
+    top: bool,
+    configured: Option<&[u16]>,
+) -> (DefaultPorts, PortRange) {
+    let set = DefaultPorts::select(udp, top, configured.is_some());
+    let range = match set {
+        DefaultPorts::AllTcp => PortRange {
+            ranges: vec![(1, u16::MAX)],
+        },
+        DefaultPorts::TopTcp => PortRange::from_ports(&[&MOST_POPULAR[..], &POPULAR[..]].concat()),
+        DefaultPorts::ConfigTop => PortRange::from_ports(configured.unwrap_or_default()),
+        DefaultPorts::Udp => {
+            PortRange::from_ports(&[&MOST_POPULAR_UDP[..], &POPULAR_UDP[..]].concat())
+        }
+        DefaultPorts::TopUdp => PortRange::from_ports(&MOST_POPULAR_UDP),
+    };
+    (set, range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_ports, DefaultPorts};
+
+    #[test]
+    fn tcp_defaults() {
+        let (set, range) = default_ports(false, false, None);
+        assert_eq!(set, DefaultPorts::AllTcp);
+        assert_eq!(range.to_string(), "1-65535");
+
+        let (set, range) = default_ports(false, true, None);
+        assert_eq!(set, DefaultPorts::TopTcp);
+        assert_eq!(
+            range.to_string(),
+            "7,9,13,21-23,25-26,37,53,79-81,88,106,110-111,113,119,135,139,143-144,179,199,\
+             389,427,443-445,465,513-515,543-544,548,554,587,631,646,873,990,993,995,\
+             1025-1029,1110,1433,1720,1723,1755,1900,2000-2001,2049,2121,2717,3000,3128,\
+             3306,3389,3986,4899,5000,5009,5051,5060,5101,5190,5357,5432,5631,5666,5800,5900,\
+             6000-6001,6646,7070,8000,8008-8009,8080-8081,8443,8888,9100,9999-10000,32768,\
+             49152-49157"
+        );
+
+        // The configuration file has the last word on the top TCP ports.
+        let (set, range) = default_ports(false, true, Some(&[8080, 22, 80, 81]));
+        assert_eq!(set, DefaultPorts::ConfigTop);
+        assert_eq!(range.to_string(), "22,80-81,8080");
+    }
+
+    #[test]
+    fn udp_defaults() {
+        let (set, range) = default_ports(true, false, None);
+        assert_eq!(set, DefaultPorts::Udp);
+        assert_eq!(
+            range.to_string(),
+            "7,9,17,19,49,53,67-69,80,88,111,120,123,135-139,158,161-162,177,427,443,445,\
+             497,500,514-515,518,520,593,623,626,631,996-999,1022-1023,1025-1030,1433-1434,\
+             1645-1646,1701,1718-1719,1812-1813,1900,2000,2048-2049,2222-2223,3283,3456,\
+             3703,4444,4500,5000,5060,5353,5632,9200,10000,17185,20031,30718,31337,\
+             32768-32769,32771,32815,33281,49152-49154,49156,49181-49182,49185-49186,49188,\
+             49190-49194,49200-49201,65024"
+        );
+
+        // TCP top ports are nothing to go by for UDP.
+        let (set, range) = default_ports(true, true, Some(&[22, 80]));
+        assert_eq!(set, DefaultPorts::TopUdp);
+        assert_eq!(
+            range.to_string(),
+            "53,67-69,123,135,137-139,161-162,445,500,514,520,631,1434,1900,4500,49152"
+        );
+    }
+}
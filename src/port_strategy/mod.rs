@@ -1,9 +1,18 @@
 //! Provides a means to hold configuration options specifically for port scanning.
+mod defaults;
+mod order_file;
+mod popularity;
 mod range_iterator;
-use crate::input::{PortRange, ScanOrder};
+mod resolution;
+mod spread;
+use crate::input::{OrderFileMode, PortRange, ScanOrder};
+pub use defaults::{default_ports, DefaultPorts};
+pub use order_file::{FileOrder, OrderFile};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use range_iterator::RangeIterator;
+pub use resolution::{resolve, Contribution, Effect, PortResolution, PortSource, PortSources};
+pub use spread::{extend_spread, is_spread, spread, Spread};
 
 /// Represents options of port scanning.
 ///
@@ -14,42 +23,86 @@ pub enum PortStrategy {
     Manual(Vec<u16>),
     Serial(SerialRange),
     Random(RandomRange),
+    Smart(SmartRange),
+    FileOrder(FileOrder),
 }
 
 impl PortStrategy {
     pub fn pick(range: &Option<PortRange>, ports: Option<Vec<u16>>, order: ScanOrder) -> Self {
+        Self::pick_spread(range, ports, order, Spread::default())
+    }
+
+    /// Like [`PortStrategy::pick`], the random orders spread by `spread`.
+    pub fn pick_spread(
+        range: &Option<PortRange>,
+        ports: Option<Vec<u16>>,
+        order: ScanOrder,
+        spread: Spread,
+    ) -> Self {
         match order {
-            ScanOrder::Serial if ports.is_none() => {
-                let range = range.as_ref().unwrap();
-                PortStrategy::Serial(SerialRange {
-                    ranges: range.ranges.clone(),
-                })
-            }
-            ScanOrder::Random if ports.is_none() => {
-                let range = range.as_ref().unwrap();
-                PortStrategy::Random(RandomRange {
-                    ranges: range.ranges.clone(),
-                })
-            }
+            ScanOrder::Serial if ports.is_none() => PortStrategy::Serial(SerialRange {
+                ranges: ranges_of(range),
+            }),
+            ScanOrder::Random if ports.is_none() => PortStrategy::Random(RandomRange {
+                ranges: ranges_of(range),
+                spread,
+            }),
+            ScanOrder::Smart if ports.is_none() => PortStrategy::Smart(SmartRange {
+                ranges: ranges_of(range),
+                spread,
+            }),
             ScanOrder::Serial => PortStrategy::Manual(ports.unwrap()),
             ScanOrder::Random => {
                 let mut rng = thread_rng();
                 let mut ports = ports.unwrap();
                 ports.shuffle(&mut rng);
-                PortStrategy::Manual(ports)
+                PortStrategy::Manual(spread::spread(ports, spread))
+            }
+            ScanOrder::Smart => {
+                let mut ports = ports.unwrap();
+                ports.shuffle(&mut thread_rng());
+                PortStrategy::Manual(by_popularity(ports, spread))
             }
         }
     }
 
+    /// The ports of `range`, or the `ports`, in the order of `file`.
+    pub fn from_file(
+        range: &Option<PortRange>,
+        ports: Option<Vec<u16>>,
+        file: &OrderFile,
+        mode: OrderFileMode,
+    ) -> Self {
+        let requested = match ports {
+            Some(ports) => ports,
+            None => SerialRange {
+                ranges: ranges_of(range),
+            }
+            .generate(),
+        };
+        PortStrategy::FileOrder(FileOrder::new(file, &requested, mode))
+    }
+
     pub fn order(&self) -> Vec<u16> {
         match self {
             PortStrategy::Manual(ports) => ports.clone(),
             PortStrategy::Serial(range) => range.generate(),
             PortStrategy::Random(range) => range.generate(),
+            PortStrategy::Smart(range) => range.generate(),
+            PortStrategy::FileOrder(order) => order.generate(),
         }
     }
 }
 
+/// The ranges of `range`, none without one: a strategy without ports is
+/// empty rather than an error.
+fn ranges_of(range: &Option<PortRange>) -> Vec<(u16, u16)> {
+    range
+        .as_ref()
+        .map(|range| range.ranges.clone())
+        .unwrap_or_default()
+}
+
 /// Trait associated with a port strategy. Each PortStrategy must be able
 /// to generate an order for future port scanning.
 trait RangeOrder {
@@ -73,10 +126,12 @@ impl RangeOrder for SerialRange {
 }
 
 /// As the name implies RandomRange will always generate a vector with
-/// a random order. This vector is built following the LCG algorithm.
+/// a random order. This vector is built following the LCG algorithm, then
+/// spread, see [`Spread`].
 #[derive(Debug)]
 pub struct RandomRange {
     ranges: Vec<(u16, u16)>,
+    spread: Spread,
 }
 
 impl RangeOrder for RandomRange {
@@ -90,25 +145,83 @@ impl RangeOrder for RandomRange {
     // port numbers close to each other are pretty slim due to the way the
     // algorithm works.
     fn generate(&self) -> Vec<u16> {
-        let mut all_ports: Vec<u16> = self
+        let iterators: Vec<RangeIterator> = self
             .ranges
             .iter()
-            .flat_map(|&(start, end)| {
-                // 使用 RangeIterator 来生成每个范围内的随机顺序端口
-                RangeIterator::new(start.into(), end.into()).collect::<Vec<u16>>()
-            })
+            .map(|&(start, end)| RangeIterator::new(start.into(), end.into()))
             .collect();
+        let mut all_ports: Vec<u16> =
+            Vec::with_capacity(iterators.iter().map(ExactSizeIterator::len).sum());
+        // 使用 RangeIterator 来生成每个范围内的随机顺序端口
+        for range in iterators {
+            all_ports.extend(range);
+        }
 
         all_ports.shuffle(&mut thread_rng());
 
-        all_ports
+        spread::spread(all_ports, self.spread)
     }
 }
 
+/// SmartRange generates a vector with the most popular ports first. The
+/// ports of every popularity tier are in random order, spread across the
+/// tiers.
+#[derive(Debug)]
+pub struct SmartRange {
+    ranges: Vec<(u16, u16)>,
+    spread: Spread,
+}
+
+impl RangeOrder for SmartRange {
+    fn generate(&self) -> Vec<u16> {
+        let random = RandomRange {
+            ranges: self.ranges.clone(),
+            spread: Spread {
+                window: 0,
+                ..self.spread
+            },
+        };
+        by_popularity(random.generate(), self.spread)
+    }
+}
+
+/// Moves the ports to the front of `ports` by popularity tier, keeping their
+/// order within each tier as long as they stay spread.
+fn by_popularity(ports: Vec<u16>, spread: Spread) -> Vec<u16> {
+    let mut tiers: Vec<Vec<u16>> = vec![Vec::new(); popularity::TIERS];
+    let mut ordered = Vec::with_capacity(ports.len());
+    for port in ports {
+        tiers[popularity::tier(port)].push(port);
+    }
+    for tier in tiers {
+        extend_spread(&mut ordered, tier, spread);
+    }
+    ordered
+}
+
 #[cfg(test)]
+#[allow(clippy::useless_conversion)]
 mod tests {
-    use super::PortStrategy;
-    use crate::input::{PortRange, ScanOrder};
+    use super::{popularity, OrderFile, PortStrategy};
+    use crate::input::{OrderFileMode, PortRange, ScanOrder};
+
+    #[test]
+    fn empty_inputs_are_empty_strategies() {
+        let empty = PortRange { ranges: Vec::new() };
+        let orders = [ScanOrder::Serial, ScanOrder::Random, ScanOrder::Smart];
+        for order in orders.iter().copied() {
+            assert!(PortStrategy::pick(&None, None, order).order().is_empty());
+            assert!(PortStrategy::pick(&Some(empty.clone()), None, order)
+                .order()
+                .is_empty());
+            assert!(PortStrategy::pick(&None, Some(Vec::new()), order)
+                .order()
+                .is_empty());
+        }
+        let file = OrderFile::parse("80\n").unwrap();
+        let ordered = PortStrategy::from_file(&None, None, &file, OrderFileMode::Literal);
+        assert!(ordered.order().is_empty());
+    }
 
     #[test]
     fn serial_strategy_with_range() {
@@ -151,4 +264,60 @@ mod tests {
         result.sort_unstable();
         assert_eq!(expected_range, result);
     }
+
+    fn assert_tiers_in_order(ports: &[u16]) {
+        let tiers: Vec<usize> = ports.iter().map(|&port| popularity::tier(port)).collect();
+        assert!(
+            tiers.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{:?}",
+            tiers
+        );
+    }
+
+    #[test]
+    fn smart_strategy_with_range() {
+        let range = PortRange {
+            ranges: vec![(1, 10_000), (30_000, 40_000)],
+        };
+        let strategy = PortStrategy::pick(&Some(range), None, ScanOrder::Smart);
+        let mut result = strategy.order();
+
+        assert_tiers_in_order(&result);
+        // The 20 most popular ports all fall in the first range.
+        assert_eq!(popularity::tier(result[0]), 0);
+        assert_eq!(popularity::tier(result[19]), 0);
+        assert_eq!(popularity::tier(result[20]), 1);
+
+        result.sort_unstable();
+        let expected: Vec<u16> = (1..=10_000).chain(30_000..=40_000).collect();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn smart_strategy_randomizes_within_tiers() {
+        let range = PortRange {
+            ranges: vec![(1, 65_535)],
+        };
+        let first = PortStrategy::pick(&Some(range.clone()), None, ScanOrder::Smart).order();
+        let second = PortStrategy::pick(&Some(range), None, ScanOrder::Smart).order();
+
+        assert_ne!(first, second);
+        assert_tiers_in_order(&first);
+        assert_eq!(first.len(), 65_535);
+    }
+
+    #[test]
+    fn smart_strategy_with_ports() {
+        let ports = vec![31337, 5432, 80, 12345, 443, 22];
+        let strategy = PortStrategy::pick(&None, Some(ports.clone()), ScanOrder::Smart);
+        let mut result = strategy.order();
+
+        assert_tiers_in_order(&result);
+        assert_eq!(popularity::tier(result[3]), 1);
+
+        result.sort_unstable();
+        let mut expected = ports;
+        expected.sort_unstable();
+        assert_eq!(expected, result);
+    }
 }
@@ -0,0 +1,197 @@
+//! Port orders of `--order-file`, for empirical port-probability data of
+//! one's own.
+//!
+//! The file lists ports in the order they should be probed, one per line,
+//! optionally followed by a weight:
+//!
+//! ```text
+//! # port weight
+//! 443 0.42
+//! 80  0.31
+//! 8443
+//! ```
+//!
+//! Blank lines and `#` comments are skipped, a port listed twice keeps its
+//! first position. Only the requested ports get probed: the ones the file
+//! lists in its order, then the ones it doesn't in random order. In
+//! weighted mode the listed ports are drawn at random instead, each with a
+//! chance matching its weight, ports without one weighing 1.
+use super::RangeOrder;
+use crate::input::OrderFileMode;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// The ports of an order file, in order, with their weights.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderFile {
+    entries: Vec<(u16, Option<f64>)>,
+}
+
+impl OrderFile {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut entries: Vec<(u16, Option<f64>)> = Vec::new();
+        let mut seen: HashSet<u16> = HashSet::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut columns = line
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|column| !column.is_empty());
+            let Some(port) = columns.next() else {
+                continue;
+            };
+            let port: u16 = port
+                .parse()
+                .map_err(|_| format!("line {}: {port:?} is not a port", number + 1))?;
+            let weight = match columns.next() {
+                Some(weight) => match weight.parse::<f64>() {
+                    Ok(weight) if weight.is_finite() && weight >= 0.0 => Some(weight),
+                    _ => return Err(format!("line {}: {weight:?} is not a weight", number + 1)),
+                },
+                None => None,
+            };
+            if let Some(extra) = columns.next() {
+                return Err(format!("line {}: unexpected {extra:?}", number + 1));
+            }
+            if seen.insert(port) {
+                entries.push((port, weight));
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// The listed ports, in order.
+    pub fn ports(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.iter().map(|(port, _)| *port)
+    }
+
+    /// The listed ports, in order, with their weights.
+    pub fn entries(&self) -> &[(u16, Option<f64>)] {
+        &self.entries
+    }
+}
+
+/// The requested ports in the order of an order file.
+#[derive(Debug)]
+pub struct FileOrder {
+    /// The requested ports the file lists, in its order, with their weights.
+    listed: Vec<(u16, f64)>,
+    /// The requested ports the file doesn't list.
+    unlisted: Vec<u16>,
+    mode: OrderFileMode,
+}
+
+impl FileOrder {
+    pub fn new(file: &OrderFile, requested: &[u16], mode: OrderFileMode) -> Self {
+        let requested_set: HashSet<u16> = requested.iter().copied().collect();
+        let listed: Vec<(u16, f64)> = file
+            .entries
+            .iter()
+            .filter(|(port, _)| requested_set.contains(port))
+            .map(|(port, weight)| (*port, weight.unwrap_or(1.0)))
+            .collect();
+        let listed_set: HashSet<u16> = listed.iter().map(|(port, _)| *port).collect();
+        let mut unlisted: Vec<u16> = Vec::new();
+        for port in requested {
+            if !listed_set.contains(port) && !unlisted.contains(port) {
+                unlisted.push(*port);
+            }
+        }
+        Self {
+            listed,
+            unlisted,
+            mode,
+        }
+    }
+}
+
+impl RangeOrder for FileOrder {
+    fn generate(&self) -> Vec<u16> {
+        let mut rng = thread_rng();
+        let mut order: Vec<u16> = match self.mode {
+            OrderFileMode::Literal => self.listed.iter().map(|(port, _)| *port).collect(),
+            OrderFileMode::Weighted => {
+                // Sorting by u^(1/weight) draws without replacement, each
+                // port ahead of the rest with a chance matching its weight.
+                let mut keyed: Vec<(f64, u16)> = self
+                    .listed
+                    .iter()
+                    .map(|(port, weight)| {
+                        let key = if *weight > 0.0 {
+                            rng.gen::<f64>().powf(1.0 / weight)
+                        } else {
+                            0.0
+                        };
+                        (key, *port)
+                    })
+                    .collect();
+                keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+                keyed.into_iter().map(|(_, port)| port).collect()
+            }
+        };
+        let mut unlisted = self.unlisted.clone();
+        unlisted.shuffle(&mut rng);
+        order.extend(unlisted);
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileOrder, OrderFile};
+    use crate::input::OrderFileMode;
+    use crate::port_strategy::RangeOrder;
+
+    const FILE: &str = "# port weight\n443 0.5\n80,0.3\n\n22\n443 9\n8080 # proxies\n";
+
+    #[test]
+    fn file_is_parsed_and_deduped() {
+        let file = OrderFile::parse(FILE).unwrap();
+        assert_eq!(file.ports().collect::<Vec<u16>>(), [443, 80, 22, 8080]);
+
+        assert!(OrderFile::parse("80\nhttp\n")
+            .unwrap_err()
+            .starts_with("line 2:"));
+        assert!(OrderFile::parse("80 -1\n").is_err());
+        assert!(OrderFile::parse("80 1 2\n").is_err());
+    }
+
+    #[test]
+    fn literal_order_is_intersected_then_completed() {
+        let file = OrderFile::parse(FILE).unwrap();
+        let order = FileOrder::new(&file, &[21, 22, 23, 80, 8080, 25], OrderFileMode::Literal);
+        let ports = order.generate();
+
+        // 443 wasn't requested, 21, 23 and 25 aren't in the file.
+        assert_eq!(ports[..3], [80, 22, 8080]);
+        let mut rest = ports[3..].to_vec();
+        rest.sort_unstable();
+        assert_eq!(rest, [21, 23, 25]);
+    }
+
+    #[test]
+    fn weighted_order_covers_every_port() {
+        let file = OrderFile::parse("1 1000\n2 1\n3 0\n4\n").unwrap();
+        let requested: Vec<u16> = (1..=6).collect();
+        let order = FileOrder::new(&file, &requested, OrderFileMode::Weighted);
+
+        let mut heaviest_first = 0;
+        for _ in 0..100 {
+            let ports = order.generate();
+            let mut sorted = ports.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, requested);
+            // A zero weight is drawn last of the listed ports.
+            assert_eq!(ports[3], 3, "{:?}", ports);
+            heaviest_first += usize::from(ports[0] == 1);
+        }
+        assert!(heaviest_first > 90, "{}", heaviest_first);
+    }
+}
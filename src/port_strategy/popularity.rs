@@ -0,0 +1,70 @@
+//! Embedded popularity tables of TCP and UDP ports, in nmap's frequency order.
+
+/// The 20 most frequently open TCP ports.
+pub(super) const MOST_POPULAR: [u16; 20] = [
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900,
+];
+
+/// The rest of the 100 most frequently open TCP ports.
+pub(super) const POPULAR: [u16; 80] = [
+    1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000, 8443,
+    8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631, 631,
+    49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156, 543,
+    544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986, 13,
+    1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// The 20 most frequently open UDP ports.
+pub(super) const MOST_POPULAR_UDP: [u16; 20] = [
+    631, 161, 137, 123, 138, 1434, 445, 135, 67, 53, 139, 500, 68, 520, 1900, 4500, 514, 49152,
+    162, 69,
+];
+
+/// The rest of the 100 most frequently open UDP ports, in ascending order.
+pub(super) const POPULAR_UDP: [u16; 80] = [
+    7, 9, 17, 19, 49, 80, 88, 111, 120, 136, 158, 177, 427, 443, 497, 515, 518, 593, 623, 626, 996,
+    997, 998, 999, 1022, 1023, 1025, 1026, 1027, 1028, 1029, 1030, 1433, 1645, 1646, 1701, 1718,
+    1719, 1812, 1813, 2000, 2048, 2049, 2222, 2223, 3283, 3456, 3703, 4444, 5000, 5060, 5353, 5632,
+    9200, 10000, 17185, 20031, 30718, 31337, 32768, 32769, 32771, 32815, 33281, 49153, 49154,
+    49156, 49181, 49182, 49185, 49186, 49188, 49190, 49191, 49192, 49193, 49194, 49200, 49201,
+    65024,
+];
+
+/// How many popularity tiers there are, the last one holding every port
+/// missing from the table.
+pub const TIERS: usize = 3;
+
+/// Returns the popularity tier of the TCP `port`, 0 being the most popular.
+pub fn tier(port: u16) -> usize {
+    if MOST_POPULAR.contains(&port) {
+        0
+    } else if POPULAR.contains(&port) {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tier, MOST_POPULAR, MOST_POPULAR_UDP, POPULAR, POPULAR_UDP};
+    use std::collections::HashSet;
+
+    #[test]
+    fn tiers_do_not_overlap() {
+        let ports: HashSet<u16> = MOST_POPULAR.iter().chain(&POPULAR).copied().collect();
+        assert_eq!(ports.len(), MOST_POPULAR.len() + POPULAR.len());
+
+        assert_eq!(tier(80), 0);
+        assert_eq!(tier(5432), 1);
+        assert_eq!(tier(31337), 2);
+
+        let udp: HashSet<u16> = MOST_POPULAR_UDP
+            .iter()
+            .chain(&POPULAR_UDP)
+            .copied()
+            .collect();
+        assert_eq!(udp.len(), MOST_POPULAR_UDP.len() + POPULAR_UDP.len());
+    }
+}
@@ -9,6 +9,7 @@ pub struct RangeIterator {
     normalized_pick: u32,
     actual_start: u32,
     step: u32,
+    remaining: u32,
 }
 
 /// An iterator that follows the `Linear Congruential Generator` algorithm.
@@ -21,13 +22,32 @@ impl RangeIterator {
     ///
     /// For example, the range `1000-2500` will be normalized to `0-1500`
     /// before going through the algorithm.
+    ///
+    /// A range whose start is after its end is empty.
     pub fn new(start: u32, end: u32) -> Self {
+        Self::with_rng(start, end, &mut rand::thread_rng())
+    }
+
+    /// Same as [`RangeIterator::new`], drawing the step and the first pick
+    /// from `rng` so that a seeded generator always yields the same order.
+    pub fn with_rng<R: Rng + ?Sized>(start: u32, end: u32, rng: &mut R) -> Self {
+        if end < start {
+            return Self {
+                active: false,
+                normalized_end: 0,
+                step: 0,
+                normalized_first_pick: 0,
+                normalized_pick: 0,
+                actual_start: start,
+                remaining: 0,
+            };
+        }
+
         let normalized_end = end - start + 1;
-        let step = pick_random_coprime(normalized_end);
+        let step = pick_random_coprime(normalized_end, rng);
 
         // Randomly choose a number within the range to be the first
         // and assign it as a pick.
-        let mut rng = rand::thread_rng();
         let normalized_first_pick = rng.gen_range(0..normalized_end);
 
         Self {
@@ -37,6 +57,7 @@ impl RangeIterator {
             normalized_first_pick,
             normalized_pick: normalized_first_pick,
             actual_start: start,
+            remaining: normalized_end,
         }
     }
 }
@@ -61,14 +82,22 @@ impl Iterator for RangeIterator {
         }
 
         self.normalized_pick = next_pick;
+        self.remaining -= 1;
         Some(
             (self.actual_start + current_pick)
                 .try_into()
                 .expect("Could not convert u32 to u16"),
         )
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining as usize;
+        (remaining, Some(remaining))
+    }
 }
 
+impl ExactSizeIterator for RangeIterator {}
+
 /// The probability that two random integers are coprime to one another
 /// works out to be around 61%, given that we can safely pick a random
 /// number and test it. Just in case we are having a bad day and we cannot
@@ -79,11 +108,10 @@ impl Iterator for RangeIterator {
 /// the boundaries, which in these case are the "start" and "end" arguments
 /// would also provide non-ideal randomization as discussed on the paragraph
 /// above.
-fn pick_random_coprime(end: u32) -> u32 {
+fn pick_random_coprime<R: Rng + ?Sized>(end: u32, rng: &mut R) -> u32 {
     let range_boundary = end / 4;
     let lower_range = range_boundary;
     let upper_range = end - range_boundary;
-    let mut rng = rand::thread_rng();
     let mut candidate = rng.gen_range(lower_range..upper_range);
 
     for _ in 0..10 {
@@ -97,8 +125,77 @@ fn pick_random_coprime(end: u32) -> u32 {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_conversion)]
 mod tests {
     use super::RangeIterator;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Asserts that the iterator yields every port of `start..=end` exactly
+    /// once, reporting the right length along the way, and then terminates.
+    fn assert_covers_exactly_once(start: u32, end: u32) {
+        let size = (end - start + 1) as usize;
+        let mut range = RangeIterator::new(start, end);
+        let mut seen = vec![false; size];
+
+        for yielded in 0..size {
+            assert_eq!(range.len(), size - yielded);
+            let port = u32::from(range.next().expect("iterator ended early"));
+            assert!(
+                (start..=end).contains(&port),
+                "{} out of {}-{}",
+                port,
+                start,
+                end
+            );
+            let index = (port - start) as usize;
+            assert!(!seen[index], "{} yielded twice", port);
+            seen[index] = true;
+        }
+
+        assert_eq!(range.len(), 0);
+        assert_eq!(range.next(), None);
+        assert_eq!(range.next(), None);
+    }
+
+    #[test]
+    fn range_iterator_small_ranges_exhaustively() {
+        for size in 1..=300 {
+            // Each size is tried a few times since the step is random.
+            for _ in 0..20 {
+                assert_covers_exactly_once(1, size);
+                assert_covers_exactly_once(65_536 - size, 65_535);
+            }
+        }
+    }
+
+    #[test]
+    fn range_iterator_degenerate_and_maximal_ranges() {
+        assert_covers_exactly_once(0, 0);
+        assert_covers_exactly_once(80, 80);
+        assert_covers_exactly_once(65_535, 65_535);
+        assert_covers_exactly_once(80, 81);
+        assert_covers_exactly_once(65_534, 65_535);
+        assert_covers_exactly_once(1, 65_535);
+        assert_covers_exactly_once(0, 65_535);
+    }
+
+    #[test]
+    fn range_iterator_reversed_range_is_empty() {
+        let mut range = RangeIterator::new(100, 99);
+        assert_eq!(range.len(), 0);
+        assert_eq!(range.next(), None);
+    }
+
+    #[test]
+    fn range_iterator_is_stable_with_seeded_rng() {
+        let first: Vec<u16> =
+            RangeIterator::with_rng(1, 1000, &mut StdRng::seed_from_u64(42)).collect();
+        let second: Vec<u16> =
+            RangeIterator::with_rng(1, 1000, &mut StdRng::seed_from_u64(42)).collect();
+
+        assert_eq!(first, second);
+    }
 
     #[test]
     fn range_iterator_iterates_through_the_entire_range() {
@@ -0,0 +1,573 @@
+//! How the ports of a host are worked out of every source giving or
+//! excluding some, see [`resolve`].
+//!
+//! The ports of a host go through the same steps, in this order:
+//!
+//! 1. The ports of the run are the ones of the first source given of
+//!    `--ports`, the top ports of `--top`, `--range`, the `range` of the
+//!    configuration file and the default set of the protocol. The sources
+//!    after it are overridden.
+//! 2. The ports a target was given of its own, with `host=ports` or by the
+//!    previous results narrowing it down, replace the ports of the run.
+//! 3. The canary ports are added.
+//! 4. The exclusions come last, every one of them: `--exclude-ports`, the
+//!    `exclude_ports` of the configuration file, and the knock ports unless
+//!    `--ports` lists them.
+//!
+//! A host left without ports is an error rather than a scan of nothing, the
+//! table of its [`PortResolution`] telling which exclusion emptied it.
+use super::DefaultPorts;
+use crate::input::PortRange;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::iter;
+
+/// A source of ports of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortSource {
+    Ports,
+    /// The top ports picked with `--top`.
+    Top(DefaultPorts),
+    Range,
+    ConfigRange,
+    /// The default set of the run, given no ports.
+    Default(DefaultPorts),
+    /// The ports of the target itself.
+    Target,
+    Canaries,
+    ExcludePorts,
+    ConfigExcludePorts,
+    Knock,
+}
+
+impl fmt::Display for PortSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSource::Ports => f.write_str("--ports"),
+            PortSource::Top(set) => write!(f, "--top ({set})"),
+            PortSource::Range => f.write_str("--range"),
+            PortSource::ConfigRange => f.write_str("range of the config file"),
+            PortSource::Default(set) => write!(f, "default ({set})"),
+            PortSource::Target => f.write_str("ports of the target"),
+            PortSource::Canaries => f.write_str("--canary-ports"),
+            PortSource::ExcludePorts => f.write_str("--exclude-ports"),
+            PortSource::ConfigExcludePorts => f.write_str("exclude_ports of the config file"),
+            PortSource::Knock => f.write_str("--knock"),
+        }
+    }
+}
+
+/// What every source gave the run, before any of them is picked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSources {
+    pub ports: Option<Vec<u16>>,
+    pub range: Option<PortRange>,
+    pub config_range: Option<PortRange>,
+    /// The default set the run fell back on, or the top ports of `--top`,
+    /// with its ports.
+    pub default: Option<(DefaultPorts, PortRange)>,
+    pub canaries: Vec<u16>,
+    pub exclude_ports: Vec<u16>,
+    pub config_exclude_ports: Vec<u16>,
+    /// The ports of the knocks of the protocol of the run.
+    pub knock: Vec<u16>,
+}
+
+/// What a source did to the ports of a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    Included,
+    /// Another source gave the ports instead.
+    Overridden(PortSource),
+    /// The ports it removed, which no exclusion before it did, and whether
+    /// they were the last ones.
+    Excluded {
+        removed: Vec<u16>,
+        emptied: bool,
+    },
+}
+
+/// A source of the ports of a host, with what it did to them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contribution {
+    pub source: PortSource,
+    pub ports: PortRange,
+    pub effect: Effect,
+}
+
+/// The ports of a host, with where they came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortResolution {
+    pub contributions: Vec<Contribution>,
+    /// The ports scanned, sorted.
+    pub ports: Vec<u16>,
+}
+
+impl PortResolution {
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// The exclusion which removed the last port, if one did.
+    pub fn emptied_by(&self) -> Option<PortSource> {
+        self.contributions
+            .iter()
+            .find(|contribution| {
+                matches!(contribution.effect, Effect::Excluded { emptied: true, .. })
+            })
+            .map(|contribution| contribution.source)
+    }
+
+    /// The sources as a table of their ports and what they did, then the
+    /// ports scanned.
+    pub fn table(&self) -> String {
+        let rows: Vec<[String; 3]> = self
+            .contributions
+            .iter()
+            .map(|contribution| {
+                let effect = match &contribution.effect {
+                    Effect::Included => "included".to_owned(),
+                    Effect::Overridden(by) => format!("overridden by {by}"),
+                    Effect::Excluded { removed, .. } if removed.is_empty() => {
+                        "excluded nothing".to_owned()
+                    }
+                    Effect::Excluded { removed, emptied } => format!(
+                        "excluded {}{}",
+                        PortRange::from_ports(removed),
+                        if *emptied { ", leaving no port" } else { "" }
+                    ),
+                };
+                [
+                    contribution.source.to_string(),
+                    contribution.ports.to_string(),
+                    effect,
+                ]
+            })
+            .collect();
+        let header = ["Source".to_owned(), "Ports".to_owned(), "Effect".to_owned()];
+        let widths: Vec<usize> = (0..2)
+            .map(|column| {
+                rows.iter()
+                    .chain(iter::once(&header))
+                    .map(|row| row[column].len())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let mut table = String::new();
+        for row in iter::once(&header).chain(&rows) {
+            table.push_str(&format!(
+                "{:width0$}  {:width1$}  {}\n",
+                row[0],
+                row[1],
+                row[2],
+                width0 = widths[0],
+                width1 = widths[1]
+            ));
+        }
+        if self.ports.is_empty() {
+            table.push_str("Scanned: no port");
+        } else {
+            table.push_str(&format!("Scanned: {}", PortRange::from_ports(&self.ports)));
+        }
+        table
+    }
+}
+
+/// The ports of a host given `target`, its own ports, out of `sources`.
+/// Never fails: a host without ports has an empty resolution.
+pub fn resolve(sources: &PortSources, target: Option<&PortRange>) -> PortResolution {
+    let (top, fallback) = match sources.default.clone() {
+        Some((set, range)) if set.is_top() => (Some((set, range)), None),
+        default => (None, default),
+    };
+    // The run's sources, first one given first.
+    let mut given: Vec<(PortSource, PortRange)> = Vec::new();
+    if let Some(ports) = &sources.ports {
+        given.push((PortSource::Ports, PortRange::from_ports(ports)));
+    }
+    if let Some((set, range)) = top {
+        given.push((PortSource::Top(set), range));
+    }
+    if let Some(range) = &sources.range {
+        given.push((PortSource::Range, range.clone()));
+    }
+    if let Some(range) = &sources.config_range {
+        given.push((PortSource::ConfigRange, range.clone()));
+    }
+    if let Some((set, range)) = fallback {
+        given.push((PortSource::Default(set), range));
+    }
+
+    let mut contributions = Vec::new();
+    let mut ports: BTreeSet<u16> = BTreeSet::new();
+    let mut decided = None;
+    if let Some(range) = target {
+        contributions.push(Contribution {
+            source: PortSource::Target,
+            ports: range.clone(),
+            effect: Effect::Included,
+        });
+        ports.extend(expand(range));
+        decided = Some(PortSource::Target);
+    }
+    for (source, range) in given {
+        let effect = match decided {
+            Some(by) => Effect::Overridden(by),
+            None => {
+                ports.extend(expand(&range));
+                decided = Some(source);
+                Effect::Included
+            }
+        };
+        contributions.push(Contribution {
+            source,
+            ports: range,
+            effect,
+        });
+    }
+    if !sources.canaries.is_empty() {
+        ports.extend(&sources.canaries);
+        contributions.push(Contribution {
+            source: PortSource::Canaries,
+            ports: PortRange::from_ports(&sources.canaries),
+            effect: Effect::Included,
+        });
+    }
+
+    let listed = sources.ports.as_deref().unwrap_or_default();
+    let knock: Vec<u16> = sources
+        .knock
+        .iter()
+        .filter(|port| !listed.contains(port))
+        .copied()
+        .collect();
+    for (source, excluded) in [
+        (PortSource::ExcludePorts, &sources.exclude_ports),
+        (
+            PortSource::ConfigExcludePorts,
+            &sources.config_exclude_ports,
+        ),
+        (PortSource::Knock, &knock),
+    ] {
+        if excluded.is_empty() {
+            continue;
+        }
+        let left = !ports.is_empty();
+        let removed: Vec<u16> = excluded
+            .iter()
+            .filter(|port| ports.remove(port))
+            .copied()
+            .collect();
+        contributions.push(Contribution {
+            source,
+            ports: PortRange::from_ports(excluded),
+            effect: Effect::Excluded {
+                emptied: left && ports.is_empty(),
+                removed,
+            },
+        });
+    }
+
+    PortResolution {
+        contributions,
+        ports: ports.into_iter().collect(),
+    }
+}
+
+fn expand(range: &PortRange) -> impl Iterator<Item = u16> + '_ {
+    range.ranges.iter().flat_map(|&(start, end)| start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, Contribution, Effect, PortSource, PortSources};
+    use crate::input::PortRange;
+    use crate::port_strategy::DefaultPorts;
+
+    fn range(ports: &str) -> PortRange {
+        ports.parse().unwrap()
+    }
+
+    /// The sources of the resolution with their effects.
+    fn effects(sources: &PortSources, target: Option<&str>) -> Vec<(PortSource, Effect)> {
+        let target = target.map(range);
+        resolve(sources, target.as_ref())
+            .contributions
+            .into_iter()
+            .map(|contribution| (contribution.source, contribution.effect))
+            .collect()
+    }
+
+    fn scanned(sources: &PortSources, target: Option<&str>) -> String {
+        let target = target.map(range);
+        PortRange::from_ports(&resolve(sources, target.as_ref()).ports).to_string()
+    }
+
+    fn excluded(removed: &[u16], emptied: bool) -> Effect {
+        Effect::Excluded {
+            removed: removed.to_vec(),
+            emptied,
+        }
+    }
+
+    #[test]
+    fn the_first_source_given_decides() {
+        let all = PortSources {
+            ports: Some(vec![22, 80]),
+            range: Some(range("1-100")),
+            config_range: Some(range("1-1000")),
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&all, None), "22,80");
+        assert_eq!(
+            effects(&all, None),
+            [
+                (PortSource::Ports, Effect::Included),
+                (PortSource::Range, Effect::Overridden(PortSource::Ports)),
+                (
+                    PortSource::ConfigRange,
+                    Effect::Overridden(PortSource::Ports)
+                ),
+            ]
+        );
+
+        let ranges = PortSources {
+            ports: None,
+            ..all.clone()
+        };
+        assert_eq!(scanned(&ranges, None), "1-100");
+        let config = PortSources {
+            range: None,
+            ..ranges.clone()
+        };
+        assert_eq!(scanned(&config, None), "1-1000");
+        assert_eq!(
+            effects(&config, None),
+            [(PortSource::ConfigRange, Effect::Included)]
+        );
+    }
+
+    #[test]
+    fn every_combination_of_sources_has_one_winner() {
+        let top = (DefaultPorts::TopTcp, range("1"));
+        let fallback = (DefaultPorts::AllTcp, range("5"));
+        // Every subset of the run's sources, with and without a target.
+        for mask in 0..32_u8 {
+            let given = |bit: u8| mask & (1 << bit) != 0;
+            let sources = PortSources {
+                ports: given(0).then(|| vec![2]),
+                range: given(1).then(|| range("3")),
+                config_range: given(2).then(|| range("4")),
+                default: match (given(3), given(4)) {
+                    (true, _) => Some(top.clone()),
+                    (false, true) => Some(fallback.clone()),
+                    (false, false) => None,
+                },
+                exclude_ports: vec![9],
+                ..PortSources::default()
+            };
+            let expected = if given(0) {
+                "2"
+            } else if given(3) {
+                "1"
+            } else if given(1) {
+                "3"
+            } else if given(2) {
+                "4"
+            } else if given(4) {
+                "5"
+            } else {
+                ""
+            };
+            assert_eq!(scanned(&sources, None), expected, "{:?}", sources);
+            assert_eq!(scanned(&sources, Some("8-9")), "8", "{:?}", sources);
+
+            let resolution = resolve(&sources, None);
+            let included: Vec<PortSource> = resolution
+                .contributions
+                .iter()
+                .filter(|contribution| contribution.effect == Effect::Included)
+                .map(|contribution| contribution.source)
+                .collect();
+            assert_eq!(
+                included.len(),
+                usize::from(!expected.is_empty()),
+                "{:?}",
+                sources
+            );
+            let overridden = resolution
+                .contributions
+                .iter()
+                .filter(|contribution| matches!(contribution.effect, Effect::Overridden(_)))
+                .count();
+            let run_sources =
+                (0..4).filter(|&bit| given(bit)).count() + usize::from(!given(3) && given(4));
+            assert_eq!(overridden, run_sources.saturating_sub(1), "{:?}", sources);
+        }
+    }
+
+    #[test]
+    fn the_default_set_only_fills_in() {
+        let defaulted = PortSources {
+            default: Some((DefaultPorts::AllTcp, range("1-65535"))),
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&defaulted, None), "1-65535");
+        assert_eq!(
+            effects(&defaulted, None),
+            [(PortSource::Default(DefaultPorts::AllTcp), Effect::Included)]
+        );
+        // A target of its own ports doesn't need the default.
+        assert_eq!(scanned(&defaulted, Some("443")), "443");
+    }
+
+    #[test]
+    fn top_ports_come_before_the_ranges() {
+        let top = PortSources {
+            range: Some(range("1-100")),
+            config_range: Some(range("1-1000")),
+            default: Some((DefaultPorts::ConfigTop, range("22,80"))),
+            ..PortSources::default()
+        };
+        let picked = PortSource::Top(DefaultPorts::ConfigTop);
+        assert_eq!(scanned(&top, None), "22,80");
+        assert_eq!(
+            effects(&top, None),
+            [
+                (picked, Effect::Included),
+                (PortSource::Range, Effect::Overridden(picked)),
+                (PortSource::ConfigRange, Effect::Overridden(picked)),
+            ]
+        );
+        let listed = PortSources {
+            ports: Some(vec![8080]),
+            ..top
+        };
+        assert_eq!(scanned(&listed, None), "8080");
+    }
+
+    #[test]
+    fn targets_override_the_ports_of_the_run() {
+        let sources = PortSources {
+            ports: Some(vec![22]),
+            config_range: Some(range("1-10")),
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&sources, Some("80-82")), "80-82");
+        assert_eq!(
+            effects(&sources, Some("80-82")),
+            [
+                (PortSource::Target, Effect::Included),
+                (PortSource::Ports, Effect::Overridden(PortSource::Target)),
+                (
+                    PortSource::ConfigRange,
+                    Effect::Overridden(PortSource::Target)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn canaries_are_added_before_the_exclusions() {
+        let sources = PortSources {
+            ports: Some(vec![80]),
+            canaries: vec![3389, 445],
+            exclude_ports: vec![445],
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&sources, None), "80,3389");
+        assert_eq!(scanned(&sources, Some("22")), "22,3389");
+    }
+
+    #[test]
+    fn every_exclusion_applies_last() {
+        let sources = PortSources {
+            range: Some(range("1-10")),
+            exclude_ports: vec![1, 2, 50],
+            config_exclude_ports: vec![2, 3],
+            knock: vec![4],
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&sources, None), "5-10");
+        assert_eq!(
+            effects(&sources, None),
+            [
+                (PortSource::Range, Effect::Included),
+                (PortSource::ExcludePorts, excluded(&[1, 2], false)),
+                // 2 is gone already.
+                (PortSource::ConfigExcludePorts, excluded(&[3], false)),
+                (PortSource::Knock, excluded(&[4], false)),
+            ]
+        );
+        // Excluded from the ports of a target too.
+        assert_eq!(scanned(&sources, Some("1-5")), "5");
+    }
+
+    #[test]
+    fn knock_ports_listed_with_ports_are_scanned() {
+        let sources = PortSources {
+            ports: Some(vec![7000, 80]),
+            knock: vec![7000, 8000],
+            ..PortSources::default()
+        };
+        assert_eq!(scanned(&sources, None), "80,7000");
+        let ranged = PortSources {
+            ports: None,
+            range: Some(range("7000-8000")),
+            ..sources
+        };
+        assert_eq!(scanned(&ranged, None), "7001-7999");
+    }
+
+    #[test]
+    fn the_exclusion_emptying_a_host_is_told() {
+        let sources = PortSources {
+            ports: Some(vec![22, 80]),
+            exclude_ports: vec![22],
+            config_exclude_ports: vec![80, 443],
+            knock: vec![9000],
+            ..PortSources::default()
+        };
+        let resolution = resolve(&sources, None);
+        assert!(resolution.is_empty());
+        assert_eq!(
+            resolution.emptied_by(),
+            Some(PortSource::ConfigExcludePorts)
+        );
+        assert_eq!(
+            resolution.contributions[3],
+            Contribution {
+                source: PortSource::Knock,
+                ports: range("9000"),
+                effect: excluded(&[], false),
+            }
+        );
+        assert_eq!(
+            resolution.table(),
+            "Source                            Ports   Effect\n\
+             --ports                           22,80   included\n\
+             --exclude-ports                   22      excluded 22\n\
+             exclude_ports of the config file  80,443  excluded 80, leaving no port\n\
+             --knock                           9000    excluded nothing\n\
+             Scanned: no port"
+        );
+
+        // A target of other ports keeps them.
+        let resolution = resolve(&sources, Some(&range("8080")));
+        assert!(!resolution.is_empty());
+        assert_eq!(resolution.emptied_by(), None);
+    }
+
+    #[test]
+    fn no_source_gives_no_port() {
+        let resolution = resolve(&PortSources::default(), None);
+        assert!(resolution.is_empty());
+        assert!(resolution.contributions.is_empty());
+        assert_eq!(resolution.emptied_by(), None);
+        assert_eq!(
+            resolution.table(),
+            "Source  Ports  Effect\nScanned: no port"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Keeps the ports probed close in time apart in number.
+//!
+//! A shuffled list of ports still has its runs, like 8080 then 8079 then
+//! 8082, which is what the sweep signatures of an IDS look for: a few
+//! destination ports close to each other within a few probes. [`spread`]
+//! re-orders a list so that any `window` ports in a row are at least
+//! `distance` apart, moving as few ports as it can so the order stays as
+//! random as it was.
+use serde_derive::Serialize;
+use std::collections::VecDeque;
+
+/// How far apart the ports probed close in time are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Spread {
+    /// How many ports in a row are kept apart.
+    pub window: usize,
+    /// The smallest difference between two ports of a window.
+    pub distance: u16,
+}
+
+impl Default for Spread {
+    fn default() -> Self {
+        Self {
+            window: 8,
+            distance: 16,
+        }
+    }
+}
+
+impl Spread {
+    /// Whether any order already keeps the ports apart.
+    pub fn is_off(&self) -> bool {
+        self.window < 2 || self.distance < 2
+    }
+
+    /// Whether `port` may come right after `before`, the ports ordered so
+    /// far.
+    fn fits_after(&self, before: &[u16], port: u16) -> bool {
+        let start = before.len().saturating_sub(self.window - 1);
+        self.apart(&before[start..], port)
+    }
+
+    /// Whether `port` may go at `position` of `ports`, before the port
+    /// there.
+    fn fits_at(&self, ports: &[u16], position: usize, port: u16) -> bool {
+        let start = position.saturating_sub(self.window - 1);
+        let end = (position + self.window - 1).min(ports.len());
+        self.apart(&ports[start..end], port)
+    }
+
+    fn apart(&self, ports: &[u16], port: u16) -> bool {
+        ports
+            .iter()
+            .all(|other| port.abs_diff(*other) >= self.distance)
+    }
+}
+
+/// Whether any `window` ports in a row of `ports` are `distance` apart.
+pub fn is_spread(ports: &[u16], spread: Spread) -> bool {
+    spread.is_off()
+        || (1..ports.len()).all(|position| spread.fits_after(&ports[..position], ports[position]))
+}
+
+/// Re-orders `ports` so that they are spread, every port kept.
+///
+/// Ports go in their order, a port too close to the ones before it waiting
+/// for the first place it fits. The ports still waiting at the end go back
+/// to the last place they fit in, at the end when there is none, which only
+/// happens when the ports are too close to each other to be spread at all.
+pub fn spread(ports: Vec<u16>, spread: Spread) -> Vec<u16> {
+    let mut spread_ports = Vec::with_capacity(ports.len());
+    extend_spread(&mut spread_ports, ports, spread);
+    spread_ports
+}
+
+/// Adds `ports` to the end of `spread_ports`, spread as with [`spread`],
+/// none of them going before the ones already there.
+pub fn extend_spread(spread_ports: &mut Vec<u16>, ports: Vec<u16>, spread: Spread) {
+    if spread.is_off() {
+        spread_ports.extend(ports);
+        return;
+    }
+    let start = spread_ports.len();
+    let mut waiting: VecDeque<u16> = VecDeque::new();
+    let mut ports = ports.into_iter();
+    loop {
+        let fitting = waiting
+            .iter()
+            .position(|port| spread.fits_after(spread_ports, *port));
+        if let Some(port) = fitting.and_then(|position| waiting.remove(position)) {
+            spread_ports.push(port);
+            continue;
+        }
+        match ports.next() {
+            Some(port) if spread.fits_after(spread_ports, port) => spread_ports.push(port),
+            Some(port) => waiting.push_back(port),
+            None => break,
+        }
+    }
+
+    for port in waiting {
+        let position = (start..=spread_ports.len())
+            .rev()
+            .find(|position| spread.fits_at(spread_ports, *position, port))
+            .unwrap_or(spread_ports.len());
+        spread_ports.insert(position, port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_spread, spread, Spread};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    fn sorted(mut ports: Vec<u16>) -> Vec<u16> {
+        ports.sort_unstable();
+        ports
+    }
+
+    #[test]
+    fn runs_are_broken_up() {
+        let tight = Spread {
+            window: 3,
+            distance: 10,
+        };
+        let ports = vec![8080, 8079, 8082, 22, 443, 3000, 9000];
+        let spread_ports = spread(ports.clone(), tight);
+        assert!(!is_spread(&ports, tight));
+        assert!(is_spread(&spread_ports, tight));
+        assert_eq!(sorted(spread_ports.clone()), sorted(ports.clone()));
+        // The ports which fit stay where they were.
+        assert_eq!(&spread_ports[..2], &[8080, 22]);
+
+        // Ports too close to each other are kept, as apart as it goes.
+        let close: Vec<u16> = (1..=10).collect();
+        assert_eq!(sorted(spread(close.clone(), tight)), close);
+
+        let off = Spread {
+            window: 1,
+            distance: 10,
+        };
+        assert_eq!(spread(ports.clone(), off), ports);
+        assert!(is_spread(&ports, off));
+    }
+
+    // Over random lists of ports wide enough to be spread, in one range or
+    // several, every port is kept and the lists end up spread.
+    #[test]
+    fn random_lists_are_spread_and_kept() {
+        let mut rng = StdRng::seed_from_u64(151);
+        for _ in 0..200 {
+            let spreading = Spread {
+                window: rng.gen_range(2..=16),
+                distance: rng.gen_range(2..=64),
+            };
+            let width = spreading.window * usize::from(spreading.distance);
+            let mut ports: Vec<u16> = Vec::new();
+            for _ in 0..rng.gen_range(1..=3) {
+                let start: u16 = rng.gen_range(1..=30_000);
+                let end = start + rng.gen_range(4 * width as u16..=8 * width as u16);
+                ports.extend(start..=end);
+            }
+            ports.sort_unstable();
+            ports.dedup();
+            ports.shuffle(&mut rng);
+
+            let spread_ports = spread(ports.clone(), spreading);
+            assert_eq!(sorted(spread_ports.clone()), sorted(ports));
+            assert!(is_spread(&spread_ports, spreading), "{:?}", spreading);
+        }
+
+        // The whole range with the defaults.
+        let mut ports: Vec<u16> = (1..=u16::MAX).collect();
+        ports.shuffle(&mut rng);
+        let spread_ports = spread(ports, Spread::default());
+        assert_eq!(spread_ports.len(), usize::from(u16::MAX));
+        assert!(is_spread(&spread_ports, Spread::default()));
+    }
+}
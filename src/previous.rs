@@ -0,0 +1,173 @@
+//! The results of a previous scan, read back to re-validate its open ports,
+//! or to rescan its filtered ones.
+//!
+//! The file is a JSON report of `--format json`. Only its run id, and the
+//! address, the open and the filtered ports of every host are read, the
+//! other fields are ignored.
+use serde_derive::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct PreviousResults {
+    #[serde(default)]
+    pub run_id: Option<String>,
+    pub hosts: Vec<PreviousHost>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PreviousHost {
+    pub ip: IpAddr,
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub filtered: Vec<u16>,
+}
+
+impl PreviousResults {
+    /// Reads the JSON report at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::from_json(&content)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// The ports which were open on `ip`, sorted, None when the host had none.
+    pub fn open_ports(&self, ip: IpAddr) -> Option<Vec<u16>> {
+        let ports = self.ports_of(ip, |host| &host.ports);
+        (!ports.is_empty()).then(|| ports.into_iter().collect())
+    }
+
+    /// The ports which were filtered on `ip`, sorted, None when the host had
+    /// none. A port found open by another entry of the host isn't.
+    pub fn filtered_ports(&self, ip: IpAddr) -> Option<Vec<u16>> {
+        let open = self.ports_of(ip, |host| &host.ports);
+        let ports: Vec<u16> = self
+            .ports_of(ip, |host| &host.filtered)
+            .difference(&open)
+            .copied()
+            .collect();
+        (!ports.is_empty()).then_some(ports)
+    }
+
+    /// The hosts which had open ports, in file order.
+    pub fn ips(&self) -> Vec<IpAddr> {
+        self.ips_with(|ip| self.open_ports(ip).is_some())
+    }
+
+    /// The hosts which had filtered ports, in file order.
+    pub fn filtered_ips(&self) -> Vec<IpAddr> {
+        self.ips_with(|ip| self.filtered_ports(ip).is_some())
+    }
+
+    /// Every host of the report, in file order.
+    pub fn all_ips(&self) -> Vec<IpAddr> {
+        self.ips_with(|_| true)
+    }
+
+    fn ports_of(&self, ip: IpAddr, ports: impl Fn(&PreviousHost) -> &[u16]) -> BTreeSet<u16> {
+        self.hosts
+            .iter()
+            .filter(|host| host.ip == ip)
+            .flat_map(|host| ports(host).iter().copied())
+            .collect()
+    }
+
+    fn ips_with(&self, keep: impl Fn(IpAddr) -> bool) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = Vec::new();
+        for host in &self.hosts {
+            if !ips.contains(&host.ip) && keep(host.ip) {
+                ips.push(host.ip);
+            }
+        }
+        ips
+    }
+
+    /// The ports of `ip` which were open but aren't in `open` anymore.
+    pub fn closed_since(&self, ip: IpAddr, open: &[u16]) -> Vec<u16> {
+        self.open_ports(ip)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|port| !open.contains(port))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreviousResults;
+
+    const REPORT: &str = r#"{
+        "hosts": [
+            {"ip": "10.0.0.1", "source": ["10.0.0.0/30"], "ports": [443, 22], "scanned": true},
+            {"ip": "10.0.0.2", "source": ["10.0.0.0/30"], "ports": [], "scanned": true},
+            {"ip": "10.0.0.1", "source": ["web"], "ports": [80, 22], "scanned": true}
+        ]
+    }"#;
+
+    #[test]
+    fn open_ports_are_read_from_a_report() {
+        let previous = PreviousResults::from_json(REPORT).unwrap();
+
+        assert_eq!(
+            previous.open_ports("10.0.0.1".parse().unwrap()),
+            Some(vec![22, 80, 443])
+        );
+        assert_eq!(previous.open_ports("10.0.0.2".parse().unwrap()), None);
+        assert_eq!(
+            previous.ips(),
+            ["10.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn closed_ports_are_found() {
+        let previous = PreviousResults::from_json(REPORT).unwrap();
+
+        assert_eq!(
+            previous.closed_since("10.0.0.1".parse().unwrap(), &[80]),
+            [22, 443]
+        );
+        assert!(previous
+            .closed_since("10.0.0.3".parse().unwrap(), &[])
+            .is_empty());
+    }
+
+    #[test]
+    fn filtered_ports_are_read_from_a_report() {
+        let previous = PreviousResults::from_json(
+            r#"{
+                "run_id": "before",
+                "hosts": [
+                    {"ip": "10.0.0.1", "ports": [22], "filtered": [8443, 25]},
+                    {"ip": "10.0.0.2", "ports": [80]},
+                    {"ip": "10.0.0.1", "ports": [8443], "filtered": [3389]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(previous.run_id.as_deref(), Some("before"));
+        // 8443 was found open too.
+        assert_eq!(
+            previous.filtered_ports("10.0.0.1".parse().unwrap()),
+            Some(vec![25, 3389])
+        );
+        assert_eq!(previous.filtered_ports("10.0.0.2".parse().unwrap()), None);
+        assert_eq!(
+            previous.filtered_ips(),
+            ["10.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+        );
+        assert_eq!(previous.all_ips().len(), 2);
+    }
+
+    #[test]
+    fn invalid_reports_are_rejected() {
+        assert!(PreviousResults::from_json("10.0.0.1 -> [80]").is_err());
+        assert!(PreviousResults::from_json(r#"{"hosts": [{"ip": "web"}]}"#).is_err());
+    }
+}
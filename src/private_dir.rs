@@ -0,0 +1,105 @@
+//! Private folders for the files a run hands to other programs or reads
+//! back, like the XML output of nmap or the files embedded in a bundle.
+//!
+//! A folder of the shared temporary folder with a name known in advance can
+//! be made beforehand by another local user, or filled with symlinks, to
+//! read or forge what goes through it. A [`PrivateDir`] has a random name,
+//! is made by this run only, readable by its owner only, and is removed with
+//! what it holds once dropped.
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many names are tried before giving up, should they be taken.
+const ATTEMPTS: usize = 16;
+
+/// A folder only this run uses, removed once dropped.
+#[derive(Debug)]
+pub struct PrivateDir {
+    path: PathBuf,
+}
+
+impl PrivateDir {
+    /// Makes a new folder named after `prefix` in the temporary folder.
+    pub fn create(prefix: &str) -> io::Result<Self> {
+        Self::create_in(&std::env::temp_dir(), prefix)
+    }
+
+    /// Makes a new folder named after `prefix` in `parent`. A name which is
+    /// taken is never reused, whoever made it.
+    pub fn create_in(parent: &Path, prefix: &str) -> io::Result<Self> {
+        for _ in 0..ATTEMPTS {
+            let suffix: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(12)
+                .map(char::from)
+                .collect();
+            let path = parent.join(format!("{prefix}-{suffix}"));
+            match builder().create(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("no free name for a private folder in {}", parent.display()),
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of `name` in the folder.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn builder() -> fs::DirBuilder {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let mut builder = fs::DirBuilder::new();
+    builder.mode(0o700);
+    builder
+}
+
+// The temporary folder of a Windows user is their own already.
+#[cfg(not(unix))]
+fn builder() -> fs::DirBuilder {
+    fs::DirBuilder::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrivateDir;
+    use std::fs;
+
+    #[test]
+    fn folders_are_private_and_removed() {
+        let dir = PrivateDir::create("rustscan-private-test").unwrap();
+        let other = PrivateDir::create("rustscan-private-test").unwrap();
+        assert_ne!(dir.path(), other.path());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        fs::write(dir.join("file"), "kept until dropped").unwrap();
+        let path = dir.path().to_owned();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,380 @@
+//! What the process is allowed to do, for the features a regular user can't
+//! use.
+//!
+//! [`detect`] builds a [`CapabilityReport`] out of a [`System`] once at
+//! startup, [`Host`] being the real one, so the branch of every platform can
+//! be checked against a made up system. Features consult the report before
+//! they start with [`CapabilityReport::check`]: one the process lacks the
+//! privilege for gives a single error naming the privilege, and the fallback
+//! used instead when there is one. `rustscan --capabilities` prints it.
+use std::fmt;
+
+/// The bit of `CAP_NET_RAW` in a Linux capability set.
+const CAP_NET_RAW: u32 = 13;
+/// The bit of `CAP_SYS_RESOURCE` in a Linux capability set.
+const CAP_SYS_RESOURCE: u32 = 24;
+
+/// The platforms privileges are detected differently on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// Root or the capabilities of the process.
+    Linux,
+    /// Root alone, like macOS and the BSDs.
+    Unix,
+    /// Raw packets go through the Npcap driver.
+    Windows,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(target_os = "linux") {
+            Platform::Linux
+        } else if cfg!(windows) {
+            Platform::Windows
+        } else {
+            Platform::Unix
+        }
+    }
+}
+
+/// A privilege a feature may need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Root,
+    NetRaw,
+    SysResource,
+    Npcap,
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Privilege::Root => "root",
+            Privilege::NetRaw => "CAP_NET_RAW",
+            Privilege::SysResource => "CAP_SYS_RESOURCE",
+            Privilege::Npcap => "Npcap",
+        })
+    }
+}
+
+/// A feature which needs a privilege on some platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Raw sockets, for SYN scans and ARP or ICMP discovery. The connect and
+    /// UDP scans don't need them.
+    RawSockets,
+    /// A `--ulimit` above the hard file limit.
+    RaiseFileLimit,
+}
+
+impl Feature {
+    const ALL: [Feature; 2] = [Feature::RawSockets, Feature::RaiseFileLimit];
+
+    /// The privilege the feature needs on `platform`, None when it needs
+    /// none there.
+    pub fn privilege(self, platform: Platform) -> Option<Privilege> {
+        match (self, platform) {
+            (Feature::RawSockets, Platform::Linux) => Some(Privilege::NetRaw),
+            (Feature::RaiseFileLimit, Platform::Linux) => Some(Privilege::SysResource),
+            (_, Platform::Unix) => Some(Privilege::Root),
+            (Feature::RawSockets, Platform::Windows) => Some(Privilege::Npcap),
+            // Windows has no file limit to raise.
+            (Feature::RaiseFileLimit, Platform::Windows) => None,
+        }
+    }
+
+    /// What is used instead when the privilege is missing.
+    pub fn fallback(self) -> Option<&'static str> {
+        match self {
+            Feature::RawSockets => Some("TCP connect scans"),
+            Feature::RaiseFileLimit => Some("the hard file limit"),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::RawSockets => "raw sockets",
+            Feature::RaiseFileLimit => "raising the file limit past the hard limit",
+        })
+    }
+}
+
+/// Where the privileges of the process are read from.
+pub trait System {
+    /// The effective user id, None where there is none.
+    fn effective_uid(&self) -> Option<u32>;
+    /// The effective capability set, None where it can't be read.
+    fn effective_capabilities(&self) -> Option<u64>;
+    /// Whether the Npcap driver is installed.
+    fn has_npcap(&self) -> bool;
+}
+
+/// The system RustScan runs on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Host;
+
+impl System for Host {
+    #[cfg(unix)]
+    fn effective_uid(&self) -> Option<u32> {
+        // SAFETY: geteuid has no preconditions and never fails.
+        Some(unsafe { libc::geteuid() })
+    }
+
+    #[cfg(not(unix))]
+    fn effective_uid(&self) -> Option<u32> {
+        None
+    }
+
+    fn effective_capabilities(&self) -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        parse_capabilities(&status)
+    }
+
+    fn has_npcap(&self) -> bool {
+        std::env::var_os("SystemRoot").is_some_and(|root| {
+            std::path::Path::new(&root)
+                .join("System32")
+                .join("Npcap")
+                .join("wpcap.dll")
+                .is_file()
+        })
+    }
+}
+
+/// Reads the effective capability set out of `/proc/self/status`.
+pub fn parse_capabilities(status: &str) -> Option<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(line.trim(), 16).ok()
+}
+
+/// A feature asked for without the privilege it needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Missing {
+    pub feature: Feature,
+    pub privilege: Privilege,
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} needs {}", self.feature, self.privilege)?;
+        match self.feature.fallback() {
+            Some(fallback) => write!(f, ", falling back to {fallback}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The privileges of the process, and the features they allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityReport {
+    pub platform: Platform,
+    pub uid: Option<u32>,
+    pub held: Vec<Privilege>,
+}
+
+/// Works the privileges held by the process out of `system`.
+pub fn detect(system: &impl System, platform: Platform) -> CapabilityReport {
+    let uid = system.effective_uid();
+    let root = uid == Some(0);
+    let mut held = Vec::new();
+    match platform {
+        Platform::Linux => {
+            if root {
+                held.push(Privilege::Root);
+            }
+            // Root may have dropped capabilities, others may have been given
+            // some. Without a capability set, root is assumed to hold them.
+            let capabilities = system.effective_capabilities();
+            for (bit, privilege) in [
+                (CAP_NET_RAW, Privilege::NetRaw),
+                (CAP_SYS_RESOURCE, Privilege::SysResource),
+            ] {
+                if capabilities.map_or(root, |set| set & (1 << bit) != 0) {
+                    held.push(privilege);
+                }
+            }
+        }
+        Platform::Unix => {
+            if root {
+                held.push(Privilege::Root);
+            }
+        }
+        Platform::Windows => {
+            if system.has_npcap() {
+                held.push(Privilege::Npcap);
+            }
+        }
+    }
+    CapabilityReport {
+        platform,
+        uid,
+        held,
+    }
+}
+
+impl CapabilityReport {
+    pub fn has(&self, privilege: Privilege) -> bool {
+        self.held.contains(&privilege)
+    }
+
+    /// Whether `feature` can be used, what it's missing otherwise.
+    pub fn check(&self, feature: Feature) -> Result<(), Missing> {
+        match feature.privilege(self.platform) {
+            Some(privilege) if !self.has(privilege) => Err(Missing { feature, privilege }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The report printed by `--capabilities`.
+    pub fn render(&self) -> String {
+        let privileges: Vec<String> = self.held.iter().map(ToString::to_string).collect();
+        let mut lines = vec![
+            format!("Platform: {:?}", self.platform),
+            match self.uid {
+                Some(uid) => format!("Effective user id: {uid}"),
+                None => "Effective user id: none".to_owned(),
+            },
+            format!(
+                "Privileges: {}",
+                if privileges.is_empty() {
+                    "none".to_owned()
+                } else {
+                    privileges.join(", ")
+                }
+            ),
+            "Features:".to_owned(),
+        ];
+        for feature in Feature::ALL {
+            lines.push(match self.check(feature) {
+                Ok(()) => format!("  {feature}: available"),
+                Err(Missing { privilege, .. }) => match feature.fallback() {
+                    Some(fallback) => {
+                        format!("  {feature}: needs {privilege}, falls back to {fallback}")
+                    }
+                    None => format!("  {feature}: needs {privilege}"),
+                },
+            });
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, parse_capabilities, Feature, Missing, Platform, Privilege, System};
+
+    struct Fake {
+        uid: Option<u32>,
+        capabilities: Option<u64>,
+        npcap: bool,
+    }
+
+    impl System for Fake {
+        fn effective_uid(&self) -> Option<u32> {
+            self.uid
+        }
+
+        fn effective_capabilities(&self) -> Option<u64> {
+            self.capabilities
+        }
+
+        fn has_npcap(&self) -> bool {
+            self.npcap
+        }
+    }
+
+    fn fake(uid: Option<u32>, capabilities: Option<u64>, npcap: bool) -> Fake {
+        Fake {
+            uid,
+            capabilities,
+            npcap,
+        }
+    }
+
+    #[test]
+    fn linux_follows_the_capabilities() {
+        let user = detect(&fake(Some(1000), Some(0), false), Platform::Linux);
+        assert!(user.held.is_empty());
+        assert_eq!(
+            user.check(Feature::RawSockets),
+            Err(Missing {
+                feature: Feature::RawSockets,
+                privilege: Privilege::NetRaw
+            })
+        );
+
+        // setcap cap_net_raw+ep on the binary.
+        let capable = detect(&fake(Some(1000), Some(1 << 13), false), Platform::Linux);
+        assert_eq!(capable.held, [Privilege::NetRaw]);
+        assert_eq!(capable.check(Feature::RawSockets), Ok(()));
+        assert!(capable.check(Feature::RaiseFileLimit).is_err());
+
+        let root = detect(
+            &fake(Some(0), Some(0x1ff_ffff_ffff), false),
+            Platform::Linux,
+        );
+        assert_eq!(
+            root.held,
+            [Privilege::Root, Privilege::NetRaw, Privilege::SysResource]
+        );
+        // A container which dropped the capabilities of root.
+        let dropped = detect(&fake(Some(0), Some(0), false), Platform::Linux);
+        assert_eq!(dropped.held, [Privilege::Root]);
+        assert!(dropped.check(Feature::RawSockets).is_err());
+        // No capability set to read, root is trusted.
+        let unknown = detect(&fake(Some(0), None, false), Platform::Linux);
+        assert_eq!(unknown.check(Feature::RaiseFileLimit), Ok(()));
+    }
+
+    #[test]
+    fn other_unixes_need_root() {
+        let user = detect(&fake(Some(501), Some(u64::MAX), true), Platform::Unix);
+        assert!(user.held.is_empty());
+        assert_eq!(
+            user.check(Feature::RaiseFileLimit).unwrap_err().privilege,
+            Privilege::Root
+        );
+
+        let root = detect(&fake(Some(0), None, false), Platform::Unix);
+        assert_eq!(root.check(Feature::RawSockets), Ok(()));
+    }
+
+    #[test]
+    fn windows_needs_npcap() {
+        let without = detect(&fake(None, None, false), Platform::Windows);
+        assert_eq!(
+            without.check(Feature::RawSockets).unwrap_err().to_string(),
+            "raw sockets needs Npcap, falling back to TCP connect scans"
+        );
+        assert_eq!(without.check(Feature::RaiseFileLimit), Ok(()));
+
+        let with = detect(&fake(None, None, true), Platform::Windows);
+        assert_eq!(with.held, [Privilege::Npcap]);
+        assert_eq!(with.check(Feature::RawSockets), Ok(()));
+    }
+
+    #[test]
+    fn report_lists_the_features() {
+        let report = detect(&fake(Some(1000), Some(1 << 13), false), Platform::Linux);
+        assert_eq!(
+            report.render(),
+            "Platform: Linux\n\
+             Effective user id: 1000\n\
+             Privileges: CAP_NET_RAW\n\
+             Features:\n  \
+             raw sockets: available\n  \
+             raising the file limit past the hard limit: needs CAP_SYS_RESOURCE, falls back to the hard file limit"
+        );
+    }
+
+    #[test]
+    fn capabilities_are_read_from_the_status() {
+        let status = "Name:\trustscan\nCapInh:\t0000000000000000\nCapEff:\t0000000000002000\nCapBnd:\t000001ffffffffff\n";
+        assert_eq!(parse_capabilities(status), Some(1 << 13));
+        assert_eq!(parse_capabilities("Name:\trustscan\n"), None);
+    }
+}
@@ -0,0 +1,601 @@
+//! Guesses the service behind open ports with a short probe pipeline.
+//!
+//! Every port first gets the chance to speak: a banner is matched against
+//! known greetings. Silent ports get a TLS ClientHello when their number is
+//! a usual TLS one and an HTTP GET otherwise. The whole pipeline of a port
+//! runs within a fixed time budget and the probes of different ports run
+//! with their own concurrency limit, apart from the scan batch size.
+//!
+//! Whatever a port sends back is matched against the signatures of
+//! [`crate::hints`], the raw bytes are kept along with the guess.
+//!
+//! A scan given the prober, see [`crate::scanner::Scanner::with_probes`],
+//! hands the connection which found a port open over to the pipeline, so
+//! that a port is connected to only once. A banner ends the pipeline, so
+//! nothing read has to be sent again: only a port which hung up before
+//! speaking is connected to again, for the ClientHello or the GET.
+use crate::hints::{self, ProtocolHint};
+use async_std::io::{self, prelude::*};
+use async_std::net::TcpStream;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Ports which usually speak TLS straight away.
+pub const TLS_PORTS: [u16; 12] = [443, 465, 636, 853, 989, 990, 992, 993, 994, 995, 5061, 8443];
+
+/// How much of an answer is read by every step.
+const READ_SIZE: usize = 2048;
+
+/// Longest evidence kept from an answer.
+const EVIDENCE_LENGTH: usize = 80;
+
+/// How much of an answer is kept as raw bytes.
+const FIRST_BYTES_LENGTH: usize = 256;
+
+/// A TLS 1.2 ClientHello without server name, offering the usual suites.
+const CLIENT_HELLO: [u8; 96] = [
+    // Record: handshake, TLS 1.0 for compatibility, 91 bytes.
+    0x16, 0x03, 0x01, 0x00, 0x5b, //
+    // Handshake: ClientHello, 87 bytes, TLS 1.2.
+    0x01, 0x00, 0x00, 0x57, 0x03, 0x03, //
+    // Random.
+    0x52, 0x75, 0x73, 0x74, 0x53, 0x63, 0x61, 0x6e, 0x52, 0x75, 0x73, 0x74, 0x53, 0x63, 0x61, 0x6e,
+    0x52, 0x75, 0x73, 0x74, 0x53, 0x63, 0x61, 0x6e, 0x52, 0x75, 0x73, 0x74, 0x53, 0x63, 0x61,
+    0x6e, //
+    // No session id.
+    0x00, //
+    // Cipher suites: ECDHE-{RSA,ECDSA}-AES{128,256}-GCM, RSA-AES{128,256}-GCM,
+    // RSA-AES{128,256}-CBC-SHA.
+    0x00, 0x10, 0xc0, 0x2f, 0xc0, 0x30, 0xc0, 0x2b, 0xc0, 0x2c, 0x00, 0x9c, 0x00, 0x9d, 0x00, 0x2f,
+    0x00, 0x35, //
+    // Null compression.
+    0x01, 0x00, //
+    // Extensions, 30 bytes.
+    0x00, 0x1e, //
+    // Supported groups: x25519, P-256.
+    0x00, 0x0a, 0x00, 0x06, 0x00, 0x04, 0x00, 0x1d, 0x00, 0x17, //
+    // Uncompressed points.
+    0x00, 0x0b, 0x00, 0x02, 0x01, 0x00, //
+    // Signature algorithms: ECDSA-P256-SHA256, RSA-PSS-SHA256,
+    // RSA-PKCS1-SHA256, RSA-PKCS1-SHA384.
+    0x00, 0x0d, 0x00, 0x0a, 0x00, 0x08, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x01,
+];
+
+/// The step of the pipeline which produced a guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProbeStep {
+    /// The port could not be connected to again.
+    Connect,
+    /// The port spoke first.
+    Banner,
+    /// The port answered a TLS ClientHello.
+    TlsClientHello,
+    /// The port answered an HTTP GET.
+    HttpGet,
+}
+
+impl fmt::Display for ProbeStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ProbeStep::Connect => "connect",
+            ProbeStep::Banner => "banner",
+            ProbeStep::TlsClientHello => "TLS ClientHello",
+            ProbeStep::HttpGet => "HTTP GET",
+        })
+    }
+}
+
+/// What a port looks like, with the evidence that gave it away.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceGuess {
+    /// The guessed service, like "ssh" or "http". None when nothing gave the
+    /// service away.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    pub probe: ProbeStep,
+    pub evidence: String,
+    /// The protocol the answer of the port looks like, see [`hints`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol_hint: Option<ProtocolHint>,
+    /// The start of the answer, escaped. None when the port sent nothing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_bytes: Option<String>,
+}
+
+impl ServiceGuess {
+    fn new(service: Option<&str>, probe: ProbeStep, evidence: String) -> Self {
+        Self {
+            service: service.map(ToOwned::to_owned),
+            probe,
+            evidence,
+            protocol_hint: None,
+            first_bytes: None,
+        }
+    }
+
+    /// Keeps the hint and the raw bytes of `answer`.
+    fn with_answer(mut self, answer: &[u8]) -> Self {
+        self.protocol_hint = hints::hint(answer);
+        if !answer.is_empty() {
+            let first_bytes = &answer[..answer.len().min(FIRST_BYTES_LENGTH)];
+            self.first_bytes = Some(first_bytes.escape_ascii().to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for ServiceGuess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let service = self.service.as_deref().unwrap_or("unknown service");
+        write!(f, "{service} ({}: {})", self.probe, self.evidence)
+    }
+}
+
+/// Runs the probe pipeline against open ports.
+#[derive(Debug, Clone)]
+pub struct Prober {
+    budget: Duration,
+    tls_ports: Vec<u16>,
+}
+
+impl Prober {
+    /// A prober spending at most `budget` on every port.
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            tls_ports: TLS_PORTS.to_vec(),
+        }
+    }
+
+    /// Sets the ports which get a TLS ClientHello when they don't speak first.
+    #[must_use]
+    pub fn with_tls_ports(mut self, tls_ports: Vec<u16>) -> Self {
+        self.tls_ports = tls_ports;
+        self
+    }
+
+    /// Probes every socket of `sockets`, at most `concurrency` at a time.
+    pub async fn probe_all(
+        &self,
+        sockets: &[SocketAddr],
+        concurrency: usize,
+    ) -> HashMap<SocketAddr, ServiceGuess> {
+        let mut sockets = sockets.iter();
+        let mut ftrs = FuturesUnordered::new();
+        let mut guesses = HashMap::new();
+        let probe = |socket: SocketAddr| async move { (socket, self.probe(socket).await) };
+
+        for socket in sockets.by_ref().take(concurrency.max(1)) {
+            ftrs.push(probe(*socket));
+        }
+
+        while let Some((socket, guess)) = ftrs.next().await {
+            if let Some(socket) = sockets.next() {
+                ftrs.push(probe(*socket));
+            }
+            guesses.insert(socket, guess);
+        }
+
+        guesses
+    }
+
+    /// Runs the pipeline against `socket` within the time budget.
+    pub async fn probe(&self, socket: SocketAddr) -> ServiceGuess {
+        let deadline = Instant::now() + self.budget;
+        match connect(socket, self.budget).await {
+            Ok(stream) => self.run(socket, stream, deadline, false).await,
+            Err(guess) => guess,
+        }
+    }
+
+    /// Runs the pipeline against `socket` on `stream`, connected already,
+    /// within the time budget. The port is only connected to again when it
+    /// hung up before speaking.
+    pub async fn probe_stream(&self, socket: SocketAddr, stream: TcpStream) -> ServiceGuess {
+        let deadline = Instant::now() + self.budget;
+        self.run(socket, stream, deadline, true).await
+    }
+
+    async fn run(
+        &self,
+        socket: SocketAddr,
+        mut stream: TcpStream,
+        deadline: Instant,
+        reconnect: bool,
+    ) -> ServiceGuess {
+        let remaining = || deadline.saturating_duration_since(Instant::now());
+
+        // Services speaking first do so as soon as the connection is up,
+        // so they get a third of the budget.
+        let (banner, closed) = read_banner(&mut stream, remaining().min(self.budget / 3)).await;
+        if !banner.is_empty() {
+            return classify_banner(&banner);
+        }
+        if closed && reconnect {
+            stream = match connect(socket, remaining()).await {
+                Ok(stream) => stream,
+                Err(guess) => return guess,
+            };
+        }
+
+        if self.tls_ports.contains(&socket.port()) {
+            let answer = exchange(&mut stream, &CLIENT_HELLO, remaining()).await;
+            classify_tls(&answer)
+        } else {
+            let request = format!(
+                "GET / HTTP/1.0\r\nHost: {}\r\nUser-Agent: RustScan\r\n\r\n",
+                host_header(socket)
+            );
+            let answer = exchange(&mut stream, request.as_bytes(), remaining()).await;
+            classify_http(&answer)
+        }
+    }
+}
+
+/// Connects to `socket` within `wait`, the guess of a port which can't be
+/// connected to otherwise.
+async fn connect(socket: SocketAddr, wait: Duration) -> Result<TcpStream, ServiceGuess> {
+    io::timeout(wait, TcpStream::connect(socket))
+        .await
+        .map_err(|e| ServiceGuess::new(None, ProbeStep::Connect, e.to_string()))
+}
+
+/// Reads what the port sends first within `wait`, with whether it hung up.
+async fn read_banner(stream: &mut TcpStream, wait: Duration) -> (Vec<u8>, bool) {
+    let mut buffer = vec![0; READ_SIZE];
+    match io::timeout(wait, stream.read(&mut buffer)).await {
+        Ok(read) => {
+            buffer.truncate(read);
+            (buffer, read == 0)
+        }
+        Err(e) => (Vec::new(), e.kind() != io::ErrorKind::TimedOut),
+    }
+}
+
+/// Reads whatever the port sends within `wait`, nothing on timeout or error.
+async fn read_answer(stream: &mut TcpStream, wait: Duration) -> Vec<u8> {
+    let mut buffer = vec![0; READ_SIZE];
+    match io::timeout(wait, stream.read(&mut buffer)).await {
+        Ok(read) => {
+            buffer.truncate(read);
+            buffer
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Sends `request` and reads the answer within `wait`.
+async fn exchange(stream: &mut TcpStream, request: &[u8], wait: Duration) -> Vec<u8> {
+    let start = Instant::now();
+    if io::timeout(wait, stream.write_all(request)).await.is_err() {
+        return Vec::new();
+    }
+    read_answer(stream, wait.saturating_sub(start.elapsed())).await
+}
+
+fn host_header(socket: SocketAddr) -> String {
+    match socket {
+        SocketAddr::V4(socket) => socket.ip().to_string(),
+        SocketAddr::V6(socket) => format!("[{}]", socket.ip()),
+    }
+}
+
+/// Guesses the service from what a port sent first.
+fn classify_banner(banner: &[u8]) -> ServiceGuess {
+    let hint = hints::hint(banner);
+    let evidence = match hint {
+        Some(ProtocolHint::Tls) => tls_record(banner),
+        Some(ProtocolHint::Mysql) => mysql_version(banner)
+            .map(|version| format!("MySQL protocol 10 greeting, server {version}")),
+        _ => None,
+    };
+    let service = hint.and_then(ProtocolHint::service);
+    ServiceGuess::new(
+        service,
+        ProbeStep::Banner,
+        evidence.unwrap_or_else(|| printable(banner)),
+    )
+    .with_answer(banner)
+}
+
+/// Guesses the service from the answer to the TLS ClientHello.
+fn classify_tls(answer: &[u8]) -> ServiceGuess {
+    let guess = match tls_record(answer) {
+        Some(record) => ServiceGuess::new(Some("tls"), ProbeStep::TlsClientHello, record),
+        None if answer.is_empty() => ServiceGuess::new(
+            None,
+            ProbeStep::TlsClientHello,
+            String::from("No answer to the TLS ClientHello"),
+        ),
+        None => ServiceGuess::new(None, ProbeStep::TlsClientHello, printable(answer)),
+    };
+    guess.with_answer(answer)
+}
+
+/// Guesses the service from the answer to the HTTP GET.
+fn classify_http(answer: &[u8]) -> ServiceGuess {
+    if answer.starts_with(b"HTTP/") {
+        let text = String::from_utf8_lossy(answer);
+        let mut lines = text.lines();
+        let mut evidence = printable(lines.next().unwrap_or_default().as_bytes());
+        let server = lines.take_while(|line| !line.is_empty()).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("server").then(|| value.trim())
+        });
+        if let Some(server) = server {
+            evidence = printable(format!("{evidence}, Server: {server}").as_bytes());
+        }
+        return ServiceGuess::new(Some("http"), ProbeStep::HttpGet, evidence).with_answer(answer);
+    }
+
+    let guess = match tls_record(answer) {
+        Some(record) => ServiceGuess::new(Some("tls"), ProbeStep::HttpGet, record),
+        None if answer.is_empty() => ServiceGuess::new(
+            None,
+            ProbeStep::HttpGet,
+            String::from("No answer to the HTTP GET"),
+        ),
+        None => ServiceGuess::new(None, ProbeStep::HttpGet, printable(answer)),
+    };
+    guess.with_answer(answer)
+}
+
+/// Describes `answer` when it starts with a TLS handshake or alert record.
+fn tls_record(answer: &[u8]) -> Option<String> {
+    match answer {
+        [0x16, 0x03, minor, ..] => Some(format!("TLS handshake record, version 3.{minor}")),
+        [0x15, 0x03, minor, ..] => Some(format!("TLS alert record, version 3.{minor}")),
+        _ => None,
+    }
+}
+
+/// Reads the server version out of a MySQL protocol 10 greeting.
+fn mysql_version(banner: &[u8]) -> Option<String> {
+    // 3 bytes of length, the sequence id and the protocol version.
+    if banner.len() < 6 || banner[3] != 0 || banner[4] != 0x0a {
+        return None;
+    }
+    let version = &banner[5..];
+    let end = version.iter().position(|byte| *byte == 0)?;
+    let version = &version[..end];
+    if version.is_empty() || !version.iter().all(u8::is_ascii_graphic) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(version).into_owned())
+}
+
+/// The first line of `answer`, non printable bytes replaced with dots and cut
+/// to a readable length.
+fn printable(answer: &[u8]) -> String {
+    answer
+        .iter()
+        .take_while(|byte| **byte != b'\r' && **byte != b'\n')
+        .take(EVIDENCE_LENGTH)
+        .map(|&byte| {
+            if byte == b' ' || byte.is_ascii_graphic() {
+                char::from(byte)
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        classify_banner, printable, ProbeStep, Prober, ProtocolHint, ServiceGuess, CLIENT_HELLO,
+    };
+    use async_std::task::block_on;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const BUDGET: Duration = Duration::from_millis(900);
+
+    /// Serves every connection of a local listener with `serve`.
+    fn fixture<F>(serve: F) -> SocketAddr
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serve = Arc::new(serve);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let serve = Arc::clone(&serve);
+                thread::spawn(move || serve(stream));
+            }
+        });
+        addr
+    }
+
+    /// Reads the request of a client which doesn't get a banner.
+    fn request(stream: &mut TcpStream) -> Vec<u8> {
+        let mut buffer = vec![0; 1024];
+        let read = stream.read(&mut buffer).unwrap_or(0);
+        buffer.truncate(read);
+        buffer
+    }
+
+    fn probe(addr: SocketAddr, tls_ports: Vec<u16>) -> ServiceGuess {
+        block_on(Prober::new(BUDGET).with_tls_ports(tls_ports).probe(addr))
+    }
+
+    #[test]
+    fn banner_is_matched() {
+        let addr = fixture(|mut stream| {
+            stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+            thread::sleep(BUDGET);
+        });
+
+        assert_eq!(
+            probe(addr, vec![]),
+            ServiceGuess {
+                service: Some("ssh".to_owned()),
+                probe: ProbeStep::Banner,
+                evidence: "SSH-2.0-OpenSSH_9.6".to_owned(),
+                protocol_hint: Some(ProtocolHint::Ssh),
+                first_bytes: Some(r"SSH-2.0-OpenSSH_9.6\r\n".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn silent_tls_port_gets_a_client_hello() {
+        let addr = fixture(|mut stream| {
+            if request(&mut stream) == CLIENT_HELLO {
+                // The start of a ServerHello.
+                stream
+                    .write_all(&[0x16, 0x03, 0x03, 0x00, 0x31, 0x02])
+                    .unwrap();
+            }
+        });
+
+        assert_eq!(
+            probe(addr, vec![addr.port()]),
+            ServiceGuess {
+                service: Some("tls".to_owned()),
+                probe: ProbeStep::TlsClientHello,
+                evidence: "TLS handshake record, version 3.3".to_owned(),
+                protocol_hint: Some(ProtocolHint::Tls),
+                first_bytes: Some(r"\x16\x03\x03\x001\x02".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn silent_port_gets_an_http_get() {
+        let addr = fixture(|mut stream| {
+            if request(&mut stream).starts_with(b"GET / HTTP/1.0\r\n") {
+                stream
+                    .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nserver: nginx/1.25\r\n\r\n")
+                    .unwrap();
+            }
+        });
+
+        assert_eq!(
+            probe(addr, vec![]),
+            ServiceGuess {
+                service: Some("http".to_owned()),
+                probe: ProbeStep::HttpGet,
+                evidence: "HTTP/1.1 404 Not Found, Server: nginx/1.25".to_owned(),
+                protocol_hint: Some(ProtocolHint::Http),
+                first_bytes: Some(
+                    r"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nserver: nginx/1.25\r\n\r\n"
+                        .to_owned()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn handed_over_stream_is_connected_again_only_when_hung_up() {
+        // The first connection is hung up on, the others get their GET answered.
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&accepted);
+        let addr = fixture(move |mut stream| {
+            if counted.fetch_add(1, Ordering::SeqCst) > 0
+                && request(&mut stream).starts_with(b"GET / HTTP/1.0\r\n")
+            {
+                stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+            }
+        });
+        let prober = Prober::new(BUDGET);
+
+        let stream = block_on(async_std::net::TcpStream::connect(addr)).unwrap();
+        let guess = block_on(prober.probe_stream(addr, stream));
+        assert_eq!(guess.service, Some("http".to_owned()));
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+
+        let stream = block_on(async_std::net::TcpStream::connect(addr)).unwrap();
+        let guess = block_on(prober.probe_stream(addr, stream));
+        assert_eq!(guess.service, Some("http".to_owned()));
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn silent_port_is_bounded_by_the_budget() {
+        let addr = fixture(|mut stream| {
+            request(&mut stream);
+            thread::sleep(BUDGET * 3);
+        });
+
+        let start = Instant::now();
+        let guess = probe(addr, vec![]);
+
+        assert!(start.elapsed() < BUDGET + Duration::from_millis(300));
+        assert_eq!(guess.service, None);
+        assert_eq!(guess.probe, ProbeStep::HttpGet);
+    }
+
+    #[test]
+    fn closed_port_is_reported() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let guess = probe(addr, vec![]);
+        assert_eq!(guess.service, None);
+        assert_eq!(guess.probe, ProbeStep::Connect);
+    }
+
+    #[test]
+    fn probes_stay_within_their_concurrency() {
+        let current = Arc::new(AtomicUsize::new(0));
+        let highest = Arc::new(AtomicUsize::new(0));
+        let (current_fixture, highest_fixture) = (Arc::clone(&current), Arc::clone(&highest));
+        let addr = fixture(move |mut stream| {
+            let now = current_fixture.fetch_add(1, Ordering::SeqCst) + 1;
+            highest_fixture.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            current_fixture.fetch_sub(1, Ordering::SeqCst);
+            stream.write_all(b"+OK ready\r\n").unwrap();
+        });
+
+        let sockets = vec![addr; 12];
+        let guesses = block_on(Prober::new(BUDGET).probe_all(&sockets, 3));
+
+        assert_eq!(guesses[&addr].service.as_deref(), Some("pop3"));
+        assert!(highest.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn banners_are_recognized() {
+        let service = |banner: &[u8]| classify_banner(banner).service;
+
+        assert_eq!(
+            service(b"220 ProFTPD Server ready.\r\n").as_deref(),
+            Some("ftp")
+        );
+        assert_eq!(
+            service(b"220 mx.example.com ESMTP Postfix\r\n").as_deref(),
+            Some("smtp")
+        );
+        assert_eq!(
+            service(b"* OK [CAPABILITY IMAP4rev1] ready\r\n").as_deref(),
+            Some("imap")
+        );
+        assert_eq!(service(b"RFB 003.008\n").as_deref(), Some("vnc"));
+        assert_eq!(service(b"\x01\x02garbage").as_deref(), None);
+        let unknown = classify_banner(b"Welcome\x00\r\n");
+        assert_eq!(unknown.protocol_hint, None);
+        assert_eq!(unknown.first_bytes.as_deref(), Some(r"Welcome\x00\r\n"));
+
+        let mysql = b"\x4a\x00\x00\x00\x0a8.0.36\x00\x08\x00\x00\x00";
+        assert_eq!(
+            classify_banner(mysql).evidence,
+            "MySQL protocol 10 greeting, server 8.0.36"
+        );
+        assert_eq!(printable(b"a\tb\x00c\r\nnext line"), "a.b.c");
+    }
+}
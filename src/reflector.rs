@@ -0,0 +1,177 @@
+//! The reflector checking that the source ports of the probes make it out,
+//! see `--reflector`.
+//!
+//! A NAT in front of the scanner rewrites the source ports it doesn't like,
+//! so the ones picked by `--randomize-source-ports` may never reach the
+//! targets. The reflector is a tiny TCP service answering every connection
+//! with the address it came from, one line like `203.0.113.7:51234`, then
+//! closing it. Connecting to it from a port of our own tells whether the
+//! port came out as it was bound.
+//!
+//! `rustscan reflector` serves it, on [`DEFAULT_PORT`] unless told
+//! otherwise, to be run on a machine outside of the network scanned from.
+use crate::scanner::SourcePorts;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// The port of the reflector when `--reflector` gives none.
+pub const DEFAULT_PORT: u16 = 7077;
+
+/// The longest answer of a reflector, an IPv6 address and a port.
+const MAX_ANSWER: usize = 64;
+
+/// The answer of the reflector to the connection from `peer`.
+pub fn answer(peer: SocketAddr) -> String {
+    format!("{peer}\n")
+}
+
+/// Parses the answer of a reflector, the address it saw.
+pub fn parse_answer(line: &str) -> Result<SocketAddr, String> {
+    line.trim_end_matches(['\r', '\n'])
+        .parse()
+        .map_err(|_| format!("The reflector answered {line:?}, not an address."))
+}
+
+/// Resolves a `--reflector` argument, `host` or `host:port`.
+pub fn resolve(reflector: &str) -> io::Result<SocketAddr> {
+    let with_port = match reflector.to_socket_addrs() {
+        Ok(addrs) => addrs.collect::<Vec<SocketAddr>>(),
+        Err(_) => {
+            let host = reflector.trim_start_matches('[').trim_end_matches(']');
+            (host, DEFAULT_PORT).to_socket_addrs()?.collect()
+        }
+    };
+    with_port.into_iter().next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{reflector} has no address"),
+        )
+    })
+}
+
+/// The source address of a connection to the reflector, as bound and as the
+/// reflector saw it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reflection {
+    pub local: SocketAddr,
+    pub observed: SocketAddr,
+}
+
+impl Reflection {
+    /// Whether the source port came out as it was bound.
+    pub fn port_kept(&self) -> bool {
+        self.local.port() == self.observed.port()
+    }
+}
+
+impl fmt::Display for Reflection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "source port {} came out as {}",
+            self.local.port(),
+            self.observed
+        )
+    }
+}
+
+/// Connects to `reflector` from the source `port`, or one the OS picks,
+/// and reads back the address it saw.
+pub fn reflect(
+    reflector: SocketAddr,
+    port: Option<u16>,
+    timeout: Duration,
+) -> io::Result<Reflection> {
+    let socket = Socket::new(
+        Domain::for_address(reflector),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if let Some(port) = port {
+        socket.set_reuse_address(true)?;
+        socket.bind(&SourcePorts::local_addr(reflector, port).into())?;
+    }
+    socket.connect_timeout(&reflector.into(), timeout)?;
+    let stream = TcpStream::from(socket);
+    stream.set_read_timeout(Some(timeout))?;
+    let local = stream.local_addr()?;
+
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_ANSWER as u64)).read_line(&mut line)?;
+    let observed = parse_answer(&line).map_err(io::Error::other)?;
+    Ok(Reflection { local, observed })
+}
+
+/// Answers every connection of `listener` with the address it came from.
+#[cfg(not(tarpaulin_include))]
+pub fn serve(listener: &TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        if let Ok(peer) = stream.peer_addr() {
+            let _ = stream.write_all(answer(peer).as_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{answer, parse_answer, reflect, resolve, serve, DEFAULT_PORT};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn answers_are_parsed_back() {
+        for peer in ["203.0.113.7:51234", "[2001:db8::1]:40000"] {
+            let peer: SocketAddr = peer.parse().unwrap();
+            assert_eq!(parse_answer(&answer(peer)), Ok(peer));
+        }
+        assert_eq!(
+            parse_answer("203.0.113.7:51234\r\n"),
+            Ok("203.0.113.7:51234".parse().unwrap())
+        );
+        assert!(parse_answer("hello\n")
+            .unwrap_err()
+            .contains("\"hello\\n\""));
+        assert!(parse_answer("").is_err());
+    }
+
+    #[test]
+    fn reflectors_get_the_default_port() {
+        assert_eq!(
+            resolve("127.0.0.1").unwrap(),
+            SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))
+        );
+        assert_eq!(
+            resolve("127.0.0.1:9000").unwrap(),
+            SocketAddr::from(([127, 0, 0, 1], 9000))
+        );
+        assert_eq!(resolve("[::1]").unwrap(), "[::1]:7077".parse().unwrap());
+    }
+
+    #[test]
+    fn the_source_port_is_reflected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reflector = listener.local_addr().unwrap();
+        thread::spawn(move || serve(&listener));
+
+        let timeout = Duration::from_secs(2);
+        let picked = reflect(reflector, None, timeout).unwrap();
+        assert!(picked.port_kept(), "{}", picked);
+
+        // A port of our own, free since the OS just handed it out.
+        let free = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = free.local_addr().unwrap().port();
+        drop(free);
+        let bound = reflect(reflector, Some(port), timeout).unwrap();
+        assert_eq!(bound.local.port(), port);
+        assert_eq!(bound.observed, bound.local);
+        assert!(bound.port_kept());
+    }
+}
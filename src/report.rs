@@ -0,0 +1,616 @@
+//! Collects the results of a scan into a report which can be rendered as JSON.
+//!
+//! Results are buffered per host and put in a deterministic order, so two
+//! runs of the same scan print the same output whatever order the sockets
+//! answered in.
+use crate::address::{Target, Unresolved};
+use crate::discovery::Liveness;
+use crate::family::FamilyDecision;
+use crate::fingerprint::Fingerprint;
+use crate::groups::HostGroup;
+use crate::input::HostOrder;
+use crate::port_strategy::DefaultPorts;
+use crate::probe::ServiceGuess;
+use crate::rescan::PortProvenance;
+use crate::resources::ResourceUsage;
+use crate::scanner::{
+    AdminProhibition, Confidence, ConntrackBackoff, Forecast, HostInterface, HostOutage,
+    HostTimeout, NetworkOutage, Shard, Throttling, TriesDowngrade, TryOutcome,
+};
+use crate::schema::SchemaVersion;
+use crate::scripts::nmap::PortService;
+use crate::scripts::port_hooks::PortHookRun;
+use crate::scripts::ScriptRun;
+use crate::signing::ReportSignature;
+use crate::snmp::SnmpFinding;
+use crate::split::ReportPart;
+use serde_derive::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The results of a whole scan.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    /// The version of this document, see [`crate::schema`].
+    pub schema_version: SchemaVersion,
+    pub mode: Mode,
+    /// The engagement or run the results belong to, see `--run-id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Which part of the report this is, with `--output-split`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<ReportPart>,
+    pub hosts: Vec<HostReport>,
+    /// The hosts grouped by their open ports, with
+    /// `--group-by-fingerprint`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<HostGroup>,
+    /// The input hostnames which could not be resolved, with the error of
+    /// each.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unresolved: Vec<Unresolved>,
+    /// What the run covered, with `--shard`, and what it used, with
+    /// `--resource-report`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ScanStats>,
+    /// What was left of a scan stopped before its end, like with the `q` of
+    /// `--tui`, and how long it would have taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forecast: Option<Forecast>,
+    /// The ports scanned, when the run was given no `--ports` or `--range`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_ports: Option<PortDefaults>,
+    /// When the network of the scanner went down, with
+    /// `--pause-on-network-down`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub network_outages: Vec<NetworkOutage>,
+    /// When the scan slowed down for a full conntrack table, with
+    /// `--conntrack-backoff`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conntrack_backoffs: Vec<ConntrackBackoff>,
+    /// How many sockets were given more than once, and probed once.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_sockets: Option<u64>,
+    /// How the run was configured, and the hash of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// The signature of the report, with `--sign-key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ReportSignature>,
+}
+
+/// What a report is the results of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// A scan of the targets.
+    #[default]
+    Scan,
+    /// An egress check, see [`crate::egress`].
+    Egress,
+}
+
+/// The default port set a run fell back on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortDefaults {
+    pub set: DefaultPorts,
+    /// The ports of the set, like `80-82,443`.
+    pub ports: String,
+}
+
+/// The share of a sharded scan, summed up by `rustscan merge`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanStats {
+    /// The shards the report covers, one for a single run, none when the
+    /// scan wasn't sharded.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shards: Vec<Shard>,
+    /// How many sockets were probed.
+    pub sockets: u64,
+    pub open_ports: u64,
+    /// The resources the process used, with `--resource-report`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceUsage>,
+}
+
+/// Why a target was not scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// The other address family of the dual-stack hostname was scanned.
+    OtherFamily,
+    /// A script of the pre phase with `on_fail = "skip"` failed.
+    PreScanFailed,
+    /// The report of `--rescan-filtered` found no filtered ports on it.
+    NothingFiltered,
+    /// Its interface of `--interface-map` is down, missing or without an
+    /// address of its family.
+    InterfaceUnusable,
+    /// The discovery found it down, or assumed so.
+    HostDown,
+}
+
+/// The service guess of a single open port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortProbe {
+    pub port: u16,
+    pub service_guess: ServiceGuess,
+}
+
+/// How sure the tries of an open port make it, see [`Confidence`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortConfidence {
+    pub port: u16,
+    pub label: Confidence,
+    /// What every try of the port ended with, in order.
+    pub tries: Vec<TryOutcome>,
+}
+
+/// The results of a single host.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostReport {
+    pub ip: IpAddr,
+    /// Every input hostname which resolved to this address.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hostnames: Vec<String>,
+    /// The canonical name of the host among its hostnames, see
+    /// [`crate::alias`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Every input token the address was expanded from.
+    pub source: Vec<String>,
+    pub ports: Vec<u16>,
+    /// The ports open in the previous results which are closed now, with
+    /// `--only-previously-open`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub closed_since: Vec<u16>,
+    /// The ports which never answered, filtered or down. TCP scans only.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filtered: Vec<u16>,
+    /// Which run decided the state of every port, with `--rescan-filtered`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub provenance: Vec<PortProvenance>,
+    /// The notes of the host, with `--notes`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// Whether the host was probed, hosts without open ports included.
+    pub scanned: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped_reason: Option<SkipReason>,
+    /// What nmap found out about the open ports, with `--nmap-args`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<PortService>,
+    /// What the probe pipeline made of the open ports, with `--probe-all`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<PortProbe>,
+    /// How sure the tries of the open ports make them, for the ports tried
+    /// more than once. A port without is open by its only try.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub confidence: Vec<PortConfidence>,
+    /// The community the SNMP agent answered, with `--snmp-probe`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snmp: Option<SnmpFinding>,
+    /// When the host stopped answering its heartbeats, with `--heartbeat`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub outages: Vec<HostOutage>,
+    /// The timeout group of the host, with `--timeout-map`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HostTimeout>,
+    /// The interface the host was scanned by, with `--interface-map`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<HostInterface>,
+    /// What the discovery made of the host, with `--discovery`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<Liveness>,
+    /// How the tries of the host were lowered, with `--adaptive-tries`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tries_downgrade: Option<TriesDowngrade>,
+    /// How the host was slowed down after blocking the scan, with
+    /// `--throttle-window`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throttling: Option<Throttling>,
+    /// Why the ports left of the host weren't probed, when it rejected the
+    /// scan as administratively prohibited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_prohibited: Option<AdminProhibition>,
+    /// The last attempt of every script run against the host.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<ScriptRun>,
+    /// Every `--port-hook` run on the open ports of the host.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub port_hooks: Vec<PortHookRun>,
+    /// How the address family was picked, for dual-stack hostnames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<FamilyDecision>,
+}
+
+impl HostReport {
+    pub fn new(target: &Target, ports: Vec<u16>) -> Self {
+        Self {
+            ip: target.ip,
+            hostnames: target.hostnames.clone(),
+            name: None,
+            source: target.sources.clone(),
+            ports,
+            closed_since: Vec::new(),
+            filtered: Vec::new(),
+            provenance: Vec::new(),
+            notes: Vec::new(),
+            scanned: true,
+            skipped_reason: None,
+            services: Vec::new(),
+            probes: Vec::new(),
+            confidence: Vec::new(),
+            snmp: None,
+            outages: Vec::new(),
+            timeout: None,
+            interface: None,
+            discovery: None,
+            tries_downgrade: None,
+            throttling: None,
+            admin_prohibited: None,
+            scripts: Vec::new(),
+            port_hooks: Vec::new(),
+            family: None,
+        }
+    }
+
+    /// A record for a target which was dropped before scanning.
+    pub fn skipped(target: &Target, reason: SkipReason) -> Self {
+        Self {
+            scanned: false,
+            skipped_reason: Some(reason),
+            ..Self::new(target, Vec::new())
+        }
+    }
+
+    /// The open ports whose tries didn't agree.
+    pub fn flaky_ports(&self) -> Vec<u16> {
+        self.confidence
+            .iter()
+            .filter(|confidence| confidence.label == Confidence::Flaky)
+            .map(|confidence| confidence.port)
+            .collect()
+    }
+
+    /// The greppable line of this host, `ip -> [port,port]`.
+    pub fn greppable(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(ToString::to_string).collect();
+        // nmap port style is 80,443. Comma separated with no spaces.
+        format!("{} -> [{}]", self.ip, ports.join(","))
+    }
+}
+
+impl ScanReport {
+    /// Groups the open sockets by host, every scanned target gets a record
+    /// even without open ports. Hosts are sorted by `order` and ports
+    /// ascending within a host.
+    pub fn new(targets: &[Target], open: &[SocketAddr], order: HostOrder) -> Self {
+        let mut ports_per_ip: HashMap<IpAddr, Vec<u16>> = HashMap::new();
+        for socket in open {
+            ports_per_ip
+                .entry(socket.ip())
+                .or_default()
+                .push(socket.port());
+        }
+
+        let mut hosts: Vec<HostReport> = targets
+            .iter()
+            .map(|target| {
+                let mut ports = ports_per_ip.remove(&target.ip).unwrap_or_default();
+                ports.sort_unstable();
+                ports.dedup();
+                HostReport::new(target, ports)
+            })
+            .collect();
+
+        // Both sorts are stable, ties keep the input order.
+        match order {
+            HostOrder::Input => {}
+            HostOrder::Ip => hosts.sort_by_key(|host| host.ip),
+            HostOrder::OpenCount => hosts.sort_by_key(|host| Reverse(host.ports.len())),
+        }
+
+        Self {
+            schema_version: SchemaVersion::default(),
+            mode: Mode::Scan,
+            run_id: None,
+            part: None,
+            hosts,
+            groups: Vec::new(),
+            unresolved: Vec::new(),
+            stats: None,
+            forecast: None,
+            default_ports: None,
+            network_outages: Vec::new(),
+            conntrack_backoffs: Vec::new(),
+            duplicate_sockets: None,
+            fingerprint: None,
+            signature: None,
+        }
+    }
+
+    /// Labels the open ports of every host tried more than once with the
+    /// outcomes of their `tries`.
+    pub fn add_confidence(&mut self, tries: &HashMap<SocketAddr, Vec<TryOutcome>>) {
+        for host in &mut self.hosts {
+            let ip = host.ip;
+            host.confidence = host
+                .ports
+                .iter()
+                .filter_map(|&port| {
+                    let tries = tries.get(&SocketAddr::new(ip, port))?;
+                    Some(PortConfidence {
+                        port,
+                        label: Confidence::of(tries).filter(|_| tries.len() > 1)?,
+                        tries: tries.clone(),
+                    })
+                })
+                .collect();
+        }
+    }
+
+    /// Lists the `filtered` sockets of every host, sorted.
+    pub fn add_filtered(&mut self, filtered: &[SocketAddr]) {
+        for host in &mut self.hosts {
+            let mut ports: Vec<u16> = filtered
+                .iter()
+                .filter(|socket| socket.ip() == host.ip)
+                .map(SocketAddr::port)
+                .collect();
+            ports.sort_unstable();
+            ports.dedup();
+            host.filtered = ports;
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the scan report.")
+    }
+}
+
+/// A random UUID, of version 4, naming a run given no `--run-id`.
+pub fn new_run_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_run_id, HostReport, ScanReport, SkipReason};
+    use crate::address::{Target, Targets};
+    use crate::family::{Family, FamilyDecision, Reason};
+    use crate::input::HostOrder;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+    use std::net::SocketAddr;
+
+    fn target(ip: &str) -> Target {
+        Target {
+            ip: ip.parse().unwrap(),
+            hostnames: Vec::new(),
+            sources: vec![ip.to_owned()],
+            ports: None,
+        }
+    }
+
+    fn render(report: &ScanReport) -> String {
+        let lines: Vec<String> = report.hosts.iter().map(HostReport::greppable).collect();
+        format!("{}\n{}", lines.join("\n"), report.to_json())
+    }
+
+    fn shuffled_reports(order: HostOrder) -> Vec<String> {
+        let targets: Vec<Target> = ["10.0.0.9", "::1", "10.0.0.10", "192.0.2.1", "2001:db8::1"]
+            .iter()
+            .map(|ip| target(ip))
+            .collect();
+        let mut open: Vec<SocketAddr> = vec![
+            "10.0.0.9:443".parse().unwrap(),
+            "10.0.0.9:22".parse().unwrap(),
+            "[::1]:8080".parse().unwrap(),
+            "10.0.0.10:80".parse().unwrap(),
+            "10.0.0.10:22".parse().unwrap(),
+            "10.0.0.10:21".parse().unwrap(),
+            "[2001:db8::1]:53".parse().unwrap(),
+        ];
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(106);
+        (0..50)
+            .map(|_| {
+                open.shuffle(&mut rng);
+                render(&ScanReport::new(&targets, &open, order))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn output_is_identical_across_permutations() {
+        for order in [HostOrder::Input, HostOrder::Ip, HostOrder::OpenCount] {
+            let outputs = shuffled_reports(order);
+            assert!(outputs.iter().all(|output| output == &outputs[0]));
+        }
+    }
+
+    #[test]
+    fn hosts_are_sorted_by_order() {
+        let ips = |order| -> Vec<String> {
+            let zero = shuffled_reports(order).remove(0);
+            zero.lines()
+                .take_while(|line| !line.starts_with('{'))
+                .map(|line| line.split(' ').next().unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            ips(HostOrder::Input),
+            ["10.0.0.9", "::1", "10.0.0.10", "192.0.2.1", "2001:db8::1"]
+        );
+        // IPv4 before IPv6, numeric within a family.
+        assert_eq!(
+            ips(HostOrder::Ip),
+            ["10.0.0.9", "10.0.0.10", "192.0.2.1", "::1", "2001:db8::1"]
+        );
+        // Ties keep the input order.
+        assert_eq!(
+            ips(HostOrder::OpenCount),
+            ["10.0.0.10", "10.0.0.9", "::1", "2001:db8::1", "192.0.2.1"]
+        );
+    }
+
+    #[test]
+    fn ports_are_sorted_within_a_host() {
+        let open: Vec<SocketAddr> = vec![
+            "10.0.0.5:443".parse().unwrap(),
+            "10.0.0.5:22".parse().unwrap(),
+            "10.0.0.5:80".parse().unwrap(),
+        ];
+        let report = ScanReport::new(&[target("10.0.0.5")], &open, HostOrder::Input);
+
+        assert_eq!(report.hosts[0].greppable(), "10.0.0.5 -> [22,80,443]");
+    }
+
+    #[test]
+    fn empty_and_skipped_hosts_get_records() {
+        let open: Vec<SocketAddr> = vec!["127.0.0.1:80".parse().unwrap()];
+        let mut report = ScanReport::new(
+            &[target("127.0.0.1"), target("192.0.2.1")],
+            &open,
+            HostOrder::Input,
+        );
+        report.hosts.push(HostReport::skipped(
+            &target("2001:db8::1"),
+            SkipReason::OtherFamily,
+        ));
+
+        assert_eq!(report.hosts[1].greppable(), "192.0.2.1 -> []");
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["hosts"][1]["ports"], serde_json::json!([]));
+        assert_eq!(json["hosts"][1]["scanned"], true);
+        assert!(json["hosts"][1].get("skipped_reason").is_none());
+        assert_eq!(json["hosts"][2]["scanned"], false);
+        assert_eq!(json["hosts"][2]["skipped_reason"], "other-family");
+    }
+
+    #[test]
+    fn json_merges_hostnames_of_duplicate_targets() {
+        let mut targets = Targets::default();
+        targets.insert("10.0.0.5".parse().unwrap(), "10.0.0.0/24", None);
+        targets.insert(
+            "10.0.0.5".parse().unwrap(),
+            "app.internal",
+            Some("app.internal"),
+        );
+        targets.insert(
+            "10.0.0.5".parse().unwrap(),
+            "db.internal",
+            Some("db.internal"),
+        );
+
+        let report = ScanReport {
+            hosts: targets
+                .hosts
+                .iter()
+                .map(|target| HostReport::new(target, vec![80]))
+                .collect(),
+            ..ScanReport::default()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["hosts"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            json["hosts"][0]["hostnames"],
+            serde_json::json!(["app.internal", "db.internal"])
+        );
+        assert_eq!(
+            json["hosts"][0]["source"],
+            serde_json::json!(["10.0.0.0/24", "app.internal", "db.internal"])
+        );
+    }
+
+    #[test]
+    fn json_includes_family_decision() {
+        let mut host = HostReport::new(&target("192.0.2.1"), vec![22, 80]);
+        host.family = Some(FamilyDecision {
+            hostname: "dual.example".to_owned(),
+            family: Family::Ipv4,
+            address: "192.0.2.1".parse().unwrap(),
+            reason: Reason::Fallback,
+        });
+        let report = ScanReport {
+            hosts: vec![host, HostReport::new(&target("::1"), vec![443])],
+            ..ScanReport::default()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["hosts"][0]["ports"], serde_json::json!([22, 80]));
+        assert_eq!(json["hosts"][0]["family"]["family"], "ipv4");
+        assert_eq!(json["hosts"][0]["family"]["reason"], "fallback");
+        assert!(json["hosts"][1].get("family").is_none());
+        assert!(json["hosts"][1].get("hostnames").is_none());
+    }
+
+    #[test]
+    fn run_ids_are_random_uuids() {
+        let id = new_run_id();
+        let groups: Vec<usize> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert!(id.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        // Version 4, of the RFC 4122 variant.
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+        assert_ne!(new_run_id(), id);
+
+        let report = ScanReport {
+            run_id: Some(id.clone()),
+            ..ScanReport::default()
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["run_id"], id);
+        assert!(
+            serde_json::from_str::<serde_json::Value>(&ScanReport::default().to_json()).unwrap()
+                ["run_id"]
+                .is_null()
+        );
+    }
+
+    #[test]
+    fn open_ports_tried_more_than_once_are_labeled() {
+        use crate::scanner::TryOutcome::{Open, Refused, TimedOut};
+        use std::collections::HashMap;
+
+        let socket = |port| SocketAddr::new("192.0.2.1".parse().unwrap(), port);
+        let mut report = ScanReport {
+            hosts: vec![HostReport::new(&target("192.0.2.1"), vec![22, 80, 443])],
+            ..ScanReport::default()
+        };
+        let tries: HashMap<SocketAddr, Vec<_>> = vec![
+            (socket(22), vec![Open]),
+            (socket(80), vec![Open, TimedOut, Refused]),
+            (socket(443), vec![Open, Open]),
+        ]
+        .into_iter()
+        .collect();
+        report.add_confidence(&tries);
+
+        assert_eq!(report.hosts[0].flaky_ports(), [80]);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(
+            json["hosts"][0]["confidence"],
+            serde_json::json!([
+                { "port": 80, "label": "flaky", "tries": ["open", "timed-out", "refused"] },
+                { "port": 443, "label": "confirmed-open", "tries": ["open", "open"] },
+            ])
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! The rescans of `--rescan-filtered`, probing again only the ports a
+//! previous report found filtered, and combining the outcomes with it.
+//!
+//! The ports of a rescanned host are the ones the previous report found
+//! open and the filtered ones which opened since. Every port the previous
+//! report knew of gets a [`PortProvenance`] telling which run decided its
+//! state: the previous run for its open ports, the rescan for its filtered
+//! ones, open, closed or still filtered now. The hosts without filtered
+//! ports aren't scanned, they are kept as the previous report had them.
+use crate::address::Target;
+use crate::previous::PreviousResults;
+use crate::report::{HostReport, SkipReason};
+use serde_derive::Serialize;
+
+/// The state of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+}
+
+/// The run which decided the state of a port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortProvenance {
+    pub port: u16,
+    pub state: PortState,
+    /// Whether the rescan decided it, rather than the previous run.
+    pub rescanned: bool,
+    /// The run id of the run which decided it, when that run had one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+/// Combines the results of the rescan of `host`, by the run `run_id`, with
+/// what `previous` found of it.
+pub fn combine(previous: &PreviousResults, host: &mut HostReport, run_id: &str) {
+    let kept = previous.open_ports(host.ip).unwrap_or_default();
+    let rescanned = previous.filtered_ports(host.ip).unwrap_or_default();
+    let mut provenance: Vec<PortProvenance> = kept
+        .iter()
+        .map(|&port| PortProvenance {
+            port,
+            state: PortState::Open,
+            rescanned: false,
+            run_id: previous.run_id.clone(),
+        })
+        .collect();
+    provenance.extend(rescanned.iter().map(|&port| PortProvenance {
+        port,
+        state: if host.ports.contains(&port) {
+            PortState::Open
+        } else if host.filtered.contains(&port) {
+            PortState::Filtered
+        } else {
+            PortState::Closed
+        },
+        rescanned: true,
+        run_id: Some(run_id.to_owned()),
+    }));
+    provenance.sort_by_key(|provenance| provenance.port);
+
+    host.ports.extend(kept);
+    host.ports.sort_unstable();
+    host.ports.dedup();
+    host.provenance = provenance;
+}
+
+/// The hosts of `previous`, read from `source`, which weren't rescanned for
+/// having no filtered ports, with the ports they had open.
+pub fn unscanned(
+    previous: &PreviousResults,
+    source: &str,
+    scanned: &[HostReport],
+) -> Vec<HostReport> {
+    previous
+        .all_ips()
+        .into_iter()
+        .filter(|ip| previous.filtered_ports(*ip).is_none())
+        .filter(|ip| !scanned.iter().any(|host| host.ip == *ip))
+        .map(|ip| {
+            let target = Target {
+                ip,
+                hostnames: Vec::new(),
+                sources: vec![source.to_owned()],
+                ports: None,
+            };
+            let mut host = HostReport::skipped(&target, SkipReason::NothingFiltered);
+            combine(previous, &mut host, "");
+            host
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, unscanned, PortProvenance, PortState};
+    use crate::address::Target;
+    use crate::previous::PreviousResults;
+    use crate::report::{HostReport, SkipReason};
+
+    const PREVIOUS: &str = r#"{
+        "run_id": "before",
+        "hosts": [
+            {"ip": "10.0.0.1", "ports": [22], "filtered": [25, 443, 8080]},
+            {"ip": "10.0.0.2", "ports": [80]},
+            {"ip": "10.0.0.3", "ports": [], "filtered": []}
+        ]
+    }"#;
+
+    fn provenance(port: u16, state: PortState, run_id: Option<&str>) -> PortProvenance {
+        PortProvenance {
+            port,
+            state,
+            rescanned: run_id == Some("after"),
+            run_id: run_id.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn rescans_are_combined_with_the_previous_results() {
+        let previous = PreviousResults::from_json(PREVIOUS).unwrap();
+        let target = Target {
+            ip: "10.0.0.1".parse().unwrap(),
+            hostnames: Vec::new(),
+            sources: vec!["10.0.0.1".to_owned()],
+            ports: None,
+        };
+        // 443 opened, 8080 is still filtered and 25 was refused.
+        let mut host = HostReport::new(&target, vec![443]);
+        host.filtered = vec![8080];
+
+        combine(&previous, &mut host, "after");
+        assert_eq!(host.ports, [22, 443]);
+        assert_eq!(
+            host.provenance,
+            [
+                provenance(22, PortState::Open, Some("before")),
+                provenance(25, PortState::Closed, Some("after")),
+                provenance(443, PortState::Open, Some("after")),
+                provenance(8080, PortState::Filtered, Some("after")),
+            ]
+        );
+        let json = serde_json::to_value(&host.provenance[1]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"port": 25, "state": "closed", "rescanned": true, "run_id": "after"})
+        );
+    }
+
+    #[test]
+    fn hosts_without_filtered_ports_are_kept_as_they_were() {
+        let previous = PreviousResults::from_json(PREVIOUS).unwrap();
+        let hosts = unscanned(&previous, "previous.json", &[]);
+        let ips: Vec<String> = hosts.iter().map(|host| host.ip.to_string()).collect();
+        assert_eq!(ips, ["10.0.0.2", "10.0.0.3"]);
+        assert!(!hosts[0].scanned);
+        assert_eq!(hosts[0].skipped_reason, Some(SkipReason::NothingFiltered));
+        assert_eq!(hosts[0].source, ["previous.json"]);
+        assert_eq!(hosts[0].ports, [80]);
+        assert_eq!(
+            hosts[0].provenance,
+            [provenance(80, PortState::Open, Some("before"))]
+        );
+        assert!(hosts[1].provenance.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+//! The resources the process uses during a scan, see `--resource-report`.
+//!
+//! A [`Sampler`] thread takes a [`Sample`] out of a [`Meter`] when it
+//! starts, every [`INTERVAL`] and once more when it's finished, and folds
+//! them into a [`ResourceUsage`]: the peaks of the open descriptors and of
+//! the resident memory, and the CPU time spent so far. The peak memory is
+//! the one the kernel kept track of, the descriptors only the most seen by
+//! a sample, open between two samples they go unnoticed. [`Process`] is the
+//! meter of this process, which reads what the platform tells: a sample
+//! misses the figures a platform has no way to read, the usage leaving them
+//! unknown rather than failing.
+use serde_derive::Serialize;
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the resources are sampled during the scan.
+pub const INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a [`Meter`] read at one point, None for what it can't read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub open_descriptors: Option<u64>,
+    /// The peak resident memory so far, in bytes.
+    pub peak_rss_bytes: Option<u64>,
+    /// The CPU time spent so far.
+    pub user_cpu: Option<Duration>,
+    pub system_cpu: Option<Duration>,
+}
+
+/// Where the samples are read from.
+pub trait Meter {
+    fn sample(&self) -> Sample;
+}
+
+/// The meter of this process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Process;
+
+impl Meter for Process {
+    #[cfg(unix)]
+    fn sample(&self) -> Sample {
+        // SAFETY: getrusage only writes to the struct it's given, which is
+        // plain old data.
+        let usage = unsafe {
+            let mut usage: libc::rusage = std::mem::zeroed();
+            (libc::getrusage(libc::RUSAGE_SELF, &mut usage) == 0).then_some(usage)
+        };
+        let time = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+        };
+        // Linux and the BSDs count the peak in kilobytes, macOS in bytes.
+        let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+        Sample {
+            open_descriptors: open_descriptors(),
+            peak_rss_bytes: usage.map(|usage| usage.ru_maxrss as u64 * rss_unit),
+            user_cpu: usage.map(|usage| time(usage.ru_utime)),
+            system_cpu: usage.map(|usage| time(usage.ru_stime)),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn sample(&self) -> Sample {
+        Sample {
+            open_descriptors: open_descriptors(),
+            ..Sample::default()
+        }
+    }
+}
+
+/// The descriptors the process has open, where they can be listed.
+fn open_descriptors() -> Option<u64> {
+    ["/proc/self/fd", "/dev/fd"]
+        .iter()
+        .find_map(|dir| std::fs::read_dir(dir).ok())
+        // Listing the directory takes a descriptor of its own.
+        .map(|entries| entries.count().saturating_sub(1) as u64)
+}
+
+/// The resources used over the samples, null in JSON when unknown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    pub peak_open_descriptors: Option<u64>,
+    pub peak_rss_bytes: Option<u64>,
+    pub user_cpu_ms: Option<u64>,
+    pub system_cpu_ms: Option<u64>,
+    pub samples: u32,
+}
+
+impl ResourceUsage {
+    /// Folds `sample` into the usage, keeping the highest figures.
+    pub fn record(&mut self, sample: &Sample) {
+        let ms = |time: Option<Duration>| time.map(|time| time.as_millis() as u64);
+        self.peak_open_descriptors = self.peak_open_descriptors.max(sample.open_descriptors);
+        self.peak_rss_bytes = self.peak_rss_bytes.max(sample.peak_rss_bytes);
+        self.user_cpu_ms = self.user_cpu_ms.max(ms(sample.user_cpu));
+        self.system_cpu_ms = self.system_cpu_ms.max(ms(sample.system_cpu));
+        self.samples += 1;
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known = |figure: Option<String>| figure.unwrap_or_else(|| "unknown".to_owned());
+        write!(
+            f,
+            "Resources: peak of {} open descriptors, peak RSS {}, CPU time {} user and {} system",
+            known(self.peak_open_descriptors.map(|count| count.to_string())),
+            known(
+                self.peak_rss_bytes
+                    .map(|bytes| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)))
+            ),
+            known(
+                self.user_cpu_ms
+                    .map(|ms| format!("{:.2}s", ms as f64 / 1000.0))
+            ),
+            known(
+                self.system_cpu_ms
+                    .map(|ms| format!("{:.2}s", ms as f64 / 1000.0))
+            ),
+        )
+    }
+}
+
+/// Samples a [`Meter`] from a thread of its own until it's finished.
+#[derive(Debug)]
+pub struct Sampler {
+    stop: Sender<()>,
+    thread: JoinHandle<ResourceUsage>,
+}
+
+impl Sampler {
+    pub fn start(meter: impl Meter + Send + 'static, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut usage = ResourceUsage::default();
+            loop {
+                usage.record(&meter.sample());
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            usage.record(&meter.sample());
+            usage
+        });
+        Self { stop, thread }
+    }
+
+    /// Stops sampling, with a last sample, and gives the usage.
+    pub fn finish(self) -> ResourceUsage {
+        let _ = self.stop.send(());
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Meter, Process, ResourceUsage, Sample, Sampler};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn sample(descriptors: u64, rss: Option<u64>, user_ms: u64) -> Sample {
+        Sample {
+            open_descriptors: Some(descriptors),
+            peak_rss_bytes: rss,
+            user_cpu: Some(Duration::from_millis(user_ms)),
+            system_cpu: None,
+        }
+    }
+
+    #[test]
+    fn usage_keeps_the_peaks() {
+        let mut usage = ResourceUsage::default();
+        for sample in [
+            sample(10, None, 5),
+            sample(240, Some(8 << 20), 40),
+            sample(12, Some(6 << 20), 90),
+        ] {
+            usage.record(&sample);
+        }
+        assert_eq!(
+            usage,
+            ResourceUsage {
+                peak_open_descriptors: Some(240),
+                peak_rss_bytes: Some(8 << 20),
+                user_cpu_ms: Some(90),
+                system_cpu_ms: None,
+                samples: 3,
+            }
+        );
+        assert_eq!(
+            usage.to_string(),
+            "Resources: peak of 240 open descriptors, peak RSS 8.0 MiB, \
+             CPU time 0.09s user and unknown system"
+        );
+    }
+
+    /// Counts its samples, every one seeing one more descriptor.
+    struct Counting(Arc<AtomicU64>);
+
+    impl Meter for Counting {
+        fn sample(&self) -> Sample {
+            Sample {
+                open_descriptors: Some(self.0.fetch_add(1, Ordering::SeqCst) + 1),
+                ..Sample::default()
+            }
+        }
+    }
+
+    #[test]
+    fn sampler_samples_until_finished() {
+        let taken = Arc::new(AtomicU64::new(0));
+        let sampler = Sampler::start(Counting(Arc::clone(&taken)), Duration::from_millis(10));
+        std::thread::sleep(Duration::from_millis(100));
+        let usage = sampler.finish();
+
+        let taken = taken.load(Ordering::SeqCst);
+        assert!(taken >= 3, "{}", taken);
+        assert_eq!(u64::from(usage.samples), taken);
+        assert_eq!(usage.peak_open_descriptors, Some(taken));
+        assert_eq!(usage.peak_rss_bytes, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn this_process_is_measured() {
+        let sample = Process.sample();
+        assert!(sample.open_descriptors.unwrap() >= 3);
+        assert!(sample.peak_rss_bytes.unwrap() > 1 << 20);
+        assert!(sample.user_cpu.is_some() && sample.system_cpu.is_some());
+    }
+}
@@ -0,0 +1,245 @@
+//! Adaptive tries, which stop retrying hosts that never answer.
+//!
+//! A firewalled host drops every probe, so each of its ports costs `tries`
+//! timeouts. Once a host stayed silent, neither accepting nor refusing a
+//! connection, for a number of probes in a row, its ports are only tried once,
+//! optionally with a shorter timeout. The first answer gives it its full
+//! tries back.
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// When and how the tries of silent hosts are lowered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveTries {
+    /// How many probes in a row a host has to leave unanswered.
+    pub silent_probes: u32,
+    /// The timeout of the probes of silent hosts, the scanner's when None.
+    pub timeout: Option<Duration>,
+}
+
+/// What a probe outcome changed about the tries of a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Downgraded,
+    Restored,
+}
+
+/// The tries of a single host, fed with the outcome of its probes.
+#[derive(Debug, Clone)]
+pub struct HostPolicy {
+    config: AdaptiveTries,
+    /// The probes left unanswered since the last answer.
+    silent: u32,
+    downgraded: bool,
+    /// How many times the host got downgraded so far.
+    downgrades: u32,
+}
+
+impl HostPolicy {
+    pub fn new(config: AdaptiveTries) -> Self {
+        Self {
+            config,
+            silent: 0,
+            downgraded: false,
+            downgrades: 0,
+        }
+    }
+
+    /// The tries of the next probe, out of the scanner's `tries`.
+    pub fn tries(&self, tries: u8) -> u8 {
+        if self.downgraded {
+            1
+        } else {
+            tries
+        }
+    }
+
+    /// The timeout of the next probe, out of the scanner's `timeout`.
+    pub fn timeout(&self, timeout: Duration) -> Duration {
+        match (self.downgraded, self.config.timeout) {
+            (true, Some(shorter)) => shorter.min(timeout),
+            _ => timeout,
+        }
+    }
+
+    /// Records whether the host answered a probe.
+    pub fn record(&mut self, answered: bool) -> Option<Change> {
+        if answered {
+            self.silent = 0;
+            if self.downgraded {
+                self.downgraded = false;
+                return Some(Change::Restored);
+            }
+            return None;
+        }
+
+        self.silent = self.silent.saturating_add(1);
+        if !self.downgraded && self.silent >= self.config.silent_probes {
+            self.downgraded = true;
+            self.downgrades += 1;
+            return Some(Change::Downgraded);
+        }
+        None
+    }
+
+    pub fn is_downgraded(&self) -> bool {
+        self.downgraded
+    }
+}
+
+/// How the tries of a host were lowered during the scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TriesDowngrade {
+    #[serde(skip)]
+    pub ip: IpAddr,
+    /// The tries of the probes while downgraded.
+    pub tries: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// How many probes in a row were left unanswered before the downgrade.
+    pub after_silent_probes: u32,
+    /// How many times the host got downgraded.
+    pub downgrades: u32,
+    /// Whether the host was still downgraded at the end of the scan.
+    pub until_the_end: bool,
+}
+
+/// The policies of every host of a scan, shared by their probes.
+#[derive(Debug)]
+pub(crate) struct HostPolicies {
+    config: AdaptiveTries,
+    hosts: Mutex<HashMap<IpAddr, HostPolicy>>,
+}
+
+impl HostPolicies {
+    pub fn new(config: AdaptiveTries) -> Self {
+        Self {
+            config,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The tries and timeout of the next probe of `ip`.
+    pub fn limits(&self, ip: IpAddr, tries: u8, timeout: Duration) -> (u8, Duration) {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(&ip).map_or((tries, timeout), |policy| {
+            (policy.tries(tries), policy.timeout(timeout))
+        })
+    }
+
+    pub fn record(&self, ip: IpAddr, answered: bool) -> Option<Change> {
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(ip)
+            .or_insert_with(|| HostPolicy::new(self.config))
+            .record(answered)
+    }
+
+    /// The hosts which got downgraded at least once.
+    pub fn into_downgrades(self) -> Vec<TriesDowngrade> {
+        let config = self.config;
+        self.hosts
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .filter(|(_, policy)| policy.downgrades > 0)
+            .map(|(ip, policy)| TriesDowngrade {
+                ip,
+                tries: 1,
+                timeout_ms: config.timeout.map(|timeout| timeout.as_millis() as u64),
+                after_silent_probes: config.silent_probes,
+                downgrades: policy.downgrades,
+                until_the_end: policy.is_downgraded(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdaptiveTries, Change, HostPolicies, HostPolicy};
+    use std::time::Duration;
+
+    const CONFIG: AdaptiveTries = AdaptiveTries {
+        silent_probes: 3,
+        timeout: Some(Duration::from_millis(500)),
+    };
+    const TIMEOUT: Duration = Duration::from_millis(1_500);
+
+    #[test]
+    fn silent_host_is_downgraded() {
+        let mut policy = HostPolicy::new(CONFIG);
+        assert_eq!(policy.record(false), None);
+        assert_eq!(policy.record(false), None);
+        assert_eq!((policy.tries(3), policy.timeout(TIMEOUT)), (3, TIMEOUT));
+
+        assert_eq!(policy.record(false), Some(Change::Downgraded));
+        assert_eq!(
+            (policy.tries(3), policy.timeout(TIMEOUT)),
+            (1, Duration::from_millis(500))
+        );
+        // Staying silent changes nothing more.
+        assert_eq!(policy.record(false), None);
+    }
+
+    #[test]
+    fn single_answer_restores_full_tries() {
+        let mut policy = HostPolicy::new(CONFIG);
+        for _ in 0..5 {
+            policy.record(false);
+        }
+
+        assert_eq!(policy.record(true), Some(Change::Restored));
+        assert_eq!((policy.tries(3), policy.timeout(TIMEOUT)), (3, TIMEOUT));
+        // The silent streak starts over.
+        policy.record(false);
+        policy.record(false);
+        assert!(!policy.is_downgraded());
+    }
+
+    #[test]
+    fn answers_in_between_keep_full_tries() {
+        let mut policy = HostPolicy::new(CONFIG);
+        for answered in [false, false, true, false, false, true, false, false] {
+            assert_eq!(policy.record(answered), None);
+        }
+        assert_eq!(policy.tries(3), 3);
+    }
+
+    #[test]
+    fn timeout_is_never_raised() {
+        let mut policy = HostPolicy::new(AdaptiveTries {
+            silent_probes: 1,
+            timeout: Some(Duration::from_secs(10)),
+        });
+        policy.record(false);
+        assert_eq!(policy.timeout(TIMEOUT), TIMEOUT);
+
+        let mut policy = HostPolicy::new(AdaptiveTries {
+            silent_probes: 1,
+            timeout: None,
+        });
+        policy.record(false);
+        assert_eq!((policy.tries(2), policy.timeout(TIMEOUT)), (1, TIMEOUT));
+    }
+
+    #[test]
+    fn hosts_are_tracked_apart() {
+        let policies = HostPolicies::new(CONFIG);
+        let (silent, chatty) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        for _ in 0..3 {
+            policies.record(silent, false);
+            policies.record(chatty, true);
+        }
+
+        assert_eq!(policies.limits(silent, 3, TIMEOUT).0, 1);
+        assert_eq!(policies.limits(chatty, 3, TIMEOUT).0, 3);
+        let downgrades = policies.into_downgrades();
+        assert_eq!(downgrades.len(), 1);
+        assert_eq!(downgrades[0].ip, silent);
+        assert!(downgrades[0].until_the_end);
+    }
+}
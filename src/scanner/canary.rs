@@ -0,0 +1,58 @@
+//! Canary ports, probed on every host before the rest of its ports.
+//!
+//! The canaries go in front of the order the port strategy picked, added to
+//! the ports of a host which don't have them. With a first pass they are
+//! done on every host before any other port is probed on any of them.
+
+/// The ports of `--canary-ports`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Canaries {
+    pub ports: Vec<u16>,
+    /// Whether every host is done with its canaries before any other port
+    /// is probed.
+    pub first_pass: bool,
+}
+
+impl Canaries {
+    pub fn contains(&self, port: u16) -> bool {
+        self.ports.contains(&port)
+    }
+
+    /// `ports` with the canaries in front, in the order they were given.
+    pub fn prepend(&self, ports: Vec<u16>) -> Vec<u16> {
+        let mut order: Vec<u16> = Vec::with_capacity(ports.len() + self.ports.len());
+        for port in &self.ports {
+            if !order.contains(port) {
+                order.push(*port);
+            }
+        }
+        order.extend(ports.into_iter().filter(|port| !self.contains(*port)));
+        order
+    }
+
+    /// How many ports at the front of `ports` are canaries.
+    pub fn leading(&self, ports: &[u16]) -> usize {
+        ports
+            .iter()
+            .take_while(|port| self.contains(**port))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Canaries;
+
+    #[test]
+    fn canaries_go_first() {
+        let canaries = Canaries {
+            ports: vec![3389, 445, 3389],
+            first_pass: false,
+        };
+        // Moved to the front when the order has them, added when it doesn't.
+        let order = canaries.prepend(vec![80, 445, 22]);
+        assert_eq!(order, [3389, 445, 80, 22]);
+        assert_eq!(canaries.leading(&order), 2);
+        assert_eq!(canaries.leading(&[80, 445]), 0);
+    }
+}
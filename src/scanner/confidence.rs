@@ -0,0 +1,164 @@
+//! How sure the tries of a socket make its state, see [`Confidence`].
+//!
+//! The scanner keeps what every try of a TCP socket ended with as a
+//! [`TryOutcome`]. The tries of a socket stop at the first one which
+//! connects, unless [`super::Scanner::with_verified_open`] makes the rest of
+//! them go too, so that a port which connects once and times out the next,
+//! like a flaky service or one behind fail2ban, isn't silently taken for
+//! open. [`Confidence::of`] folds the outcomes of the tries, in whatever
+//! order they came:
+//!
+//! - every try connected: confirmed-open,
+//! - some tries connected and some didn't: flaky,
+//! - none connected and at least one was refused: confirmed-closed, the
+//!   host answered for the port,
+//! - none connected nor was refused, they timed out or failed otherwise:
+//!   filtered.
+//!
+//! Only the outcomes of the sockets which connected at least once are kept
+//! in [`super::ScanOutcome::tries`], the others being closed or filtered.
+use serde_derive::Serialize;
+use std::fmt;
+use std::io;
+
+/// What a single try of a socket ended with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TryOutcome {
+    Open,
+    Refused,
+    TimedOut,
+    /// Any other error, like an unreachable host.
+    Failed,
+}
+
+impl TryOutcome {
+    pub fn of<T>(result: &io::Result<T>) -> Self {
+        match result {
+            Ok(_) => TryOutcome::Open,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => TryOutcome::Refused,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => TryOutcome::TimedOut,
+            Err(_) => TryOutcome::Failed,
+        }
+    }
+}
+
+/// The state the tries of a socket agree on, or that they don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Confidence {
+    ConfirmedOpen,
+    Flaky,
+    ConfirmedClosed,
+    Filtered,
+}
+
+impl Confidence {
+    /// Folds the outcomes of the tries of a socket, None without any.
+    pub fn of(tries: &[TryOutcome]) -> Option<Self> {
+        if tries.is_empty() {
+            return None;
+        }
+        let open = tries
+            .iter()
+            .filter(|&&tried| tried == TryOutcome::Open)
+            .count();
+        Some(if open == tries.len() {
+            Confidence::ConfirmedOpen
+        } else if open > 0 {
+            Confidence::Flaky
+        } else if tries.contains(&TryOutcome::Refused) {
+            Confidence::ConfirmedClosed
+        } else {
+            Confidence::Filtered
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Confidence::ConfirmedOpen => "confirmed-open",
+            Confidence::Flaky => "flaky",
+            Confidence::ConfirmedClosed => "confirmed-closed",
+            Confidence::Filtered => "filtered",
+        }
+    }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Confidence, TryOutcome};
+    use std::io;
+
+    const OUTCOMES: [TryOutcome; 4] = [
+        TryOutcome::Open,
+        TryOutcome::Refused,
+        TryOutcome::TimedOut,
+        TryOutcome::Failed,
+    ];
+
+    /// The most tries checked, every combination of their outcomes being.
+    const MAX_TRIES: u32 = 6;
+
+    #[test]
+    fn outcomes_of_results() {
+        let error = |kind| io::Result::<()>::Err(io::Error::from(kind));
+        assert_eq!(TryOutcome::of(&Ok(())), TryOutcome::Open);
+        assert_eq!(
+            TryOutcome::of(&error(io::ErrorKind::ConnectionRefused)),
+            TryOutcome::Refused
+        );
+        assert_eq!(
+            TryOutcome::of(&error(io::ErrorKind::TimedOut)),
+            TryOutcome::TimedOut
+        );
+        assert_eq!(
+            TryOutcome::of(&error(io::ErrorKind::HostUnreachable)),
+            TryOutcome::Failed
+        );
+    }
+
+    #[test]
+    fn every_combination_of_tries() {
+        assert_eq!(Confidence::of(&[]), None);
+        for tries in 1..=MAX_TRIES {
+            for combination in 0..4usize.pow(tries) {
+                let outcomes: Vec<TryOutcome> = (0..tries)
+                    .map(|index| OUTCOMES[combination / 4usize.pow(index) % 4])
+                    .collect();
+                let count = |outcome| outcomes.iter().filter(|&&tried| tried == outcome).count();
+                let (open, refused) = (count(TryOutcome::Open), count(TryOutcome::Refused));
+                let expected = match (open, refused) {
+                    (open, _) if open == outcomes.len() => Confidence::ConfirmedOpen,
+                    (0, 0) => Confidence::Filtered,
+                    (0, _) => Confidence::ConfirmedClosed,
+                    _ => Confidence::Flaky,
+                };
+                assert_eq!(Confidence::of(&outcomes), Some(expected), "{:?}", outcomes);
+
+                // The order of the tries doesn't matter.
+                let mut reversed = outcomes.clone();
+                reversed.reverse();
+                assert_eq!(Confidence::of(&reversed), Some(expected), "{:?}", outcomes);
+            }
+        }
+    }
+
+    #[test]
+    fn labels_are_kebab_case() {
+        assert_eq!(Confidence::Flaky.to_string(), "flaky");
+        assert_eq!(
+            serde_json::to_string(&Confidence::ConfirmedOpen).unwrap(),
+            "\"confirmed-open\""
+        );
+        assert_eq!(
+            serde_json::to_string(&TryOutcome::TimedOut).unwrap(),
+            "\"timed-out\""
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Notices a connection tracking table filling up in the middle of a scan.
+//!
+//! A fast scan through a Linux NAT router, or from a machine tracking its
+//! own connections, fills the conntrack table of the kernel, which then
+//! drops the packets of every new connection. Hosts which were answering
+//! all stop at once and the rest of the scan turns into filtered ports,
+//! without this machine seeing a single error. When most of the last
+//! probes of hosts which answered before time out, and none failed here,
+//! the batch size of the scan is halved; the table of this machine is read
+//! to tell whether it's the full one.
+use super::network::ErrorWindow;
+use super::throttle::Outcome;
+use serde_derive::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where the kernel tells the size of its conntrack table.
+pub const CONNTRACK_DIR: &str = "/proc/sys/net/netfilter";
+
+/// The share of the table in use from which it's considered full.
+const FULL: f64 = 0.9;
+
+/// When the table is considered full, and how the scan slows down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conntrack {
+    /// How many of the last probes of answering hosts the timeouts are
+    /// counted over.
+    pub window: usize,
+    /// The share of timeouts over the window, from 0 to 1, at which the
+    /// table is considered full.
+    pub threshold: f64,
+    /// How many times the batch size may be halved at most.
+    pub max_slowdown: u32,
+    /// The directory with `nf_conntrack_count` and `nf_conntrack_max`, none
+    /// where there is no such thing.
+    pub table: Option<PathBuf>,
+}
+
+impl Conntrack {
+    /// The conntrack table of this machine, on Linux only.
+    pub fn local_table() -> Option<PathBuf> {
+        cfg!(target_os = "linux").then(|| PathBuf::from(CONNTRACK_DIR))
+    }
+}
+
+/// How full a conntrack table is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ConntrackUsage {
+    pub count: u64,
+    pub max: u64,
+}
+
+impl ConntrackUsage {
+    /// Reads the usage of the table from `dir`, like [`CONNTRACK_DIR`].
+    pub fn read(dir: &Path) -> io::Result<Self> {
+        let read = |name: &str| -> io::Result<u64> {
+            fs::read_to_string(dir.join(name))?
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        Ok(Self {
+            count: read("nf_conntrack_count")?,
+            max: read("nf_conntrack_max")?,
+        })
+    }
+
+    /// The share of the table in use, from 0 to 1.
+    pub fn ratio(&self) -> f64 {
+        if self.max == 0 {
+            return 0.0;
+        }
+        self.count as f64 / self.max as f64
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.ratio() >= FULL
+    }
+}
+
+/// Notices the hosts which answered going silent all at once, while no
+/// probe fails on this machine.
+#[derive(Debug, Clone)]
+pub struct CliffDetector {
+    /// The hosts which answered at least once.
+    responsive: HashSet<IpAddr>,
+    /// Whether each of the last probes of responsive hosts timed out.
+    window: ErrorWindow,
+    size: usize,
+    /// The probes recorded since the last local error.
+    clean: usize,
+}
+
+impl CliffDetector {
+    pub fn new(window: usize, threshold: f64) -> Self {
+        Self {
+            responsive: HashSet::new(),
+            window: ErrorWindow::new(window, threshold),
+            size: window.max(1),
+            clean: 0,
+        }
+    }
+
+    /// Records how a probe of `ip` ended, true when the answering hosts
+    /// just fell off a cliff. Timeouts of hosts which never answered are
+    /// filtered ports, and tell nothing.
+    pub fn record(&mut self, ip: IpAddr, outcome: Outcome) -> bool {
+        let silent = match outcome {
+            Outcome::Answered => {
+                self.responsive.insert(ip);
+                false
+            }
+            Outcome::TimedOut if self.responsive.contains(&ip) => true,
+            Outcome::TimedOut => return false,
+            // Errors of this machine are someone else's problem, like the
+            // network going down.
+            Outcome::Failed => {
+                self.clean = 0;
+                return false;
+            }
+        };
+        self.clean += 1;
+        if !self.window.record(silent) || self.clean < self.size {
+            return false;
+        }
+        // A whole new window has to fill before the next cliff.
+        self.window.clear();
+        self.clean = 0;
+        true
+    }
+}
+
+/// A time the scan slowed down for a full conntrack table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConntrackBackoff {
+    /// When the answering hosts went silent, in RFC 3339 format.
+    pub detected_at: String,
+    /// The batch size the scan went on with.
+    pub batch_size: usize,
+    /// The table of this machine at the time, when it could be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ConntrackUsage>,
+}
+
+/// Lowers the batch size of a scan each time a cliff is detected.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    table: Option<PathBuf>,
+    detector: CliffDetector,
+    batch_size: usize,
+    min_batch_size: usize,
+    backoffs: Vec<ConntrackBackoff>,
+}
+
+impl Backoff {
+    pub fn new(conntrack: Conntrack, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        Self {
+            detector: CliffDetector::new(conntrack.window, conntrack.threshold),
+            table: conntrack.table,
+            batch_size,
+            min_batch_size: (batch_size / conntrack.max_slowdown.max(1) as usize).max(1),
+            backoffs: Vec::new(),
+        }
+    }
+
+    /// The most probes in flight at a time.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Records how a probe of `ip` ended, the backoff when the scan just
+    /// slowed down.
+    pub fn probed(&mut self, ip: IpAddr, outcome: Outcome) -> Option<&ConntrackBackoff> {
+        if !self.detector.record(ip, outcome) {
+            return None;
+        }
+        self.batch_size = (self.batch_size / 2).max(self.min_batch_size);
+        let usage = self
+            .table
+            .as_deref()
+            .and_then(|dir| ConntrackUsage::read(dir).ok());
+        self.backoffs.push(ConntrackBackoff {
+            detected_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            batch_size: self.batch_size,
+            usage,
+        });
+        self.backoffs.last()
+    }
+
+    pub fn into_backoffs(self) -> Vec<ConntrackBackoff> {
+        self.backoffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, CliffDetector, Conntrack, ConntrackUsage};
+    use crate::scanner::Outcome;
+    use std::fs;
+    use std::net::IpAddr;
+
+    // Records the outcomes of `pattern` round robin over 10.0.0.1 and
+    // 10.0.0.2, returning the probes at which a cliff was detected.
+    fn feed(detector: &mut CliffDetector, pattern: &str) -> Vec<usize> {
+        pattern
+            .chars()
+            .enumerate()
+            .filter(|(probe, outcome)| {
+                let ip = IpAddr::from([10, 0, 0, 1 + (*probe % 2) as u8]);
+                let outcome = match outcome {
+                    'a' => Outcome::Answered,
+                    't' => Outcome::TimedOut,
+                    _ => Outcome::Failed,
+                };
+                detector.record(ip, outcome)
+            })
+            .map(|(probe, _)| probe)
+            .collect()
+    }
+
+    #[test]
+    fn answering_hosts_going_silent_is_a_cliff() {
+        let mut detector = CliffDetector::new(4, 0.75);
+        assert_eq!(feed(&mut detector, "aaaattt"), [6]);
+        // A new window has to fill first.
+        assert_eq!(feed(&mut detector, "ttt"), Vec::<usize>::new());
+        assert_eq!(feed(&mut detector, "t"), [0]);
+
+        // Hosts which never answered are filtered, not silenced.
+        let mut detector = CliffDetector::new(4, 0.75);
+        assert!(feed(&mut detector, "tttttttt").is_empty());
+
+        // Errors of this machine point somewhere else.
+        let mut detector = CliffDetector::new(4, 0.75);
+        assert!(feed(&mut detector, "aattftt").is_empty());
+        assert_eq!(feed(&mut detector, "tt"), [1]);
+    }
+
+    #[test]
+    fn usage_is_read_from_the_table() {
+        let dir = std::env::temp_dir().join(format!("rustscan-conntrack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(ConntrackUsage::read(&dir).is_err());
+        fs::write(dir.join("nf_conntrack_count"), "62000\n").unwrap();
+        fs::write(dir.join("nf_conntrack_max"), "65536\n").unwrap();
+        let usage = ConntrackUsage::read(&dir);
+
+        let mut backoff = Backoff::new(
+            Conntrack {
+                window: 2,
+                threshold: 1.0,
+                max_slowdown: 4,
+                table: Some(dir.clone()),
+            },
+            1_000,
+        );
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        backoff.probed(ip, Outcome::Answered);
+        for _ in 0..6 {
+            backoff.probed(ip, Outcome::TimedOut);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let usage = usage.unwrap();
+        assert_eq!((usage.count, usage.max), (62_000, 65_536));
+        assert!(usage.is_full());
+        // Halved three times, but not below a quarter of the batch size.
+        assert_eq!(backoff.batch_size(), 250);
+        let backoffs = backoff.into_backoffs();
+        assert_eq!(backoffs.len(), 3);
+        assert_eq!(backoffs[0].batch_size, 500);
+        assert_eq!(backoffs[0].usage, Some(usage));
+    }
+}
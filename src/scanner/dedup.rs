@@ -0,0 +1,116 @@
+//! Keeps a socket from being scheduled twice in a scan.
+//!
+//! The ports of a host come from a single list, the one of the port
+//! strategy or of its override, with the canaries in front. Ports given
+//! twice, like `-p 80,80` or `web=80,443;web=80`, are dropped from that list
+//! before anything is scheduled, and addresses given twice are scanned
+//! once. Lists are shared by every host which has them, so the ports seen
+//! are tracked once per list, never per host: a set while there are few of
+//! them, a bitmap of every port beyond.
+use std::collections::HashSet;
+
+/// How many ports a set holds before it turns into a bitmap, which takes
+/// the 8 KiB of 65536 bits whatever the ports.
+const SPARSE_MAX: usize = 2_048;
+
+/// The ports seen so far.
+#[derive(Debug, Clone)]
+pub enum PortSet {
+    Sparse(HashSet<u16>),
+    Bitmap(Box<[u64; 1024]>),
+}
+
+impl PortSet {
+    /// An empty set, a bitmap right away for more than [`SPARSE_MAX`] ports.
+    pub fn with_capacity(ports: usize) -> Self {
+        if ports > SPARSE_MAX {
+            PortSet::Bitmap(Box::new([0; 1024]))
+        } else {
+            PortSet::Sparse(HashSet::with_capacity(ports))
+        }
+    }
+
+    /// Adds `port`, true when it wasn't seen before.
+    pub fn insert(&mut self, port: u16) -> bool {
+        match self {
+            PortSet::Sparse(ports) => {
+                let inserted = ports.insert(port);
+                if ports.len() > SPARSE_MAX {
+                    let mut bitmap = Box::new([0; 1024]);
+                    for port in ports.iter() {
+                        set(&mut bitmap, *port);
+                    }
+                    *self = PortSet::Bitmap(bitmap);
+                }
+                inserted
+            }
+            PortSet::Bitmap(bitmap) => set(bitmap, port),
+        }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        match self {
+            PortSet::Sparse(ports) => ports.contains(&port),
+            PortSet::Bitmap(bitmap) => bitmap[usize::from(port) / 64] & bit(port) != 0,
+        }
+    }
+}
+
+fn bit(port: u16) -> u64 {
+    1 << (port % 64)
+}
+
+/// Sets the bit of `port`, true when it wasn't set.
+fn set(bitmap: &mut [u64; 1024], port: u16) -> bool {
+    let word = &mut bitmap[usize::from(port) / 64];
+    let unset = *word & bit(port) == 0;
+    *word |= bit(port);
+    unset
+}
+
+/// `ports` with the first of every port only, in order, and how many
+/// repeats were dropped.
+pub fn unique(mut ports: Vec<u16>) -> (Vec<u16>, usize) {
+    if ports.len() < 2 {
+        return (ports, 0);
+    }
+    let before = ports.len();
+    let mut seen = PortSet::with_capacity(before);
+    ports.retain(|port| seen.insert(*port));
+    let repeats = before - ports.len();
+    (ports, repeats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unique, PortSet, SPARSE_MAX};
+
+    #[test]
+    fn repeats_are_dropped_in_order() {
+        assert_eq!(
+            unique(vec![443, 80, 443, 22, 80, 443]),
+            (vec![443, 80, 22], 3)
+        );
+        assert_eq!(unique(vec![80]), (vec![80], 0));
+
+        // Big lists go through the bitmap.
+        let mut ports: Vec<u16> = (1..=u16::MAX).collect();
+        ports.extend(1..=100);
+        let (ports, repeats) = unique(ports);
+        assert_eq!((ports.len(), repeats), (65_535, 100));
+    }
+
+    #[test]
+    fn sparse_sets_turn_into_bitmaps() {
+        let mut set = PortSet::with_capacity(0);
+        for port in 0..=SPARSE_MAX as u16 {
+            assert!(set.insert(port * 3));
+        }
+        assert!(matches!(set, PortSet::Bitmap(_)));
+        assert!(!set.insert(0));
+        assert!(set.contains(3 * SPARSE_MAX as u16));
+        assert!(!set.contains(1));
+        assert!(set.insert(u16::MAX));
+        assert!(set.contains(u16::MAX));
+    }
+}
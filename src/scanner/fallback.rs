@@ -0,0 +1,203 @@
+//! The other family of dual-stack hosts, scanned in place of the family
+//! which won the race once its first batch got no response at all, see
+//! [`crate::family`].
+//!
+//! A refused connection is a response too, a host whose ports are all
+//! closed didn't fall back. The first batch of an address is its first
+//! probes, as many as the batch size or as it has ports. The rest of its
+//! sockets are left out once it falls back, the other address being scanned
+//! with the same ports.
+use super::TryOutcome;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+
+/// An address scanned in place of another, see [`super::Scanner::with_fallbacks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fallback {
+    /// The address whose first batch got no response.
+    pub from: IpAddr,
+    pub to: IpAddr,
+    /// The sockets of `to`.
+    pub sockets: usize,
+}
+
+/// The first batch of an address with a fallback, while it is probed.
+struct FirstBatch {
+    fallback: IpAddr,
+    sockets: usize,
+    left: usize,
+    responses: usize,
+}
+
+/// Counts the responses to the first batch of the addresses with a
+/// fallback, during a scan.
+pub(crate) struct Fallbacks {
+    pending: HashMap<IpAddr, FirstBatch>,
+    /// The addresses which fell back, their sockets left are skipped.
+    abandoned: HashSet<IpAddr>,
+    /// The addresses to scan instead, not handed out yet.
+    released: Vec<IpAddr>,
+    fallbacks: Vec<Fallback>,
+}
+
+impl Fallbacks {
+    /// Watches the `hosts` of a scan which have an address in `fallbacks`,
+    /// with first batches of `batch_size`.
+    pub fn new(
+        fallbacks: &HashMap<IpAddr, IpAddr>,
+        hosts: &[(IpAddr, &[u16])],
+        batch_size: usize,
+    ) -> Self {
+        let pending = hosts
+            .iter()
+            .filter(|(_, ports)| !ports.is_empty())
+            .filter_map(|(ip, ports)| {
+                let fallback = *fallbacks.get(ip)?;
+                let first = FirstBatch {
+                    fallback,
+                    sockets: ports.len(),
+                    left: batch_size.clamp(1, ports.len()),
+                    responses: 0,
+                };
+                Some((*ip, first))
+            })
+            .collect();
+        Self {
+            pending,
+            abandoned: HashSet::new(),
+            released: Vec::new(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// The first probe of `socket` is over with `outcome`. Returns the
+    /// fallback of its address once its first batch got no response, the
+    /// address to scan instead being released.
+    pub fn probed(&mut self, socket: SocketAddr, outcome: TryOutcome) -> Option<Fallback> {
+        let ip = socket.ip();
+        let first = self.pending.get_mut(&ip)?;
+        if matches!(outcome, TryOutcome::Open | TryOutcome::Refused) {
+            first.responses += 1;
+        }
+        first.left -= 1;
+        if first.left > 0 {
+            return None;
+        }
+        let first = self.pending.remove(&ip)?;
+        if first.responses > 0 {
+            return None;
+        }
+        let fallback = Fallback {
+            from: ip,
+            to: first.fallback,
+            sockets: first.sockets,
+        };
+        self.abandoned.insert(ip);
+        self.released.push(first.fallback);
+        self.fallbacks.push(fallback);
+        Some(fallback)
+    }
+
+    /// The addresses released since the last call.
+    pub fn take_released(&mut self) -> Vec<IpAddr> {
+        std::mem::take(&mut self.released)
+    }
+
+    /// Whether the sockets left of `ip` are skipped, it fell back.
+    pub fn is_abandoned(&self, ip: IpAddr) -> bool {
+        self.abandoned.contains(&ip)
+    }
+
+    pub fn into_fallbacks(self) -> Vec<Fallback> {
+        self.fallbacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fallback, Fallbacks};
+    use crate::scanner::TryOutcome;
+    use std::collections::HashMap;
+    use std::net::{IpAddr, SocketAddr};
+
+    const PORTS: [u16; 4] = [22, 80, 443, 8080];
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn fallbacks(batch_size: usize) -> Fallbacks {
+        let pairs = HashMap::from([
+            (ip("192.0.2.1"), ip("2001:db8::1")),
+            (ip("192.0.2.2"), ip("2001:db8::2")),
+        ]);
+        Fallbacks::new(
+            &pairs,
+            &[
+                (ip("192.0.2.1"), &PORTS),
+                (ip("192.0.2.2"), &PORTS),
+                (ip("192.0.2.3"), &PORTS),
+            ],
+            batch_size,
+        )
+    }
+
+    fn socket(address: &str, port: u16) -> SocketAddr {
+        SocketAddr::new(ip(address), port)
+    }
+
+    #[test]
+    fn silent_first_batch_falls_back() {
+        let mut fallbacks = fallbacks(2);
+        assert_eq!(
+            fallbacks.probed(socket("192.0.2.1", 22), TryOutcome::TimedOut),
+            None
+        );
+        let fallback = Fallback {
+            from: ip("192.0.2.1"),
+            to: ip("2001:db8::1"),
+            sockets: 4,
+        };
+        assert_eq!(
+            fallbacks.probed(socket("192.0.2.1", 80), TryOutcome::Failed),
+            Some(fallback)
+        );
+        assert!(fallbacks.is_abandoned(ip("192.0.2.1")));
+        assert_eq!(fallbacks.take_released(), [ip("2001:db8::1")]);
+        assert!(fallbacks.take_released().is_empty());
+        // The first batch is over.
+        assert_eq!(
+            fallbacks.probed(socket("192.0.2.1", 443), TryOutcome::TimedOut),
+            None
+        );
+        assert_eq!(fallbacks.into_fallbacks(), [fallback]);
+    }
+
+    #[test]
+    fn refused_is_a_response() {
+        let mut fallbacks = fallbacks(1);
+        assert_eq!(
+            fallbacks.probed(socket("192.0.2.2", 22), TryOutcome::Refused),
+            None
+        );
+        assert!(!fallbacks.is_abandoned(ip("192.0.2.2")));
+        // Hosts without a fallback are left alone.
+        assert_eq!(
+            fallbacks.probed(socket("192.0.2.3", 22), TryOutcome::TimedOut),
+            None
+        );
+        assert!(fallbacks.into_fallbacks().is_empty());
+    }
+
+    #[test]
+    fn first_batch_is_at_most_the_ports() {
+        let mut fallbacks = fallbacks(4500);
+        for port in &PORTS[..3] {
+            let probed = fallbacks.probed(socket("192.0.2.1", *port), TryOutcome::TimedOut);
+            assert_eq!(probed, None);
+        }
+        assert!(fallbacks
+            .probed(socket("192.0.2.1", 8080), TryOutcome::TimedOut)
+            .is_some());
+    }
+}
@@ -0,0 +1,110 @@
+//! What a scan reports while it runs, and how it is steered from outside.
+//!
+//! The scanner sends a [`ScanUpdate`] down the channel given to
+//! [`super::Scanner::with_feed`] as sockets are probed, and checks the
+//! [`ScanControl`] given to [`super::Scanner::with_control`] before every
+//! socket it starts. Nothing here knows who listens or steers, the
+//! dashboard of `--tui` being one of them.
+use super::Forecast;
+use crate::probe::ServiceGuess;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A step of a running scan.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanUpdate {
+    /// The scan starts with these hosts, each with its amount of sockets.
+    Started {
+        hosts: Vec<(IpAddr, usize)>,
+        batch_size: u16,
+    },
+    /// The first probe of a socket is over, whatever it found. A socket it
+    /// found open was told [`ScanUpdate::Open`] just before.
+    Probed(SocketAddr),
+    /// A socket is open, found by its first probe or a retry.
+    Open(SocketAddr),
+    /// A socket was left out because its host was skipped.
+    Skipped(SocketAddr),
+    /// When the scan should be done, sent every [`super::forecast::SAMPLE`].
+    Forecast(Forecast),
+    /// The probe pipeline guessed the service of an open socket, on the
+    /// connection which found it open, see [`super::Scanner::with_probes`].
+    Served(SocketAddr, ServiceGuess),
+}
+
+/// Pauses, slows down, skips hosts of, or stops a running scan, from any
+/// thread.
+#[derive(Debug, Clone, Default)]
+pub struct ScanControl {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: AtomicBool,
+    /// Paused by a guard like the one of `--allowed-window`, whatever the
+    /// user does.
+    held: AtomicBool,
+    stopped: AtomicBool,
+    skipped: Mutex<HashSet<IpAddr>>,
+    /// The most probes in flight, none when 0.
+    batch_limit: AtomicUsize,
+}
+
+impl ScanControl {
+    /// Holds back new probes while `paused`, the ones in flight finish.
+    pub fn set_paused(&self, paused: bool) {
+        self.state.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::Relaxed)
+    }
+
+    /// Holds back new probes like [`ScanControl::set_paused`], apart from
+    /// it, so that neither resumes a scan the other paused.
+    pub fn set_held(&self, held: bool) {
+        self.state.held.store(held, Ordering::Relaxed);
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.state.held.load(Ordering::Relaxed)
+    }
+
+    /// Starts no more probes, the scan ends once the ones in flight are
+    /// over with what it found so far.
+    pub fn stop(&self) {
+        self.state.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Leaves the remaining sockets of `ip` out of the scan.
+    pub fn skip(&self, ip: IpAddr) {
+        self.state.skipped.lock().unwrap().insert(ip);
+    }
+
+    pub fn is_skipped(&self, ip: IpAddr) -> bool {
+        self.state.skipped.lock().unwrap().contains(&ip)
+    }
+
+    /// Keeps at most `limit` probes in flight, at least one, until lifted
+    /// with `None`. The probes already in flight over it finish.
+    pub fn limit_batch(&self, limit: Option<usize>) {
+        let limit = limit.map_or(0, |limit| limit.max(1));
+        self.state.batch_limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn batch_limit(&self) -> Option<usize> {
+        Some(self.state.batch_limit.load(Ordering::Relaxed)).filter(|limit| *limit > 0)
+    }
+
+    /// Whether no new probe may start right now.
+    pub(crate) fn holds(&self) -> bool {
+        self.is_paused() || self.is_held() || self.is_stopped()
+    }
+}
@@ -0,0 +1,261 @@
+//! When a running scan should be done.
+//!
+//! [`forecast`] works the estimate out of a [`Progress`] snapshot alone. The
+//! rate of the recent samples gives the estimate and their variance how far
+//! it can be trusted. The estimate never goes past the slowest the scan can
+//! be: every socket left timing out on all of its tries, with the batch
+//! size, and the concurrency a paced host is down to, in flight at a time.
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a sample of the rate lasts.
+pub const SAMPLE: Duration = Duration::from_secs(1);
+
+/// How many samples are kept, the rate only follows the recent past.
+const SAMPLES: usize = 30;
+
+/// Fewer samples than this lower the confidence.
+const STEADY_SAMPLES: usize = 10;
+
+/// What bounds the speed of a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub batch_size: usize,
+    pub timeout: Duration,
+    pub tries: u8,
+}
+
+/// The counters of a running scan at some point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub now: SystemTime,
+    /// The sockets settled in each of the last [`SAMPLE`]s, oldest first.
+    pub samples: Vec<u64>,
+    /// The sockets left of every host, with the most probes it may have in
+    /// flight.
+    pub hosts: Vec<(u64, usize)>,
+}
+
+/// When a scan should be done, and how sure that is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Forecast {
+    /// The sockets left to probe.
+    pub remaining: u64,
+    /// The sockets settled per second lately.
+    pub rate: f64,
+    pub eta_secs: u64,
+    pub completes_at: String,
+    /// From 0 to 1, how steady the rate was.
+    pub confidence: f64,
+}
+
+/// Forecasts the end of the scan `progress` is of.
+pub fn forecast(progress: &Progress, limits: Limits) -> Forecast {
+    let remaining: u64 = progress.hosts.iter().map(|(left, _)| left).sum();
+    let batch_size = limits.batch_size.max(1);
+    let probe = limits.timeout.as_secs_f64() * f64::from(limits.tries.max(1));
+    let slowest = progress
+        .hosts
+        .iter()
+        .map(|(left, concurrency)| {
+            *left as f64 * probe / (*concurrency).clamp(1, batch_size) as f64
+        })
+        .fold(remaining as f64 * probe / batch_size as f64, f64::max);
+
+    let count = progress.samples.len();
+    let mean = progress.samples.iter().sum::<u64>() as f64 / count.max(1) as f64;
+    let variance = progress
+        .samples
+        .iter()
+        .map(|sample| (*sample as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count.max(1) as f64;
+    let rate = mean / SAMPLE.as_secs_f64();
+
+    let (eta, confidence) = if remaining == 0 {
+        (0.0, 1.0)
+    } else if rate > 0.0 {
+        let steadiness = (1.0 - variance.sqrt() / mean).clamp(0.0, 1.0);
+        let history = count.min(STEADY_SAMPLES) as f64 / STEADY_SAMPLES as f64;
+        ((remaining as f64 / rate).min(slowest), steadiness * history)
+    } else {
+        (slowest, 0.0)
+    };
+    let eta_secs = eta.ceil() as u64;
+
+    Forecast {
+        remaining,
+        rate,
+        eta_secs,
+        completes_at: humantime::format_rfc3339_seconds(
+            progress.now + Duration::from_secs(eta_secs),
+        )
+        .to_string(),
+        confidence,
+    }
+}
+
+/// Keeps the counters of a running scan.
+#[derive(Debug)]
+pub(crate) struct Tracker {
+    limits: Limits,
+    remaining: HashMap<IpAddr, u64>,
+    samples: VecDeque<u64>,
+    sample_start: Instant,
+    /// The sockets settled since `sample_start`.
+    current: u64,
+}
+
+impl Tracker {
+    pub fn new(
+        limits: Limits,
+        hosts: impl IntoIterator<Item = (IpAddr, usize)>,
+        now: Instant,
+    ) -> Self {
+        let mut remaining: HashMap<IpAddr, u64> = HashMap::new();
+        for (ip, sockets) in hosts {
+            *remaining.entry(ip).or_default() += sockets as u64;
+        }
+        Self {
+            limits,
+            remaining,
+            samples: VecDeque::new(),
+            sample_start: now,
+            current: 0,
+        }
+    }
+
+    /// Adds the `sockets` of `ip` to the ones left, for a host which joined
+    /// the scan.
+    pub fn add_host(&mut self, ip: IpAddr, sockets: usize) {
+        *self.remaining.entry(ip).or_default() += sockets as u64;
+    }
+
+    /// Records that `socket` won't be probed for the first time again, in
+    /// the sample [`Tracker::roll`] was last called for.
+    pub fn settled(&mut self, socket: SocketAddr) {
+        if let Some(left) = self.remaining.get_mut(&socket.ip()) {
+            *left = left.saturating_sub(1);
+        }
+        self.current += 1;
+    }
+
+    /// Closes the samples which are over, true when there was one.
+    pub fn roll(&mut self, now: Instant) -> bool {
+        let mut rolled = false;
+        while now.duration_since(self.sample_start) >= SAMPLE {
+            self.samples.push_back(std::mem::take(&mut self.current));
+            if self.samples.len() > SAMPLES {
+                self.samples.pop_front();
+            }
+            self.sample_start += SAMPLE;
+            rolled = true;
+        }
+        rolled
+    }
+
+    /// The forecast as of now, with the concurrency every host is allowed.
+    pub fn forecast(&self, concurrency: impl Fn(IpAddr) -> usize) -> Forecast {
+        let progress = Progress {
+            now: SystemTime::now(),
+            samples: self.samples.iter().copied().collect(),
+            hosts: self
+                .remaining
+                .iter()
+                .map(|(ip, left)| (*left, concurrency(*ip)))
+                .collect(),
+        };
+        forecast(&progress, self.limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forecast, Limits, Progress, Tracker, SAMPLE};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    const LIMITS: Limits = Limits {
+        batch_size: 100,
+        timeout: Duration::from_secs(1),
+        tries: 2,
+    };
+
+    fn progress(samples: &[u64], hosts: &[(u64, usize)]) -> Progress {
+        Progress {
+            now: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            samples: samples.to_vec(),
+            hosts: hosts.to_vec(),
+        }
+    }
+
+    #[test]
+    fn steady_rate_is_trusted() {
+        let forecast = forecast(&progress(&[50; 10], &[(300, 100), (200, 100)]), LIMITS);
+        assert_eq!(forecast.remaining, 500);
+        assert!((forecast.rate - 50.0).abs() < f64::EPSILON);
+        assert_eq!(forecast.eta_secs, 10);
+        assert_eq!(forecast.completes_at, "2023-11-14T22:13:30Z");
+        assert!((forecast.confidence - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn unsteady_or_short_history_lowers_confidence() {
+        let hosts = [(500, 100)];
+        let steady = forecast(&progress(&[50; 10], &hosts), LIMITS).confidence;
+        let bursty = forecast(
+            &progress(&[0, 100, 0, 100, 0, 100, 0, 100, 0, 100], &hosts),
+            LIMITS,
+        );
+        let short = forecast(&progress(&[50, 50], &hosts), LIMITS).confidence;
+
+        // Same mean rate, the estimate is the same.
+        assert_eq!(bursty.eta_secs, 10);
+        assert!(bursty.confidence < steady);
+        assert!((short - 0.2).abs() < 1e-9, "{}", short);
+    }
+
+    #[test]
+    fn estimate_is_bounded_by_the_limits() {
+        // No rate yet: every socket times out twice, 100 at a time.
+        let unknown = forecast(&progress(&[], &[(1_000, 100)]), LIMITS);
+        assert_eq!(unknown.eta_secs, 20);
+        assert!(unknown.confidence.abs() < f64::EPSILON);
+
+        // A crawl never gets slower than every probe timing out.
+        let crawling = forecast(&progress(&[1; 10], &[(1_000, 100)]), LIMITS);
+        assert_eq!(crawling.eta_secs, 20);
+
+        // A host slowed down to 10 probes at a time holds the scan up.
+        let paced = forecast(&progress(&[], &[(100, 10), (900, 100)]), LIMITS);
+        assert_eq!(paced.eta_secs, 20);
+        let paced = forecast(&progress(&[], &[(500, 10), (500, 100)]), LIMITS);
+        assert_eq!(paced.eta_secs, 100);
+
+        let done = forecast(&progress(&[50; 10], &[(0, 100)]), LIMITS);
+        assert_eq!((done.remaining, done.eta_secs), (0, 0));
+    }
+
+    #[test]
+    fn tracker_samples_the_settled_sockets() {
+        let start = Instant::now();
+        let socket: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let mut tracker = Tracker::new(LIMITS, [(socket.ip(), 10)], start);
+
+        for _ in 0..4 {
+            tracker.settled(socket);
+        }
+        assert!(!tracker.roll(start + SAMPLE / 2));
+        // A quiet second counts as a sample too.
+        assert!(tracker.roll(start + SAMPLE * 2));
+        tracker.settled(socket);
+
+        assert_eq!(tracker.samples, [4, 0]);
+        let forecast = tracker.forecast(|_| 100);
+        assert_eq!(forecast.remaining, 5);
+        assert!((forecast.rate - 2.0).abs() < f64::EPSILON);
+        assert!(forecast.completes_at.ends_with('Z'));
+    }
+}
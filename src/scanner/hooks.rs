@@ -0,0 +1,402 @@
+//! Calls the closures of a library user as a scan goes, see [`Hooks`].
+//!
+//! The closures run one after the other on a thread of their own, fed by a
+//! bounded queue, so that a slow one holds back the next ones rather than
+//! the probes. Once the queue is full progress is dropped, the next one
+//! telling more anyway, while opens and finished hosts wait for room in a
+//! backlog of the dispatcher. The scan never waits on the queue: it holds
+//! back new probes while there is a backlog, which the probes in flight
+//! can only grow by their own events. A closure which returns an error or
+//! panics is reported with the outcome of the scan, and still called for
+//! the events after.
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How many events wait for the closures at most.
+const QUEUE: usize = 1_024;
+
+type OnOpen = dyn Fn(SocketAddr, Duration) -> anyhow::Result<()> + Send + Sync;
+type OnHostComplete = dyn Fn(&HostSummary) -> anyhow::Result<()> + Send + Sync;
+type OnProgress = dyn Fn(&ScanStats) -> anyhow::Result<()> + Send + Sync;
+
+/// The closures called as a scan goes, see [`super::Scanner::with_hooks`].
+#[derive(Clone, Default)]
+pub struct Hooks {
+    on_open: Option<Arc<OnOpen>>,
+    on_host_complete: Option<Arc<OnHostComplete>>,
+    on_progress: Option<Arc<OnProgress>>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_open", &self.on_open.is_some())
+            .field("on_host_complete", &self.on_host_complete.is_some())
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called with every open socket, and how long its probe took.
+    #[must_use]
+    pub fn on_open(
+        mut self,
+        hook: impl Fn(SocketAddr, Duration) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_open = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called once every socket of a host was probed or skipped.
+    #[must_use]
+    pub fn on_host_complete(
+        mut self,
+        hook: impl Fn(&HostSummary) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_host_complete = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called about every second of the scan, and once it's over.
+    #[must_use]
+    pub fn on_progress(
+        mut self,
+        hook: impl Fn(&ScanStats) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Arc::new(hook));
+        self
+    }
+}
+
+/// A host all the sockets of which are over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostSummary {
+    pub ip: IpAddr,
+    /// The ports found open by then, in the order they were found.
+    pub open: Vec<u16>,
+    pub sockets: usize,
+    /// From the start of the scan.
+    pub elapsed: Duration,
+}
+
+/// The counters of a running scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanStats {
+    pub sockets: usize,
+    /// The sockets probed or skipped.
+    pub settled: usize,
+    pub open: usize,
+    pub elapsed: Duration,
+}
+
+/// A closure which returned an error or panicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookFailure {
+    /// `on_open`, `on_host_complete` or `on_progress`.
+    pub hook: &'static str,
+    pub error: String,
+}
+
+enum HookEvent {
+    Open(SocketAddr, Duration),
+    HostComplete(HostSummary),
+    Progress(ScanStats),
+}
+
+/// Turns the steps of a scan into events for the thread of the closures.
+pub(crate) struct Dispatcher {
+    sender: SyncSender<HookEvent>,
+    /// The events which found the queue full, sent first once there is room.
+    backlog: VecDeque<HookEvent>,
+    thread: JoinHandle<Vec<HookFailure>>,
+    /// The sockets left of every host, and its ports found open.
+    hosts: HashMap<IpAddr, (usize, Vec<u16>)>,
+    sockets: HashMap<IpAddr, usize>,
+    stats: ScanStats,
+    started: Instant,
+}
+
+impl Dispatcher {
+    /// Starts the thread of the closures, for `hosts` with their amount of
+    /// sockets. Hosts without any are complete right away.
+    pub fn start(hooks: Hooks, hosts: impl Iterator<Item = (IpAddr, usize)>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        let thread = thread::spawn(move || {
+            let mut failures = Vec::new();
+            for event in receiver {
+                let (hook, result) = match &event {
+                    HookEvent::Open(socket, latency) => {
+                        ("on_open", call(&hooks.on_open, |f| f(*socket, *latency)))
+                    }
+                    HookEvent::HostComplete(summary) => (
+                        "on_host_complete",
+                        call(&hooks.on_host_complete, |f| f(summary)),
+                    ),
+                    HookEvent::Progress(stats) => {
+                        ("on_progress", call(&hooks.on_progress, |f| f(stats)))
+                    }
+                };
+                if let Err(error) = result {
+                    failures.push(HookFailure { hook, error });
+                }
+            }
+            failures
+        });
+
+        let sockets: HashMap<IpAddr, usize> = hosts.collect();
+        let mut dispatcher = Self {
+            sender,
+            backlog: VecDeque::new(),
+            thread,
+            hosts: sockets
+                .iter()
+                .map(|(ip, sockets)| (*ip, (*sockets, Vec::new())))
+                .collect(),
+            stats: ScanStats {
+                sockets: sockets.values().sum(),
+                settled: 0,
+                open: 0,
+                elapsed: Duration::ZERO,
+            },
+            sockets,
+            started: Instant::now(),
+        };
+        let empty: Vec<IpAddr> = dispatcher
+            .hosts
+            .iter()
+            .filter(|(_, (left, _))| *left == 0)
+            .map(|(ip, _)| *ip)
+            .collect();
+        for ip in empty {
+            dispatcher.complete(ip);
+        }
+        dispatcher
+    }
+
+    /// Adds `ip` with its amount of sockets, for a host which joined the
+    /// scan.
+    pub fn add_host(&mut self, ip: IpAddr, sockets: usize) {
+        self.stats.sockets += sockets;
+        self.sockets.insert(ip, sockets);
+        self.hosts.insert(ip, (sockets, Vec::new()));
+        if sockets == 0 {
+            self.complete(ip);
+        }
+    }
+
+    /// `socket` is open, found by a probe which took `latency`.
+    pub fn open(&mut self, socket: SocketAddr, latency: Duration) {
+        self.stats.open += 1;
+        if let Some((_, open)) = self.hosts.get_mut(&socket.ip()) {
+            open.push(socket.port());
+        }
+        self.send(HookEvent::Open(socket, latency));
+    }
+
+    /// `socket` was probed or skipped for good.
+    pub fn settled(&mut self, socket: SocketAddr) {
+        self.stats.settled += 1;
+        let ip = socket.ip();
+        if let Some((left, _)) = self.hosts.get_mut(&ip) {
+            *left = left.saturating_sub(1);
+            if *left == 0 {
+                self.complete(ip);
+            }
+        }
+    }
+
+    /// Tells the counters, unless the closures are behind.
+    pub fn progress(&mut self) {
+        if self.is_behind() {
+            return;
+        }
+        let _ = self.sender.try_send(HookEvent::Progress(self.stats()));
+    }
+
+    /// Moves what it can of the backlog to the queue, and tells whether
+    /// there is some left, new probes waiting until there is none.
+    pub fn is_behind(&mut self) -> bool {
+        while let Some(event) = self.backlog.pop_front() {
+            match self.sender.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event)) => {
+                    self.backlog.push_front(event);
+                    return true;
+                }
+                // The thread of the closures is gone, nobody to tell.
+                Err(TrySendError::Disconnected(_)) => self.backlog.clear(),
+            }
+        }
+        false
+    }
+
+    /// Tells the last counters and waits for the closures to be done, the
+    /// ones which failed returned. The scan is over, the backlog can wait
+    /// for room.
+    pub fn finish(mut self) -> Vec<HookFailure> {
+        let progress = HookEvent::Progress(self.stats());
+        for event in self.backlog.drain(..).chain(Some(progress)) {
+            let _ = self.sender.send(event);
+        }
+        drop(self.sender);
+        self.thread.join().unwrap_or_else(|_| {
+            vec![HookFailure {
+                hook: "dispatcher",
+                error: "the thread of the hooks panicked".to_owned(),
+            }]
+        })
+    }
+
+    fn stats(&self) -> ScanStats {
+        ScanStats {
+            elapsed: self.started.elapsed(),
+            ..self.stats.clone()
+        }
+    }
+
+    fn complete(&mut self, ip: IpAddr) {
+        let Some((_, open)) = self.hosts.remove(&ip) else {
+            return;
+        };
+        let summary = HostSummary {
+            ip,
+            open,
+            sockets: self.sockets.get(&ip).copied().unwrap_or(0),
+            elapsed: self.started.elapsed(),
+        };
+        self.send(HookEvent::HostComplete(summary));
+    }
+
+    /// Queues `event` behind the backlog, without waiting for room.
+    fn send(&mut self, event: HookEvent) {
+        if !self.backlog.is_empty() {
+            self.backlog.push_back(event);
+            return;
+        }
+        if let Err(TrySendError::Full(event)) = self.sender.try_send(event) {
+            self.backlog.push_back(event);
+        }
+    }
+}
+
+/// Calls `hook` when there is one, its panics turned into errors.
+fn call<F: ?Sized>(
+    hook: &Option<Arc<F>>,
+    run: impl FnOnce(&F) -> anyhow::Result<()>,
+) -> Result<(), String> {
+    let Some(hook) = hook else {
+        return Ok(());
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| run(hook))) {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(payload) => Err(match payload.downcast_ref::<&str>() {
+            Some(message) => format!("panicked: {message}"),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => format!("panicked: {message}"),
+                None => "panicked".to_owned(),
+            },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dispatcher, HookFailure, Hooks, QUEUE};
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn hosts_complete_once_settled() {
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let hooks = Hooks::new().on_host_complete({
+            let completed = Arc::clone(&completed);
+            move |summary| {
+                completed
+                    .lock()
+                    .unwrap()
+                    .push((summary.ip, summary.open.clone()));
+                Ok(())
+            }
+        });
+        let busy: IpAddr = "10.0.0.1".parse().unwrap();
+        let empty: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut dispatcher = Dispatcher::start(hooks, [(busy, 2), (empty, 0)].iter().copied());
+        dispatcher.open(SocketAddr::new(busy, 22), Duration::from_millis(3));
+        dispatcher.settled(SocketAddr::new(busy, 22));
+        dispatcher.settled(SocketAddr::new(busy, 80));
+        assert!(dispatcher.finish().is_empty());
+        assert_eq!(
+            *completed.lock().unwrap(),
+            [(empty, vec![]), (busy, vec![22])]
+        );
+    }
+
+    #[test]
+    fn slow_hooks_never_wait_on_the_queue() {
+        let gate = Arc::new(Mutex::new(()));
+        let opened = Arc::new(AtomicUsize::new(0));
+        let hooks = Hooks::new().on_open({
+            let gate = Arc::clone(&gate);
+            let opened = Arc::clone(&opened);
+            move |_, _| {
+                drop(gate.lock().unwrap());
+                opened.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let sockets = 2 * QUEUE;
+        let closed = gate.lock().unwrap();
+        let mut dispatcher = Dispatcher::start(hooks, [(ip, sockets)].iter().copied());
+        // Twice what the queue holds, while the closure is stuck.
+        for port in 0..sockets {
+            dispatcher.open(SocketAddr::new(ip, port as u16), Duration::ZERO);
+        }
+        assert!(dispatcher.is_behind());
+
+        drop(closed);
+        while dispatcher.is_behind() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(dispatcher.finish().is_empty());
+        assert_eq!(opened.load(Ordering::SeqCst), sockets);
+    }
+
+    #[test]
+    fn failing_hooks_are_reported() {
+        let hooks = Hooks::new()
+            .on_open(|socket, _| anyhow::bail!("{socket} is not welcome"))
+            .on_progress(|_| panic!("no progress"));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut dispatcher = Dispatcher::start(hooks, [(ip, 1)].iter().copied());
+        dispatcher.open(SocketAddr::new(ip, 22), Duration::ZERO);
+        dispatcher.settled(SocketAddr::new(ip, 22));
+        assert_eq!(
+            dispatcher.finish(),
+            [
+                HookFailure {
+                    hook: "on_open",
+                    error: "10.0.0.1:22 is not welcome".to_owned()
+                },
+                HookFailure {
+                    hook: "on_progress",
+                    error: "panicked: no progress".to_owned()
+                },
+            ]
+        );
+    }
+}
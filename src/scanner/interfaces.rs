@@ -0,0 +1,485 @@
+//! The network interfaces the probes leave by, with `--interface-map`.
+//!
+//! The map names an interface per network, and optionally a default one:
+//!
+//! ```text
+//! 10.10.0.0/16=wg-clientA,172.16.0.0/12=wg-clientB,default=eth0
+//! ```
+//!
+//! A host goes out by the interface of the longest prefix holding it, the
+//! `default` interface when none does, and by the routing table when there
+//! is no default either. The probes of a host are bound to an address of its
+//! interface of the family of the host, see [`select`], and on Linux to the
+//! interface itself when the scan may, so that they can't leave by another
+//! one. A host whose interface is down, missing or without an address of its
+//! family isn't scanned, it is reported as skipped with the reason instead
+//! of with every port unreachable.
+use cidr_utils::cidr::IpCidr;
+use serde_derive::Serialize;
+use socket2::Socket;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// The interface of the hosts of a network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceRoute {
+    /// The hosts going out by the interface, every host for the default.
+    pub network: Option<IpCidr>,
+    pub interface: String,
+}
+
+impl fmt::Display for InterfaceRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.network {
+            Some(network) => write!(f, "{network}={}", self.interface),
+            None => write!(f, "default={}", self.interface),
+        }
+    }
+}
+
+/// The routes of `--interface-map`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceMap {
+    routes: Vec<InterfaceRoute>,
+}
+
+impl InterfaceMap {
+    /// The route of `ip`, by longest prefix match.
+    pub fn resolve(&self, ip: IpAddr) -> Option<&InterfaceRoute> {
+        self.routes
+            .iter()
+            .filter(|route| route.network.is_some_and(|network| network.contains(&ip)))
+            .max_by_key(|route| route.network.map(|network| network.network_length()))
+            .or_else(|| self.routes.iter().find(|route| route.network.is_none()))
+    }
+
+    pub fn routes(&self) -> &[InterfaceRoute] {
+        &self.routes
+    }
+}
+
+impl fmt::Display for InterfaceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, route) in self.routes.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{route}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InterfaceMap {
+    type Err = String;
+
+    fn from_str(map: &str) -> Result<Self, String> {
+        let mut routes: Vec<InterfaceRoute> = Vec::new();
+        for entry in map.split(',').map(str::trim) {
+            let route = parse_route(entry).map_err(|e| format!("{entry:?}: {e}"))?;
+            if let Some(other) = routes.iter().find(|other| other.network == route.network) {
+                let clash = match route.network {
+                    Some(_) => format!("the network is given to {} already", other.interface),
+                    None => String::from("the default is given twice"),
+                };
+                return Err(format!("{entry:?}: {clash}"));
+            }
+            routes.push(route);
+        }
+        Ok(Self { routes })
+    }
+}
+
+/// Parses `network=interface` or `default=interface`.
+fn parse_route(entry: &str) -> Result<InterfaceRoute, String> {
+    let (network, interface) = entry
+        .split_once('=')
+        .ok_or("expected network=interface or default=interface")?;
+    // The names the kernels take, IFNAMSIZ is 16 with the nul on Linux.
+    let valid = interface.len() < 16
+        && interface
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:@".contains(c));
+    if interface.is_empty() || !valid {
+        return Err(format!("{interface:?} is not an interface name"));
+    }
+    let network = match network {
+        "default" => None,
+        network => Some(
+            IpCidr::from_str(network)
+                .map_err(|_| format!("{network:?} is not a network, like 10.0.0.0/8"))?,
+        ),
+    };
+    Ok(InterfaceRoute {
+        network,
+        interface: interface.to_owned(),
+    })
+}
+
+/// A network interface of this machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub up: bool,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Interface {
+    /// The interfaces of this machine, with their addresses.
+    #[cfg(unix)]
+    pub fn list() -> io::Result<Vec<Interface>> {
+        let mut interfaces: Vec<Interface> = Vec::new();
+        let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        // SAFETY: the list is only read until it is freed below.
+        if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut cursor = addrs;
+        while !cursor.is_null() {
+            // SAFETY: every entry of the list is valid until it is freed.
+            let entry = unsafe { &*cursor };
+            cursor = entry.ifa_next;
+            // SAFETY: the name of an entry is a nul terminated string.
+            let name = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) }
+                .to_string_lossy()
+                .into_owned();
+            let up = entry.ifa_flags & libc::IFF_UP as libc::c_uint != 0;
+            // SAFETY: the address, when there is one, is of its family.
+            let address = unsafe { address_of(entry.ifa_addr) };
+            let index = match interfaces.iter().position(|known| known.name == name) {
+                Some(index) => index,
+                None => {
+                    interfaces.push(Interface {
+                        name,
+                        up,
+                        addresses: Vec::new(),
+                    });
+                    interfaces.len() - 1
+                }
+            };
+            interfaces[index].up |= up;
+            interfaces[index].addresses.extend(address);
+        }
+        // SAFETY: the list came from getifaddrs and nothing refers to it now.
+        unsafe { libc::freeifaddrs(addrs) };
+        Ok(interfaces)
+    }
+
+    #[cfg(not(unix))]
+    pub fn list() -> io::Result<Vec<Interface>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The interfaces can only be listed on Unix systems.",
+        ))
+    }
+}
+
+/// The IP address of `addr`, none for the other families.
+///
+/// # Safety
+///
+/// `addr` is null or points to a socket address of its family.
+#[cfg(unix)]
+unsafe fn address_of(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    match i32::from((*addr).sa_family) {
+        libc::AF_INET => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            Some(IpAddr::from(addr.sin_addr.s_addr.to_ne_bytes()))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            Some(IpAddr::from(addr.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
+
+/// The source the probes of a host are bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub interface: String,
+    pub source: IpAddr,
+    /// Whether the sockets are bound to the interface itself too, see
+    /// [`can_bind_device`].
+    pub device: bool,
+}
+
+impl Binding {
+    /// Binds `socket` to the source address, from `port`, 0 for any.
+    pub(super) fn bind(&self, socket: &Socket, port: u16) -> io::Result<()> {
+        if self.device {
+            bind_device(socket, &self.interface)?;
+        }
+        socket.bind(&SocketAddr::new(self.source, port).into())
+    }
+}
+
+/// Why the hosts of an interface can't go out by it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unusable {
+    Missing(String),
+    Down(String),
+    NoAddress { interface: String, ipv6: bool },
+}
+
+impl Unusable {
+    pub fn interface(&self) -> &str {
+        match self {
+            Unusable::Missing(interface)
+            | Unusable::Down(interface)
+            | Unusable::NoAddress { interface, .. } => interface,
+        }
+    }
+}
+
+impl fmt::Display for Unusable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unusable::Missing(interface) => write!(f, "the interface {interface} doesn't exist"),
+            Unusable::Down(interface) => write!(f, "the interface {interface} is down"),
+            Unusable::NoAddress { interface, ipv6 } => write!(
+                f,
+                "the interface {interface} has no {} address",
+                if *ipv6 { "IPv6" } else { "IPv4" }
+            ),
+        }
+    }
+}
+
+/// The source of the probes of `ip` among `interfaces`, none when `map` has
+/// no route for it. The first address of the family of `ip` is taken, for
+/// IPv6 a link-local one only when `ip` is link-local too. The sockets are
+/// only bound to the address, see [`can_bind_device`] for the interface.
+pub fn select(
+    map: &InterfaceMap,
+    interfaces: &[Interface],
+    ip: IpAddr,
+) -> Option<Result<Binding, Unusable>> {
+    let route = map.resolve(ip)?;
+    let name = &route.interface;
+    let Some(interface) = interfaces.iter().find(|interface| interface.name == *name) else {
+        return Some(Err(Unusable::Missing(name.clone())));
+    };
+    if !interface.up {
+        return Some(Err(Unusable::Down(name.clone())));
+    }
+    let source = interface
+        .addresses
+        .iter()
+        .copied()
+        .filter(|source| source.is_ipv6() == ip.is_ipv6())
+        .min_by_key(|source| is_link_local(*source) != is_link_local(ip));
+    Some(match source {
+        Some(source) => Ok(Binding {
+            interface: name.clone(),
+            source,
+            device: false,
+        }),
+        None => Err(Unusable::NoAddress {
+            interface: name.clone(),
+            ipv6: ip.is_ipv6(),
+        }),
+    })
+}
+
+fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+/// Whether the sockets can be bound to `interface` itself, not only to its
+/// address. It takes `CAP_NET_RAW` on Linux and isn't done elsewhere.
+pub fn can_bind_device(interface: &str) -> bool {
+    let Ok(socket) = Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None) else {
+        return false;
+    };
+    cfg!(target_os = "linux") && bind_device(&socket, interface).is_ok()
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_: &Socket, _: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// The interface a host was scanned by, with `--interface-map`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostInterface {
+    pub name: String,
+    /// The address the probes were sent from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+    /// Why the host wasn't scanned, when the interface couldn't be used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unusable: Option<String>,
+}
+
+impl HostInterface {
+    pub fn new(selected: &Result<Binding, Unusable>) -> Self {
+        match selected {
+            Ok(binding) => Self {
+                name: binding.interface.clone(),
+                source: Some(binding.source),
+                unusable: None,
+            },
+            Err(unusable) => Self {
+                name: unusable.interface().to_owned(),
+                source: None,
+                unusable: Some(unusable.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, Binding, Interface, InterfaceMap, Unusable};
+    use std::net::IpAddr;
+
+    const MAP: &str = "10.10.0.0/16=wg-clientA,172.16.0.0/12=wg-clientB,default=eth0";
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn interface(map: &InterfaceMap, host: &str) -> Option<String> {
+        map.resolve(ip(host)).map(|route| route.interface.clone())
+    }
+
+    fn interfaces() -> Vec<Interface> {
+        vec![
+            Interface {
+                name: "eth0".to_owned(),
+                up: true,
+                addresses: vec![ip("fe80::1"), ip("192.0.2.10"), ip("2001:db8::10")],
+            },
+            Interface {
+                name: "wg-clientA".to_owned(),
+                up: true,
+                addresses: vec![ip("10.10.0.2")],
+            },
+            Interface {
+                name: "wg-clientB".to_owned(),
+                up: false,
+                addresses: vec![ip("172.16.0.2")],
+            },
+        ]
+    }
+
+    #[test]
+    fn maps_are_parsed() {
+        let map: InterfaceMap = MAP.parse().unwrap();
+        assert_eq!(map.routes().len(), 3);
+        assert_eq!(map.to_string(), MAP);
+        let map: InterfaceMap = " 2001:db8::/32=wg0 , 10.0.0.1=tun0.1".parse().unwrap();
+        assert_eq!(map.to_string(), "2001:db8::/32=wg0,10.0.0.1=tun0.1");
+    }
+
+    #[test]
+    fn malformed_maps_are_refused() {
+        for (map, error) in [
+            ("10.0.0.0/8", "expected network=interface"),
+            ("10.0.0.0/33=eth0", "is not a network"),
+            ("lan=eth0", "is not a network"),
+            ("10.0.0.0/8=", "is not an interface name"),
+            ("10.0.0.0/8=eth 0", "is not an interface name"),
+            ("10.0.0.0/8=a-name-far-too-long", "is not an interface name"),
+            ("10.0.0.0/8=eth0,10.0.0.0/8=eth1", "given to eth0 already"),
+            ("default=eth0,default=eth1", "the default is given twice"),
+        ] {
+            let parsed = map.parse::<InterfaceMap>();
+            assert!(
+                parsed.as_ref().is_err_and(|e| e.contains(error)),
+                "{}: {:?}",
+                map,
+                parsed
+            );
+        }
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let map: InterfaceMap = "10.0.0.0/8=wg0,10.10.0.0/16=wg1,10.10.10.10=wg2,default=eth0"
+            .parse()
+            .unwrap();
+        assert_eq!(interface(&map, "10.10.10.10").as_deref(), Some("wg2"));
+        assert_eq!(interface(&map, "10.10.10.11").as_deref(), Some("wg1"));
+        assert_eq!(interface(&map, "10.11.0.1").as_deref(), Some("wg0"));
+        assert_eq!(interface(&map, "192.0.2.1").as_deref(), Some("eth0"));
+        assert_eq!(interface(&map, "2001:db8::1").as_deref(), Some("eth0"));
+
+        // Hosts outside every network follow the routing table.
+        let map: InterfaceMap = "10.0.0.0/8=wg0".parse().unwrap();
+        assert_eq!(interface(&map, "192.0.2.1"), None);
+    }
+
+    #[test]
+    fn sources_are_selected_per_target() {
+        let map: InterfaceMap = MAP.parse().unwrap();
+        let interfaces = interfaces();
+        let source = |host: &str| select(&map, &interfaces, ip(host)).unwrap();
+
+        assert_eq!(
+            source("10.10.3.4"),
+            Ok(Binding {
+                interface: "wg-clientA".to_owned(),
+                source: ip("10.10.0.2"),
+                device: false,
+            })
+        );
+        // The address of the family of the host.
+        assert_eq!(source("192.0.2.1").unwrap().source, ip("192.0.2.10"));
+        assert_eq!(source("2001:db8::1").unwrap().source, ip("2001:db8::10"));
+        // A link-local address only for a link-local host.
+        assert_eq!(source("fe80::2").unwrap().source, ip("fe80::1"));
+
+        assert_eq!(
+            source("172.16.0.1"),
+            Err(Unusable::Down("wg-clientB".to_owned()))
+        );
+    }
+
+    #[test]
+    fn unusable_interfaces_tell_why() {
+        let map: InterfaceMap = "10.10.0.0/16=wg-clientA,default=wg9".parse().unwrap();
+        let interfaces = interfaces();
+        let unusable = |host: &str| {
+            select(&map, &interfaces, ip(host))
+                .unwrap()
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(unusable("192.0.2.1"), "the interface wg9 doesn't exist");
+        let map: InterfaceMap = "10.10.0.0/16=wg-clientA,::/0=wg-clientA".parse().unwrap();
+        assert_eq!(
+            select(&map, &interfaces, ip("fd00::1"))
+                .unwrap()
+                .unwrap_err()
+                .to_string(),
+            "the interface wg-clientA has no IPv6 address"
+        );
+        assert_eq!(select(&map, &interfaces, ip("192.0.2.1")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_loopback_interface_is_listed() {
+        let interfaces = Interface::list().unwrap();
+        let lo = interfaces
+            .iter()
+            .find(|interface| interface.addresses.contains(&ip("127.0.0.1")))
+            .unwrap();
+        assert!(lo.up, "{:?}", lo);
+    }
+}
@@ -0,0 +1,188 @@
+//! Port-knocking sequences fired at a host before it gets scanned.
+//!
+//! The sequence of a host is fired right before its first probe, and its
+//! probes wait for the sequence to be over: the ports a knock daemon opens
+//! are often open for a few seconds only, gone by the time a large scan gets
+//! to the host if every host was knocked before the scan.
+use crate::input::{Knock, KnockProtocol};
+use async_std::net::{TcpStream, UdpSocket};
+use async_std::{io, task};
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
+use log::debug;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// The knock sequences fired during a scan, one per host.
+#[derive(Default)]
+pub(crate) struct Knocks<'a> {
+    fired: RefCell<HashMap<IpAddr, Shared<LocalBoxFuture<'a, ()>>>>,
+}
+
+impl<'a> Knocks<'a> {
+    /// The sequence of `ip`, fired by `knock` the first time it's asked for,
+    /// to be awaited before every probe of the host.
+    pub fn of(
+        &self,
+        ip: IpAddr,
+        knock: impl FnOnce() -> LocalBoxFuture<'a, ()>,
+    ) -> Shared<LocalBoxFuture<'a, ()>> {
+        self.fired
+            .borrow_mut()
+            .entry(ip)
+            .or_insert_with(|| knock().shared())
+            .clone()
+    }
+}
+
+/// Fires every knock of `sequence` at `ip` in order, waiting `delay` between
+/// the start of two consecutive knocks.
+///
+/// Refused or timed out knocks are what we expect from a knock daemon, so
+/// only the errors showing that a knock could not leave this machine (for
+/// example an unreachable network) are returned to the caller.
+pub async fn knock(
+    ip: IpAddr,
+    sequence: &[Knock],
+    delay: Duration,
+    timeout: Duration,
+) -> Vec<io::Error> {
+    let mut errors = Vec::new();
+    // Closed knock ports never answer, don't hold the sequence longer than the delay.
+    let wait = if delay.is_zero() {
+        timeout
+    } else {
+        delay.min(timeout)
+    };
+
+    for (nr_knock, entry) in sequence.iter().enumerate() {
+        let started = Instant::now();
+        let socket = SocketAddr::new(ip, entry.port);
+
+        let result = match entry.protocol {
+            KnockProtocol::Tcp => knock_tcp(socket, wait).await,
+            KnockProtocol::Udp => knock_udp(socket).await,
+        };
+        debug!("Knocked {} ({:?}): {:?}", socket, entry.protocol, result);
+
+        if let Err(e) = result {
+            if !matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut
+            ) {
+                errors.push(e);
+            }
+        }
+
+        if nr_knock + 1 < sequence.len() {
+            task::sleep(delay.saturating_sub(started.elapsed())).await;
+        }
+    }
+
+    errors
+}
+
+async fn knock_tcp(socket: SocketAddr, wait: Duration) -> io::Result<()> {
+    io::timeout(wait, async move { TcpStream::connect(socket).await }).await?;
+    Ok(())
+}
+
+async fn knock_udp(socket: SocketAddr) -> io::Result<()> {
+    let local_addr = match socket {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse::<SocketAddr>().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse::<SocketAddr>().unwrap(),
+    };
+
+    let udp_socket = UdpSocket::bind(local_addr).await?;
+    udp_socket.send_to(&[], socket).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::knock;
+    use crate::input::{Knock, KnockProtocol};
+    use async_std::task::block_on;
+    use std::net::{IpAddr, TcpListener, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn knocks_arrive_in_order_with_delay() {
+        let arrivals: Arc<Mutex<Vec<(u16, Instant)>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut sequence = Vec::new();
+
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let arrivals = Arc::clone(&arrivals);
+            thread::spawn(move || {
+                let _stream = listener.accept().unwrap();
+                arrivals.lock().unwrap().push((port, Instant::now()));
+            });
+            sequence.push(Knock {
+                port,
+                protocol: KnockProtocol::Tcp,
+            });
+        }
+
+        let udp_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = udp_socket.local_addr().unwrap().port();
+        let udp_arrivals = Arc::clone(&arrivals);
+        thread::spawn(move || {
+            let mut buf = [0u8; 16];
+            udp_socket.recv_from(&mut buf).unwrap();
+            udp_arrivals.lock().unwrap().push((port, Instant::now()));
+        });
+        sequence.push(Knock {
+            port,
+            protocol: KnockProtocol::Udp,
+        });
+
+        let delay = Duration::from_millis(100);
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let errors = block_on(knock(ip, &sequence, delay, Duration::from_millis(500)));
+        assert!(errors.is_empty());
+
+        // Give the listener threads a moment to record the last knock.
+        thread::sleep(Duration::from_millis(100));
+        let arrivals = arrivals.lock().unwrap();
+        let ports: Vec<u16> = arrivals.iter().map(|(port, _)| *port).collect();
+        let expected: Vec<u16> = sequence.iter().map(|k| k.port).collect();
+        assert_eq!(expected, ports);
+
+        for pair in arrivals.windows(2) {
+            let gap = pair[1].1.saturating_duration_since(pair[0].1);
+            assert!(
+                gap >= delay - Duration::from_millis(10),
+                "gap was {:?}",
+                gap
+            );
+        }
+    }
+
+    #[test]
+    fn closed_knock_ports_are_not_failures() {
+        // Bind then drop a listener to find a port which refuses connections.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let sequence = vec![Knock {
+            port,
+            protocol: KnockProtocol::Tcp,
+        }];
+        let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+        let errors = block_on(knock(
+            ip,
+            &sequence,
+            Duration::ZERO,
+            Duration::from_millis(200),
+        ));
+
+        assert!(errors.is_empty());
+    }
+}
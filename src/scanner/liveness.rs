@@ -0,0 +1,525 @@
+//! Heartbeats which notice hosts going down in the middle of their scan.
+//!
+//! Once a port of a host is found open, it's probed again every heartbeat
+//! interval for as long as the host has ports left to scan. A host missing
+//! several heartbeats in a row is considered down, and its remaining ports
+//! are held back, dropped or scanned anyway depending on [`HostDown`].
+use crate::input::HostDown;
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How the hosts are watched during the scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    /// How many heartbeats in a row a host has to miss to be down.
+    pub misses: u32,
+    pub on_down: HostDown,
+    /// How long a paused host may stay down before its ports are dropped.
+    pub resume_window: Duration,
+}
+
+/// A host which stopped answering during its scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostOutage {
+    #[serde(skip)]
+    pub ip: IpAddr,
+    /// When the host was found to be down, in RFC 3339 format.
+    pub went_down_at: String,
+    /// Whether the host answered again afterwards.
+    pub came_back: bool,
+    /// How many ports of the host were never probed because of the outage.
+    pub unprobed_ports: usize,
+}
+
+/// What an answer, or the lack of one, changed about a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Unchanged,
+    WentDown,
+    CameBack,
+    /// The host stayed down past the resume window, or for good.
+    GaveUp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Up { missed: u32 },
+    Down { since: Instant },
+    Gone,
+}
+
+/// The liveness of a single host, fed with the outcome of its heartbeats.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    misses: u32,
+    resume_window: Duration,
+    state: State,
+}
+
+impl Liveness {
+    /// A live host, down after `misses` missed heartbeats in a row and for
+    /// good once it didn't answer for `resume_window`.
+    pub fn new(misses: u32, resume_window: Duration) -> Self {
+        Self {
+            misses: misses.max(1),
+            resume_window,
+            state: State::Up { missed: 0 },
+        }
+    }
+
+    /// Records whether the host answered at `now`.
+    pub fn record(&mut self, answered: bool, now: Instant) -> Transition {
+        match (self.state, answered) {
+            (State::Up { .. }, true) => {
+                self.state = State::Up { missed: 0 };
+                Transition::Unchanged
+            }
+            (State::Up { missed }, false) if missed + 1 >= self.misses => {
+                self.state = State::Down { since: now };
+                Transition::WentDown
+            }
+            (State::Up { missed }, false) => {
+                self.state = State::Up { missed: missed + 1 };
+                Transition::Unchanged
+            }
+            (State::Down { since }, true) if now.duration_since(since) <= self.resume_window => {
+                self.state = State::Up { missed: 0 };
+                Transition::CameBack
+            }
+            (State::Down { .. }, _) => self.expire(now),
+            (State::Gone, _) => Transition::Unchanged,
+        }
+    }
+
+    /// Gives up on a host which has been down for longer than the resume
+    /// window at `now`.
+    pub fn expire(&mut self, now: Instant) -> Transition {
+        match self.state {
+            State::Down { since } if now.duration_since(since) > self.resume_window => {
+                self.give_up()
+            }
+            _ => Transition::Unchanged,
+        }
+    }
+
+    /// Gives up on the host whatever its state.
+    pub fn give_up(&mut self) -> Transition {
+        if self.state == State::Gone {
+            return Transition::Unchanged;
+        }
+        self.state = State::Gone;
+        Transition::GaveUp
+    }
+
+    pub fn is_up(&self) -> bool {
+        matches!(self.state, State::Up { .. })
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.state == State::Gone
+    }
+}
+
+/// The liveness and the remaining ports of a host being scanned.
+#[derive(Debug)]
+struct Watch {
+    liveness: Liveness,
+    /// A port which answered, probed by the heartbeats.
+    port: Option<u16>,
+    /// The probes not done yet, the held ones included.
+    remaining: usize,
+    /// The sockets held back while the host is paused.
+    held: Vec<SocketAddr>,
+    unprobed: usize,
+    beating: bool,
+    outages: Vec<HostOutage>,
+}
+
+/// Watches every host of a scan, deciding which sockets get probed and
+/// which hosts are due a heartbeat.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    heartbeat: Heartbeat,
+    hosts: HashMap<IpAddr, Watch>,
+    /// Held sockets of hosts which came back, probed before any other.
+    resumed: VecDeque<SocketAddr>,
+}
+
+impl Watchdog {
+    /// Watches `hosts`, each with its amount of sockets to probe.
+    pub fn new(heartbeat: Heartbeat, hosts: impl IntoIterator<Item = (IpAddr, usize)>) -> Self {
+        let mut watchdog = Self {
+            heartbeat,
+            hosts: HashMap::new(),
+            resumed: VecDeque::new(),
+        };
+        for (ip, remaining) in hosts {
+            watchdog.watch(ip, remaining);
+        }
+        watchdog
+    }
+
+    /// Watches `ip` too, with its amount of sockets to probe.
+    pub fn watch(&mut self, ip: IpAddr, remaining: usize) {
+        let resume_window = match self.heartbeat.on_down {
+            HostDown::Pause => self.heartbeat.resume_window,
+            HostDown::Abort => Duration::ZERO,
+            HostDown::Ignore => Duration::MAX,
+        };
+        let watch = Watch {
+            liveness: Liveness::new(self.heartbeat.misses, resume_window),
+            port: None,
+            remaining,
+            held: Vec::new(),
+            unprobed: 0,
+            beating: false,
+            outages: Vec::new(),
+        };
+        self.hosts.insert(ip, watch);
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.heartbeat.interval
+    }
+
+    /// The next socket to probe, held sockets of paused hosts are kept back
+    /// and the ones of hosts given up on are dropped.
+    pub fn next_socket(
+        &mut self,
+        sockets: &mut impl Iterator<Item = SocketAddr>,
+    ) -> Option<SocketAddr> {
+        if let Some(socket) = self.resumed.pop_front() {
+            return Some(socket);
+        }
+
+        for socket in sockets {
+            let Some(watch) = self.hosts.get_mut(&socket.ip()) else {
+                return Some(socket);
+            };
+            if watch.liveness.is_gone() {
+                watch.unprobed += 1;
+                watch.remaining -= 1;
+            } else if watch.liveness.is_up() || self.heartbeat.on_down == HostDown::Ignore {
+                return Some(socket);
+            } else {
+                watch.held.push(socket);
+            }
+        }
+        None
+    }
+
+    /// Records the result of the probe of `socket`. An open port is proof
+    /// the host is up, and the first one becomes its heartbeat port.
+    pub fn probed(&mut self, socket: SocketAddr, open: bool, now: Instant) -> Transition {
+        let Some(watch) = self.hosts.get_mut(&socket.ip()) else {
+            return Transition::Unchanged;
+        };
+        watch.remaining -= 1;
+        if !open {
+            return Transition::Unchanged;
+        }
+        watch.port.get_or_insert(socket.port());
+        self.answered(socket.ip(), true, now)
+    }
+
+    /// Records the outcome of a heartbeat of `ip`.
+    pub fn heartbeat_done(&mut self, ip: IpAddr, answered: bool, now: Instant) -> Transition {
+        if let Some(watch) = self.hosts.get_mut(&ip) {
+            watch.beating = false;
+        }
+        self.answered(ip, answered, now)
+    }
+
+    fn answered(&mut self, ip: IpAddr, answered: bool, now: Instant) -> Transition {
+        let on_down = self.heartbeat.on_down;
+        let Some(watch) = self.hosts.get_mut(&ip) else {
+            return Transition::Unchanged;
+        };
+
+        let mut transition = watch.liveness.record(answered, now);
+        match transition {
+            Transition::WentDown => {
+                watch.outages.push(HostOutage {
+                    ip,
+                    went_down_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                    came_back: false,
+                    unprobed_ports: 0,
+                });
+                if on_down == HostDown::Abort {
+                    watch.liveness.give_up();
+                    watch.drop_held();
+                    transition = Transition::GaveUp;
+                }
+            }
+            Transition::CameBack => {
+                if let Some(outage) = watch.outages.last_mut() {
+                    outage.came_back = true;
+                }
+                self.resumed.extend(watch.held.drain(..));
+            }
+            Transition::GaveUp => watch.drop_held(),
+            Transition::Unchanged => {}
+        }
+        transition
+    }
+
+    /// Gives up on the paused hosts whose resume window ran out at `now`.
+    pub fn expire(&mut self, now: Instant) -> Vec<IpAddr> {
+        let mut given_up = Vec::new();
+        for (ip, watch) in &mut self.hosts {
+            if watch.liveness.expire(now) == Transition::GaveUp {
+                watch.drop_held();
+                given_up.push(*ip);
+            }
+        }
+        given_up
+    }
+
+    /// The sockets to send a heartbeat to, one per host with a known open
+    /// port which still has ports left to scan.
+    pub fn due_heartbeats(&mut self) -> Vec<SocketAddr> {
+        self.hosts
+            .iter_mut()
+            .filter(|(_, watch)| !watch.beating && watch.remaining > 0 && !watch.liveness.is_gone())
+            .filter_map(|(ip, watch)| {
+                let port = watch.port?;
+                watch.beating = true;
+                Some(SocketAddr::new(*ip, port))
+            })
+            .collect()
+    }
+
+    /// Whether sockets are held back for a host which may still come back.
+    pub fn is_waiting(&self) -> bool {
+        self.hosts.values().any(|watch| !watch.held.is_empty())
+    }
+
+    /// Every outage of the scan, the unprobed ports counted against the
+    /// last outage of each host.
+    pub fn into_outages(self) -> Vec<HostOutage> {
+        let mut outages = Vec::new();
+        for (_, mut watch) in self.hosts {
+            let unprobed = watch.unprobed + watch.held.len();
+            if let Some(outage) = watch.outages.last_mut() {
+                outage.unprobed_ports = unprobed;
+            }
+            outages.extend(watch.outages);
+        }
+        outages
+    }
+}
+
+impl Watch {
+    fn drop_held(&mut self) {
+        self.unprobed += self.held.len();
+        self.remaining -= self.held.len();
+        self.held.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heartbeat, Liveness, Transition, Watchdog};
+    use crate::input::HostDown;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    // Feeds `outcomes` one second apart, returns the transitions.
+    fn feed(liveness: &mut Liveness, start: Instant, outcomes: &[bool]) -> Vec<Transition> {
+        outcomes
+            .iter()
+            .enumerate()
+            .map(|(second, answered)| {
+                liveness.record(*answered, start + Duration::from_secs(second as u64))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn host_goes_down_after_consecutive_misses() {
+        let mut liveness = Liveness::new(3, WINDOW);
+        let transitions = feed(
+            &mut liveness,
+            Instant::now(),
+            &[true, false, false, true, false, false, false],
+        );
+
+        use Transition::{Unchanged, WentDown};
+        // The answer in between resets the missed heartbeats.
+        assert_eq!(
+            transitions,
+            [Unchanged, Unchanged, Unchanged, Unchanged, Unchanged, Unchanged, WentDown]
+        );
+        assert!(!liveness.is_up());
+    }
+
+    #[test]
+    fn host_comes_back_within_the_window() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(2, WINDOW);
+        feed(&mut liveness, start, &[false, false]);
+
+        assert_eq!(
+            liveness.record(false, start + Duration::from_secs(10)),
+            Transition::Unchanged
+        );
+        assert_eq!(
+            liveness.record(true, start + Duration::from_secs(20)),
+            Transition::CameBack
+        );
+        assert!(liveness.is_up());
+    }
+
+    #[test]
+    fn host_down_past_the_window_is_given_up() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(1, WINDOW);
+        assert_eq!(liveness.record(false, start), Transition::WentDown);
+
+        assert_eq!(
+            liveness.expire(start + Duration::from_secs(29)),
+            Transition::Unchanged
+        );
+        assert_eq!(
+            liveness.expire(start + Duration::from_secs(31)),
+            Transition::GaveUp
+        );
+        // Answering too late doesn't bring it back.
+        assert_eq!(
+            liveness.record(true, start + Duration::from_secs(32)),
+            Transition::Unchanged
+        );
+        assert!(liveness.is_gone());
+    }
+
+    #[test]
+    fn late_answer_gives_the_host_up() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(1, WINDOW);
+        liveness.record(false, start);
+
+        assert_eq!(
+            liveness.record(true, start + Duration::from_secs(60)),
+            Transition::GaveUp
+        );
+    }
+
+    fn watchdog(on_down: HostDown) -> Watchdog {
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(1),
+            misses: 2,
+            on_down,
+            resume_window: WINDOW,
+        };
+        Watchdog::new(heartbeat, [("10.0.0.1".parse().unwrap(), 10)])
+    }
+
+    fn sockets(ports: std::ops::Range<u16>) -> impl Iterator<Item = SocketAddr> {
+        ports.map(|port| SocketAddr::new("10.0.0.1".parse().unwrap(), port))
+    }
+
+    // Probes port 1 open, then misses two heartbeats.
+    fn take_down(watchdog: &mut Watchdog, now: Instant) {
+        let open = watchdog.next_socket(&mut sockets(1..2)).unwrap();
+        watchdog.probed(open, true, now);
+        assert_eq!(watchdog.due_heartbeats(), [open]);
+        assert_eq!(
+            watchdog.heartbeat_done(open.ip(), false, now),
+            Transition::Unchanged
+        );
+        watchdog.due_heartbeats();
+        assert_eq!(
+            watchdog.heartbeat_done(open.ip(), false, now),
+            Transition::WentDown
+        );
+    }
+
+    #[test]
+    fn paused_host_resumes_its_ports() {
+        let now = Instant::now();
+        let mut watchdog = watchdog(HostDown::Pause);
+        take_down(&mut watchdog, now);
+
+        let mut rest = sockets(2..11);
+        assert_eq!(watchdog.next_socket(&mut rest), None);
+        assert!(watchdog.is_waiting());
+
+        watchdog.due_heartbeats();
+        assert_eq!(
+            watchdog.heartbeat_done("10.0.0.1".parse().unwrap(), true, now),
+            Transition::CameBack
+        );
+        let resumed: Vec<u16> = std::iter::from_fn(|| watchdog.next_socket(&mut rest))
+            .map(|socket| socket.port())
+            .collect();
+        assert_eq!(resumed, (2..11).collect::<Vec<u16>>());
+
+        let outages = watchdog.into_outages();
+        assert_eq!(outages.len(), 1);
+        assert!(outages[0].came_back);
+        assert_eq!(outages[0].unprobed_ports, 0);
+    }
+
+    #[test]
+    fn paused_host_is_dropped_after_the_window() {
+        let now = Instant::now();
+        let mut watchdog = watchdog(HostDown::Pause);
+        take_down(&mut watchdog, now);
+        assert_eq!(watchdog.next_socket(&mut sockets(2..11)), None);
+
+        assert!(watchdog.expire(now + WINDOW / 2).is_empty());
+        assert_eq!(
+            watchdog.expire(now + WINDOW * 2),
+            ["10.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+        );
+        assert!(!watchdog.is_waiting());
+        // No heartbeats for a host given up on.
+        assert!(watchdog.due_heartbeats().is_empty());
+
+        let outages = watchdog.into_outages();
+        assert!(!outages[0].came_back);
+        assert_eq!(outages[0].unprobed_ports, 9);
+    }
+
+    #[test]
+    fn aborted_host_drops_its_ports() {
+        let now = Instant::now();
+        let mut watchdog = watchdog(HostDown::Abort);
+        let open = watchdog.next_socket(&mut sockets(1..2)).unwrap();
+        watchdog.probed(open, true, now);
+        for _ in 0..2 {
+            watchdog.due_heartbeats();
+            watchdog.heartbeat_done(open.ip(), false, now);
+        }
+
+        assert_eq!(watchdog.next_socket(&mut sockets(2..11)), None);
+        assert!(!watchdog.is_waiting());
+        assert_eq!(watchdog.into_outages()[0].unprobed_ports, 9);
+    }
+
+    #[test]
+    fn ignored_outage_keeps_probing() {
+        let now = Instant::now();
+        let mut watchdog = watchdog(HostDown::Ignore);
+        take_down(&mut watchdog, now);
+
+        assert_eq!(watchdog.next_socket(&mut sockets(2..11)).unwrap().port(), 2);
+        let outages = watchdog.into_outages();
+        assert_eq!(outages.len(), 1);
+        assert_eq!(outages[0].unprobed_ports, 0);
+    }
+
+    #[test]
+    fn no_heartbeats_before_an_open_port() {
+        let mut watchdog = watchdog(HostDown::Pause);
+        let closed = watchdog.next_socket(&mut sockets(1..2)).unwrap();
+        watchdog.probed(closed, false, Instant::now());
+
+        assert!(watchdog.due_heartbeats().is_empty());
+    }
+}
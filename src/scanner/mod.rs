@@ -1,24 +1,187 @@
 //! Core functionality for actual scanning behaviour.
+use crate::errors::ErrorCode;
 use crate::generated::get_parsed_data;
+use crate::input::{Fairness, HostDown, Knock};
 use crate::port_strategy::PortStrategy;
+use crate::probe::{Prober, ServiceGuess};
+use crate::sink::{Normal, OutputSink, PortEvent, ScanStart, Sinks};
+use crate::trace::{self, TraceEvent, Tracer, WaitReason};
+use crate::{detail, verbose, warning};
 use log::debug;
 
+mod adaptive;
+mod canary;
+mod confidence;
+mod conntrack;
+mod dedup;
+mod fallback;
+mod feed;
+mod forecast;
+mod hooks;
+mod interfaces;
+mod knock;
+mod liveness;
+mod network;
+mod platform;
+mod priorities;
+mod prohibited;
+mod shard;
 mod socket_iterator;
+mod socket_options;
+mod socket_pool;
+mod socks;
+mod source_ports;
+mod spread_tries;
+mod ssh_jump;
+mod throttle;
+mod timeouts;
+use adaptive::HostPolicies;
+pub use adaptive::{AdaptiveTries, Change, HostPolicy, TriesDowngrade};
+pub use canary::Canaries;
+pub use confidence::{Confidence, TryOutcome};
+use conntrack::Backoff;
+pub use conntrack::{CliffDetector, Conntrack, ConntrackBackoff, ConntrackUsage, CONNTRACK_DIR};
+pub use dedup::PortSet;
+pub use fallback::Fallback;
+use fallback::Fallbacks;
+pub use feed::{ScanControl, ScanUpdate};
+use forecast::Tracker;
+pub use forecast::{forecast, Forecast, Limits, Progress};
+use hooks::Dispatcher;
+pub use hooks::{HookFailure, Hooks, HostSummary, ScanStats};
+pub use interfaces::{
+    can_bind_device, select, Binding, HostInterface, Interface, InterfaceMap, InterfaceRoute,
+    Unusable,
+};
+use knock::Knocks;
+use liveness::Watchdog;
+pub use liveness::{Heartbeat, HostOutage, Liveness, Transition};
+use network::Network;
+pub use network::{is_unreachable, Connectivity, ErrorWindow, Health, NetworkOutage};
+pub use platform::{Os, PlatformDefaults};
+use prohibited::Firewalls;
+pub use prohibited::{AdminProhibited, AdminProhibition, HostFirewall, Signal};
+pub use shard::Shard;
 use socket_iterator::SocketIterator;
+pub use socket_options::SocketOptions;
+pub use socket_pool::SocketPool;
+pub use socks::{choose, NoProxy, ProxyChoice, ProxyRoute, ProxySource, SocksProxy, SocksVersion};
+pub use source_ports::SourcePorts;
+pub use spread_tries::SpreadTries;
+use spread_tries::{Attempt, RetryQueue};
+pub use ssh_jump::{classify, BastionNames, ChannelResult, JumpSession, SshJump};
+use throttle::Throttle;
+pub use throttle::{Detector, Outcome, Pacing, Throttling};
+pub use timeouts::{HostTimeout, TimeoutGroup, TimeoutMap};
 
 use async_std::net::TcpStream;
 use async_std::prelude::*;
 use async_std::{io, net::UdpSocket};
-use colored::Colorize;
+use futures::future::{FutureExt, LocalBoxFuture};
 use futures::stream::FuturesUnordered;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
 use std::{
     collections::HashSet,
     net::{IpAddr, Shutdown, SocketAddr},
     num::NonZeroU8,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+/// What a scan found, see [`Scanner::scan`].
+#[derive(Debug, Default)]
+pub struct ScanOutcome {
+    pub open: Vec<SocketAddr>,
+    /// The hosts which went down during their scan, with heartbeats.
+    pub outages: Vec<HostOutage>,
+    /// The hosts whose tries got lowered, with adaptive tries.
+    pub downgrades: Vec<TriesDowngrade>,
+    /// The hosts found to block the scan, with pacing.
+    pub throttlings: Vec<Throttling>,
+    /// The hosts whose other ports were left out for rejecting the scan as
+    /// administratively prohibited.
+    pub prohibitions: Vec<AdminProhibition>,
+    /// The times the network of the scanner went down, with connectivity
+    /// checks.
+    pub network_outages: Vec<NetworkOutage>,
+    /// The times the scan slowed down for a full conntrack table.
+    pub conntrack_backoffs: Vec<ConntrackBackoff>,
+    /// The sockets which were given more than once, and probed once.
+    pub duplicates: u64,
+    /// The times a closure of [`Scanner::with_hooks`] failed.
+    pub hook_failures: Vec<HookFailure>,
+    /// The sockets which still had tries to go when the scan was stopped,
+    /// with spread tries. They count as closed.
+    pub pending_retries: usize,
+    /// When the scan should have been done, as it ended. Something is left
+    /// only when it was stopped.
+    pub forecast: Forecast,
+    /// What the probe pipeline guessed of the open sockets, with
+    /// [`Scanner::with_probes`].
+    pub probes: HashMap<SocketAddr, ServiceGuess>,
+    /// What the tries of every open TCP socket ended with, in order.
+    pub tries: HashMap<SocketAddr, Vec<TryOutcome>>,
+    /// The TCP sockets which never answered, their last try timed out or
+    /// failed otherwise.
+    pub filtered: Vec<SocketAddr>,
+    /// The addresses scanned in place of the ones whose first batch got no
+    /// response, with [`Scanner::with_fallbacks`].
+    pub fallbacks: Vec<Fallback>,
+}
+
+impl ScanOutcome {
+    /// Adds what another scan found, of other hosts.
+    pub fn extend(&mut self, other: ScanOutcome) {
+        self.open.extend(other.open);
+        self.outages.extend(other.outages);
+        self.downgrades.extend(other.downgrades);
+        self.throttlings.extend(other.throttlings);
+        self.prohibitions.extend(other.prohibitions);
+        self.network_outages.extend(other.network_outages);
+        self.conntrack_backoffs.extend(other.conntrack_backoffs);
+        self.duplicates += other.duplicates;
+        self.hook_failures.extend(other.hook_failures);
+        self.pending_retries += other.pending_retries;
+        if self.forecast.remaining == 0 {
+            self.forecast = other.forecast;
+        }
+        self.probes.extend(other.probes);
+        self.tries.extend(other.tries);
+        self.filtered.extend(other.filtered);
+        self.fallbacks.extend(other.fallbacks);
+    }
+}
+
+/// What finished while the sockets are being scanned.
+enum Event {
+    /// A socket was probed, the probe taking the time given, with what its
+    /// tries ended with. The connection of an open socket is kept for the
+    /// probe pipeline.
+    Probe(
+        SocketAddr,
+        io::Result<SocketAddr>,
+        Duration,
+        Option<TcpStream>,
+        Vec<TryOutcome>,
+    ),
+    /// The probe pipeline is over for an open socket.
+    Served(SocketAddr, ServiceGuess),
+    Heartbeat(SocketAddr, bool),
+    /// The heartbeat interval elapsed.
+    Tick,
+    /// The cooldown of a host which blocked the scan is over.
+    CooledDown(IpAddr),
+    /// The reference of the connectivity checks was probed, while the
+    /// network is down.
+    Connectivity(bool),
+    /// Time to check whether a paused scan was resumed.
+    Poll,
+    /// The wake-up armed then for the spread tries.
+    RetryDue(Instant),
+}
+
 /// The class for the scanner
 /// IP is data type IpAddr and is the IP address
 /// start & end is where the port scan starts and ends
@@ -39,6 +202,38 @@ pub struct Scanner {
     accessible: bool,
     exclude_ports: Vec<u16>,
     udp: bool,
+    knock: Vec<Knock>,
+    knock_delay: Duration,
+    socket_options: SocketOptions,
+    socket_pool: Option<SocketPool>,
+    fairness: Fairness,
+    port_overrides: HashMap<IpAddr, PortStrategy>,
+    source_ports: Option<SourcePorts>,
+    bindings: HashMap<IpAddr, Binding>,
+    heartbeat: Option<Heartbeat>,
+    adaptive_tries: Option<AdaptiveTries>,
+    admin_prohibited: Option<AdminProhibited>,
+    spread_tries: Option<SpreadTries>,
+    pacing: Option<Pacing>,
+    connectivity: Option<Connectivity>,
+    conntrack: Option<Conntrack>,
+    timeout_map: Option<TimeoutMap>,
+    canaries: Option<Canaries>,
+    priorities: HashMap<IpAddr, HashSet<u16>>,
+    fallbacks: HashMap<IpAddr, IpAddr>,
+    shard: Option<(Shard, u64)>,
+    platform: PlatformDefaults,
+    /// Whether the burst errors of the platform were already warned about.
+    burst_warned: AtomicBool,
+    feeds: Vec<Sender<ScanUpdate>>,
+    hooks: Option<Hooks>,
+    ssh_jump: Option<Arc<JumpSession>>,
+    proxy: Option<Arc<ProxyRoute>>,
+    control: Option<ScanControl>,
+    prober: Option<Prober>,
+    verify_open: bool,
+    trace: Option<Tracer>,
+    sinks: Sinks,
 }
 
 // Allowing too many arguments for clippy.
@@ -65,60 +260,1295 @@ impl Scanner {
             accessible,
             exclude_ports,
             udp,
+            knock: Vec::new(),
+            knock_delay: Duration::ZERO,
+            socket_options: SocketOptions::default(),
+            socket_pool: None,
+            fairness: Fairness::RoundRobin,
+            port_overrides: HashMap::new(),
+            source_ports: None,
+            bindings: HashMap::new(),
+            heartbeat: None,
+            adaptive_tries: None,
+            admin_prohibited: None,
+            spread_tries: None,
+            pacing: None,
+            connectivity: None,
+            conntrack: None,
+            timeout_map: None,
+            canaries: None,
+            priorities: HashMap::new(),
+            fallbacks: HashMap::new(),
+            shard: None,
+            platform: PlatformDefaults::current(),
+            burst_warned: AtomicBool::new(false),
+            feeds: Vec::new(),
+            hooks: None,
+            ssh_jump: None,
+            proxy: None,
+            control: None,
+            prober: None,
+            verify_open: false,
+            trace: None,
+            sinks: Sinks::new().with_sink(Normal::new(greppable, accessible)),
+        }
+    }
+
+    /// Sets a port-knocking sequence which is fired at every host, waiting
+    /// `delay` between each knock, right before its first probe.
+    #[must_use]
+    pub fn with_knock(mut self, knock: Vec<Knock>, delay: Duration) -> Self {
+        self.knock = knock;
+        self.knock_delay = delay;
+        self
+    }
+
+    /// Sets the options applied to every probe socket before it connects.
+    #[must_use]
+    pub fn with_socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+
+    /// Creates the TCP probe sockets by batches ahead of the probes, as many
+    /// as the batch size, rather than each probe its own, see [`SocketPool`].
+    #[must_use]
+    pub fn with_socket_pool(mut self) -> Self {
+        self.socket_pool = Some(SocketPool::new(self.batch_size.into()));
+        self
+    }
+
+    /// Sets how the ports of the different hosts are interleaved.
+    #[must_use]
+    pub fn with_fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = fairness;
+        self
+    }
+
+    /// Scans the hosts found in `port_overrides` with their own port
+    /// strategy instead of the scanner wide one.
+    #[must_use]
+    pub fn with_port_overrides(mut self, port_overrides: HashMap<IpAddr, PortStrategy>) -> Self {
+        self.port_overrides = port_overrides;
+        self
+    }
+
+    /// Binds every probe socket to a random source port of `source_ports`.
+    #[must_use]
+    pub fn with_source_ports(mut self, source_ports: SourcePorts) -> Self {
+        self.source_ports = Some(source_ports);
+        self
+    }
+
+    /// Binds the probe sockets of the hosts found in `bindings` to their
+    /// source, see [`select`].
+    #[must_use]
+    pub fn with_bindings(mut self, bindings: HashMap<IpAddr, Binding>) -> Self {
+        self.bindings = bindings;
+        self
+    }
+
+    /// Watches every host with heartbeats to an open port while it's being
+    /// scanned, see [`Heartbeat`]. TCP scans only.
+    #[must_use]
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// Tries the ports of hosts which stopped answering only once, see
+    /// [`AdaptiveTries`]. TCP scans only.
+    #[must_use]
+    pub fn with_adaptive_tries(mut self, adaptive_tries: AdaptiveTries) -> Self {
+        self.adaptive_tries = Some(adaptive_tries);
+        self
+    }
+
+    /// Leaves out the ports of hosts which reject the scan as
+    /// administratively prohibited, see [`AdminProhibited`]. TCP scans only.
+    #[must_use]
+    pub fn with_admin_prohibited(mut self, admin_prohibited: AdminProhibited) -> Self {
+        self.admin_prohibited = Some(admin_prohibited);
+        self
+    }
+
+    /// Goes on with the tries left of a socket once one connects, so that
+    /// the ports which don't connect every time are told apart as flaky,
+    /// see [`Confidence`]. The spread tries stop at the first which
+    /// connects still. TCP scans only.
+    #[must_use]
+    pub fn with_verified_open(mut self) -> Self {
+        self.verify_open = true;
+        self
+    }
+
+    /// Spreads the tries of every port over `spread`, one at a time, rather
+    /// than trying again right away, see [`SpreadTries`]. TCP scans only.
+    #[must_use]
+    pub fn with_spread_tries(mut self, spread: SpreadTries) -> Self {
+        self.spread_tries = Some(spread);
+        self
+    }
+
+    /// Slows down the hosts which seem to block the scan, see [`Pacing`].
+    /// TCP scans only.
+    #[must_use]
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+
+    /// Pauses the whole scan while the network seems down, see
+    /// [`Connectivity`]. TCP scans only.
+    #[must_use]
+    pub fn with_connectivity(mut self, connectivity: Connectivity) -> Self {
+        self.connectivity = Some(connectivity);
+        self
+    }
+
+    /// Halves the batch size each time the hosts which answered go silent
+    /// all at once, like when a conntrack table is full, see [`Conntrack`].
+    /// TCP scans only.
+    #[must_use]
+    pub fn with_conntrack(mut self, conntrack: Conntrack) -> Self {
+        self.conntrack = Some(conntrack);
+        self
+    }
+
+    /// Gives the hosts of every group of `timeout_map` its timeout and
+    /// tries, see [`TimeoutMap`].
+    #[must_use]
+    pub fn with_timeout_map(mut self, timeout_map: TimeoutMap) -> Self {
+        self.timeout_map = Some(timeout_map);
+        self
+    }
+
+    /// Probes the `canaries` of every host before its other ports, whatever
+    /// the port strategy, see [`Canaries`].
+    #[must_use]
+    pub fn with_canaries(mut self, canaries: Canaries) -> Self {
+        self.canaries = Some(canaries);
+        self
+    }
+
+    /// Probes the ports of `priorities` of every host in it before its other
+    /// ports, behind the canaries, like the ports a previous scan found
+    /// open. The hosts which aren't in it keep their order.
+    #[must_use]
+    pub fn with_priorities(mut self, priorities: HashMap<IpAddr, Vec<u16>>) -> Self {
+        self.priorities = priorities
+            .into_iter()
+            .map(|(ip, ports)| (ip, ports.into_iter().collect()))
+            .collect();
+        self
+    }
+
+    /// Scans the address `fallbacks` gives for a host instead of it, with the
+    /// same ports, once its first batch got no response, connected or
+    /// refused, see [`Fallback`]. The hosts of another scan are ignored.
+    #[must_use]
+    pub fn with_fallbacks(mut self, fallbacks: HashMap<IpAddr, IpAddr>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    /// Only scans the sockets of `shard`, partitioned with `seed`. The
+    /// ports of every host are then held in memory.
+    #[must_use]
+    pub fn with_shard(mut self, shard: Shard, seed: u64) -> Self {
+        self.shard = Some((shard, seed));
+        self
+    }
+
+    /// Sets the defaults of the operating system the scan runs on, those of
+    /// this build by default. Probes failing with one of its burst errors are
+    /// tried again without using up their tries.
+    #[must_use]
+    pub fn with_platform(mut self, platform: PlatformDefaults) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Calls the closures of `hooks` as the scan goes, on a thread of their
+    /// own. The scan is over once they were called for every event.
+    #[must_use]
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Probes every socket through the bastion of `session` rather than
+    /// from this machine, with no more probes at once than it has channels.
+    /// TCP scans only.
+    #[must_use]
+    pub fn with_ssh_jump(mut self, session: Arc<JumpSession>) -> Self {
+        self.batch_size = self.batch_size.min(session.max_channels());
+        self.ssh_jump = Some(session);
+        self
+    }
+
+    /// Probes every socket through the SOCKS proxy of `route`, but for the
+    /// addresses it sends straight. TCP scans only.
+    #[must_use]
+    pub fn with_proxy(mut self, route: ProxyRoute) -> Self {
+        self.proxy = Some(Arc::new(route));
+        self
+    }
+
+    /// Sends every step of the scan to `feed`, see [`ScanUpdate`], along
+    /// with the feeds given before. A feed nobody listens to any more is no
+    /// error.
+    #[must_use]
+    pub fn with_feed(mut self, feed: Sender<ScanUpdate>) -> Self {
+        self.feeds.push(feed);
+        self
+    }
+
+    /// Adds `sink` to the outputs of the scan, next to the normal output
+    /// and the sinks given before. The scanner tells it when the scan
+    /// starts and what happens to every socket, the hosts and the end of
+    /// the run being told through [`Scanner::sinks`] by whoever builds the
+    /// report.
+    #[must_use]
+    pub fn with_sink(self, sink: impl OutputSink + 'static) -> Self {
+        self.sinks.add(Box::new(sink), false);
+        self
+    }
+
+    /// Adds `sink` like [`Scanner::with_sink`], a failure of it stopping
+    /// the scan.
+    #[must_use]
+    pub fn with_critical_sink(self, sink: impl OutputSink + 'static) -> Self {
+        self.sinks.add(Box::new(sink), true);
+        self
+    }
+
+    /// Replaces the outputs of the scan with `sinks`, the normal output
+    /// included, so that several scans can share them.
+    #[must_use]
+    pub fn with_sinks(mut self, sinks: Sinks) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// The outputs of the scan, to tell them the hosts and the end of the
+    /// run, and to check their failures.
+    pub fn sinks(&self) -> Sinks {
+        self.sinks.clone()
+    }
+
+    /// Runs the pipeline of `prober` on the connection which found a TCP
+    /// socket open instead of closing it, so that the port is connected to
+    /// once. The guesses are sent as [`ScanUpdate::Served`] and kept in
+    /// [`ScanOutcome::probes`]. A socket being served takes its room in the
+    /// batch until the pipeline is over. The sockets reached through the SSH
+    /// bastion or the SOCKS proxy have no connection to hand over, nor the
+    /// UDP ones.
+    #[must_use]
+    pub fn with_probes(mut self, prober: Prober) -> Self {
+        self.prober = Some(prober);
+        self
+    }
+
+    /// Lets `control` pause, skip hosts of, or stop the scan while it runs.
+    #[must_use]
+    pub fn with_control(mut self, control: ScanControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Records the batch, the probes, the retries and the waits of the scan
+    /// with `tracer`.
+    #[must_use]
+    pub fn with_trace(mut self, tracer: Tracer) -> Self {
+        self.trace = Some(tracer);
+        self
+    }
+
+    /// The ports scanned on `ip`, in no particular order.
+    pub fn host_ports(&self, ip: IpAddr) -> Vec<u16> {
+        let (ports, _) = self.ports_of(self.port_overrides.get(&ip).unwrap_or(&self.port_strategy));
+        match self.shard {
+            Some((shard, seed)) => shard.ports(ip, &ports, seed),
+            None => ports,
         }
     }
 
+    /// The hosts scanned, each once, in the order they were given.
+    pub fn hosts(&self) -> Vec<IpAddr> {
+        let mut seen: HashSet<IpAddr> = HashSet::new();
+        self.ips
+            .iter()
+            .copied()
+            .filter(|ip| seen.insert(*ip))
+            .collect()
+    }
+
+    /// How many sockets the scan probes, whatever the tries.
+    pub fn sockets(&self) -> u64 {
+        self.hosts()
+            .into_iter()
+            .map(|ip| self.host_ports(ip).len() as u64)
+            .sum()
+    }
+
+    /// Scans `ips` instead of the hosts the scanner was built with.
+    #[must_use]
+    pub fn with_hosts(mut self, ips: &[IpAddr]) -> Self {
+        self.ips = ips.to_vec();
+        self
+    }
+
+    /// Leaves `ips` out of the scan.
+    #[must_use]
+    pub fn without_hosts(mut self, ips: &[IpAddr]) -> Self {
+        self.ips.retain(|ip| !ips.contains(ip));
+        self
+    }
+
+    /// Checks that the socket options can be set for every address family
+    /// being scanned.
+    pub fn check_socket_options(&self) -> io::Result<()> {
+        self.socket_options.check(&self.ips, self.udp)
+    }
+
     /// Runs scan_range with chunk sizes
     /// If you want to run RustScan normally, this is the entry point used
     /// Returns all open ports as `Vec<u16>`
     /// Added by wasuaje - 01/26/2024:
     ///    Filtering port against exclude port list
     pub async fn run(&self) -> Vec<SocketAddr> {
-        let ports: Vec<u16> = self
-            .port_strategy
-            .order()
+        self.scan().await.open
+    }
+
+    /// Like [`Scanner::run`], with what happened to the hosts during their
+    /// scan besides the open sockets.
+    pub async fn scan(&self) -> ScanOutcome {
+        // The sockets still in the pool are closed however the scan ends.
+        let _pool = self.socket_pool.as_ref().map(SocketPool::drain_on_drop);
+
+        // Every list and every host once, the repeats counted as duplicates.
+        let (ports, repeats) = self.ports_of(&self.port_strategy);
+        let mut duplicates: u64 = 0;
+        let mut occurrences: HashMap<IpAddr, usize> = HashMap::new();
+        for ip in &self.ips {
+            *occurrences.entry(*ip).or_default() += 1;
+        }
+        let mut overrides: HashMap<IpAddr, Vec<u16>> = HashMap::new();
+        for (ip, strategy) in &self.port_overrides {
+            let (host_ports, host_repeats) = self.ports_of(strategy);
+            let occurrences = occurrences.get(ip).copied().unwrap_or(0);
+            duplicates += (host_repeats * occurrences) as u64;
+            overrides.insert(*ip, host_ports);
+        }
+        let occurrences = self
+            .ips
             .iter()
-            .filter(|&port| !self.exclude_ports.contains(port))
-            .copied()
+            .filter(|ip| !overrides.contains_key(ip))
+            .count();
+        duplicates += (repeats * occurrences) as u64;
+        let sharded: HashMap<IpAddr, Vec<u16>> = match self.shard {
+            Some((shard, seed)) => self
+                .ips
+                .iter()
+                .map(|ip| {
+                    (
+                        *ip,
+                        shard.ports(*ip, overrides.get(ip).unwrap_or(&ports), seed),
+                    )
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+        let prioritized: HashMap<IpAddr, Vec<u16>> = self
+            .ips
+            .iter()
+            .filter_map(|ip| {
+                let first = self.priorities.get(ip)?;
+                let host_ports = sharded.get(ip).or_else(|| overrides.get(ip));
+                let host_ports = host_ports.unwrap_or(&ports);
+                let leading = self
+                    .canaries
+                    .as_ref()
+                    .map_or(0, |canaries| canaries.leading(host_ports));
+                Some((*ip, priorities::prioritize(host_ports, first, leading)))
+            })
+            .collect();
+        let mut seen: HashSet<IpAddr> = HashSet::new();
+        let mut hosts: Vec<(IpAddr, &[u16])> = Vec::with_capacity(self.ips.len());
+        for ip in &self.ips {
+            let host_ports = prioritized
+                .get(ip)
+                .or_else(|| sharded.get(ip))
+                .or_else(|| overrides.get(ip));
+            let host_ports = host_ports.unwrap_or(&ports).as_slice();
+            if seen.insert(*ip) {
+                hosts.push((*ip, host_ports));
+            } else {
+                duplicates += host_ports.len() as u64;
+            }
+        }
+        let sockets: usize = hosts.iter().map(|(_, ports)| ports.len()).sum();
+        let batch_started = Instant::now();
+        self.trace(|| TraceEvent::BatchStarted {
+            hosts: hosts.len(),
+            sockets,
+            batch_size: self.batch_size,
+        });
+        self.send(ScanUpdate::Started {
+            hosts: hosts.iter().map(|(ip, ports)| (*ip, ports.len())).collect(),
+            batch_size: self.batch_size,
+        });
+        let mut watchdog = self
+            .heartbeat
+            .clone()
+            .filter(|_| !self.udp)
+            .map(|heartbeat| {
+                Watchdog::new(
+                    heartbeat,
+                    hosts.iter().map(|(ip, ports)| (*ip, ports.len())),
+                )
+            });
+        let mut dispatcher = self.hooks.clone().map(|hooks| {
+            Dispatcher::start(hooks, hosts.iter().map(|(ip, ports)| (*ip, ports.len())))
+        });
+        let mut tracker = Tracker::new(
+            Limits {
+                batch_size: self.batch_size.into(),
+                timeout: self.timeout,
+                tries: self.tries.get(),
+            },
+            hosts.iter().map(|(ip, ports)| (*ip, ports.len())),
+            Instant::now(),
+        );
+        // The other family of a host keeps its ports, held back until its
+        // first batch gets no response.
+        let mut fallbacks = (!self.fallbacks.is_empty())
+            .then(|| Fallbacks::new(&self.fallbacks, &hosts, self.batch_size.into()));
+        let held: Vec<(IpAddr, &[u16])> = hosts
+            .iter()
+            .filter_map(|(ip, ports)| Some((*self.fallbacks.get(ip)?, *ports)))
             .collect();
-        let mut socket_iterator: SocketIterator = SocketIterator::new(&self.ips, &ports);
+        let mut socket_iterator: SocketIterator = match &self.canaries {
+            Some(canaries) if canaries.first_pass => {
+                let (first, then) = hosts
+                    .iter()
+                    .map(|(ip, ports)| {
+                        let (first, then) = ports.split_at(canaries.leading(ports));
+                        ((*ip, first), (*ip, then))
+                    })
+                    .unzip();
+                SocketIterator::in_passes(first, then, self.fairness)
+            }
+            _ => SocketIterator::new(hosts, self.fairness),
+        };
+        socket_iterator.hold(held);
         let mut open_sockets: Vec<SocketAddr> = Vec::new();
-        let mut ftrs = FuturesUnordered::new();
+        let mut probes: HashMap<SocketAddr, ServiceGuess> = HashMap::new();
+        let mut tried: HashMap<SocketAddr, Vec<TryOutcome>> = HashMap::new();
+        let mut filtered: Vec<SocketAddr> = Vec::new();
+        let policies = self
+            .adaptive_tries
+            .filter(|_| !self.udp)
+            .map(HostPolicies::new);
+        let mut ftrs: FuturesUnordered<LocalBoxFuture<'_, Event>> = FuturesUnordered::new();
         let mut errors: HashSet<String> = HashSet::new();
         let udp_map = get_parsed_data();
 
-        for _ in 0..self.batch_size {
-            if let Some(socket) = socket_iterator.next() {
-                ftrs.push(self.scan_socket(socket, udp_map.clone()));
-            } else {
-                break;
+        let host_limits = self.host_limits();
+        let limits_of = |ip: IpAddr| {
+            host_limits
+                .get(&ip)
+                .copied()
+                .unwrap_or((self.tries.get(), self.timeout))
+        };
+        let spread_tries = self.spread_tries.filter(|_| !self.udp);
+        let mut retries = spread_tries.map(|_| RetryQueue::new());
+        let knocks = Knocks::default();
+        let probe = |socket: SocketAddr| {
+            let udp_map = udp_map.clone();
+            let policies = policies.as_ref();
+            let knock = (!self.knock.is_empty())
+                .then(|| knocks.of(socket.ip(), || self.knock_host(socket.ip()).boxed_local()));
+            let limits = limits_of(socket.ip());
+            // The other tries of a spread socket come later, one at a time.
+            let limits = match spread_tries {
+                Some(_) => (1, limits.1),
+                None => limits,
+            };
+            async move {
+                if let Some(knock) = knock {
+                    knock.await;
+                }
+                self.trace(|| TraceEvent::ProbeLaunched { socket });
+                let started = Instant::now();
+                let mut tries = Vec::new();
+                let (result, stream) = match self
+                    .scan_socket(socket, udp_map, policies, limits, &mut tries)
+                    .await
+                {
+                    Ok(stream) => (Ok(socket), stream),
+                    Err(e) => (Err(e), None),
+                };
+                Event::Probe(socket, result, started.elapsed(), stream, tries)
+            }
+            .boxed_local()
+        };
+        let mut throttle = self
+            .pacing
+            .filter(|_| !self.udp)
+            .map(|pacing| Throttle::new(pacing, self.batch_size.into()));
+        let mut network = self.connectivity.filter(|_| !self.udp).map(Network::new);
+        let mut backoff = self
+            .conntrack
+            .clone()
+            .filter(|_| !self.udp)
+            .map(|conntrack| Backoff::new(conntrack, self.batch_size.into()));
+        let mut firewalls = self
+            .admin_prohibited
+            .filter(|_| !self.udp)
+            .map(Firewalls::new);
+        let mut next_socket = |watchdog: &mut Option<Watchdog>,
+                               throttle: &mut Option<Throttle>,
+                               network: &mut Option<Network>,
+                               tracker: &mut Tracker,
+                               dispatcher: &mut Option<Dispatcher>,
+                               retries: &mut Option<RetryQueue>,
+                               firewalls: &mut Option<Firewalls>,
+                               fallbacks: &mut Option<Fallbacks>| {
+            if self.control.as_ref().is_some_and(ScanControl::holds)
+                || self.sinks.failed_critically()
+                || network.as_ref().is_some_and(Network::is_down)
+                || dispatcher.as_mut().is_some_and(Dispatcher::is_behind)
+            {
+                return None;
             }
+            // The addresses scanned instead of silent ones join the others.
+            if let Some(fallbacks) = fallbacks {
+                for ip in fallbacks.take_released() {
+                    socket_iterator.release(ip);
+                }
+            }
+            loop {
+                let mut sockets = || match watchdog {
+                    Some(watchdog) => watchdog.next_socket(&mut socket_iterator),
+                    None => socket_iterator.next(),
+                };
+                // The probes which failed during an outage go first, then the
+                // spread tries due.
+                let requeued = network.as_mut().and_then(Network::next_requeued);
+                let socket = match requeued.or_else(|| {
+                    retries
+                        .as_mut()
+                        .and_then(|retries| retries.next_due(Instant::now()))
+                }) {
+                    Some(socket) => socket,
+                    None => match throttle {
+                        Some(throttle) => throttle.next_socket(&mut sockets),
+                        None => sockets(),
+                    }?,
+                };
+                let prohibited = firewalls
+                    .as_ref()
+                    .is_some_and(|firewalls| firewalls.is_prohibited(socket.ip()));
+                let abandoned = fallbacks
+                    .as_ref()
+                    .is_some_and(|fallbacks| fallbacks.is_abandoned(socket.ip()));
+                if !self.is_skipped(socket.ip()) && !prohibited && !abandoned {
+                    return Some(socket);
+                }
+                // Already settled the first time it was probed.
+                if let Some(retries) = retries.as_mut().filter(|retries| retries.is_retry(socket)) {
+                    retries.abandon(socket);
+                    continue;
+                }
+                if network
+                    .as_ref()
+                    .is_some_and(|network| network.is_retry(socket))
+                {
+                    continue;
+                }
+                // Settled as if probed, without telling anything about the host.
+                if let Some(watchdog) = watchdog {
+                    watchdog.probed(socket, false, Instant::now());
+                }
+                if let Some(throttle) = throttle {
+                    throttle.probed(socket, Outcome::Failed);
+                }
+                if let Some(firewalls) = firewalls.as_mut().filter(|_| prohibited) {
+                    firewalls.skipped(socket.ip());
+                }
+                tracker.settled(socket);
+                if let Some(dispatcher) = dispatcher {
+                    dispatcher.settled(socket);
+                }
+                self.send(ScanUpdate::Skipped(socket));
+            }
+        };
+        let mut polling = false;
+        let mut forecast_printed = Instant::now();
+        // When the next wake-up of the spread tries is armed for.
+        let mut retry_armed: Option<Instant> = None;
+        // What is left of the scan, to spread the tries over.
+        let mut remaining = Duration::from_secs(
+            tracker
+                .forecast(|ip| concurrency(throttle.as_ref(), ip))
+                .eta_secs,
+        );
+
+        let mut in_flight: usize = 0;
+        while in_flight < batch_size(self.batch_size, backoff.as_ref(), self.control.as_ref()) {
+            let Some(socket) = next_socket(
+                &mut watchdog,
+                &mut throttle,
+                &mut network,
+                &mut tracker,
+                &mut dispatcher,
+                &mut retries,
+                &mut firewalls,
+                &mut fallbacks,
+            ) else {
+                break;
+            };
+            ftrs.push(probe(socket));
+            in_flight += 1;
+        }
+        if let Some(watchdog) = &watchdog {
+            ftrs.push(tick(watchdog.interval()));
+        }
+        if self.is_paused() || self.hooks_behind(&mut dispatcher) {
+            ftrs.push(poll());
+            polling = true;
         }
 
-        debug!("Start scanning sockets. \nBatch size {}\nNumber of ip-s {}\nNumber of ports {}\nTargets all together {} ",
+        debug!("Start scanning sockets. \nBatch size {}\nNumber of ip-s {}\nNumber of ports {}\nPort overrides {}\nTargets all together {} ",
             self.batch_size,
             self.ips.len(),
             &ports.len(),
-            (self.ips.len() * ports.len()));
+            overrides.len(),
+            sockets);
 
-        while let Some(result) = ftrs.next().await {
-            if let Some(socket) = socket_iterator.next() {
-                ftrs.push(self.scan_socket(socket, udp_map.clone()));
-            }
+        while let Some(event) = ftrs.next().await {
+            let sampled = tracker.roll(Instant::now());
+            match event {
+                Event::Probe(socket, result, latency, stream, outcomes) => {
+                    in_flight -= 1;
+                    let added = outcomes.len();
+                    self.trace(|| TraceEvent::ProbeResolved {
+                        socket,
+                        state: TryOutcome::of(&result),
+                        tries: added,
+                        duration_us: trace::micros(latency),
+                    });
+                    if added > 0 {
+                        tried.entry(socket).or_default().extend(outcomes);
+                    }
+                    // The connection stays in the batch while it is served.
+                    if let (Some(prober), Some(stream)) = (&self.prober, stream) {
+                        ftrs.push(
+                            prober
+                                .probe_stream(socket, stream)
+                                .map(move |guess| Event::Served(socket, guess))
+                                .boxed_local(),
+                        );
+                        in_flight += 1;
+                    }
+                    // The first probe of a retried socket was already counted.
+                    let requeued = network
+                        .as_ref()
+                        .is_some_and(|network| network.is_retry(socket));
+                    let retry = throttle
+                        .as_ref()
+                        .is_some_and(|throttle| throttle.is_retry(socket))
+                        || requeued
+                        || retries
+                            .as_ref()
+                            .is_some_and(|retries| retries.is_retry(socket));
+                    if let (Some(watchdog), false) = (&mut watchdog, retry) {
+                        let transition = watchdog.probed(socket, result.is_ok(), Instant::now());
+                        self.report_transition(socket.ip(), transition);
+                    }
+                    // Told open first, the socket is over once probed.
+                    if result.is_ok() {
+                        let flaky = tried.get(&socket).and_then(|tries| Confidence::of(tries))
+                            == Some(Confidence::Flaky);
+                        let canary = self
+                            .canaries
+                            .as_ref()
+                            .is_some_and(|canaries| canaries.contains(socket.port()));
+                        self.sinks.port(&PortEvent::Open {
+                            socket,
+                            canary,
+                            flaky,
+                        });
+                        if let Some(dispatcher) = &mut dispatcher {
+                            dispatcher.open(socket, latency);
+                        }
+                        self.send(ScanUpdate::Open(socket));
+                    }
+                    if !retry {
+                        tracker.settled(socket);
+                        if let Some(dispatcher) = &mut dispatcher {
+                            dispatcher.settled(socket);
+                        }
+                        self.send(ScanUpdate::Probed(socket));
+                        let answered = matches!(
+                            TryOutcome::of(&result),
+                            TryOutcome::Open | TryOutcome::Refused
+                        );
+                        if !answered && !self.udp {
+                            filtered.push(socket);
+                        }
+                        if let Some(fallback) = fallbacks
+                            .as_mut()
+                            .and_then(|fallbacks| fallbacks.probed(socket, TryOutcome::of(&result)))
+                        {
+                            self.report_fallback(&fallback);
+                            tracker.add_host(fallback.to, fallback.sockets);
+                            if let Some(dispatcher) = &mut dispatcher {
+                                dispatcher.add_host(fallback.to, fallback.sockets);
+                            }
+                            if let Some(watchdog) = &mut watchdog {
+                                watchdog.watch(fallback.to, fallback.sockets);
+                            }
+                            self.send(ScanUpdate::Started {
+                                hosts: vec![(fallback.to, fallback.sockets)],
+                                batch_size: self.batch_size,
+                            });
+                        }
+                    }
+                    if let Some(network) = &mut network {
+                        match network.probed(socket, &result) {
+                            Health::WentDown => {
+                                self.report_network_down(network);
+                                ftrs.push(self.check_connectivity(network));
+                            }
+                            Health::NoReference => self.report_network_down(network),
+                            Health::Unchanged => {}
+                        }
+                    }
+                    if let Some(throttle) = &mut throttle {
+                        if throttle.probed(socket, outcome(&result)) {
+                            self.report_throttling(socket.ip(), throttle.cooldown());
+                            let ip = socket.ip();
+                            self.trace(|| TraceEvent::RateWait {
+                                ip: Some(ip),
+                                reason: WaitReason::Throttle,
+                                wait_ms: trace::millis(throttle.cooldown()),
+                            });
+                            ftrs.push(
+                                async_std::task::sleep(throttle.cooldown())
+                                    .map(move |()| Event::CooledDown(ip))
+                                    .boxed_local(),
+                            );
+                        }
+                    }
+                    if let Some(backoff) = &mut backoff {
+                        if let Some(slowed) = backoff.probed(socket.ip(), outcome(&result)) {
+                            self.report_conntrack(slowed);
+                        }
+                    }
+                    // Every host is unreachable while the network is down.
+                    let network_down = network.as_ref().is_some_and(Network::is_down);
+                    if let (Some(firewalls), false) = (&mut firewalls, network_down) {
+                        if firewalls.record(socket.ip(), &result) {
+                            self.report_prohibited(socket.ip(), &result);
+                        }
+                    }
+                    // A probe lost to an outage is tried again once the
+                    // network is back, not counted as a try.
+                    let lost = !requeued
+                        && network
+                            .as_ref()
+                            .is_some_and(|network| network.is_retry(socket));
+                    if let (Some(retries), Some(spread), false) = (&mut retries, spread_tries, lost)
+                    {
+                        let (tries, timeout) = limits_of(socket.ip());
+                        let tries = match &policies {
+                            Some(policies) => policies.limits(socket.ip(), tries, timeout).0,
+                            None => tries,
+                        };
+                        let interval = match spread {
+                            SpreadTries::Over(interval) => interval,
+                            SpreadTries::Remaining => remaining.max(timeout * u32::from(tries)),
+                        };
+                        let attempt =
+                            retries.probed(socket, result.is_ok(), tries, interval, Instant::now());
+                        if let Attempt::RetryAt(due) = attempt {
+                            debug!("Trying {socket} again at {due:?}");
+                            self.trace(|| TraceEvent::RetryScheduled {
+                                socket,
+                                due_in_ms: trace::millis(
+                                    due.saturating_duration_since(Instant::now()),
+                                ),
+                            });
+                        }
+                    }
+                    // Only the tries of the open sockets are kept, and of the
+                    // ones to be tried again. A lost probe wasn't a try.
+                    if let Some(outcomes) = tried.get_mut(&socket) {
+                        if lost {
+                            outcomes.truncate(outcomes.len() - added);
+                        }
+                        let pending = lost
+                            || retries
+                                .as_ref()
+                                .is_some_and(|retries| retries.is_retry(socket));
+                        if !pending && !outcomes.contains(&TryOutcome::Open) {
+                            tried.remove(&socket);
+                        }
+                    }
 
-            match result {
-                Ok(socket) => open_sockets.push(socket),
-                Err(e) => {
-                    let error_string = e.to_string();
-                    if errors.len() < self.ips.len() * 1000 {
-                        errors.insert(error_string);
+                    match result {
+                        Ok(socket) => open_sockets.push(socket),
+                        Err(e) => {
+                            let error_string = e.to_string();
+                            if errors.len() < self.ips.len() * 1000 {
+                                errors.insert(error_string);
+                            }
+                        }
+                    }
+                }
+                Event::Served(socket, guess) => {
+                    in_flight -= 1;
+                    self.send(ScanUpdate::Served(socket, guess.clone()));
+                    probes.insert(socket, guess);
+                }
+                Event::Heartbeat(socket, answered) => {
+                    if let Some(watchdog) = &mut watchdog {
+                        let transition =
+                            watchdog.heartbeat_done(socket.ip(), answered, Instant::now());
+                        self.report_transition(socket.ip(), transition);
+                    }
+                }
+                Event::Tick => {
+                    let Some(watchdog) = &mut watchdog else {
+                        continue;
+                    };
+                    for ip in watchdog.expire(Instant::now()) {
+                        self.report_transition(ip, Transition::GaveUp);
                     }
+                    // The heartbeats would only find every host down.
+                    let network_down = network.as_ref().is_some_and(Network::is_down);
+                    for socket in watchdog
+                        .due_heartbeats()
+                        .into_iter()
+                        .filter(|_| !network_down)
+                    {
+                        ftrs.push(
+                            async move {
+                                Event::Heartbeat(
+                                    socket,
+                                    self.reach(socket, self.timeout).await.is_ok(),
+                                )
+                            }
+                            .boxed_local(),
+                        );
+                    }
+                    // Ticking stops once nothing is left to scan or wait for.
+                    if (in_flight > 0 || watchdog.is_waiting() || network_down)
+                        && !self.is_stopped()
+                    {
+                        ftrs.push(tick(watchdog.interval()));
+                    }
+                }
+                Event::CooledDown(ip) => {
+                    if let Some(throttle) = &mut throttle {
+                        throttle.cooled_down(ip);
+                    }
+                }
+                Event::Connectivity(answered) => {
+                    let Some(network) = &mut network else {
+                        continue;
+                    };
+                    if network.checked(answered) {
+                        self.report_network_back();
+                    } else if !self.is_stopped() {
+                        ftrs.push(self.check_connectivity(network));
+                    }
+                }
+                Event::Poll => polling = false,
+                Event::RetryDue(armed) => {
+                    if retry_armed == Some(armed) {
+                        retry_armed = None;
+                    }
+                }
+            }
+
+            // Resumed hosts may have several sockets to fill the batch with.
+            while in_flight < batch_size(self.batch_size, backoff.as_ref(), self.control.as_ref()) {
+                let Some(socket) = next_socket(
+                    &mut watchdog,
+                    &mut throttle,
+                    &mut network,
+                    &mut tracker,
+                    &mut dispatcher,
+                    &mut retries,
+                    &mut firewalls,
+                    &mut fallbacks,
+                ) else {
+                    break;
+                };
+                ftrs.push(probe(socket));
+                in_flight += 1;
+            }
+            // A paused scan keeps waiting, even with nothing in flight, and
+            // so does one the hooks are behind.
+            if (self.is_paused() || self.hooks_behind(&mut dispatcher)) && !polling {
+                ftrs.push(poll());
+                polling = true;
+            }
+            // The spread tries keep the scan going until the last is due,
+            // unless it was stopped.
+            let next_retry = retries.as_ref().and_then(RetryQueue::next_wake);
+            if let (Some(due), false) = (next_retry, self.is_stopped()) {
+                let wake = retry_wake(due, Instant::now());
+                if retry_armed.is_none_or(|armed| wake < armed) {
+                    ftrs.push(retry_due(wake));
+                    retry_armed = Some(wake);
+                }
+            }
+
+            if sampled {
+                let forecast = tracker.forecast(|ip| concurrency(throttle.as_ref(), ip));
+                remaining = Duration::from_secs(forecast.eta_secs);
+                if forecast_printed.elapsed() >= FORECAST_INTERVAL {
+                    self.report_forecast(&forecast);
+                    forecast_printed = Instant::now();
+                }
+                self.send(ScanUpdate::Forecast(forecast));
+                if let Some(dispatcher) = &mut dispatcher {
+                    dispatcher.progress();
                 }
             }
         }
         debug!("Typical socket connection errors {:?}", errors);
         debug!("Open Sockets found: {:?}", &open_sockets);
-        open_sockets
+        drop(ftrs);
+        let forecast = tracker.forecast(|ip| concurrency(throttle.as_ref(), ip));
+        let hook_failures = dispatcher.map(Dispatcher::finish).unwrap_or_default();
+        self.report_hook_failures(&hook_failures);
+        // The retries left by a stopped scan are closed.
+        tried.retain(|_, outcomes| outcomes.contains(&TryOutcome::Open));
+        self.trace(|| TraceEvent::BatchFinished {
+            open: open_sockets.len(),
+            duration_us: trace::micros(batch_started.elapsed()),
+        });
+        ScanOutcome {
+            open: open_sockets,
+            outages: watchdog.map(Watchdog::into_outages).unwrap_or_default(),
+            downgrades: policies
+                .map(HostPolicies::into_downgrades)
+                .unwrap_or_default(),
+            throttlings: throttle.map(Throttle::into_throttlings).unwrap_or_default(),
+            prohibitions: firewalls
+                .map(Firewalls::into_prohibitions)
+                .unwrap_or_default(),
+            network_outages: network.map(Network::into_outages).unwrap_or_default(),
+            conntrack_backoffs: backoff.map(Backoff::into_backoffs).unwrap_or_default(),
+            duplicates,
+            hook_failures,
+            pending_retries: retries.as_ref().map_or(0, RetryQueue::pending),
+            forecast,
+            probes,
+            tries: tried,
+            filtered,
+            fallbacks: fallbacks.map(Fallbacks::into_fallbacks).unwrap_or_default(),
+        }
+    }
+
+    /// Records the event made by `event`, only made when tracing.
+    fn trace(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(tracer) = &self.trace {
+            tracer.record(event());
+        }
+    }
+
+    fn send(&self, update: ScanUpdate) {
+        match &update {
+            ScanUpdate::Started { hosts, batch_size } => self.sinks.started(&ScanStart {
+                hosts: hosts.clone(),
+                batch_size: *batch_size,
+            }),
+            ScanUpdate::Probed(socket) => self.sinks.port(&PortEvent::Probed(*socket)),
+            ScanUpdate::Skipped(socket) => self.sinks.port(&PortEvent::Skipped(*socket)),
+            ScanUpdate::Served(socket, guess) => {
+                self.sinks.port(&PortEvent::Served(*socket, guess.clone()));
+            }
+            // Told with its flags as it is found.
+            ScanUpdate::Open(_) | ScanUpdate::Forecast(_) => {}
+        }
+        for feed in &self.feeds {
+            let _ = feed.send(update.clone());
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.control.as_ref().is_some_and(|control| {
+            (control.is_paused() || control.is_held()) && !control.is_stopped()
+        })
+    }
+
+    /// Whether the closures of the hooks hold back new probes, until they
+    /// catch up.
+    fn hooks_behind(&self, dispatcher: &mut Option<Dispatcher>) -> bool {
+        !self.is_stopped() && dispatcher.as_mut().is_some_and(Dispatcher::is_behind)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.control.as_ref().is_some_and(ScanControl::is_stopped) || self.sinks.failed_critically()
+    }
+
+    fn is_skipped(&self, ip: IpAddr) -> bool {
+        self.control
+            .as_ref()
+            .is_some_and(|control| control.is_skipped(ip))
+    }
+
+    /// Lets the user know the tries of a host changed.
+    fn report_change(&self, ip: IpAddr, change: Option<Change>) {
+        let message = match (change, &self.adaptive_tries) {
+            (Some(Change::Downgraded), Some(adaptive)) => format!(
+                "Host {ip} left {} probes in a row unanswered, trying its ports once.",
+                adaptive.silent_probes
+            ),
+            (Some(Change::Restored), _) => format!(
+                "Host {ip} answered, trying its ports {} times again.",
+                self.tries
+            ),
+            _ => return,
+        };
+        verbose!(message, self.greppable, self.accessible);
+    }
+
+    /// Lets the user know when the scan should be done.
+    fn report_forecast(&self, forecast: &Forecast) {
+        verbose!(
+            format!(
+                "{} socket(s) left at {:.0}/s, done in about {} ({}, {:.0}% confidence)",
+                forecast.remaining,
+                forecast.rate,
+                humantime::format_duration(Duration::from_secs(forecast.eta_secs)),
+                forecast.completes_at,
+                forecast.confidence * 100.0
+            ),
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    /// Lets the user know a host seems to block the scan.
+    fn report_throttling(&self, ip: IpAddr, cooldown: Duration) {
+        warning!(
+            ErrorCode::Throttled,
+            format!(
+                "Host {ip} stopped answering after a burst of probes, pausing it for {} and slowing it down.",
+                humantime::format_duration(cooldown)
+            ),
+            self.greppable,
+            self.accessible,
+            host = ip
+        );
+    }
+
+    /// Lets the user know the ports left of a host are skipped.
+    fn report_prohibited(&self, ip: IpAddr, result: &io::Result<SocketAddr>) {
+        let error = result
+            .as_ref()
+            .err()
+            .map_or_else(String::new, |e| io::Error::from(e.kind()).to_string());
+        warning!(
+            ErrorCode::AdminProhibited,
+            format!(
+                "Host {ip} rejects the scan as administratively prohibited ({error}), skipping its other ports. --ignore-admin-prohibited probes them anyway."
+            ),
+            self.greppable,
+            self.accessible,
+            host = ip
+        );
+    }
+
+    /// Lets the user know the scan slowed down for a full conntrack table,
+    /// with the table of this machine when it could be read.
+    fn report_conntrack(&self, backoff: &ConntrackBackoff) {
+        let table = match backoff.usage {
+            Some(usage) if usage.is_full() => format!(
+                "the conntrack table of this machine holds {} of {} entries",
+                usage.count, usage.max
+            ),
+            Some(usage) => format!(
+                "the conntrack table of this machine only holds {} of {} entries, the one of a NAT router on the way may be full",
+                usage.count, usage.max
+            ),
+            None => "a conntrack table on the way may be full".to_owned(),
+        };
+        warning!(
+            ErrorCode::ConntrackFull,
+            format!(
+                "Hosts which answered all stopped at once without errors here, {table}. Lowering the batch size to {}.",
+                backoff.batch_size
+            ),
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    /// Lets the user know closures of the hooks failed, the first of them.
+    fn report_hook_failures(&self, failures: &[HookFailure]) {
+        let Some(first) = failures.first() else {
+            return;
+        };
+        warning!(
+            ErrorCode::HookFailed,
+            format!(
+                "{} call(s) of the hooks failed, the scan went on. The first: {} {}.",
+                failures.len(),
+                first.hook,
+                first.error
+            ),
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    /// Lets the user know the network seems down, and what happens to the scan.
+    fn report_network_down(&self, network: &Network) {
+        let message = match (network.reference(), network.down_since()) {
+            (Some(reference), Some(since)) if network.is_down() => format!(
+                "THE NETWORK APPEARS DOWN since {}, most probes being unreachable. Pausing the scan and checking {reference} every {} until it's back.",
+                time_of_day(since),
+                humantime::format_duration(network.interval())
+            ),
+            _ => "Most probes are unreachable, but no answering socket is known to tell whether the network is down. Carrying on, give one with --connectivity-check.".to_owned(),
+        };
+        warning!(
+            ErrorCode::NetworkDown,
+            message,
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    fn report_fallback(&self, fallback: &Fallback) {
+        detail!(
+            format!(
+                "No response from the first batch of {}, scanning {} instead",
+                fallback.from, fallback.to
+            ),
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    fn report_network_back(&self) {
+        warning!(
+            ErrorCode::NetworkDown,
+            "The network is back, resuming the scan with the probes which failed during the outage.",
+            self.greppable,
+            self.accessible
+        );
+    }
+
+    /// Probes the reference of `network` after its check interval.
+    fn check_connectivity<'a>(&'a self, network: &Network) -> LocalBoxFuture<'a, Event> {
+        let interval = network.interval();
+        let reference = network.reference();
+        async move {
+            async_std::task::sleep(interval).await;
+            let Some(reference) = reference else {
+                return Event::Connectivity(false);
+            };
+            // Refused is an answer too, the network carried it.
+            let answered = match self.reach(reference, self.timeout).await {
+                Ok(_) => true,
+                Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+            };
+            Event::Connectivity(answered)
+        }
+        .boxed_local()
+    }
+
+    /// Lets the user know a host went down or came back mid-scan.
+    fn report_transition(&self, ip: IpAddr, transition: Transition) {
+        let message = match (transition, self.heartbeat.as_ref().map(|h| h.on_down)) {
+            (Transition::WentDown, Some(HostDown::Pause)) => {
+                format!("Host {ip} stopped answering its heartbeats, pausing its scan.")
+            }
+            (Transition::WentDown, _) => {
+                format!("Host {ip} stopped answering its heartbeats.")
+            }
+            (Transition::CameBack, _) => {
+                format!("Host {ip} is answering again, resuming its scan.")
+            }
+            (Transition::GaveUp, _) => {
+                format!("Host {ip} is down, dropping the rest of its ports.")
+            }
+            (Transition::Unchanged, _) => return,
+        };
+        warning!(
+            ErrorCode::HostDown,
+            message,
+            self.greppable,
+            self.accessible,
+            host = ip
+        );
+    }
+
+    /// The tries and timeout of every host of a group of the timeout map,
+    /// resolved once for the whole scan.
+    fn host_limits(&self) -> HashMap<IpAddr, (u8, Duration)> {
+        let Some(timeout_map) = &self.timeout_map else {
+            return HashMap::new();
+        };
+        let mut limits = HashMap::new();
+        for ip in &self.ips {
+            let Some(group) = timeout_map.resolve(*ip) else {
+                continue;
+            };
+            let timeout = HostTimeout::new(group, self.tries.get());
+            verbose!(
+                format!(
+                    "Host {ip} is in the {} timeout group, probed with a {}ms timeout and {} tries.",
+                    timeout.group, timeout.timeout_ms, timeout.tries
+                ),
+                self.greppable,
+                self.accessible
+            );
+            limits.insert(*ip, (timeout.tries, group.timeout));
+        }
+        limits
+    }
+
+    /// The ports `strategy` picks, without the excluded ones, each once,
+    /// with how many repeats were dropped.
+    fn ports_of(&self, strategy: &PortStrategy) -> (Vec<u16>, usize) {
+        let order = strategy.order();
+        let order = match &self.canaries {
+            Some(canaries) => canaries.prepend(order),
+            None => order,
+        };
+        dedup::unique(
+            order
+                .iter()
+                .filter(|&port| !self.exclude_ports.contains(port))
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Fires the knock sequence at `ip`. Knock failures are reported but
+    /// never abort the scan.
+    async fn knock_host(&self, ip: IpAddr) {
+        let errors = knock::knock(ip, &self.knock, self.knock_delay, self.timeout).await;
+        for e in errors {
+            warning!(
+                ErrorCode::KnockFailed,
+                format!("Knock sequence to {ip} failed: {e}"),
+                self.greppable,
+                self.accessible,
+                host = ip
+            );
+        }
     }
 
     /// Given a socket, scan it self.tries times.
@@ -139,47 +1569,103 @@ impl Scanner {
         &self,
         socket: SocketAddr,
         udp_map: BTreeMap<Vec<u16>, Vec<u8>>,
-    ) -> io::Result<SocketAddr> {
+        policies: Option<&HostPolicies>,
+        limits: (u8, Duration),
+        outcomes: &mut Vec<TryOutcome>,
+    ) -> io::Result<Option<TcpStream>> {
         if self.udp {
-            return self.scan_udp_socket(socket, udp_map).await;
+            return self
+                .scan_udp_socket(socket, udp_map, limits)
+                .await
+                .map(|_| None);
         }
 
-        let tries = self.tries.get();
-        for nr_try in 1..=tries {
-            match self.connect(socket).await {
-                Ok(tcp_stream) => {
-                    debug!(
-                        "Connection was successful, shutting down stream {}",
-                        &socket
-                    );
-                    if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
-                        debug!("Shutdown stream error {}", &e);
-                    }
-                    self.fmt_ports(socket);
+        let mut nr_try = 0;
+        let mut bursts = 0;
+        loop {
+            nr_try += 1;
+            // The adaptive tries have the last word over the timeout map.
+            let (tries, timeout) = match policies {
+                Some(policies) => policies.limits(socket.ip(), limits.0, limits.1),
+                None => limits,
+            };
+            let result = self.hold(socket, timeout, self.prober.is_some()).await;
+            if let Err(e) = &result {
+                // Errors of this machine under load tell nothing of the port.
+                if bursts < self.platform.burst_retries && self.platform.is_burst_error(e) {
+                    self.warn_burst(e);
+                    let delay = self.platform.burst_delay * (1 << bursts);
+                    self.trace(|| TraceEvent::RateWait {
+                        ip: Some(socket.ip()),
+                        reason: WaitReason::Burst,
+                        wait_ms: trace::millis(delay),
+                    });
+                    async_std::task::sleep(delay).await;
+                    bursts += 1;
+                    nr_try -= 1;
+                    continue;
+                }
+            }
+            if let Some(policies) = policies {
+                // A refused connection is an answer too, only silence counts.
+                let answered = match &result {
+                    Ok(_) => true,
+                    Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+                };
+                self.report_change(socket.ip(), policies.record(socket.ip(), answered));
+            }
 
+            outcomes.push(TryOutcome::of(&result));
+
+            match result {
+                Ok(stream) => {
                     debug!("Return Ok after {} tries", nr_try);
-                    return Ok(socket);
+                    if self.verify_open {
+                        for _ in nr_try..tries {
+                            let verified = self.reach(socket, timeout).await;
+                            outcomes.push(TryOutcome::of(&verified));
+                        }
+                    }
+                    return Ok(stream);
                 }
                 Err(e) => {
                     let mut error_string = e.to_string();
 
                     assert!(!error_string.to_lowercase().contains("too many open files"), "Too many open files. Please reduce batch size. The default is 5000. Try -b 2500.");
 
-                    if nr_try == tries {
+                    if nr_try >= tries {
                         error_string.push(' ');
                         error_string.push_str(&socket.ip().to_string());
-                        return Err(io::Error::new(io::ErrorKind::Other, error_string));
+                        // The kind tells timeouts apart, to pace the host.
+                        return Err(io::Error::new(e.kind(), error_string));
                     }
                 }
             };
         }
-        unreachable!();
+    }
+
+    /// Warns of the first burst error of the scan, which is retried.
+    fn warn_burst(&self, error: &io::Error) {
+        if self.burst_warned.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        warning!(
+            ErrorCode::BurstErrors,
+            format!(
+                "Connections fail with \"{error}\" under load, retrying them. \
+                 If ports go missing, lower the batch size, like -b {}.",
+                self.batch_size / 2
+            ),
+            self.greppable,
+            self.accessible
+        );
     }
 
     async fn scan_udp_socket(
         &self,
         socket: SocketAddr,
         udp_map: BTreeMap<Vec<u16>, Vec<u8>>,
+        (tries, timeout): (u8, Duration),
     ) -> io::Result<SocketAddr> {
         let mut payload: Vec<u8> = Vec::new();
         for (key, value) in udp_map {
@@ -188,9 +1674,8 @@ impl Scanner {
             }
         }
 
-        let tries = self.tries.get();
         for _ in 1..=tries {
-            match self.udp_scan(socket, &payload, self.timeout).await {
+            match self.udp_scan(socket, &payload, timeout).await {
                 Ok(true) => return Ok(socket),
                 Ok(false) => continue,
                 Err(e) => return Err(e),
@@ -209,20 +1694,77 @@ impl Scanner {
     /// // ip is an IpAddr type
     /// let ip = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
     /// let socket = SocketAddr::new(ip, port);
-    /// scanner.connect(socket);
+    /// scanner.connect(socket, timeout);
     /// // returns Result which is either Ok(stream) for port is open, or Er for port is closed.
-    /// // Timeout occurs after self.timeout seconds
+    /// // Timeout occurs after `timeout`
     /// ```
     ///
-    async fn connect(&self, socket: SocketAddr) -> io::Result<TcpStream> {
-        let stream = io::timeout(
-            self.timeout,
-            async move { TcpStream::connect(socket).await },
-        )
+    async fn connect(&self, socket: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let binding = self.bindings.get(&socket.ip());
+        let stream = io::timeout(timeout, async move {
+            if self.socket_options.is_default()
+                && self.source_ports.is_none()
+                && self.socket_pool.is_none()
+                && binding.is_none()
+            {
+                TcpStream::connect(socket).await
+            } else {
+                self.socket_options
+                    .connect(
+                        socket,
+                        self.source_ports.as_ref(),
+                        self.socket_pool.as_ref(),
+                        binding,
+                    )
+                    .await
+            }
+        })
         .await?;
         Ok(stream)
     }
 
+    /// Connects to `socket`, through the SSH bastion or the SOCKS proxy when
+    /// there is one, and closes the connection right away. Ok when the port
+    /// is open.
+    async fn reach(&self, socket: SocketAddr, timeout: Duration) -> io::Result<()> {
+        self.hold(socket, timeout, false).await.map(drop)
+    }
+
+    /// Like [`Scanner::reach`], keeping the connection when asked to and
+    /// there is one of this machine.
+    async fn hold(
+        &self,
+        socket: SocketAddr,
+        timeout: Duration,
+        keep: bool,
+    ) -> io::Result<Option<TcpStream>> {
+        if let Some(session) = &self.ssh_jump {
+            return Arc::clone(session)
+                .probe(socket, timeout)
+                .await
+                .map(|()| None);
+        }
+        if let Some(route) = self
+            .proxy
+            .as_ref()
+            .filter(|route| route.proxies(socket.ip()))
+        {
+            return route.probe(socket, timeout).await.map(|()| None);
+        }
+        let tcp_stream = self.connect(socket, timeout).await?;
+        if keep {
+            return Ok(Some(tcp_stream));
+        }
+        debug!(
+            "Connection was successful, shutting down stream {}",
+            &socket
+        );
+        if let Err(e) = tcp_stream.shutdown(Shutdown::Both) {
+            debug!("Shutdown stream error {}", &e);
+        }
+        Ok(None)
+    }
+
     /// Binds to a UDP socket so we can send and recieve packets
     /// # Example
     ///
@@ -238,6 +1780,13 @@ impl Scanner {
     /// ```
     ///
     async fn udp_bind(&self, socket: SocketAddr) -> io::Result<UdpSocket> {
+        let binding = self.bindings.get(&socket.ip());
+        if !self.socket_options.is_default() || self.source_ports.is_some() || binding.is_some() {
+            return self
+                .socket_options
+                .bind_udp(socket, self.source_ports.as_ref(), binding);
+        }
+
         let local_addr = match socket {
             SocketAddr::V4(_) => "0.0.0.0:0".parse::<SocketAddr>().unwrap(),
             SocketAddr::V6(_) => "[::]:0".parse::<SocketAddr>().unwrap(),
@@ -277,7 +1826,6 @@ impl Scanner {
                 match io::timeout(wait, udp_socket.recv(&mut buf)).await {
                     Ok(size) => {
                         debug!("Received {} bytes", size);
-                        self.fmt_ports(socket);
                         Ok(true)
                     }
                     Err(e) => {
@@ -290,29 +1838,91 @@ impl Scanner {
                 }
             }
             Err(e) => {
-                println!("Err E binding sock {:?}", e);
+                eprintln!("Err E binding sock {:?}", e);
                 Err(e)
             }
         }
     }
+}
 
-    /// Formats and prints the port status
-    fn fmt_ports(&self, socket: SocketAddr) {
-        if !self.greppable {
-            if self.accessible {
-                println!("Open {socket}");
-            } else {
-                println!("Open {}", socket.to_string().purple());
-            }
-        }
+/// How the probe of a socket ended, for pacing.
+fn outcome(result: &io::Result<SocketAddr>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Answered,
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Outcome::Answered,
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => Outcome::TimedOut,
+        Err(_) => Outcome::Failed,
+    }
+}
+
+/// The most probes in flight at a time, lowered by the conntrack backoff
+/// and the limit of the control.
+fn batch_size(batch_size: u16, backoff: Option<&Backoff>, control: Option<&ScanControl>) -> usize {
+    let batch_size = backoff.map_or(batch_size.into(), Backoff::batch_size);
+    match control.and_then(ScanControl::batch_limit) {
+        Some(limit) => batch_size.min(limit),
+        None => batch_size,
+    }
+}
+
+/// The `14:32:05` of an RFC 3339 time, UTC.
+fn time_of_day(rfc3339: &str) -> String {
+    match rfc3339.get(11..19) {
+        Some(time) => format!("{time} UTC"),
+        None => rfc3339.to_owned(),
+    }
+}
+
+/// The most probes of `ip` in flight at a time, as far as pacing goes.
+fn concurrency(throttle: Option<&Throttle>, ip: IpAddr) -> usize {
+    throttle.map_or(usize::MAX, |throttle| throttle.concurrency(ip))
+}
+
+/// How often the forecast is printed in verbose mode.
+const FORECAST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often a paused scan checks whether it was resumed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn poll<'a>() -> LocalBoxFuture<'a, Event> {
+    async_std::task::sleep(POLL_INTERVAL)
+        .map(|()| Event::Poll)
+        .boxed_local()
+}
+
+/// The longest the spread tries sleep, so that a stopped scan doesn't wait
+/// for its next retry to end.
+const RETRY_WAKE: Duration = Duration::from_secs(1);
+
+/// When to wake up for the spread tries next, the earliest being `due`: a
+/// retry due already waits for room, or for the scan to be resumed.
+fn retry_wake(due: Instant, now: Instant) -> Instant {
+    if due <= now {
+        now + POLL_INTERVAL
+    } else {
+        due.min(now + RETRY_WAKE)
     }
 }
 
+fn retry_due<'a>(wake: Instant) -> LocalBoxFuture<'a, Event> {
+    async_std::task::sleep(wake.saturating_duration_since(Instant::now()))
+        .map(move |()| Event::RetryDue(wake))
+        .boxed_local()
+}
+
+/// Waits for `interval`, the clock of the heartbeats.
+fn tick<'a>(interval: Duration) -> LocalBoxFuture<'a, Event> {
+    async_std::task::sleep(interval)
+        .map(|()| Event::Tick)
+        .boxed_local()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::input::{PortRange, ScanOrder};
     use async_std::task::block_on;
+    use std::sync::atomic::AtomicUsize;
     use std::{net::IpAddr, time::Duration};
 
     #[test]
@@ -428,6 +2038,906 @@ mod tests {
         assert_eq!(1, 1);
     }
 
+    #[test]
+    fn low_ttl_scanner_finds_open_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let addrs = vec!["127.0.0.1".parse::<IpAddr>().unwrap()];
+        let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &addrs,
+            10,
+            Duration::from_millis(500),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+            false,
+        )
+        .with_socket_options(SocketOptions {
+            ttl: Some(1),
+            nodelay: true,
+        });
+
+        assert!(scanner.check_socket_options().is_ok());
+        let open = block_on(scanner.run());
+        assert_eq!(open, vec![SocketAddr::new(addrs[0], port)]);
+    }
+
+    // The whole of 127.0.0.0/8 only reaches the listeners on Linux.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn port_overrides_limit_their_host() {
+        let listeners: Vec<std::net::TcpListener> = (0..3)
+            .map(|_| std::net::TcpListener::bind("0.0.0.0:0").unwrap())
+            .collect();
+        let ports: Vec<u16> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port())
+            .collect();
+        let overridden: IpAddr = "127.0.0.2".parse().unwrap();
+        let global: IpAddr = "127.0.0.3".parse().unwrap();
+        let strategy = PortStrategy::pick(&None, Some(ports.clone()), ScanOrder::Serial);
+        let override_strategy =
+            PortStrategy::pick(&None, Some(ports[..2].to_vec()), ScanOrder::Serial);
+        let scanner = Scanner::new(
+            &[overridden, global],
+            10,
+            Duration::from_millis(500),
+            1,
+            true,
+            strategy,
+            true,
+            vec![],
+            false,
+        )
+        .with_port_overrides(HashMap::from([(overridden, override_strategy)]));
+
+        let mut open = block_on(scanner.run());
+        open.sort();
+        let mut expected: Vec<SocketAddr> = ports[..2]
+            .iter()
+            .map(|port| SocketAddr::new(overridden, *port))
+            .chain(ports.iter().map(|port| SocketAddr::new(global, *port)))
+            .collect();
+        expected.sort();
+        assert_eq!(open, expected);
+    }
+
+    #[test]
+    fn canaries_are_probed_before_the_other_ports() {
+        let ips: [IpAddr; 2] = ["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let canaries = [40_120, 40_010];
+        let probed = |fairness: Fairness, first_pass: bool| {
+            let range = PortRange {
+                ranges: vec![(40_000, 40_019)],
+            };
+            let (feed, updates) = std::sync::mpsc::channel();
+            // One socket at a time, probed in the order they are handed out.
+            let scanner = Scanner::new(
+                &ips,
+                1,
+                Duration::from_millis(200),
+                1,
+                true,
+                PortStrategy::pick(&Some(range), None, ScanOrder::Random),
+                true,
+                vec![],
+                false,
+            )
+            .with_fairness(fairness)
+            .with_canaries(Canaries {
+                ports: canaries.to_vec(),
+                first_pass,
+            })
+            .with_feed(feed);
+            block_on(scanner.run());
+            updates
+                .try_iter()
+                .filter_map(|update| match update {
+                    ScanUpdate::Probed(socket) => Some(socket),
+                    _ => None,
+                })
+                .collect::<Vec<SocketAddr>>()
+        };
+        let is_canary = |socket: &SocketAddr| canaries.contains(&socket.port());
+
+        // Added to the range, first of every host.
+        let sockets = probed(Fairness::InputOrder, false);
+        assert_eq!(sockets.len(), 42);
+        let canary_positions: Vec<usize> = (0..sockets.len())
+            .filter(|index| is_canary(&sockets[*index]))
+            .collect();
+        assert_eq!(canary_positions, [0, 1, 21, 22]);
+
+        // Done on every host before any other port, however interleaved.
+        for fairness in [
+            Fairness::RoundRobin,
+            Fairness::Proportional,
+            Fairness::InputOrder,
+        ] {
+            let sockets = probed(fairness, true);
+            assert_eq!(sockets.len(), 42);
+            assert!(sockets[..4].iter().all(is_canary), "{:?}", sockets);
+            assert!(!sockets[4..].iter().any(is_canary), "{:?}", sockets);
+        }
+    }
+
+    #[test]
+    fn hosts_are_knocked_right_before_their_probes() {
+        use crate::input::{Knock, KnockProtocol};
+        use std::net::TcpListener;
+        use std::sync::Mutex;
+
+        let knocked: Arc<Mutex<Vec<(IpAddr, Instant)>>> = Arc::default();
+        let knock_listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let knock_port = knock_listener.local_addr().unwrap().port();
+        let recorder = Arc::clone(&knocked);
+        std::thread::spawn(move || {
+            for stream in knock_listener.incoming().flatten() {
+                let ip = stream.local_addr().unwrap().ip();
+                recorder.lock().unwrap().push((ip, Instant::now()));
+            }
+        });
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let ips: [IpAddr; 2] = ["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let knock = Knock {
+            port: knock_port,
+            protocol: KnockProtocol::Tcp,
+        };
+        let delay = Duration::from_millis(200);
+        // One socket at a time, the second host after the first one.
+        let scanner = Scanner::new(
+            &ips,
+            1,
+            Duration::from_millis(500),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        .with_fairness(Fairness::InputOrder)
+        .with_knock(vec![knock, knock], delay);
+        let mut open = block_on(scanner.run());
+        open.sort_unstable();
+        assert_eq!(
+            open,
+            ips.map(|ip| SocketAddr::new(ip, port)),
+            "every host is probed after its knocks"
+        );
+
+        std::thread::sleep(Duration::from_millis(50));
+        let knocked = knocked.lock().unwrap();
+        let hosts: Vec<IpAddr> = knocked.iter().map(|(ip, _)| *ip).collect();
+        assert_eq!(hosts, [ips[0], ips[0], ips[1], ips[1]]);
+        // The second host is knocked once the first one is probed, not with it.
+        let gap = knocked[2].1.saturating_duration_since(knocked[0].1);
+        assert!(
+            gap >= delay - Duration::from_millis(20),
+            "gap was {:?}",
+            gap
+        );
+    }
+
+    #[test]
+    fn previously_open_ports_are_probed_first() {
+        let ips: [IpAddr; 2] = ["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let ports = vec![40_001, 40_002, 40_003, 40_004, 40_005];
+        let (feed, updates) = std::sync::mpsc::channel();
+        let scanner = Scanner::new(
+            &ips,
+            1,
+            Duration::from_millis(200),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(ports), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        .with_fairness(Fairness::InputOrder)
+        .with_canaries(Canaries {
+            ports: vec![40_005],
+            first_pass: false,
+        })
+        // 40_009 isn't scanned, it isn't added.
+        .with_priorities(HashMap::from([(ips[0], vec![40_004, 40_002, 40_009])]))
+        .with_feed(feed);
+        block_on(scanner.run());
+        let probed: Vec<SocketAddr> = updates
+            .try_iter()
+            .filter_map(|update| match update {
+                ScanUpdate::Probed(socket) => Some(socket),
+                _ => None,
+            })
+            .collect();
+        let order = |ip: IpAddr| -> Vec<u16> {
+            probed
+                .iter()
+                .filter(|socket| socket.ip() == ip)
+                .map(SocketAddr::port)
+                .collect()
+        };
+        // The canary still first, the serial order for the rest.
+        assert_eq!(order(ips[0]), [40_005, 40_002, 40_004, 40_001, 40_003]);
+        assert_eq!(order(ips[1]), [40_005, 40_001, 40_002, 40_003, 40_004]);
+    }
+
+    #[test]
+    fn overlapping_sources_probe_every_socket_once() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ips: [IpAddr; 3] = [
+            "127.0.0.1".parse().unwrap(),
+            "127.0.0.2".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+        ];
+        let ports = vec![open, 40_001, open, 40_002, 40_001];
+        let (feed, updates) = std::sync::mpsc::channel();
+        let scanner = Scanner::new(
+            &ips,
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(ports), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        // The canary is in the list already, and given twice.
+        .with_canaries(Canaries {
+            ports: vec![40_002, 40_002],
+            first_pass: true,
+        })
+        .with_port_overrides(HashMap::from([(
+            ips[1],
+            PortStrategy::pick(&None, Some(vec![40_003, 40_003]), ScanOrder::Serial),
+        )]))
+        .with_feed(feed);
+        assert_eq!(scanner.sockets(), 5);
+
+        let outcome = block_on(scanner.scan());
+        let mut probed: Vec<SocketAddr> = updates
+            .try_iter()
+            .filter_map(|update| match update {
+                ScanUpdate::Probed(socket) => Some(socket),
+                _ => None,
+            })
+            .collect();
+        probed.sort();
+        let mut unique = probed.clone();
+        unique.dedup();
+        assert_eq!(probed, unique);
+        assert_eq!(probed.len(), 5);
+        assert_eq!(outcome.open, [SocketAddr::new(ips[0], open)]);
+        // 2 repeats in the list of 127.0.0.1, given twice, 1 in the one of
+        // 127.0.0.2, and the 3 sockets of the second 127.0.0.1.
+        assert_eq!(outcome.duplicates, 2 * 2 + 1 + 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn burst_errors_are_retried() {
+        // Refused connections stand in for the bursts of macOS.
+        const REFUSED: [i32; 1] = [libc::ECONNREFUSED];
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let scanner = Scanner::new(
+            &[ip],
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(vec![40_004]), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        .with_platform(PlatformDefaults {
+            burst_errors: &REFUSED,
+            burst_retries: 2,
+            burst_delay: Duration::from_millis(40),
+            ..PlatformDefaults::of(Os::MacOs)
+        });
+        let started = Instant::now();
+        assert!(block_on(scanner.run()).is_empty());
+        // 40 ms, then 80 ms, before giving up on the one try.
+        assert!(started.elapsed() >= Duration::from_millis(120));
+        assert!(scanner.burst_warned.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn spread_tries_find_flaky_ports() {
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = free.local_addr().unwrap().port();
+        drop(free);
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let scanner = |spread| {
+            Scanner::new(
+                &[ip],
+                10,
+                Duration::from_millis(200),
+                3,
+                true,
+                PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial),
+                true,
+                vec![],
+                false,
+            )
+            .with_spread_tries(SpreadTries::Over(spread))
+        };
+
+        // Refused at first, the port opens before its second try, 300ms in.
+        let opened = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap();
+            std::thread::sleep(Duration::from_millis(1_000));
+            drop(listener);
+        });
+        let started = Instant::now();
+        let outcome = block_on(scanner(Duration::from_millis(600)).scan());
+        assert_eq!(outcome.open, [SocketAddr::new(ip, port)]);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert_eq!(outcome.pending_retries, 0);
+        let tries = &outcome.tries[&SocketAddr::new(ip, port)];
+        assert_eq!(tries, &[TryOutcome::Refused, TryOutcome::Open]);
+        assert_eq!(Confidence::of(tries), Some(Confidence::Flaky));
+        opened.join().unwrap();
+
+        // A stopped scan doesn't wait for the tries to go, which count as
+        // closed.
+        let control = ScanControl::default();
+        let stop = control.clone();
+        let stopped = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            stop.stop();
+        });
+        let started = Instant::now();
+        let outcome = block_on(
+            scanner(Duration::from_secs(60))
+                .with_control(control)
+                .scan(),
+        );
+        stopped.join().unwrap();
+        assert!(outcome.open.is_empty());
+        assert_eq!(outcome.pending_retries, 1);
+        assert!(outcome.tries.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn verified_open_ports_go_through_their_tries() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = {
+            let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            free.local_addr().unwrap()
+        };
+        let scanner = || {
+            Scanner::new(
+                &[open.ip()],
+                10,
+                Duration::from_millis(500),
+                3,
+                true,
+                PortStrategy::pick(
+                    &None,
+                    Some(vec![open.port(), closed.port()]),
+                    ScanOrder::Serial,
+                ),
+                true,
+                vec![],
+                false,
+            )
+        };
+
+        // The tries stop at the first which connects otherwise.
+        let outcome = block_on(scanner().scan());
+        assert_eq!(outcome.tries.len(), 1);
+        assert_eq!(outcome.tries[&open], [TryOutcome::Open]);
+
+        let outcome = block_on(scanner().with_verified_open().scan());
+        assert_eq!(outcome.open, [open]);
+        assert_eq!(outcome.tries.len(), 1);
+        assert_eq!(outcome.tries[&open], [TryOutcome::Open; 3]);
+        assert_eq!(
+            Confidence::of(&outcome.tries[&open]),
+            Some(Confidence::ConfirmedOpen)
+        );
+    }
+
+    #[test]
+    fn sockets_which_never_answer_are_filtered() {
+        use std::io::{Read, Write};
+
+        // A SOCKS5 proxy which reaches port 1 of 192.0.2.1, is refused by
+        // its other ports, can't reach 192.0.2.2 and never answers for
+        // 192.0.2.3.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut silent = Vec::new();
+            for mut stream in listener.incoming().flatten() {
+                let mut greeting = [0; 3];
+                let mut request = [0; 10];
+                if stream.read_exact(&mut greeting).is_err()
+                    || stream.write_all(&[5, 0]).is_err()
+                    || stream.read_exact(&mut request).is_err()
+                {
+                    continue;
+                }
+                let reply = match (request[7], request[9]) {
+                    (1, 1) => 0,
+                    (1, _) => 5,
+                    (2, _) => 4,
+                    _ => {
+                        silent.push(stream);
+                        continue;
+                    }
+                };
+                let _ = stream.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]);
+            }
+        });
+
+        let ips: Vec<IpAddr> = vec![
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+            "192.0.2.3".parse().unwrap(),
+        ];
+        let scanner = Scanner::new(
+            &ips,
+            10,
+            Duration::from_millis(300),
+            1,
+            true,
+            PortStrategy::pick(
+                &Some(PortRange {
+                    ranges: vec![(1, 2)],
+                }),
+                None,
+                ScanOrder::Serial,
+            ),
+            true,
+            vec![],
+            false,
+        )
+        .with_proxy(ProxyRoute::new(
+            format!("socks5://{address}").parse().unwrap(),
+            address,
+        ));
+
+        let mut outcome = block_on(scanner.scan());
+        assert_eq!(outcome.open, [SocketAddr::new(ips[0], 1)]);
+        outcome.filtered.sort();
+        assert_eq!(
+            outcome.filtered,
+            [
+                SocketAddr::new(ips[1], 1),
+                SocketAddr::new(ips[1], 2),
+                SocketAddr::new(ips[2], 1),
+                SocketAddr::new(ips[2], 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn probes_go_through_the_socks_proxy() {
+        use std::io::{Read, Write};
+
+        // A SOCKS5 proxy which only reaches 192.0.2.1:8443, refused by the
+        // other ports.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut greeting = [0; 3];
+                let mut request = [0; 10];
+                if stream.read_exact(&mut greeting).is_err()
+                    || stream.write_all(&[5, 0]).is_err()
+                    || stream.read_exact(&mut request).is_err()
+                {
+                    continue;
+                }
+                let reached =
+                    request[4..8] == [192, 0, 2, 1] && request[8..] == 8443u16.to_be_bytes();
+                let reply = if reached { 0 } else { 5 };
+                let _ = stream.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]);
+            }
+        });
+
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let scanner = Scanner::new(
+            &[ip],
+            10,
+            Duration::from_millis(500),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(vec![22, 8443, 9000]), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        );
+        let proxy: SocksProxy = format!("socks5://{address}").parse().unwrap();
+        let outcome = block_on(scanner.with_proxy(ProxyRoute::new(proxy, address)).scan());
+        assert_eq!(outcome.open, [SocketAddr::new(ip, 8443)]);
+    }
+
+    #[test]
+    fn ports_left_of_prohibited_hosts_are_skipped() {
+        use std::io::{Read, Write};
+
+        // A SOCKS5 proxy whose ruleset doesn't allow 192.0.2.1, refused by
+        // every port of the other hosts.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut greeting = [0; 3];
+                let mut request = [0; 10];
+                if stream.read_exact(&mut greeting).is_err()
+                    || stream.write_all(&[5, 0]).is_err()
+                    || stream.read_exact(&mut request).is_err()
+                {
+                    continue;
+                }
+                let reply = if request[4..8] == [192, 0, 2, 1] {
+                    2
+                } else {
+                    5
+                };
+                let _ = stream.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]);
+            }
+        });
+
+        let (prohibited, refusing): (IpAddr, IpAddr) =
+            ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let scan = |admin_prohibited: Option<AdminProhibited>| {
+            let (feed, updates) = std::sync::mpsc::channel();
+            let scanner = Scanner::new(
+                &[prohibited, refusing],
+                1,
+                Duration::from_millis(500),
+                1,
+                true,
+                PortStrategy::pick(
+                    &Some(PortRange {
+                        ranges: vec![(1, 20)],
+                    }),
+                    None,
+                    ScanOrder::Serial,
+                ),
+                true,
+                vec![],
+                false,
+            )
+            .with_proxy(ProxyRoute::new(
+                format!("socks5://{address}").parse().unwrap(),
+                address,
+            ))
+            .with_feed(feed);
+            let scanner = match admin_prohibited {
+                Some(admin_prohibited) => scanner.with_admin_prohibited(admin_prohibited),
+                None => scanner,
+            };
+            let outcome = block_on(scanner.scan());
+            let (mut probed, mut skipped) = (HashMap::new(), HashMap::new());
+            for update in updates.try_iter() {
+                match update {
+                    ScanUpdate::Probed(socket) => *probed.entry(socket.ip()).or_insert(0) += 1,
+                    ScanUpdate::Skipped(socket) => *skipped.entry(socket.ip()).or_insert(0) += 1,
+                    _ => {}
+                }
+            }
+            (outcome, probed, skipped)
+        };
+
+        let (outcome, probed, skipped) = scan(Some(AdminProhibited { after: 3 }));
+        assert_eq!(outcome.forecast.remaining, 0);
+        assert_eq!(probed[&prohibited], 3);
+        assert_eq!(skipped[&prohibited], 17);
+        assert_eq!(probed[&refusing], 20);
+        assert!(!skipped.contains_key(&refusing));
+        assert_eq!(
+            outcome.prohibitions,
+            [AdminProhibition {
+                ip: prohibited,
+                error: "permission denied".to_owned(),
+                after_probes: 3,
+                skipped_ports: 17,
+            }]
+        );
+
+        let (outcome, probed, skipped) = scan(None);
+        assert!(outcome.prohibitions.is_empty());
+        assert_eq!(probed[&prohibited], 20);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn hooks_are_called_as_the_scan_goes() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::{Arc, Mutex};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let progress = Arc::new(AtomicUsize::new(0));
+        let hooks = Hooks::new()
+            .on_open({
+                let opened = Arc::clone(&opened);
+                move |socket, _| {
+                    opened.lock().unwrap().push(socket);
+                    Ok(())
+                }
+            })
+            .on_host_complete({
+                let completed = Arc::clone(&completed);
+                move |summary| {
+                    completed.lock().unwrap().push(summary.clone());
+                    // A failing hook stops nothing.
+                    anyhow::bail!("done with {}", summary.ip)
+                }
+            })
+            .on_progress({
+                let progress = Arc::clone(&progress);
+                move |stats| {
+                    progress.store(stats.settled, Ordering::Relaxed);
+                    Ok(())
+                }
+            });
+        let scanner = Scanner::new(
+            &[ip],
+            10,
+            Duration::from_millis(200),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(vec![open, 40_005, 40_006]), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        .with_hooks(hooks);
+
+        let outcome = block_on(scanner.scan());
+        assert_eq!(outcome.open, [SocketAddr::new(ip, open)]);
+        assert_eq!(*opened.lock().unwrap(), outcome.open);
+        let completed = completed.lock().unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(
+            (completed[0].open.as_slice(), completed[0].sockets),
+            (&[open][..], 3)
+        );
+        // The last progress is told once the scan is over.
+        assert_eq!(progress.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            outcome.hook_failures,
+            [HookFailure {
+                hook: "on_host_complete",
+                error: "done with 127.0.0.1".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn silent_hosts_fall_back_during_the_scan() {
+        use std::io::{Read, Write};
+
+        // A SOCKS5 proxy which never answers for 192.0.2.1, reaches
+        // 192.0.2.2 and is refused by 192.0.2.3.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut silent = Vec::new();
+            for mut stream in listener.incoming().flatten() {
+                let mut greeting = [0; 3];
+                let mut request = [0; 10];
+                if stream.read_exact(&mut greeting).is_err()
+                    || stream.write_all(&[5, 0]).is_err()
+                    || stream.read_exact(&mut request).is_err()
+                {
+                    continue;
+                }
+                let reply = match request[7] {
+                    2 => 0,
+                    3 => 5,
+                    _ => {
+                        silent.push(stream);
+                        continue;
+                    }
+                };
+                let _ = stream.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]);
+            }
+        });
+
+        let ip = |last: u8| IpAddr::from([192, 0, 2, last]);
+        let (feed, updates) = std::sync::mpsc::channel();
+        let scanner = Scanner::new(
+            &[ip(1), ip(3)],
+            10,
+            Duration::from_millis(300),
+            1,
+            true,
+            PortStrategy::pick(&None, Some(vec![1]), ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+        .with_proxy(ProxyRoute::new(
+            format!("socks5://{address}").parse().unwrap(),
+            address,
+        ))
+        .with_fallbacks(HashMap::from([(ip(1), ip(2)), (ip(3), ip(4))]))
+        .with_feed(feed);
+
+        // A refused connection is an answer, only the silent host falls back.
+        let outcome = block_on(scanner.scan());
+        assert_eq!(outcome.open, [SocketAddr::new(ip(2), 1)]);
+        assert_eq!(
+            outcome.fallbacks,
+            [Fallback {
+                from: ip(1),
+                to: ip(2),
+                sockets: 1,
+            }]
+        );
+        assert!(updates.try_iter().any(|update| update
+            == ScanUpdate::Started {
+                hosts: vec![(ip(2), 1)],
+                batch_size: 10,
+            }));
+    }
+
+    #[test]
+    fn control_steers_the_scan() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let kept: IpAddr = "127.0.0.1".parse().unwrap();
+        let skipped: IpAddr = "127.0.0.2".parse().unwrap();
+        let scanner = |control: &ScanControl, feed| {
+            let strategy = PortStrategy::pick(&None, Some(vec![port]), ScanOrder::Serial);
+            Scanner::new(
+                &[kept, skipped],
+                10,
+                Duration::from_millis(500),
+                1,
+                true,
+                strategy,
+                true,
+                vec![],
+                false,
+            )
+            .with_control(control.clone())
+            .with_feed(feed)
+        };
+
+        // A skipped host is left out, a paused scan waits to be resumed.
+        let control = ScanControl::default();
+        control.skip(skipped);
+        control.set_paused(true);
+        let resume = control.clone();
+        let resumed = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            resume.set_paused(false);
+        });
+        let (feed, updates) = std::sync::mpsc::channel();
+        let start = Instant::now();
+        let open = block_on(scanner(&control, feed).run());
+        resumed.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(open, [SocketAddr::new(kept, port)]);
+        assert_eq!(
+            updates.try_iter().collect::<Vec<ScanUpdate>>(),
+            [
+                ScanUpdate::Started {
+                    hosts: vec![(kept, 1), (skipped, 1)],
+                    batch_size: 10,
+                },
+                ScanUpdate::Skipped(SocketAddr::new(skipped, port)),
+                ScanUpdate::Open(SocketAddr::new(kept, port)),
+                ScanUpdate::Probed(SocketAddr::new(kept, port)),
+            ]
+        );
+
+        // A limited batch probes one socket after the other.
+        let control = ScanControl::default();
+        control.limit_batch(Some(1));
+        let (feed, updates) = std::sync::mpsc::channel();
+        let open = block_on(scanner(&control, feed).run());
+        assert_eq!(open, [SocketAddr::new(kept, port)]);
+        assert_eq!(
+            updates.try_iter().collect::<Vec<ScanUpdate>>(),
+            [
+                ScanUpdate::Started {
+                    hosts: vec![(kept, 1), (skipped, 1)],
+                    batch_size: 10,
+                },
+                ScanUpdate::Open(SocketAddr::new(kept, port)),
+                ScanUpdate::Probed(SocketAddr::new(kept, port)),
+                ScanUpdate::Probed(SocketAddr::new(skipped, port)),
+            ]
+        );
+
+        // A stopped scan probes nothing more, and says what was left.
+        let control = ScanControl::default();
+        control.stop();
+        let (feed, _updates) = std::sync::mpsc::channel();
+        let outcome = block_on(scanner(&control, feed).scan());
+        assert!(outcome.open.is_empty());
+        assert_eq!(outcome.forecast.remaining, 2);
+    }
+
+    /// A local listener counting its connections, greeting them with `banner`
+    /// when there is one and keeping them open.
+    fn counting_listener(banner: Option<&'static [u8]>) -> (u16, Arc<AtomicUsize>) {
+        use std::io::Write;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&accepted);
+        std::thread::spawn(move || {
+            let mut kept = Vec::new();
+            for mut stream in listener.incoming().flatten() {
+                counted.fetch_add(1, Ordering::SeqCst);
+                if let Some(banner) = banner {
+                    let _ = stream.write_all(banner);
+                }
+                kept.push(stream);
+            }
+        });
+        (port, accepted)
+    }
+
+    #[test]
+    fn open_sockets_are_served_on_their_connection() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let budget = Duration::from_millis(300);
+        let scanner = |ports: Vec<u16>, feed| {
+            Scanner::new(
+                &[ip],
+                1,
+                Duration::from_millis(500),
+                1,
+                true,
+                PortStrategy::pick(&None, Some(ports), ScanOrder::Serial),
+                true,
+                vec![],
+                false,
+            )
+            .with_probes(Prober::new(budget))
+            .with_feed(feed)
+        };
+
+        // A port speaking first is connected to once.
+        let (ssh, ssh_accepted) = counting_listener(Some(b"SSH-2.0-OpenSSH_9.6\r\n"));
+        let (ftp, ftp_accepted) = counting_listener(Some(b"220 ProFTPD Server ready\r\n"));
+        let (feed, updates) = std::sync::mpsc::channel();
+        let outcome = block_on(scanner(vec![ssh, ftp], feed).scan());
+        assert_eq!(outcome.open.len(), 2);
+        assert_eq!(ssh_accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(ftp_accepted.load(Ordering::SeqCst), 1);
+        let served = &outcome.probes[&SocketAddr::new(ip, ssh)];
+        assert_eq!(served.service.as_deref(), Some("ssh"));
+        assert!(updates
+            .try_iter()
+            .any(|update| update == ScanUpdate::Served(SocketAddr::new(ip, ssh), served.clone())));
+
+        // A socket being served keeps the room it took in the batch.
+        let (first, _) = counting_listener(None);
+        let (second, _) = counting_listener(None);
+        let (feed, _updates) = std::sync::mpsc::channel();
+        let start = Instant::now();
+        let outcome = block_on(scanner(vec![first, second], feed).scan());
+        assert_eq!(outcome.probes.len(), 2);
+        assert!(start.elapsed() >= budget * 2);
+    }
+
     #[test]
     fn udp_scan_runs() {
         // Makes sure the program still runs and doesn't panic
@@ -0,0 +1,380 @@
+//! Notices the network of the scanner going down in the middle of a scan.
+//!
+//! A pulled cable or a dropped VPN turns every probe into a network or host
+//! unreachable error, and the rest of the scan into closed ports. When most
+//! of the last probes failed that way the scan is paused, and a reference
+//! socket, the one of `--connectivity-check` or else the first one found
+//! open, is probed every check interval. Once it answers the scan resumes,
+//! the probes which failed during the outage being tried again first.
+use serde_derive::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+/// When the network is considered down, and how it's checked for recovery.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Connectivity {
+    /// How many of the last probes the unreachable errors are counted over.
+    pub window: usize,
+    /// The share of unreachable errors over the window, from 0 to 1, at
+    /// which the network is considered down.
+    pub threshold: f64,
+    /// How often the reference is probed while the network is down.
+    pub interval: Duration,
+    /// The socket to check connectivity against, the first open one when
+    /// not given.
+    pub reference: Option<SocketAddr>,
+}
+
+/// Whether a probe failed because the network or the host can't be reached
+/// from here, rather than because of the port.
+pub fn is_unreachable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NetworkUnreachable | io::ErrorKind::HostUnreachable
+    )
+}
+
+/// The share of unreachable errors among the last probes.
+#[derive(Debug, Clone)]
+pub struct ErrorWindow {
+    size: usize,
+    threshold: f64,
+    /// Whether each of the last probes was unreachable, the oldest first.
+    samples: VecDeque<bool>,
+    unreachable: usize,
+}
+
+impl ErrorWindow {
+    pub fn new(size: usize, threshold: f64) -> Self {
+        let size = size.max(1);
+        Self {
+            size,
+            threshold,
+            samples: VecDeque::with_capacity(size),
+            unreachable: 0,
+        }
+    }
+
+    /// Records whether a probe was unreachable, true when the window is full
+    /// and the share of unreachable ones reached the threshold.
+    pub fn record(&mut self, unreachable: bool) -> bool {
+        if self.samples.len() == self.size && self.samples.pop_front() == Some(true) {
+            self.unreachable -= 1;
+        }
+        self.samples.push_back(unreachable);
+        self.unreachable += usize::from(unreachable);
+        self.samples.len() == self.size && self.ratio() >= self.threshold
+    }
+
+    pub fn ratio(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.unreachable as f64 / self.samples.len() as f64
+    }
+
+    /// Forgets every probe, once the network recovered.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.unreachable = 0;
+    }
+}
+
+/// What a probe changed about the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Unchanged,
+    WentDown,
+    /// The errors spiked without a reference to check connectivity against,
+    /// the scan goes on. Only told once.
+    NoReference,
+}
+
+/// A time the network went down during the scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkOutage {
+    /// When the network was found to be down, in RFC 3339 format.
+    pub went_down_at: String,
+    /// Whether the network came back before the end of the scan.
+    pub came_back: bool,
+    /// How many probes which failed during the outage were tried again.
+    pub requeued_probes: usize,
+}
+
+/// Watches the errors of every probe of a scan, holding the scan back while
+/// the network is down.
+#[derive(Debug)]
+pub(crate) struct Network {
+    connectivity: Connectivity,
+    window: ErrorWindow,
+    reference: Option<SocketAddr>,
+    down: bool,
+    warned: bool,
+    probes: u64,
+    /// The unreachable probes of the window, with their number.
+    suspects: VecDeque<(u64, SocketAddr)>,
+    /// The sockets to try again once the network is back.
+    requeued: VecDeque<SocketAddr>,
+    retried: HashSet<SocketAddr>,
+    outages: Vec<NetworkOutage>,
+}
+
+impl Network {
+    pub fn new(connectivity: Connectivity) -> Self {
+        Self {
+            connectivity,
+            window: ErrorWindow::new(connectivity.window, connectivity.threshold),
+            reference: connectivity.reference,
+            down: false,
+            warned: false,
+            probes: 0,
+            suspects: VecDeque::new(),
+            requeued: VecDeque::new(),
+            retried: HashSet::new(),
+            outages: Vec::new(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.connectivity.interval
+    }
+
+    /// The socket connectivity is checked against.
+    pub fn reference(&self) -> Option<SocketAddr> {
+        self.reference
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.down
+    }
+
+    /// Records the result of the probe of `socket`. While the network is
+    /// down, unreachable probes are queued to be tried again.
+    pub fn probed(&mut self, socket: SocketAddr, result: &io::Result<SocketAddr>) -> Health {
+        let unreachable = result.as_ref().err().is_some_and(is_unreachable);
+        if result.is_ok() {
+            self.reference.get_or_insert(socket);
+        }
+        if self.down {
+            if unreachable {
+                self.requeue(socket);
+            }
+            return Health::Unchanged;
+        }
+
+        self.probes += 1;
+        let oldest = self.probes.saturating_sub(self.window.size as u64);
+        while self
+            .suspects
+            .front()
+            .is_some_and(|(probe, _)| *probe <= oldest)
+        {
+            self.suspects.pop_front();
+        }
+        if unreachable {
+            self.suspects.push_back((self.probes, socket));
+        }
+        if !self.window.record(unreachable) {
+            return Health::Unchanged;
+        }
+
+        if self.reference.is_none() {
+            self.window.clear();
+            self.suspects.clear();
+            if self.warned {
+                return Health::Unchanged;
+            }
+            self.warned = true;
+            return Health::NoReference;
+        }
+        self.down = true;
+        self.outages.push(NetworkOutage {
+            went_down_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            came_back: false,
+            requeued_probes: 0,
+        });
+        // The probes which gave the outage away failed because of it too.
+        while let Some((_, socket)) = self.suspects.pop_front() {
+            self.requeue(socket);
+        }
+        Health::WentDown
+    }
+
+    /// Records whether the reference answered a check, true when the
+    /// network just came back.
+    pub fn checked(&mut self, answered: bool) -> bool {
+        if !self.down || !answered {
+            return false;
+        }
+        self.down = false;
+        self.window.clear();
+        if let Some(outage) = self.outages.last_mut() {
+            outage.came_back = true;
+        }
+        true
+    }
+
+    /// The next socket to try again, once the network is back.
+    pub fn next_requeued(&mut self) -> Option<SocketAddr> {
+        if self.down {
+            return None;
+        }
+        self.requeued.pop_front()
+    }
+
+    /// Whether `socket` is being tried again after an outage.
+    pub fn is_retry(&self, socket: SocketAddr) -> bool {
+        self.retried.contains(&socket)
+    }
+
+    /// When the last outage started.
+    pub fn down_since(&self) -> Option<&str> {
+        self.outages
+            .last()
+            .map(|outage| outage.went_down_at.as_str())
+    }
+
+    pub fn into_outages(self) -> Vec<NetworkOutage> {
+        self.outages
+    }
+
+    /// Queues `socket` to be tried again, once for the whole scan so that a
+    /// host which can't be reached anyway doesn't keep coming back.
+    fn requeue(&mut self, socket: SocketAddr) {
+        if !self.retried.insert(socket) {
+            return;
+        }
+        self.requeued.push_back(socket);
+        if let Some(outage) = self.outages.last_mut() {
+            outage.requeued_probes += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Connectivity, ErrorWindow, Health, Network};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    const CONNECTIVITY: Connectivity = Connectivity {
+        window: 4,
+        threshold: 0.75,
+        interval: Duration::from_secs(5),
+        reference: None,
+    };
+
+    fn result(outcome: char, socket: SocketAddr) -> io::Result<SocketAddr> {
+        match outcome {
+            'o' => Ok(socket),
+            'u' => Err(io::ErrorKind::NetworkUnreachable.into()),
+            'h' => Err(io::ErrorKind::HostUnreachable.into()),
+            _ => Err(io::ErrorKind::ConnectionRefused.into()),
+        }
+    }
+
+    // Probes port 1, 2, ... of 10.0.0.1 with the outcomes of `pattern`, and
+    // returns the ports at which the health changed.
+    fn feed(network: &mut Network, first: u16, pattern: &str) -> Vec<(u16, Health)> {
+        pattern
+            .chars()
+            .zip(first..)
+            .filter_map(|(outcome, port)| {
+                let socket = SocketAddr::from(([10, 0, 0, 1], port));
+                let health = network.probed(socket, &result(outcome, socket));
+                Some((port, health)).filter(|(_, health)| *health != Health::Unchanged)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window_spikes_over_the_threshold() {
+        let mut window = ErrorWindow::new(4, 0.75);
+        let spikes: Vec<usize> = "uuu.uu.uuu...uuuu"
+            .chars()
+            .enumerate()
+            .filter(|(_, outcome)| window.record(*outcome == 'u'))
+            .map(|(position, _)| position)
+            .collect();
+        // Not before the window is full, and only while 3 of the last 4 are.
+        assert_eq!(spikes, [3, 4, 5, 7, 8, 9, 10, 15, 16]);
+        assert!((window.ratio() - 1.0).abs() < f64::EPSILON);
+
+        window.clear();
+        assert!(!window.record(true));
+    }
+
+    #[test]
+    fn outage_requeues_the_failed_probes() {
+        let mut network = Network::new(CONNECTIVITY);
+        // The first open socket becomes the reference.
+        assert_eq!(feed(&mut network, 1, "c.o.uhcu"), [(8, Health::WentDown)]);
+        assert_eq!(
+            network.reference(),
+            Some(SocketAddr::from(([10, 0, 0, 1], 3)))
+        );
+        assert!(network.is_down());
+        assert!(network.down_since().is_some());
+
+        // Probes still in flight fail too, refused ones tell nothing.
+        assert!(feed(&mut network, 9, "uc").is_empty());
+        assert_eq!(network.next_requeued(), None);
+        assert!(!network.checked(false));
+        assert!(network.checked(true));
+
+        let requeued: Vec<u16> = std::iter::from_fn(|| network.next_requeued())
+            .map(|socket| socket.port())
+            .collect();
+        assert_eq!(requeued, [5, 6, 8, 9]);
+        assert!(network.is_retry(SocketAddr::from(([10, 0, 0, 1], 6))));
+        assert!(!network.is_retry(SocketAddr::from(([10, 0, 0, 1], 7))));
+
+        let outages = network.into_outages();
+        assert_eq!(outages.len(), 1);
+        assert!(outages[0].came_back);
+        assert_eq!(outages[0].requeued_probes, 4);
+    }
+
+    #[test]
+    fn recovered_network_starts_a_new_window() {
+        let mut network = Network::new(CONNECTIVITY);
+        feed(&mut network, 1, "ouuuu");
+        assert!(network.checked(true));
+        // The errors from before the recovery aren't counted again.
+        assert!(feed(&mut network, 6, "u.u").is_empty());
+        assert_eq!(feed(&mut network, 9, "u"), [(9, Health::WentDown)]);
+
+        // A socket is only tried again once for the whole scan.
+        network.checked(true);
+        while network.next_requeued().is_some() {}
+        assert_eq!(feed(&mut network, 2, "uuuu"), [(5, Health::WentDown)]);
+        assert_eq!(network.next_requeued(), None);
+
+        let requeued: Vec<usize> = network
+            .into_outages()
+            .iter()
+            .map(|outage| outage.requeued_probes)
+            .collect();
+        assert_eq!(requeued, [4, 3, 0]);
+    }
+
+    #[test]
+    fn spike_without_a_reference_is_told_once() {
+        let mut network = Network::new(CONNECTIVITY);
+        assert_eq!(
+            feed(&mut network, 1, "uuuuuuuu"),
+            [(4, Health::NoReference)]
+        );
+        assert!(!network.is_down());
+        assert_eq!(network.next_requeued(), None);
+
+        let mut network = Network::new(Connectivity {
+            reference: Some(SocketAddr::from(([192, 0, 2, 1], 443))),
+            ..CONNECTIVITY
+        });
+        assert_eq!(feed(&mut network, 1, "uuuu"), [(4, Health::WentDown)]);
+    }
+}
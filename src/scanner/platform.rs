@@ -0,0 +1,155 @@
+//! The scan defaults which depend on the operating system.
+//!
+//! macOS keeps small listen queues and file limits by default. With a big
+//! batch size its connects fail in bursts with `EPIPE`, `ECONNRESET` or
+//! `ENOBUFS`, errors of this machine rather than answers of the port, which
+//! turned into phantom closed ports. There the default batch size is lower,
+//! and the probes which failed with one of these errors are tried again
+//! after a short pause, without using up their tries.
+use std::io;
+use std::time::Duration;
+
+/// The operating systems with defaults of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    Linux,
+    MacOs,
+    Windows,
+    /// The BSDs and the rest.
+    Other,
+}
+
+impl Os {
+    pub fn current() -> Self {
+        if cfg!(target_os = "linux") {
+            Os::Linux
+        } else if cfg!(target_os = "macos") {
+            Os::MacOs
+        } else if cfg!(windows) {
+            Os::Windows
+        } else {
+            Os::Other
+        }
+    }
+}
+
+/// The defaults of an operating system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformDefaults {
+    /// The batch size when none is given.
+    pub batch_size: u16,
+    /// The raw OS errors a connect fails with in bursts under load.
+    pub burst_errors: &'static [i32],
+    /// How many more times a probe which failed with a burst error is tried.
+    pub burst_retries: u8,
+    /// The pause before a probe which failed with a burst error is tried
+    /// again, doubled at every retry.
+    pub burst_delay: Duration,
+}
+
+/// `EPIPE`, `ECONNRESET` and `ENOBUFS` on macOS.
+const MACOS_BURST_ERRORS: [i32; 3] = [32, 54, 55];
+
+const DEFAULTS: [(Os, PlatformDefaults); 4] = [
+    (
+        Os::Linux,
+        PlatformDefaults {
+            batch_size: 4_500,
+            burst_errors: &[],
+            burst_retries: 0,
+            burst_delay: Duration::ZERO,
+        },
+    ),
+    (
+        Os::MacOs,
+        PlatformDefaults {
+            batch_size: 2_048,
+            burst_errors: &MACOS_BURST_ERRORS,
+            burst_retries: 2,
+            burst_delay: Duration::from_millis(100),
+        },
+    ),
+    (
+        Os::Windows,
+        PlatformDefaults {
+            batch_size: 4_500,
+            burst_errors: &[],
+            burst_retries: 0,
+            burst_delay: Duration::ZERO,
+        },
+    ),
+    (
+        Os::Other,
+        PlatformDefaults {
+            batch_size: 4_500,
+            burst_errors: &[],
+            burst_retries: 0,
+            burst_delay: Duration::ZERO,
+        },
+    ),
+];
+
+impl PlatformDefaults {
+    pub fn of(os: Os) -> Self {
+        DEFAULTS
+            .iter()
+            .find(|(defaults_os, _)| *defaults_os == os)
+            .map(|(_, defaults)| *defaults)
+            .expect("Every OS has defaults.")
+    }
+
+    /// The defaults of the operating system RustScan was built for.
+    pub fn current() -> Self {
+        Self::of(Os::current())
+    }
+
+    /// Whether `error` is one a connect fails with in bursts under load,
+    /// rather than an answer of the port.
+    pub fn is_burst_error(&self, error: &io::Error) -> bool {
+        error
+            .raw_os_error()
+            .is_some_and(|code| self.burst_errors.contains(&code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Os, PlatformDefaults};
+    use std::io;
+
+    #[test]
+    fn macos_bursts_are_told_apart() {
+        let macos = PlatformDefaults::of(Os::MacOs);
+        for code in [32, 54, 55] {
+            assert!(macos.is_burst_error(&io::Error::from_raw_os_error(code)));
+        }
+        // ECONNREFUSED and ETIMEDOUT are answers, or their absence.
+        for code in [61, 60] {
+            assert!(!macos.is_burst_error(&io::Error::from_raw_os_error(code)));
+        }
+        assert!(!macos.is_burst_error(&io::ErrorKind::BrokenPipe.into()));
+        assert!(macos.burst_retries > 0);
+        assert!(macos.batch_size < PlatformDefaults::of(Os::Linux).batch_size);
+
+        // 54 is EXFULL on Linux, which keeps errors as they are.
+        let linux = PlatformDefaults::of(Os::Linux);
+        assert!(!linux.is_burst_error(&io::Error::from_raw_os_error(54)));
+        assert_eq!(linux.burst_retries, 0);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn macos_build_has_tuned_defaults() {
+        assert_eq!(Os::current(), Os::MacOs);
+        assert_eq!(PlatformDefaults::current().batch_size, 2_048);
+        assert_eq!(PlatformDefaults::current().burst_retries, 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linux_build_keeps_the_defaults() {
+        assert_eq!(Os::current(), Os::Linux);
+        assert_eq!(PlatformDefaults::current().batch_size, 4_500);
+        assert!(PlatformDefaults::current().burst_errors.is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+//! The ports of a host probed before its others, see
+//! [`super::Scanner::with_priorities`].
+//!
+//! A re-scan finds the ports which were open last time first, when they
+//! still are: they move to the front of the order of their host, the other
+//! ports keeping the order the port strategy picked. Canaries stay in front
+//! of them all, and ports the host doesn't scan aren't added.
+use std::collections::HashSet;
+
+/// `ports` with the ones of `first` moved behind the `leading` canaries,
+/// each part in the order it had.
+pub fn prioritize(ports: &[u16], first: &HashSet<u16>, leading: usize) -> Vec<u16> {
+    let (canaries, rest) = ports.split_at(leading.min(ports.len()));
+    let (prioritized, others): (Vec<u16>, Vec<u16>) =
+        rest.iter().partition(|port| first.contains(port));
+    [canaries, &prioritized, &others].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prioritize;
+    use std::collections::HashSet;
+
+    #[test]
+    fn prioritized_ports_go_first_after_the_canaries() {
+        let first: HashSet<u16> = [443, 22, 8080].iter().copied().collect();
+        // 8080 isn't scanned on the host, it isn't added.
+        assert_eq!(
+            prioritize(&[3389, 80, 22, 21, 443], &first, 1),
+            [3389, 22, 443, 80, 21]
+        );
+        assert_eq!(prioritize(&[80, 22], &HashSet::new(), 0), [80, 22]);
+        // A prioritized canary stays where the canaries are.
+        assert_eq!(prioritize(&[22, 80, 443], &first, 1), [22, 443, 80]);
+    }
+}
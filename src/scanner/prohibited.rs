@@ -0,0 +1,268 @@
+//! Hosts whose firewall rejects the scan as administratively prohibited.
+//!
+//! A firewall rejecting a probe with ICMP destination unreachable, code 13
+//! (communication administratively prohibited), or codes 9 and 10 for the
+//! network and the host, says it won't let anything through: probing the
+//! rest of the ports of the host is only more of the same. The ICMP error
+//! itself isn't seen by a connect scan, the system turns it into the error
+//! of the connection, `EHOSTUNREACH`, or `EACCES` and `EPERM` when a local
+//! firewall rejects it. An unreachable host gives the same errors, so a host
+//! is only taken for prohibited once that many probes in a row ended with
+//! them, see [`AdminProhibited`]. An answer, connected or refused, starts
+//! the streak over, a timeout doesn't tell anything either way.
+//!
+//! The ports of a prohibited host which are left aren't probed, they are
+//! settled as skipped, and the host is reported with an [`AdminProhibition`].
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+
+/// When a host is taken for administratively prohibited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdminProhibited {
+    /// How many probes in a row have to end with a prohibited error.
+    pub after: u32,
+}
+
+impl Default for AdminProhibited {
+    fn default() -> Self {
+        Self { after: 3 }
+    }
+}
+
+/// What a probe tells of the firewall of its host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Connected or refused, something answered for the port.
+    Answered,
+    Prohibited(io::ErrorKind),
+    /// Timed out or failed otherwise.
+    Nothing,
+}
+
+impl Signal {
+    pub fn of<T>(result: &io::Result<T>) -> Self {
+        match result {
+            Ok(_) => Signal::Answered,
+            Err(e) => match e.kind() {
+                io::ErrorKind::ConnectionRefused => Signal::Answered,
+                kind @ (io::ErrorKind::HostUnreachable | io::ErrorKind::PermissionDenied) => {
+                    Signal::Prohibited(kind)
+                }
+                _ => Signal::Nothing,
+            },
+        }
+    }
+}
+
+/// The firewall of a single host, fed with the outcome of its probes.
+#[derive(Debug, Clone)]
+pub struct HostFirewall {
+    config: AdminProhibited,
+    /// The prohibited errors since the last answer.
+    streak: u32,
+    /// The error which made the host prohibited.
+    prohibited: Option<io::ErrorKind>,
+    /// The ports left out since.
+    skipped: usize,
+}
+
+impl HostFirewall {
+    pub fn new(config: AdminProhibited) -> Self {
+        Self {
+            config,
+            streak: 0,
+            prohibited: None,
+            skipped: 0,
+        }
+    }
+
+    /// Records what a probe of the host ended with, true when it's the one
+    /// making the host prohibited.
+    pub fn record(&mut self, signal: Signal) -> bool {
+        if self.prohibited.is_some() {
+            return false;
+        }
+        match signal {
+            Signal::Answered => self.streak = 0,
+            Signal::Nothing => {}
+            Signal::Prohibited(kind) => {
+                self.streak += 1;
+                if self.streak >= self.config.after {
+                    self.prohibited = Some(kind);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn is_prohibited(&self) -> bool {
+        self.prohibited.is_some()
+    }
+}
+
+/// Why the ports left of a host weren't probed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdminProhibition {
+    #[serde(skip)]
+    pub ip: IpAddr,
+    /// The error the probes ended with, like `host unreachable`.
+    pub error: String,
+    /// How many probes in a row ended with it.
+    pub after_probes: u32,
+    /// How many ports were left out, as admin-filtered.
+    pub skipped_ports: usize,
+}
+
+/// The firewalls of every host of a scan.
+#[derive(Debug)]
+pub(crate) struct Firewalls {
+    config: AdminProhibited,
+    hosts: HashMap<IpAddr, HostFirewall>,
+}
+
+impl Firewalls {
+    pub fn new(config: AdminProhibited) -> Self {
+        Self {
+            config,
+            hosts: HashMap::new(),
+        }
+    }
+
+    pub fn record<T>(&mut self, ip: IpAddr, result: &io::Result<T>) -> bool {
+        let config = self.config;
+        self.hosts
+            .entry(ip)
+            .or_insert_with(|| HostFirewall::new(config))
+            .record(Signal::of(result))
+    }
+
+    pub fn is_prohibited(&self, ip: IpAddr) -> bool {
+        self.hosts.get(&ip).is_some_and(HostFirewall::is_prohibited)
+    }
+
+    /// Counts a port of a prohibited host left out.
+    pub fn skipped(&mut self, ip: IpAddr) {
+        if let Some(host) = self.hosts.get_mut(&ip) {
+            host.skipped += 1;
+        }
+    }
+
+    /// The hosts which were prohibited.
+    pub fn into_prohibitions(self) -> Vec<AdminProhibition> {
+        let after_probes = self.config.after;
+        self.hosts
+            .into_iter()
+            .filter_map(|(ip, host)| {
+                host.prohibited.map(|kind| AdminProhibition {
+                    ip,
+                    error: io::Error::from(kind).to_string(),
+                    after_probes,
+                    skipped_ports: host.skipped,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdminProhibited, Firewalls, HostFirewall, Signal};
+    use std::io::{self, ErrorKind};
+
+    const CONFIG: AdminProhibited = AdminProhibited { after: 3 };
+
+    fn error(kind: ErrorKind) -> io::Result<()> {
+        Err(io::Error::from(kind))
+    }
+
+    #[test]
+    fn signals_of_results() {
+        assert_eq!(Signal::of(&Ok(())), Signal::Answered);
+        assert_eq!(
+            Signal::of(&error(ErrorKind::ConnectionRefused)),
+            Signal::Answered
+        );
+        for kind in [ErrorKind::HostUnreachable, ErrorKind::PermissionDenied] {
+            assert_eq!(Signal::of(&error(kind)), Signal::Prohibited(kind));
+        }
+        for kind in [ErrorKind::TimedOut, ErrorKind::NetworkUnreachable] {
+            assert_eq!(Signal::of(&error(kind)), Signal::Nothing);
+        }
+    }
+
+    #[test]
+    fn prohibited_streak_decides() {
+        let prohibited = Signal::Prohibited(ErrorKind::HostUnreachable);
+        let mut host = HostFirewall::new(CONFIG);
+        assert!(!host.record(prohibited));
+        // A timeout in between doesn't break the streak.
+        assert!(!host.record(Signal::Nothing));
+        assert!(!host.record(prohibited));
+        assert!(!host.is_prohibited());
+
+        assert!(host.record(prohibited));
+        assert!(host.is_prohibited());
+        // Decided once, nothing changes it.
+        assert!(!host.record(prohibited));
+        assert!(!host.record(Signal::Answered));
+        assert!(host.is_prohibited());
+    }
+
+    #[test]
+    fn answers_start_the_streak_over() {
+        let prohibited = Signal::Prohibited(ErrorKind::PermissionDenied);
+        let mut host = HostFirewall::new(CONFIG);
+        for signal in [
+            prohibited,
+            prohibited,
+            Signal::Answered,
+            prohibited,
+            prohibited,
+            Signal::Answered,
+            prohibited,
+        ] {
+            assert!(!host.record(signal));
+        }
+        assert!(!host.is_prohibited());
+        assert!(!host.record(prohibited));
+        assert!(host.record(prohibited));
+    }
+
+    #[test]
+    fn silent_hosts_are_never_prohibited() {
+        let mut host = HostFirewall::new(AdminProhibited { after: 1 });
+        for _ in 0..100 {
+            assert!(!host.record(Signal::Nothing));
+        }
+        assert!(!host.is_prohibited());
+    }
+
+    #[test]
+    fn hosts_are_tracked_apart() {
+        let mut firewalls = Firewalls::new(CONFIG);
+        let (rejecting, open) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let decided: Vec<bool> = (0..3)
+            .map(|_| {
+                firewalls.record(open, &error(ErrorKind::HostUnreachable));
+                firewalls.record(open, &Ok(()));
+                firewalls.record(rejecting, &error(ErrorKind::HostUnreachable))
+            })
+            .collect();
+        assert_eq!(decided, [false, false, true]);
+        assert!(firewalls.is_prohibited(rejecting));
+        assert!(!firewalls.is_prohibited(open));
+
+        firewalls.skipped(rejecting);
+        firewalls.skipped(rejecting);
+        firewalls.skipped(open);
+        let prohibitions = firewalls.into_prohibitions();
+        assert_eq!(prohibitions.len(), 1);
+        assert_eq!(prohibitions[0].ip, rejecting);
+        assert_eq!(prohibitions[0].after_probes, 3);
+        assert_eq!(prohibitions[0].skipped_ports, 2);
+        assert_eq!(prohibitions[0].error, "host unreachable");
+    }
+}
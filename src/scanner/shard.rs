@@ -0,0 +1,163 @@
+//! Splits the host×port space of a scan between independent runs, see
+//! `--shard`.
+//!
+//! Every socket belongs to exactly one of the `n` shards, picked by a hash of
+//! its address, port and the seed of the run. The hash is spelled out here
+//! instead of using the standard library's, whose output may change between
+//! Rust versions: shards of different RustScan versions have to agree.
+use crate::hash::fnv1a;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The `index`th of `count` shards, counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Whether the socket `ip:port` is scanned by this shard.
+    pub fn contains(&self, ip: IpAddr, port: u16, seed: u64) -> bool {
+        socket_hash(ip, port, seed) % u64::from(self.count) == u64::from(self.index - 1)
+    }
+
+    /// The ports of `ports` scanned on `ip` by this shard.
+    pub fn ports(&self, ip: IpAddr, ports: &[u16], seed: u64) -> Vec<u16> {
+        ports
+            .iter()
+            .copied()
+            .filter(|port| self.contains(ip, *port, seed))
+            .collect()
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    /// Parses `i/n`, like `2/4` for the second of four shards.
+    fn from_str(shard: &str) -> Result<Self, String> {
+        let (index, count) = shard
+            .split_once('/')
+            .ok_or_else(|| format!("Expected i/n, like 1/4, got {shard:?}."))?;
+        let index: u32 = index
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid shard index {index:?}."))?;
+        let count: u32 = count
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid shard count {count:?}."))?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!(
+                "The shard index has to be between 1 and {count}, got {index}."
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Serialize for Shard {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// FNV-1a over the seed, the address octets and the port, finished with the
+/// SplitMix64 mixer so that the low bits are spread too. Never change it.
+fn socket_hash(ip: IpAddr, port: u16, seed: u64) -> u64 {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let bytes = seed
+        .to_le_bytes()
+        .iter()
+        .chain(&octets)
+        .chain(&port.to_be_bytes())
+        .copied()
+        .collect::<Vec<u8>>();
+    let mut hash = fnv1a(bytes);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{socket_hash, Shard};
+    use std::net::IpAddr;
+
+    fn ips() -> Vec<IpAddr> {
+        ["10.0.0.1", "10.0.0.2", "192.168.7.40", "2001:db8::5"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn shards_cover_the_space_exactly_once() {
+        let ports: Vec<u16> = (1..=2_000).collect();
+        for count in [1, 2, 3, 7] {
+            for ip in ips() {
+                let mut covered = vec![0; ports.len()];
+                for index in 1..=count {
+                    let shard = Shard { index, count };
+                    for port in shard.ports(ip, &ports, 42) {
+                        covered[usize::from(port) - 1] += 1;
+                    }
+                }
+                assert!(
+                    covered.iter().all(|&shards| shards == 1),
+                    "{} {}",
+                    ip,
+                    count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shards_are_balanced() {
+        let ports: Vec<u16> = (1..=4_000).collect();
+        let ip = "10.0.0.1".parse().unwrap();
+        for index in 1..=4 {
+            let share = Shard { index, count: 4 }.ports(ip, &ports, 0).len();
+            assert!((900..=1_100).contains(&share), "{}: {}", index, share);
+        }
+    }
+
+    #[test]
+    fn partition_is_stable() {
+        // Pinned values, a change here breaks shards across versions.
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(socket_hash(ip, 80, 0), 0xd678_f5f0_4a2e_d000);
+        assert_eq!(
+            socket_hash("2001:db8::5".parse().unwrap(), 443, 7),
+            0x6b6e_b412_240a_80d6
+        );
+        let ports: Vec<u16> = (1..=16).collect();
+        assert_ne!(
+            Shard { index: 1, count: 2 }.ports(ip, &ports, 0),
+            Shard { index: 1, count: 2 }.ports(ip, &ports, 1)
+        );
+    }
+
+    #[test]
+    fn shards_are_parsed() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert_eq!(Shard { index: 2, count: 4 }.to_string(), "2/4");
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("1/0".parse::<Shard>().is_err());
+        assert!("1-4".parse::<Shard>().is_err());
+    }
+}
@@ -1,68 +1,247 @@
-use itertools::{iproduct, Product};
+use crate::input::Fairness;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 use std::net::{IpAddr, SocketAddr};
 
+/// The ports of a single host which are still to be handed out.
+struct HostQueue<'s> {
+    ip: IpAddr,
+    ports: std::slice::Iter<'s, u16>,
+    emitted: usize,
+    total: usize,
+}
+
+/// How far a host is through its ports, ordered by completion ratio and then
+/// by input order so that ties go to the host given first.
+#[derive(PartialEq, Eq)]
+struct Progress {
+    emitted: usize,
+    total: usize,
+    index: usize,
+}
+
+impl Ord for Progress {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // emitted / total compared without floats.
+        let ratio = (self.emitted as u128 * other.total as u128)
+            .cmp(&(other.emitted as u128 * self.total as u128));
+        ratio.then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for Progress {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Which host gets to hand out the next socket.
+enum Schedule {
+    /// Hosts with ports left, in turn order.
+    RoundRobin(VecDeque<usize>),
+    /// Hosts with ports left, least complete first.
+    Proportional(BinaryHeap<Reverse<Progress>>),
+    /// The host currently being scanned.
+    InputOrder(usize),
+}
+
 pub struct SocketIterator<'s> {
-    // product_it is a cartesian product iterator over
-    // the slices of ports and IP addresses.
-    //
-    // The IP/port order is intentionally reversed here since we want
-    // the itertools::iproduct! macro below to generate the pairs with
-    // all the IPs for one port before moving on to the next one
-    // ("hold the port, go through all the IPs, then advance the port...").
-    // See also the comments in the iterator implementation for an example.
-    product_it:
-        Product<Box<std::slice::Iter<'s, u16>>, Box<std::slice::Iter<'s, std::net::IpAddr>>>,
+    hosts: Vec<HostQueue<'s>>,
+    schedule: Schedule,
+    /// The pass which starts once this one is over.
+    then: Option<Box<SocketIterator<'s>>>,
+    /// The hosts handed out once released, whatever the pass.
+    held: Vec<(IpAddr, &'s [u16])>,
 }
 
-/// An iterator that receives a slice of IPs and ports and returns a Socket
-/// for each IP and port pair until all of these combinations are exhausted.
+/// An iterator that receives the ports to scan of every host and returns a
+/// Socket for each IP and port pair until all of these combinations are
+/// exhausted, interleaving the hosts according to the [`Fairness`].
 /// The goal of this iterator is to go over every IP and port combination
 /// without generating a big memory footprint. The alternative would be
 /// generating a vector containing all these combinations.
 impl<'s> SocketIterator<'s> {
-    pub fn new(ips: &'s [IpAddr], ports: &'s [u16]) -> Self {
-        let ports_it = Box::new(ports.iter());
-        let ips_it = Box::new(ips.iter());
+    pub fn new(hosts: Vec<(IpAddr, &'s [u16])>, fairness: Fairness) -> Self {
+        let hosts: Vec<HostQueue<'s>> = hosts
+            .into_iter()
+            .map(|(ip, ports)| HostQueue {
+                ip,
+                ports: ports.iter(),
+                emitted: 0,
+                total: ports.len(),
+            })
+            .collect();
+        let pending = (0..hosts.len()).filter(|&index| hosts[index].total > 0);
+
+        let schedule = match fairness {
+            Fairness::RoundRobin => Schedule::RoundRobin(pending.collect()),
+            Fairness::Proportional => Schedule::Proportional(
+                pending
+                    .map(|index| {
+                        Reverse(Progress {
+                            emitted: 0,
+                            total: hosts[index].total,
+                            index,
+                        })
+                    })
+                    .collect(),
+            ),
+            Fairness::InputOrder => Schedule::InputOrder(0),
+        };
+
         Self {
-            product_it: iproduct!(ports_it, ips_it),
+            hosts,
+            schedule,
+            then: None,
+            held: Vec::new(),
         }
     }
+
+    /// Hands out every socket of `first` before any of `then`, both passes
+    /// interleaving the hosts according to the [`Fairness`].
+    pub fn in_passes(
+        first: Vec<(IpAddr, &'s [u16])>,
+        then: Vec<(IpAddr, &'s [u16])>,
+        fairness: Fairness,
+    ) -> Self {
+        let mut first = Self::new(first, fairness);
+        first.then = Some(Box::new(Self::new(then, fairness)));
+        first
+    }
+
+    /// Keeps `hosts` back until they are released.
+    pub fn hold(&mut self, hosts: Vec<(IpAddr, &'s [u16])>) {
+        self.held.extend(hosts);
+    }
+
+    /// Hands out the ports of the held host `ip` too, among the hosts of the
+    /// pass going on, as if it was given last.
+    pub fn release(&mut self, ip: IpAddr) {
+        if let Some(position) = self.held.iter().position(|(held, _)| *held == ip) {
+            let (ip, ports) = self.held.remove(position);
+            self.add_host(ip, ports);
+        }
+    }
+
+    fn add_host(&mut self, ip: IpAddr, ports: &'s [u16]) {
+        let index = self.hosts.len();
+        self.hosts.push(HostQueue {
+            ip,
+            ports: ports.iter(),
+            emitted: 0,
+            total: ports.len(),
+        });
+        if ports.is_empty() {
+            return;
+        }
+        match &mut self.schedule {
+            Schedule::RoundRobin(turns) => turns.push_back(index),
+            Schedule::Proportional(progress) => progress.push(Reverse(Progress {
+                emitted: 0,
+                total: ports.len(),
+                index,
+            })),
+            // Reached once the hosts before it are done.
+            Schedule::InputOrder(_) => {}
+        }
+    }
+
+    /// Takes the next port of the host at `index`.
+    fn emit(&mut self, index: usize) -> Option<SocketAddr> {
+        let host = &mut self.hosts[index];
+        let port = host.ports.next()?;
+        host.emitted += 1;
+        Some(SocketAddr::new(host.ip, *port))
+    }
+
+    fn has_ports_left(&self, index: usize) -> bool {
+        self.hosts[index].emitted < self.hosts[index].total
+    }
 }
 
 #[allow(clippy::doc_link_with_quotes)]
 impl<'s> Iterator for SocketIterator<'s> {
     type Item = SocketAddr;
 
-    /// Returns a socket based on the combination of one of the provided
-    /// IPs and ports or None when these combinations are exhausted. Every
-    /// IP will have the same port until a port is incremented.
+    /// Returns the next socket to scan or None when every host is done.
+    /// With round-robin fairness every host hands out one port in turn,
+    /// hosts out of ports drop out of the rotation.
     ///
-    /// let it = SocketIterator::new(&["127.0.0.1", "192.168.0.1"], &[80, 443]);
+    /// let it = SocketIterator::new(vec![(127.0.0.1, &[80, 443]), (192.168.0.1, &[80])], RoundRobin);
     /// it.next(); // 127.0.0.1:80
     /// it.next(); // 192.168.0.1:80
     /// it.next(); // 127.0.0.1:443
-    /// it.next(); // 192.168.0.1:443
     /// it.next(); // None
     fn next(&mut self) -> Option<Self::Item> {
-        self.product_it
-            .next()
-            .map(|(port, ip)| SocketAddr::new(*ip, *port))
+        match self.next_in_pass() {
+            Some(socket) => Some(socket),
+            None => {
+                let then = self.then.take()?;
+                let held = std::mem::take(&mut self.held);
+                *self = *then;
+                self.held = held;
+                self.next()
+            }
+        }
+    }
+}
+
+impl SocketIterator<'_> {
+    fn next_in_pass(&mut self) -> Option<SocketAddr> {
+        match &mut self.schedule {
+            Schedule::RoundRobin(turns) => {
+                let index = turns.pop_front()?;
+                let socket = self.emit(index);
+                if self.has_ports_left(index) {
+                    if let Schedule::RoundRobin(turns) = &mut self.schedule {
+                        turns.push_back(index);
+                    }
+                }
+                socket
+            }
+            Schedule::Proportional(progress) => {
+                let Reverse(mut least) = progress.pop()?;
+                let socket = self.emit(least.index);
+                least.emitted += 1;
+                if self.has_ports_left(least.index) {
+                    if let Schedule::Proportional(progress) = &mut self.schedule {
+                        progress.push(Reverse(least));
+                    }
+                }
+                socket
+            }
+            Schedule::InputOrder(current) => {
+                let mut index = *current;
+                while index < self.hosts.len() && !self.has_ports_left(index) {
+                    index += 1;
+                }
+                self.schedule = Schedule::InputOrder(index);
+                if index < self.hosts.len() {
+                    self.emit(index)
+                } else {
+                    None
+                }
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SocketIterator;
+    use crate::input::Fairness;
+    use std::collections::HashMap;
     use std::net::{IpAddr, SocketAddr};
 
     #[test]
     fn goes_through_every_ip_port_combination() {
-        let addrs = vec![
+        let addrs = [
             "127.0.0.1".parse::<IpAddr>().unwrap(),
             "192.168.0.1".parse::<IpAddr>().unwrap(),
         ];
         let ports: Vec<u16> = vec![22, 80, 443];
-        let mut it = SocketIterator::new(&addrs, &ports);
+        let hosts = addrs.iter().map(|ip| (*ip, &ports[..])).collect();
+        let mut it = SocketIterator::new(hosts, Fairness::RoundRobin);
 
         assert_eq!(Some(SocketAddr::new(addrs[0], ports[0])), it.next());
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[0])), it.next());
@@ -72,4 +251,186 @@ mod tests {
         assert_eq!(Some(SocketAddr::new(addrs[1], ports[2])), it.next());
         assert_eq!(None, it.next());
     }
+
+    #[test]
+    fn released_hosts_take_turns_too() {
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let added: IpAddr = "2001:db8::1".parse().unwrap();
+        let ports: Vec<u16> = vec![22, 80, 443];
+        for fairness in [
+            Fairness::RoundRobin,
+            Fairness::Proportional,
+            Fairness::InputOrder,
+        ] {
+            let mut it = SocketIterator::new(vec![(first, &ports[..])], fairness);
+            it.hold(vec![(added, &ports[..]), (first, &ports[..1])]);
+            assert_eq!(it.next(), Some(SocketAddr::new(first, 22)));
+            it.release(added);
+            let mut rest: Vec<SocketAddr> = it.by_ref().collect();
+            assert_eq!(rest.len(), 5, "{fairness:?}");
+            rest.sort();
+            assert_eq!(
+                rest[2..],
+                [22, 80, 443].map(|port| SocketAddr::new(added, port))
+            );
+
+            // Even once every other host is done.
+            it.release(first);
+            assert_eq!(it.next(), Some(SocketAddr::new(first, 22)));
+            assert_eq!(it.next(), None);
+        }
+    }
+
+    #[test]
+    fn empty_inputs_hand_out_nothing() {
+        let fairnesses = [
+            Fairness::RoundRobin,
+            Fairness::Proportional,
+            Fairness::InputOrder,
+        ];
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for fairness in fairnesses.iter().copied() {
+            assert_eq!(SocketIterator::new(vec![], fairness).next(), None);
+            assert_eq!(SocketIterator::new(vec![(ip, &[])], fairness).next(), None);
+            let passes = SocketIterator::in_passes(vec![], vec![(ip, &[])], fairness);
+            assert_eq!(passes.count(), 0);
+        }
+    }
+
+    /// Hosts 10.0.0.0, 10.0.0.1 and 10.0.0.2 with 6, 2 and 3 ports, returns
+    /// the last octet of every emitted host.
+    fn skewed_pattern(fairness: Fairness) -> Vec<u8> {
+        let ports: Vec<u16> = (1..=6).collect();
+        let hosts = vec![
+            ("10.0.0.0".parse().unwrap(), &ports[..]),
+            ("10.0.0.1".parse().unwrap(), &ports[..2]),
+            ("10.0.0.2".parse().unwrap(), &ports[..3]),
+        ];
+
+        SocketIterator::new(hosts, fairness)
+            .map(|socket| match socket.ip() {
+                IpAddr::V4(ip) => ip.octets()[3],
+                IpAddr::V6(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_takes_turns_between_hosts() {
+        assert_eq!(
+            skewed_pattern(Fairness::RoundRobin),
+            [0, 1, 2, 0, 1, 2, 0, 2, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn proportional_keeps_completion_together() {
+        assert_eq!(
+            skewed_pattern(Fairness::Proportional),
+            [0, 1, 2, 0, 0, 2, 0, 1, 0, 2, 0]
+        );
+    }
+
+    #[test]
+    fn input_order_scans_hosts_one_after_the_other() {
+        assert_eq!(
+            skewed_pattern(Fairness::InputOrder),
+            [0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn every_socket_is_emitted_once_in_port_order() {
+        let big: Vec<u16> = (1..=1000).collect();
+        let small: Vec<u16> = (1..=10).collect();
+        let mut hosts: Vec<(IpAddr, &[u16])> = vec![("10.0.1.1".parse().unwrap(), &big[..])];
+        for octet in 0..20 {
+            hosts.push((format!("10.0.0.{octet}").parse().unwrap(), &small[..]));
+        }
+        // Hosts without ports are skipped.
+        hosts.push(("10.0.2.1".parse().unwrap(), &[]));
+
+        for fairness in [
+            Fairness::RoundRobin,
+            Fairness::Proportional,
+            Fairness::InputOrder,
+        ] {
+            let mut seen: HashMap<IpAddr, Vec<u16>> = HashMap::new();
+            for socket in SocketIterator::new(hosts.clone(), fairness) {
+                seen.entry(socket.ip()).or_default().push(socket.port());
+            }
+
+            assert_eq!(seen.len(), 21);
+            for (ip, ports) in &hosts {
+                assert_eq!(seen.get(ip).map_or(&[][..], Vec::as_slice), *ports);
+            }
+        }
+    }
+
+    #[test]
+    fn first_pass_is_over_before_the_rest() {
+        // The first two ports of every host are its canaries.
+        let skewed: Vec<u16> = vec![3389, 445, 1, 2, 3, 4];
+        let short: Vec<u16> = vec![3389, 445, 1];
+        let ips: Vec<IpAddr> = (0..3)
+            .map(|octet| format!("10.0.0.{octet}").parse().unwrap())
+            .collect();
+        let hosts = |range: fn(&[u16]) -> &[u16]| -> Vec<(IpAddr, &[u16])> {
+            vec![
+                (ips[0], range(&skewed)),
+                (ips[1], range(&short)),
+                (ips[2], range(&skewed)),
+            ]
+        };
+
+        for fairness in [
+            Fairness::RoundRobin,
+            Fairness::Proportional,
+            Fairness::InputOrder,
+        ] {
+            let sockets: Vec<SocketAddr> = SocketIterator::in_passes(
+                hosts(|ports| &ports[..2]),
+                hosts(|ports| &ports[2..]),
+                fairness,
+            )
+            .collect();
+            assert_eq!(sockets.len(), 15);
+            assert!(
+                sockets[..6]
+                    .iter()
+                    .all(|socket| [3389, 445].contains(&socket.port())),
+                "{:?}",
+                sockets
+            );
+            assert!(sockets[6..]
+                .iter()
+                .all(|socket| ![3389, 445].contains(&socket.port())));
+        }
+    }
+
+    #[test]
+    fn proportional_progress_never_drifts_apart() {
+        let big: Vec<u16> = (1..=1000).collect();
+        let small: Vec<u16> = (1..=10).collect();
+        let mut hosts: Vec<(IpAddr, &[u16])> = vec![("10.0.1.1".parse().unwrap(), &big[..])];
+        for octet in 0..20 {
+            hosts.push((format!("10.0.0.{octet}").parse().unwrap(), &small[..]));
+        }
+        let totals: HashMap<IpAddr, usize> =
+            hosts.iter().map(|(ip, ports)| (*ip, ports.len())).collect();
+        let mut emitted: HashMap<IpAddr, usize> = HashMap::new();
+
+        for socket in SocketIterator::new(hosts.clone(), Fairness::Proportional) {
+            *emitted.entry(socket.ip()).or_default() += 1;
+
+            let completion: Vec<f64> = totals
+                .iter()
+                .map(|(ip, total)| *emitted.get(ip).unwrap_or(&0) as f64 / *total as f64)
+                .collect();
+            let spread = completion.iter().copied().fold(0.0, f64::max)
+                - completion.iter().copied().fold(1.0, f64::min);
+            // A host is at most one of the small hosts' steps ahead.
+            assert!(spread <= 0.1 + f64::EPSILON, "spread was {:?}", spread);
+        }
+    }
 }
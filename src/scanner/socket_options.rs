@@ -0,0 +1,282 @@
+//! Socket options applied to every probe socket before it connects.
+use super::interfaces::Binding;
+use super::socket_pool::SocketPool;
+use super::source_ports::{SourcePorts, MAX_BIND_ATTEMPTS};
+use async_io::Async;
+use async_std::io;
+use async_std::net::{TcpStream, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr};
+
+/// Options set on the probe sockets.
+///
+/// The default leaves every socket as the OS creates it, in which case the
+/// scanner keeps using the plain async-std connect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// IP time to live of outgoing probes, set as the hop limit on IPv6.
+    pub ttl: Option<u8>,
+    /// Whether TCP_NODELAY is set on TCP connections.
+    pub nodelay: bool,
+}
+
+impl SocketOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Makes sure the options can be set on sockets of every family found in
+    /// `ips`, so an unsupported option is reported once instead of turning
+    /// every port into a closed one.
+    pub fn check(&self, ips: &[IpAddr], udp: bool) -> io::Result<()> {
+        let kind = if udp { Type::DGRAM } else { Type::STREAM };
+        let mut families = Vec::new();
+        for ip in ips {
+            let domain = Domain::for_address(SocketAddr::new(*ip, 0));
+            if !families.contains(&domain) {
+                families.push(domain);
+            }
+        }
+
+        for domain in families {
+            let socket = Socket::new(domain, kind, None)?;
+            self.apply(&socket, domain, kind)?;
+        }
+
+        Ok(())
+    }
+
+    /// Connects to `addr` with a socket configured with these options, from
+    /// a port of `source_ports` when given. Ports which turn out to be taken
+    /// are swapped for other ones a few times before the OS picks the port.
+    /// The socket comes from `pool` when there is one, and is bound to the
+    /// source of `binding` when given.
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+        source_ports: Option<&SourcePorts>,
+        pool: Option<&SocketPool>,
+        binding: Option<&Binding>,
+    ) -> io::Result<TcpStream> {
+        if let Some(source_ports) = source_ports {
+            for _ in 0..MAX_BIND_ATTEMPTS {
+                let Some(port) = source_ports.take() else {
+                    break;
+                };
+                let result = self.connect_from(addr, Some(port), pool, binding).await;
+                source_ports.release(port);
+                match result {
+                    Err(e) if address_taken(&e) => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        self.connect_from(addr, None, pool, binding).await
+    }
+
+    async fn connect_from(
+        &self,
+        addr: SocketAddr,
+        port: Option<u16>,
+        pool: Option<&SocketPool>,
+        binding: Option<&Binding>,
+    ) -> io::Result<TcpStream> {
+        let domain = Domain::for_address(addr);
+        // Lent until the connect is over, or given up on.
+        let mut lease = pool.map(|pool| pool.lend(domain, self)).transpose()?;
+        let socket = match lease.as_mut() {
+            Some(lease) => lease.take(),
+            None => self.stream_socket(domain)?,
+        };
+        match (binding, port) {
+            (Some(binding), port) => binding.bind(&socket, port.unwrap_or(0))?,
+            (None, Some(port)) => socket.bind(&SourcePorts::local_addr(addr, port).into())?,
+            (None, None) => {}
+        }
+
+        match socket.connect(&addr.into()) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || in_progress(&e) => {}
+            Err(e) => return Err(e),
+        }
+
+        let stream = Async::new(std::net::TcpStream::from(socket))?;
+        stream.writable().await?;
+        if let Some(e) = stream.get_ref().take_error()? {
+            return Err(e);
+        }
+        // Some platforms only report a refused connection through peer_addr.
+        stream.get_ref().peer_addr()?;
+
+        Ok(TcpStream::from(stream.into_inner()?))
+    }
+
+    /// Binds a UDP socket able to reach `addr`, configured with these options,
+    /// to a port of `source_ports` and the source of `binding` when given,
+    /// like [`Self::connect`].
+    pub fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        source_ports: Option<&SourcePorts>,
+        binding: Option<&Binding>,
+    ) -> io::Result<UdpSocket> {
+        if let Some(source_ports) = source_ports {
+            for _ in 0..MAX_BIND_ATTEMPTS {
+                let Some(port) = source_ports.take() else {
+                    break;
+                };
+                let result = self.bind_udp_to(addr, port, binding);
+                source_ports.release(port);
+                match result {
+                    Err(e) if address_taken(&e) => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        self.bind_udp_to(addr, 0, binding)
+    }
+
+    fn bind_udp_to(
+        &self,
+        addr: SocketAddr,
+        port: u16,
+        binding: Option<&Binding>,
+    ) -> io::Result<UdpSocket> {
+        let domain = Domain::for_address(addr);
+        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+        self.apply(&socket, domain, Type::DGRAM)?;
+        match binding {
+            Some(binding) => binding.bind(&socket, port)?,
+            None => socket.bind(&SourcePorts::local_addr(addr, port).into())?,
+        }
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// A non-blocking TCP socket of `domain` with these options, ready to
+    /// connect.
+    pub(super) fn stream_socket(&self, domain: Domain) -> io::Result<Socket> {
+        let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+        self.apply(&socket, domain, Type::STREAM)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    fn apply(&self, socket: &Socket, domain: Domain, kind: Type) -> io::Result<()> {
+        if let Some(ttl) = self.ttl {
+            if domain == Domain::IPV6 {
+                socket
+                    .set_unicast_hops_v6(ttl.into())
+                    .map_err(|e| unsupported("IPV6_UNICAST_HOPS", "IPv6", &e))?;
+            } else {
+                socket
+                    .set_ttl(ttl.into())
+                    .map_err(|e| unsupported("IP_TTL", "IPv4", &e))?;
+            }
+        }
+
+        if self.nodelay && kind == Type::STREAM {
+            socket
+                .set_nodelay(true)
+                .map_err(|e| unsupported("TCP_NODELAY", "TCP", &e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the source port of a socket was already taken by another one.
+fn address_taken(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+    )
+}
+
+fn unsupported(option: &str, socket: &str, e: &io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("Could not set {option} on {socket} sockets: {e}"),
+    )
+}
+
+#[cfg(unix)]
+fn in_progress(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EINPROGRESS)
+}
+
+#[cfg(not(unix))]
+fn in_progress(_: &io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketOptions;
+    use crate::scanner::Binding;
+    use async_std::task::block_on;
+    use std::net::{IpAddr, SocketAddr, TcpListener};
+
+    const OPTIONS: SocketOptions = SocketOptions {
+        ttl: Some(2),
+        nodelay: true,
+    };
+
+    #[test]
+    fn options_are_set_before_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let stream = block_on(OPTIONS.connect(addr, None, None, None)).unwrap();
+
+        assert_eq!(stream.ttl().unwrap(), 2);
+        assert!(stream.nodelay().unwrap());
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn closed_port_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        assert!(block_on(OPTIONS.connect(addr, None, None, None)).is_err());
+    }
+
+    #[test]
+    fn options_are_supported_on_both_families() {
+        let ips: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+
+        assert!(OPTIONS.check(&ips, false).is_ok());
+        assert!(OPTIONS.check(&ips, true).is_ok());
+    }
+
+    #[test]
+    fn udp_socket_gets_the_ttl() {
+        let addr: SocketAddr = "127.0.0.1:53".parse().unwrap();
+        let socket = OPTIONS.bind_udp(addr, None, None).unwrap();
+
+        assert_eq!(socket.ttl().unwrap(), 2);
+    }
+
+    // The whole of 127.0.0.0/8 is on the loopback interface of Linux.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sockets_are_bound_to_the_source_of_their_host() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let binding = Binding {
+            interface: "lo".to_owned(),
+            source: "127.0.0.2".parse().unwrap(),
+            device: false,
+        };
+
+        let stream = block_on(OPTIONS.connect(addr, None, None, Some(&binding))).unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), binding.source);
+        let socket = OPTIONS.bind_udp(addr, None, Some(&binding)).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), binding.source);
+    }
+}
@@ -0,0 +1,188 @@
+//! TCP probe sockets created ahead of the probes which use them, see
+//! [`super::Scanner::with_socket_pool`].
+//!
+//! Creating a socket and setting its options costs a few syscalls which every
+//! probe otherwise pays on its own, right before it connects. The pool creates
+//! them by batches instead, one family at a time, when a probe finds none of
+//! its family left. A socket serves a single connect: once it's over the
+//! socket is the probe's to close, and its place goes to one of the next
+//! batch.
+//!
+//! The pool never holds more sockets than its capacity, the batch size,
+//! counting the ones lent to probes still connecting, so the scan doesn't need
+//! more descriptors than it did without it. What's left is closed when the
+//! scan is over or dropped, see [`SocketPool::drain_on_drop`].
+use super::socket_options::SocketOptions;
+use socket2::{Domain, Socket};
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Debug)]
+pub struct SocketPool {
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    ipv4: Vec<Socket>,
+    ipv6: Vec<Socket>,
+    /// The sockets lent to probes which are still connecting.
+    lent: usize,
+}
+
+impl State {
+    fn idle(&mut self, domain: Domain) -> &mut Vec<Socket> {
+        if domain == Domain::IPV6 {
+            &mut self.ipv6
+        } else {
+            &mut self.ipv4
+        }
+    }
+
+    fn held(&self) -> usize {
+        self.ipv4.len() + self.ipv6.len() + self.lent
+    }
+}
+
+impl SocketPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Lends a socket of `domain` configured with `options`, creating the
+    /// next batch first when the pool has none of that family left.
+    pub fn lend(&self, domain: Domain, options: &SocketOptions) -> io::Result<Lease<'_>> {
+        let mut state = self.lock();
+        if state.idle(domain).is_empty() {
+            self.refill(&mut state, domain, options)?;
+        }
+        let socket = state.idle(domain).pop();
+        state.lent += 1;
+        Ok(Lease { pool: self, socket })
+    }
+
+    /// Fills the room left in the pool with sockets of `domain`, making some
+    /// by closing the idle ones of the other family if need be.
+    fn refill(&self, state: &mut State, domain: Domain, options: &SocketOptions) -> io::Result<()> {
+        if state.held() >= self.capacity {
+            let other = if domain == Domain::IPV6 {
+                Domain::IPV4
+            } else {
+                Domain::IPV6
+            };
+            state.idle(other).clear();
+        }
+        // A probe always gets its socket, even past the capacity.
+        let room = self.capacity.saturating_sub(state.held()).max(1);
+        for _ in 0..room {
+            match options.stream_socket(domain) {
+                Ok(socket) => state.idle(domain).push(socket),
+                // The batch stops short when the descriptors run out.
+                Err(e) if state.idle(domain).is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the idle sockets.
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.ipv4.clear();
+        state.ipv6.clear();
+    }
+
+    /// Clears the pool when the guard returned is dropped, the scan being
+    /// over or cancelled.
+    pub fn drain_on_drop(&self) -> Drain<'_> {
+        Drain(self)
+    }
+
+    /// The idle sockets, and the lent ones.
+    pub fn held(&self) -> (usize, usize) {
+        let state = self.lock();
+        (state.ipv4.len() + state.ipv6.len(), state.lent)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A socket lent by the pool, counted against its capacity until dropped.
+#[derive(Debug)]
+pub struct Lease<'a> {
+    pool: &'a SocketPool,
+    socket: Option<Socket>,
+}
+
+impl Lease<'_> {
+    /// The socket lent, the lease itself keeping its place in the pool.
+    pub fn take(&mut self) -> Socket {
+        self.socket
+            .take()
+            .expect("the socket of a lease is taken once")
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.pool.lock().lent -= 1;
+    }
+}
+
+#[derive(Debug)]
+pub struct Drain<'a>(&'a SocketPool);
+
+impl Drop for Drain<'_> {
+    fn drop(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketPool;
+    use crate::scanner::SocketOptions;
+    use socket2::Domain;
+
+    #[test]
+    fn batches_stay_within_the_capacity() {
+        let options = SocketOptions::default();
+        let pool = SocketPool::new(4);
+
+        let mut first = pool.lend(Domain::IPV4, &options).unwrap();
+        assert_eq!(pool.held(), (3, 1));
+        let second = pool.lend(Domain::IPV4, &options).unwrap();
+        assert_eq!(pool.held(), (2, 2));
+        first.take();
+        drop(first);
+        assert_eq!(pool.held(), (2, 1));
+
+        // The room left goes to IPv6, then the idle IPv4 sockets do.
+        let ipv6 = pool.lend(Domain::IPV6, &options).unwrap();
+        assert_eq!(pool.held(), (2, 2));
+        let other = pool.lend(Domain::IPV6, &options).unwrap();
+        assert_eq!(pool.held(), (1, 3));
+        drop((second, ipv6, other));
+
+        {
+            let _drain = pool.drain_on_drop();
+        }
+        assert_eq!(pool.held(), (0, 0));
+    }
+
+    #[test]
+    fn a_full_pool_still_lends() {
+        let options = SocketOptions::default();
+        let pool = SocketPool::new(1);
+
+        let _first = pool.lend(Domain::IPV4, &options).unwrap();
+        let _second = pool.lend(Domain::IPV4, &options).unwrap();
+        assert_eq!(pool.held(), (0, 2));
+    }
+}
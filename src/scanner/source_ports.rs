@@ -0,0 +1,190 @@
+//! Random source ports for the probe sockets.
+//!
+//! The OS hands out ephemeral ports in sequence, which makes consecutive
+//! probes easy to tell apart from regular traffic. With a [`SourcePorts`]
+//! pool every probe socket is bound to a random port of the ephemeral range
+//! before it connects.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+
+/// How many ports are drawn before giving up on finding one not in use.
+const MAX_DRAWS: usize = 16;
+
+/// How many source ports are tried before letting the OS pick one.
+pub const MAX_BIND_ATTEMPTS: usize = 8;
+
+/// The ephemeral port range used where the OS doesn't tell its own.
+const IANA_EPHEMERAL_RANGE: RangeInclusive<u16> = 49152..=65535;
+
+/// Hands out random source ports, never the same one to two sockets at a
+/// time. The same seed draws the same sequence of ports.
+#[derive(Debug)]
+pub struct SourcePorts {
+    range: RangeInclusive<u16>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    rng: StdRng,
+    in_use: HashSet<u16>,
+}
+
+impl SourcePorts {
+    /// A pool over the ephemeral port range of the OS.
+    pub fn new(seed: Option<u64>) -> Self {
+        Self::with_range(ephemeral_range(), seed)
+    }
+
+    /// A pool over `range`, seeded with `seed` when given.
+    pub fn with_range(range: RangeInclusive<u16>, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            range,
+            state: Mutex::new(State {
+                rng,
+                in_use: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Takes a random port which no other socket holds, None when none was
+    /// found in a few draws.
+    pub fn take(&self) -> Option<u16> {
+        let mut state = self.state.lock().unwrap();
+        for _ in 0..MAX_DRAWS {
+            let port = state.rng.gen_range(self.range.clone());
+            if state.in_use.insert(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Gives `port` back once its socket is done with it.
+    pub fn release(&self, port: u16) {
+        self.state.lock().unwrap().in_use.remove(&port);
+    }
+
+    /// The local address binding to `port` to reach `remote`.
+    pub fn local_addr(remote: SocketAddr, port: u16) -> SocketAddr {
+        let ip: IpAddr = match remote {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        SocketAddr::new(ip, port)
+    }
+}
+
+/// Returns the range of ports the OS assigns to sockets bound to port 0.
+fn ephemeral_range() -> RangeInclusive<u16> {
+    #[cfg(target_os = "linux")]
+    if let Ok(range) = std::fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range") {
+        if let Some(range) = parse_port_range(&range) {
+            return range;
+        }
+    }
+
+    IANA_EPHEMERAL_RANGE
+}
+
+/// Parses the `low high` format of `ip_local_port_range`.
+fn parse_port_range(input: &str) -> Option<RangeInclusive<u16>> {
+    let mut bounds = input.split_whitespace().map(str::parse::<u16>);
+    match (bounds.next()?, bounds.next()?) {
+        (Ok(low), Ok(high)) if low <= high && low > 0 => Some(low..=high),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_port_range, SourcePorts};
+    use crate::scanner::SocketOptions;
+    use async_std::task::block_on;
+    use std::net::TcpListener;
+
+    #[test]
+    fn ports_held_are_not_handed_out_twice() {
+        let pool = SourcePorts::with_range(40000..=40003, Some(7));
+        let mut ports: Vec<u16> = (0..4).filter_map(|_| pool.take()).collect();
+        ports.sort_unstable();
+
+        assert_eq!(ports, [40000, 40001, 40002, 40003]);
+        assert_eq!(pool.take(), None);
+
+        pool.release(40002);
+        assert_eq!(pool.take(), Some(40002));
+    }
+
+    #[test]
+    fn seeded_pools_draw_the_same_ports() {
+        let draw = |seed| {
+            let pool = SourcePorts::with_range(32768..=60999, Some(seed));
+            (0..100).filter_map(|_| pool.take()).collect::<Vec<u16>>()
+        };
+
+        assert_eq!(draw(117), draw(117));
+        assert_ne!(draw(117), draw(118));
+    }
+
+    #[test]
+    fn probes_come_from_random_source_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = SourcePorts::with_range(40000..=60000, Some(117));
+
+        let ports: Vec<u16> = (0..20)
+            .map(|_| {
+                let stream =
+                    block_on(SocketOptions::default().connect(addr, Some(&pool), None, None))
+                        .unwrap();
+                let (_, peer) = listener.accept().unwrap();
+                assert_eq!(peer, stream.local_addr().unwrap());
+                peer.port()
+            })
+            .collect();
+
+        assert!(ports.iter().all(|port| (40000..=60000).contains(port)));
+        assert!(
+            !ports.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            ports
+        );
+        // Every connection is done, so every port went back to the pool.
+        assert!(pool.state.lock().unwrap().in_use.is_empty());
+    }
+
+    #[test]
+    fn taken_source_port_falls_back_to_the_os() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let pool = SourcePorts::with_range(port..=port, None);
+
+        let stream =
+            block_on(SocketOptions::default().connect(addr, Some(&pool), None, None)).unwrap();
+        assert_ne!(stream.local_addr().unwrap().port(), port);
+
+        let socket = SocketOptions::default()
+            .bind_udp(addr, Some(&pool), None)
+            .unwrap();
+        assert_ne!(socket.local_addr().unwrap().port(), 0);
+    }
+
+    #[test]
+    fn port_range_is_parsed() {
+        assert_eq!(parse_port_range("32768\t60999\n"), Some(32768..=60999));
+        assert_eq!(parse_port_range("60999 32768"), None);
+        assert_eq!(parse_port_range("0 10"), None);
+        assert_eq!(parse_port_range("1024"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Spreads the tries of a port over time, see [`SpreadTries`].
+//!
+//! Services behind fail2ban, or shedding load, answer now and then: three
+//! tries in a row within a timeout of each other all land in the same bad
+//! moment. With spread tries a port gets a single probe at a time, and a
+//! failed one comes back later, its tries spaced evenly over the interval
+//! or what is left of the scan. The retries wait in a [`RetryQueue`], which
+//! the batch loop takes the due ones from before the next sockets of the
+//! scan. Any try which connects makes the port open.
+//!
+//! A port is settled by its first try, like the tries of a host missing its
+//! heartbeats. A scan stopped with retries still waiting leaves them out:
+//! their ports are what their tries so far made of them, closed.
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Over how long the tries of a port are spread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpreadTries {
+    /// What is left of the scan when the first try fails, as forecast.
+    Remaining,
+    Over(Duration),
+}
+
+impl FromStr for SpreadTries {
+    type Err = String;
+
+    /// Parses `remaining`, or a duration like `2m`.
+    fn from_str(input: &str) -> Result<Self, String> {
+        if input.trim() == "remaining" {
+            return Ok(SpreadTries::Remaining);
+        }
+        match humantime::parse_duration(input.trim()) {
+            Ok(interval) if !interval.is_zero() => Ok(SpreadTries::Over(interval)),
+            Ok(_) => Err("the tries can't be spread over no time".to_owned()),
+            Err(e) => Err(format!("{e}, expected remaining or a duration like 2m")),
+        }
+    }
+}
+
+impl fmt::Display for SpreadTries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpreadTries::Remaining => f.write_str("remaining"),
+            SpreadTries::Over(interval) => write!(f, "{}", humantime::format_duration(*interval)),
+        }
+    }
+}
+
+/// What the tries of a socket made of it so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    /// A try connected, the socket is over.
+    Open,
+    /// The try failed, the next one is due then.
+    RetryAt(Instant),
+    /// Every try failed.
+    Closed,
+}
+
+/// The tries of a socket which failed so far.
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    /// The tries done.
+    done: u8,
+    tries: u8,
+    first: Instant,
+    interval: Duration,
+}
+
+impl Retry {
+    /// When the try after `done` is due: the tries are evenly spaced from
+    /// the first, the last at the end of the interval.
+    fn due(&self) -> Instant {
+        let gaps = u32::from(self.tries.saturating_sub(1)).max(1);
+        self.first + self.interval * u32::from(self.done) / gaps
+    }
+}
+
+/// The retries waiting for their time, earliest first.
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    due: BinaryHeap<Reverse<(Instant, u64, SocketAddr)>>,
+    retries: HashMap<SocketAddr, Retry>,
+    /// Keeps the retries due at once in the order they failed in.
+    sequence: u64,
+}
+
+impl RetryQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merges a try of `socket` which ended at `now` into the ones before:
+    /// open once one connects, tried again after `interval` spread over its
+    /// `tries` otherwise, closed after the last. The interval is the one of
+    /// the first try.
+    pub fn probed(
+        &mut self,
+        socket: SocketAddr,
+        open: bool,
+        tries: u8,
+        interval: Duration,
+        now: Instant,
+    ) -> Attempt {
+        if open {
+            self.retries.remove(&socket);
+            return Attempt::Open;
+        }
+        let retry = self.retries.entry(socket).or_insert(Retry {
+            done: 0,
+            tries,
+            first: now,
+            interval,
+        });
+        retry.done += 1;
+        if retry.done >= retry.tries {
+            self.retries.remove(&socket);
+            return Attempt::Closed;
+        }
+        let due = retry.due().max(now);
+        self.sequence += 1;
+        self.due.push(Reverse((due, self.sequence, socket)));
+        Attempt::RetryAt(due)
+    }
+
+    /// The socket of the earliest retry due by `now`.
+    pub fn next_due(&mut self, now: Instant) -> Option<SocketAddr> {
+        let Reverse((due, _, _)) = self.due.peek()?;
+        if *due > now {
+            return None;
+        }
+        self.due.pop().map(|Reverse((_, _, socket))| socket)
+    }
+
+    /// When the earliest retry is due.
+    pub fn next_wake(&self) -> Option<Instant> {
+        self.due.peek().map(|Reverse((due, _, _))| *due)
+    }
+
+    /// Whether `socket` had a try already, and was settled by it.
+    pub fn is_retry(&self, socket: SocketAddr) -> bool {
+        self.retries.contains_key(&socket)
+    }
+
+    /// Gives up on the tries left of `socket`, like those of a skipped host.
+    pub fn abandon(&mut self, socket: SocketAddr) {
+        self.retries.remove(&socket);
+    }
+
+    /// How many sockets still have tries to go.
+    pub fn pending(&self) -> usize {
+        self.retries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attempt, RetryQueue, SpreadTries};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    fn socket(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn retries_come_out_when_due_in_order() {
+        let start = Instant::now();
+        let mut queue = RetryQueue::new();
+        // Three tries over a minute: the retries come 30s and 60s after the
+        // first failure.
+        assert_eq!(
+            queue.probed(socket(80), false, 3, MINUTE, start),
+            Attempt::RetryAt(start + MINUTE / 2)
+        );
+        let later = start + Duration::from_secs(10);
+        queue.probed(socket(443), false, 3, MINUTE, later);
+        // Due at the same time as 80, after it.
+        queue.probed(socket(22), false, 3, Duration::from_secs(40), later);
+        assert_eq!(queue.pending(), 3);
+        assert!(queue.is_retry(socket(443)));
+        assert!(!queue.is_retry(socket(8080)));
+
+        assert_eq!(queue.next_due(start + Duration::from_secs(29)), None);
+        assert_eq!(queue.next_wake(), Some(start + MINUTE / 2));
+        let due = start + Duration::from_secs(45);
+        let order: Vec<u16> = std::iter::from_fn(|| queue.next_due(due))
+            .map(|socket| socket.port())
+            .collect();
+        assert_eq!(order, [80, 22, 443]);
+
+        // The last try is at the end of the interval, at once when late.
+        let late = start + 2 * MINUTE;
+        assert_eq!(
+            queue.probed(socket(80), false, 3, MINUTE, start + MINUTE / 2),
+            Attempt::RetryAt(start + MINUTE)
+        );
+        assert_eq!(
+            queue.probed(socket(443), false, 3, MINUTE, late),
+            Attempt::RetryAt(late)
+        );
+    }
+
+    #[test]
+    fn any_try_which_connects_makes_the_port_open() {
+        let start = Instant::now();
+        let mut queue = RetryQueue::new();
+        queue.probed(socket(80), false, 3, MINUTE, start);
+        queue.probed(socket(80), false, 3, MINUTE, start + MINUTE / 2);
+        assert_eq!(
+            queue.probed(socket(80), true, 3, MINUTE, start + MINUTE),
+            Attempt::Open
+        );
+        assert_eq!(queue.pending(), 0);
+
+        // Every try failing closes the port, after its tries only.
+        queue.probed(socket(22), false, 2, MINUTE, start);
+        assert!(queue.is_retry(socket(22)));
+        assert_eq!(
+            queue.probed(socket(22), false, 2, MINUTE, start + MINUTE),
+            Attempt::Closed
+        );
+        assert!(!queue.is_retry(socket(22)));
+
+        // A single try is never retried.
+        assert_eq!(
+            queue.probed(socket(443), false, 1, MINUTE, start),
+            Attempt::Closed
+        );
+        assert_eq!(queue.pending(), 0);
+    }
+
+    #[test]
+    fn spreads_are_parsed() {
+        assert_eq!("remaining".parse(), Ok(SpreadTries::Remaining));
+        assert_eq!("2m".parse(), Ok(SpreadTries::Over(2 * MINUTE)));
+        assert!("0s".parse::<SpreadTries>().is_err());
+        assert!("soon".parse::<SpreadTries>().is_err());
+        assert_eq!(
+            SpreadTries::Over(90 * Duration::from_secs(1)).to_string(),
+            "1m 30s"
+        );
+    }
+}
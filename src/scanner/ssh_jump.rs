@@ -0,0 +1,476 @@
+//! Probes the ports behind an SSH bastion, see [`SshJump`].
+//!
+//! The jump goes through the OpenSSH client, run as a command of its own the
+//! way nmap and the scripts are. A master connection authenticates once, with
+//! the agent or a key file, and every probe then asks it for a `direct-tcpip`
+//! channel to the socket with `ssh -W`. The bastion connects to the socket
+//! itself, so its answer tells the state of the port: a confirmed channel is
+//! open, a refused one closed, and one still pending at the timeout filtered.
+//! Addresses are given to the bastion as they are, IPv6 ones in brackets.
+//!
+//! Hostnames are given as they are too, the bastion resolving them: the
+//! hosts behind it are often named by a DNS only it sees. Until then they
+//! are scanned under a stand-in address of 198.18.0.0/15, a block kept for
+//! benchmarks and never routed, see [`BastionNames`].
+//!
+//! The control socket of the master connection and its log go in a
+//! [`PrivateDir`], another local user could talk to the bastion through a
+//! socket of the shared temporary folder.
+use crate::address::{HostResolver, ResolutionError};
+use crate::private_dir::PrivateDir;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the master connection has to authenticate.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often the master connection is checked while it authenticates.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Told by `ssh -v` once the bastion confirmed the channel.
+const CONFIRMED: &str = "master session id:";
+
+/// Told by `ssh` once the bastion refused the channel.
+const REFUSED: [&str; 2] = ["Session open refused by peer", "open failed"];
+
+/// The first stand-in address of the hostnames, and how many there are.
+const STAND_INS: (Ipv4Addr, u32) = (Ipv4Addr::new(198, 18, 0, 0), 1 << 17);
+
+/// The SSH bastion the probes go through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshJump {
+    /// `user@bastion`, or any destination `ssh` takes.
+    pub destination: String,
+    /// The key to authenticate with, the agent and the keys of the SSH
+    /// configuration otherwise.
+    pub identity: Option<PathBuf>,
+    /// How many channels are open through the bastion at once.
+    pub max_channels: u16,
+    /// The hostnames the bastion resolves, by their stand-in address.
+    pub hostnames: HashMap<IpAddr, String>,
+}
+
+/// Resolves hostnames to stand-in addresses, one per hostname, for the
+/// bastion to resolve them instead, see [`SshJump::with_hostnames`].
+#[derive(Debug, Default)]
+pub struct BastionNames {
+    names: RefCell<Vec<String>>,
+}
+
+impl BastionNames {
+    /// The hostnames resolved so far, by their stand-in address.
+    pub fn into_hostnames(self) -> HashMap<IpAddr, String> {
+        let start = u32::from(STAND_INS.0);
+        self.names
+            .into_inner()
+            .into_iter()
+            .zip(start..)
+            .map(|(name, ip)| (IpAddr::V4(ip.into()), name))
+            .collect()
+    }
+}
+
+impl HostResolver for BastionNames {
+    fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, ResolutionError> {
+        let mut names = self.names.borrow_mut();
+        let index = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(host))
+        {
+            Some(index) => index,
+            None => {
+                names.push(host.to_owned());
+                names.len() - 1
+            }
+        };
+        match u32::try_from(index) {
+            Ok(index) if index < STAND_INS.1 => {
+                Ok(vec![IpAddr::V4((u32::from(STAND_INS.0) + index).into())])
+            }
+            _ => {
+                names.pop();
+                Err(ResolutionError::Failed(format!(
+                    "more than {} hostnames behind the bastion",
+                    STAND_INS.1
+                )))
+            }
+        }
+    }
+}
+
+/// What the bastion made of a channel to a socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelResult {
+    /// The channel was confirmed, the bastion reached the port.
+    Open,
+    /// The channel was refused, the port refused the bastion.
+    Closed,
+    /// The channel was still pending at the timeout.
+    Filtered,
+    /// `ssh` gave up without an answer of the bastion, with its last words.
+    Failed(String),
+}
+
+impl ChannelResult {
+    /// The result as the one of a direct connect, the kind of the error
+    /// telling refused ports from silent ones.
+    pub fn into_io(self, socket: SocketAddr) -> io::Result<()> {
+        match self {
+            ChannelResult::Open => Ok(()),
+            ChannelResult::Closed => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("The bastion was refused by {socket}"),
+            )),
+            ChannelResult::Filtered => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("The bastion got no answer from {socket}"),
+            )),
+            ChannelResult::Failed(reason) => Err(io::Error::other(reason)),
+        }
+    }
+}
+
+/// Classifies a channel from what `ssh -v -W` wrote on its standard error
+/// so far, and whether it exited since.
+pub fn classify(stderr: &str, exited: bool) -> ChannelResult {
+    if stderr.contains(CONFIRMED) {
+        return ChannelResult::Open;
+    }
+    if REFUSED.iter().any(|refused| stderr.contains(refused)) {
+        return ChannelResult::Closed;
+    }
+    if !exited {
+        return ChannelResult::Filtered;
+    }
+    let reason = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty() && !line.starts_with("debug"))
+        .unwrap_or("ssh exited without an answer of the bastion");
+    ChannelResult::Failed(reason.trim().to_owned())
+}
+
+impl SshJump {
+    pub fn new(destination: impl Into<String>) -> Self {
+        Self {
+            destination: destination.into(),
+            identity: None,
+            max_channels: 10,
+            hostnames: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_identity(mut self, identity: PathBuf) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_channels(mut self, max_channels: u16) -> Self {
+        self.max_channels = max_channels.max(1);
+        self
+    }
+
+    /// Gives the probes of the stand-in addresses of [`BastionNames`] their
+    /// hostname.
+    #[must_use]
+    pub fn with_hostnames(mut self, hostnames: HashMap<IpAddr, String>) -> Self {
+        self.hostnames = hostnames;
+        self
+    }
+
+    /// Opens the master connection, an error when the bastion can't be
+    /// reached or authenticated with.
+    pub fn connect(self) -> anyhow::Result<JumpSession> {
+        let dir = PrivateDir::create("rustscan-ssh")?;
+        let control_path = dir.join("control");
+        let log_path = dir.join("master.log");
+
+        let mut master = Command::new("ssh");
+        master
+            .args(["-M", "-N", "-S"])
+            .arg(&control_path)
+            .args(MASTER_OPTIONS.iter().flat_map(|option| ["-o", option]));
+        if let Some(identity) = &self.identity {
+            master
+                .arg("-i")
+                .arg(identity)
+                .args(["-o", "IdentitiesOnly=yes"]);
+        }
+        let master = master
+            .arg(&self.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(File::create(&log_path)?)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Can't run ssh: {e}"))?;
+
+        let session = JumpSession {
+            jump: self,
+            control_path,
+            log_path,
+            master: Mutex::new(master),
+            _dir: dir,
+        };
+        session.wait_authenticated()?;
+        Ok(session)
+    }
+}
+
+/// The options of the master connection: no prompts, and gone with RustScan.
+const MASTER_OPTIONS: [&str; 4] = [
+    "BatchMode=yes",
+    "ControlPersist=no",
+    "ExitOnForwardFailure=yes",
+    "ConnectTimeout=10",
+];
+
+/// An authenticated connection to the bastion, closed once dropped.
+#[derive(Debug)]
+pub struct JumpSession {
+    jump: SshJump,
+    control_path: PathBuf,
+    /// Where the master connection writes its errors, read when it fails.
+    log_path: PathBuf,
+    master: Mutex<Child>,
+    /// Holds the control socket and the log, removed after them.
+    _dir: PrivateDir,
+}
+
+impl JumpSession {
+    pub fn destination(&self) -> &str {
+        &self.jump.destination
+    }
+
+    pub fn max_channels(&self) -> u16 {
+        self.jump.max_channels
+    }
+
+    /// Probes `socket` through the bastion, as a direct connect would.
+    pub async fn probe(self: Arc<Self>, socket: SocketAddr, timeout: Duration) -> io::Result<()> {
+        async_std::task::spawn_blocking(move || self.open_channel(socket, timeout))
+            .await
+            .into_io(socket)
+    }
+
+    /// Asks the bastion for a channel to `socket`, waiting `timeout` at most
+    /// for its answer.
+    pub fn open_channel(&self, socket: SocketAddr, timeout: Duration) -> ChannelResult {
+        let target = match self.jump.hostnames.get(&socket.ip()) {
+            Some(hostname) => format!("{hostname}:{}", socket.port()),
+            None => socket.to_string(),
+        };
+        let child = self
+            .control_command()
+            .args(["-v", "-W"])
+            .arg(target)
+            .arg(&self.jump.destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => return ChannelResult::Failed(format!("Can't run ssh: {e}")),
+        };
+
+        // The lines come from a thread of their own, to wait on them with a
+        // timeout, and stop once the channel is told apart.
+        let (sender, lines) = mpsc::channel();
+        let stderr = child.stderr.take().expect("The standard error is piped.");
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let deadline = Instant::now() + timeout;
+        let mut told = String::new();
+        let result = loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(left) {
+                Ok(line) => {
+                    told.push_str(&line);
+                    told.push('\n');
+                    match classify(&told, false) {
+                        ChannelResult::Filtered => continue,
+                        result => break result,
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break classify(&told, false),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break classify(&told, true),
+            }
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        result
+    }
+
+    /// `ssh` talking to the master connection.
+    fn control_command(&self) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-S")
+            .arg(&self.control_path)
+            .args(["-o", "BatchMode=yes"]);
+        command
+    }
+
+    /// Waits for the master connection to be up, an error with the last
+    /// words of `ssh` when it exits first.
+    fn wait_authenticated(&self) -> anyhow::Result<()> {
+        let started = Instant::now();
+        loop {
+            let exited = self.master.lock().unwrap().try_wait()?;
+            if let Some(status) = exited {
+                let log = fs::read_to_string(&self.log_path).unwrap_or_default();
+                let reason = match classify(&log, true) {
+                    ChannelResult::Failed(reason) => reason,
+                    _ => format!("ssh exited with {status}"),
+                };
+                anyhow::bail!("Can't connect to {}: {reason}", self.jump.destination);
+            }
+            if self.is_up() {
+                return Ok(());
+            }
+            if started.elapsed() > CONNECT_TIMEOUT {
+                anyhow::bail!(
+                    "Can't connect to {}: no connection after {}s",
+                    self.jump.destination,
+                    CONNECT_TIMEOUT.as_secs()
+                );
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.control_command()
+            .args(["-O", "check"])
+            .arg(&self.jump.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+impl Drop for JumpSession {
+    fn drop(&mut self) {
+        let _ = self
+            .control_command()
+            .args(["-O", "exit"])
+            .arg(&self.jump.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Ok(master) = self.master.get_mut() {
+            let _ = master.kill();
+            let _ = master.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, BastionNames, ChannelResult, SshJump};
+    use crate::address::HostResolver;
+    use std::io;
+    use std::net::{IpAddr, SocketAddr};
+
+    #[test]
+    fn channels_are_classified() {
+        let confirmed = "debug1: Reading configuration data /etc/ssh/ssh_config\n\
+                         debug1: mux_client_request_stdio_fwd: master session id: 2\n";
+        assert_eq!(classify(confirmed, false), ChannelResult::Open);
+        // Confirmed channels stay open, even closed by the port right after.
+        assert_eq!(classify(confirmed, true), ChannelResult::Open);
+
+        let refused = "debug1: mux_client_request_session: ...\n\
+                       Stdio forwarding request failed: Session open refused by peer\n";
+        assert_eq!(classify(refused, true), ChannelResult::Closed);
+        assert_eq!(
+            classify(
+                "channel 0: open failed: connect failed: Connection refused\n",
+                true
+            ),
+            ChannelResult::Closed
+        );
+
+        // Nothing from the bastion yet, the port is silent.
+        let pending = "debug1: Reading configuration data /etc/ssh/ssh_config\n";
+        assert_eq!(classify(pending, false), ChannelResult::Filtered);
+        assert_eq!(classify("", false), ChannelResult::Filtered);
+
+        // ssh giving up is none of these.
+        let lost = "debug1: Reading configuration data /etc/ssh/ssh_config\n\
+                    Control socket connect(/tmp/rustscan-ssh): Connection refused\n\
+                    debug1: exiting\n";
+        assert_eq!(
+            classify(lost, true),
+            ChannelResult::Failed(
+                "Control socket connect(/tmp/rustscan-ssh): Connection refused".to_owned()
+            )
+        );
+        assert!(matches!(classify("", true), ChannelResult::Failed(_)));
+        assert_eq!(
+            classify("jump@bastion: Permission denied (publickey).\n", true),
+            ChannelResult::Failed("jump@bastion: Permission denied (publickey).".to_owned())
+        );
+    }
+
+    #[test]
+    fn results_map_to_connect_errors() {
+        let socket: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        // IPv6 addresses go to the bastion in brackets.
+        assert_eq!(socket.to_string(), "[2001:db8::1]:443");
+        assert!(ChannelResult::Open.into_io(socket).is_ok());
+        let kind = |result: ChannelResult| result.into_io(socket).unwrap_err().kind();
+        assert_eq!(
+            kind(ChannelResult::Closed),
+            io::ErrorKind::ConnectionRefused
+        );
+        assert_eq!(kind(ChannelResult::Filtered), io::ErrorKind::TimedOut);
+        assert_eq!(
+            kind(ChannelResult::Failed("gone".to_owned())),
+            io::ErrorKind::Other
+        );
+        assert_eq!(SshJump::new("a@b").with_max_channels(0).max_channels, 1);
+    }
+
+    #[test]
+    fn hostnames_are_left_to_the_bastion() {
+        let names = BastionNames::default();
+        let stand_in = |host: &str| names.resolve(host).unwrap();
+        let db: IpAddr = "198.18.0.0".parse().unwrap();
+        assert_eq!(stand_in("db.internal"), [db]);
+        assert_eq!(
+            stand_in("git.internal"),
+            ["198.18.0.1".parse::<IpAddr>().unwrap()]
+        );
+        // The same name, whatever its case, is the same host.
+        assert_eq!(stand_in("DB.internal"), [db]);
+
+        let hostnames = names.into_hostnames();
+        assert_eq!(hostnames.len(), 2);
+        assert_eq!(hostnames[&db], "db.internal");
+        let jump = SshJump::new("a@b").with_hostnames(hostnames);
+        assert_eq!(jump.hostnames[&db], "db.internal");
+    }
+}
@@ -0,0 +1,395 @@
+//! Pacing of hosts which seem to rate-limit the probes.
+//!
+//! Some intrusion prevention systems let a burst of probes through, then
+//! silently drop everything else, which turns the rest of the scan into
+//! filtered ports. A host which answered, opened or refused a connection,
+//! and then lets a run of probes in a row time out is assumed to block the
+//! scan: its probes are paused for a cooldown, its concurrency is halved and
+//! the ports which timed out are tried again once the cooldown is over.
+use serde_derive::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// When a host is considered to block the scan, and how it's slowed down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// How many probes in a row have to time out after an answer.
+    pub window: u32,
+    /// How long the probes of a blocking host are paused.
+    pub cooldown: Duration,
+    /// How many times the concurrency of a host may be divided at most.
+    pub max_slowdown: u32,
+}
+
+/// How a probe ended, as far as blocking goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The connection was accepted or refused.
+    Answered,
+    TimedOut,
+    /// Any other error, which tells nothing about the host.
+    Failed,
+}
+
+/// Notices a host going from answering to a run of timeouts.
+#[derive(Debug, Clone)]
+pub struct Detector {
+    window: u32,
+    answered: bool,
+    /// The timeouts in a row since the last answer.
+    silent: u32,
+}
+
+impl Detector {
+    pub fn new(window: u32) -> Self {
+        Self {
+            window: window.max(1),
+            answered: false,
+            silent: 0,
+        }
+    }
+
+    /// Records how a probe ended, true when the host just started blocking.
+    ///
+    /// Hosts which never answered are filtered rather than blocking. Once
+    /// blocking was detected, the host has to answer again before it can be
+    /// detected another time.
+    pub fn record(&mut self, outcome: Outcome) -> bool {
+        match outcome {
+            Outcome::Answered => {
+                self.answered = true;
+                self.silent = 0;
+                false
+            }
+            Outcome::TimedOut if self.answered => {
+                self.silent += 1;
+                if self.silent < self.window {
+                    return false;
+                }
+                self.answered = false;
+                self.silent = 0;
+                true
+            }
+            Outcome::TimedOut | Outcome::Failed => false,
+        }
+    }
+}
+
+/// How a host was slowed down during the scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Throttling {
+    #[serde(skip)]
+    pub ip: IpAddr,
+    /// How many times the host was found to block the scan.
+    pub detections: u32,
+    pub cooldown_ms: u64,
+    /// The most probes of the host in flight at a time, at the end of the scan.
+    pub concurrency: usize,
+    /// The ports tried again after a cooldown.
+    pub retried_ports: usize,
+}
+
+/// The pacing of a single host.
+#[derive(Debug)]
+struct Paced {
+    detector: Detector,
+    /// What the batch size is divided by for the host.
+    slowdown: u32,
+    in_flight: usize,
+    cooling: bool,
+    /// The probes which timed out since the last answer.
+    suspects: VecDeque<SocketAddr>,
+    /// The sockets held back, the ones to try again first.
+    held: VecDeque<SocketAddr>,
+    retried: HashSet<SocketAddr>,
+    detections: u32,
+}
+
+/// Paces every host of a scan, deciding which sockets get probed.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    pacing: Pacing,
+    batch_size: usize,
+    hosts: HashMap<IpAddr, Paced>,
+    /// The hosts with held sockets.
+    holding: BTreeSet<IpAddr>,
+}
+
+impl Throttle {
+    pub fn new(pacing: Pacing, batch_size: usize) -> Self {
+        Self {
+            pacing,
+            batch_size: batch_size.max(1),
+            hosts: HashMap::new(),
+            holding: BTreeSet::new(),
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.pacing.cooldown
+    }
+
+    /// The most probes of `ip` allowed in flight at a time.
+    pub fn concurrency(&self, ip: IpAddr) -> usize {
+        self.hosts
+            .get(&ip)
+            .map_or(self.batch_size, |paced| paced.concurrency(self.batch_size))
+    }
+
+    /// The next socket to probe. The sockets of hosts cooling down, or with
+    /// as many probes in flight as they may have, are held back until they
+    /// can go.
+    pub fn next_socket(
+        &mut self,
+        sockets: &mut impl FnMut() -> Option<SocketAddr>,
+    ) -> Option<SocketAddr> {
+        let batch_size = self.batch_size;
+        let ready = self.holding.iter().copied().find(|ip| {
+            self.hosts
+                .get(ip)
+                .is_some_and(|paced| paced.can_probe(batch_size))
+        });
+        if let Some(ip) = ready {
+            let paced = self.hosts.get_mut(&ip).unwrap();
+            let socket = paced.held.pop_front();
+            if paced.held.is_empty() {
+                self.holding.remove(&ip);
+            }
+            paced.in_flight += 1;
+            return socket;
+        }
+
+        while let Some(socket) = sockets() {
+            let paced = self.paced(socket.ip());
+            if paced.can_probe(batch_size) {
+                paced.in_flight += 1;
+                return Some(socket);
+            }
+            paced.held.push_back(socket);
+            self.holding.insert(socket.ip());
+        }
+        None
+    }
+
+    /// Whether `socket` is being tried again after a cooldown.
+    pub fn is_retry(&self, socket: SocketAddr) -> bool {
+        self.hosts
+            .get(&socket.ip())
+            .is_some_and(|paced| paced.retried.contains(&socket))
+    }
+
+    /// Records how the probe of `socket` ended, true when its host just
+    /// started blocking and has to cool down.
+    pub fn probed(&mut self, socket: SocketAddr, outcome: Outcome) -> bool {
+        let (window, max_slowdown) = (self.pacing.window as usize, self.pacing.max_slowdown);
+        let paced = self.paced(socket.ip());
+        paced.in_flight = paced.in_flight.saturating_sub(1);
+
+        match outcome {
+            Outcome::Answered => paced.suspects.clear(),
+            // Probes sent before the cooldown started may time out during it.
+            Outcome::TimedOut if paced.cooling => {
+                paced.retry(socket);
+                if !paced.held.is_empty() {
+                    self.holding.insert(socket.ip());
+                }
+                return false;
+            }
+            Outcome::TimedOut => {
+                paced.suspects.push_back(socket);
+                if paced.suspects.len() > window {
+                    paced.suspects.pop_front();
+                }
+            }
+            Outcome::Failed => {}
+        }
+        if !paced.detector.record(outcome) {
+            return false;
+        }
+
+        paced.detections += 1;
+        paced.slowdown = paced.slowdown.saturating_mul(2).min(max_slowdown.max(1));
+        paced.cooling = true;
+        for suspect in std::mem::take(&mut paced.suspects) {
+            paced.retry(suspect);
+        }
+        if !paced.held.is_empty() {
+            self.holding.insert(socket.ip());
+        }
+        true
+    }
+
+    /// Lets `ip` probe again once its cooldown is over.
+    pub fn cooled_down(&mut self, ip: IpAddr) {
+        if let Some(paced) = self.hosts.get_mut(&ip) {
+            paced.cooling = false;
+        }
+    }
+
+    /// The hosts which were found to block the scan at least once.
+    pub fn into_throttlings(self) -> Vec<Throttling> {
+        let (cooldown, batch_size) = (self.pacing.cooldown, self.batch_size);
+        self.hosts
+            .into_iter()
+            .filter(|(_, paced)| paced.detections > 0)
+            .map(|(ip, paced)| Throttling {
+                ip,
+                detections: paced.detections,
+                cooldown_ms: cooldown.as_millis() as u64,
+                concurrency: paced.concurrency(batch_size),
+                retried_ports: paced.retried.len(),
+            })
+            .collect()
+    }
+
+    fn paced(&mut self, ip: IpAddr) -> &mut Paced {
+        let window = self.pacing.window;
+        self.hosts.entry(ip).or_insert_with(|| Paced {
+            detector: Detector::new(window),
+            slowdown: 1,
+            in_flight: 0,
+            cooling: false,
+            suspects: VecDeque::new(),
+            held: VecDeque::new(),
+            retried: HashSet::new(),
+            detections: 0,
+        })
+    }
+}
+
+impl Paced {
+    fn concurrency(&self, batch_size: usize) -> usize {
+        (batch_size / self.slowdown as usize).max(1)
+    }
+
+    fn can_probe(&self, batch_size: usize) -> bool {
+        !self.cooling && self.in_flight < self.concurrency(batch_size)
+    }
+
+    /// Tries `socket` again after the cooldown, only once.
+    fn retry(&mut self, socket: SocketAddr) {
+        if self.retried.insert(socket) {
+            self.held.push_front(socket);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Detector, Outcome, Pacing, Throttle};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    const PACING: Pacing = Pacing {
+        window: 3,
+        cooldown: Duration::from_secs(10),
+        max_slowdown: 4,
+    };
+
+    fn outcomes(pattern: &str) -> impl Iterator<Item = Outcome> + '_ {
+        pattern.chars().map(|outcome| match outcome {
+            'a' => Outcome::Answered,
+            't' => Outcome::TimedOut,
+            _ => Outcome::Failed,
+        })
+    }
+
+    // The positions in `pattern` at which blocking is detected.
+    fn detections(pattern: &str) -> Vec<usize> {
+        let mut detector = Detector::new(3);
+        outcomes(pattern)
+            .enumerate()
+            .filter(|(_, outcome)| detector.record(*outcome))
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    #[test]
+    fn clean_host_turning_silent_is_blocking() {
+        assert_eq!(detections("aaaattt"), [6]);
+        assert_eq!(detections("aaaatttttttt"), [6]);
+    }
+
+    #[test]
+    fn scattered_timeouts_are_not_blocking() {
+        assert!(detections("attattattatta").is_empty());
+        // Other errors neither count nor break the run.
+        assert_eq!(detections("atftft"), [5]);
+    }
+
+    #[test]
+    fn hosts_which_never_answered_are_filtered() {
+        assert!(detections("tttttttttt").is_empty());
+    }
+
+    #[test]
+    fn blocking_is_detected_again_after_an_answer() {
+        assert_eq!(detections("attttttatttt"), [3, 10]);
+    }
+
+    fn socket(port: u16) -> SocketAddr {
+        SocketAddr::new("10.0.0.1".parse().unwrap(), port)
+    }
+
+    #[test]
+    fn blocking_host_cools_down_and_retries_its_suspects() {
+        let mut throttle = Throttle::new(PACING, 8);
+        let mut ports = (1..=20).map(socket);
+        let mut next = || ports.next();
+
+        let sent: Vec<SocketAddr> = (0..5)
+            .filter_map(|_| throttle.next_socket(&mut next))
+            .collect();
+        assert!(!throttle.probed(sent[0], Outcome::Answered));
+        assert!(!throttle.probed(sent[1], Outcome::TimedOut));
+        assert!(!throttle.probed(sent[2], Outcome::TimedOut));
+        assert!(throttle.probed(sent[3], Outcome::TimedOut));
+        // Still in flight when the cooldown started.
+        assert!(!throttle.probed(sent[4], Outcome::TimedOut));
+
+        // Nothing goes while cooling down.
+        assert_eq!(throttle.next_socket(&mut next), None);
+        throttle.cooled_down(socket(1).ip());
+
+        // The suspects go first, with half the concurrency.
+        let retried: Vec<u16> = (0..4)
+            .filter_map(|_| throttle.next_socket(&mut || None))
+            .map(|socket| socket.port())
+            .collect();
+        assert_eq!(retried.len(), 4);
+        assert!(retried.iter().all(|port| (2..=5).contains(port)));
+        assert!(throttle.is_retry(socket(2)));
+        assert_eq!(throttle.next_socket(&mut || None), None);
+        throttle.probed(socket(2), Outcome::Answered);
+        assert_eq!(throttle.next_socket(&mut || None), Some(socket(6)));
+
+        let throttlings = throttle.into_throttlings();
+        assert_eq!(throttlings.len(), 1);
+        assert_eq!(throttlings[0].detections, 1);
+        assert_eq!(throttlings[0].concurrency, 4);
+        assert_eq!(throttlings[0].retried_ports, 4);
+    }
+
+    #[test]
+    fn concurrency_is_capped_and_never_below_one() {
+        let mut throttle = Throttle::new(PACING, 8);
+        let ip = socket(1).ip();
+        for _ in 0..4 {
+            throttle.probed(socket(1), Outcome::Answered);
+            for port in 2..=4 {
+                throttle.probed(socket(port), Outcome::TimedOut);
+            }
+            throttle.cooled_down(ip);
+        }
+        // Slowed down by 2, 4, then never more than the maximum of 4.
+        assert_eq!(throttle.into_throttlings()[0].concurrency, 2);
+
+        let mut throttle = Throttle::new(PACING, 1);
+        throttle.probed(socket(1), Outcome::Answered);
+        for port in 2..=4 {
+            throttle.probed(socket(port), Outcome::TimedOut);
+        }
+        assert_eq!(throttle.into_throttlings()[0].concurrency, 1);
+    }
+}
@@ -0,0 +1,227 @@
+//! Per-network timeouts of `--timeout-map`.
+//!
+//! The map names groups of hosts by CIDR, each with its own timeout and,
+//! optionally, tries:
+//!
+//! ```text
+//! lan=10.0.0.0/8:300,vpn=172.16.0.0/12:2000:2,default:1000
+//! ```
+//!
+//! A host gets the group of the longest prefix holding it, the `default`
+//! group when none does, and the `--timeout` and `--tries` of the run when
+//! there is no default either. Nested networks are fine, the same network
+//! twice is not.
+use cidr_utils::cidr::IpCidr;
+use serde_derive::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A group of hosts sharing a timeout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutGroup {
+    pub name: String,
+    /// The hosts of the group, every host for the default group.
+    pub network: Option<IpCidr>,
+    pub timeout: Duration,
+    /// The tries of the group, the ones of the run when not given.
+    pub tries: Option<u8>,
+}
+
+impl fmt::Display for TimeoutGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.network {
+            Some(network) => write!(f, "{}={network}", self.name)?,
+            None => f.write_str("default")?,
+        }
+        write!(f, ":{}", self.timeout.as_millis())?;
+        match self.tries {
+            Some(tries) => write!(f, ":{tries}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The groups of `--timeout-map`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeoutMap {
+    groups: Vec<TimeoutGroup>,
+}
+
+impl TimeoutMap {
+    /// The group of `ip`, by longest prefix match.
+    pub fn resolve(&self, ip: IpAddr) -> Option<&TimeoutGroup> {
+        self.groups
+            .iter()
+            .filter(|group| group.network.is_some_and(|network| network.contains(&ip)))
+            .max_by_key(|group| group.network.map(|network| network.network_length()))
+            .or_else(|| self.groups.iter().find(|group| group.network.is_none()))
+    }
+
+    pub fn groups(&self) -> &[TimeoutGroup] {
+        &self.groups
+    }
+}
+
+/// The timeout a host was scanned with, from its group of `--timeout-map`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostTimeout {
+    pub group: String,
+    pub timeout_ms: u64,
+    pub tries: u8,
+}
+
+impl HostTimeout {
+    /// The limits of `group`, the `tries` of the run standing in for missing ones.
+    pub fn new(group: &TimeoutGroup, tries: u8) -> Self {
+        Self {
+            group: group.name.clone(),
+            timeout_ms: group.timeout.as_millis() as u64,
+            tries: group.tries.unwrap_or(tries).max(1),
+        }
+    }
+}
+
+impl fmt::Display for TimeoutMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, group) in self.groups.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{group}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TimeoutMap {
+    type Err = String;
+
+    fn from_str(map: &str) -> Result<Self, String> {
+        let mut groups: Vec<TimeoutGroup> = Vec::new();
+        for entry in map.split(',').map(str::trim) {
+            let group = parse_group(entry).map_err(|e| format!("{entry:?}: {e}"))?;
+            if let Some(other) = groups
+                .iter()
+                .find(|other| other.name == group.name || other.network == group.network)
+            {
+                let clash = if other.name == group.name {
+                    format!("the group {} is given twice", group.name)
+                } else {
+                    format!("{} and {} are the same network", other.name, group.name)
+                };
+                return Err(format!("{entry:?}: {clash}"));
+            }
+            groups.push(group);
+        }
+        Ok(Self { groups })
+    }
+}
+
+/// Parses `name=network:timeout[:tries]` or `default:timeout[:tries]`.
+fn parse_group(entry: &str) -> Result<TimeoutGroup, String> {
+    let (name, network, limits) = match entry.strip_prefix("default:") {
+        Some(limits) => ("default", None, limits),
+        None => {
+            let (name, spec) = entry
+                .split_once('=')
+                .ok_or("expected name=network:timeout or default:timeout")?;
+            let valid = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if name.is_empty() || !valid || name == "default" {
+                return Err(format!("{name:?} is not a group name"));
+            }
+            // IPv6 networks have colons too, the limits start after the prefix.
+            let start = spec.find('/').unwrap_or(0);
+            let colon = spec[start..]
+                .find(':')
+                .ok_or("expected a timeout after the network")?;
+            let (network, limits) = (&spec[..start + colon], &spec[start + colon + 1..]);
+            let network = IpCidr::from_str(network)
+                .map_err(|_| format!("{network:?} is not a network, like 10.0.0.0/8"))?;
+            (name, Some(network), limits)
+        }
+    };
+
+    let (timeout, tries) = match limits.split_once(':') {
+        Some((timeout, tries)) => (timeout, Some(tries)),
+        None => (limits, None),
+    };
+    let timeout = match timeout.parse::<u64>() {
+        Ok(timeout) if timeout > 0 => Duration::from_millis(timeout),
+        _ => return Err(format!("{timeout:?} is not a timeout in milliseconds")),
+    };
+    let tries = match tries.map(str::parse::<u8>) {
+        Some(Ok(tries)) if tries > 0 => Some(tries),
+        Some(_) => return Err(String::from("the tries are a number from 1 to 255")),
+        None => None,
+    };
+    Ok(TimeoutGroup {
+        name: name.to_owned(),
+        network,
+        timeout,
+        tries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeoutMap;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn group(map: &TimeoutMap, ip: &str) -> Option<String> {
+        map.resolve(ip.parse::<IpAddr>().unwrap())
+            .map(|group| group.name.clone())
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let map: TimeoutMap =
+            "lan=10.0.0.0/8:300,lab=10.1.0.0/16:50:3,vpn=2001:db8::/32:2000,default:1000"
+                .parse()
+                .unwrap();
+        assert_eq!(group(&map, "10.1.2.3").as_deref(), Some("lab"));
+        assert_eq!(group(&map, "10.2.0.1").as_deref(), Some("lan"));
+        assert_eq!(group(&map, "2001:db8::1").as_deref(), Some("vpn"));
+        assert_eq!(group(&map, "192.0.2.1").as_deref(), Some("default"));
+
+        let lab = map.resolve("10.1.0.1".parse().unwrap()).unwrap();
+        assert_eq!(lab.timeout, Duration::from_millis(50));
+        assert_eq!(lab.tries, Some(3));
+        assert_eq!(lab.to_string(), "lab=10.1.0.0/16:50:3");
+
+        // Hosts outside every group keep the timeout of the run.
+        let map: TimeoutMap = "lan=10.0.0.0/8:300".parse().unwrap();
+        assert_eq!(group(&map, "192.0.2.1"), None);
+        // A single address is a network of one.
+        let map: TimeoutMap = "gw=10.0.0.1:20,lan=10.0.0.0/8:300".parse().unwrap();
+        assert_eq!(group(&map, "10.0.0.1").as_deref(), Some("gw"));
+    }
+
+    #[test]
+    fn malformed_maps_are_refused() {
+        for (map, error) in [
+            ("lan=10.0.0.0/8", "expected a timeout"),
+            ("10.0.0.0/8:300", "expected name=network"),
+            ("lan=10.0.0.0/33:300", "is not a network"),
+            ("lan=10.0.0.0/8:fast", "is not a timeout"),
+            ("lan=10.0.0.0/8:0", "is not a timeout"),
+            ("lan=10.0.0.0/8:300:0", "the tries"),
+            ("l an=10.0.0.0/8:300", "is not a group name"),
+            ("a=10.0.0.0/8:300,a=10.1.0.0/16:300", "given twice"),
+            ("a=10.0.0.0/8:300,b=10.0.0.0/8:500", "the same network"),
+            ("default:300,default:500", "given twice"),
+        ] {
+            let parsed = map.parse::<TimeoutMap>();
+            assert!(
+                parsed.as_ref().is_err_and(|e| e.contains(error)),
+                "{}: {:?}",
+                map,
+                parsed
+            );
+        }
+    }
+}
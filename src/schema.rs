@@ -0,0 +1,354 @@
+//! The version of the JSON reports, see `--schema` and `--schema-version`.
+//!
+//! Every report starts with the `schema_version` it was written with. The
+//! version only goes up for a breaking change, a field renamed, removed or
+//! of another type, adding a field isn't one. When it goes up the previous
+//! version is still written for one more release with `--schema-version`,
+//! back to [`OLDEST_SCHEMA_VERSION`].
+//!
+//! The JSON Schema of every version is kept in `schema/` at the root of the
+//! repository, which `--schema` prints, to validate the reports against.
+#![allow(clippy::module_name_repetitions)]
+
+use serde_derive::Serialize;
+use std::fmt;
+
+/// The version of the reports written by default.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The oldest version `--schema-version` still writes.
+pub const OLDEST_SCHEMA_VERSION: u32 = 1;
+
+/// The JSON Schema of every version written.
+const SCHEMAS: [(u32, &str); 1] = [(1, include_str!("../schema/report.v1.json"))];
+
+/// A version of the reports which is written, the current one by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct SchemaVersion(u32);
+
+impl SchemaVersion {
+    pub fn get(self) -> u32 {
+        self.0
+    }
+
+    /// The JSON Schema of the reports of this version.
+    pub fn schema(self) -> &'static str {
+        SCHEMAS
+            .iter()
+            .find(|(version, _)| *version == self.0)
+            .map(|(_, schema)| *schema)
+            .expect("Every version written has a schema.")
+    }
+}
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        Self(SCHEMA_VERSION)
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses a `--schema-version` argument, one of the versions still written.
+pub fn parse_schema_version(input: &str) -> Result<SchemaVersion, String> {
+    let version: u32 = input
+        .parse()
+        .map_err(|_| format!("Invalid schema version {input:?}."))?;
+    if (OLDEST_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) {
+        return Ok(SchemaVersion(version));
+    }
+    Err(if OLDEST_SCHEMA_VERSION == SCHEMA_VERSION {
+        format!("Only the schema version {SCHEMA_VERSION} is written, not {version}.")
+    } else {
+        format!(
+            "Only the schema versions {OLDEST_SCHEMA_VERSION} to {SCHEMA_VERSION} are written, not {version}."
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_schema_version, SchemaVersion, SCHEMA_VERSION};
+    use crate::address::{ResolutionError, Target, Unresolved};
+    use crate::egress::EgressReport;
+    use crate::family::{Family, FamilyDecision, Reason};
+    use crate::groups::group_hosts;
+    use crate::hints::ProtocolHint;
+    use crate::input::HostOrder;
+    use crate::probe::{ProbeStep, ServiceGuess};
+    use crate::report::{HostReport, PortConfidence, PortProbe, ScanReport, ScanStats, SkipReason};
+    use crate::rescan::{PortProvenance, PortState};
+    use crate::resources::ResourceUsage;
+    use crate::scanner::{Confidence, Shard, TryOutcome};
+    use crate::scripts::nmap::PortService;
+    use crate::scripts::{Phase, ScriptRun};
+    use crate::split::{split_report, IndexedPart, OutputSplit, SplitIndex};
+    use serde_json::Value;
+    use std::net::SocketAddr;
+
+    fn schema() -> Value {
+        serde_json::from_str(SchemaVersion::default().schema()).unwrap()
+    }
+
+    /// Checks `value` against `schema`, for the subset of JSON Schema the
+    /// schemas are written with. Unlike JSON Schema, objects can't have
+    /// properties the schema doesn't know of, so that none goes undocumented.
+    fn validate(root: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/$defs/");
+            return validate(root, &root["$defs"][name], value, path);
+        }
+        if let Some(options) = schema["oneOf"].as_array() {
+            let matching = options
+                .iter()
+                .filter(|option| validate(root, option, value, path).is_ok())
+                .count();
+            return match matching {
+                1 => Ok(()),
+                _ => Err(format!("{path} matches {matching} of its options")),
+            };
+        }
+        if schema
+            .get("const")
+            .is_some_and(|constant| constant != value)
+        {
+            return Err(format!("{path} isn't {}", schema["const"]));
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return Err(format!("{path} has the unknown value {value}"));
+            }
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(kind) => vec![kind.as_str()],
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let kind = match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(number) if number.is_f64() => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        // Integers are numbers too.
+        let typed = types.contains(&kind) || (kind == "integer" && types.contains(&"number"));
+        if !types.is_empty() && !typed {
+            return Err(format!("{path} is a {kind}, not a {}", types.join(" or ")));
+        }
+        if let Some(minimum) = schema["minimum"].as_i64() {
+            if value.as_i64().is_some_and(|number| number < minimum) {
+                return Err(format!("{path} is below {minimum}"));
+            }
+        }
+
+        if let Value::Object(fields) = value {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !fields.contains_key(required) {
+                    return Err(format!("{path} has no {required}"));
+                }
+            }
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, field) in fields {
+                    let property = properties
+                        .get(name)
+                        .ok_or_else(|| format!("{path}.{name} isn't in the schema"))?;
+                    validate(root, property, field, &format!("{path}.{name}"))?;
+                }
+            }
+        }
+        if let (Value::Array(items), Some(item)) = (value, schema.get("items")) {
+            for (index, value) in items.iter().enumerate() {
+                validate(root, item, value, &format!("{path}[{index}]"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn target(ip: &str, hostname: Option<&str>) -> Target {
+        Target {
+            ip: ip.parse().unwrap(),
+            hostnames: hostname.into_iter().map(str::to_owned).collect(),
+            sources: vec![hostname.unwrap_or(ip).to_owned()],
+            ports: None,
+        }
+    }
+
+    fn script_run(script: &str, error: Option<&str>, phase: Phase) -> ScriptRun {
+        ScriptRun {
+            script: script.to_owned(),
+            attempts: 1,
+            output: "done\n".to_owned(),
+            error: error.map(str::to_owned),
+            retried: Vec::new(),
+            phase,
+        }
+    }
+
+    /// A report with most of what a scan can find.
+    fn representative_report() -> ScanReport {
+        let targets = [
+            target("192.0.2.1", Some("example.test")),
+            target("2001:db8::1", None),
+        ];
+        let open: Vec<SocketAddr> = ["192.0.2.1:443", "192.0.2.1:22", "[2001:db8::1]:22"]
+            .iter()
+            .map(|socket| socket.parse().unwrap())
+            .collect();
+        let mut report = ScanReport::new(&targets, &open, HostOrder::Input);
+        report.run_id = Some("engagement-1".to_owned());
+
+        let host = &mut report.hosts[0];
+        host.name = Some("example.test".to_owned());
+        host.notes = vec!["The lab router".to_owned()];
+        host.filtered = vec![8080];
+        host.provenance = vec![
+            PortProvenance {
+                port: 22,
+                state: PortState::Open,
+                rescanned: false,
+                run_id: Some("engagement-0".to_owned()),
+            },
+            PortProvenance {
+                port: 8080,
+                state: PortState::Filtered,
+                rescanned: true,
+                run_id: Some("engagement-1".to_owned()),
+            },
+        ];
+        host.services = vec![PortService {
+            port: 22,
+            protocol: "tcp".to_owned(),
+            service: Some("ssh".to_owned()),
+            product: Some("OpenSSH".to_owned()),
+            version: None,
+        }];
+        host.probes = vec![PortProbe {
+            port: 443,
+            service_guess: ServiceGuess {
+                service: Some("https".to_owned()),
+                probe: ProbeStep::TlsClientHello,
+                evidence: "TLS handshake record".to_owned(),
+                protocol_hint: Some(ProtocolHint::Tls),
+                first_bytes: None,
+            },
+        }];
+        host.confidence = vec![PortConfidence {
+            port: 443,
+            label: Confidence::Flaky,
+            tries: vec![TryOutcome::TimedOut, TryOutcome::Open],
+        }];
+        host.scripts = vec![
+            script_run("audit.sh", None, Phase::Pre),
+            script_run("default", Some("Exit code = 1"), Phase::Post),
+        ];
+        host.family = Some(FamilyDecision {
+            hostname: "example.test".to_owned(),
+            family: Family::Ipv4,
+            address: "192.0.2.1".parse().unwrap(),
+            reason: Reason::FirstResponse,
+        });
+        report.hosts.push(HostReport::skipped(
+            &target("192.0.2.2", None),
+            SkipReason::PreScanFailed,
+        ));
+
+        report.groups = group_hosts(&report.hosts);
+        report.unresolved = vec![Unresolved {
+            name: "gone.test".to_owned(),
+            file: None,
+            error: ResolutionError::NotFound,
+        }];
+        report.stats = Some(ScanStats {
+            shards: vec![Shard { index: 1, count: 2 }],
+            sockets: 2000,
+            open_ports: 3,
+            resources: Some(ResourceUsage {
+                peak_open_descriptors: Some(24),
+                peak_rss_bytes: Some(8 << 20),
+                user_cpu_ms: Some(120),
+                system_cpu_ms: None,
+                samples: 3,
+            }),
+        });
+        report.duplicate_sockets = Some(2);
+        report
+    }
+
+    #[test]
+    fn supported_versions_are_parsed() {
+        assert_eq!(parse_schema_version("1"), Ok(SchemaVersion(1)));
+        assert_eq!(
+            parse_schema_version("2"),
+            Err("Only the schema version 1 is written, not 2.".to_owned())
+        );
+        assert!(parse_schema_version("0").is_err());
+        assert!(parse_schema_version("v1").is_err());
+        assert_eq!(SchemaVersion::default().get(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn schema_is_of_its_version() {
+        let schema = schema();
+        assert_eq!(schema["$defs"]["SchemaVersion"]["const"], SCHEMA_VERSION);
+    }
+
+    // Renaming or removing a field of the report breaks the tools reading it,
+    // changing this snapshot has to come with a new schema version.
+    #[test]
+    fn report_matches_its_snapshot() {
+        let report = serde_json::to_value(representative_report()).unwrap();
+        let snapshot: Value =
+            serde_json::from_str(include_str!("../fixtures/schema/report.json")).unwrap();
+        assert_eq!(report, snapshot);
+    }
+
+    #[test]
+    fn reports_follow_the_schema() {
+        let schema = schema();
+        let report = serde_json::to_value(representative_report()).unwrap();
+        assert_eq!(validate(&schema, &schema, &report, "report"), Ok(()));
+
+        let open = ["192.0.2.9:443".parse().unwrap()];
+        let egress =
+            EgressReport::new("192.0.2.9", "192.0.2.9".parse().unwrap(), &[80, 443], &open);
+        let egress = serde_json::to_value(egress).unwrap();
+        assert_eq!(validate(&schema, &schema, &egress, "report"), Ok(()));
+
+        let mut split = representative_report();
+        let mut parts = Vec::new();
+        for (hosts, json) in split_report(&mut split, OutputSplit::Hosts(1)) {
+            let part: Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(validate(&schema, &schema, &part, "part"), Ok(()));
+            parts.push(IndexedPart {
+                file: format!("report.{:04}.json", parts.len() + 1),
+                hosts,
+            });
+        }
+        let index = SplitIndex {
+            schema_version: SchemaVersion::default(),
+            run_id: split.run_id,
+            split: OutputSplit::Hosts(1).to_string(),
+            parts,
+        };
+        let index = serde_json::to_value(index).unwrap();
+        assert_eq!(validate(&schema, &schema, &index, "index"), Ok(()));
+
+        let mut renamed = report;
+        let host = renamed["hosts"][0].as_object_mut().unwrap();
+        let ports = host.remove("ports").unwrap();
+        host.insert("open_ports".to_owned(), ports);
+        assert_eq!(
+            validate(&schema, &schema["$defs"]["ScanReport"], &renamed, "report"),
+            Err("report.hosts[0] has no ports".to_owned())
+        );
+    }
+}
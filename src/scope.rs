@@ -0,0 +1,140 @@
+//! The allowlist of `--scope`, keeping every scan inside the engagement scope.
+//!
+//! The file holds one entry per line, `#` starts a comment:
+//! - an IP address or CIDR allows the addresses it holds,
+//! - anything else is a hostname suffix, `example.com` allows the targets
+//!   resolved from `example.com` and all of its subdomains. A leading `*.`
+//!   is accepted too.
+//!
+//! The check runs on the resolved addresses, a hostname which doesn't match
+//! a suffix has to resolve inside one of the CIDRs.
+use cidr_utils::cidr::IpCidr;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope {
+    cidrs: Vec<IpCidr>,
+    suffixes: Vec<String>,
+}
+
+impl Scope {
+    /// Reads the scope file at `path`.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut scope = Self::default();
+        for (number, line) in content.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            if let Ok(cidr) = IpCidr::from_str(entry) {
+                scope.cidrs.push(cidr);
+                continue;
+            }
+
+            let suffix = normalize(entry.trim_start_matches("*."));
+            let valid = suffix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_');
+            if suffix.is_empty() || !valid {
+                return Err(format!(
+                    "Line {}: {entry:?} is neither a CIDR nor a hostname suffix.",
+                    number + 1
+                ));
+            }
+            scope.suffixes.push(suffix);
+        }
+
+        if scope.cidrs.is_empty() && scope.suffixes.is_empty() {
+            return Err(String::from("The scope is empty."));
+        }
+        Ok(scope)
+    }
+
+    /// Whether `ip`, resolved from `hostnames`, is in scope.
+    pub fn allows(&self, ip: IpAddr, hostnames: &[String]) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(&ip))
+            || hostnames
+                .iter()
+                .any(|hostname| self.allows_hostname(hostname))
+    }
+
+    fn allows_hostname(&self, hostname: &str) -> bool {
+        let hostname = normalize(hostname);
+        self.suffixes.iter().any(|suffix| {
+            hostname == *suffix
+                || hostname
+                    .strip_suffix(suffix.as_str())
+                    .is_some_and(|subdomain| subdomain.ends_with('.'))
+        })
+    }
+}
+
+/// Hostnames are compared case insensitively, without the root dot.
+fn normalize(hostname: &str) -> String {
+    hostname
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scope;
+    use std::net::IpAddr;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn cidrs_and_addresses_are_allowed() {
+        let scope =
+            Scope::parse("# Engagement 42\n10.0.0.0/24\n\n192.168.1.7 # gateway\n2001:db8::/64\n")
+                .unwrap();
+
+        assert!(scope.allows(ip("10.0.0.200"), &[]));
+        assert!(scope.allows(ip("192.168.1.7"), &[]));
+        assert!(scope.allows(ip("2001:db8::1"), &[]));
+        assert!(!scope.allows(ip("10.0.1.1"), &[]));
+        assert!(!scope.allows(ip("192.168.1.8"), &[]));
+        assert!(!scope.allows(ip("2001:db9::1"), &[]));
+    }
+
+    #[test]
+    fn hostname_suffixes_match_whole_labels() {
+        let scope = Scope::parse("example.com\n*.corp.test.\n").unwrap();
+        let allows = |hostname: &str| scope.allows(ip("203.0.113.1"), &[hostname.to_owned()]);
+
+        assert!(allows("example.com"));
+        assert!(allows("WWW.Example.com."));
+        assert!(allows("a.b.corp.test"));
+        assert!(allows("corp.test"));
+        assert!(!allows("badexample.com"));
+        assert!(!allows("example.com.evil.test"));
+        // Hostnames outside the suffixes are held to the CIDRs.
+        assert!(!scope.allows(ip("203.0.113.1"), &[]));
+    }
+
+    #[test]
+    fn invalid_entries_are_refused() {
+        assert_eq!(
+            Scope::parse("10.0.0.0/8\n10.0.0.1/8\n"),
+            Err(String::from(
+                "Line 2: \"10.0.0.1/8\" is neither a CIDR nor a hostname suffix."
+            ))
+        );
+        assert!(Scope::parse("exa mple.com").is_err());
+        assert_eq!(
+            Scope::parse("# nothing yet\n"),
+            Err(String::from("The scope is empty."))
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The cap on the child processes running at once, see `--max-children`.
+//!
+//! Scripts, the runs of nmap and the commands of `--port-hook` all take a
+//! slot of [`CHILDREN`] before they are spawned and give it back once they
+//! are reaped, so their waits never pile up past the cap whichever of them
+//! run at the same time. A child waiting for a slot blocks the thread
+//! which starts it, never the scanner: children are only run from threads
+//! of their own or once the scan is over.
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// The slots of every child process of the run.
+pub static CHILDREN: ChildSlots = ChildSlots::new(usize::MAX);
+
+/// A counting semaphore of child processes.
+#[derive(Debug)]
+pub struct ChildSlots {
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    running: usize,
+    peak: usize,
+}
+
+impl ChildSlots {
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                limit,
+                running: 0,
+                peak: 0,
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Sets how many children run at once, at least one. The children
+    /// already running over a lower limit finish first.
+    pub fn set_limit(&self, limit: usize) {
+        self.lock().limit = limit.max(1);
+        self.freed.notify_all();
+    }
+
+    /// Waits for a free slot, held until the slot is dropped.
+    pub fn acquire(&self) -> ChildSlot<'_> {
+        let mut state = self.lock();
+        while state.running >= state.limit {
+            state = self
+                .freed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state.running += 1;
+        state.peak = state.peak.max(state.running);
+        ChildSlot(self)
+    }
+
+    /// How many children run right now.
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    /// The most children which ran at once.
+    pub fn peak(&self) -> usize {
+        self.lock().peak
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A slot of [`ChildSlots`], given back when dropped.
+#[derive(Debug)]
+pub struct ChildSlot<'a>(&'a ChildSlots);
+
+impl Drop for ChildSlot<'_> {
+    fn drop(&mut self) {
+        self.0.lock().running -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChildSlots;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn slots_are_capped() {
+        let slots = ChildSlots::new(3);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..12 {
+                scope.spawn(|| {
+                    let _slot = slots.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(slots.peak(), 3);
+        assert_eq!(slots.running(), 0);
+    }
+
+    #[test]
+    fn lowered_limit_lets_the_running_children_finish() {
+        let slots = ChildSlots::new(2);
+        let first = slots.acquire();
+        let second = slots.acquire();
+        slots.set_limit(1);
+        drop(first);
+        assert_eq!(slots.running(), 1);
+
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                let _third = slots.acquire();
+                slots.running()
+            });
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(slots.running(), 1);
+            drop(second);
+            assert_eq!(waiting.join().unwrap(), 1);
+        });
+
+        slots.set_limit(0);
+        let _slot = slots.acquire();
+    }
+}
@@ -67,31 +67,143 @@
 //! And when there is only `{{ip}}` and `{{port}}` is in the format, only those
 //! will be replaced with the arguments from the scan.
 //!
+//! Both variants can also use `{{hints}}`, replaced with the protocol hints
+//! of the open ports found with `--probe-all` as `port:hint` pairs separated
+//! with commas, like `22:ssh,443:tls`, see [`crate::hints`]. It's empty
+//! without `--probe-all`.
+//!
+//! `{{hostname}}` is replaced with the canonical name of the host, picked
+//! among its names by `--prefer-names` and `--aliases`, see [`crate::alias`].
+//! It's the ip when the host wasn't given any name.
+//!
 //! This makes it easy to run a system installed command like `nmap`, and give
 //! any kind of arguments to it.
 //!
+//! A command longer than [`MAX_COMMAND_LEN`] gets its consecutive ports
+//! collapsed into nmap style ranges, like `80-89`, and when it's still too
+//! long the ports are split over as many runs as needed, whose outputs are
+//! put together. `{{hints}}` then only lists the ports of each run.
+//!
 //! If the format is different, the script will be silently discarded and will
 //! not run. With the `Debug` option it's possible to see where it goes wrong.
+//!
+//! ## Requirements
+//!
+//! Script files can list what they need to run in two more optional fields:
+//!
+//! - `requires_bins = ["python3", "nmap"]`, binaries which have to be found
+//!   on the `PATH` (or at the given path when they contain a `/`).
+//! - `min_version = "2.3.0"`, the oldest RustScan version the script works with.
+//!
+//! The requirements are checked once, before the scan begins, by
+//! [`check_scripts`]. Scripts whose requirements aren't met are disabled
+//! instead of failing for every host later, or abort the run with
+//! `--strict-scripts`.
+//!
+//! ## Conditions
+//!
+//! Script files can also narrow down the hosts they run against, on top of
+//! the tags selecting them:
+//!
+//! - `requires_hint = ["http", "tls"]`, one of the open ports has to be
+//!   hinted as one of those protocols. Hints come from `--probe-all`, see
+//!   [`crate::hints`], without it no host meets this condition.
+//! - `min_open_ports = 3`, how many ports have to be open at least.
+//!
+//! They are checked against the results of every host by
+//! [`ScriptFile::unmet_condition`] before the script runs. Hosts which don't
+//! meet them skip the script, which is only mentioned in verbose mode.
+//!
+//! ## Retries
+//!
+//! A failed script run, one that exits with a non zero code, is run again
+//! for the same host. Script files set how in three more optional fields,
+//! which default to `--script-retries`, `--script-retry-delay` and
+//! `--no-retry-codes`:
+//!
+//! - `retries = 2`, how many times a failed run is retried.
+//! - `retry_delay = 1000`, the milliseconds to wait before a retry.
+//! - `no_retry_codes = [2]`, exit codes which mean retrying won't help.
+//!
+//! A script which times out through a wrapper like `timeout 60 nmap ...`
+//! exits with the code of the wrapper and is retried like any other failure.
+//! Only the last attempt is kept in the report, see [`ScriptRun`].
+//!
+//! ## Phases
+//!
+//! Scripts run after the scan of a host by default. Setting up for the
+//! scan, like adding a route or logging an audit event, is done by scripts
+//! of the pre phase, which run against every host before any of its ports
+//! is probed:
+//!
+//! - `phase = "pre"`, or `"post"` for the default.
+//! - `on_fail = "skip"` drops the host from the scan when the script fails,
+//!   `"warn"`, the default, only warns and scans it anyway.
+//!
+//! The scripts of a phase run in the order of their file names, see
+//! [`split_phases`]. No port is known to be open before the scan, so
+//! `{{port}}` is the count of ports planned for the host in the pre phase,
+//! `{{hints}}` is empty and the conditions above aren't checked.
+//!
+//! ## `--nmap-args`
+//!
+//! Extra arguments for the embedded nmap script, see [`nmap`]. They don't
+//! apply to custom scripts.
+//!
+//! ## `--port-hook`
+//!
+//! Commands run once per matching open port rather than per host, with the
+//! same placeholders and a timeout, see [`port_hooks`].
+//!
+//! ## `--max-children`
+//!
+//! The scripts, nmap and the port hooks share a cap on the processes they
+//! run at once, see [`children`].
+//!
+//! ## `--pipeline-scripts`
+//!
+//! The scripts of a host start as soon as its scan is over rather than once
+//! every host was scanned, the scan slowing down while many of them run,
+//! see [`pipeline`].
 
 #![allow(clippy::module_name_repetitions)]
 
-use crate::input::ScriptsRequired;
+pub mod children;
+
+pub mod nmap;
+
+pub mod pipeline;
+
+pub mod port_hooks;
+
+use crate::hints::{self, ProtocolHint};
+use crate::input::{PortRange, ScriptsRequired};
 use anyhow::{anyhow, Result};
 use log::debug;
 use serde_derive::{Deserialize, Serialize};
 use std::convert::TryInto;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{self, prelude::*};
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::string::ToString;
+use std::thread;
+use std::time::Duration;
 use subprocess::{Exec, ExitStatus};
 use text_placeholder::Template;
 
+/// The environment variable giving spawned scripts the run id, see
+/// `--run-id`.
+pub const RUN_ID_VAR: &str = "RUSTSCAN_RUN_ID";
+
 static DEFAULT: &str = r#"tags = ["core_approved", "RustScan", "default"]
 developer = [ "RustScan", "https://github.com/RustScan" ]
 ports_separator = ","
 call_format = "nmap -vvv -p {{port}} {{ip}}"
+requires_bins = ["nmap"]
 "#;
 
 #[cfg(not(tarpaulin_include))]
@@ -143,6 +255,38 @@ pub fn init_scripts(scripts: &ScriptsRequired) -> Result<Vec<ScriptFile>> {
     Ok(scripts_to_run)
 }
 
+/// The outcome of checking the requirements of a script.
+#[derive(Debug, Clone)]
+pub struct ScriptCheck {
+    pub script: ScriptFile,
+    /// Every requirement which isn't met, empty when the script can run.
+    pub unmet: Vec<String>,
+}
+
+impl ScriptCheck {
+    pub fn is_ready(&self) -> bool {
+        self.unmet.is_empty()
+    }
+
+    /// The script path, or `default` for the embedded nmap script.
+    pub fn name(&self) -> String {
+        self.script.name()
+    }
+}
+
+/// Checks the requirements of every script, looking binaries up in the
+/// directories listed in `path`, formatted like the `PATH` variable.
+pub fn check_scripts(scripts: Vec<ScriptFile>, path: Option<&OsStr>) -> Vec<ScriptCheck> {
+    scripts
+        .into_iter()
+        .map(|script| {
+            let unmet = script.unmet_requirements(path);
+            debug!("Script requirements {:?} unmet {:?}", &script.path, &unmet);
+            ScriptCheck { script, unmet }
+        })
+        .collect()
+}
+
 pub fn parse_scripts(scripts: Vec<PathBuf>) -> Vec<ScriptFile> {
     let mut parsed_scripts: Vec<ScriptFile> = Vec::with_capacity(scripts.len());
     for script in scripts {
@@ -154,6 +298,55 @@ pub fn parse_scripts(scripts: Vec<PathBuf>) -> Vec<ScriptFile> {
     parsed_scripts
 }
 
+/// The longest command handed to a script or nmap, in bytes. `cmd.exe` takes
+/// up to 8191 characters, Linux 128 KiB in the single argument of `sh -c`.
+pub const MAX_COMMAND_LEN: usize = 8_000;
+
+/// Splits the sorted `ports` into the port lists of as few runs as possible,
+/// each with the ports it holds, keeping the commands `fits` accepts. The
+/// ports are all listed when they fit in one run, collapsed into ranges
+/// otherwise. A single range which doesn't fit still gets its own run.
+pub fn port_batches(
+    ports: &[u16],
+    separator: &str,
+    fits: impl Fn(&str, &[u16]) -> bool,
+) -> Vec<(String, Vec<u16>)> {
+    let listed = ports
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(separator);
+    if fits(&listed, ports) {
+        return vec![(listed, ports.to_vec())];
+    }
+
+    let mut batches = Vec::new();
+    let mut batch = String::new();
+    let mut batch_ports: Vec<u16> = Vec::new();
+    for range in PortRange::from_ports(ports).ranges {
+        let range_str = PortRange {
+            ranges: vec![range],
+        }
+        .to_string();
+        let (previous_len, previous_ports) = (batch.len(), batch_ports.len());
+        if !batch.is_empty() {
+            batch.push_str(separator);
+        }
+        batch.push_str(&range_str);
+        batch_ports.extend(range.0..=range.1);
+        if previous_len > 0 && !fits(&batch, &batch_ports) {
+            batch.truncate(previous_len);
+            let rest = batch_ports.split_off(previous_ports);
+            batches.push((std::mem::replace(&mut batch, range_str), batch_ports));
+            batch_ports = rest;
+        }
+    }
+    if !batch.is_empty() {
+        batches.push((batch, batch_ports));
+    }
+    batches
+}
+
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct Script {
@@ -177,19 +370,37 @@ pub struct Script {
 
     // The format how we want the script to run.
     call_format: Option<String>,
+
+    // Protocol hints of the open ports, for the {{hints}} placeholder.
+    hints: Vec<(u16, ProtocolHint)>,
+
+    // The run id, for the {{run_id}} placeholder and RUSTSCAN_RUN_ID.
+    run_id: Option<String>,
+
+    // The canonical name of the host, for the {{hostname}} placeholder.
+    hostname: Option<String>,
+
+    // The ports planned for the host, {{port}} in the pre phase.
+    planned_ports: Option<usize>,
 }
 
 #[derive(Serialize)]
 struct ExecPartsScript {
     script: String,
     ip: String,
+    hostname: String,
     port: String,
+    hints: String,
+    run_id: String,
 }
 
 #[derive(Serialize)]
 struct ExecParts {
     ip: String,
+    hostname: String,
     port: String,
+    hints: String,
+    run_id: String,
 }
 
 impl Script {
@@ -210,69 +421,126 @@ impl Script {
             ports_separator,
             tags,
             call_format,
+            hints: Vec::new(),
+            run_id: None,
+            hostname: None,
+            planned_ports: None,
         }
     }
 
-    // Some variables get changed before read, and compiler throws warning on warn(unused_assignments)
-    #[allow(unused_assignments)]
+    /// Sets the protocol hints of the open ports given with `{{hints}}`.
+    #[must_use]
+    pub fn with_hints(mut self, hints: Vec<(u16, ProtocolHint)>) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    /// Sets the run id given with `{{run_id}}` and in `RUSTSCAN_RUN_ID`.
+    #[must_use]
+    pub fn with_run_id(mut self, run_id: Option<String>) -> Self {
+        self.run_id = run_id;
+        self
+    }
+
+    /// Sets the canonical name of the host given with `{{hostname}}`.
+    #[must_use]
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Runs the script before the scan, with `{{port}}` being the count of
+    /// ports planned for the host.
+    #[must_use]
+    pub fn with_planned_ports(mut self, count: usize) -> Self {
+        self.planned_ports = Some(count);
+        self
+    }
+
     pub fn run(self) -> Result<String> {
         debug!("run self {:?}", &self);
 
-        let separator = self.ports_separator.unwrap_or_else(|| ",".into());
-
-        let mut ports_str = self
-            .open_ports
-            .iter()
-            .map(ToString::to_string)
-            .collect::<Vec<String>>()
-            .join(&separator);
-        if let Some(port) = self.trigger_port {
-            ports_str = port;
+        let mut output = String::new();
+        for to_run in self.commands()? {
+            debug!("\nScript format to run {}", to_run);
+            output.push_str(&execute_script(&to_run, self.run_id.as_deref())?);
         }
+        Ok(output)
+    }
 
-        let mut final_call_format = String::new();
-        if let Some(call_format) = self.call_format {
-            final_call_format = call_format;
-        } else {
+    /// The commands to run, more than one when the open ports don't fit in
+    /// [`MAX_COMMAND_LEN`].
+    pub fn commands(&self) -> Result<Vec<String>> {
+        let Some(call_format) = &self.call_format else {
             return Err(anyhow!("Failed to parse execution format."));
+        };
+        let default_template: Template = Template::new(call_format);
+        let fill = |ports_str: String, open_ports: &[u16]| -> Result<String> {
+            let hints: Vec<(u16, ProtocolHint)> = self
+                .hints
+                .iter()
+                .filter(|(port, _)| open_ports.binary_search(port).is_ok())
+                .cloned()
+                .collect();
+            let hints = hints::placeholder(&hints);
+            let run_id = self.run_id.clone().unwrap_or_default();
+            let hostname = self.hostname.clone().unwrap_or_else(|| self.ip.to_string());
+
+            if call_format.contains("{{script}}") {
+                let exec_parts_script: ExecPartsScript = ExecPartsScript {
+                    script: self.path.as_deref().unwrap().to_str().unwrap().to_string(),
+                    ip: self.ip.to_string(),
+                    hostname,
+                    port: ports_str,
+                    hints,
+                    run_id,
+                };
+                Ok(default_template.fill_with_struct(&exec_parts_script)?)
+            } else {
+                let exec_parts: ExecParts = ExecParts {
+                    ip: self.ip.to_string(),
+                    hostname,
+                    port: ports_str,
+                    hints,
+                    run_id,
+                };
+                Ok(default_template.fill_with_struct(&exec_parts)?)
+            }
+        };
+
+        let mut open_ports = self.open_ports.clone();
+        open_ports.sort_unstable();
+        if let Some(count) = self.planned_ports {
+            return Ok(vec![fill(count.to_string(), &[])?]);
         }
-        let default_template: Template = Template::new(&final_call_format);
-        let mut to_run = String::new();
-
-        if final_call_format.contains("{{script}}") {
-            let exec_parts_script: ExecPartsScript = ExecPartsScript {
-                script: self.path.unwrap().to_str().unwrap().to_string(),
-                ip: self.ip.to_string(),
-                port: ports_str,
-            };
-            to_run = default_template.fill_with_struct(&exec_parts_script)?;
-        } else {
-            let exec_parts: ExecParts = ExecParts {
-                ip: self.ip.to_string(),
-                port: ports_str,
-            };
-            to_run = default_template.fill_with_struct(&exec_parts)?;
+        if let Some(port) = &self.trigger_port {
+            return Ok(vec![fill(port.clone(), &open_ports)?]);
         }
-        debug!("\nScript format to run {}", to_run);
 
-        execute_script(&to_run)
+        let separator = self.ports_separator.as_deref().unwrap_or(",");
+        port_batches(&open_ports, separator, |ports_str, ports| {
+            fill(ports_str.to_owned(), ports).map_or(true, |to_run| to_run.len() <= MAX_COMMAND_LEN)
+        })
+        .into_iter()
+        .map(|(ports_str, ports)| fill(ports_str, &ports))
+        .collect()
     }
 }
 
 #[cfg(not(tarpaulin_include))]
-fn execute_script(script: &str) -> Result<String> {
+fn execute_script(script: &str, run_id: Option<&str>) -> Result<String> {
     debug!("\nScript arguments {}", script);
-    let process = Exec::shell(script);
+    let process = with_run_id(Exec::shell(script), run_id);
+    let _slot = children::CHILDREN.acquire();
     match process.capture() {
         Ok(c) => {
-            let es = match c.exit_status {
-                ExitStatus::Exited(c) => c.try_into().unwrap(),
-                ExitStatus::Signaled(c) => c.into(),
-                ExitStatus::Other(c) => c,
-                ExitStatus::Undetermined => -1,
-            };
+            let es = exit_code(c.exit_status);
             if es != 0 {
-                return Err(anyhow!("Exit code = {}", es));
+                return Err(ScriptExit {
+                    code: es,
+                    output: c.stdout_str(),
+                }
+                .into());
             }
             Ok(c.stdout_str())
         }
@@ -283,6 +551,133 @@ fn execute_script(script: &str) -> Result<String> {
     }
 }
 
+/// Gives the run id to the environment of `process`, when there is one.
+fn with_run_id(process: Exec, run_id: Option<&str>) -> Exec {
+    match run_id {
+        Some(run_id) => process.env(RUN_ID_VAR, run_id),
+        None => process,
+    }
+}
+
+/// The exit code of a finished process, -1 when it's unknown.
+fn exit_code(status: ExitStatus) -> i32 {
+    match status {
+        ExitStatus::Exited(c) => c.try_into().unwrap(),
+        ExitStatus::Signaled(c) => c.into(),
+        ExitStatus::Other(c) => c,
+        ExitStatus::Undetermined => -1,
+    }
+}
+
+/// The error of a script which exited with a non zero code.
+#[derive(Debug)]
+pub struct ScriptExit {
+    pub code: i32,
+    /// What the script printed before failing.
+    pub output: String,
+}
+
+impl fmt::Display for ScriptExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Exit code = {}", self.code)
+    }
+}
+
+impl std::error::Error for ScriptExit {}
+
+/// How the failed runs of a script are retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a failed run is retried.
+    pub retries: u32,
+    /// The pause before every retry.
+    pub delay: Duration,
+    /// Exit codes which mean the failure is permanent.
+    pub no_retry_codes: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay: Duration::from_secs(1),
+            no_retry_codes: Vec::new(),
+        }
+    }
+}
+
+/// A failed attempt of a script run which was retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptAttempt {
+    pub output: String,
+    pub error: String,
+}
+
+/// The outcome of a script run against a host, retries included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptRun {
+    /// The script path, or `default` for the embedded nmap script.
+    pub script: String,
+    /// How many times the script ran.
+    pub attempts: u32,
+    /// The output of the last attempt.
+    pub output: String,
+    /// Why the last attempt failed, None when it succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The attempts before the last one, which all failed.
+    #[serde(skip)]
+    pub retried: Vec<ScriptAttempt>,
+    /// When the script ran, only given for the pre phase.
+    #[serde(default, skip_serializing_if = "Phase::is_post")]
+    pub phase: Phase,
+}
+
+impl ScriptRun {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Calls `run` until it succeeds, fails with one of the permanent exit
+/// codes of `policy` or ran out of retries, sleeping between attempts.
+pub fn run_with_retries(
+    script: String,
+    policy: &RetryPolicy,
+    mut run: impl FnMut() -> Result<String>,
+) -> ScriptRun {
+    let mut retried: Vec<ScriptAttempt> = Vec::new();
+    loop {
+        let (output, error) = match run() {
+            Ok(output) => (output, None),
+            Err(e) => {
+                let exit = e.downcast_ref::<ScriptExit>();
+                let output = exit.map(|exit| exit.output.clone()).unwrap_or_default();
+                let permanent = exit.is_some_and(|exit| policy.no_retry_codes.contains(&exit.code));
+                if !permanent && retried.len() < policy.retries as usize {
+                    debug!("Script {} failed, retrying: {:#}", script, e);
+                    retried.push(ScriptAttempt {
+                        output,
+                        error: e.to_string(),
+                    });
+                    thread::sleep(policy.delay);
+                    continue;
+                }
+                (output, Some(e.to_string()))
+            }
+        };
+
+        return ScriptRun {
+            script,
+            attempts: retried.len() as u32 + 1,
+            output,
+            error,
+            retried,
+            phase: Phase::Post,
+        };
+    }
+}
+
 pub fn find_scripts(mut path: PathBuf) -> Result<Vec<PathBuf>> {
     path.push(".rustscan_scripts");
     if path.is_dir() {
@@ -292,6 +687,8 @@ pub fn find_scripts(mut path: PathBuf) -> Result<Vec<PathBuf>> {
             let entry = entry?;
             files_vec.push(entry.path());
         }
+        // The scripts of a phase run in this order.
+        files_vec.sort();
         Ok(files_vec)
     } else {
         Err(anyhow!("Can't find scripts folder {}", path.display()))
@@ -306,9 +703,137 @@ pub struct ScriptFile {
     pub port: Option<String>,
     pub ports_separator: Option<String>,
     pub call_format: Option<String>,
+    pub requires_bins: Option<Vec<String>>,
+    pub min_version: Option<String>,
+    pub retries: Option<u32>,
+    /// In milliseconds.
+    pub retry_delay: Option<u64>,
+    pub no_retry_codes: Option<Vec<i32>>,
+    pub requires_hint: Option<Vec<ProtocolHint>>,
+    pub min_open_ports: Option<usize>,
+    #[serde(default)]
+    pub phase: Phase,
+    #[serde(default)]
+    pub on_fail: OnFail,
+}
+
+/// When a script runs against a host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Before any port of the host is probed.
+    Pre,
+    /// After the scan, with the open ports.
+    #[default]
+    Post,
+}
+
+impl Phase {
+    pub fn is_post(&self) -> bool {
+        *self == Phase::Post
+    }
+}
+
+/// What a failed script of the pre phase does to its host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFail {
+    /// The host isn't scanned.
+    Skip,
+    /// The host is scanned anyway.
+    #[default]
+    Warn,
+}
+
+/// Splits `scripts` into the ones of the pre phase and the ones of the post
+/// phase, both in the order they were given.
+pub fn split_phases(scripts: Vec<ScriptFile>) -> (Vec<ScriptFile>, Vec<ScriptFile>) {
+    scripts
+        .into_iter()
+        .partition(|script| script.phase == Phase::Pre)
 }
 
 impl ScriptFile {
+    /// The retry policy of the script, its own fields override `defaults`.
+    pub fn retry_policy(&self, defaults: &RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries.unwrap_or(defaults.retries),
+            delay: self
+                .retry_delay
+                .map_or(defaults.delay, Duration::from_millis),
+            no_retry_codes: self
+                .no_retry_codes
+                .clone()
+                .unwrap_or_else(|| defaults.no_retry_codes.clone()),
+        }
+    }
+
+    /// The script path, or `default` for the embedded nmap script.
+    pub fn name(&self) -> String {
+        self.path
+            .as_ref()
+            .map_or_else(|| "default".to_owned(), |path| path.display().to_string())
+    }
+
+    /// Describes the condition of the script the host with `open_ports`,
+    /// whose protocols are `hints`, doesn't meet. None when it meets them all.
+    pub fn unmet_condition(
+        &self,
+        open_ports: &[u16],
+        hints: &[(u16, ProtocolHint)],
+    ) -> Option<String> {
+        if let Some(min_open_ports) = self.min_open_ports {
+            if open_ports.len() < min_open_ports {
+                return Some(format!(
+                    "has {} open port(s), the script needs {min_open_ports}",
+                    open_ports.len()
+                ));
+            }
+        }
+
+        if let Some(required) = &self.requires_hint {
+            let hinted = hints
+                .iter()
+                .any(|(port, hint)| open_ports.contains(port) && required.contains(hint));
+            if !hinted {
+                let required: Vec<String> = required.iter().map(ToString::to_string).collect();
+                return Some(format!(
+                    "has no open port hinted as {}",
+                    required.join(" or ")
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Describes every requirement of the script which isn't met.
+    pub fn unmet_requirements(&self, path: Option<&OsStr>) -> Vec<String> {
+        let mut unmet = Vec::new();
+
+        for bin in self.requires_bins.iter().flatten() {
+            if find_binary(bin, path).is_none() {
+                unmet.push(format!("requires `{bin}` which is not on the PATH"));
+            }
+        }
+
+        if let Some(min_version) = &self.min_version {
+            match (
+                parse_version(min_version),
+                parse_version(env!("CARGO_PKG_VERSION")),
+            ) {
+                (Some(required), Some(current)) if required > current => unmet.push(format!(
+                    "requires RustScan {min_version} or newer, this is {}",
+                    env!("CARGO_PKG_VERSION")
+                )),
+                (None, _) => unmet.push(format!("has an invalid min_version {min_version:?}")),
+                _ => {}
+            }
+        }
+
+        unmet
+    }
+
     fn new(script: PathBuf) -> Option<ScriptFile> {
         let real_path = script.clone();
         let mut lines_buf = String::new();
@@ -344,6 +869,52 @@ impl ScriptFile {
     }
 }
 
+/// Looks `bin` up like a shell would, directly when it contains a path
+/// separator and in every directory of `path` otherwise.
+pub(crate) fn find_binary(bin: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    if bin.contains(std::path::MAIN_SEPARATOR) || bin.contains('/') {
+        let bin = PathBuf::from(bin);
+        return is_executable(&bin).then_some(bin);
+    }
+
+    env::split_paths(path?)
+        .flat_map(|dir| {
+            let candidate = dir.join(bin);
+            if cfg!(windows) {
+                vec![candidate.with_extension("exe"), candidate]
+            } else {
+                vec![candidate]
+            }
+        })
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Parses a `major.minor.patch` version, missing parts count as 0.
+fn parse_version(version: &str) -> Option<[u64; 3]> {
+    let mut parsed = [0; 3];
+    let mut parts = version.trim().split('.');
+    for part in &mut parsed {
+        if let Some(number) = parts.next() {
+            *part = number.parse().ok()?;
+        }
+    }
+    parts.next().is_none().then_some(parsed)
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ScriptConfig {
     pub tags: Option<Vec<String>>,
@@ -367,7 +938,14 @@ impl ScriptConfig {
 
 #[cfg(test)]
 mod tests {
-    use super::{find_scripts, parse_scripts, Script, ScriptFile};
+    use super::{
+        check_scripts, find_scripts, parse_scripts, parse_version, run_with_retries, split_phases,
+        OnFail, Phase, RetryPolicy, Script, ScriptCheck, ScriptFile, ScriptRun, MAX_COMMAND_LEN,
+    };
+    use crate::hints::ProtocolHint;
+    use crate::input::parse_range;
+    use std::ffi::OsStr;
+    use std::time::Duration;
 
     // Function for testing only, it inserts static values into ip and open_ports
     // Doesn't use impl in case it's implemented in the super module at some point
@@ -482,4 +1060,330 @@ mod tests {
         // output has a newline at the end by default, .trim() trims it
         assert_eq!(output.trim(), "Total args passed to fixtures/.rustscan_scripts/test_script.pl : 2\nArg # 1 : 127.0.0.1\nArg # 2 : 80,8080");
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn hints_fill_their_placeholder() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo {{ip}} {{port}} [{{hints}}]".to_string());
+        let script = into_script(script_f.clone())
+            .with_hints(vec![(80, ProtocolHint::Http), (8080, ProtocolHint::Binary)]);
+        assert_eq!(
+            script.run().unwrap().trim(),
+            "127.0.0.1 80,8080 [80:http,8080:binary]"
+        );
+
+        let output = into_script(script_f).run().unwrap();
+        assert_eq!(output.trim(), "127.0.0.1 80,8080 []");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_id_is_given_to_scripts() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo {{run_id}} {{ip}} $RUSTSCAN_RUN_ID".to_string());
+        let script = into_script(script_f.clone()).with_run_id(Some("acme-0424".to_owned()));
+        assert_eq!(
+            script.commands().unwrap(),
+            ["echo acme-0424 127.0.0.1 $RUSTSCAN_RUN_ID"]
+        );
+        assert_eq!(
+            script.run().unwrap().trim(),
+            "acme-0424 127.0.0.1 acme-0424"
+        );
+
+        let output = into_script(script_f).run().unwrap();
+        assert_eq!(output.trim(), "127.0.0.1");
+    }
+
+    #[test]
+    fn hostname_is_given_to_scripts() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo {{hostname}} {{ip}}".to_string());
+        let script =
+            into_script(script_f.clone()).with_hostname(Some("web01.corp.local".to_owned()));
+        assert_eq!(
+            script.commands().unwrap(),
+            ["echo web01.corp.local 127.0.0.1"]
+        );
+        assert_eq!(
+            into_script(script_f).commands().unwrap(),
+            ["echo 127.0.0.1 127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn conditions_are_checked_against_the_host() {
+        let script = |header: &str| toml::from_str::<ScriptFile>(header).unwrap();
+        let any = script("");
+        let web = script(r#"requires_hint = ["http", "tls"]"#);
+        let busy = script("min_open_ports = 3");
+        let both = script("requires_hint = [\"http\"]\nmin_open_ports = 3");
+
+        let hints = [(22, ProtocolHint::Ssh), (443, ProtocolHint::Tls)];
+        // The open ports, their hints, and which of any, web, busy and both run.
+        type Case<'a> = (&'a [u16], &'a [(u16, ProtocolHint)], [bool; 4]);
+        let matrix: [Case; 5] = [
+            (&[], &[], [true, false, false, false]),
+            (&[22, 443], &hints, [true, true, false, false]),
+            (&[22, 80, 443], &hints, [true, true, true, false]),
+            (
+                &[22, 80, 443],
+                &[(80, ProtocolHint::Http)],
+                [true, true, true, true],
+            ),
+            // Only the open ports count, without --probe-all nothing is hinted.
+            (
+                &[22, 80, 8080],
+                &[(443, ProtocolHint::Tls)],
+                [true, false, true, false],
+            ),
+        ];
+        for (open, hints, runs) in matrix {
+            let outcome = [&any, &web, &busy, &both]
+                .map(|script| script.unmet_condition(open, hints).is_none());
+            assert_eq!(outcome, runs, "{:?} {:?}", open, hints);
+        }
+
+        assert_eq!(
+            both.unmet_condition(&[80], &[(80, ProtocolHint::Http)]),
+            Some("has 1 open port(s), the script needs 3".to_owned())
+        );
+        assert_eq!(
+            web.unmet_condition(&[22], &hints),
+            Some("has no open port hinted as http or tls".to_owned())
+        );
+        assert!(toml::from_str::<ScriptFile>(r#"requires_hint = ["gopher"]"#).is_err());
+    }
+
+    #[test]
+    fn long_port_lists_are_split() {
+        let command = |open_ports: Vec<u16>, hints| {
+            Script::build(
+                None,
+                "127.0.0.1".parse().unwrap(),
+                open_ports,
+                None,
+                Some(",".to_owned()),
+                None,
+                Some("nmap -p {{port}} {{ip}} # {{hints}}".to_owned()),
+            )
+            .with_hints(hints)
+            .commands()
+            .unwrap()
+        };
+
+        // Every other port, nothing collapses into a range.
+        let ports: Vec<u16> = (1..=10_000).map(|port| port * 2).collect();
+        let hints = vec![(2, ProtocolHint::Ssh), (20_000, ProtocolHint::Http)];
+        let commands = command(ports.clone(), hints);
+        assert!(commands.len() > 1);
+
+        let mut covered = Vec::new();
+        for command in &commands {
+            assert!(command.len() <= MAX_COMMAND_LEN, "{}", command.len());
+            let parts: Vec<&str> = command.split(' ').collect();
+            assert_eq!(parts[3], "127.0.0.1");
+            let ranges = parse_range(parts[2]).unwrap().ranges;
+            covered.extend(ranges.into_iter().flat_map(|(start, end)| start..=end));
+        }
+        assert_eq!(covered, ports);
+        // Every run only hints at its own ports.
+        assert!(commands[0].ends_with("# 2:ssh"));
+        assert!(commands.last().unwrap().ends_with("# 20000:http"));
+        assert!(commands[1].ends_with("# "));
+
+        let ports: Vec<u16> = (1..=10_000).collect();
+        assert_eq!(command(ports, vec![]), ["nmap -p 1-10000 127.0.0.1 # "]);
+        assert_eq!(
+            command(vec![80, 443], vec![]),
+            ["nmap -p 80,443 127.0.0.1 # "]
+        );
+    }
+
+    // Only the shim directory is searched, whatever is installed on the machine.
+    fn check_fixture(name: &str) -> ScriptCheck {
+        let script =
+            ScriptFile::new(format!("fixtures/requirements/.rustscan_scripts/{name}").into())
+                .unwrap();
+        check_scripts(vec![script], Some(OsStr::new("fixtures/requirements/bin"))).remove(0)
+    }
+
+    #[test]
+    fn parse_script_requirements() {
+        let check = check_fixture("needs_python.sh");
+        assert_eq!(
+            check.script.requires_bins,
+            Some(vec!["python3".to_string()])
+        );
+        assert_eq!(check.script.min_version, Some("2.0.0".to_string()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn script_with_met_requirements_is_ready() {
+        let check = check_fixture("needs_python.sh");
+        assert!(check.is_ready(), "{:?}", check.unmet);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn script_with_missing_binary_is_disabled() {
+        let check = check_fixture("needs_missing_bin.sh");
+        assert_eq!(
+            check.unmet,
+            vec!["requires `rustscan-missing-binary` which is not on the PATH".to_string()]
+        );
+    }
+
+    #[test]
+    fn script_for_newer_rustscan_is_disabled() {
+        let check = check_fixture("needs_newer_rustscan.sh");
+        assert!(!check.is_ready());
+        assert!(check.unmet[0].starts_with("requires RustScan 99.0.0 or newer"));
+    }
+
+    #[test]
+    fn missing_path_disables_binaries() {
+        let script =
+            ScriptFile::new("fixtures/requirements/.rustscan_scripts/needs_python.sh".into())
+                .unwrap();
+        assert_eq!(script.unmet_requirements(None).len(), 1);
+    }
+
+    #[test]
+    fn parse_script_versions() {
+        assert_eq!(parse_version("2.3.0"), Some([2, 3, 0]));
+        assert_eq!(parse_version("2.3"), Some([2, 3, 0]));
+        assert!(parse_version("3.0.0") > parse_version("2.10.1"));
+        assert_eq!(parse_version("2.x"), None);
+        assert_eq!(parse_version("1.2.3.4"), None);
+    }
+
+    // Runs the flaky fixture, which exits with `code` on its first `failures` runs.
+    fn run_flaky(name: &str, code: i32, failures: u32) -> ScriptRun {
+        let runs = std::env::temp_dir().join(format!("rustscan-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&runs);
+
+        let mut script_f = ScriptFile::new("fixtures/retries/flaky.sh".into()).unwrap();
+        script_f.call_format = Some(format!(
+            "bash {{{{script}}}} {} {code} {failures}",
+            runs.display()
+        ));
+        let retries = script_f.retry_policy(&RetryPolicy::default());
+        let script = into_script(script_f);
+        let run = run_with_retries("flaky".to_owned(), &retries, || script.clone().run());
+
+        let _ = std::fs::remove_file(&runs);
+        run
+    }
+
+    #[test]
+    fn parse_script_retry_policy() {
+        let script_f = ScriptFile::new("fixtures/retries/flaky.sh".into()).unwrap();
+        assert_eq!(
+            script_f.retry_policy(&RetryPolicy::default()),
+            RetryPolicy {
+                retries: 2,
+                delay: Duration::from_millis(10),
+                no_retry_codes: vec![3],
+            }
+        );
+
+        let defaults = RetryPolicy {
+            retries: 5,
+            ..RetryPolicy::default()
+        };
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        assert_eq!(script_f.retry_policy(&defaults), defaults);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_script_is_retried_until_it_succeeds() {
+        let run = run_flaky("succeeds", 1, 2);
+
+        assert!(run.succeeded(), "{:?}", run);
+        assert_eq!(run.attempts, 3);
+        assert_eq!(run.output, "run 3\n");
+        let retried: Vec<(&str, &str)> = run
+            .retried
+            .iter()
+            .map(|attempt| (attempt.output.as_str(), attempt.error.as_str()))
+            .collect();
+        assert_eq!(
+            retried,
+            [("run 1\n", "Exit code = 1"), ("run 2\n", "Exit code = 1")]
+        );
+
+        // Only the last attempt goes into the report.
+        assert_eq!(
+            serde_json::to_value(&run).unwrap(),
+            serde_json::json!({"script": "flaky", "attempts": 3, "output": "run 3\n"})
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failed_script_gives_up_after_its_retries() {
+        let run = run_flaky("gives-up", 1, 5);
+
+        assert_eq!(run.attempts, 3);
+        assert_eq!(run.output, "run 3\n");
+        assert_eq!(run.error.as_deref(), Some("Exit code = 1"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn permanent_exit_code_is_not_retried() {
+        let run = run_flaky("permanent", 3, 5);
+
+        assert_eq!(run.attempts, 1);
+        assert!(run.retried.is_empty());
+        assert_eq!(run.error.as_deref(), Some("Exit code = 3"));
+    }
+
+    fn pre_scan_fixture(name: &str) -> ScriptFile {
+        ScriptFile::new(format!("fixtures/pre_scan/{name}").into()).unwrap()
+    }
+
+    #[test]
+    fn scripts_are_split_by_phase_in_order() {
+        let scripts = ["30-report.sh", "20-route.sh", "10-audit.sh"]
+            .iter()
+            .map(|name| pre_scan_fixture(name))
+            .collect();
+        let (pre, post) = split_phases(scripts);
+
+        let phases: Vec<(String, OnFail)> = pre
+            .iter()
+            .map(|script| (script.name(), script.on_fail))
+            .collect();
+        assert_eq!(
+            phases,
+            [
+                ("fixtures/pre_scan/20-route.sh".to_owned(), OnFail::Skip),
+                ("fixtures/pre_scan/10-audit.sh".to_owned(), OnFail::Warn),
+            ]
+        );
+        assert_eq!(post.len(), 1);
+        assert_eq!(post[0].phase, Phase::Post);
+    }
+
+    #[test]
+    fn pre_phase_port_is_the_planned_count() {
+        let script = into_script(pre_scan_fixture("10-audit.sh"));
+        assert_eq!(
+            script.clone().with_planned_ports(1000).commands().unwrap(),
+            ["bash fixtures/pre_scan/10-audit.sh 127.0.0.1 1000"]
+        );
+        assert_eq!(
+            script.commands().unwrap(),
+            ["bash fixtures/pre_scan/10-audit.sh 127.0.0.1 80,8080"]
+        );
+    }
 }
@@ -0,0 +1,679 @@
+//! Hands the open ports over to nmap with extra user arguments.
+//!
+//! The arguments given with `--nmap-args` are split like a POSIX shell would
+//! but never go through one: nmap is spawned with the resulting argv. The
+//! options selecting the ports and the targets or writing the outputs are
+//! RustScan's and are rejected, and so are targets: nmap only scans the
+//! hosts RustScan scanned. The option table of nmap tells the values of its
+//! options from targets, and its options from the abbreviations of others.
+use super::ScriptExit;
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde_derive::Serialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use subprocess::{Exec, ExitStatus};
+
+/// Whether an nmap option takes a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    None,
+    /// Glued to the option, or the next argument.
+    Required,
+    /// Glued to the option only.
+    Optional,
+}
+
+/// The short options of nmap, out of its getopt string
+/// `46Ab:D:d::e:Ffg:hIi:M:m:nO::o:P::p:qRrS:s:T:Vv::`.
+const SHORT_OPTIONS: [(char, Value); 28] = [
+    ('4', Value::None),
+    ('6', Value::None),
+    ('A', Value::None),
+    ('b', Value::Required),
+    ('D', Value::Required),
+    ('d', Value::Optional),
+    ('e', Value::Required),
+    ('F', Value::None),
+    ('f', Value::None),
+    ('g', Value::Required),
+    ('h', Value::None),
+    ('I', Value::None),
+    ('i', Value::Required),
+    ('M', Value::Required),
+    ('m', Value::Required),
+    ('n', Value::None),
+    ('O', Value::Optional),
+    ('o', Value::Required),
+    ('P', Value::Optional),
+    ('p', Value::Required),
+    ('q', Value::None),
+    ('R', Value::None),
+    ('r', Value::None),
+    ('S', Value::Required),
+    ('s', Value::Required),
+    ('T', Value::Required),
+    ('V', Value::None),
+    ('v', Value::Optional),
+];
+
+/// The long options of nmap, which it also takes with a single dash and
+/// spelled with `_` instead of `-`, or abbreviated.
+const LONG_OPTIONS: [(&str, Value); 98] = [
+    ("adler32", Value::None),
+    ("allports", Value::None),
+    ("append-output", Value::None),
+    ("badsum", Value::None),
+    ("data", Value::Required),
+    ("data-length", Value::Required),
+    ("data-string", Value::Required),
+    ("datadir", Value::Required),
+    ("debug", Value::Optional),
+    ("defeat-icmp-ratelimit", Value::None),
+    ("defeat-rst-ratelimit", Value::None),
+    ("deprecated-xml-osclass", Value::None),
+    ("disable-arp-ping", Value::None),
+    ("discovery-ignore-rst", Value::None),
+    ("dns-servers", Value::Required),
+    ("exclude", Value::Required),
+    ("exclude-ports", Value::Required),
+    ("excludefile", Value::Required),
+    ("ff", Value::None),
+    ("fuzzy", Value::None),
+    ("help", Value::None),
+    ("host-timeout", Value::Required),
+    ("iL", Value::Required),
+    ("iR", Value::Required),
+    ("iflist", Value::None),
+    ("initial-rtt-timeout", Value::Required),
+    ("ip-options", Value::Required),
+    ("log-errors", Value::None),
+    ("max-hostgroup", Value::Required),
+    ("max-os-tries", Value::Required),
+    ("max-parallelism", Value::Required),
+    ("max-rate", Value::Required),
+    ("max-retries", Value::Required),
+    ("max-rtt-timeout", Value::Required),
+    ("max-scan-delay", Value::Required),
+    ("min-hostgroup", Value::Required),
+    ("min-parallelism", Value::Required),
+    ("min-rate", Value::Required),
+    ("min-rtt-timeout", Value::Required),
+    ("mtu", Value::Required),
+    ("no-stylesheet", Value::None),
+    ("noninteractive", Value::None),
+    ("nsock-engine", Value::Required),
+    ("oA", Value::Required),
+    ("oG", Value::Required),
+    ("oH", Value::Required),
+    ("oM", Value::Required),
+    ("oN", Value::Required),
+    ("oS", Value::Required),
+    ("oX", Value::Required),
+    ("open", Value::None),
+    ("osscan-guess", Value::None),
+    ("osscan-limit", Value::None),
+    ("packet-trace", Value::None),
+    ("port-ratio", Value::Required),
+    ("privileged", Value::None),
+    ("proxies", Value::Required),
+    ("proxy", Value::Required),
+    ("rH", Value::None),
+    ("randomize-hosts", Value::None),
+    ("reason", Value::None),
+    ("release-memory", Value::None),
+    ("resolve-all", Value::None),
+    ("resume", Value::Required),
+    ("route-dst", Value::Required),
+    ("scan-delay", Value::Required),
+    ("scanflags", Value::Required),
+    ("script", Value::Required),
+    ("script-args", Value::Required),
+    ("script-args-file", Value::Required),
+    ("script-help", Value::Required),
+    ("script-timeout", Value::Required),
+    ("script-trace", Value::None),
+    ("script-updatedb", Value::None),
+    ("send-eth", Value::None),
+    ("send-ip", Value::None),
+    ("servicedb", Value::Required),
+    ("sI", Value::Required),
+    ("source-port", Value::Required),
+    ("spoof-mac", Value::Required),
+    ("stats-every", Value::Required),
+    ("stylesheet", Value::Required),
+    ("system-dns", Value::None),
+    ("thc", Value::None),
+    ("timing", Value::Required),
+    ("top-ports", Value::Required),
+    ("traceroute", Value::None),
+    ("ttl", Value::Required),
+    ("unique", Value::None),
+    ("unprivileged", Value::None),
+    ("version", Value::None),
+    ("version-all", Value::None),
+    ("version-intensity", Value::Required),
+    ("version-light", Value::None),
+    ("version-trace", Value::None),
+    ("versiondb", Value::Required),
+    ("vv", Value::None),
+    ("webxml", Value::None),
+];
+/// The options selecting the ports or the targets, or writing outputs,
+/// RustScan passes those itself. `-m` is the old `-oM`.
+const CONTROLLED_SHORT: [char; 5] = ['F', 'i', 'm', 'o', 'p'];
+const CONTROLLED_LONG: [&str; 13] = [
+    "exclude-ports",
+    "iL",
+    "iR",
+    "oA",
+    "oG",
+    "oH",
+    "oM",
+    "oN",
+    "oS",
+    "oX",
+    "port-ratio",
+    "resume",
+    "top-ports",
+];
+
+/// Splits `input` into arguments the way a POSIX shell does, handling single
+/// and double quotes and backslash escapes, without expanding anything.
+pub fn split_args(input: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // Quotes produce an argument even when empty, like `''`.
+    let mut in_word = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(String::from("Unterminated single quote.")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => current.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err(String::from("Unterminated double quote.")),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(String::from("Unterminated double quote.")),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some('\n') => {}
+                    Some(c) => current.push(c),
+                    None => return Err(String::from("Trailing backslash.")),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// The long option `name` stands for, exactly or abbreviated, with its
+/// value. Nothing when it is no option of nmap.
+fn long_option(name: &str) -> Result<Option<(&'static str, Value)>, ()> {
+    let name = name.replace('_', "-");
+    if let Some(option) = LONG_OPTIONS.iter().find(|(long, _)| *long == name) {
+        return Ok(Some(*option));
+    }
+    let mut abbreviated = LONG_OPTIONS
+        .iter()
+        .filter(|(long, _)| long.starts_with(&name));
+    match (abbreviated.next(), abbreviated.next()) {
+        (Some(option), None) => Ok(Some(*option)),
+        (Some(_), Some(_)) => Err(()),
+        (None, _) => Ok(None),
+    }
+}
+
+fn short_option(c: char) -> Option<Value> {
+    SHORT_OPTIONS
+        .iter()
+        .find(|(short, _)| *short == c)
+        .map(|(_, value)| *value)
+}
+
+/// Rejects the arguments which would conflict with the ports, targets and
+/// outputs RustScan hands to nmap, and the targets. nmap reads its options
+/// like `getopt_long_only`: one dash is enough for a long option, a long
+/// option may be abbreviated, and short options which take no value are
+/// followed by other ones, like `-Pn` or `-sV`.
+pub fn validate_args(args: &[String]) -> Result<(), String> {
+    let conflict = |arg: &str| {
+        Err(format!(
+            "The nmap argument {arg:?} conflicts with the ports, targets or outputs RustScan controls."
+        ))
+    };
+    let target = |arg: &str| {
+        Err(format!(
+            "The nmap argument {arg:?} is a target, nmap only scans the hosts RustScan scanned."
+        ))
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            // Nothing but targets follows.
+            return args.next().map_or(Ok(()), |arg| target(arg));
+        }
+        let Some(name) = arg.strip_prefix('-').filter(|name| !name.is_empty()) else {
+            return target(arg);
+        };
+        let (long, single_dash) = match name.strip_prefix('-') {
+            Some(long) => (long, false),
+            None => (name, true),
+        };
+        let (long, glued) = match long.split_once('=') {
+            Some((long, _)) => (long, true),
+            None => (long, false),
+        };
+
+        let mut first = name.chars();
+        let short = first.next().and_then(short_option);
+        // A single dash and a single letter is always a short option.
+        let option = if single_dash && short.is_some() && first.as_str().is_empty() {
+            None
+        } else {
+            long_option(long).map_err(|()| {
+                format!("The nmap argument {arg:?} abbreviates several nmap options.")
+            })?
+        };
+
+        let takes = match option {
+            Some((long, _)) if CONTROLLED_LONG.contains(&long) => return conflict(arg),
+            Some((_, value)) => (!glued).then_some(value),
+            // Short options, one after the other until one takes a value,
+            // which is the rest of the argument when there is a rest.
+            None if single_dash && short.is_some() => {
+                let mut takes = None;
+                for (at, c) in name.char_indices() {
+                    if CONTROLLED_SHORT.contains(&c) {
+                        return conflict(arg);
+                    }
+                    match short_option(c) {
+                        Some(Value::None) => continue,
+                        Some(value) => {
+                            let rest = &name[at + c.len_utf8()..];
+                            takes = rest.is_empty().then_some(value);
+                        }
+                        // nmap refuses it.
+                        None => {}
+                    }
+                    break;
+                }
+                takes
+            }
+            // No option of nmap, which refuses it.
+            None => None,
+        };
+        if takes == Some(Value::Required) {
+            args.next();
+        }
+    }
+    Ok(())
+}
+
+/// The arguments given with `--nmap-args`, split and validated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NmapArgs(pub Vec<String>);
+
+/// Parses the `--nmap-args` value.
+pub fn parse_args(input: &str) -> Result<NmapArgs, String> {
+    let args = split_args(input)?;
+    validate_args(&args)?;
+    Ok(NmapArgs(args))
+}
+
+/// Builds the nmap argvs for `ip`, user arguments going before the target.
+/// IPv6 targets are given bare, without brackets, along with the `-6` nmap
+/// needs to scan them. There is one argv unless the ports don't fit in
+/// [`super::MAX_COMMAND_LEN`], see [`super::port_batches`].
+pub fn argvs(ip: IpAddr, ports: &[u16], args: &[String], xml: Option<&Path>) -> Vec<Vec<String>> {
+    let argv = |ports: String| {
+        let mut argv = vec!["nmap".to_owned(), "-vvv".to_owned(), "-p".to_owned(), ports];
+        if ip.is_ipv6() && !args.iter().any(|arg| arg == "-6") {
+            argv.push("-6".to_owned());
+        }
+        argv.extend(args.iter().cloned());
+        if let Some(xml) = xml {
+            argv.push("-oX".to_owned());
+            argv.push(xml.display().to_string());
+        }
+        argv.push(ip.to_string());
+        argv
+    };
+
+    super::port_batches(ports, ",", |ports, _| {
+        command_len(&argv(ports.to_owned())) <= super::MAX_COMMAND_LEN
+    })
+    .into_iter()
+    .map(|(ports, _)| argv(ports))
+    .collect()
+}
+
+/// The length of `argv` on a command line, spaces between the arguments.
+pub fn command_len(argv: &[String]) -> usize {
+    argv.iter().map(|arg| arg.len() + 1).sum()
+}
+
+/// Runs nmap with `argv`, no shell involved, and returns its output. The
+/// run id, when there is one, is in its environment.
+#[cfg(not(tarpaulin_include))]
+pub fn run(argv: &[String], run_id: Option<&str>) -> Result<String> {
+    debug!("\nNmap argv {:?}", argv);
+    let _slot = super::children::CHILDREN.acquire();
+    let capture = super::with_run_id(Exec::cmd(&argv[0]).args(&argv[1..]), run_id)
+        .capture()
+        .map_err(|error| anyhow!(error.to_string()))?;
+
+    match capture.exit_status {
+        ExitStatus::Exited(0) => Ok(capture.stdout_str()),
+        status => Err(anyhow::Error::new(ScriptExit {
+            code: super::exit_code(status),
+            output: capture.stdout_str(),
+        })
+        .context(format!("Nmap failed, exit status = {status:?}"))),
+    }
+}
+
+/// Where the XML output of the nmap run against `ip` is written, in `dir`.
+/// nmap often runs as root, `dir` is to be a [`crate::private_dir::PrivateDir`]
+/// so that nobody plants a symlink there or forges the results.
+pub fn xml_path(dir: &Path, ip: IpAddr) -> PathBuf {
+    let ip = ip.to_string().replace(':', "_");
+    dir.join(format!("{ip}.xml"))
+}
+
+/// The part of nmap's findings for a port which is folded into the JSON report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortService {
+    pub port: u16,
+    pub protocol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Reads the service and version of every port out of nmap's XML output.
+pub fn parse_services(xml: &str) -> Vec<PortService> {
+    let mut services = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find("<port ") {
+        rest = &rest[start..];
+        let end = rest.find("</port>").unwrap_or(rest.len());
+        let block = &rest[..end];
+        rest = &rest[end..];
+
+        let Some(port) = attribute(block, "portid").and_then(|port| port.parse().ok()) else {
+            continue;
+        };
+        let service = block.find("<service ").map(|start| &block[start..]);
+        services.push(PortService {
+            port,
+            protocol: attribute(block, "protocol").unwrap_or_else(|| "tcp".to_owned()),
+            service: service.and_then(|tag| attribute(tag, "name")),
+            product: service.and_then(|tag| attribute(tag, "product")),
+            version: service.and_then(|tag| attribute(tag, "version")),
+        });
+    }
+
+    services
+}
+
+/// Returns the value of the attribute `name` of the first tag in `tag`.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    let pattern = format!(" {name}=\"");
+    let start = tag.find(&pattern)? + pattern.len();
+    let end = start + tag[start..].find('"')?;
+
+    Some(
+        tag[start..end]
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{argvs, command_len, parse_args, parse_services, split_args, PortService};
+    use crate::input::parse_range;
+    use crate::scripts::MAX_COMMAND_LEN;
+    use std::path::Path;
+
+    fn split(input: &str) -> Vec<String> {
+        split_args(input).unwrap()
+    }
+
+    #[test]
+    fn split_plain_arguments() {
+        assert_eq!(
+            split(" -sV  -O\t--script vuln "),
+            ["-sV", "-O", "--script", "vuln"]
+        );
+        assert!(split("").is_empty());
+    }
+
+    #[test]
+    fn split_quoted_arguments() {
+        assert_eq!(split(r#"--script "a,b""#), ["--script", "a,b"]);
+        assert_eq!(
+            split("--script-args 'user=admin pass=secret'"),
+            ["--script-args", "user=admin pass=secret"]
+        );
+        assert_eq!(split(r#"--script="a b",c"#), ["--script=a b,c"]);
+        assert_eq!(split(r"a\ b"), ["a b"]);
+        assert_eq!(split(r#""say \"hi\"" '\n'"#), [r#"say "hi""#, r"\n"]);
+        assert_eq!(split("'' x"), ["", "x"]);
+    }
+
+    #[test]
+    fn split_rejects_unterminated_input() {
+        assert!(split_args("--script 'vuln").is_err());
+        assert!(split_args(r#"--script "vuln"#).is_err());
+        assert!(split_args(r"-sV \").is_err());
+    }
+
+    #[test]
+    fn controlled_arguments_are_rejected() {
+        for args in [
+            "-p 80",
+            "-p80",
+            "-p-",
+            "--top-ports 100",
+            "-F",
+            "-oX out.xml",
+            "-oA scan",
+            "-oN=out",
+            "-iL hosts.txt",
+            "-iLhosts.txt",
+            "-iR100",
+            "--exclude-ports=22",
+            "--top 10",
+            "-top-ports 10",
+            "--exclude-p 22",
+            "-resum scan.log",
+        ] {
+            assert!(parse_args(args).is_err(), "{:?} was accepted", args);
+        }
+        assert!(parse_args("-sV -O -Pn --script vuln --open").is_ok());
+        assert!(parse_args("-r -e eth0 --exclude 10.0.0.1 --reason -T4").is_ok());
+    }
+
+    #[test]
+    fn targets_are_rejected() {
+        for args in [
+            "-sV 10.0.0.1",
+            "--script vuln 10.0.0.1",
+            "-Pn -- 10.0.0.1",
+            "--open scanme.nmap.org",
+            "-n -",
+        ] {
+            let error = parse_args(args).unwrap_err();
+            assert!(error.contains("is a target"), "{:?}: {}", args, error);
+        }
+        // The values of the options aren't targets.
+        assert!(parse_args("-S 10.0.0.2 -D 10.0.0.3 -sI zombie.lan --").is_ok());
+        assert!(parse_args("--script=vuln --max-retries 2 -T 4").is_ok());
+    }
+
+    #[test]
+    fn long_options_take_a_single_dash() {
+        assert!(parse_args("-open -privileged -sV").is_ok());
+        assert!(parse_args("-script vuln -max_retries 2").is_ok());
+        assert!(parse_args("-oX out.xml").is_err());
+        // Several options start with it, nmap refuses it too.
+        let error = parse_args("--max-r 2").unwrap_err();
+        assert!(error.contains("abbreviates several"), "{}", error);
+    }
+
+    #[test]
+    fn user_arguments_go_before_the_target() {
+        let args = parse_args(r#"-sV --script "a,b""#).unwrap().0;
+        let argvs = argvs(
+            "127.0.0.1".parse().unwrap(),
+            &[22, 80],
+            &args,
+            Some(Path::new("/tmp/scan.xml")),
+        );
+
+        assert_eq!(
+            argvs,
+            [[
+                "nmap",
+                "-vvv",
+                "-p",
+                "22,80",
+                "-sV",
+                "--script",
+                "a,b",
+                "-oX",
+                "/tmp/scan.xml",
+                "127.0.0.1"
+            ]]
+        );
+    }
+
+    #[test]
+    fn ipv6_targets_are_given_bare() {
+        let ip = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            argvs(ip, &[443], &[], None),
+            [["nmap", "-vvv", "-p", "443", "-6", "2001:db8::1"]]
+        );
+
+        let args = parse_args("-6 -sV").unwrap().0;
+        assert_eq!(
+            argvs(ip, &[443], &args, None),
+            [["nmap", "-vvv", "-p", "443", "-6", "-sV", "2001:db8::1"]]
+        );
+    }
+
+    #[test]
+    fn long_port_lists_are_split() {
+        let ip = "127.0.0.1".parse().unwrap();
+        // Every other port, nothing collapses into a range.
+        let ports: Vec<u16> = (1..=10_000).map(|port| port * 2).collect();
+        let split = argvs(ip, &ports, &["-sV".to_owned()], None);
+        assert!(split.len() > 1);
+
+        let mut covered = Vec::new();
+        for argv in &split {
+            assert!(
+                command_len(argv) <= MAX_COMMAND_LEN,
+                "{}",
+                command_len(argv)
+            );
+            assert_eq!(argv[argv.len() - 2..], ["-sV", "127.0.0.1"]);
+            let ranges = parse_range(&argv[3]).unwrap().ranges;
+            covered.extend(ranges.into_iter().flat_map(|(start, end)| start..=end));
+        }
+        assert_eq!(covered, ports);
+
+        let ports: Vec<u16> = (1..=10_000).collect();
+        assert_eq!(
+            argvs(ip, &ports, &[], None),
+            [["nmap", "-vvv", "-p", "1-10000", "127.0.0.1"]]
+        );
+    }
+
+    #[test]
+    fn services_are_read_from_xml() {
+        let xml = r#"<?xml version="1.0"?>
+<nmaprun><host><ports>
+<port protocol="tcp" portid="22"><state state="open"/><service name="ssh" product="OpenSSH" version="8.9p1 Ubuntu" method="probed"/></port>
+<port protocol="tcp" portid="8080"><state state="open"/><service name="http-proxy" product="&quot;Proxy&quot; &amp; co"/></port>
+<port protocol="udp" portid="53"><state state="open"/></port>
+</ports></host></nmaprun>"#;
+
+        assert_eq!(
+            parse_services(xml),
+            [
+                PortService {
+                    port: 22,
+                    protocol: "tcp".to_owned(),
+                    service: Some("ssh".to_owned()),
+                    product: Some("OpenSSH".to_owned()),
+                    version: Some("8.9p1 Ubuntu".to_owned()),
+                },
+                PortService {
+                    port: 8080,
+                    protocol: "tcp".to_owned(),
+                    service: Some("http-proxy".to_owned()),
+                    product: Some("\"Proxy\" & co".to_owned()),
+                    version: None,
+                },
+                PortService {
+                    port: 53,
+                    protocol: "udp".to_owned(),
+                    service: None,
+                    product: None,
+                    version: None,
+                },
+            ]
+        );
+    }
+}
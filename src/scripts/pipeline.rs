@@ -0,0 +1,278 @@
+//! Runs the scripts of every host as soon as its scan is over, see
+//! `--pipeline-scripts`.
+//!
+//! The scanner tells the hosts it's done with through its [`Hooks`], which
+//! put them in a bounded queue read by a few workers running their scripts.
+//! A full queue holds back the thread of the hooks, never the scan, and
+//! hooks which are behind hold back the launch of new probes, so that hosts
+//! never pile up faster than their scripts run.
+//! The children of the workers take slots of [`super::children::CHILDREN`]
+//! like any other. While many hosts run their scripts the [`Backpressure`]
+//! also keeps fewer probes in flight, so that the children don't fight the
+//! rest of the scan for sockets and CPU, and gives the scan its whole batch
+//! back once they are done.
+//!
+//! The results are kept per host rather than printed, so that they are
+//! told in the order of the report once the scan is over.
+use crate::scanner::{Hooks, HostSummary, ScanControl};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// How many scanned hosts wait for a worker at most.
+pub const QUEUE: usize = 16;
+
+enum Job {
+    Host(IpAddr, Vec<u16>),
+    Done,
+}
+
+/// Slows a scan down while many hosts run their scripts.
+#[derive(Debug)]
+pub struct Backpressure {
+    control: ScanControl,
+    batch_size: usize,
+    busy: usize,
+    state: Mutex<Pressure>,
+}
+
+#[derive(Debug, Default)]
+struct Pressure {
+    /// The hosts running their scripts.
+    active: usize,
+    slowdowns: usize,
+}
+
+impl Backpressure {
+    /// Halves the batch of the scans steered by `control`, of `batch_size`,
+    /// while `busy` hosts or more run their scripts.
+    pub fn new(control: ScanControl, batch_size: usize, busy: usize) -> Self {
+        Self {
+            control,
+            batch_size,
+            busy: busy.max(1),
+            state: Mutex::new(Pressure::default()),
+        }
+    }
+
+    /// A host starts its scripts, until the guard is dropped.
+    pub fn running(&self) -> Running<'_> {
+        let mut state = self.lock();
+        state.active += 1;
+        if state.active == self.busy {
+            self.control.limit_batch(Some(self.batch_size / 2));
+            state.slowdowns += 1;
+        }
+        Running(self)
+    }
+
+    /// How many times the scan was slowed down.
+    pub fn slowdowns(&self) -> usize {
+        self.lock().slowdowns
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pressure> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A host running its scripts, see [`Backpressure::running`].
+#[derive(Debug)]
+pub struct Running<'a>(&'a Backpressure);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        if state.active == self.0.busy {
+            self.0.control.limit_batch(None);
+        }
+        state.active -= 1;
+    }
+}
+
+/// The workers running the scripts of the scanned hosts, with `run`, which
+/// gives the results of a host and its open ports.
+pub struct Pipeline<R> {
+    queue: SyncSender<Job>,
+    workers: Vec<JoinHandle<()>>,
+    results: Arc<Mutex<HashMap<IpAddr, R>>>,
+    pressure: Arc<Backpressure>,
+}
+
+impl<R: Send + 'static> Pipeline<R> {
+    /// Starts `workers` workers, at least one, fed by a queue of `queue`
+    /// hosts.
+    pub fn start(
+        workers: usize,
+        queue: usize,
+        pressure: Backpressure,
+        run: impl Fn(IpAddr, Vec<u16>) -> R + Send + Sync + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let results = Arc::new(Mutex::new(HashMap::new()));
+        let pressure = Arc::new(pressure);
+        let run = Arc::new(run);
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let results = Arc::clone(&results);
+                let pressure = Arc::clone(&pressure);
+                let run = Arc::clone(&run);
+                thread::spawn(move || {
+                    while let Some((ip, ports)) = next_job(&receiver) {
+                        let _running = pressure.running();
+                        let result = run(ip, ports);
+                        lock(&results).insert(ip, result);
+                    }
+                })
+            })
+            .collect();
+        Self {
+            queue: sender,
+            workers,
+            results,
+            pressure,
+        }
+    }
+
+    /// The hooks feeding the scanned hosts to the workers.
+    pub fn hooks(&self) -> Hooks {
+        let queue = self.queue.clone();
+        Hooks::new().on_host_complete(move |summary| submit(&queue, summary))
+    }
+
+    /// Hands a scanned host to the workers, waiting for room in the
+    /// queue: call it off the thread of a scan, like the hooks are. Hosts
+    /// without open ports have no scripts to run.
+    pub fn completed(&self, summary: &HostSummary) -> anyhow::Result<()> {
+        submit(&self.queue, summary)
+    }
+
+    /// Waits for the scripts of the hosts handed so far, the results of
+    /// which are returned with how many times the scan was slowed down.
+    pub fn finish(self) -> (HashMap<IpAddr, R>, usize) {
+        // The hooks of the scanners may still hold the queue.
+        for _ in &self.workers {
+            let _ = self.queue.send(Job::Done);
+        }
+        for worker in self.workers {
+            // The host of a worker which panicked has no results, its
+            // scripts run again with the others.
+            let _ = worker.join();
+        }
+        let results = std::mem::take(&mut *lock(&self.results));
+        (results, self.pressure.slowdowns())
+    }
+}
+
+fn submit(queue: &SyncSender<Job>, summary: &HostSummary) -> anyhow::Result<()> {
+    if summary.open.is_empty() {
+        return Ok(());
+    }
+    let mut ports = summary.open.clone();
+    ports.sort_unstable();
+    ports.dedup();
+    queue
+        .send(Job::Host(summary.ip, ports))
+        .map_err(|_| anyhow::anyhow!("the script workers are gone"))
+}
+
+fn next_job(receiver: &Mutex<Receiver<Job>>) -> Option<(IpAddr, Vec<u16>)> {
+    match lock(receiver).recv() {
+        Ok(Job::Host(ip, ports)) => Some((ip, ports)),
+        Ok(Job::Done) | Err(_) => None,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backpressure, Pipeline};
+    use crate::scanner::{HostSummary, ScanControl};
+    use std::net::IpAddr;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn summary(ip: &str, open: &[u16]) -> HostSummary {
+        HostSummary {
+            ip: ip.parse().unwrap(),
+            open: open.to_vec(),
+            sockets: 3,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn busy_scripts_slow_the_scan_down() {
+        let control = ScanControl::default();
+        let pressure = Backpressure::new(control.clone(), 100, 2);
+        let first = pressure.running();
+        assert_eq!(control.batch_limit(), None);
+        let second = pressure.running();
+        assert_eq!(control.batch_limit(), Some(50));
+        let third = pressure.running();
+        drop(first);
+        assert_eq!(control.batch_limit(), Some(50));
+        drop(second);
+        assert_eq!(control.batch_limit(), None);
+        drop(third);
+        assert_eq!(pressure.slowdowns(), 1);
+
+        // The batch never goes down to nothing.
+        let pressure = Backpressure::new(control.clone(), 1, 0);
+        let _running = pressure.running();
+        assert_eq!(control.batch_limit(), Some(1));
+    }
+
+    #[test]
+    fn hosts_run_as_they_are_scanned() {
+        let control = ScanControl::default();
+        let (started, starts) = mpsc::channel::<IpAddr>();
+        let (release, released) = mpsc::channel::<()>();
+        let started = Mutex::new(started);
+        let released = Arc::new(Mutex::new(released));
+        let pipeline = Pipeline::start(
+            2,
+            1,
+            Backpressure::new(control.clone(), 10, 2),
+            move |ip, ports| {
+                started.lock().unwrap().send(ip).unwrap();
+                released.lock().unwrap().recv().unwrap();
+                (ip, ports)
+            },
+        );
+
+        pipeline
+            .completed(&summary("10.0.0.1", &[443, 22]))
+            .unwrap();
+        pipeline.completed(&summary("10.0.0.2", &[])).unwrap();
+        pipeline.completed(&summary("10.0.0.3", &[80])).unwrap();
+        let mut running = vec![starts.recv().unwrap(), starts.recv().unwrap()];
+        running.sort();
+        assert_eq!(
+            running,
+            [
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "10.0.0.3".parse().unwrap()
+            ]
+        );
+        // Both workers are busy, and so is the scan.
+        assert_eq!(control.batch_limit(), Some(5));
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+
+        let (results, slowdowns) = pipeline.finish();
+        assert_eq!(control.batch_limit(), None);
+        assert_eq!(slowdowns, 1);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[&ip], (ip, vec![22, 443]));
+    }
+}
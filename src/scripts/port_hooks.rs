@@ -0,0 +1,566 @@
+//! The per port commands of `--port-hook`.
+//!
+//! Scripts run once per host with all its open ports, a port hook runs once
+//! per open port it matches, like
+//! `tag=web cmd="gowitness single {{scheme}}://{{host}}:{{port}}"`. A hook is
+//! a list of `key=value` pairs separated by spaces, values with spaces being
+//! quoted:
+//!
+//! - `cmd`, the command template, run by the shell like the scripts are.
+//! - `ports=80,8000-8100`, the ports the hook runs on.
+//! - `tag=http,ssh`, the protocol hints of the ports it runs on, `web` being
+//!   `http` and `tls`. Hints come from `--probe-all`, see [`crate::hints`],
+//!   without it no port has one.
+//! - `name`, how the runs of the hook are reported, the program of `cmd` by
+//!   default.
+//! - `timeout=30s`, how long the command may take before it's killed with
+//!   the processes it started, `--port-hook-timeout` by default.
+//!
+//! A hook with both `ports` and `tag` runs on the ports matching either, one
+//! with neither on every open port. The templates get the placeholders of
+//! the scripts, `{{ip}}`, `{{port}}` and `{{run_id}}`, and a few of their
+//! own: `{{host}}` is the IP in brackets for IPv6, to go in URLs,
+//! `{{hint}}` the hint of the port, and `{{scheme}}` `https` for TLS ports,
+//! `http` otherwise.
+//!
+//! At most `--port-hook-concurrency` commands run at the same time, fewer
+//! when the other children of the run hold the slots of `--max-children`,
+//! see [`super::children`]. Every run is kept in the report, see
+//! [`PortHookRun`], with the path of what it wrote when the last line it
+//! printed names an existing file.
+use super::{exit_code, RUN_ID_VAR};
+use crate::hints::ProtocolHint;
+use crate::input::parse_range;
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde_derive::Serialize;
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use subprocess::{Popen, PopenConfig, Redirection};
+use text_placeholder::Template;
+
+/// The ports served over TLS when they have no hint.
+const TLS_PORTS: [u16; 4] = [443, 8443, 9443, 10443];
+
+/// A hook of `--port-hook`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortHook {
+    pub name: String,
+    pub command: String,
+    /// The ports of `ports=`, empty without it.
+    pub ports: Vec<u16>,
+    /// The hints of `tag=`, empty without it.
+    pub hints: Vec<ProtocolHint>,
+    /// The `timeout=` of the hook, None for `--port-hook-timeout`.
+    pub timeout: Option<Duration>,
+}
+
+/// An open port the hooks are matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookTarget {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub hint: Option<ProtocolHint>,
+}
+
+#[derive(Serialize)]
+struct HookParts {
+    ip: String,
+    host: String,
+    port: String,
+    scheme: String,
+    hint: String,
+    run_id: String,
+}
+
+impl HookParts {
+    fn new(target: &HookTarget, run_id: Option<&str>) -> Self {
+        let host = match target.ip {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{ip}]"),
+        };
+        let tls = match target.hint {
+            Some(hint) => hint == ProtocolHint::Tls,
+            None => TLS_PORTS.contains(&target.port),
+        };
+        Self {
+            ip: target.ip.to_string(),
+            host,
+            port: target.port.to_string(),
+            scheme: if tls { "https" } else { "http" }.to_owned(),
+            hint: target.hint.map(|hint| hint.to_string()).unwrap_or_default(),
+            run_id: run_id.unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+impl PortHook {
+    /// Whether the hook runs on `target`.
+    pub fn matches(&self, target: &HookTarget) -> bool {
+        if self.ports.is_empty() && self.hints.is_empty() {
+            return true;
+        }
+        self.ports.contains(&target.port)
+            || target.hint.is_some_and(|hint| self.hints.contains(&hint))
+    }
+
+    /// The command run on `target`.
+    pub fn command(&self, target: &HookTarget, run_id: Option<&str>) -> String {
+        Template::new(&self.command)
+            .fill_with_struct(&HookParts::new(target, run_id))
+            .unwrap_or_default()
+    }
+}
+
+/// Parses a `--port-hook` argument, `key=value` pairs separated by spaces.
+pub fn parse_port_hook(input: &str) -> Result<PortHook, String> {
+    let mut name = None;
+    let mut command = None;
+    let mut ports = Vec::new();
+    let mut hints = Vec::new();
+    let mut timeout = None;
+    for (key, value) in pairs(input)? {
+        match key.as_str() {
+            "name" => name = Some(value),
+            "cmd" => command = Some(value),
+            "ports" => {
+                let range = parse_range(&value).map_err(|e| e.to_string())?;
+                ports = range
+                    .ranges
+                    .iter()
+                    .flat_map(|&(start, end)| start..=end)
+                    .collect();
+            }
+            "tag" => {
+                for tag in value.split(',') {
+                    match tag {
+                        "web" => hints.extend([ProtocolHint::Http, ProtocolHint::Tls]),
+                        tag => hints.push(tag.parse()?),
+                    }
+                }
+            }
+            "timeout" => {
+                timeout = Some(
+                    humantime::parse_duration(&value)
+                        .map_err(|e| format!("Invalid port hook timeout {value:?}: {e}."))?,
+                );
+            }
+            key => {
+                return Err(format!(
+                    "Unknown port hook key {key:?}, expected cmd, ports, tag, name or timeout."
+                ))
+            }
+        }
+    }
+
+    let Some(command) = command.filter(|command| !command.trim().is_empty()) else {
+        return Err(
+            "The port hook has no command. Example: 'tag=web cmd=\"curl -sI {{scheme}}://{{host}}:{{port}}\"'."
+                .to_owned(),
+        );
+    };
+    // Every placeholder has to be known, an empty one would go unnoticed.
+    let example = HookTarget {
+        ip: IpAddr::from([127, 0, 0, 1]),
+        port: 80,
+        hint: None,
+    };
+    Template::new(&command)
+        .fill_with_struct_strict(&HookParts::new(&example, None))
+        .map_err(|_| {
+            format!(
+                "The port hook command {command:?} has an unknown placeholder, \
+                 expected {{{{ip}}}}, {{{{host}}}}, {{{{port}}}}, {{{{scheme}}}}, \
+                 {{{{hint}}}} or {{{{run_id}}}}."
+            )
+        })?;
+    let name = name.unwrap_or_else(|| {
+        let program = command.split_whitespace().next().unwrap_or_default();
+        Path::new(program).file_name().map_or_else(
+            || program.to_owned(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    });
+
+    Ok(PortHook {
+        name,
+        command,
+        ports,
+        hints,
+        timeout,
+    })
+}
+
+/// Splits `input` into its `key=value` pairs, the values in double or single
+/// quotes keeping their spaces.
+fn pairs(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else {
+            return Err(format!(
+                "Invalid port hook {input:?}, {rest:?} isn't a key=value pair."
+            ));
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("Invalid port hook {input:?}, {key:?} isn't a key."));
+        }
+        let (value, after) = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let Some((value, after)) = after[1..].split_once(quote) else {
+                    return Err(format!(
+                        "Invalid port hook {input:?}, the value of {key} is never closed by {quote}."
+                    ));
+                };
+                (value, after)
+            }
+            _ => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+        };
+        if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+            return Err(format!(
+                "Invalid port hook {input:?}, the value of {key} is followed by {after:?}."
+            ));
+        }
+        pairs.push((key.to_owned(), value.to_owned()));
+        rest = after.trim_start();
+    }
+    Ok(pairs)
+}
+
+/// The outcome of a hook run on an open port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortHookRun {
+    pub hook: String,
+    pub port: u16,
+    /// The command as it ran.
+    pub command: String,
+    /// None when the command timed out or didn't start.
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// The file the last line of the output named, when it exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_path: Option<PathBuf>,
+    /// Why the command didn't exit on its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What the command printed on stdout.
+    #[serde(skip)]
+    pub output: String,
+}
+
+impl PortHookRun {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Runs every hook on every target it matches, `concurrency` commands at a
+/// time. The runs come back in the order of the targets, then of the hooks.
+pub fn run_hooks(
+    hooks: &[PortHook],
+    targets: &[HookTarget],
+    default_timeout: Duration,
+    concurrency: usize,
+    run_id: Option<&str>,
+) -> Vec<(IpAddr, PortHookRun)> {
+    let jobs: Vec<(&HookTarget, &PortHook)> = targets
+        .iter()
+        .flat_map(|target| {
+            hooks
+                .iter()
+                .filter(move |hook| hook.matches(target))
+                .map(move |hook| (target, hook))
+        })
+        .collect();
+    let runs: Vec<Mutex<Option<PortHookRun>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&(target, hook)) = jobs.get(index) else {
+                    break;
+                };
+                debug!("Port hook {} on {}:{}", hook.name, target.ip, target.port);
+                *runs[index].lock().unwrap() =
+                    Some(run_hook(hook, target, default_timeout, run_id));
+            });
+        }
+    });
+
+    jobs.iter()
+        .zip(runs)
+        .filter_map(|((target, _), run)| Some((target.ip, run.into_inner().unwrap()?)))
+        .collect()
+}
+
+/// Runs `hook` on `target`, killing the command once the timeout is over.
+pub fn run_hook(
+    hook: &PortHook,
+    target: &HookTarget,
+    default_timeout: Duration,
+    run_id: Option<&str>,
+) -> PortHookRun {
+    let command = hook.command(target, run_id);
+    let timeout = hook.timeout.unwrap_or(default_timeout);
+    let started = Instant::now();
+    let (exit_code, output, error) = match execute(&command, timeout, run_id) {
+        Ok((code, output)) => (Some(code), output, None),
+        Err((output, e)) => (None, output, Some(e.to_string())),
+    };
+
+    PortHookRun {
+        hook: hook.name.clone(),
+        port: target.port,
+        output_path: output_path(&output),
+        command,
+        exit_code,
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+        output,
+    }
+}
+
+/// The shell running the commands, the one of `Exec::shell`.
+#[cfg(unix)]
+const SHELL: [&str; 2] = ["sh", "-c"];
+#[cfg(windows)]
+const SHELL: [&str; 2] = ["cmd.exe", "/c"];
+
+/// Commands are started one at a time, so that none inherits the pipe of
+/// another one and keeps its output open.
+static SPAWN: Mutex<()> = Mutex::new(());
+
+/// The exit code and stdout of `command`, or what it printed before timing
+/// out or failing.
+#[cfg(not(tarpaulin_include))]
+fn execute(
+    command: &str,
+    timeout: Duration,
+    run_id: Option<&str>,
+) -> std::result::Result<(i32, String), (String, anyhow::Error)> {
+    let mut config = PopenConfig {
+        stdout: Redirection::Pipe,
+        ..PopenConfig::default()
+    };
+    // The command gets a process group of its own, to be killed along with
+    // what it started.
+    #[cfg(unix)]
+    {
+        config.setpgid = true;
+    }
+    if let Some(run_id) = run_id {
+        let mut env = PopenConfig::current_env();
+        env.push((RUN_ID_VAR.into(), run_id.into()));
+        config.env = Some(env);
+    }
+    let argv = [SHELL[0], SHELL[1], command];
+    // Held until the command is reaped, killed or not.
+    let _slot = super::children::CHILDREN.acquire();
+    let spawned = {
+        let _spawn = SPAWN.lock().unwrap_or_else(PoisonError::into_inner);
+        Popen::create(&argv, config)
+    };
+    let mut popen = spawned.map_err(|e| (String::new(), anyhow!(e.to_string())))?;
+
+    let captured = popen
+        .communicate_start(None)
+        .limit_time(timeout)
+        .read_string();
+    match captured {
+        Ok((stdout, _)) => {
+            let status = popen
+                .wait()
+                .map_err(|e| (String::new(), anyhow!(e.to_string())))?;
+            Ok((exit_code(status), stdout.unwrap_or_default()))
+        }
+        Err(e) => {
+            let output = String::from_utf8_lossy(&e.capture.0.unwrap_or_default()).into_owned();
+            kill(&mut popen);
+            let error = if e.error.kind() == ErrorKind::TimedOut {
+                anyhow!("Timed out after {}", humantime::format_duration(timeout))
+            } else {
+                anyhow!(e.error.to_string())
+            };
+            Err((output, error))
+        }
+    }
+}
+
+/// Kills the process group of `popen`, then reaps it.
+fn kill(popen: &mut Popen) {
+    #[cfg(unix)]
+    if let Some(pid) = popen.pid().and_then(|pid| i32::try_from(pid).ok()) {
+        // SAFETY: kill has no preconditions, the group is the one of the
+        // command which isn't reaped yet.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    let _ = popen.kill();
+    let _ = popen.wait();
+}
+
+/// The file the last line of `output` names, when it exists.
+fn output_path(output: &str) -> Option<PathBuf> {
+    let last = output.lines().rev().find(|line| !line.trim().is_empty())?;
+    let path = PathBuf::from(last.trim());
+    path.exists().then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_port_hook, run_hooks, HookTarget, PortHook};
+    use crate::hints::ProtocolHint;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn target(ip: &str, port: u16, hint: Option<ProtocolHint>) -> HookTarget {
+        HookTarget {
+            ip: ip.parse().unwrap(),
+            port,
+            hint,
+        }
+    }
+
+    fn hook(spec: &str) -> PortHook {
+        parse_port_hook(spec).unwrap()
+    }
+
+    #[test]
+    fn hooks_are_parsed() {
+        let web = hook(r#"tag=web cmd="gowitness single {{scheme}}://{{host}}:{{port}}""#);
+        assert_eq!(web.name, "gowitness");
+        assert_eq!(
+            web.command,
+            "gowitness single {{scheme}}://{{host}}:{{port}}"
+        );
+        assert_eq!(web.hints, [ProtocolHint::Http, ProtocolHint::Tls]);
+        assert!(web.ports.is_empty());
+
+        let ssh = hook("name=audit ports=22,2222-2223 tag=ssh timeout=5s cmd='ssh-audit {{ip}}'");
+        assert_eq!(ssh.name, "audit");
+        assert_eq!(ssh.ports, [22, 2222, 2223]);
+        assert_eq!(ssh.hints, [ProtocolHint::Ssh]);
+        assert_eq!(ssh.timeout, Some(Duration::from_secs(5)));
+
+        assert_eq!(hook("cmd=/usr/bin/true").name, "true");
+    }
+
+    #[test]
+    fn invalid_hooks_are_rejected() {
+        for (spec, error) in [
+            ("tag=web", "has no command"),
+            ("cmd=\"echo {{address}}\"", "unknown placeholder"),
+            ("tag=gopher cmd=true", "Unknown protocol hint"),
+            ("ports=80-a cmd=true", "Invalid range format"),
+            ("port=80 cmd=true", "Unknown port hook key"),
+            ("cmd=\"echo {{ip}}", "never closed"),
+            ("cmd=\"echo\"x", "is followed by"),
+            ("cmd=true timeout=soon", "Invalid port hook timeout"),
+            ("echo", "isn't a key=value pair"),
+        ] {
+            let e = parse_port_hook(spec).unwrap_err();
+            assert!(e.contains(error), "{}: {}", spec, e);
+        }
+    }
+
+    #[test]
+    fn hooks_match_their_ports_or_hints() {
+        let web = hook("tag=web ports=8080 cmd=true");
+        assert!(web.matches(&target("10.0.0.1", 80, Some(ProtocolHint::Http))));
+        assert!(web.matches(&target("10.0.0.1", 8443, Some(ProtocolHint::Tls))));
+        assert!(web.matches(&target("10.0.0.1", 8080, None)));
+        assert!(!web.matches(&target("10.0.0.1", 22, Some(ProtocolHint::Ssh))));
+        assert!(!web.matches(&target("10.0.0.1", 80, None)));
+
+        assert!(hook("cmd=true").matches(&target("10.0.0.1", 22, None)));
+    }
+
+    #[test]
+    fn commands_are_filled_per_port() {
+        let web = hook(r#"cmd="curl {{scheme}}://{{host}}:{{port}} {{hint}} {{run_id}}""#);
+        let command = |ip, port, hint| web.command(&target(ip, port, hint), Some("r1"));
+        assert_eq!(
+            command("10.0.0.1", 80, Some(ProtocolHint::Http)),
+            "curl http://10.0.0.1:80 http r1"
+        );
+        assert_eq!(
+            command("::1", 8000, Some(ProtocolHint::Tls)),
+            "curl https://[::1]:8000 tls r1"
+        );
+        // Without a hint the well-known TLS ports are https.
+        assert_eq!(
+            command("10.0.0.1", 443, None),
+            "curl https://10.0.0.1:443  r1"
+        );
+    }
+
+    #[test]
+    fn every_matching_port_gets_a_run() {
+        let dir = std::env::temp_dir().join(format!("rustscan-port-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let shot = hook(&format!(
+            "ports=80,443 cmd=\"echo {{{{scheme}}}} > {dir}/{{{{port}}}}.txt && echo {dir}/{{{{port}}}}.txt\"",
+            dir = dir.display()
+        ));
+        let fail = hook("name=fail tag=ssh cmd='echo no; exit 3'");
+        let targets = [
+            target("127.0.0.1", 22, Some(ProtocolHint::Ssh)),
+            target("127.0.0.1", 80, None),
+            target("127.0.0.1", 443, None),
+        ];
+        let runs = run_hooks(&[shot, fail], &targets, Duration::from_secs(10), 2, None);
+
+        let ran: Vec<(IpAddr, &str, u16, Option<i32>)> = runs
+            .iter()
+            .map(|(ip, run)| (*ip, run.hook.as_str(), run.port, run.exit_code))
+            .collect();
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(
+            ran,
+            [
+                (localhost, "fail", 22, Some(3)),
+                (localhost, "echo", 80, Some(0)),
+                (localhost, "echo", 443, Some(0))
+            ]
+        );
+        assert_eq!(runs[0].1.output, "no\n");
+        assert_eq!(runs[0].1.output_path, None);
+        let written = runs[2].1.output_path.clone().unwrap();
+        assert_eq!(written, dir.join("443.txt"));
+        assert_eq!(std::fs::read_to_string(written).unwrap(), "https\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn slow_commands_are_killed() {
+        // The shell forks the sleep, which is killed along with it.
+        let slow = hook("timeout=200ms cmd='echo started; sleep 10; echo done'");
+        let fast = hook("name=fast cmd='echo fast'");
+        let started = std::time::Instant::now();
+        let runs = run_hooks(
+            &[slow, fast],
+            &[target("127.0.0.1", 80, None)],
+            Duration::from_secs(60),
+            2,
+            None,
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let run = &runs[0].1;
+        assert_eq!(run.exit_code, None);
+        assert_eq!(run.error.as_deref(), Some("Timed out after 200ms"));
+        assert_eq!(run.output, "started\n");
+        assert!(runs[1].1.succeeded());
+        assert_eq!(runs[1].1.output, "fast\n");
+    }
+}
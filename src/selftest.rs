@@ -0,0 +1,284 @@
+//! Checks the environment RustScan runs in end to end, see `rustscan selftest`.
+//!
+//! Every check goes through the code a real run uses: the open and closed
+//! ports are scanned by the [`Scanner`] on localhost, the name is resolved
+//! by the resolver of `--resolver`, the scripts are read from the scripts
+//! folder and nmap is looked up in `PATH` like the scripts look their
+//! binaries up. Nothing but localhost is probed, the resolver aside.
+use crate::address::{get_resolver, parse_address};
+use crate::benchmark::tune;
+use crate::input::{Opts, PortRange, ScanOrder};
+use crate::port_strategy::PortStrategy;
+use crate::scanner::Scanner;
+use crate::scripts::{find_binary, find_scripts, parse_scripts};
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+/// The check of one part of the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was found, why the check failed when it did.
+    pub detail: String,
+    /// How to fix a failed check.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl ToString) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.to_string(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl ToString, hint: impl ToString) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed { "PASS" } else { "FAIL" };
+        write!(f, "[{status}] {}: {}", self.name, self.detail)?;
+        match &self.hint {
+            Some(hint) => write!(f, "\n       hint: {hint}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs every check, resolving `name` and measuring up to `max_batch_size`
+/// connections at once.
+pub async fn run(opts: &Opts, name: &str, max_batch_size: usize) -> Vec<Check> {
+    let mut checks = vec![open_port(opts).await];
+    let (closed, closed_port) = closed_port(opts).await;
+    checks.push(closed);
+    checks.push(concurrency(closed_port, max_batch_size).await);
+    checks.push(resolver(opts, name));
+    checks.push(scripts(dirs::home_dir()));
+    checks.push(nmap(env::var_os("PATH").as_deref()));
+    checks
+}
+
+/// Scans `port` of localhost like a run of RustScan would.
+async fn scan(opts: &Opts, port: u16) -> Vec<SocketAddr> {
+    let range = PortRange::from_ports(&[port]);
+    Scanner::new(
+        &[IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        1,
+        Duration::from_millis(opts.timeout.into()),
+        opts.tries,
+        true,
+        PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+        true,
+        Vec::new(),
+        false,
+    )
+    .run()
+    .await
+}
+
+async fn open_port(opts: &Opts) -> Check {
+    const NAME: &str = "open port";
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            return Check::fail(
+                NAME,
+                format!("Could not listen on localhost: {e}"),
+                "Check that the loopback interface is up.",
+            )
+        }
+    };
+    let port = listener.local_addr().map_or(0, |addr| addr.port());
+    let open = scan(opts, port).await;
+    if open.iter().any(|socket| socket.port() == port) {
+        Check::pass(NAME, format!("127.0.0.1:{port} was found open"))
+    } else {
+        Check::fail(
+            NAME,
+            format!("127.0.0.1:{port} is listening but wasn't found open"),
+            "A firewall or security software may block local connections, or \
+             the --timeout is too short for this machine.",
+        )
+    }
+}
+
+/// Checks a port which was just freed, returned for the other checks.
+async fn closed_port(opts: &Opts) -> (Check, u16) {
+    const NAME: &str = "closed port";
+    let port = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener.local_addr().map_or(0, |addr| addr.port()),
+        Err(e) => {
+            let check = Check::fail(
+                NAME,
+                format!("Could not find a free port on localhost: {e}"),
+                "Check that the loopback interface is up.",
+            );
+            return (check, 0);
+        }
+    };
+    let open = scan(opts, port).await;
+    let check = if open.is_empty() {
+        Check::pass(NAME, format!("127.0.0.1:{port} was found closed"))
+    } else {
+        Check::fail(
+            NAME,
+            format!("127.0.0.1:{port} has nothing listening but was found open"),
+            "A proxy or security software accepts every local connection, \
+             scans of this machine will report every port open.",
+        )
+    };
+    (check, port)
+}
+
+/// Opens batches of connections to the closed `port`, refused connections
+/// being answers too.
+async fn concurrency(port: u16, max_batch_size: usize) -> Check {
+    const NAME: &str = "concurrency";
+    let socket = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let concurrency = tune::measure(socket, max_batch_size).await.concurrency;
+    if concurrency > 0 {
+        Check::pass(
+            NAME,
+            format!("{concurrency} connections could be open at once"),
+        )
+    } else {
+        Check::fail(
+            NAME,
+            format!(
+                "{} connections at once already failed",
+                tune::FIRST_BATCH.min(max_batch_size)
+            ),
+            "Raise the file limit with --ulimit or `ulimit -n`.",
+        )
+    }
+}
+
+fn resolver(opts: &Opts, name: &str) -> Check {
+    const NAME: &str = "resolver";
+    let ips = parse_address(name, &get_resolver(&opts.resolver));
+    match ips.first() {
+        Some(ip) => Check::pass(NAME, format!("{name} resolved to {ip}")),
+        None => Check::fail(
+            NAME,
+            format!("{name} could not be resolved"),
+            "Check the DNS settings of this machine, or give resolvers with --resolver.",
+        ),
+    }
+}
+
+/// Parses every script of the scripts folder of `home`.
+fn scripts(home: Option<PathBuf>) -> Check {
+    const NAME: &str = "scripts";
+    let Some(home) = home else {
+        return Check::fail(
+            NAME,
+            "Could not find the home directory",
+            "Set the HOME environment variable.",
+        );
+    };
+    let Ok(paths) = find_scripts(home) else {
+        return Check::pass(
+            NAME,
+            "No scripts folder, only the default script is available",
+        );
+    };
+    let broken: Vec<String> = paths
+        .iter()
+        .filter(|path| parse_scripts(vec![path.to_path_buf()]).is_empty())
+        .map(|path| path.display().to_string())
+        .collect();
+    if broken.is_empty() {
+        Check::pass(NAME, format!("{} scripts parsed", paths.len()))
+    } else {
+        Check::fail(
+            NAME,
+            format!("Could not parse {}", broken.join(", ")),
+            "The # lines after the shebang of a script are its TOML header, see the scripts documentation.",
+        )
+    }
+}
+
+/// Looks nmap up in the directories of `path` and asks for its version.
+fn nmap(path: Option<&std::ffi::OsStr>) -> Check {
+    const NAME: &str = "nmap";
+    let Some(nmap) = find_binary("nmap", path) else {
+        return Check::fail(
+            NAME,
+            "nmap is not in PATH",
+            "Install nmap for the default script, or scan with --scripts none.",
+        );
+    };
+    match Command::new(&nmap).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let version = stdout.lines().find(|line| !line.trim().is_empty());
+            Check::pass(NAME, version.unwrap_or("found").trim())
+        }
+        Ok(output) => Check::fail(
+            NAME,
+            format!("{} --version exited with {}", nmap.display(), output.status),
+            "Reinstall nmap.",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("Could not run {}: {e}", nmap.display()),
+            "Check that nmap is executable.",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nmap, scripts, Check};
+    use std::fs;
+
+    #[test]
+    fn checks_render_with_their_hints() {
+        assert_eq!(
+            Check::pass("open port", "127.0.0.1:80 was found open").to_string(),
+            "[PASS] open port: 127.0.0.1:80 was found open"
+        );
+        let failed = Check::fail("nmap", "nmap is not in PATH", "Install nmap.");
+        assert_eq!(
+            failed.to_string(),
+            "[FAIL] nmap: nmap is not in PATH\n       hint: Install nmap."
+        );
+        assert!(!nmap(Some("".as_ref())).passed);
+    }
+
+    #[test]
+    fn broken_scripts_fail_the_check() {
+        let home = std::env::temp_dir().join(format!("rustscan-selftest-{}", std::process::id()));
+        assert!(scripts(Some(home.clone())).passed);
+
+        let folder = home.join(".rustscan_scripts");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(
+            folder.join("good.sh"),
+            "#!/bin/sh\n#tags = [\"core\"]\n#call_format = \"echo {{ip}}\"\n",
+        )
+        .unwrap();
+        assert_eq!(scripts(Some(home.clone())).detail, "1 scripts parsed");
+
+        fs::write(folder.join("bad.sh"), "#!/bin/sh\n#tags = [\n").unwrap();
+        let check = scripts(Some(home.clone()));
+        fs::remove_dir_all(&home).unwrap();
+        assert!(!check.passed);
+        assert!(check.detail.contains("bad.sh"), "{}", check.detail);
+    }
+}
@@ -0,0 +1,582 @@
+//! Where the results of a run go, see [`OutputSink`].
+//!
+//! Every output of a scan is a sink: the `Open` lines printed as the ports
+//! are found, the greppable lines, the JSON report on stdout or in
+//! `--output-file`, the page of `--html-report` and the webhooks of
+//! `--notify`. Library users add their own next to them, like a database.
+//! [`Sinks`] holds the sinks of a run and calls each of them in turn: as the
+//! scan starts, for every event of a port, for every host once its results
+//! are in, scripts included, and once the run is over.
+//!
+//! The scanner calls the sinks it is given as it goes, see
+//! [`crate::scanner::Scanner::with_sink`], on its own task: a slow sink
+//! slows the scan down, so the ones talking to slow endpoints had better
+//! queue their work like the webhooks do. The hosts and the end are told by
+//! whoever builds the report, the scanner only knowing the sockets.
+//!
+//! A sink which fails is still called for the events after, its failures
+//! being summed up by [`Sinks::failures`]. A critical sink failing stops
+//! the scan instead, and the run ends with its error.
+use crate::errors::ErrorCode;
+use crate::html;
+use crate::probe::ServiceGuess;
+use crate::report::{HostReport, ScanReport};
+use crate::split::{self, OutputSplit};
+use crate::tui::{self, Verbosity};
+use anyhow::Context;
+use colored::Colorize;
+use log::debug;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// An output of the results of a run. Every call but [`OutputSink::name`]
+/// does nothing unless implemented.
+pub trait OutputSink: Send {
+    /// What the sink is called in the warnings about its failures.
+    fn name(&self) -> String;
+
+    /// The code of the warnings about its failures.
+    fn code(&self) -> ErrorCode {
+        ErrorCode::SinkFailed
+    }
+
+    /// A scan starts. Called again for the fallback scan of the dual-stack
+    /// hosts which didn't answer on their first family.
+    fn started(&mut self, _start: &ScanStart) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Something happened to a socket, as the scan goes.
+    fn port(&mut self, _event: &PortEvent) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The results of a host are in, once for every host of the report.
+    fn host(&mut self, _host: &HostReport) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// The run is over, with its report.
+    fn finished(&mut self, _end: &ScanEnd) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// What a scan starts with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanStart {
+    /// The hosts of the scan, each with its amount of sockets.
+    pub hosts: Vec<(IpAddr, usize)>,
+    pub batch_size: u16,
+}
+
+/// An event of a socket of a running scan.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortEvent {
+    /// The socket is open, found by its first probe or a retry. A canary is
+    /// a port of `--canary-ports`, a flaky one didn't connect every try.
+    Open {
+        socket: SocketAddr,
+        canary: bool,
+        flaky: bool,
+    },
+    /// The first probe of the socket is over, whatever it found. An open
+    /// socket was told [`PortEvent::Open`] just before.
+    Probed(SocketAddr),
+    /// The socket was left out because its host was skipped.
+    Skipped(SocketAddr),
+    /// The probe pipeline guessed the service of the open socket.
+    Served(SocketAddr, ServiceGuess),
+}
+
+/// The end of a run.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanEnd<'a> {
+    pub report: &'a ScanReport,
+    /// The hosts which were scanned, the skipped ones left out.
+    pub hosts: usize,
+    pub open_ports: usize,
+}
+
+impl<'a> ScanEnd<'a> {
+    pub fn of(report: &'a ScanReport) -> Self {
+        Self {
+            report,
+            hosts: report.hosts.iter().filter(|host| host.scanned).count(),
+            open_ports: report.hosts.iter().map(|host| host.ports.len()).sum(),
+        }
+    }
+}
+
+/// A sink which failed at least once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkFailure {
+    pub sink: String,
+    pub code: ErrorCode,
+    pub critical: bool,
+    pub count: u64,
+    /// The last error of the sink.
+    pub error: String,
+}
+
+impl fmt::Display for SinkFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.sink, self.error)?;
+        if self.count > 1 {
+            write!(f, " ({} failures)", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The sinks of a run, cheap to clone: the clones call the same sinks.
+#[derive(Clone, Default)]
+pub struct Sinks {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    sinks: Mutex<Vec<Registered>>,
+    failed_critically: AtomicBool,
+}
+
+struct Registered {
+    sink: Box<dyn OutputSink>,
+    critical: bool,
+    failures: u64,
+    error: String,
+}
+
+impl fmt::Debug for Sinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.lock().iter().map(|registered| registered.sink.name()))
+            .finish()
+    }
+}
+
+impl Sinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `sink`, called after the sinks added before.
+    #[must_use]
+    pub fn with_sink(self, sink: impl OutputSink + 'static) -> Self {
+        self.add(Box::new(sink), false);
+        self
+    }
+
+    /// Adds `sink`, whose failures stop the scan.
+    #[must_use]
+    pub fn with_critical_sink(self, sink: impl OutputSink + 'static) -> Self {
+        self.add(Box::new(sink), true);
+        self
+    }
+
+    pub fn add(&self, sink: Box<dyn OutputSink>, critical: bool) {
+        self.lock().push(Registered {
+            sink,
+            critical,
+            failures: 0,
+            error: String::new(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn started(&self, start: &ScanStart) {
+        self.each(|sink| sink.started(start));
+    }
+
+    pub fn port(&self, event: &PortEvent) {
+        self.each(|sink| sink.port(event));
+    }
+
+    pub fn host(&self, host: &HostReport) {
+        self.each(|sink| sink.host(host));
+    }
+
+    pub fn finished(&self, end: &ScanEnd) {
+        self.each(|sink| sink.finished(end));
+    }
+
+    /// Whether a critical sink failed, so that the scan has to stop.
+    pub fn failed_critically(&self) -> bool {
+        self.shared.failed_critically.load(Ordering::Relaxed)
+    }
+
+    /// The sinks which failed so far, in the order they were added.
+    pub fn failures(&self) -> Vec<SinkFailure> {
+        self.lock()
+            .iter()
+            .filter(|registered| registered.failures > 0)
+            .map(|registered| SinkFailure {
+                sink: registered.sink.name(),
+                code: registered.sink.code(),
+                critical: registered.critical,
+                count: registered.failures,
+                error: registered.error.clone(),
+            })
+            .collect()
+    }
+
+    fn each(&self, mut call: impl FnMut(&mut dyn OutputSink) -> anyhow::Result<()>) {
+        for registered in self.lock().iter_mut() {
+            if let Err(e) = call(registered.sink.as_mut()) {
+                registered.failures += 1;
+                registered.error = format!("{e:#}");
+                if registered.critical {
+                    self.shared.failed_critically.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Registered>> {
+        self.shared
+            .sinks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The `Open` lines printed as the ports are found, unless greppable.
+#[derive(Debug)]
+pub struct Normal {
+    greppable: bool,
+    accessible: bool,
+}
+
+impl Normal {
+    pub fn new(greppable: bool, accessible: bool) -> Self {
+        Self {
+            greppable,
+            accessible,
+        }
+    }
+}
+
+impl OutputSink for Normal {
+    fn name(&self) -> String {
+        "normal output".to_owned()
+    }
+
+    fn port(&mut self, event: &PortEvent) -> anyhow::Result<()> {
+        let PortEvent::Open {
+            socket,
+            canary,
+            flaky,
+        } = event
+        else {
+            return Ok(());
+        };
+        if !tui::shows(Verbosity::Normal, self.greppable) {
+            return Ok(());
+        }
+        let flags = format!(
+            "{}{}",
+            if *canary { " (canary)" } else { "" },
+            if *flaky { " (flaky)" } else { "" }
+        );
+        let mut stdout = io::stdout().lock();
+        if self.accessible {
+            writeln!(stdout, "Open {socket}{flags}")?;
+        } else {
+            writeln!(stdout, "Open {}{flags}", socket.to_string().purple())?;
+        }
+        Ok(())
+    }
+}
+
+/// The greppable line of every scanned host, like `ip -> [ports]`.
+#[derive(Debug)]
+pub struct Greppable {
+    prefix: String,
+    show_empty: bool,
+}
+
+impl Greppable {
+    /// Lines behind `prefix`, the hosts without open ports getting one
+    /// only when `show_empty`.
+    pub fn new(prefix: Option<String>, show_empty: bool) -> Self {
+        Self {
+            prefix: prefix.unwrap_or_default(),
+            show_empty,
+        }
+    }
+}
+
+impl OutputSink for Greppable {
+    fn name(&self) -> String {
+        "greppable output".to_owned()
+    }
+
+    fn host(&mut self, host: &HostReport) -> anyhow::Result<()> {
+        if !host.scanned || (host.ports.is_empty() && !self.show_empty) {
+            return Ok(());
+        }
+        writeln!(io::stdout().lock(), "{}{}", self.prefix, host.greppable())?;
+        Ok(())
+    }
+}
+
+/// The JSON report, on stdout or in a file, in the parts of an
+/// `--output-split` if given.
+#[derive(Debug)]
+pub struct Json {
+    path: Option<PathBuf>,
+    split: Option<OutputSplit>,
+}
+
+impl Json {
+    pub fn stdout() -> Self {
+        Self {
+            path: None,
+            split: None,
+        }
+    }
+
+    pub fn file(path: PathBuf, split: Option<OutputSplit>) -> Self {
+        Self {
+            path: Some(path),
+            split,
+        }
+    }
+}
+
+impl OutputSink for Json {
+    fn name(&self) -> String {
+        "JSON report".to_owned()
+    }
+
+    fn code(&self) -> ErrorCode {
+        ErrorCode::ReportWriteFailed
+    }
+
+    fn finished(&mut self, end: &ScanEnd) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            writeln!(io::stdout().lock(), "{}", end.report.to_json())?;
+            return Ok(());
+        };
+        match self.split {
+            Some(output_split) => {
+                let mut report = end.report.clone();
+                let index = split::write_split(path, &mut report, output_split)
+                    .with_context(|| format!("Couldn't write the report to {}", path.display()))?;
+                debug!(
+                    "Split the report into {} part(s), listed in {}",
+                    index.parts.len(),
+                    split::index_path(path).display()
+                );
+            }
+            None => fs::write(path, end.report.to_json() + "\n")
+                .with_context(|| format!("Couldn't write the report to {}", path.display()))?,
+        }
+        Ok(())
+    }
+}
+
+/// The HTML page of the results, written to a file, see [`crate::html`].
+#[derive(Debug)]
+pub struct Html {
+    path: PathBuf,
+}
+
+impl Html {
+    pub fn file(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl OutputSink for Html {
+    fn name(&self) -> String {
+        "HTML report".to_owned()
+    }
+
+    fn code(&self) -> ErrorCode {
+        ErrorCode::ReportWriteFailed
+    }
+
+    fn finished(&mut self, end: &ScanEnd) -> anyhow::Result<()> {
+        // The page has the JSON report at its end.
+        fs::write(&self.path, html::render(end.report, "#raw"))
+            .with_context(|| format!("Couldn't write the page to {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputSink, PortEvent, ScanEnd, ScanStart, SinkFailure, Sinks};
+    use crate::address::Target;
+    use crate::errors::ErrorCode;
+    use crate::input::HostOrder;
+    use crate::report::{HostReport, ScanReport};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    /// Writes down every call it gets, failing the ones of `fails`.
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        fails: &'static [&'static str],
+    }
+
+    impl Recorder {
+        fn call(&self, call: String) -> anyhow::Result<()> {
+            let stage = call.split(' ').next().unwrap_or_default().to_owned();
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} {call}", self.name));
+            if self.fails.contains(&stage.as_str()) {
+                anyhow::bail!("{stage} refused");
+            }
+            Ok(())
+        }
+    }
+
+    impl OutputSink for Recorder {
+        fn name(&self) -> String {
+            self.name.to_owned()
+        }
+
+        fn started(&mut self, start: &ScanStart) -> anyhow::Result<()> {
+            self.call(format!("started {:?}", start.hosts))
+        }
+
+        fn port(&mut self, event: &PortEvent) -> anyhow::Result<()> {
+            self.call(format!("port {:?}", event))
+        }
+
+        fn host(&mut self, host: &HostReport) -> anyhow::Result<()> {
+            self.call(format!("host {} {:?}", host.ip, host.ports))
+        }
+
+        fn finished(&mut self, end: &ScanEnd) -> anyhow::Result<()> {
+            self.call(format!("finished {} {}", end.hosts, end.open_ports))
+        }
+    }
+
+    fn report(open: &[SocketAddr]) -> ScanReport {
+        let target = Target {
+            ip: "192.0.2.1".parse().unwrap(),
+            hostnames: Vec::new(),
+            sources: vec!["192.0.2.1".to_owned()],
+            ports: None,
+        };
+        ScanReport::new(&[target], open, HostOrder::Input)
+    }
+
+    // Drives `sinks` through a run finding one open port.
+    fn run(sinks: &Sinks) {
+        let socket: SocketAddr = "192.0.2.1:22".parse().unwrap();
+        sinks.started(&ScanStart {
+            hosts: vec![(socket.ip(), 1)],
+            batch_size: 10,
+        });
+        sinks.port(&PortEvent::Open {
+            socket,
+            canary: false,
+            flaky: false,
+        });
+        sinks.port(&PortEvent::Probed(socket));
+        let report = report(&[socket]);
+        for host in &report.hosts {
+            sinks.host(host);
+        }
+        sinks.finished(&ScanEnd::of(&report));
+    }
+
+    #[test]
+    fn every_sink_gets_every_call_in_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name| Recorder {
+            name,
+            calls: calls.clone(),
+            fails: &[],
+        };
+        let sinks = Sinks::new()
+            .with_sink(recorder("first"))
+            .with_sink(recorder("second"));
+        run(&sinks);
+
+        let sequence = |name| {
+            vec![
+                format!("{name} started [(192.0.2.1, 1)]"),
+                format!("{name} port Open {{ socket: 192.0.2.1:22, canary: false, flaky: false }}"),
+                format!("{name} port Probed(192.0.2.1:22)"),
+                format!("{name} host 192.0.2.1 [22]"),
+                format!("{name} finished 1 1"),
+            ]
+        };
+        let calls = calls.lock().unwrap();
+        let first: Vec<&String> = calls.iter().filter(|c| c.starts_with("first")).collect();
+        let second: Vec<&String> = calls.iter().filter(|c| c.starts_with("second")).collect();
+        assert_eq!(first, sequence("first").iter().collect::<Vec<_>>());
+        assert_eq!(second, sequence("second").iter().collect::<Vec<_>>());
+        // Each call goes to the first sink, then to the second.
+        assert!(calls[0].starts_with("first") && calls[1].starts_with("second"));
+        assert!(sinks.failures().is_empty());
+        assert!(!sinks.failed_critically());
+    }
+
+    #[test]
+    fn failing_sinks_are_reported_and_still_called() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let sinks = Sinks::new()
+            .with_sink(Recorder {
+                name: "database",
+                calls: calls.clone(),
+                fails: &["port"],
+            })
+            .with_sink(Recorder {
+                name: "file",
+                calls: calls.clone(),
+                fails: &[],
+            });
+        run(&sinks);
+
+        assert_eq!(calls.lock().unwrap().len(), 10);
+        assert!(!sinks.failed_critically());
+        let failures = sinks.failures();
+        assert_eq!(
+            failures,
+            [SinkFailure {
+                sink: "database".to_owned(),
+                code: ErrorCode::SinkFailed,
+                critical: false,
+                count: 2,
+                error: "port refused".to_owned(),
+            }]
+        );
+        assert_eq!(
+            failures[0].to_string(),
+            "database: port refused (2 failures)"
+        );
+    }
+
+    #[test]
+    fn critical_sinks_failing_stop_the_scan() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let sinks = Sinks::new().with_critical_sink(Recorder {
+            name: "database",
+            calls,
+            fails: &["finished"],
+        });
+        // The clones share the sinks and their failures.
+        let scanner_side = sinks.clone();
+        run(&sinks);
+        assert!(scanner_side.failed_critically());
+        assert!(sinks.failures()[0].critical);
+        assert_eq!(format!("{:?}", scanner_side), "[\"database\"]");
+    }
+}
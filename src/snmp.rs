@@ -0,0 +1,437 @@
+//! Checks the community strings of the SNMP agents the UDP scan found, see
+//! `--snmp-probe`.
+//!
+//! An agent answering on 161 is always worth a community check. Every
+//! community gets a single GET of `sysDescr.0`, one after the other until an
+//! agent answers one, each within the probe timeout: a host costs at most a
+//! packet per community. Agents drop the requests of a wrong community
+//! without a word, so the first answer tells the community which works, and
+//! `sysDescr` what the agent runs.
+//!
+//! Only the BER the request and its response need is encoded and decoded
+//! here, not SNMP at large.
+use async_std::io;
+use async_std::net::UdpSocket;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The port of the SNMP agents.
+pub const SNMP_PORT: u16 = 161;
+
+/// The communities every probe tries, before the ones of `--snmp-community`.
+pub const DEFAULT_COMMUNITIES: [&str; 2] = ["public", "private"];
+
+/// `1.3.6.1.2.1.1.1.0`, the sysDescr of the system group, encoded.
+const SYS_DESCR: [u8; 8] = [0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00];
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GET_REQUEST: u8 = 0xa0;
+const GET_RESPONSE: u8 = 0xa2;
+
+/// The largest answer read, sysDescr is a line or two.
+const READ_SIZE: usize = 1500;
+
+/// The version of SNMP the requests are sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SnmpVersion {
+    #[serde(rename = "1")]
+    V1,
+    #[serde(rename = "2c")]
+    V2c,
+}
+
+impl SnmpVersion {
+    /// The version field of the messages.
+    fn number(self) -> u8 {
+        match self {
+            SnmpVersion::V1 => 0,
+            SnmpVersion::V2c => 1,
+        }
+    }
+}
+
+impl FromStr for SnmpVersion {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, String> {
+        match input.trim().to_ascii_lowercase().trim_start_matches('v') {
+            "1" => Ok(SnmpVersion::V1),
+            "2c" | "2" => Ok(SnmpVersion::V2c),
+            _ => Err(format!("{input:?} isn't 1 or 2c")),
+        }
+    }
+}
+
+impl fmt::Display for SnmpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnmpVersion::V1 => f.write_str("1"),
+            SnmpVersion::V2c => f.write_str("2c"),
+        }
+    }
+}
+
+/// What an agent answered its community check with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnmpFinding {
+    pub version: SnmpVersion,
+    /// The community the agent answered.
+    pub community: String,
+    /// None when the agent has no sysDescr to give.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sys_descr: Option<String>,
+}
+
+/// The response to a GET of sysDescr.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub version: u8,
+    pub community: Vec<u8>,
+    pub request_id: i32,
+    pub error_status: i32,
+    /// The value of the first variable, when it's a string.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Encodes a GET of `sysDescr.0` with `community`.
+pub fn encode_get(version: SnmpVersion, community: &str, request_id: i32) -> Vec<u8> {
+    let mut varbind = tlv(OBJECT_IDENTIFIER, &SYS_DESCR);
+    varbind.extend(tlv(NULL, &[]));
+    let varbinds = tlv(SEQUENCE, &tlv(SEQUENCE, &varbind));
+
+    let mut pdu = integer(request_id);
+    // Error status and index.
+    pdu.extend(integer(0));
+    pdu.extend(integer(0));
+    pdu.extend(varbinds);
+
+    let mut message = integer(version.number().into());
+    message.extend(tlv(OCTET_STRING, community.as_bytes()));
+    message.extend(tlv(GET_REQUEST, &pdu));
+    tlv(SEQUENCE, &message)
+}
+
+/// Decodes the GetResponse of an agent.
+pub fn decode_response(bytes: &[u8]) -> Result<Response, String> {
+    let mut reader = Reader::new(bytes);
+    let mut message = Reader::new(reader.expect(SEQUENCE)?);
+    let version = read_integer(&mut message)?;
+    let community = message.expect(OCTET_STRING)?.to_vec();
+    let mut pdu = Reader::new(message.expect(GET_RESPONSE)?);
+    let request_id = read_integer(&mut pdu)?;
+    let error_status = read_integer(&mut pdu)?;
+    let _error_index = read_integer(&mut pdu)?;
+
+    let mut varbinds = Reader::new(pdu.expect(SEQUENCE)?);
+    let value = if varbinds.is_empty() {
+        None
+    } else {
+        let mut varbind = Reader::new(varbinds.expect(SEQUENCE)?);
+        varbind.expect(OBJECT_IDENTIFIER)?;
+        // noSuchObject and the like are no string.
+        match varbind.next()? {
+            (OCTET_STRING, value) => Some(value.to_vec()),
+            _ => None,
+        }
+    };
+    Ok(Response {
+        version: u8::try_from(version).map_err(|_| format!("Unknown version {version}"))?,
+        community,
+        request_id,
+        error_status,
+        value,
+    })
+}
+
+/// A type, length and value, with the short or long form of the length.
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = value.len();
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        encoded.push(0x80 | bytes.len() as u8);
+        encoded.extend(bytes);
+    }
+    encoded.extend_from_slice(value);
+    encoded
+}
+
+/// An INTEGER, in as few bytes as its two's complement takes.
+fn integer(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 3 {
+        let redundant = (bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    tlv(INTEGER, &bytes[start..])
+}
+
+fn read_integer(reader: &mut Reader<'_>) -> Result<i32, String> {
+    let bytes = reader.expect(INTEGER)?;
+    if bytes.is_empty() || bytes.len() > 4 {
+        return Err(format!("An INTEGER of {} bytes", bytes.len()));
+    }
+    let negative = bytes[0] & 0x80 != 0;
+    let mut value: i32 = if negative { -1 } else { 0 };
+    for byte in bytes {
+        value = (value << 8) | i32::from(*byte);
+    }
+    Ok(value)
+}
+
+/// Reads the values of a BER encoding one after the other.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The next value with its tag.
+    fn next(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let truncated = || String::from("The message is truncated");
+        let (&tag, rest) = self.bytes.split_first().ok_or_else(truncated)?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        let length = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let count = usize::from(first & 0x7f);
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err(format!("A length of {count} bytes"));
+            }
+            let (bytes, after) = rest.split_at(count);
+            rest = after;
+            bytes
+                .iter()
+                .fold(0, |length, byte| (length << 8) | usize::from(*byte))
+        };
+        if rest.len() < length {
+            return Err(truncated());
+        }
+        let (value, after) = rest.split_at(length);
+        self.bytes = after;
+        Ok((tag, value))
+    }
+
+    /// The next value, which must have `tag`.
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8], String> {
+        match self.next()? {
+            (found, value) if found == tag => Ok(value),
+            (found, _) => Err(format!("Expected the tag {tag:#04x}, got {found:#04x}")),
+        }
+    }
+}
+
+/// Checks the communities of SNMP agents, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct SnmpProber {
+    version: SnmpVersion,
+    communities: Vec<String>,
+    timeout: Duration,
+}
+
+impl SnmpProber {
+    /// A prober of the default communities, waiting `timeout` for the answer
+    /// to each.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            version: SnmpVersion::V2c,
+            communities: DEFAULT_COMMUNITIES
+                .iter()
+                .map(|c| (*c).to_owned())
+                .collect(),
+            timeout,
+        }
+    }
+
+    #[must_use]
+    pub fn with_version(mut self, version: SnmpVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Tries `communities` too, after the ones before. Repeated ones are
+    /// tried once.
+    #[must_use]
+    pub fn with_communities(mut self, communities: &[String]) -> Self {
+        for community in communities {
+            if !self.communities.contains(community) {
+                self.communities.push(community.clone());
+            }
+        }
+        self
+    }
+
+    /// Checks the agents of `sockets`, `concurrency` of them at a time.
+    pub async fn probe_all(
+        &self,
+        sockets: &[SocketAddr],
+        concurrency: usize,
+    ) -> HashMap<SocketAddr, SnmpFinding> {
+        let mut sockets = sockets.iter();
+        let mut ftrs = FuturesUnordered::new();
+        let mut findings = HashMap::new();
+        let probe = |socket: SocketAddr| async move { (socket, self.probe(socket).await) };
+
+        for socket in sockets.by_ref().take(concurrency.max(1)) {
+            ftrs.push(probe(*socket));
+        }
+
+        while let Some((socket, finding)) = ftrs.next().await {
+            if let Some(socket) = sockets.next() {
+                ftrs.push(probe(*socket));
+            }
+            if let Some(finding) = finding {
+                findings.insert(socket, finding);
+            }
+        }
+
+        findings
+    }
+
+    /// Tries the communities on the agent of `socket` until one answers.
+    pub async fn probe(&self, socket: SocketAddr) -> Option<SnmpFinding> {
+        let local: SocketAddr = match socket.ip() {
+            IpAddr::V4(_) => "0.0.0.0:0".parse().ok()?,
+            IpAddr::V6(_) => "[::]:0".parse().ok()?,
+        };
+        let udp = UdpSocket::bind(local).await.ok()?;
+        udp.connect(socket).await.ok()?;
+        // Told apart from the answers to the probes of other scans.
+        let base = rand::random::<u16>();
+
+        for (index, community) in self.communities.iter().enumerate() {
+            let request_id = i32::from(base) + index as i32;
+            if udp
+                .send(&encode_get(self.version, community, request_id))
+                .await
+                .is_err()
+            {
+                return None;
+            }
+            if let Some(response) = self.answer(&udp, request_id).await {
+                if response.error_status != 0 {
+                    continue;
+                }
+                return Some(SnmpFinding {
+                    version: self.version,
+                    community: community.clone(),
+                    sys_descr: response
+                        .value
+                        .map(|value| String::from_utf8_lossy(&value).trim().to_owned()),
+                });
+            }
+        }
+        None
+    }
+
+    /// The answer to `request_id`, read within the timeout.
+    async fn answer(&self, udp: &UdpSocket, request_id: i32) -> Option<Response> {
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = [0; READ_SIZE];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let read = io::timeout(remaining, udp.recv(&mut buffer)).await.ok()?;
+            // Late answers to an earlier community, or garbage, are skipped.
+            match decode_response(&buffer[..read]) {
+                Ok(response) if response.request_id == request_id => return Some(response),
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_response, encode_get, integer, tlv, SnmpVersion};
+
+    // A GET of sysDescr.0 with public, as `snmpget -v2c` sends it.
+    const GET: [u8; 43] = [
+        0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa0, 0x1c,
+        0x02, 0x04, 0x1a, 0x2b, 0x3c, 0x4d, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+        0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+    ];
+
+    // The answer of an agent to it.
+    const RESPONSE: [u8; 62] = [
+        0x30, 0x3c, 0x02, 0x01, 0x01, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa2, 0x2f,
+        0x02, 0x04, 0x1a, 0x2b, 0x3c, 0x4d, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x21, 0x30,
+        0x1f, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x04, 0x13, 0x4c, 0x69,
+        0x6e, 0x75, 0x78, 0x20, 0x72, 0x6f, 0x75, 0x74, 0x65, 0x72, 0x20, 0x35, 0x2e, 0x31, 0x30,
+        0x2e, 0x30,
+    ];
+
+    // noSuchObject, with the long form of the first length.
+    const NO_SUCH_OBJECT: [u8; 44] = [
+        0x30, 0x81, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0xa2,
+        0x1c, 0x02, 0x04, 0x1a, 0x2b, 0x3c, 0x4d, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e,
+        0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x80, 0x00,
+    ];
+
+    #[test]
+    fn get_requests_are_encoded_like_snmpget() {
+        assert_eq!(encode_get(SnmpVersion::V2c, "public", 0x1a2b_3c4d), GET);
+        let v1 = encode_get(SnmpVersion::V1, "private", 7);
+        assert_eq!(&v1[..5], [0x30, 0x27, 0x02, 0x01, 0x00]);
+        assert_eq!(&v1[14..19], [0xa0, 0x19, 0x02, 0x01, 0x07]);
+    }
+
+    #[test]
+    fn integers_take_the_fewest_bytes() {
+        assert_eq!(integer(0), [0x02, 0x01, 0x00]);
+        assert_eq!(integer(127), [0x02, 0x01, 0x7f]);
+        assert_eq!(integer(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(-1), [0x02, 0x01, 0xff]);
+        assert_eq!(integer(-129), [0x02, 0x02, 0xff, 0x7f]);
+        assert_eq!(tlv(0x04, &[0; 200])[..3], [0x04, 0x81, 0xc8]);
+    }
+
+    #[test]
+    fn responses_are_decoded() {
+        let response = decode_response(&RESPONSE).unwrap();
+        assert_eq!(response.version, 1);
+        assert_eq!(response.community, b"public");
+        assert_eq!(response.request_id, 0x1a2b_3c4d);
+        assert_eq!(response.error_status, 0);
+        assert_eq!(response.value.as_deref(), Some(&b"Linux router 5.10.0"[..]));
+
+        let response = decode_response(&NO_SUCH_OBJECT).unwrap();
+        assert_eq!(response.value, None);
+
+        // The request is no response, and a truncated one no message.
+        assert!(decode_response(&GET).is_err());
+        assert!(decode_response(&RESPONSE[..50]).is_err());
+        assert!(decode_response(&[]).is_err());
+        assert_eq!("v1".parse(), Ok(SnmpVersion::V1));
+        assert_eq!("2C".parse(), Ok(SnmpVersion::V2c));
+        assert!("3".parse::<SnmpVersion>().is_err());
+    }
+}
@@ -0,0 +1,390 @@
+//! Splits the JSON report of `--output-file` into parts, see `--output-split`.
+//!
+//! A report of `FILE.json` split by `hosts=N` or `size=N` is written to
+//! `FILE.0001.json`, `FILE.0002.json`... next to `FILE.index.json`, which
+//! lists every part with the hosts it holds. Each part is a whole report,
+//! its `part` telling which one it is, so a part can be read on its own:
+//!
+//! - hosts are never cut between parts, a host bigger than `size=N` gets a
+//!   part of its own;
+//! - the groups of `--group-by-fingerprint` are made again from the hosts
+//!   of each part;
+//! - the unresolved hostnames are in the first part only, and everything
+//!   else about the run, like `stats` or `fingerprint`, is in every part.
+use crate::groups::group_hosts;
+use crate::report::{HostReport, ScanReport};
+use crate::schema::SchemaVersion;
+use serde_derive::Serialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The units of `size=N`, the longest first.
+const UNITS: [(&str, u64); 4] = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)];
+
+/// Where `--output-split` starts a new part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSplit {
+    /// After this many hosts.
+    Hosts(usize),
+    /// Before a host would take the part over this many bytes.
+    Size(u64),
+}
+
+impl FromStr for OutputSplit {
+    type Err = String;
+
+    /// Parses `hosts=N` or `size=N`, the size in bytes or with a unit of
+    /// `KB`, `MB` or `GB`, as powers of 1024.
+    fn from_str(split: &str) -> Result<Self, String> {
+        let (kind, value) = split
+            .split_once('=')
+            .ok_or_else(|| format!("expected hosts=N or size=N, not {split:?}"))?;
+        let value = value.trim();
+        match kind.trim() {
+            "hosts" => match value.parse() {
+                Ok(0) | Err(_) => Err(format!("{value:?} is not a number of hosts")),
+                Ok(hosts) => Ok(OutputSplit::Hosts(hosts)),
+            },
+            "size" => {
+                let upper = value.to_ascii_uppercase();
+                let (number, unit) = UNITS
+                    .iter()
+                    .find_map(|(unit, bytes)| Some((upper.strip_suffix(unit)?, *bytes)))
+                    .unwrap_or((&upper, 1));
+                match number.trim().parse::<u64>().map(|n| n.checked_mul(unit)) {
+                    Ok(Some(bytes)) if bytes > 0 => Ok(OutputSplit::Size(bytes)),
+                    _ => Err(format!("{value:?} is not a size, like 100MB")),
+                }
+            }
+            _ => Err(format!("{kind:?} is not a split, use hosts=N or size=N")),
+        }
+    }
+}
+
+impl fmt::Display for OutputSplit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSplit::Hosts(hosts) => write!(f, "hosts={hosts}"),
+            OutputSplit::Size(bytes) => {
+                let (unit, size) = UNITS
+                    .iter()
+                    .find(|(_, size)| bytes.is_multiple_of(*size))
+                    .expect("Every size is a number of bytes.");
+                write!(f, "size={}{unit}", bytes / size)
+            }
+        }
+    }
+}
+
+/// Which part of a split report a report is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReportPart {
+    /// From 1.
+    pub index: usize,
+    pub count: usize,
+}
+
+/// The index of a split report, written to `FILE.index.json`.
+#[derive(Debug, Serialize)]
+pub struct SplitIndex {
+    pub schema_version: SchemaVersion,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// The `--output-split` the report was split by.
+    pub split: String,
+    pub parts: Vec<IndexedPart>,
+}
+
+/// A part of a split report, in the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndexedPart {
+    /// The name of the file of the part, next to the index.
+    pub file: String,
+    /// The hosts of the part, in the order of the report.
+    pub hosts: Vec<IpAddr>,
+}
+
+impl SplitIndex {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize the split index.")
+    }
+}
+
+/// The file of the part `index` of the report of `path`, `FILE.0001.json`
+/// for `FILE.json`.
+pub fn part_path(path: &Path, index: usize) -> PathBuf {
+    with_infix(path, &format!("{index:04}"))
+}
+
+/// The file of the index of the split report of `path`, `FILE.index.json`
+/// for `FILE.json`.
+pub fn index_path(path: &Path) -> PathBuf {
+    with_infix(path, "index")
+}
+
+fn with_infix(path: &Path, infix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}.{infix}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{infix}"),
+    };
+    path.with_file_name(name)
+}
+
+/// The JSON of every part of `report` split by `split`, with its hosts.
+/// `report` is left whole once done.
+pub fn split_report(report: &mut ScanReport, split: OutputSplit) -> Vec<(Vec<IpAddr>, String)> {
+    let hosts = mem::take(&mut report.hosts);
+    let unresolved = mem::take(&mut report.unresolved);
+    let groups = mem::take(&mut report.groups);
+    let grouped = !groups.is_empty();
+    let mut render = |hosts: &mut Vec<HostReport>, part: ReportPart| {
+        report.hosts = mem::take(hosts);
+        report.part = Some(part);
+        report.groups = if grouped {
+            group_hosts(&report.hosts)
+        } else {
+            Vec::new()
+        };
+        report.unresolved = if part.index == 1 {
+            unresolved.clone()
+        } else {
+            Vec::new()
+        };
+        let json = report.to_json();
+        *hosts = mem::take(&mut report.hosts);
+        json
+    };
+    // Measured with the widest part numbers the parts can get.
+    let widest = |index| ReportPart {
+        index,
+        count: usize::MAX,
+    };
+
+    let mut chunks = match split {
+        OutputSplit::Hosts(per_part) => chunk_hosts(hosts, per_part as u64, |_| 1),
+        OutputSplit::Size(limit) => {
+            let base = render(&mut Vec::new(), widest(1)).len() as u64;
+            let chunks = chunk_hosts(hosts, limit.saturating_sub(base), pretty_size);
+            // The groups and the unresolved hostnames aren't in the estimate,
+            // the parts still over the limit are cut in two until they fit.
+            let mut fitting = Vec::with_capacity(chunks.len());
+            let mut pending = chunks;
+            pending.reverse();
+            while let Some(mut chunk) = pending.pop() {
+                let json = render(&mut chunk, widest(fitting.len() + 1));
+                if chunk.len() > 1 && json.len() as u64 + 1 > limit {
+                    let half = chunk.split_off(chunk.len() / 2);
+                    pending.push(half);
+                    pending.push(chunk);
+                } else {
+                    fitting.push(chunk);
+                }
+            }
+            fitting
+        }
+    };
+
+    let count = chunks.len();
+    let parts = chunks
+        .iter_mut()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let hosts = chunk.iter().map(|host| host.ip).collect();
+            let part = ReportPart {
+                index: index + 1,
+                count,
+            };
+            (hosts, render(chunk, part))
+        })
+        .collect();
+
+    report.hosts = chunks.into_iter().flatten().collect();
+    report.unresolved = unresolved;
+    report.groups = groups;
+    report.part = None;
+    parts
+}
+
+/// Cuts `hosts` into parts of at most `limit`, each host taking its `size`
+/// and a part at least one host. Always gives one part, even without hosts.
+fn chunk_hosts(
+    hosts: Vec<HostReport>,
+    limit: u64,
+    size: impl Fn(&HostReport) -> u64,
+) -> Vec<Vec<HostReport>> {
+    let mut chunks: Vec<Vec<HostReport>> = vec![Vec::new()];
+    let mut taken = 0;
+    for host in hosts {
+        let host_size = size(&host);
+        if taken + host_size > limit && !chunks.last().unwrap().is_empty() {
+            chunks.push(Vec::new());
+            taken = 0;
+        }
+        taken += host_size;
+        chunks.last_mut().unwrap().push(host);
+    }
+    chunks
+}
+
+/// The bytes `host` takes in the `hosts` of a pretty report: its own JSON
+/// indented twice more on every line, and the comma and newline after it.
+fn pretty_size(host: &HostReport) -> u64 {
+    let json = serde_json::to_string_pretty(host).expect("Failed to serialize a host.");
+    (json.len() + 4 * (json.lines().count()) + 2) as u64
+}
+
+/// Writes the parts of `report` split by `split` and their index, for the
+/// report of `path`. Gives the index written.
+pub fn write_split(
+    path: &Path,
+    report: &mut ScanReport,
+    split: OutputSplit,
+) -> io::Result<SplitIndex> {
+    let mut parts = Vec::new();
+    for (index, (hosts, json)) in split_report(report, split).into_iter().enumerate() {
+        let part = part_path(path, index + 1);
+        fs::write(&part, json + "\n")?;
+        parts.push(IndexedPart {
+            file: part
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            hosts,
+        });
+    }
+    let index = SplitIndex {
+        schema_version: report.schema_version,
+        run_id: report.run_id.clone(),
+        split: split.to_string(),
+        parts,
+    };
+    fs::write(index_path(path), index.to_json() + "\n")?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index_path, part_path, split_report, OutputSplit, ReportPart};
+    use crate::address::{ResolutionError, Target, Unresolved};
+    use crate::input::HostOrder;
+    use crate::report::ScanReport;
+    use serde_json::Value;
+    use std::net::SocketAddr;
+    use std::path::Path;
+
+    fn report(hosts: usize) -> ScanReport {
+        let targets: Vec<Target> = (1..=hosts)
+            .map(|host| Target {
+                ip: format!("192.0.2.{host}").parse().unwrap(),
+                hostnames: Vec::new(),
+                sources: vec![format!("192.0.2.{host}")],
+                ports: None,
+            })
+            .collect();
+        let open: Vec<SocketAddr> = targets
+            .iter()
+            .map(|target| SocketAddr::new(target.ip, 443))
+            .collect();
+        let mut report = ScanReport::new(&targets, &open, HostOrder::Input);
+        report.unresolved = vec![Unresolved {
+            name: "gone.test".to_owned(),
+            file: None,
+            error: ResolutionError::NotFound,
+        }];
+        report
+    }
+
+    #[test]
+    fn splits_are_parsed() {
+        assert_eq!("hosts=500".parse(), Ok(OutputSplit::Hosts(500)));
+        assert_eq!("size=100MB".parse(), Ok(OutputSplit::Size(100 << 20)));
+        assert_eq!("size=2kb".parse(), Ok(OutputSplit::Size(2048)));
+        assert_eq!("size=300".parse(), Ok(OutputSplit::Size(300)));
+        assert_eq!("size=1GB".parse(), Ok(OutputSplit::Size(1 << 30)));
+        assert!("hosts=0".parse::<OutputSplit>().is_err());
+        assert!("size=0B".parse::<OutputSplit>().is_err());
+        assert!("size=MB".parse::<OutputSplit>().is_err());
+        assert!("lines=10".parse::<OutputSplit>().is_err());
+        assert!("500".parse::<OutputSplit>().is_err());
+
+        assert_eq!(OutputSplit::Size(100 << 20).to_string(), "size=100MB");
+        assert_eq!(OutputSplit::Size(1500).to_string(), "size=1500B");
+        assert_eq!(OutputSplit::Hosts(7).to_string(), "hosts=7");
+    }
+
+    #[test]
+    fn parts_are_named_after_the_report() {
+        let path = Path::new("out/scan.json");
+        assert_eq!(part_path(path, 1), Path::new("out/scan.0001.json"));
+        assert_eq!(part_path(path, 12), Path::new("out/scan.0012.json"));
+        assert_eq!(index_path(path), Path::new("out/scan.index.json"));
+        assert_eq!(part_path(Path::new("scan"), 2), Path::new("scan.0002"));
+    }
+
+    #[test]
+    fn every_host_is_in_a_single_part() {
+        let mut scan = report(5);
+        let parts = split_report(&mut scan, OutputSplit::Hosts(2));
+        let hosts: Vec<usize> = parts.iter().map(|(hosts, _)| hosts.len()).collect();
+        assert_eq!(hosts, [2, 2, 1]);
+
+        for (index, (hosts, json)) in parts.iter().enumerate() {
+            let part: Value = serde_json::from_str(json).unwrap();
+            assert_eq!(
+                part["part"],
+                serde_json::to_value(ReportPart {
+                    index: index + 1,
+                    count: 3
+                })
+                .unwrap()
+            );
+            let ips: Vec<String> = hosts.iter().map(ToString::to_string).collect();
+            let reported: Vec<&str> = part["hosts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|host| host["ip"].as_str().unwrap())
+                .collect();
+            assert_eq!(reported, ips);
+            assert_eq!(part["unresolved"].is_array(), index == 0);
+        }
+
+        // The report is left whole.
+        assert_eq!(scan.hosts.len(), 5);
+        assert_eq!(scan.unresolved.len(), 1);
+        assert_eq!(scan.part, None);
+    }
+
+    #[test]
+    fn parts_stay_under_their_size() {
+        let mut scan = report(20);
+        let limit = 1200;
+        let parts = split_report(&mut scan, OutputSplit::Size(limit));
+        assert!(parts.len() > 1);
+        for (hosts, json) in &parts {
+            assert!(!hosts.is_empty());
+            assert!((json.len() as u64) < limit, "{} bytes", json.len());
+        }
+        let total: usize = parts.iter().map(|(hosts, _)| hosts.len()).sum();
+        assert_eq!(total, 20);
+
+        // A host bigger than the limit still gets written, on its own.
+        let parts = split_report(&mut scan, OutputSplit::Size(1));
+        assert_eq!(parts.len(), 20);
+    }
+
+    #[test]
+    fn empty_report_is_a_single_part() {
+        let mut scan = report(0);
+        let parts = split_report(&mut scan, OutputSplit::Hosts(10));
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].0.is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+//! The execution trace of `--trace-file`, telling where the time of a run
+//! went.
+//!
+//! Every event is written as a single line JSON object, see [`TraceEvent`],
+//! with `ts_us`, the microseconds since the trace started on a monotonic
+//! clock. Recording never slows the scan down for the trace: the events go
+//! through a bounded channel to a thread writing them, and the events which
+//! don't fit while it falls behind are dropped and counted. The last line,
+//! `trace-finished`, tells how many were.
+use crate::scanner::TryOutcome;
+use serde_derive::Serialize;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How many events wait for the writer at most.
+pub const CAPACITY: usize = 65_536;
+
+/// What happened during the run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum TraceEvent {
+    /// The scanner started on its sockets, the whole scan or, for dual-stack
+    /// hosts, the one of the other family.
+    BatchStarted {
+        hosts: usize,
+        sockets: usize,
+        batch_size: u16,
+    },
+    BatchFinished {
+        open: usize,
+        duration_us: u64,
+    },
+    ProbeLaunched {
+        socket: SocketAddr,
+    },
+    /// A probe is over, with what its last try ended with.
+    ProbeResolved {
+        socket: SocketAddr,
+        state: TryOutcome,
+        tries: usize,
+        duration_us: u64,
+    },
+    /// A spread try of the socket is due later.
+    RetryScheduled {
+        socket: SocketAddr,
+        due_in_ms: u64,
+    },
+    /// The scan holds back, for a host blocking it or for errors of the
+    /// machine under load.
+    RateWait {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ip: Option<IpAddr>,
+        reason: WaitReason,
+        wait_ms: u64,
+    },
+    ScriptStarted {
+        script: String,
+        ip: IpAddr,
+    },
+    ScriptFinished {
+        script: String,
+        ip: IpAddr,
+        succeeded: bool,
+        duration_us: u64,
+    },
+    /// The last event, along with the count of the ones dropped.
+    TraceFinished {
+        dropped: u64,
+    },
+}
+
+/// Why the scan waited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WaitReason {
+    /// The cooldown of a host which blocked the scan, with `--throttle-window`.
+    Throttle,
+    /// The backoff after a burst of connection errors.
+    Burst,
+}
+
+#[derive(Debug, Serialize)]
+struct Line {
+    ts_us: u64,
+    #[serde(flatten)]
+    event: TraceEvent,
+}
+
+enum Message {
+    Event(Line),
+    Finish,
+}
+
+/// Records the events of the trace, cloned for everything which has some.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    sender: SyncSender<Message>,
+    epoch: Instant,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Tracer {
+    /// Records `event`, or counts it as dropped when the writer is behind.
+    pub fn record(&self, event: TraceEvent) {
+        let line = Line {
+            ts_us: micros(self.epoch.elapsed()),
+            event,
+        };
+        match self.sender.try_send(Message::Event(line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The thread writing the trace.
+#[derive(Debug)]
+pub struct TraceWriter {
+    sender: SyncSender<Message>,
+    thread: JoinHandle<io::Result<u64>>,
+}
+
+impl TraceWriter {
+    /// Creates the trace file at `path`, holding at most `capacity` events
+    /// waiting to be written.
+    pub fn create(path: &Path, capacity: usize) -> io::Result<(Self, Tracer)> {
+        Self::start(BufWriter::new(File::create(path)?), capacity)
+    }
+
+    /// Writes the trace to `out`.
+    pub fn start(out: impl Write + Send + 'static, capacity: usize) -> io::Result<(Self, Tracer)> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let tracer = Tracer {
+            sender: sender.clone(),
+            epoch: Instant::now(),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let (epoch, dropped) = (tracer.epoch, Arc::clone(&tracer.dropped));
+        let thread = thread::Builder::new()
+            .name("trace".to_owned())
+            .spawn(move || write(out, &receiver, epoch, &dropped))?;
+        Ok((Self { sender, thread }, tracer))
+    }
+
+    /// Writes what is left of the trace and its last event, returning how
+    /// many events were dropped. The events recorded from now on are too.
+    pub fn finish(self) -> io::Result<u64> {
+        // Waits for room, the scan is over.
+        let _ = self.sender.send(Message::Finish);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("The trace writer panicked.")))
+    }
+}
+
+fn write(
+    mut out: impl Write,
+    receiver: &Receiver<Message>,
+    epoch: Instant,
+    dropped: &AtomicU64,
+) -> io::Result<u64> {
+    let mut write_line = |line: &Line| -> io::Result<()> {
+        serde_json::to_writer(&mut out, line)?;
+        out.write_all(b"\n")
+    };
+    for message in receiver {
+        match message {
+            Message::Event(line) => write_line(&line)?,
+            Message::Finish => break,
+        }
+    }
+    let dropped = dropped.load(Ordering::Relaxed);
+    write_line(&Line {
+        ts_us: micros(epoch.elapsed()),
+        event: TraceEvent::TraceFinished { dropped },
+    })?;
+    out.flush()?;
+    Ok(dropped)
+}
+
+/// The microseconds of `duration`, saturated.
+pub fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// The milliseconds of `duration`, saturated.
+pub fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceEvent, TraceWriter, WaitReason};
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// The output of a writer, shared with the test.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            let out = self.0.lock().unwrap();
+            std::str::from_utf8(&out)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn events_are_written_in_order() {
+        let out = Shared::default();
+        let (writer, tracer) = TraceWriter::start(out.clone(), 16).unwrap();
+        tracer.record(TraceEvent::ProbeLaunched {
+            socket: "127.0.0.1:80".parse().unwrap(),
+        });
+        tracer.record(TraceEvent::RateWait {
+            ip: None,
+            reason: WaitReason::Burst,
+            wait_ms: 10,
+        });
+        assert_eq!(writer.finish().unwrap(), 0);
+
+        let lines = out.lines();
+        let events: Vec<&str> = lines
+            .iter()
+            .map(|line| line["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(events, ["probe-launched", "rate-wait", "trace-finished"]);
+        assert_eq!(lines[0]["socket"], "127.0.0.1:80");
+        assert_eq!(
+            lines[1],
+            serde_json::json!({
+                "ts_us": lines[1]["ts_us"],
+                "event": "rate-wait",
+                "reason": "burst",
+                "wait_ms": 10,
+            })
+        );
+        assert_eq!(lines[2]["dropped"], 0);
+        let stamps: Vec<u64> = lines
+            .iter()
+            .map(|line| line["ts_us"].as_u64().unwrap())
+            .collect();
+        assert!(
+            stamps.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{:?}",
+            stamps
+        );
+    }
+
+    #[test]
+    fn events_which_dont_fit_are_dropped_and_counted() {
+        // The writer waits on the output until the events are recorded.
+        let out = Shared::default();
+        let held = out.0.lock().unwrap();
+        let (writer, tracer) = TraceWriter::start(out.clone(), 2).unwrap();
+        for _ in 0..10 {
+            tracer.record(TraceEvent::BatchFinished {
+                open: 0,
+                duration_us: 0,
+            });
+        }
+        drop(held);
+        let dropped = writer.finish().unwrap();
+        // The writer may have taken one off the channel before it blocked.
+        assert!((7..=8).contains(&dropped), "{}", dropped);
+
+        let lines = out.lines();
+        assert_eq!(lines.len() as u64, 10 - dropped + 1);
+        assert_eq!(lines.last().unwrap()["dropped"], dropped);
+    }
+}
@@ -1,43 +1,144 @@
 //! Utilities for terminal output during scanning.
+//!
+//! How much gets printed besides the results is decided by the process-wide
+//! [`Verbosity`], set once by the binary. Greppable and JSON output are
+//! independent from it: they only decide how the results look.
+//!
+//! Only the results go to stdout, through [`output!`] when they are printed
+//! for humans, so that piping them never picks up anything else. Warnings,
+//! details, the banner and the output of the scripts go to stderr.
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// The output levels, from the least to the most chatty.
+///   - quiet prints the results and the warnings only.
+///   - normal also prints progress details and open ports as they are found.
+///   - verbose also prints details about how the scan is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static SILENCE_WARNINGS: AtomicBool = AtomicBool::new(false);
+static ERRORS_JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Drops every warning, whatever the verbosity.
+pub fn set_silence_warnings(silence: bool) {
+    SILENCE_WARNINGS.store(silence, Ordering::Relaxed);
+}
+
+pub fn warnings_silenced() -> bool {
+    SILENCE_WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Prints every warning as a JSON object on stderr, see [`crate::errors`].
+pub fn set_errors_json(json: bool) {
+    ERRORS_JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn errors_json() -> bool {
+    ERRORS_JSON.load(Ordering::Relaxed)
+}
+
+/// Whether progress details, printed through `greppable`-aware macros, are
+/// shown at the current verbosity.
+pub fn shows(level: Verbosity, greppable: bool) -> bool {
+    !greppable && verbosity() >= level
+}
 
 /// Terminal User Interface Module for RustScan
 /// Defines macros to use
+///
+/// Warnings given a [`crate::errors::ErrorCode`] first, with an optional
+/// `host = ...` and `port = ...` context, are printed as JSON objects on
+/// stderr with `--errors-format json`.
 #[macro_export]
 macro_rules! warning {
-    ($name:expr) => {
-        println!("{} {}", ansi_term::Colour::Red.bold().paint("[!]"), $name);
-    };
-    ($name:expr, $greppable:expr, $accessible:expr) => {
-        // if not greppable then print, otherwise no else statement so do not print.
-        if !$greppable {
+    (@text $name:expr, $greppable:expr, $accessible:expr) => {
+        // Printed with the other details, and in quiet mode too.
+        if $crate::tui::shows($crate::tui::Verbosity::Normal, $greppable)
+            || $crate::tui::verbosity() == $crate::tui::Verbosity::Quiet
+        {
             if $accessible {
                 // Don't print the ascii art
-                println!("{}", $name);
+                eprintln!("{}", $name);
             } else {
-                println!("{} {}", ansi_term::Colour::Red.bold().paint("[!]"), $name);
+                eprintln!("{} {}", ansi_term::Colour::Red.bold().paint("[!]"), $name);
             }
         }
     };
+    ($name:expr) => {
+        eprintln!("{} {}", ansi_term::Colour::Red.bold().paint("[!]"), $name);
+    };
+    ($name:expr, $greppable:expr, $accessible:expr) => {
+        $crate::warning!(
+            $crate::errors::ErrorCode::Warning,
+            $name,
+            $greppable,
+            $accessible
+        )
+    };
+    ($code:expr, $name:expr, $greppable:expr, $accessible:expr $(, host = $host:expr)? $(, port = $port:expr)?) => {
+        if $crate::tui::warnings_silenced() {
+        } else if $crate::tui::errors_json() {
+            let event = $crate::errors::ErrorEvent::new($code, &$name)
+                $(.with_host($host))?
+                $(.with_port($port))?;
+            eprintln!("{}", event.to_json());
+        } else {
+            $crate::warning!(@text $name, $greppable, $accessible);
+        }
+    };
 }
 
 #[macro_export]
 macro_rules! detail {
     ($name:expr) => {
-        println!("{} {}", ansi_term::Colour::Blue.bold().paint("[~]"), $name);
+        eprintln!("{} {}", ansi_term::Colour::Blue.bold().paint("[~]"), $name);
     };
     ($name:expr, $greppable:expr, $accessible:expr) => {
         // if not greppable then print, otherwise no else statement so do not print.
-        if !$greppable {
+        if $crate::tui::shows($crate::tui::Verbosity::Normal, $greppable) {
             if $accessible {
                 // Don't print the ascii art
-                println!("{}", $name);
+                eprintln!("{}", $name);
+            } else {
+                eprintln!("{} {}", ansi_term::Colour::Blue.bold().paint("[~]"), $name);
+            }
+        }
+    };
+}
+
+/// Like `detail!`, only printed in verbose mode.
+#[macro_export]
+macro_rules! verbose {
+    ($name:expr, $greppable:expr, $accessible:expr) => {
+        if $crate::tui::shows($crate::tui::Verbosity::Verbose, $greppable) {
+            if $accessible {
+                // Don't print the ascii art
+                eprintln!("{}", $name);
             } else {
-                println!("{} {}", ansi_term::Colour::Blue.bold().paint("[~]"), $name);
+                eprintln!("{} {}", ansi_term::Colour::Blue.bold().paint("[~]"), $name);
             }
         }
     };
 }
 
+/// Prints results for humans, on stdout unlike the other macros.
 #[macro_export]
 macro_rules! output {
     ($name:expr) => {
@@ -49,7 +150,7 @@ macro_rules! output {
     };
     ($name:expr, $greppable:expr, $accessible:expr) => {
         // if not greppable then print, otherwise no else statement so do not print.
-        if !$greppable {
+        if $crate::tui::shows($crate::tui::Verbosity::Normal, $greppable) {
             if $accessible {
                 // Don't print the ascii art
                 println!("{}", $name);
@@ -100,6 +201,6 @@ macro_rules! funny_opening {
         ];
         let random_quote = quotes.choose(&mut rand::thread_rng()).unwrap();
 
-        println!("{}\n", random_quote);
+        eprintln!("{}\n", random_quote);
     };
 }
@@ -0,0 +1,553 @@
+//! Probes the product and version of the services behind open ports with
+//! the rules of `--probe-rules`, a light take on `nmap -sV`.
+//!
+//! A rule names the ports it's for, what to send them and a regex over the
+//! answer, the `product` and `version` groups of which tell the product and
+//! its version. The rules are TOML:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "redis"
+//! ports = "6379,7000-7005"
+//! # Sent as is, nothing to read the greeting of the port.
+//! probe = "INFO server\r\n"
+//! # Or, for binary payloads:
+//! # probe_hex = "0a0b0c"
+//! match = 'redis_version:(?P<version>[\d.]+)'
+//! service = "redis"
+//! # When the regex has no product group.
+//! product = "Redis"
+//! # In milliseconds, for the connection and the answer.
+//! timeout = 1000
+//! ```
+//!
+//! Regexes match the raw bytes of the answer, `(?-u)` lets them match bytes
+//! which aren't UTF-8. The rules of the file go before the built-in ones,
+//! and the rules of a port are tried in order until one matches, each with
+//! its own connection, except for the rules reading the greeting which
+//! share the first one. Ports get at most a few connections whatever the
+//! rules, see [`VersionProber::with_max_probes`].
+use crate::input::{parse_range, PortRange};
+use crate::scripts::nmap::PortService;
+use async_std::io::{self, prelude::*};
+use async_std::net::TcpStream;
+use futures::stream::{FuturesUnordered, StreamExt};
+use regex::bytes::Regex;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The rules every run has, after the ones of `--probe-rules`.
+const DEFAULT_RULES: &str = r#"
+[[rule]]
+name = "ssh"
+ports = "22,2222"
+match = '^SSH-[\d.]+-(?P<product>[A-Za-z]+)[_-](?P<version>[\w.]+)'
+service = "ssh"
+
+[[rule]]
+name = "ftp"
+ports = "21,2121"
+match = '(?i)^220[ -][^\r\n]*?\b(?P<product>ProFTPD|vsFTPd|Pure-FTPd|FileZilla Server)[ ]?(?P<version>\d[\w.]*)?'
+service = "ftp"
+
+[[rule]]
+name = "smtp"
+ports = "25,587,2525"
+match = '^220[ -]\S+ E?SMTP (?P<product>Postfix|Exim|Sendmail|OpenSMTPD)(?:[ /](?P<version>\d[\w.]*))?'
+service = "smtp"
+
+[[rule]]
+name = "mysql"
+ports = "3306"
+match = '(?s-u)^.{4}\x0a(?P<version>[\w.\-]+)\x00'
+service = "mysql"
+product = "MySQL"
+
+[[rule]]
+name = "http"
+ports = "80,81,591,3000,5000,8000,8008,8080,8081,8888"
+probe = "HEAD / HTTP/1.0\r\n\r\n"
+match = '(?ims)^HTTP/[\d.]+ \d+.*?^server:[ \t]*(?P<product>[^/\r\n ]+)(?:/(?P<version>[^\s]+))?'
+service = "http"
+
+[[rule]]
+name = "redis"
+ports = "6379"
+probe = "INFO server\r\n"
+match = 'redis_version:(?P<version>[\d.]+)'
+service = "redis"
+product = "Redis"
+"#;
+
+/// The timeout of rules which don't give one.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1_000);
+
+/// The connections a port gets at most by default.
+pub const DEFAULT_MAX_PROBES: usize = 3;
+
+/// The most of an answer the regexes see.
+const ANSWER_SIZE: usize = 8_192;
+
+/// A rule of the file, as written.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    name: String,
+    ports: String,
+    #[serde(default)]
+    probe: Option<String>,
+    #[serde(default)]
+    probe_hex: Option<String>,
+    #[serde(rename = "match")]
+    pattern: String,
+    service: String,
+    #[serde(default)]
+    product: Option<String>,
+    #[serde(default)]
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RawRule>,
+}
+
+/// What to send to some ports, and how to read their answer.
+#[derive(Debug, Clone)]
+pub struct VersionRule {
+    pub name: String,
+    pub ports: PortRange,
+    /// Nothing to read the greeting of the port.
+    pub probe: Vec<u8>,
+    pub pattern: Regex,
+    pub service: String,
+    /// The product when the regex doesn't capture one.
+    pub product: Option<String>,
+    pub timeout: Duration,
+}
+
+impl VersionRule {
+    pub fn applies_to(&self, port: u16) -> bool {
+        self.ports
+            .ranges
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&port))
+    }
+
+    /// What `answer` of `port` tells, when the regex matches it.
+    pub fn evaluate(&self, port: u16, answer: &[u8]) -> Option<PortService> {
+        let captures = self.pattern.captures(answer)?;
+        let group = |name: &str| {
+            captures
+                .name(name)
+                .map(|group| String::from_utf8_lossy(group.as_bytes()).trim().to_owned())
+                .filter(|group| !group.is_empty())
+        };
+        Some(PortService {
+            port,
+            protocol: "tcp".to_owned(),
+            service: Some(self.service.clone()),
+            product: group("product").or_else(|| self.product.clone()),
+            version: group("version"),
+        })
+    }
+
+    fn reads_greeting(&self) -> bool {
+        self.probe.is_empty()
+    }
+}
+
+/// Parses the rules of a TOML file, telling the rule at fault otherwise.
+pub fn parse_rules(content: &str) -> Result<Vec<VersionRule>, String> {
+    let file: RulesFile = toml::from_str(content).map_err(|e| e.to_string())?;
+    file.rule
+        .into_iter()
+        .map(|raw| {
+            let name = raw.name.clone();
+            rule(raw).map_err(|e| format!("rule {name:?}: {e}"))
+        })
+        .collect()
+}
+
+fn rule(raw: RawRule) -> Result<VersionRule, String> {
+    let probe = match (raw.probe, raw.probe_hex) {
+        (Some(_), Some(_)) => return Err("give either probe or probe_hex".to_owned()),
+        (Some(text), None) => text.into_bytes(),
+        (None, Some(hex)) => parse_hex(&hex)?,
+        (None, None) => Vec::new(),
+    };
+    let timeout = match raw.timeout {
+        Some(0) => return Err("the timeout must be above 0".to_owned()),
+        Some(timeout) => Duration::from_millis(timeout),
+        None => DEFAULT_TIMEOUT,
+    };
+    Ok(VersionRule {
+        ports: parse_range(&raw.ports).map_err(|e| e.to_string())?,
+        pattern: Regex::new(&raw.pattern).map_err(|e| e.to_string())?,
+        name: raw.name,
+        probe,
+        service: raw.service,
+        product: raw.product,
+        timeout,
+    })
+}
+
+/// Reads hex bytes, like `0a ff 00`, spaces allowed between them.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("{hex:?} has an odd amount of hex digits"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("{hex:?} is not hex"))
+        })
+        .collect()
+}
+
+/// The built-in rules.
+pub fn default_rules() -> Vec<VersionRule> {
+    parse_rules(DEFAULT_RULES).expect("The built-in version rules parse.")
+}
+
+/// Reads the rules of the file at `path`, followed by the built-in ones.
+pub fn read_rules(path: &Path) -> Result<Vec<VersionRule>, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut rules = parse_rules(&content)?;
+    rules.extend(default_rules());
+    Ok(rules)
+}
+
+/// Runs the rules against open ports.
+#[derive(Debug, Clone)]
+pub struct VersionProber {
+    rules: Vec<VersionRule>,
+    max_probes: usize,
+}
+
+impl VersionProber {
+    pub fn new(rules: Vec<VersionRule>) -> Self {
+        Self {
+            rules,
+            max_probes: DEFAULT_MAX_PROBES,
+        }
+    }
+
+    /// Sets how many connections a port gets at most, the rules past them
+    /// being left out.
+    #[must_use]
+    pub fn with_max_probes(mut self, max_probes: usize) -> Self {
+        self.max_probes = max_probes.max(1);
+        self
+    }
+
+    /// Probes every socket of `sockets`, at most `concurrency` at a time,
+    /// returning the ones a rule matched.
+    pub async fn probe_all(
+        &self,
+        sockets: &[SocketAddr],
+        concurrency: usize,
+    ) -> HashMap<SocketAddr, PortService> {
+        let mut sockets = sockets.iter();
+        let mut ftrs = FuturesUnordered::new();
+        let mut services = HashMap::new();
+        let probe = |socket: SocketAddr| async move { (socket, self.probe(socket).await) };
+
+        for socket in sockets.by_ref().take(concurrency.max(1)) {
+            ftrs.push(probe(*socket));
+        }
+
+        while let Some((socket, service)) = ftrs.next().await {
+            if let Some(socket) = sockets.next() {
+                ftrs.push(probe(*socket));
+            }
+            if let Some(service) = service {
+                services.insert(socket, service);
+            }
+        }
+
+        services
+    }
+
+    /// Tries the rules of the port of `socket` until one matches.
+    pub async fn probe(&self, socket: SocketAddr) -> Option<PortService> {
+        let mut greeting: Option<Vec<u8>> = None;
+        let mut probes = 0;
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| rule.applies_to(socket.port()))
+        {
+            let answer = match &greeting {
+                Some(greeting) if rule.reads_greeting() => greeting.clone(),
+                _ if probes >= self.max_probes => break,
+                _ => {
+                    probes += 1;
+                    let answer = answer(socket, rule).await;
+                    if rule.reads_greeting() {
+                        greeting = Some(answer.clone());
+                    }
+                    answer
+                }
+            };
+            if let Some(service) = rule.evaluate(socket.port(), &answer) {
+                return Some(service);
+            }
+        }
+        None
+    }
+}
+
+/// Connects to `socket`, sends the probe of `rule` and reads the answer
+/// until its regex matches, the port hangs up or the timeout is over.
+async fn answer(socket: SocketAddr, rule: &VersionRule) -> Vec<u8> {
+    let deadline = Instant::now() + rule.timeout;
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let Ok(mut stream) = io::timeout(remaining(), TcpStream::connect(socket)).await else {
+        return Vec::new();
+    };
+    if !rule.probe.is_empty()
+        && io::timeout(remaining(), stream.write_all(&rule.probe))
+            .await
+            .is_err()
+    {
+        return Vec::new();
+    }
+
+    let mut answer = Vec::new();
+    let mut buffer = vec![0; 2_048];
+    while answer.len() < ANSWER_SIZE && !rule.pattern.is_match(&answer) {
+        match io::timeout(remaining(), stream.read(&mut buffer)).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => answer.extend_from_slice(&buffer[..read]),
+        }
+    }
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_rules, parse_hex, parse_rules, VersionProber, VersionRule};
+    use crate::scripts::nmap::PortService;
+    use async_std::task::block_on;
+    use std::convert::TryInto;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // The service, product and version the built-in rules find in `answer`
+    // of `port`.
+    fn evaluate(port: u16, answer: &[u8]) -> Option<(String, Option<String>, Option<String>)> {
+        let rules = default_rules();
+        let mut rules = rules.iter().filter(|rule| rule.applies_to(port));
+        rules.find_map(|rule| rule.evaluate(port, answer)).map(
+            |PortService {
+                 service,
+                 product,
+                 version,
+                 ..
+             }| (service.unwrap(), product, version),
+        )
+    }
+
+    fn found(
+        service: &str,
+        product: &str,
+        version: Option<&str>,
+    ) -> Option<(String, Option<String>, Option<String>)> {
+        Some((
+            service.to_owned(),
+            Some(product.to_owned()),
+            version.map(ToOwned::to_owned),
+        ))
+    }
+
+    #[test]
+    fn built_in_rules_read_greetings() {
+        assert_eq!(
+            evaluate(22, b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n"),
+            found("ssh", "OpenSSH", Some("9.6p1"))
+        );
+        assert_eq!(
+            evaluate(2222, b"SSH-2.0-dropbear_2022.83\r\n"),
+            found("ssh", "dropbear", Some("2022.83"))
+        );
+        assert_eq!(
+            evaluate(21, b"220 (vsFTPd 3.0.5)\r\n"),
+            found("ftp", "vsFTPd", Some("3.0.5"))
+        );
+        assert_eq!(
+            evaluate(21, b"220 ProFTPD Server (Debian) [::ffff:10.0.0.1]\r\n"),
+            found("ftp", "ProFTPD", None)
+        );
+        assert_eq!(
+            evaluate(25, b"220 mx.example.com ESMTP Postfix (Debian/GNU)\r\n"),
+            found("smtp", "Postfix", None)
+        );
+        assert_eq!(
+            evaluate(
+                587,
+                b"220 mail.example.com ESMTP Exim 4.96 Mon, 14 Oct 2024\r\n"
+            ),
+            found("smtp", "Exim", Some("4.96"))
+        );
+        assert_eq!(
+            evaluate(
+                3306,
+                b"\x4a\x00\x00\x00\x0a8.0.36-0ubuntu0.22.04.1\x00\x08\x00\xff\xfe"
+            ),
+            found("mysql", "MySQL", Some("8.0.36-0ubuntu0.22.04.1"))
+        );
+        // The rules of other ports are left alone.
+        assert_eq!(evaluate(8022, b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+        assert_eq!(evaluate(21, b"530 Go away\r\n"), None);
+    }
+
+    #[test]
+    fn built_in_rules_read_answers() {
+        assert_eq!(
+            evaluate(
+                8080,
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nServer: nginx/1.25.3\r\n\r\n"
+            ),
+            found("http", "nginx", Some("1.25.3"))
+        );
+        assert_eq!(
+            evaluate(80, b"HTTP/1.0 404 Not Found\r\nserver: Caddy\r\n\r\n"),
+            found("http", "Caddy", None)
+        );
+        // No Server header, nothing told.
+        assert_eq!(evaluate(80, b"HTTP/1.1 200 OK\r\n\r\n"), None);
+        assert_eq!(
+            evaluate(
+                6379,
+                b"$3000\r\n# Server\r\nredis_version:7.2.4\r\nredis_git_sha1:0\r\n"
+            ),
+            found("redis", "Redis", Some("7.2.4"))
+        );
+        assert_eq!(
+            evaluate(6379, b"-NOAUTH Authentication required.\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let rules = parse_rules(
+            r#"
+            [[rule]]
+            name = "memcached"
+            ports = "11211,21211-21212"
+            probe = "version\r\n"
+            match = '^VERSION (?P<version>\S+)'
+            service = "memcached"
+            product = "memcached"
+            timeout = 250
+
+            [[rule]]
+            name = "binary"
+            ports = "9000"
+            probe_hex = "de ad BE EF"
+            match = '(?-u)^\xca\xfe(?P<version>\d)'
+            service = "custom"
+            "#,
+        )
+        .unwrap();
+        let [memcached, binary]: [VersionRule; 2] = rules.try_into().unwrap();
+        assert_eq!(memcached.probe, b"version\r\n");
+        assert_eq!(memcached.timeout, Duration::from_millis(250));
+        assert!(memcached.applies_to(21_212) && !memcached.applies_to(21_213));
+        assert_eq!(
+            memcached.evaluate(11_211, b"VERSION 1.6.21\r\n"),
+            Some(PortService {
+                port: 11_211,
+                protocol: "tcp".to_owned(),
+                service: Some("memcached".to_owned()),
+                product: Some("memcached".to_owned()),
+                version: Some("1.6.21".to_owned()),
+            })
+        );
+        assert_eq!(binary.probe, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(binary.timeout, Duration::from_millis(1_000));
+        let service = binary.evaluate(9000, b"\xca\xfe7\xff").unwrap();
+        assert_eq!(
+            (service.product, service.version),
+            (None, Some("7".to_owned()))
+        );
+        assert!(binary.evaluate(9000, b"\xca\xfd7").is_none());
+    }
+
+    #[test]
+    fn ports_get_a_few_connections_at_most() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&connections);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(b"HELLO\r\n");
+            }
+        });
+        let rule = |name: &str, probe: &str| {
+            format!(
+                "[[rule]]\nname = \"{name}\"\nports = \"{}\"\nprobe = \"{probe}\"\n\
+                 match = '^NEVER'\nservice = \"x\"\ntimeout = 200\n",
+                socket.port()
+            )
+        };
+        // The greeting is read once for the three rules reading it.
+        let rules = parse_rules(
+            &["", "", "", "a", "b", "c"]
+                .iter()
+                .enumerate()
+                .map(|(n, probe)| rule(&n.to_string(), probe))
+                .collect::<String>(),
+        )
+        .unwrap();
+
+        let prober = VersionProber::new(rules).with_max_probes(2);
+        assert_eq!(block_on(prober.probe(socket)), None);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn broken_rules_are_told() {
+        let error = |rule: &str| {
+            parse_rules(&format!(
+                "[[rule]]\nname = \"broken\"\nports = \"80\"\nservice = \"x\"\n{rule}"
+            ))
+            .unwrap_err()
+        };
+        assert!(error("match = '('").contains("rule \"broken\""));
+        assert!(error("match = 'a'\nprobe = 'a'\nprobe_hex = '61'").contains("either"));
+        assert!(error("match = 'a'\ntimeout = 0").contains("above 0"));
+        assert!(error("match = 'a'\ntimout = 10").contains("unknown field"));
+        assert!(parse_rules(
+            "[[rule]]\nname = \"x\"\nports = \"80-\"\nmatch = 'a'\nservice = \"x\""
+        )
+        .unwrap_err()
+        .contains("Invalid range"));
+        assert!(parse_rules("").unwrap().is_empty());
+
+        assert_eq!(parse_hex("0a FF00").unwrap(), [0x0a, 0xff, 0x00]);
+        assert!(parse_hex("abc").unwrap_err().contains("odd"));
+        assert!(parse_hex("zz").unwrap_err().contains("not hex"));
+    }
+}
@@ -0,0 +1,876 @@
+//! The times of day probes may start in, see `--allowed-window`.
+//!
+//! An [`AllowedWindow`] is a range of wall clock times like `22:00-06:00`,
+//! which crosses midnight when it ends before it starts, in local time or
+//! at a fixed UTC offset. Whether a time is inside of one is always worked
+//! out from a [`Clock`], never from the system directly, so the windows are
+//! tested with clocks of their own. The local time of [`SystemClock`]
+//! follows the daylight saving time of the system: a window starting at a
+//! time skipped when the clocks go forward opens at the first time after
+//! it, and the hour repeated when they go back is inside of a window twice.
+//!
+//! A [`Guard`] follows the scan from a thread of its own and steers it
+//! through its [`ScanControl`] as the windows open and close. Outside of
+//! them no probe starts while the ones in flight finish, and the sockets
+//! left are written to a [`Checkpoint`], a targets file which resumes the
+//! scan when given to `-a`, as the same run of the same configuration.
+//! With `--no-wait` the scan is stopped at the edge of the windows instead
+//! of waiting for the next one to open.
+use crate::fingerprint::{Fingerprint, ScanConfig};
+use crate::input::PortRange;
+use crate::scanner::{ScanControl, ScanUpdate};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the guard checks the windows.
+pub const POLL: Duration = Duration::from_secs(1);
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How far ahead the next window is looked for: a day, and the hours the
+/// daylight saving time shifts it by.
+const LOOKAHEAD: Duration = Duration::from_secs(2 * DAY as u64);
+
+/// The widest UTC offsets in use, of UTC-12:00 and UTC+14:00.
+const MAX_OFFSET_HOURS: u32 = 14;
+
+/// Where the time, and the local offset of UTC, are read from.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+
+    /// The offset of the local time east of UTC at `at`, in seconds.
+    fn local_offset(&self, at: SystemTime) -> i32;
+}
+
+/// The clock of the system, its local time being the one of the `TZ` or
+/// the settings of the system. Outside of Unix the local time is UTC.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    #[cfg(unix)]
+    fn local_offset(&self, at: SystemTime) -> i32 {
+        let time = unix_seconds(at) as libc::time_t;
+        // SAFETY: localtime_r only writes to the struct it's given, which is
+        // plain old data, and is safe to call from any thread.
+        unsafe {
+            let mut local: libc::tm = std::mem::zeroed();
+            if libc::localtime_r(&time, &mut local).is_null() {
+                return 0;
+            }
+            local.tm_gmtoff as i32
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn local_offset(&self, _at: SystemTime) -> i32 {
+        0
+    }
+}
+
+/// The seconds of `at` since the Unix epoch, negative before it.
+fn unix_seconds(at: SystemTime) -> i64 {
+    match at.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// The time zone the times of a window are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// The local time of the clock, with its daylight saving time.
+    Local,
+    /// A fixed offset east of UTC, in seconds.
+    Offset(i32),
+}
+
+impl Zone {
+    fn offset_at(self, at: SystemTime, clock: &dyn Clock) -> i32 {
+        match self {
+            Zone::Local => clock.local_offset(at),
+            Zone::Offset(offset) => offset,
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("Unknown zone {input:?}, expected local or a UTC offset like UTC+02:00.");
+        let offset = match input {
+            "local" => return Ok(Zone::Local),
+            "UTC" | "GMT" | "Z" => return Ok(Zone::Offset(0)),
+            _ => input
+                .strip_prefix("UTC")
+                .or_else(|| input.strip_prefix("GMT"))
+                .unwrap_or(input),
+        };
+        let (sign, offset) = if let Some(offset) = offset.strip_prefix('+') {
+            (1, offset)
+        } else if let Some(offset) = offset.strip_prefix('-') {
+            (-1, offset)
+        } else {
+            return Err(invalid());
+        };
+        let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "00"));
+        let number = |digits: &str| {
+            (1..=2)
+                .contains(&digits.len())
+                .then(|| digits.parse::<u32>().ok())
+                .flatten()
+        };
+        match (number(hours), number(minutes)) {
+            (Some(hours), Some(minutes))
+                if minutes < 60 && hours * 60 + minutes <= MAX_OFFSET_HOURS * 60 =>
+            {
+                Ok(Zone::Offset(sign * (hours * 3600 + minutes * 60) as i32))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Zone::Local => f.write_str("local"),
+            Zone::Offset(0) => f.write_str("UTC"),
+            Zone::Offset(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let minutes = offset.unsigned_abs() / 60;
+                write!(f, "UTC{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+            }
+        }
+    }
+}
+
+/// A range of the times of day of `--allowed-window`, like `22:00-06:00`
+/// or `09:00-17:00 UTC+02:00`. The start is inside of it, the end isn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedWindow {
+    /// The minutes since midnight it starts at, and ends at, up to a whole
+    /// day for an end at 24:00.
+    start: u32,
+    end: u32,
+    zone: Zone,
+}
+
+impl AllowedWindow {
+    /// Whether `at` is inside of the window, on the wall clock of its zone.
+    pub fn contains(&self, at: SystemTime, clock: &dyn Clock) -> bool {
+        let offset = self.zone.offset_at(at, clock);
+        let minute = ((unix_seconds(at) + i64::from(offset)).rem_euclid(DAY) / 60) as u32;
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            // It crosses midnight.
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for AllowedWindow {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("Invalid window {input:?}, expected HH:MM-HH:MM and an optional zone like UTC+02:00.")
+        };
+        let mut fields = input.split_whitespace();
+        let (Some(range), zone, None) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid());
+        };
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let (Some(start), Some(end)) = (time_of_day(start, false), time_of_day(end, true)) else {
+            return Err(invalid());
+        };
+        if start == end {
+            return Err(format!(
+                "The window {input:?} is empty, use 00:00-24:00 for the whole day."
+            ));
+        }
+        let zone = zone.map_or(Ok(Zone::Local), str::parse)?;
+        Ok(Self { start, end, zone })
+    }
+}
+
+/// The minutes since midnight of a time like `06:30`, and of `24:00`
+/// when it's the `end` of a window.
+fn time_of_day(time: &str, end: bool) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    if !(1..=2).contains(&hours.len()) || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    match (hours, minutes) {
+        (24, 0) if end => Some(24 * 60),
+        _ if hours < 24 && minutes < 60 => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
+
+impl fmt::Display for AllowedWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )?;
+        match self.zone {
+            Zone::Local => Ok(()),
+            zone => write!(f, " {zone}"),
+        }
+    }
+}
+
+/// Whether a probe may start at `at`, inside of any of `windows`.
+pub fn allows(windows: &[AllowedWindow], at: SystemTime, clock: &dyn Clock) -> bool {
+    windows.iter().any(|window| window.contains(at, clock))
+}
+
+/// The first time from `from` on any of `windows` is open, `from` itself
+/// when one already is. None when none opens within two days.
+pub fn next_opening(
+    windows: &[AllowedWindow],
+    from: SystemTime,
+    clock: &dyn Clock,
+) -> Option<SystemTime> {
+    if allows(windows, from, clock) {
+        return Some(from);
+    }
+    // The windows open on whole minutes, the UTC offsets being made of them.
+    let minute = Duration::from_secs(60);
+    let since = from.duration_since(UNIX_EPOCH).ok()?;
+    let mut at = UNIX_EPOCH + Duration::from_secs(since.as_secs() / 60 * 60) + minute;
+    while at <= from + LOOKAHEAD {
+        if allows(windows, at, clock) {
+            return Some(at);
+        }
+        at += minute;
+    }
+    None
+}
+
+/// When the windows open again, like `2026-10-14T20:00:00Z, in 3h 12m`.
+pub fn reopening(reopens: Option<SystemTime>, now: SystemTime) -> String {
+    match reopens {
+        Some(reopens) => {
+            let wait = reopens.duration_since(now).unwrap_or_default();
+            format!(
+                "{}, in {}",
+                humantime::format_rfc3339_seconds(reopens),
+                humantime::format_duration(Duration::from_secs(wait.as_secs().div_ceil(60) * 60))
+            )
+        }
+        None => "no window opens within two days".to_owned(),
+    }
+}
+
+/// What the windows call for since they were last checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    /// The windows closed, no probe starts until they open `reopens`.
+    Pause { reopens: Option<SystemTime> },
+    /// A window opened again.
+    Resume,
+    /// The windows closed with `--no-wait`, the scan ends there.
+    Stop { reopens: Option<SystemTime> },
+}
+
+/// Tells the turns of the windows from one check to the next.
+#[derive(Debug, Clone)]
+pub struct Warden {
+    windows: Vec<AllowedWindow>,
+    no_wait: bool,
+    open: Option<bool>,
+    stopped: bool,
+}
+
+impl Warden {
+    pub fn new(windows: Vec<AllowedWindow>, no_wait: bool) -> Self {
+        Self {
+            windows,
+            no_wait,
+            open: None,
+            stopped: false,
+        }
+    }
+
+    /// The turn the windows took since the last check, the first check only
+    /// turning when they're closed. Nothing turns once stopped.
+    pub fn check(&mut self, clock: &dyn Clock) -> Option<Turn> {
+        if self.stopped {
+            return None;
+        }
+        let now = clock.now();
+        let open = allows(&self.windows, now, clock);
+        match (self.open.replace(open), open) {
+            (Some(true) | None, false) => {
+                let reopens = next_opening(&self.windows, now, clock);
+                self.stopped = self.no_wait;
+                Some(if self.no_wait {
+                    Turn::Stop { reopens }
+                } else {
+                    Turn::Pause { reopens }
+                })
+            }
+            (Some(false), true) => Some(Turn::Resume),
+            _ => None,
+        }
+    }
+}
+
+/// The header of a checkpoint, before its first line.
+const HEADER: &str = "# rustscan checkpoint ";
+
+/// The run which left a checkpoint, told by its header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    pub run_id: String,
+    /// The hash of the configuration of the run, see [`crate::fingerprint`].
+    pub fingerprint: String,
+    /// The targets and the ports of the run, which are the checkpoint's own
+    /// once it's resumed.
+    pub targets: Vec<String>,
+    pub ports: String,
+}
+
+impl Origin {
+    /// The origin of the run `run_id`, of the configuration `fingerprint`.
+    pub fn new(run_id: &str, fingerprint: &Fingerprint) -> Self {
+        Self {
+            run_id: run_id.to_owned(),
+            fingerprint: fingerprint.hash.clone(),
+            targets: fingerprint.config.targets.clone(),
+            ports: fingerprint.config.ports.clone(),
+        }
+    }
+
+    /// The origin in the header of the checkpoint at `path`, if it's one.
+    pub fn read(path: &Path) -> Option<Self> {
+        let mut first = String::new();
+        BufReader::new(File::open(path).ok()?)
+            .read_line(&mut first)
+            .ok()?;
+        serde_json::from_str(first.trim_end().strip_prefix(HEADER)?).ok()
+    }
+
+    /// The origin of the first checkpoint among the targets `addresses`.
+    pub fn find(addresses: &[String]) -> Option<(PathBuf, Self)> {
+        addresses
+            .iter()
+            .flat_map(|addresses| addresses.split([',', ';']))
+            .map(|token| Path::new(token.trim()))
+            .filter(|path| path.is_file())
+            .find_map(|path| Some((path.to_owned(), Self::read(path)?)))
+    }
+
+    /// The fingerprint of a run resuming the checkpoint with `config`, which
+    /// is the one of the run which left it unless more than the targets and
+    /// the ports changed.
+    pub fn resume(&self, mut config: ScanConfig) -> Fingerprint {
+        config.targets = self.targets.clone();
+        config.ports = self.ports.clone();
+        config.fingerprint()
+    }
+
+    fn header(&self) -> String {
+        let json = serde_json::to_string(self).expect("Origins always serialize.");
+        format!("{HEADER}{json}\n")
+    }
+}
+
+/// The targets file of the sockets a scan has left, which resumes it when
+/// given to `-a`: a header telling the [`Origin`] of the scan, then a line
+/// for every host, with its ports.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub origin: Origin,
+    /// The hosts of the scan with their ports.
+    pub planned: Vec<(IpAddr, Vec<u16>)>,
+}
+
+impl Checkpoint {
+    /// The lines of the sockets `planned` which aren't `done`, after the
+    /// header, and how many.
+    pub fn left(&self, done: &HashSet<SocketAddr>) -> (String, usize) {
+        let mut lines = self.origin.header();
+        let mut left = 0;
+        for (ip, ports) in &self.planned {
+            let ports: Vec<u16> = ports
+                .iter()
+                .copied()
+                .filter(|port| !done.contains(&SocketAddr::new(*ip, *port)))
+                .collect();
+            if !ports.is_empty() {
+                left += ports.len();
+                let _ = writeln!(lines, "{ip} {}", PortRange::from_ports(&ports));
+            }
+        }
+        (lines, left)
+    }
+
+    /// Writes the sockets left, giving how many.
+    pub fn write(&self, done: &HashSet<SocketAddr>) -> io::Result<usize> {
+        let (lines, left) = self.left(done);
+        fs::write(&self.path, lines)?;
+        Ok(left)
+    }
+}
+
+/// What the guard did to the scan.
+#[derive(Debug)]
+pub enum Event {
+    /// The scan paused, with how many sockets it left in the checkpoint.
+    Paused {
+        reopens: Option<SystemTime>,
+        left: io::Result<usize>,
+    },
+    Resumed,
+    /// The scan stopped with `--no-wait`. The checkpoint is written again
+    /// by [`Guard::finish`], without the probes which were in flight.
+    Stopped {
+        reopens: Option<SystemTime>,
+        left: io::Result<usize>,
+    },
+}
+
+/// Steers a scan by the windows, from a thread of its own.
+#[derive(Debug)]
+pub struct Guard {
+    stop: Sender<()>,
+    thread: JoinHandle<Watch>,
+}
+
+struct Watch {
+    warden: Warden,
+    clock: Box<dyn Clock + Send>,
+    control: ScanControl,
+    checkpoint: Checkpoint,
+    /// The sockets probed, or skipped, so far.
+    done: HashSet<SocketAddr>,
+    written: bool,
+    on_event: Box<dyn FnMut(Event) + Send>,
+}
+
+impl fmt::Debug for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watch")
+            .field("warden", &self.warden)
+            .field("checkpoint", &self.checkpoint.path)
+            .field("done", &self.done.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watch {
+    fn record(&mut self, updates: &Receiver<ScanUpdate>) {
+        for update in updates.try_iter() {
+            if let ScanUpdate::Probed(socket) | ScanUpdate::Skipped(socket) = update {
+                self.done.insert(socket);
+            }
+        }
+    }
+
+    fn check(&mut self) {
+        let Some(turn) = self.warden.check(&*self.clock) else {
+            return;
+        };
+        let event = match turn {
+            Turn::Pause { reopens } => {
+                self.control.set_held(true);
+                Event::Paused {
+                    reopens,
+                    left: self.write(),
+                }
+            }
+            Turn::Resume => {
+                self.control.set_held(false);
+                Event::Resumed
+            }
+            Turn::Stop { reopens } => {
+                self.control.stop();
+                Event::Stopped {
+                    reopens,
+                    left: self.write(),
+                }
+            }
+        };
+        (self.on_event)(event);
+    }
+
+    fn write(&mut self) -> io::Result<usize> {
+        self.written = true;
+        self.checkpoint.write(&self.done)
+    }
+}
+
+impl Guard {
+    /// Follows the scan of `control` through its `updates`, checking the
+    /// windows of `warden` every `poll`. They're checked once before this
+    /// returns, so that a scan started outside of them never probes.
+    pub fn start(
+        warden: Warden,
+        clock: impl Clock + Send + 'static,
+        control: ScanControl,
+        checkpoint: Checkpoint,
+        updates: Receiver<ScanUpdate>,
+        poll: Duration,
+        on_event: impl FnMut(Event) + Send + 'static,
+    ) -> Self {
+        let mut watch = Watch {
+            warden,
+            clock: Box::new(clock),
+            control,
+            checkpoint,
+            done: HashSet::new(),
+            written: false,
+            on_event: Box::new(on_event),
+        };
+        watch.check();
+
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while stopped.recv_timeout(poll) == Err(RecvTimeoutError::Timeout) {
+                watch.record(&updates);
+                watch.check();
+            }
+            watch.record(&updates);
+            watch
+        });
+        Self { stop, thread }
+    }
+
+    /// Stops following the scan once it's over, and gives how many sockets
+    /// it left in the checkpoint. The checkpoint of a scan which went
+    /// through it all is removed, leaving none, and a scan which never was
+    /// out of the windows has none either.
+    pub fn finish(self) -> io::Result<usize> {
+        let _ = self.stop.send(());
+        let watch = self
+            .thread
+            .join()
+            .map_err(|_| io::Error::other("the guard of --allowed-window panicked"))?;
+        if !watch.written {
+            return Ok(0);
+        }
+        let (lines, left) = watch.checkpoint.left(&watch.done);
+        if left == 0 {
+            match fs::remove_file(&watch.checkpoint.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => return Ok(0),
+            }
+        }
+        fs::write(&watch.checkpoint.path, lines)?;
+        Ok(left)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        allows, next_opening, reopening, AllowedWindow, Checkpoint, Clock, Origin, Turn, Warden,
+        Zone,
+    };
+    use crate::fingerprint::{Fingerprint, ScanConfig};
+    use crate::input::{Opts, PortRange};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const HOUR: u64 = 3600;
+
+    /// A clock set by hand, whose local time is `offset` east of UTC.
+    struct Mock {
+        now: Mutex<SystemTime>,
+        offset: fn(SystemTime) -> i32,
+    }
+
+    impl Mock {
+        fn at(now: SystemTime) -> Self {
+            Self {
+                now: Mutex::new(now),
+                offset: |_| 0,
+            }
+        }
+
+        fn set(&self, now: SystemTime) {
+            *self.now.lock().unwrap() = now;
+        }
+    }
+
+    impl Clock for Mock {
+        fn now(&self) -> SystemTime {
+            *self.now.lock().unwrap()
+        }
+
+        fn local_offset(&self, at: SystemTime) -> i32 {
+            (self.offset)(at)
+        }
+    }
+
+    // 2026-03-29, a Sunday, at 00:00 UTC.
+    fn day() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_774_742_400)
+    }
+
+    fn time(hours: u64, minutes: u64) -> SystemTime {
+        day() + Duration::from_secs(hours * HOUR + minutes * 60)
+    }
+
+    fn windows(windows: &[&str]) -> Vec<AllowedWindow> {
+        windows
+            .iter()
+            .map(|window| window.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn windows_are_parsed() {
+        for (input, shown) in [
+            ("22:00-06:00", "22:00-06:00"),
+            ("9:30-17:00  UTC", "09:30-17:00 UTC"),
+            ("09:00-24:00 UTC+02:00", "09:00-24:00 UTC+02:00"),
+            ("00:00-12:00 -05:30", "00:00-12:00 UTC-05:30"),
+            ("00:00-12:00 GMT+1", "00:00-12:00 UTC+01:00"),
+            ("00:00-12:00 local", "00:00-12:00"),
+        ] {
+            let window: AllowedWindow = input.parse().unwrap();
+            assert_eq!(window.to_string(), shown);
+            assert_eq!(window.to_string().parse::<AllowedWindow>(), Ok(window));
+        }
+        for input in [
+            "",
+            "22:00",
+            "22:00-",
+            "24:00-06:00",
+            "22:00-06:60",
+            "22:00-6:0",
+            "22h-06h",
+            "22:00-06:00 Europe/Paris",
+            "22:00-06:00 UTC+15:00",
+            "22:00-06:00 UTC 1",
+            "08:00-08:00",
+        ] {
+            assert!(input.parse::<AllowedWindow>().is_err(), "{}", input);
+        }
+        assert_eq!("UTC-12".parse(), Ok(Zone::Offset(-12 * 3600)));
+    }
+
+    #[test]
+    fn windows_cross_midnight() {
+        let clock = Mock::at(day());
+        let night = windows(&["22:00-06:00"]);
+        for (hours, minutes, inside) in [
+            (0, 0, true),
+            (5, 59, true),
+            (6, 0, false),
+            (12, 0, false),
+            (21, 59, false),
+            (22, 0, true),
+            (23, 59, true),
+        ] {
+            assert_eq!(
+                allows(&night, time(hours, minutes), &clock),
+                inside,
+                "{}:{}",
+                hours,
+                minutes
+            );
+        }
+        assert_eq!(
+            next_opening(&night, time(12, 0) + Duration::from_secs(17), &clock),
+            Some(time(22, 0))
+        );
+        assert_eq!(next_opening(&night, time(23, 0), &clock), Some(time(23, 0)));
+
+        // The first of the windows to open.
+        let both = windows(&["22:00-06:00", "12:30-13:00 UTC+01:00"]);
+        assert!(allows(&both, time(11, 45), &clock));
+        assert_eq!(next_opening(&both, time(9, 0), &clock), Some(time(11, 30)));
+        assert_eq!(next_opening(&both, time(12, 0), &clock), Some(time(22, 0)));
+    }
+
+    #[test]
+    fn windows_follow_daylight_saving_time() {
+        // UTC+01:00 until 01:00 UTC, when 02:00 local becomes 03:00.
+        let forward = Mock {
+            now: Mutex::new(day()),
+            offset: |at| if at < time(1, 0) { 3600 } else { 7200 },
+        };
+        // 02:30 local never happens, the window opens at 03:00 local.
+        let skipped = windows(&["02:30-04:00"]);
+        assert_eq!(
+            next_opening(&skipped, time(0, 0), &forward),
+            Some(time(1, 0))
+        );
+        assert!(!allows(&skipped, time(0, 59), &forward));
+        assert!(!allows(&skipped, time(2, 0), &forward));
+        // A window of fixed offset doesn't move.
+        let fixed = windows(&["02:30-04:00 UTC+01:00"]);
+        assert_eq!(
+            next_opening(&fixed, time(0, 0), &forward),
+            Some(time(1, 30))
+        );
+
+        // UTC+02:00 until 01:00 UTC, when 03:00 local becomes 02:00 again.
+        let back = Mock {
+            now: Mutex::new(day()),
+            offset: |at| if at < time(1, 0) { 7200 } else { 3600 },
+        };
+        let repeated = windows(&["02:00-03:00"]);
+        assert!(allows(&repeated, time(0, 30), &back));
+        assert!(allows(&repeated, time(1, 30), &back));
+        assert!(!allows(&repeated, time(2, 0), &back));
+    }
+
+    #[test]
+    fn warden_pauses_and_resumes() {
+        let clock = Mock::at(time(12, 0));
+        let mut warden = Warden::new(windows(&["22:00-06:00"]), false);
+        assert_eq!(
+            warden.check(&clock),
+            Some(Turn::Pause {
+                reopens: Some(time(22, 0))
+            })
+        );
+        clock.set(time(21, 59));
+        assert_eq!(warden.check(&clock), None);
+        clock.set(time(22, 0));
+        assert_eq!(warden.check(&clock), Some(Turn::Resume));
+        clock.set(time(23, 0));
+        assert_eq!(warden.check(&clock), None);
+        clock.set(time(30, 0));
+        assert_eq!(
+            warden.check(&clock),
+            Some(Turn::Pause {
+                reopens: Some(time(46, 0))
+            })
+        );
+
+        // Inside of a window at first, nothing turns.
+        let mut warden = Warden::new(windows(&["22:00-06:00"]), true);
+        clock.set(time(23, 0));
+        assert_eq!(warden.check(&clock), None);
+        clock.set(time(30, 0));
+        assert_eq!(
+            warden.check(&clock),
+            Some(Turn::Stop {
+                reopens: Some(time(46, 0))
+            })
+        );
+        clock.set(time(46, 0));
+        assert_eq!(warden.check(&clock), None);
+    }
+
+    #[test]
+    fn checkpoint_lists_the_sockets_left() {
+        let first = "192.0.2.1".parse().unwrap();
+        let second = "2001:db8::1".parse().unwrap();
+        let checkpoint = Checkpoint {
+            path: "unused".into(),
+            origin: origin(&config("192.0.2.1")),
+            planned: vec![(first, vec![443, 80, 81, 82]), (second, vec![22])],
+        };
+        let mut done: HashSet<SocketAddr> = HashSet::new();
+        done.insert(SocketAddr::new(first, 81));
+        let (lines, left) = checkpoint.left(&done);
+        assert_eq!(left, 4);
+        assert_eq!(
+            lines.lines().skip(1).collect::<Vec<_>>(),
+            ["192.0.2.1 80,82,443", "2001:db8::1 22"]
+        );
+        done.insert(SocketAddr::new(second, 22));
+        assert_eq!(checkpoint.left(&done).1, 3);
+    }
+
+    #[test]
+    fn checkpoint_resumes_its_run() {
+        let path = std::env::temp_dir().join(format!(
+            "rustscan-checkpoint-{}-round-trip.txt",
+            std::process::id()
+        ));
+        let left = config("192.0.2.0/24");
+        let checkpoint = Checkpoint {
+            path: path.clone(),
+            origin: origin(&left),
+            planned: vec![("192.0.2.1".parse().unwrap(), vec![80])],
+        };
+        checkpoint.write(&HashSet::new()).unwrap();
+        let read = Origin::read(&path);
+        let found = Origin::find(&[format!("192.0.2.9,{}", path.display())]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.as_ref(), Some(&checkpoint.origin));
+        assert_eq!(found, Some((path, checkpoint.origin.clone())));
+
+        // Resumed from the checkpoint, with the ports of the defaults.
+        let mut opts = Opts {
+            addresses: vec!["rustscan-checkpoint.txt".to_owned()],
+            ..Opts::default()
+        };
+        let ports = PortRange {
+            ranges: vec![(1, 65_535)],
+        };
+        let resumed = ScanConfig::new(&opts, &ports, None);
+        assert_eq!(checkpoint.origin.resume(resumed), left);
+        opts.timeout += 1;
+        let other = ScanConfig::new(&opts, &ports, None);
+        assert!(!checkpoint.origin.resume(other).matches(&left.hash));
+    }
+
+    #[test]
+    fn only_checkpoints_have_an_origin() {
+        assert_eq!(Origin::read(Path::new("Cargo.toml")), None);
+        assert_eq!(Origin::find(&["Cargo.toml,192.0.2.1".to_owned()]), None);
+    }
+
+    fn config(targets: &str) -> Fingerprint {
+        let opts = Opts {
+            addresses: vec![targets.to_owned()],
+            ..Opts::default()
+        };
+        let ports = PortRange {
+            ranges: vec![(80, 80)],
+        };
+        ScanConfig::new(&opts, &ports, None).fingerprint()
+    }
+
+    fn origin(fingerprint: &Fingerprint) -> Origin {
+        Origin::new("engagement-7", fingerprint)
+    }
+
+    #[test]
+    fn reopening_is_shown_with_the_wait() {
+        assert_eq!(
+            reopening(Some(time(22, 0)), time(18, 47) + Duration::from_secs(30)),
+            "2026-03-29T22:00:00Z, in 3h 13m"
+        );
+        assert_eq!(
+            reopening(None, time(0, 0)),
+            "no window opens within two days"
+        );
+    }
+}
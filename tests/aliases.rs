@@ -0,0 +1,141 @@
+/*
+ * Checks that a host given under several names, by its hostname and by its
+ * address, is named by --prefer-names in the JSON report with every name it
+ * was given kept, that --aliases forces its name over the flag, and that its
+ * scripts get that name as {{hostname}}, before and after the scan.
+ */
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rustscan-aliases-{}-{name}", std::process::id()))
+}
+
+fn write_aliases(name: &str) -> PathBuf {
+    let aliases = temp_path(name);
+    std::fs::write(&aliases, r#""127.0.0.1" = "loopback.lab""#).unwrap();
+    aliases
+}
+
+fn rustscan(port: u16, args: &[&str], home: Option<&Path>) -> Output {
+    let mut command = common::rustscan();
+    command
+        .args(["-a", "localhost,127.0.0.1", "-p", &port.to_string()])
+        .args(args);
+    if let Some(home) = home {
+        command
+            .env("HOME", home)
+            .env("ALIASES_LOG", home.join("runs.log"));
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+/// The name and hostnames of 127.0.0.1 in the JSON report of the scan.
+fn loopback(port: u16, args: &[&str]) -> (serde_json::Value, serde_json::Value) {
+    let mut json = vec!["-n", "--format", "json"];
+    json.extend_from_slice(args);
+    let output = rustscan(port, &json, None);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let host = report["hosts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|host| host["ip"] == "127.0.0.1")
+        .unwrap_or_else(|| panic!("{:?}", report))
+        .clone();
+    assert_eq!(
+        host["source"],
+        serde_json::json!(["localhost", "127.0.0.1"])
+    );
+    (host["name"].clone(), host["hostnames"].clone())
+}
+
+#[test]
+fn names_follow_the_precedence_flag() {
+    let (_listener, port) = common::listener();
+    let localhost = serde_json::json!(["localhost"]);
+
+    assert_eq!(loopback(port, &[]), ("localhost".into(), localhost.clone()));
+    assert_eq!(
+        loopback(port, &["--prefer-names", "short"]),
+        ("localhost".into(), localhost.clone())
+    );
+    assert_eq!(
+        loopback(port, &["--prefer-names", "ip"]),
+        ("127.0.0.1".into(), localhost.clone())
+    );
+
+    let aliases = write_aliases("report.toml");
+    let forced = loopback(
+        port,
+        &[
+            "--prefer-names",
+            "ip",
+            "--aliases",
+            aliases.to_str().unwrap(),
+        ],
+    );
+    let _ = std::fs::remove_file(&aliases);
+    assert_eq!(forced, ("loopback.lab".into(), localhost));
+}
+
+#[test]
+fn bad_aliases_abort_the_run() {
+    let aliases = temp_path("bad.toml");
+    std::fs::write(&aliases, r#""localhost" = "loopback.lab""#).unwrap();
+    let output = common::rustscan()
+        .args(["-a", "127.0.0.1", "--aliases"])
+        .arg(&aliases)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&aliases);
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Can't read the aliases"), "{}", stderr);
+}
+
+#[test]
+fn scripts_get_the_canonical_name() {
+    let home = temp_path("home");
+    let scripts = home.join(".rustscan_scripts");
+    std::fs::create_dir_all(&scripts).unwrap();
+    std::fs::write(
+        home.join(".rustscan_scripts.toml"),
+        "tags = [\"aliases\"]\n",
+    )
+    .unwrap();
+    for fixture in ["10-before.sh", "20-after.sh"] {
+        std::fs::copy(format!("fixtures/aliases/{fixture}"), scripts.join(fixture)).unwrap();
+    }
+    let aliases = write_aliases("scripts.toml");
+
+    let (_listener, port) = common::listener();
+    rustscan(
+        port,
+        &[
+            "--accessible",
+            "--scripts",
+            "custom",
+            "--aliases",
+            aliases.to_str().unwrap(),
+        ],
+        Some(&home),
+    );
+    let log = std::fs::read_to_string(home.join("runs.log")).unwrap_or_default();
+    let _ = std::fs::remove_file(&aliases);
+    let _ = std::fs::remove_dir_all(&home);
+    let loopback: Vec<&str> = log
+        .lines()
+        .filter(|line| line.contains(" 127.0.0.1 "))
+        .collect();
+    assert_eq!(
+        loopback,
+        ["pre 127.0.0.1 loopback.lab", "post 127.0.0.1 loopback.lab"],
+        "{}",
+        log
+    );
+}
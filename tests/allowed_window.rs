@@ -0,0 +1,310 @@
+/*
+ * Checks that the guard of --allowed-window steers a real scan by a clock
+ * set by hand: a scan started outside of the windows waits without probing
+ * and goes through once one opens, and with --no-wait it ends at once and
+ * leaves every socket in a checkpoint which resumes it, from the command
+ * line as well.
+ */
+mod common;
+
+use async_std::task::block_on;
+use rustscan::input::{PortRange, ScanOrder};
+use rustscan::listen::Listener;
+use rustscan::port_strategy::PortStrategy;
+use rustscan::scanner::{ScanControl, ScanUpdate, Scanner};
+use rustscan::window::{Checkpoint, Clock, Event, Guard, Origin, Warden};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A clock set by hand, at UTC.
+#[derive(Clone)]
+struct Mock(Arc<Mutex<SystemTime>>);
+
+impl Mock {
+    fn set(&self, hours: u64) {
+        // 2026-10-14 at 00:00 UTC.
+        *self.0.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(1_791_936_000 + hours * 3600);
+    }
+}
+
+impl Clock for Mock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+
+    fn local_offset(&self, _at: SystemTime) -> i32 {
+        0
+    }
+}
+
+/// The lines of the checkpoint at `path` after its header.
+fn sockets(path: &Path) -> String {
+    let checkpoint = fs::read_to_string(path).unwrap();
+    let (header, sockets) = checkpoint.split_once('\n').unwrap();
+    assert!(header.starts_with("# rustscan checkpoint {"), "{}", header);
+    sockets.to_owned()
+}
+
+struct Setup {
+    open: SocketAddr,
+    scanner: Scanner,
+    control: ScanControl,
+    checkpoint: Checkpoint,
+    updates: mpsc::Receiver<ScanUpdate>,
+    probes: mpsc::Receiver<ScanUpdate>,
+    _listener: Listener,
+}
+
+// A scan of the 21 ports up to an open one, followed by the guard and by
+// `probes`.
+fn setup(name: &str) -> Setup {
+    let (listener, port) = common::listener();
+    let open = SocketAddr::new(common::LOCALHOST, port);
+    let ip: IpAddr = open.ip();
+    let range = PortRange {
+        ranges: vec![(open.port() - 20, open.port())],
+    };
+    let control = ScanControl::default();
+    let (feed, updates) = mpsc::channel();
+    let (probes_feed, probes) = mpsc::channel();
+    let scanner = Scanner::new(
+        &[ip],
+        5,
+        Duration::from_millis(500),
+        1,
+        true,
+        PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+        true,
+        vec![],
+        false,
+    )
+    .with_feed(feed)
+    .with_feed(probes_feed)
+    .with_control(control.clone());
+    let checkpoint = Checkpoint {
+        path: std::env::temp_dir()
+            .join(format!("rustscan-window-{}-{name}.txt", std::process::id())),
+        origin: Origin {
+            run_id: name.to_owned(),
+            fingerprint: "0123456789abcdef".to_owned(),
+            targets: vec![ip.to_string()],
+            ports: format!("{}-{}", open.port() - 20, open.port()),
+        },
+        planned: vec![(ip, scanner.host_ports(ip))],
+    };
+    Setup {
+        open,
+        scanner,
+        control,
+        checkpoint,
+        updates,
+        probes,
+        _listener: listener,
+    }
+}
+
+#[test]
+fn scan_waits_for_the_window() {
+    let setup = setup("wait");
+    let clock = Mock(Arc::new(Mutex::new(UNIX_EPOCH)));
+    clock.set(12);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = events.clone();
+    let guard = Guard::start(
+        Warden::new(vec!["22:00-06:00 UTC".parse().unwrap()], false),
+        clock.clone(),
+        setup.control.clone(),
+        setup.checkpoint.clone(),
+        setup.updates,
+        Duration::from_millis(10),
+        move |event| seen.lock().unwrap().push(event),
+    );
+    assert!(setup.control.is_held());
+    let ports = sockets(&setup.checkpoint.path);
+    assert_eq!(
+        ports,
+        format!(
+            "127.0.0.1 {}-{}\n",
+            setup.open.port() - 20,
+            setup.open.port()
+        )
+    );
+
+    let scanner = setup.scanner;
+    let scan = thread::spawn(move || block_on(scanner.scan()));
+    thread::sleep(Duration::from_millis(300));
+    assert!(
+        !setup
+            .probes
+            .try_iter()
+            .any(|update| matches!(update, ScanUpdate::Probed(_))),
+        "probed outside of the window"
+    );
+
+    clock.set(22);
+    let outcome = scan.join().unwrap();
+    assert_eq!(outcome.open, [setup.open]);
+    assert_eq!(outcome.forecast.remaining, 0);
+    assert_eq!(guard.finish().unwrap(), 0);
+    assert!(!setup.checkpoint.path.exists());
+
+    let events = events.lock().unwrap();
+    assert!(
+        matches!(
+            events[..],
+            [
+                Event::Paused {
+                    reopens: Some(_),
+                    left: Ok(21)
+                },
+                Event::Resumed
+            ]
+        ),
+        "{:?}",
+        events
+    );
+}
+
+#[test]
+fn the_window_and_the_user_pause_apart() {
+    let setup = setup("user");
+    let clock = Mock(Arc::new(Mutex::new(UNIX_EPOCH)));
+    clock.set(12);
+    let guard = Guard::start(
+        Warden::new(vec!["22:00-06:00 UTC".parse().unwrap()], false),
+        clock.clone(),
+        setup.control.clone(),
+        setup.checkpoint.clone(),
+        setup.updates,
+        Duration::from_millis(10),
+        |_| {},
+    );
+    let probed = |probes: &mpsc::Receiver<ScanUpdate>| {
+        probes
+            .try_iter()
+            .any(|update| matches!(update, ScanUpdate::Probed(_)))
+    };
+    let scanner = setup.scanner;
+    let scan = thread::spawn(move || block_on(scanner.scan()));
+
+    // Resuming from the dashboard doesn't reopen the window.
+    setup.control.set_paused(false);
+    thread::sleep(Duration::from_millis(300));
+    assert!(!probed(&setup.probes), "probed outside of the window");
+
+    // Nor does the window reopening resume a pause of the user.
+    setup.control.set_paused(true);
+    clock.set(22);
+    thread::sleep(Duration::from_millis(300));
+    assert!(!setup.control.is_held());
+    assert!(!probed(&setup.probes), "probed while paused by the user");
+
+    setup.control.set_paused(false);
+    let outcome = scan.join().unwrap();
+    assert_eq!(outcome.open, [setup.open]);
+    assert_eq!(guard.finish().unwrap(), 0);
+}
+
+#[test]
+fn scan_ends_outside_of_the_window_with_no_wait() {
+    let setup = setup("no-wait");
+    let clock = Mock(Arc::new(Mutex::new(UNIX_EPOCH)));
+    clock.set(12);
+    let guard = Guard::start(
+        Warden::new(vec!["22:00-06:00 UTC".parse().unwrap()], true),
+        clock,
+        setup.control.clone(),
+        setup.checkpoint.clone(),
+        setup.updates,
+        Duration::from_millis(10),
+        |_| {},
+    );
+    assert!(setup.control.is_stopped());
+
+    let outcome = block_on(setup.scanner.scan());
+    assert!(outcome.open.is_empty());
+    assert_eq!(outcome.forecast.remaining, 21);
+    assert_eq!(guard.finish().unwrap(), 21);
+    let ports = sockets(&setup.checkpoint.path);
+    fs::remove_file(&setup.checkpoint.path).unwrap();
+    assert_eq!(
+        ports,
+        format!(
+            "127.0.0.1 {}-{}\n",
+            setup.open.port() - 20,
+            setup.open.port()
+        )
+    );
+}
+
+#[test]
+fn no_wait_leaves_a_checkpoint_which_resumes_the_scan() {
+    let (_listener, port) = common::listener();
+    let checkpoint =
+        std::env::temp_dir().join(format!("rustscan-window-{}-cli.txt", std::process::id()));
+    // A window of an hour, twelve hours from now.
+    let hour = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 3600
+        % 24;
+    let window = format!("{:02}:00-{:02}:00 UTC", (hour + 12) % 24, (hour + 13) % 24);
+    let rustscan = |args: &[&str]| {
+        common::rustscan()
+            .args(["-g", "--no-config"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let stopped = rustscan(&[
+        "-a",
+        "127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "--allowed-window",
+        &window,
+        "--no-wait",
+        "--checkpoint",
+        checkpoint.to_str().unwrap(),
+        "--run-id",
+        "engagement-7",
+    ]);
+    assert!(stopped.status.success(), "{:?}", stopped);
+    assert!(stopped.stdout.is_empty(), "{:?}", stopped);
+    assert_eq!(sockets(&checkpoint), format!("127.0.0.1 {port}\n"));
+
+    // Only its targets and ports are the checkpoint's own.
+    let changed = common::rustscan()
+        .args(["--no-config", "-t", "999", "-a"])
+        .arg(&checkpoint)
+        .output()
+        .unwrap();
+    assert_eq!(changed.status.code(), Some(1), "{:?}", changed);
+    assert!(
+        String::from_utf8_lossy(&changed.stderr).contains("was left by a run with the fingerprint"),
+        "{:?}",
+        changed
+    );
+
+    let resumed = rustscan(&["-a", checkpoint.to_str().unwrap()]);
+    let json = common::rustscan()
+        .args(["--no-config", "--format", "json", "-a"])
+        .arg(&checkpoint)
+        .output()
+        .unwrap();
+    fs::remove_file(&checkpoint).unwrap();
+    assert!(resumed.status.success(), "{:?}", resumed);
+    assert_eq!(
+        String::from_utf8_lossy(&resumed.stdout).trim(),
+        format!("127.0.0.1 -> [{port}]")
+    );
+    let report: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(report["run_id"], "engagement-7", "{:?}", json);
+}
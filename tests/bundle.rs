@@ -0,0 +1,145 @@
+/*
+ * Checks that `rustscan bundle export` writes the configuration of a scan
+ * with the files it reads, that `rustscan bundle run` scans with it from
+ * anywhere, and that a bundle whose custom scripts are missing is refused
+ * unless --ignore-missing-scripts. The scripts are installed in a HOME of
+ * their own.
+ */
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustscan-bundle-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rustscan(home: &Path, args: &[&str]) -> Output {
+    common::rustscan()
+        .args(args)
+        .env("HOME", home)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn exported_scan_is_run_again() {
+    let dir = temp_dir("run");
+    let (_listener, port) = common::listener();
+    let targets = dir.join("targets.txt");
+    std::fs::write(&targets, "127.0.0.1\n").unwrap();
+    let bundle = dir.join("engagement.toml");
+    let bundle = bundle.to_str().unwrap();
+
+    let output = rustscan(
+        &dir,
+        &[
+            "-a",
+            targets.to_str().unwrap(),
+            "-p",
+            &port.to_string(),
+            "--seed",
+            "7",
+            "--scripts",
+            "none",
+            "bundle",
+            "export",
+            bundle,
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    // The targets travel with the bundle.
+    std::fs::remove_file(&targets).unwrap();
+
+    let output = rustscan(&dir, &["--format", "json", "bundle", "run", bundle]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["hosts"][0]["ip"], "127.0.0.1");
+    assert_eq!(report["hosts"][0]["ports"], serde_json::json!([port]));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn missing_scripts_refuse_the_bundle() {
+    let dir = temp_dir("scripts");
+    let scripts = dir.join(".rustscan_scripts");
+    std::fs::create_dir_all(&scripts).unwrap();
+    std::fs::write(dir.join(".rustscan_scripts.toml"), "tags = [\"audit\"]\n").unwrap();
+    std::fs::write(
+        scripts.join("audit.sh"),
+        "#!/bin/bash\n# tags = [\"audit\"]\n# call_format = \"true\"\n",
+    )
+    .unwrap();
+    let bundle = dir.join("engagement.toml");
+    let bundle = bundle.to_str().unwrap();
+
+    let output = rustscan(
+        &dir,
+        &[
+            "-a",
+            "127.0.0.1",
+            "-p",
+            "1",
+            "--scripts",
+            "custom",
+            "bundle",
+            "export",
+            bundle,
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    assert!(std::fs::read_to_string(bundle)
+        .unwrap()
+        .contains("scripts = [\"audit.sh\"]"));
+    std::fs::remove_file(scripts.join("audit.sh")).unwrap();
+
+    let output = rustscan(&dir, &["--accessible", "bundle", "run", bundle]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("The scripts audit.sh of the bundle are missing from the scripts folder"),
+        "{}",
+        stderr
+    );
+
+    let output = rustscan(
+        &dir,
+        &[
+            "--accessible",
+            "bundle",
+            "run",
+            bundle,
+            "--ignore-missing-scripts",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("scanning anyway."), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn invalid_bundle_is_refused_with_its_location() {
+    let dir = temp_dir("invalid");
+    let bundle = dir.join("engagement.toml");
+    std::fs::write(&bundle, "bundle_version = 9\n").unwrap();
+
+    let output = rustscan(
+        &dir,
+        &["--accessible", "bundle", "run", bundle.to_str().unwrap()],
+    );
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("line 1, column 18: bundle_version 9 can't be read, only 1"),
+        "{}",
+        stderr
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
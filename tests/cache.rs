@@ -0,0 +1,57 @@
+/*
+ * Checks that a host with a fresh cache entry isn't probed again, counting
+ * the connections a listener accepts during every run.
+ */
+mod common;
+
+use std::path::Path;
+
+/// The results of the scan, and the details on stderr.
+fn rustscan(cache: &Path, ports: &str, extra: &[&str]) -> (String, String) {
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-a", "127.0.0.1"])
+        .args(["-p", ports, "--cache"])
+        .arg(cache)
+        .args(extra)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn cached_hosts_are_not_probed_again() {
+    let cache = std::env::temp_dir().join(format!("rustscan-cache-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache);
+    let (listener, port) = common::listener();
+    let port = port.to_string();
+    let expected = format!("127.0.0.1 -> [{port}]");
+
+    let (stdout, _) = rustscan(&cache, &port, &[]);
+    assert!(stdout.contains(&expected), "{:?}", stdout);
+    assert_eq!(common::probes(&listener), 1);
+
+    // The cached open port is reported without a single probe.
+    let (stdout, stderr) = rustscan(&cache, &port, &[]);
+    assert!(stdout.contains(&expected), "{:?}", stdout);
+    assert!(stderr.contains("Using the cached scan of 127.0.0.1"));
+    assert_eq!(common::probes(&listener), 0);
+
+    // Other ports make another entry.
+    let ports = format!("{port},1");
+    let (stdout, _) = rustscan(&cache, &ports, &["--no-cache-write"]);
+    assert!(stdout.contains(&expected), "{:?}", stdout);
+    assert_eq!(common::probes(&listener), 1);
+    let (_, stderr) = rustscan(&cache, &ports, &[]);
+    assert!(!stderr.contains("Using the cached scan"), "{:?}", stderr);
+    assert_eq!(common::probes(&listener), 1);
+
+    let (stdout, _) = rustscan(&cache, &port, &["--refresh"]);
+    assert!(stdout.contains(&expected), "{:?}", stdout);
+    assert_eq!(common::probes(&listener), 1);
+
+    let _ = std::fs::remove_dir_all(&cache);
+}
@@ -0,0 +1,16 @@
+/*
+ * Checks that --capabilities prints the privileges of the process and the
+ * features they allow, then exits before anything is scanned.
+ */
+mod common;
+
+#[test]
+fn capabilities_are_printed() {
+    let output = common::rustscan().arg("--capabilities").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.starts_with("Platform: "), "{}", report);
+    assert!(report.contains("\nPrivileges: "), "{}", report);
+    assert!(report.contains("\n  raw sockets: "), "{}", report);
+}
@@ -0,0 +1,75 @@
+/*
+ * Checks that the cap of --max-children holds for the scripts and the port
+ * hooks together: with both running at the same time, from threads of
+ * their own, no more children than the cap run at once.
+ */
+use rustscan::scripts::children::CHILDREN;
+use rustscan::scripts::port_hooks::{run_hooks, HookTarget, PortHook};
+use rustscan::scripts::Script;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::thread;
+use std::time::Duration;
+
+const CAP: usize = 3;
+
+#[cfg(unix)]
+#[test]
+fn cap_holds_across_scripts_and_port_hooks() {
+    let dir = std::env::temp_dir().join(format!("rustscan-children-{}", std::process::id()));
+    let running = dir.join("running");
+    fs::create_dir_all(&running).unwrap();
+    let log = dir.join("seen.log");
+    // Every child counts the children running with it, itself included.
+    let command = format!(
+        "touch {running}/$$; ls {running} | wc -l >> {log}; sleep 0.2; rm {running}/$$",
+        running = running.display(),
+        log = log.display()
+    );
+    CHILDREN.set_limit(CAP);
+
+    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let hook = PortHook {
+        name: "count".to_owned(),
+        command: command.clone(),
+        ports: Vec::new(),
+        hints: Vec::new(),
+        timeout: None,
+    };
+    let targets: Vec<HookTarget> = (1..=8)
+        .map(|port| HookTarget {
+            ip,
+            port,
+            hint: None,
+        })
+        .collect();
+
+    thread::scope(|scope| {
+        let hooks = scope.spawn(|| run_hooks(&[hook], &targets, Duration::from_secs(10), 8, None));
+        let scripts: Vec<_> = (0..6)
+            .map(|_| {
+                let script =
+                    Script::build(None, ip, vec![80], None, None, None, Some(command.clone()));
+                scope.spawn(move || script.run())
+            })
+            .collect();
+
+        for script in scripts {
+            script.join().unwrap().unwrap();
+        }
+        let runs = hooks.join().unwrap();
+        assert_eq!(runs.len(), 8);
+        assert!(runs.iter().all(|(_, run)| run.succeeded()), "{:?}", runs);
+    });
+
+    let seen: Vec<usize> = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim().parse().unwrap())
+        .collect();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(seen.len(), 14);
+    assert!(seen.iter().all(|&count| count <= CAP), "{:?}", seen);
+    assert_eq!(CHILDREN.peak(), CAP);
+    assert_eq!(CHILDREN.running(), 0);
+}
@@ -0,0 +1,56 @@
+/*
+ * Checks that greppable and JSON scans print their results alone on stdout,
+ * whatever else they have to say: a batch size above the file limit, a
+ * target which doesn't resolve, the banner and the verbose details all go to
+ * stderr.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(port: u16, args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["-a", "127.0.0.1,rustscan-test.invalid", "-p"])
+        .arg(port.to_string())
+        .args(["-b", "200", "-u", "100", "--verbose", "--scripts", "none"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn greppable_stdout_has_the_results_only() {
+    let (_listener, port) = common::listener();
+
+    let output = rustscan(port, &["--greppable"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("127.0.0.1 -> [{port}]\n"));
+}
+
+#[test]
+fn json_stdout_parses() {
+    let (_listener, port) = common::listener();
+
+    let output = rustscan(port, &["--format", "json"]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{e}: {}", String::from_utf8_lossy(&output.stdout)));
+    assert_eq!(report["hosts"][0]["ports"], serde_json::json!([port]));
+
+    // The details are still there, on stderr.
+    let output = rustscan(port, &[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stdout.contains(&format!("127.0.0.1 -> [{port}]")),
+        "{}",
+        stdout
+    );
+    assert!(
+        !stdout.contains("[!]") && !stdout.contains("[~]"),
+        "{}",
+        stdout
+    );
+    assert!(stderr.contains("rustscan-test.invalid"), "{}", stderr);
+}
@@ -0,0 +1,80 @@
+/*
+ * What the integration tests share: the binary of the crate to run, and the
+ * listener of `rustscan listen` for the ports they scan, whose connections
+ * are counted to tell what a scan probed.
+ */
+// Every test uses some of these only.
+#![allow(dead_code)]
+
+use rustscan::listen::{Answer, Listener, Protocol, Summary};
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+pub const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// The binary of the crate, without the `RUST_LOG` of the tests.
+pub fn rustscan() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rustscan"));
+    command.env_remove("RUST_LOG");
+    command
+}
+
+/// Runs the binary with `args` only.
+pub fn run(args: &[&str]) -> Output {
+    rustscan().args(args).output().unwrap()
+}
+
+/// A listener of `count` open ports of `address`, and the ports. Their
+/// connections are left for [`probes`] to count, unless it's served.
+pub fn listener_on(address: IpAddr, count: usize) -> (Listener, Vec<u16>) {
+    let (listener, failures) = Listener::bind(address, &vec![0; count], &[Protocol::Tcp]);
+    assert!(failures.is_empty(), "{:?}", failures);
+    let ports = listener
+        .local_addrs()
+        .iter()
+        .map(|(_, address)| address.port())
+        .collect();
+    (listener, ports)
+}
+
+/// A listener of an open port of 127.0.0.1, and the port.
+pub fn listener() -> (Listener, u16) {
+    let (listener, ports) = listener_on(LOCALHOST, 1);
+    (listener, ports[0])
+}
+
+/// A port of 127.0.0.1 nothing listens on, which refuses connections.
+pub fn closed_port() -> u16 {
+    listener().1
+}
+
+/// How many connections the listener got since the last call.
+pub fn probes(listener: &Listener) -> u64 {
+    // Stopped already, it answers what is waiting and returns.
+    let stop = AtomicBool::new(true);
+    listener.serve(&Answer::Nothing, &stop, |_| {}).total()
+}
+
+/// Serves `listener` with `answer` while `run` runs, giving what `run` gave
+/// and what connected meanwhile.
+pub fn serving<T>(listener: &Listener, answer: &Answer, run: impl FnOnce() -> T) -> (T, Summary) {
+    // Set even when `run` panics, or the scope would wait on the listener.
+    struct Stop<'a>(&'a AtomicBool);
+    impl Drop for Stop<'_> {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let served = scope.spawn(|| listener.serve(answer, &stop, |_| {}));
+        let ran = {
+            let _stop = Stop(&stop);
+            run()
+        };
+        (ran, served.join().unwrap())
+    })
+}
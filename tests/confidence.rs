@@ -0,0 +1,67 @@
+/*
+ * Checks that a port whose spread tries disagree, refused at first and open
+ * later, is labeled flaky in the JSON report and flagged where it is found,
+ * and that --export-exclude-flaky leaves it out of the exports.
+ */
+mod common;
+
+use rustscan::listen::{Listener, Protocol};
+use std::fs;
+use std::process::{Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+// Scans a port which is closed for its first try and opens after a second,
+// before its second try two seconds later.
+fn scan_flaky_port(args: &[&str]) -> (u16, Output) {
+    let port = common::closed_port();
+    let child = common::rustscan()
+        .args(["-n", "-a", "127.0.0.1", "-p", &port.to_string()])
+        .args(["--tries", "2", "--spread-tries", "2s"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    let (listener, failures) = Listener::bind(common::LOCALHOST, &[port], &[Protocol::Tcp]);
+    assert!(failures.is_empty(), "{:?}", failures);
+    let output = child.wait_with_output().unwrap();
+    drop(listener);
+    assert!(output.status.success(), "{:?}", output);
+    (port, output)
+}
+
+#[test]
+fn flaky_port_is_labeled_and_left_out_of_exports() {
+    let exported = std::env::temp_dir().join(format!("rustscan-flaky-{}.txt", std::process::id()));
+    let export = format!("hostport={}", exported.display());
+    let (port, output) = scan_flaky_port(&[
+        "--format",
+        "json",
+        "--export",
+        &export,
+        "--export-exclude-flaky",
+    ]);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let host = &report["hosts"][0];
+    assert_eq!(host["ports"], serde_json::json!([port]));
+    assert_eq!(
+        host["confidence"],
+        serde_json::json!([{ "port": port, "label": "flaky", "tries": ["refused", "open"] }])
+    );
+    assert_eq!(fs::read_to_string(&exported).unwrap(), "");
+    fs::remove_file(&exported).unwrap();
+}
+
+#[test]
+fn flaky_port_is_flagged_when_found() {
+    let (port, output) = scan_flaky_port(&["--accessible"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("Open 127.0.0.1:{port} (flaky)")),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,59 @@
+/*
+ * Checks that the sockets created by each probe and the ones taken from the
+ * socket pool of --preallocate-sockets tell the same ports of localhost open
+ * and closed.
+ */
+mod common;
+
+use async_std::task::block_on;
+use rustscan::input::{PortRange, ScanOrder};
+use rustscan::port_strategy::PortStrategy;
+use rustscan::scanner::{ScanOutcome, Scanner};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+fn scan(ports: &[u16], pooled: bool) -> ScanOutcome {
+    let range = PortRange::from_ports(ports);
+    let scanner = Scanner::new(
+        &["127.0.0.1".parse::<IpAddr>().unwrap()],
+        // Fewer sockets than ports, so that the pool hands out several batches.
+        4,
+        Duration::from_millis(1_000),
+        1,
+        true,
+        PortStrategy::pick(&Some(range), None, ScanOrder::Random),
+        true,
+        vec![],
+        false,
+    );
+    let scanner = if pooled {
+        scanner.with_socket_pool()
+    } else {
+        scanner
+    };
+    block_on(scanner.scan())
+}
+
+#[test]
+fn both_paths_classify_ports_alike() {
+    let (_listener, open) = common::listener_on(common::LOCALHOST, 6);
+    let mut open: Vec<SocketAddr> = open
+        .into_iter()
+        .map(|port| SocketAddr::new(common::LOCALHOST, port))
+        .collect();
+    open.sort_unstable();
+    let closed: Vec<u16> = (0..6).map(|_| common::closed_port()).collect();
+    let ports: Vec<u16> = open
+        .iter()
+        .map(SocketAddr::port)
+        .chain(closed.iter().copied())
+        .collect();
+
+    for pooled in [false, true] {
+        let mut outcome = scan(&ports, pooled);
+        outcome.open.sort_unstable();
+        assert_eq!(outcome.open, open, "pooled: {}", pooled);
+        // Refused, the other ports aren't filtered either.
+        assert!(outcome.filtered.is_empty(), "{:?}", outcome.filtered);
+    }
+}
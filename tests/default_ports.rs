@@ -0,0 +1,66 @@
+/*
+ * Checks that a run given no ports falls back on the default set of its
+ * protocol, and that --dry-run and the JSON report say which one it was.
+ */
+mod common;
+
+#[test]
+fn dry_run_shows_the_default_ports() {
+    let output = common::rustscan()
+        .args(["-n", "--udp", "--dry-run", "-a", "127.0.0.1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let printed = String::from_utf8(output.stdout).unwrap();
+    assert!(printed.contains("Hosts: 127.0.0.1\n"), "{}", printed);
+    assert!(printed.contains("Protocol: UDP\n"), "{}", printed);
+    assert!(
+        printed.contains("Ports: 7,9,17,19,49,53,67-69,"),
+        "{}",
+        printed
+    );
+    assert!(
+        printed.contains("(the 100 most frequently open UDP ports, the default)"),
+        "{}",
+        printed
+    );
+}
+
+#[test]
+fn given_ports_are_no_default() {
+    let output = common::rustscan()
+        .args(["-n", "--dry-run", "-a", "127.0.0.1", "-p", "443,80,81"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let printed = String::from_utf8(output.stdout).unwrap();
+    assert!(printed.contains("Ports: 80-81,443\n"), "{}", printed);
+    assert!(!printed.contains("the default)"), "{}", printed);
+}
+
+#[test]
+fn report_names_the_default_ports() {
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--udp",
+            "--top",
+            "--format",
+            "json",
+            "-a",
+            "127.0.0.1",
+        ])
+        .args(["-t", "50", "--tries", "1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["default_ports"]["set"], "top-udp");
+    assert_eq!(
+        report["default_ports"]["ports"],
+        "53,67-69,123,135,137-139,161-162,445,500,514,520,631,1434,1900,4500,49152"
+    );
+}
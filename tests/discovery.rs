@@ -0,0 +1,65 @@
+/*
+ * Checks that --discovery scans the ports of the hosts which answer and
+ * reports the others as skipped, down or assumed down, and that its
+ * deadline can't be given without it.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["--accessible", "--no-config", "-t", "500"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn hosts_which_dont_answer_are_left_out() {
+    let (_listener, port) = common::listener();
+    // The documentation network is routed nowhere, its probes time out or
+    // fail, whichever the network of the machine makes of them.
+    let output = rustscan(&[
+        "-a",
+        "127.0.0.1,2001:db8::1",
+        "-p",
+        &port.to_string(),
+        "--discovery",
+        "--discovery-timeout",
+        "5s",
+        "--discovery-undetermined",
+        "dead",
+        "--format",
+        "json",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let host = |ip: &str| {
+        report["hosts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|host| host["ip"] == ip)
+            .unwrap_or_else(|| panic!("{:?}", report))
+            .clone()
+    };
+
+    let up = host("127.0.0.1");
+    assert_eq!(up["discovery"], "alive");
+    assert_eq!(up["ports"], serde_json::json!([port]));
+    let down = host("2001:db8::1");
+    assert_eq!(down["scanned"], false);
+    assert_eq!(down["skipped_reason"], "host-down");
+    assert!(
+        ["dead", "assumed-dead"].contains(&down["discovery"].as_str().unwrap()),
+        "{}",
+        down
+    );
+}
+
+#[test]
+fn the_deadline_needs_the_discovery() {
+    let output = rustscan(&["-a", "127.0.0.1", "--discovery-timeout", "5s"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
@@ -0,0 +1,60 @@
+/*
+ * Runs --egress-check against local listeners bound on a couple of ports,
+ * with a port nothing listens on, and checks the allowed and blocked split
+ * of the report.
+ */
+mod common;
+
+use std::process::Output;
+
+fn check(args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["--egress-check", "127.0.0.1", "--tries", "1"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn ports_are_split_into_allowed_and_blocked() {
+    let (_listener, mut allowed) = common::listener_on(common::LOCALHOST, 2);
+    allowed.sort_unstable();
+    let blocked = common::closed_port();
+    let ports = format!("{},{blocked},{}", allowed[0], allowed[1]);
+
+    let output = check(&["-p", &ports, "--format", "json"]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["mode"], "egress");
+    assert_eq!(report["listener"], "127.0.0.1");
+    assert_eq!(report["allowed"], serde_json::json!(allowed));
+    assert_eq!(report["blocked"], serde_json::json!([blocked]));
+    assert!(report.get("hosts").is_none());
+
+    // The lines say it's an egress check, not open ports of a target.
+    let output = check(&["-p", &ports, "--accessible", "--scripts", "none"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!(
+            "Egress check against 127.0.0.1 (127.0.0.1): 2 of 3 ports allowed out\n\
+             Allowed out: [{},{}]",
+            allowed[0], allowed[1]
+        )),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("Open 127.0.0.1"), "{}", stdout);
+
+    let output = check(&["-p", &ports, "-g"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        format!("egress 127.0.0.1 -> [{},{}]\n", allowed[0], allowed[1])
+    );
+}
+
+#[test]
+fn egress_checks_have_no_targets() {
+    let output = check(&["-a", "127.0.0.1"]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,42 @@
+/*
+ * Checks that hosts without open ports get a record when asked to.
+ */
+
+mod common;
+
+// 192.0.2.1 is in the TEST-NET-1 documentation range and never answers.
+fn scan(port: u16, args: &[&str]) -> String {
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-t", "500"])
+        .args(["-a", "127.0.0.1,192.0.2.1", "-p", &port.to_string()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn empty_hosts_are_shown_on_demand() {
+    let (_listener, port) = common::listener();
+
+    assert_eq!(scan(port, &["-g"]), format!("127.0.0.1 -> [{port}]\n"));
+    assert_eq!(
+        scan(port, &["-g", "--show-empty-hosts"]),
+        format!("127.0.0.1 -> [{port}]\n192.0.2.1 -> []\n")
+    );
+}
+
+#[test]
+fn json_always_has_empty_hosts() {
+    let (_listener, port) = common::listener();
+
+    let json: serde_json::Value = serde_json::from_str(&scan(port, &["--format", "json"])).unwrap();
+    let hosts = json["hosts"].as_array().unwrap();
+
+    assert_eq!(hosts.len(), 2);
+    assert_eq!(hosts[0]["ports"], serde_json::json!([port]));
+    assert_eq!(hosts[1]["ip"], "192.0.2.1");
+    assert_eq!(hosts[1]["ports"], serde_json::json!([]));
+    assert_eq!(hosts[1]["scanned"], true);
+}
@@ -0,0 +1,78 @@
+/*
+ * Checks that `--errors-format json` reports every error on stderr as one
+ * JSON object per line, with the exit code of the error, and that malformed
+ * targets are shown with where they are malformed.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none"])
+        .args(["--errors-format", "json"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The codes of the error events on stderr, in order.
+fn codes(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(|line| {
+            let event: serde_json::Value = serde_json::from_str(line)
+                .unwrap_or_else(|e| panic!("{:?} is not an error event: {}", line, e));
+            assert!(event["message"].is_string(), "{:?}", line);
+            event["code"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn unresolved_host_is_reported() {
+    let output = rustscan(&["-a", "/nonexistent/targets.txt"]);
+
+    assert_eq!(codes(&output), ["unresolved-host", "no-targets"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("could not be resolved"), "{:?}", stdout);
+}
+
+#[test]
+fn invalid_port_range_is_reported() {
+    let output = rustscan(&["-a", "127.0.0.1", "-p", "abc"]);
+
+    assert_eq!(codes(&output), ["invalid-arguments"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn invalid_target_ports_are_reported() {
+    let output = rustscan(&["-a", "127.0.0.1=80,abc"]);
+
+    assert_eq!(codes(&output), ["invalid-target", "no-targets"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(r#""host":"127.0.0.1=80,abc""#),
+        "{:?}",
+        stderr
+    );
+}
+
+#[test]
+fn malformed_cidr_is_shown_with_its_position() {
+    let output = rustscan(&["-a", "10.0.0.0/-1"]);
+
+    assert_eq!(codes(&output), ["invalid-target", "no-targets"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            r#"Invalid CIDR \"10.0.0.0/-1\", the prefix length at column 10 must be from 0 to 32."#
+        ),
+        "{:?}",
+        stderr
+    );
+}
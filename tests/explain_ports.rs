@@ -0,0 +1,68 @@
+/*
+ * Checks that --explain-ports prints where the ports of a target come from
+ * without scanning, and that a run whose exclusions leave a host without
+ * ports aborts with the same table instead of scanning nothing.
+ */
+mod common;
+
+fn rustscan(args: &[&str]) -> std::process::Output {
+    common::rustscan()
+        .args(["--accessible", "--no-config"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn ports_of_a_target_are_explained() {
+    let output = rustscan(&[
+        "-a",
+        "192.0.2.1;192.0.2.2=22-25",
+        "-r",
+        "1-100",
+        "-e",
+        "23",
+        "--explain-ports",
+        "192.0.2.2",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "Ports of 192.0.2.2:",
+            "Source               Ports  Effect",
+            "ports of the target  22-25  included",
+            "--range              1-100  overridden by ports of the target",
+            "--exclude-ports      23     excluded 23",
+            "Scanned: 22,24-25",
+        ],
+        "{}",
+        stdout
+    );
+
+    let output = rustscan(&["-a", "192.0.2.1", "--explain-ports", "192.0.2.9"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not one of the targets"));
+}
+
+#[test]
+fn hosts_left_without_ports_abort_the_run() {
+    let output = rustscan(&["-a", "127.0.0.1", "-p", "80,443", "-e", "80,443"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "No port left to scan on 127.0.0.1, --exclude-ports excluded the last of them"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("excluded 80,443, leaving no port"),
+        "{}",
+        stderr
+    );
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Open"));
+}
@@ -0,0 +1,96 @@
+/*
+ * Checks that equivalent invocations get the same configuration fingerprint,
+ * that the JSON report carries it and that --verify-config aborts a run
+ * whose configuration doesn't match. The JSON plan of --dry-run carries it
+ * too.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(args: &[&str]) -> Output {
+    common::rustscan().arg("-n").args(args).output().unwrap()
+}
+
+fn dry_run_fingerprint(args: &[&str]) -> String {
+    let output = rustscan(&[args, &["--dry-run"]].concat());
+    assert!(output.status.success(), "{:?}", output);
+    let printed = String::from_utf8(output.stdout).unwrap();
+    printed
+        .lines()
+        .find_map(|line| line.strip_prefix("Fingerprint: "))
+        .unwrap_or_else(|| panic!("{}", printed))
+        .to_owned()
+}
+
+#[test]
+fn equivalent_invocations_share_a_fingerprint() {
+    let fingerprint = dry_run_fingerprint(&["-a", "127.0.0.1,127.0.0.2", "-p", "80,443"]);
+    assert_eq!(
+        fingerprint,
+        dry_run_fingerprint(&["-a", "127.0.0.2", "-a", "127.0.0.1", "-p", "443,80,443"])
+    );
+    assert_ne!(
+        fingerprint,
+        dry_run_fingerprint(&["-a", "127.0.0.1,127.0.0.2", "-p", "80,443", "-t", "500"])
+    );
+}
+
+#[test]
+fn report_carries_the_fingerprint() {
+    let args = ["-a", "127.0.0.1", "-p", "1", "-t", "100"];
+    let fingerprint = dry_run_fingerprint(&args);
+    let output = rustscan(
+        &[
+            &args[..],
+            &["--format", "json", "--verify-config", &fingerprint],
+        ]
+        .concat(),
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["fingerprint"]["hash"], fingerprint.as_str());
+    assert_eq!(
+        report["fingerprint"]["config"]["targets"],
+        serde_json::json!(["127.0.0.1"])
+    );
+    assert_eq!(report["fingerprint"]["config"]["ports"], "1");
+}
+
+#[test]
+fn dry_run_plan_carries_the_fingerprint() {
+    let args = ["-a", "127.0.0.1;127.0.0.2=22-24", "-p", "80,443"];
+    let fingerprint = dry_run_fingerprint(&args);
+    let output = rustscan(&[&args[..], &["--dry-run", "--format", "json"]].concat());
+    assert!(output.status.success(), "{:?}", output);
+
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["fingerprint"]["hash"], fingerprint.as_str());
+    assert_eq!(plan["hosts"][0]["sockets"], 2);
+    assert_eq!(plan["hosts"][1]["ports"], "22-24");
+    assert_eq!(plan["sockets"], 5);
+}
+
+#[test]
+fn mismatching_configuration_aborts() {
+    let output = rustscan(&[
+        "-a",
+        "127.0.0.1",
+        "-p",
+        "1",
+        "--errors-format",
+        "json",
+        "--verify-config",
+        "0000000000000000",
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // The details go to stderr too, as text.
+    let codes: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .map(|event| event["code"].clone())
+        .collect();
+    assert!(codes.contains(&"config-mismatch".into()), "{}", stderr);
+}
@@ -0,0 +1,130 @@
+/*
+ * Checks that the server of --serve-report answers the page of a report at
+ * / and its JSON report at /report.json, that a scan serves its results
+ * until Ctrl-C, and that --html-report writes the same page to a file.
+ */
+mod common;
+
+use rustscan::address::Target;
+use rustscan::html::{self, Server, JSON_PATH};
+use rustscan::input::HostOrder;
+use rustscan::report::ScanReport;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The status line and the body of the answer to a GET of `path`.
+fn get(address: SocketAddr, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: {address}\r\n\r\n").unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+    let (head, body) = answer.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_owned(), body.to_owned())
+}
+
+#[test]
+fn server_answers_the_page_and_the_json_report() {
+    let target = Target {
+        ip: "192.0.2.1".parse().unwrap(),
+        hostnames: vec!["web01.corp.local".to_owned()],
+        sources: vec!["web01.corp.local".to_owned()],
+        ports: None,
+    };
+    let open: Vec<SocketAddr> = vec!["192.0.2.1:443".parse().unwrap()];
+    let report = ScanReport::new(&[target], &open, HostOrder::Input);
+    let page = html::render(&report, JSON_PATH);
+    let json = report.to_json();
+
+    let server = Server::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let address = server.local_addr().unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let serving = {
+        let (page, json, stop) = (page.clone(), json.clone(), stop.clone());
+        thread::spawn(move || server.serve(&page, &json, &stop))
+    };
+
+    assert_eq!(
+        get(address, "/"),
+        ("HTTP/1.1 200 OK".to_owned(), page.clone())
+    );
+    assert!(page.contains("<a href=\"/report.json\">Raw JSON</a>"));
+    assert!(page.contains("<a href=\"#host-0\">192.0.2.1</a>"));
+    let (status, body) = get(address, JSON_PATH);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, json);
+    let served: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(served["hosts"][0]["ports"], serde_json::json!([443]));
+    assert_eq!(get(address, "/missing").0, "HTTP/1.1 404 Not Found");
+
+    stop.store(true, Ordering::SeqCst);
+    assert_eq!(serving.join().unwrap(), 3);
+}
+
+#[test]
+#[cfg(unix)]
+fn scan_results_are_served_until_interrupted() {
+    let (_listener, port) = common::listener();
+    let address = SocketAddr::new(common::LOCALHOST, common::closed_port());
+
+    let child = common::rustscan()
+        .args(["-g", "-a", "127.0.0.1", "-p", &port.to_string()])
+        .args(["--serve-report", &address.to_string()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while TcpStream::connect(address).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(20), "never served");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let (status, page) = get(address, "/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(page.contains(&format!("<tr><td>{port}</td>")), "{}", page);
+    let (_, json) = get(address, JSON_PATH);
+    let report: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(report["hosts"][0]["ports"], serde_json::json!([port]));
+
+    // SAFETY: signals the child spawned above, which is still running.
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!("127.0.0.1 -> [{port}]")
+    );
+}
+
+#[test]
+fn html_report_is_written_to_a_file() {
+    let (_listener, port) = common::listener();
+    let path = std::env::temp_dir().join(format!("rustscan-report-{}.html", std::process::id()));
+
+    let output = common::rustscan()
+        .args([
+            "-g",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+            "--html-report",
+        ])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let page = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>"), "{}", page);
+    assert!(page.contains("<a href=\"#raw\">Raw JSON</a>"));
+    assert!(page.contains(&format!("<tr><td>{port}</td>")), "{}", page);
+    assert!(page.contains("<details id=\"raw\">"));
+}
@@ -0,0 +1,75 @@
+/*
+ * Checks that --import scans the hosts of a masscan list, with
+ * --import-ports-only exactly the ports the list found open, counting the
+ * connections every listener accepts, and that a malformed nmap report
+ * aborts the scan telling where it's broken.
+ */
+mod common;
+
+fn import_file(name: &str, content: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("rustscan-{name}-{}", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn only_the_imported_ports_are_probed() {
+    let (imported, imported_port) = common::listener();
+    let (other, other_port) = common::listener();
+    let list = import_file(
+        "masscan.txt",
+        &format!(
+            "#masscan\nopen tcp {imported_port} 127.0.0.1 1700000000\n\
+             open tcp {imported_port} 127.0.0.1 1700000001\n# end\n"
+        ),
+    );
+
+    // The host of the list is added to the empty --addresses.
+    let ports = format!("{imported_port},{other_port}");
+    let output = common::rustscan()
+        .args(["-n", "--greppable", "-p", &ports, "--import"])
+        .arg(&list)
+        .arg("--import-ports-only")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("127.0.0.1 -> [{imported_port}]\n"));
+    assert_eq!(common::probes(&imported), 1);
+    assert_eq!(common::probes(&other), 0);
+
+    // Without --import-ports-only the host gets every port.
+    let output = common::rustscan()
+        .args(["-n", "--greppable", "-p", &ports, "--import"])
+        .arg(&list)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::probes(&imported), 1);
+    assert_eq!(common::probes(&other), 1);
+
+    let _ = std::fs::remove_file(&list);
+}
+
+#[test]
+fn malformed_reports_abort_the_scan() {
+    let report = import_file(
+        "nmap.xml",
+        "<?xml version=\"1.0\"?>\n<nmaprun>\n<host><status state=\"up\"/>\n\
+         <address addr=\"127.0.0.1\" addrtype=\"ipv4\"/>\n",
+    );
+    let output = common::rustscan()
+        .args(["-n", "-a", "127.0.0.1", "--import"])
+        .arg(&report)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("line 3: <host> is never closed"),
+        "{}",
+        stderr
+    );
+
+    let _ = std::fs::remove_file(&report);
+}
@@ -0,0 +1,108 @@
+/*
+ * Checks that --interface-map binds the probes of every host to its
+ * interface, saying which one in verbose output, and that the hosts whose
+ * interface can't be used are reported as skipped with the reason instead
+ * of being scanned.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["--accessible", "--no-config", "-t", "500"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn hosts_use_the_interface_of_their_network() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let output = rustscan(&[
+        "-a",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "--verbose",
+        "--interface-map",
+        "127.0.0.0/8=lo,default=rustscan-none",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Host 127.0.0.1 uses the interface lo, from 127.0.0.1"),
+        "{}",
+        stderr
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("Open 127.0.0.1:{port}")),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn hosts_of_unusable_interfaces_are_skipped() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let args = [
+        "-a",
+        "127.0.0.1,192.0.2.1",
+        "-p",
+        &port,
+        "--interface-map",
+        "192.0.2.0/24=rustscan-none,default=lo",
+    ];
+    let output = rustscan(&args);
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Skipping the 1 host(s) going out by rustscan-none, the interface rustscan-none doesn't exist."
+        ),
+        "{}",
+        stderr
+    );
+
+    let output = rustscan(&[&args[..], &["--format", "json"]].concat());
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let host = |ip: &str| {
+        report["hosts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|host| host["ip"] == ip)
+            .unwrap_or_else(|| panic!("{:?}", report))
+            .clone()
+    };
+    let skipped = host("192.0.2.1");
+    assert_eq!(skipped["scanned"], false);
+    assert_eq!(skipped["skipped_reason"], "interface-unusable");
+    assert_eq!(
+        skipped["interface"],
+        serde_json::json!({
+            "name": "rustscan-none",
+            "unusable": "the interface rustscan-none doesn't exist",
+        })
+    );
+    let scanned = host("127.0.0.1");
+    assert_eq!(
+        scanned["ports"],
+        serde_json::json!([port.parse::<u16>().unwrap()])
+    );
+    assert_eq!(
+        scanned["interface"],
+        serde_json::json!({"name": "lo", "source": "127.0.0.1"})
+    );
+}
+
+#[test]
+fn malformed_maps_are_refused() {
+    let output = rustscan(&["-a", "127.0.0.1", "--interface-map", "10.0.0.0/8"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected network=interface"));
+}
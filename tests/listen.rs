@@ -0,0 +1,116 @@
+/*
+ * Checks that scans find the ports of the listener of `rustscan listen`,
+ * started in this process, over TCP and UDP, and that `rustscan listen`
+ * reports the ports it can't bind, serves the others and prints what
+ * connected when interrupted.
+ */
+mod common;
+
+use rustscan::listen::{Answer, Listener, Protocol, Summary};
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::process::Stdio;
+
+// Scans the listener of `protocol` with the extra `args`, checking that its
+// port is found open, and gives what the listener saw.
+fn scan_listener(protocol: Protocol, args: &[&str]) -> Summary {
+    let (listener, failures) = Listener::bind(common::LOCALHOST, &[0], &[protocol]);
+    assert!(failures.is_empty(), "{:?}", failures);
+    let port = listener.local_addrs()[0].1.port();
+
+    let answer = Answer::Banner("hello".to_owned());
+    let (output, summary) = common::serving(&listener, &answer, || {
+        common::rustscan()
+            .args([
+                "-a",
+                "127.0.0.1",
+                "--format",
+                "json",
+                "-p",
+                &port.to_string(),
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+    });
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["hosts"][0]["ports"], serde_json::json!([port]));
+    summary
+}
+
+#[test]
+fn tcp_listener_is_found_open() {
+    let summary = scan_listener(Protocol::Tcp, &[]);
+    assert!(summary.total() >= 1);
+    assert!(summary.to_string().contains("connection(s) from 127.0.0.1"));
+}
+
+#[test]
+fn udp_listener_is_found_open() {
+    let summary = scan_listener(Protocol::Udp, &["--udp"]);
+    assert!(summary.total() >= 1);
+    assert!(summary.to_string().contains("datagram(s) from 127.0.0.1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn listen_serves_the_free_ports_until_interrupted() {
+    let (_taken, taken_port) = common::listener();
+    let free_port = common::closed_port();
+
+    let mut child = common::rustscan()
+        .args([
+            "--accessible",
+            "listen",
+            "--address",
+            "127.0.0.1",
+            "--banner",
+            "hi",
+        ])
+        .arg("--ports")
+        .arg(format!("{taken_port},{free_port}"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(
+        line,
+        "Listening on 1 socket(s) of 127.0.0.1, Ctrl-C to stop\n"
+    );
+
+    let mut banner = String::new();
+    TcpStream::connect(("127.0.0.1", free_port))
+        .unwrap()
+        .read_to_string(&mut banner)
+        .unwrap();
+    assert_eq!(banner, "hi\n");
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert!(
+        line.contains(&format!(" tcp/{free_port} <- 127.0.0.1:")),
+        "{}",
+        line
+    );
+
+    // SAFETY: the pid is the one of the child, still running.
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert_eq!(
+        rest,
+        format!("tcp/{free_port}: 1 connection(s) from 127.0.0.1\n")
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("Could not listen on tcp/{taken_port}: ")),
+        "{}",
+        stderr
+    );
+}
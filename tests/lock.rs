@@ -0,0 +1,112 @@
+/*
+ * Checks that a run with --lock warns about another live run locking some
+ * of its targets, that --lock-strict refuses to scan them, that the locks
+ * of runs which are gone are cleaned up, and that a run removes its own
+ * lockfile once done.
+ */
+mod common;
+
+use rustscan::lock::{LockRecord, LockScope};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustscan-locks-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("rustscan-locks")).unwrap();
+    dir
+}
+
+/// Leaves the lockfile of a run of `pid` scanning `network`.
+fn lock(tmp: &Path, run_id: &str, pid: u32, network: &str) {
+    let ips: Vec<IpAddr> = vec![];
+    let mut record = LockRecord::new(
+        run_id,
+        "0123456789abcdef",
+        LockScope::new(&[network.to_owned()], &ips),
+    );
+    record.pid = pid;
+    std::fs::write(
+        tmp.join("rustscan-locks").join(format!("{run_id}.lock")),
+        serde_json::to_string(&record).unwrap(),
+    )
+    .unwrap();
+}
+
+fn rustscan(tmp: &Path, port: u16, lock: &str) -> Output {
+    common::rustscan()
+        .args([
+            "--accessible",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+            lock,
+        ])
+        .env("TMPDIR", tmp)
+        .output()
+        .unwrap()
+}
+
+fn lockfiles(tmp: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(tmp.join("rustscan-locks"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn overlapping_runs_are_warned_or_refused() {
+    let (_listener, port) = common::listener();
+    let tmp = temp_dir("overlap");
+    // This test stands for the other run, it's alive.
+    lock(&tmp, "other-run", std::process::id(), "127.0.0.0/8");
+
+    let warned = rustscan(&tmp, port, "--lock");
+    assert!(warned.status.success(), "{:?}", warned);
+    let stdout = String::from_utf8_lossy(&warned.stdout);
+    assert!(
+        stdout.contains(&format!("Open 127.0.0.1:{port}")),
+        "{}",
+        stdout
+    );
+    let stderr = String::from_utf8_lossy(&warned.stderr);
+    assert!(
+        stderr.contains("locked by another run: run other-run of PID"),
+        "{}",
+        stderr
+    );
+
+    let refused = rustscan(&tmp, port, "--lock-strict");
+    assert_eq!(refused.status.code(), Some(1), "{:?}", refused);
+    let stdout = String::from_utf8_lossy(&refused.stdout);
+    assert!(!stdout.contains("Open 127.0.0.1"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert!(stderr.contains("because of --lock-strict"), "{}", stderr);
+
+    // Neither run left its lockfile behind.
+    assert_eq!(lockfiles(&tmp), ["other-run.lock"]);
+    let _ = std::fs::remove_dir_all(&tmp);
+}
+
+#[test]
+fn stale_locks_are_cleaned_up() {
+    let (_listener, port) = common::listener();
+    let tmp = temp_dir("stale");
+    let mut child = Command::new("true").spawn().unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    lock(&tmp, "crashed-run", dead, "127.0.0.1");
+    // Another part of the loopback network, locked by a live run.
+    lock(&tmp, "apart-run", std::process::id(), "127.0.1.0/24");
+
+    let output = rustscan(&tmp, port, "--lock-strict");
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("locked"), "{}", stderr);
+    assert_eq!(lockfiles(&tmp), ["apart-run.lock"]);
+    let _ = std::fs::remove_dir_all(&tmp);
+}
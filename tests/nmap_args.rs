@@ -0,0 +1,78 @@
+/*
+ * Runs the nmap handoff against a stand-in nmap which echoes its argv, its
+ * output forwarded to stdout.
+ */
+
+mod common;
+
+use std::env;
+use std::path::Path;
+
+fn scan(port: u16, args: &[&str]) -> String {
+    let shim = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/nmap/bin");
+    let mut path = vec![shim];
+    path.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+    let (soft, _) = rlimit::Resource::NOFILE.get().unwrap();
+
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--accessible",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+        ])
+        .args(["-b", "10", "-u", &soft.to_string()])
+        .args(args)
+        .env("PATH", env::join_paths(path).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+#[cfg(unix)]
+fn nmap_args_are_passed_as_argv() {
+    let (_listener, port) = common::listener();
+
+    let stdout = scan(
+        port,
+        &[
+            "--forward-script-output",
+            "--nmap-args",
+            r#"-sV --script "a,b" --script-args 'x=1 y=2'"#,
+        ],
+    );
+
+    let argv = format!("[-vvv][-p][{port}][-sV][--script][a,b][--script-args][x=1 y=2][127.0.0.1]");
+    assert!(stdout.lines().any(|line| line == argv), "{}", stdout);
+}
+
+#[test]
+#[cfg(unix)]
+fn nmap_services_are_folded_into_json() {
+    let (_listener, port) = common::listener();
+
+    let stdout = scan(port, &["--format", "json", "--nmap-args", "-sV"]);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    let services = &json["hosts"][0]["services"];
+    assert_eq!(services[0]["port"], 80);
+    assert_eq!(services[0]["service"], "http");
+    assert_eq!(services[0]["product"], "Fake httpd");
+    assert_eq!(services[0]["version"], "1.0");
+}
+
+#[test]
+fn trailing_arguments_are_validated_too() {
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "-a", "127.0.0.1", "-p", "80"])
+        .args(["--nmap-args", "-sV", "--", "-Pn", "10.0.0.1"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("\"10.0.0.1\" is a target"), "{}", stderr);
+}
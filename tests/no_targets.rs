@@ -0,0 +1,64 @@
+/*
+ * Checks that a run left without targets, here by --scope and by
+ * --import-ports-only, exits with the code of its own and tells what every
+ * stage of the filtering removed, in the message and in the JSON plan.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(name: &str, content: &str, args: &[&str]) -> Output {
+    let path = std::env::temp_dir().join(format!("rustscan-{name}-{}", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-p", "80"])
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    output
+}
+
+#[test]
+fn out_of_scope_targets_leave_nothing() {
+    let output = rustscan(
+        "empty-scope.txt",
+        "10.0.0.0/8\n",
+        &[
+            "-a",
+            "127.0.0.1,127.0.0.1",
+            "--scope-mode",
+            "warn",
+            "--scope",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "No targets left to scan, aborting scan: 2 parsed -> 2 resolved -> \
+             1 deduped (1 duplicates) -> 0 in scope (1 out of scope)."
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn imported_hosts_without_ports_leave_nothing() {
+    let report = "<nmaprun>\n<host><status state=\"up\"/>\
+                  <address addr=\"127.0.0.1\" addrtype=\"ipv4\"/></host>\n</nmaprun>\n";
+    let output = rustscan(
+        "empty-import.xml",
+        report,
+        &["--format", "json", "--import-ports-only", "--import"],
+    );
+    assert_eq!(output.status.code(), Some(3), "{:?}", output);
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(plan["hosts"], serde_json::json!([]));
+    assert_eq!(
+        plan["stages"][3],
+        serde_json::json!({"stage": "imported", "removed": 1, "left": 0})
+    );
+}
@@ -0,0 +1,42 @@
+/*
+ * Checks that the notes of --notes end up in the JSON report of the hosts
+ * they match, by IP address or by hostname, and nowhere else.
+ */
+mod common;
+
+#[test]
+fn notes_are_attached_to_matching_hosts() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+
+    let notes = std::env::temp_dir().join(format!("rustscan-notes-{}.toml", std::process::id()));
+    std::fs::write(
+        &notes,
+        r#"
+            "127.0.0.1" = "loopback"
+            "LOCALHOST" = "this machine"
+            "192.0.2.1" = "decommissioned"
+        "#,
+    )
+    .unwrap();
+
+    let output = common::rustscan()
+        .args(["-n", "--format", "json", "--show-empty-hosts", "-p", &port])
+        .args(["-a", "localhost,127.0.0.2", "--notes"])
+        .arg(&notes)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&notes);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let hosts = report["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 2, "{:?}", report);
+    assert_eq!(hosts[0]["ip"], "127.0.0.1");
+    assert_eq!(
+        hosts[0]["notes"],
+        serde_json::json!(["loopback", "this machine"])
+    );
+    assert_eq!(hosts[1]["ip"], "127.0.0.2");
+    assert!(hosts[1].get("notes").is_none(), "{:?}", hosts[1]);
+}
@@ -0,0 +1,131 @@
+/*
+ * Checks that the webhooks of --notify get the events of a scan of one
+ * open localhost port, each POSTed to the URL of its kind, and that failed
+ * deliveries are summed up in a single warning.
+ */
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Accepts HTTP requests until `expected` were received or a few seconds
+/// went by, answering 204 to each, and returns their paths and bodies.
+fn serve(listener: TcpListener, expected: usize) -> thread::JoinHandle<Vec<(String, String)>> {
+    listener.set_nonblocking(true).unwrap();
+    thread::spawn(move || {
+        let mut requests = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while requests.len() < expected && Instant::now() < deadline {
+            let Ok((stream, _)) = listener.accept() else {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+            stream.set_nonblocking(false).unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line.split_whitespace().nth(1).unwrap().to_owned();
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            requests.push((path, String::from_utf8(body).unwrap()));
+        }
+        requests
+    })
+}
+
+#[test]
+fn events_are_posted() {
+    let (_open, port) = common::listener();
+    let hooks = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", hooks.local_addr().unwrap());
+    let server = serve(hooks, 3);
+
+    let output = common::rustscan()
+        .args(["-n", "-g", "-a", "127.0.0.1", "-p", &port.to_string()])
+        .arg("--notify")
+        .arg(format!("open-port={url}/open"))
+        .arg("--notify")
+        .arg(format!("host-complete={url}/host"))
+        .arg("--notify")
+        .arg(format!("scan-complete={url}/scan"))
+        .args(["--run-id", "acme-0424"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let mut events: Vec<(String, serde_json::Value)> = server
+        .join()
+        .unwrap()
+        .into_iter()
+        .map(|(path, body)| (path, serde_json::from_str(&body).unwrap()))
+        .collect();
+    // Every event goes over a connection of its own, they may land in any order.
+    events.sort_by_key(|(path, _)| path.clone());
+    let paths: Vec<&str> = events.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["/host", "/open", "/scan"], "{:?}", events);
+
+    let (host, open, scan) = (&events[0].1, &events[1].1, &events[2].1);
+    assert_eq!(open["event"], "open-port");
+    assert_eq!(open["ip"], "127.0.0.1");
+    assert_eq!(open["port"], port);
+    assert!(open["time"].as_str().unwrap().ends_with('Z'));
+
+    assert_eq!(host["event"], "host-complete");
+    assert_eq!(host["open_ports"], serde_json::json!([port]));
+
+    assert_eq!(scan["event"], "scan-complete");
+    assert_eq!(scan["hosts"], 1);
+    assert_eq!(scan["open_ports"], 1);
+    assert_eq!(scan["dropped_events"], 0);
+    for event in [host, open, scan] {
+        assert_eq!(event["run_id"], "acme-0424");
+    }
+    // Nothing failed, nothing to warn about.
+    assert!(output.stderr.is_empty(), "{:?}", output);
+}
+
+#[test]
+fn failures_are_summed_up() {
+    let (_open, port) = common::listener();
+    let port = port.to_string();
+    let url = format!("http://127.0.0.1:{}/scan", common::closed_port());
+
+    let output = common::rustscan()
+        .args(["-n", "-g", "-a", "127.0.0.1", "-p", &port])
+        .arg("--notify")
+        .arg(format!("scan-complete={url}"))
+        .args(["--errors-format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let warnings: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(warnings.len(), 1, "{}", stderr);
+    assert_eq!(warnings[0]["code"], "notify-failed");
+    let message = warnings[0]["message"].as_str().unwrap();
+    assert!(
+        message.contains(&format!("1 failed for {url}")),
+        "{}",
+        message
+    );
+}
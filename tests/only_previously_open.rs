@@ -0,0 +1,76 @@
+/*
+ * Checks that --only-previously-open probes exactly the ports a fabricated
+ * previous report found open, counting the connections every listener
+ * accepts.
+ */
+mod common;
+
+#[test]
+fn only_the_previously_open_ports_are_probed() {
+    let (still_open, open_port) = common::listener();
+    let (not_previously_open, other_port) = common::listener();
+    // Open in the previous results, closed since.
+    let closed_port = common::listener().1;
+
+    let previous =
+        std::env::temp_dir().join(format!("rustscan-previous-{}.json", std::process::id()));
+    std::fs::write(
+        &previous,
+        format!(
+            r#"{{"hosts": [
+                {{"ip": "127.0.0.1", "source": ["127.0.0.1"], "ports": [{open_port}, {closed_port}], "scanned": true}},
+                {{"ip": "127.0.0.2", "source": ["127.0.0.2"], "ports": [], "scanned": true}}
+            ]}}"#
+        ),
+    )
+    .unwrap();
+
+    // The host of the report is added to the empty --addresses.
+    let ports = format!("{open_port},{other_port},{closed_port}");
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--format",
+            "json",
+            "-p",
+            &ports,
+            "--only-previously-open",
+        ])
+        .arg(&previous)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let hosts = report["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 1, "{:?}", report);
+    assert_eq!(hosts[0]["ip"], "127.0.0.1");
+    assert_eq!(hosts[0]["ports"], serde_json::json!([open_port]));
+    assert_eq!(hosts[0]["closed_since"], serde_json::json!([closed_port]));
+    assert_eq!(common::probes(&still_open), 1);
+    assert_eq!(common::probes(&not_previously_open), 0);
+
+    // Only the hosts given with --addresses are rescanned.
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--accessible",
+            "-a",
+            "127.0.0.2",
+            "--targets-from-file-only",
+        ])
+        .args(["--scripts", "none", "--only-previously-open"])
+        .arg(&previous)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("127.0.0.2 had no open ports in"),
+        "{:?}",
+        stderr
+    );
+    assert_eq!(common::probes(&still_open), 0);
+
+    let _ = std::fs::remove_file(&previous);
+}
@@ -0,0 +1,123 @@
+/*
+ * Checks that --output-split writes the report of --output-file in parts,
+ * each a whole report under its threshold, and an index listing the parts
+ * with the hosts each holds, every host in a single part.
+ */
+mod common;
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HOSTS: [&str; 6] = [
+    "127.0.0.1",
+    "127.0.0.2",
+    "127.0.0.3",
+    "127.0.0.4",
+    "127.0.0.5",
+    "127.0.0.6",
+];
+
+// Scans HOSTS with the report split by `split` into a directory of its own,
+// giving the directory, the index and the parts it lists.
+fn split_scan(split: &str) -> (PathBuf, Value, Vec<(String, Value)>) {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let dir = std::env::temp_dir().join(format!(
+        "rustscan-split-{}-{}",
+        std::process::id(),
+        split.replace('=', "-")
+    ));
+    fs::create_dir_all(&dir).unwrap();
+
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--format",
+            "json",
+            "-p",
+            &port,
+            "-a",
+            &HOSTS.join(","),
+        ])
+        .arg("--output-file")
+        .arg(dir.join("scan.json"))
+        .args(["--output-split", split])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!dir.join("scan.json").exists());
+
+    let index = read(&dir.join("scan.index.json"));
+    let parts = index["parts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|part| {
+            let file = part["file"].as_str().unwrap().to_owned();
+            let report = read(&dir.join(&file));
+            (file, report)
+        })
+        .collect();
+    (dir, index, parts)
+}
+
+fn read(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+// Checks that the index lists the hosts of every part, and every host once.
+fn assert_consistent(index: &Value, parts: &[(String, Value)]) {
+    let mut all = Vec::new();
+    for (number, (file, report)) in parts.iter().enumerate() {
+        assert_eq!(file, &format!("scan.{:04}.json", number + 1));
+        assert_eq!(report["part"]["index"], number + 1);
+        assert_eq!(report["part"]["count"], parts.len());
+        let hosts: Vec<Value> = report["hosts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|host| host["ip"].clone())
+            .collect();
+        assert_eq!(index["parts"][number]["hosts"], Value::from(hosts.clone()));
+        all.extend(hosts);
+    }
+    assert_eq!(
+        all,
+        HOSTS
+            .iter()
+            .map(|&host| Value::from(host))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn report_is_split_by_hosts() {
+    let (dir, index, parts) = split_scan("hosts=4");
+    assert_eq!(index["split"], "hosts=4");
+    assert_eq!(parts.len(), 2);
+    assert_consistent(&index, &parts);
+    assert_eq!(parts[0].1["hosts"][0]["ports"].as_array().unwrap().len(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn report_is_split_by_size() {
+    let (dir, index, parts) = split_scan("size=1KB");
+    assert!(parts.len() > 1, "{}", index);
+    assert_consistent(&index, &parts);
+    for (file, _) in &parts {
+        let size = fs::metadata(dir.join(file)).unwrap().len();
+        assert!(size <= 1024, "{} is {} bytes", file, size);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn split_needs_an_output_file() {
+    let output = common::rustscan()
+        .args(["-a", "127.0.0.1", "--output-split", "hosts=2"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
@@ -0,0 +1,187 @@
+/*
+ * Checks that --pipeline-scripts starts the scripts of the hosts scanned
+ * first while the scan of the others goes on, that the cap of
+ * --max-children still holds for them, and that their results are told
+ * and reported in the same order as without pipelining. The fixture sleeps
+ * and logs how many of its runs run at once to a file, from a HOME of its
+ * own.
+ */
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+const CAP: usize = 2;
+
+struct Run {
+    output: Output,
+    /// The events of --trace-file.
+    trace: Vec<serde_json::Value>,
+    report: serde_json::Value,
+    /// How many scripts ran at once, as seen by every run.
+    seen: Vec<usize>,
+}
+
+fn home(name: &str) -> PathBuf {
+    let home =
+        std::env::temp_dir().join(format!("rustscan-pipeline-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+    let scripts = home.join(".rustscan_scripts");
+    std::fs::create_dir_all(&scripts).unwrap();
+    std::fs::write(
+        home.join(".rustscan_scripts.toml"),
+        "tags = [\"pipeline\"]\n",
+    )
+    .unwrap();
+    std::fs::copy("fixtures/pipeline/sleep.sh", scripts.join("sleep.sh")).unwrap();
+    home
+}
+
+fn read(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_default()
+}
+
+/// Scans six hosts with an open port, and a seventh whose closed port is
+/// tried again for two seconds.
+fn rustscan(name: &str, port: u16, args: &[&str]) -> Run {
+    let home = home(name);
+    let addresses = format!(
+        "{};127.0.0.9=1",
+        (1..=6)
+            .map(|host| format!("127.0.0.{host}"))
+            .collect::<Vec<String>>()
+            .join(",")
+    );
+    let output = common::rustscan()
+        .args([
+            "--accessible",
+            "--scripts",
+            "custom",
+            "--forward-script-output",
+        ])
+        .args(["-a", &addresses, "-p", &port.to_string()])
+        .args(["--tries", "3", "--spread-tries", "2s"])
+        .args(["--max-children", &CAP.to_string()])
+        .arg("--trace-file")
+        .arg(home.join("trace.jsonl"))
+        .arg("--output-file")
+        .arg(home.join("report.json"))
+        .args(args)
+        .env("HOME", &home)
+        .env("PIPELINE_DIR", &home)
+        .output()
+        .unwrap();
+
+    let run = Run {
+        output,
+        trace: read(&home.join("trace.jsonl"))
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect(),
+        report: serde_json::from_str(&read(&home.join("report.json")))
+            .unwrap_or(serde_json::Value::Null),
+        seen: read(&home.join("seen.log"))
+            .lines()
+            .map(|line| line.split(' ').nth(1).unwrap().parse().unwrap())
+            .collect(),
+    };
+    let _ = std::fs::remove_dir_all(&home);
+    run
+}
+
+/// The microseconds of the first `event` of the trace.
+fn first(trace: &[serde_json::Value], event: &str) -> u64 {
+    trace
+        .iter()
+        .find(|line| line["event"] == event)
+        .and_then(|line| line["ts_us"].as_u64())
+        .unwrap_or_else(|| panic!("{:?}", trace))
+}
+
+/// The script output lines of stdout, in order.
+fn scripted(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("scripted"))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The hosts of the report, with their ports and the outputs of their
+/// scripts.
+fn hosts(report: &serde_json::Value) -> Vec<serde_json::Value> {
+    report["hosts"]
+        .as_array()
+        .unwrap_or_else(|| panic!("{:?}", report))
+        .iter()
+        .map(|host| {
+            serde_json::json!({
+                "ip": host["ip"],
+                "ports": host["ports"],
+                "scripts": host["scripts"]
+                    .as_array()
+                    .map(|runs| runs.iter().map(|run| run["output"].clone()).collect())
+                    .unwrap_or_else(Vec::<serde_json::Value>::new),
+            })
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn scripts_overlap_the_scan_in_order() {
+    let (_listener, port) = common::listener_on(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 1);
+    let port = port[0];
+    let expected: Vec<String> = (1..=6)
+        .map(|host| format!("scripted 127.0.0.{host} {port}"))
+        .collect();
+
+    let pipelined = rustscan(
+        "pipelined",
+        port,
+        &["--pipeline-scripts", "--script-concurrency", "4"],
+    );
+    assert!(pipelined.output.status.success(), "{:?}", pipelined.output);
+    // The first scripts started while the slow host was still scanned.
+    assert!(
+        first(&pipelined.trace, "script-started") < first(&pipelined.trace, "batch-finished"),
+        "{:?}",
+        pipelined.trace
+    );
+    assert_eq!(pipelined.seen.len(), 6);
+    assert!(
+        pipelined.seen.iter().all(|&running| running <= CAP),
+        "{:?}",
+        pipelined.seen
+    );
+    assert_eq!(scripted(&pipelined.output), expected);
+
+    let sequential = rustscan("sequential", port, &[]);
+    assert!(
+        sequential.output.status.success(),
+        "{:?}",
+        sequential.output
+    );
+    assert!(
+        first(&sequential.trace, "script-started") > first(&sequential.trace, "batch-finished")
+    );
+    assert_eq!(scripted(&sequential.output), expected);
+    assert_eq!(hosts(&pipelined.report), hosts(&sequential.report));
+    assert_eq!(hosts(&pipelined.report).len(), 7);
+}
+
+#[test]
+fn the_concurrency_needs_the_pipeline() {
+    let output = common::rustscan()
+        .args([
+            "--no-config",
+            "-a",
+            "127.0.0.1",
+            "--script-concurrency",
+            "2",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+}
@@ -0,0 +1,52 @@
+/*
+ * Checks that --port-hook runs its command once per matching open port, the
+ * echo fixture writing what it was given to a file, and that every run and
+ * the file it printed end up in the JSON report.
+ */
+mod common;
+
+#[test]
+fn hooks_run_per_matching_port() {
+    let (_listener, mut ports) = common::listener_on(common::LOCALHOST, 2);
+    // The ports of a host are reported in ascending order.
+    ports.sort_unstable();
+    let dir = std::env::temp_dir().join(format!("rustscan-port-hooks-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let echo = format!(
+        "name=echo ports={} cmd=\"sh fixtures/port_hooks/echo.sh {} {{{{ip}}}} {{{{port}}}} {{{{scheme}}}}\"",
+        ports[0],
+        dir.display()
+    );
+    let output = common::rustscan()
+        .args(["-a", "127.0.0.1", "--format", "json", "-p"])
+        .arg(format!("{},{}", ports[0], ports[1]))
+        .args(["--port-hook", &echo])
+        .args(["--port-hook", "name=every cmd='echo {{port}}'"])
+        // Without --probe-all no port has a hint.
+        .args(["--port-hook", "name=ssh tag=ssh cmd=true"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let runs = report["hosts"][0]["port_hooks"].as_array().unwrap();
+    let ran: Vec<(&str, u64)> = runs
+        .iter()
+        .map(|run| (run["hook"].as_str().unwrap(), run["port"].as_u64().unwrap()))
+        .collect();
+    let (first, second) = (u64::from(ports[0]), u64::from(ports[1]));
+    assert_eq!(ran, [("echo", first), ("every", first), ("every", second)]);
+    assert!(runs.iter().all(|run| run["exit_code"] == 0), "{:?}", runs);
+
+    let written = dir.join(format!("127.0.0.1-{}.txt", ports[0]));
+    assert_eq!(runs[0]["output_path"], written.to_str().unwrap());
+    assert_eq!(
+        std::fs::read_to_string(&written).unwrap(),
+        format!("{} 127.0.0.1 {} http\n", dir.display(), ports[0])
+    );
+    // What isn't a file is no output path.
+    assert_eq!(runs[1].get("output_path"), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
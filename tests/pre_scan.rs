@@ -0,0 +1,104 @@
+/*
+ * Checks that the scripts of the pre phase run against the host before it's
+ * scanned, in the order of their file names and before the scripts of the
+ * post phase, and that a failing one drops the host with on_fail = "skip"
+ * or only warns otherwise. The fixtures are installed in a HOME of their own
+ * and log their runs to a file.
+ */
+mod common;
+
+use std::path::PathBuf;
+use std::process::Output;
+
+struct Run {
+    output: Output,
+    log: String,
+    /// Whether the scan connected to the open port.
+    probed: bool,
+}
+
+fn rustscan(name: &str, env: &[(&str, &str)]) -> Run {
+    let home: PathBuf =
+        std::env::temp_dir().join(format!("rustscan-pre-scan-{name}-{}", std::process::id()));
+    let scripts = home.join(".rustscan_scripts");
+    std::fs::create_dir_all(&scripts).unwrap();
+    std::fs::write(
+        home.join(".rustscan_scripts.toml"),
+        "tags = [\"pre_scan\"]\n",
+    )
+    .unwrap();
+    for fixture in ["10-audit.sh", "20-route.sh", "30-report.sh"] {
+        std::fs::copy(
+            format!("fixtures/pre_scan/{fixture}"),
+            scripts.join(fixture),
+        )
+        .unwrap();
+    }
+    let log = home.join("runs.log");
+
+    let (listener, port) = common::listener();
+    let output = common::rustscan()
+        .args([
+            "-a",
+            "127.0.0.1",
+            "--accessible",
+            "--scripts",
+            "custom",
+            "-p",
+        ])
+        .arg(port.to_string())
+        .env("HOME", &home)
+        .env("PRE_SCAN_LOG", &log)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    let probed = common::probes(&listener) > 0;
+
+    let log = std::fs::read_to_string(&log).unwrap_or_default();
+    let _ = std::fs::remove_dir_all(&home);
+    Run {
+        output,
+        log: log.replace(&port.to_string(), "PORT"),
+        probed,
+    }
+}
+
+#[test]
+fn pre_scripts_run_before_the_scan_in_order() {
+    let run = rustscan("order", &[]);
+    assert!(run.output.status.success(), "{:?}", run.output);
+    assert!(run.probed);
+    assert_eq!(
+        run.log,
+        "pre audit 127.0.0.1 1\npre route 127.0.0.1 1\npost report 127.0.0.1 PORT\n"
+    );
+}
+
+#[test]
+fn failed_pre_script_skips_the_host() {
+    let run = rustscan("skip", &[("ROUTE_EXIT", "1")]);
+    assert!(!run.probed);
+    assert_eq!(run.log, "pre audit 127.0.0.1 1\npre route 127.0.0.1 1\n");
+    let stderr = String::from_utf8(run.output.stderr).unwrap();
+    assert!(
+        stderr.contains("20-route.sh failed on 127.0.0.1, skipping the host."),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn failed_pre_script_only_warns() {
+    let run = rustscan("warn", &[("AUDIT_EXIT", "1")]);
+    assert!(run.probed);
+    assert_eq!(
+        run.log,
+        "pre audit 127.0.0.1 1\npre route 127.0.0.1 1\npost report 127.0.0.1 PORT\n"
+    );
+    let stderr = String::from_utf8(run.output.stderr).unwrap();
+    assert!(
+        stderr.contains("10-audit.sh failed on 127.0.0.1, scanning anyway."),
+        "{}",
+        stderr
+    );
+}
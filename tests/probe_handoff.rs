@@ -0,0 +1,29 @@
+/*
+ * Checks that --probe-all guesses the service of an open port on the
+ * connection the scan found it open with, the port being connected to only
+ * once.
+ */
+mod common;
+
+use rustscan::listen::Answer;
+
+#[test]
+fn banner_port_is_connected_to_once() {
+    let (listener, port) = common::listener();
+    let banner = Answer::Banner("SSH-2.0-OpenSSH_9.6\r".to_owned());
+    let (output, served) = common::serving(&listener, &banner, || {
+        common::rustscan()
+            .args(["-a", "127.0.0.1", "--format", "json", "--probe-all", "-p"])
+            .arg(port.to_string())
+            .output()
+            .unwrap()
+    });
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let probes = &report["hosts"][0]["probes"];
+    assert_eq!(probes[0]["port"], port);
+    assert_eq!(probes[0]["service_guess"]["service"], "ssh");
+    assert_eq!(probes[0]["service_guess"]["probe"], "banner");
+    assert_eq!(served.total(), 1);
+}
@@ -0,0 +1,77 @@
+/*
+ * Checks that --reflector lets the scan go on when the bundled `rustscan
+ * reflector` sees the source ports as they were bound, and that a reflector
+ * seeing other ones, as behind a NAT, gets a warning, or aborts the scan
+ * with --strict-evasion.
+ */
+mod common;
+
+use rustscan::listen::Answer;
+use std::io::{BufRead, BufReader};
+use std::process::{Output, Stdio};
+
+fn scan(reflector: &str, args: &[&str]) -> Output {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    common::rustscan()
+        .args([
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &port,
+            "--scripts",
+            "none",
+            "--accessible",
+        ])
+        .args(["--randomize-source-ports", "--reflector", reflector])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn bundled_reflector_sees_the_bound_ports() {
+    let mut reflector = common::rustscan()
+        .args(["--accessible", "reflector", "--listen", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(reflector.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap().to_owned();
+
+    let output = scan(&address, &["--strict-evasion"]);
+    let _ = reflector.kill();
+    let _ = reflector.wait();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("rewrites"), "{}", stderr);
+}
+
+#[test]
+fn rewritten_ports_are_reported() {
+    // Every source port comes out as 1, like behind a NAT.
+    let (nat, port) = common::listener();
+    let address = format!("127.0.0.1:{port}");
+    let answer = Answer::Banner("127.0.0.1:1".to_owned());
+    let (output, _) = common::serving(&nat, &answer, || scan(&address, &[]));
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "A NAT rewrites the source ports before the reflector {address}"
+        )),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("came out as 127.0.0.1:1"), "{}", stderr);
+    assert!(stderr.contains("scanning anyway."), "{}", stderr);
+
+    let (output, _) = common::serving(&nat, &answer, || scan(&address, &["--strict-evasion"]));
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("aborting scan."), "{}", stderr);
+}
@@ -0,0 +1,68 @@
+/*
+ * Checks that --rescan-filtered probes exactly the ports a fabricated
+ * previous report found filtered, counting the connections every listener
+ * accepts, and that the JSON report combines the outcomes with the previous
+ * results, telling which run decided the state of every port.
+ */
+mod common;
+
+#[test]
+fn only_the_previously_filtered_ports_are_probed() {
+    let (previously_open, open_port) = common::listener();
+    let (opened, opened_port) = common::listener();
+    let (not_in_report, other_port) = common::listener();
+    // Filtered in the previous results, refused now.
+    let closed_port = common::listener().1;
+
+    let previous = std::env::temp_dir().join(format!(
+        "rustscan-rescan-filtered-{}.json",
+        std::process::id()
+    ));
+    std::fs::write(
+        &previous,
+        format!(
+            r#"{{"run_id": "before", "hosts": [
+                {{"ip": "127.0.0.1", "source": ["127.0.0.1"], "ports": [{open_port}], "filtered": [{opened_port}, {closed_port}], "scanned": true}},
+                {{"ip": "127.0.0.2", "source": ["127.0.0.2"], "ports": [80], "scanned": true}}
+            ]}}"#
+        ),
+    )
+    .unwrap();
+
+    let output = common::rustscan()
+        .args(["-n", "--format", "json", "--run-id", "after"])
+        .args(["-p", &other_port.to_string(), "--rescan-filtered"])
+        .arg(&previous)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&previous);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(common::probes(&opened), 1);
+    assert_eq!(common::probes(&previously_open), 0);
+    assert_eq!(common::probes(&not_in_report), 0);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let hosts = report["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 2, "{:?}", report);
+    assert_eq!(hosts[0]["ip"], "127.0.0.1");
+    let mut ports = vec![open_port, opened_port];
+    ports.sort_unstable();
+    assert_eq!(hosts[0]["ports"], serde_json::json!(ports));
+    let mut provenance = vec![
+        serde_json::json!({"port": open_port, "state": "open", "rescanned": false, "run_id": "before"}),
+        serde_json::json!({"port": opened_port, "state": "open", "rescanned": true, "run_id": "after"}),
+        serde_json::json!({"port": closed_port, "state": "closed", "rescanned": true, "run_id": "after"}),
+    ];
+    provenance.sort_by_key(|port| port["port"].as_u64());
+    assert_eq!(hosts[0]["provenance"], serde_json::json!(provenance));
+
+    // The host without filtered ports is kept as it was, without a probe.
+    assert_eq!(hosts[1]["ip"], "127.0.0.2");
+    assert_eq!(hosts[1]["scanned"], false);
+    assert_eq!(hosts[1]["skipped_reason"], "nothing-filtered");
+    assert_eq!(hosts[1]["ports"], serde_json::json!([80]));
+    assert_eq!(
+        hosts[1]["provenance"],
+        serde_json::json!([{"port": 80, "state": "open", "rescanned": false, "run_id": "before"}])
+    );
+}
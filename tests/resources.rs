@@ -0,0 +1,43 @@
+/*
+ * Checks that --resource-report puts the resources the process used in the
+ * stats of the JSON report after a localhost scan, and that a run without
+ * it has no stats.
+ */
+mod common;
+
+fn json_stats(args: &[&str]) -> serde_json::Value {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let output = common::rustscan()
+        .args(["-a", "127.0.0.1", "-p", &port, "--format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    report["stats"].clone()
+}
+
+#[test]
+fn resources_are_in_the_stats() {
+    let stats = json_stats(&["--resource-report"]);
+    assert_eq!(stats["sockets"], 1);
+    assert_eq!(stats["open_ports"], 1);
+    assert_eq!(stats.get("shards"), None);
+
+    let resources = &stats["resources"];
+    for field in [
+        "peak_open_descriptors",
+        "peak_rss_bytes",
+        "user_cpu_ms",
+        "system_cpu_ms",
+    ] {
+        assert!(resources.get(field).is_some(), "{} in {}", field, resources);
+        #[cfg(target_os = "linux")]
+        assert!(resources[field].is_u64(), "{} in {}", field, resources);
+    }
+    // One when the sampler starts, one when it's finished.
+    assert!(resources["samples"].as_u64().unwrap() >= 2, "{}", resources);
+
+    assert_eq!(json_stats(&[]), serde_json::Value::Null);
+}
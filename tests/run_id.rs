@@ -0,0 +1,51 @@
+/*
+ * Checks that the run id of --run-id, or the one generated without it, is
+ * in the JSON report, and in front of the greppable lines with
+ * --greppable-prefix.
+ */
+mod common;
+
+fn scan(args: &[&str]) -> String {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let output = common::rustscan()
+        .args(["-a", "127.0.0.1", "-p", &port])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn json_run_id(args: &[&str]) -> String {
+    let mut args = args.to_vec();
+    args.extend(["--format", "json"]);
+    let json: serde_json::Value = serde_json::from_str(&scan(&args)).unwrap();
+    json["run_id"].as_str().unwrap().to_owned()
+}
+
+#[test]
+fn run_id_is_in_the_json_report() {
+    assert_eq!(json_run_id(&["--run-id", "acme-0424"]), "acme-0424");
+
+    // A UUID otherwise, another one every run.
+    let generated = json_run_id(&[]);
+    assert_eq!(generated.len(), 36);
+    assert_ne!(json_run_id(&[]), generated);
+}
+
+#[test]
+fn greppable_lines_get_the_prefix() {
+    let stdout = scan(&[
+        "-g",
+        "--run-id",
+        "acme-0424",
+        "--greppable-prefix",
+        "{run_id}\t",
+    ]);
+    assert!(
+        stdout.starts_with("acme-0424\t127.0.0.1 -> ["),
+        "{}",
+        stdout
+    );
+}
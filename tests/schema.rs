@@ -0,0 +1,51 @@
+/*
+ * Checks that the JSON report gives its schema_version, that --schema
+ * prints the JSON Schema of that version and that --schema-version refuses
+ * the versions which aren't written.
+ */
+mod common;
+
+#[test]
+fn report_gives_its_schema_version() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let output = common::run(&[
+        "-a",
+        "127.0.0.1",
+        "-p",
+        &port,
+        "--format",
+        "json",
+        "--schema-version",
+        "1",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["schema_version"], 1);
+}
+
+#[test]
+fn schema_is_printed() {
+    let output = common::run(&["--schema"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(schema["$defs"]["SchemaVersion"]["const"], 1);
+    assert_eq!(
+        schema["$defs"]["ScanReport"]["required"],
+        serde_json::json!(["schema_version", "mode", "hosts"])
+    );
+}
+
+#[test]
+fn unwritten_versions_are_refused() {
+    let output = common::run(&["--schema", "--schema-version", "2"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Only the schema version 1 is written, not 2."),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,66 @@
+/*
+ * Checks that --scope keeps the scan inside the allowlist: out-of-scope
+ * targets abort the run before any probe, or are skipped with
+ * --scope-mode warn. The connections the listener accepts are counted.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(addresses: &str, port: u16, scope_mode: &str) -> Output {
+    let scope = std::env::temp_dir().join(format!("rustscan-scope-{}.txt", std::process::id()));
+    std::fs::write(&scope, "# Engagement\n127.0.0.1/32\n").unwrap();
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-a", addresses])
+        .args([
+            "-p",
+            &port.to_string(),
+            "--scope-mode",
+            scope_mode,
+            "--scope",
+        ])
+        .arg(&scope)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&scope);
+    output
+}
+
+#[test]
+fn scans_stay_inside_the_scope() {
+    let (listener, port) = common::listener();
+
+    for mode in ["enforce", "warn"] {
+        let output = rustscan("127.0.0.1", port, mode);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(common::probes(&listener), 1, "{mode}");
+    }
+
+    // A target outside the scope aborts the run before any probe.
+    let output = rustscan("127.0.0.1,127.0.0.2/31", port, "enforce");
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("2 target(s) outside the scope")
+            && stderr.contains("127.0.0.3 (from 127.0.0.2/31)"),
+        "{:?}",
+        stderr
+    );
+    assert_eq!(common::probes(&listener), 0);
+
+    // Only the targets inside the scope are scanned with a warning.
+    let output = rustscan("127.0.0.1,127.0.0.3", port, "warn");
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("127.0.0.3 (from 127.0.0.3) is outside the scope"),
+        "{:?}",
+        stderr
+    );
+    assert_eq!(common::probes(&listener), 1);
+
+    // Nothing is left to scan.
+    let output = rustscan("127.0.0.3", port, "warn");
+    assert!(!output.status.success(), "{:?}", output);
+    assert_eq!(common::probes(&listener), 0);
+}
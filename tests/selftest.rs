@@ -0,0 +1,42 @@
+/*
+ * Checks that `rustscan selftest` finds a listening port of localhost open
+ * and a free one closed, and that its exit status reflects the checks.
+ */
+
+mod common;
+
+#[test]
+fn open_and_closed_ports_pass() {
+    let output = common::rustscan()
+        .args(["--accessible", "selftest", "--name", "localhost"])
+        .args(["--max-batch-size", "128"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let line = |name: &str| {
+        stdout
+            .lines()
+            .find(|line| line.contains(&format!("] {name}: ")))
+            .unwrap_or_else(|| panic!("no {} check in {:?}", name, stdout))
+            .to_owned()
+    };
+    for name in [
+        "open port",
+        "closed port",
+        "concurrency",
+        "resolver",
+        "scripts",
+        "nmap",
+    ] {
+        line(name);
+    }
+    assert!(line("open port").starts_with("[PASS]"), "{:?}", stdout);
+    assert!(line("closed port").starts_with("[PASS]"), "{:?}", stdout);
+    assert!(line("resolver").starts_with("[PASS]"), "{:?}", stdout);
+
+    // Missing nmap or scripts only fail the run, with a hint each.
+    let failed = stdout.matches("[FAIL]").count();
+    assert_eq!(stdout.matches("hint: ").count(), failed);
+    assert_eq!(output.status.success(), failed == 0, "{:?}", stdout);
+}
@@ -0,0 +1,95 @@
+/*
+ * Checks that --shard splits a scan between runs: two shards probe every
+ * socket of the unsharded scan exactly once between them, and `rustscan
+ * merge` combines their reports. The connections the listeners accept are
+ * counted.
+ */
+mod common;
+
+use rustscan::listen::Listener;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::process::Output;
+
+/// The open ports of the only host of `report`, and its stats.
+fn results(output: &Output) -> (BTreeSet<u64>, serde_json::Value) {
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let ports = report["hosts"][0]["ports"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|port| port.as_u64().unwrap())
+        .collect();
+    (ports, report["stats"].clone())
+}
+
+#[test]
+fn shards_split_the_scan() {
+    let (listeners, ports): (Vec<Listener>, Vec<u16>) = (0..8).map(|_| common::listener()).unzip();
+    let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+    let ports = ports.join(",");
+    let scan = |shard: &str| {
+        common::run(&[
+            "-n",
+            "--format",
+            "json",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &ports,
+            "--seed",
+            "130",
+            "--shard",
+            shard,
+        ])
+    };
+
+    let files: Vec<PathBuf> = ["1/2", "2/2"]
+        .iter()
+        .map(|shard| {
+            std::env::temp_dir().join(format!(
+                "rustscan-shard-{}-{}.json",
+                std::process::id(),
+                &shard[..1]
+            ))
+        })
+        .collect();
+    let mut shards = Vec::new();
+    for (shard, file) in ["1/2", "2/2"].iter().zip(&files) {
+        let output = scan(shard);
+        std::fs::write(file, &output.stdout).unwrap();
+        shards.push(results(&output));
+    }
+
+    // Every socket of the unsharded scan is probed by exactly one shard.
+    for listener in &listeners {
+        assert_eq!(common::probes(listener), 1, "{:?}", listener.local_addrs());
+    }
+    let all: BTreeSet<u64> = ports.split(',').map(|port| port.parse().unwrap()).collect();
+    let (first, second) = (&shards[0].0, &shards[1].0);
+    assert!(first.is_disjoint(second), "{:?} {:?}", first, second);
+    assert_eq!(&(first | second), &all);
+    assert_eq!(shards[0].1["shards"], serde_json::json!(["1/2"]));
+    assert_eq!(
+        shards[0].1["sockets"].as_u64().unwrap() + shards[1].1["sockets"].as_u64().unwrap(),
+        8
+    );
+
+    // The same seed gives the same split.
+    assert_eq!(results(&scan("1/2")).0, *first);
+    common::probes(&listeners[0]);
+
+    let mut args = vec!["merge"];
+    args.extend(files.iter().map(|file| file.to_str().unwrap()));
+    let output = common::run(&args);
+    for file in &files {
+        let _ = std::fs::remove_file(file);
+    }
+    let (merged, stats) = results(&output);
+    assert_eq!(merged, all);
+    assert_eq!(
+        stats,
+        serde_json::json!({"shards": ["1/2", "2/2"], "sockets": 8, "open_ports": 8})
+    );
+}
@@ -0,0 +1,96 @@
+/*
+ * Checks that the reports of --sign-key runs verify with the public key of
+ * `rustscan keygen`, and stop verifying once a port is changed, and that
+ * keys of the wrong kind are refused with a message saying so.
+ */
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+fn rustscan(args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["--accessible", "--no-config", "-t", "500"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// An empty folder for the keys and reports of `name`.
+fn folder(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "rustscan-signing-test-{}-{name}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn signed_reports_verify_until_changed() {
+    let dir = folder("verify");
+    let key = dir.join("key");
+    let output = rustscan(&["keygen", path(&key)]);
+    assert!(output.status.success(), "{:?}", output);
+    let public = dir.join("key.pub");
+    assert!(public.exists());
+
+    let (_listener, port) = common::listener();
+    let output = rustscan(&[
+        "-a",
+        "127.0.0.1",
+        "-p",
+        &port.to_string(),
+        "--format",
+        "json",
+        "--sign-key",
+        path(&key),
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let report = dir.join("report.json");
+    std::fs::write(&report, &output.stdout).unwrap();
+
+    let output = rustscan(&["verify", path(&report), "--pubkey", path(&public)]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is signed by"));
+
+    let mut tampered: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(tampered["hosts"][0]["ports"], serde_json::json!([port]));
+    tampered["hosts"][0]["ports"][0] = serde_json::json!(port.wrapping_add(1));
+    std::fs::write(&report, tampered.to_string()).unwrap();
+    let output = rustscan(&["verify", path(&report), "--pubkey", path(&public)]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("it was changed after it was signed"),
+        "{:?}",
+        output
+    );
+}
+
+#[test]
+fn public_keys_dont_sign() {
+    let dir = folder("kinds");
+    let key = dir.join("key");
+    assert!(rustscan(&["keygen", path(&key)]).status.success());
+    let output = rustscan(&[
+        "-a",
+        "127.0.0.1",
+        "--output-file",
+        path(&dir.join("report.json")),
+        "--sign-key",
+        path(&dir.join("key.pub")),
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("it holds a PUBLIC KEY, not a PRIVATE KEY"),
+        "{:?}",
+        output
+    );
+}
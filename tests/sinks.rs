@@ -0,0 +1,162 @@
+/*
+ * Checks that a sink of a library user gets every call of a real scan of
+ * localhost in order, from the scanner and then from the report, and that
+ * a failing sink only stops the scan when it is critical.
+ */
+mod common;
+
+use async_std::task::block_on;
+use rustscan::address::Target;
+use rustscan::input::{HostOrder, PortRange, ScanOrder};
+use rustscan::port_strategy::PortStrategy;
+use rustscan::report::{HostReport, ScanReport};
+use rustscan::scanner::Scanner;
+use rustscan::sink::{OutputSink, PortEvent, ScanEnd, ScanStart};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Writes down every call it gets, failing its port events when `failing`.
+struct Recorder {
+    calls: Arc<Mutex<Vec<String>>>,
+    failing: bool,
+}
+
+impl Recorder {
+    fn new(failing: bool) -> (Self, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Self {
+            calls: calls.clone(),
+            failing,
+        };
+        (recorder, calls)
+    }
+
+    fn record(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl OutputSink for Recorder {
+    fn name(&self) -> String {
+        "recorder".to_owned()
+    }
+
+    fn started(&mut self, start: &ScanStart) -> anyhow::Result<()> {
+        self.record(format!("started {:?}", start.hosts));
+        Ok(())
+    }
+
+    fn port(&mut self, event: &PortEvent) -> anyhow::Result<()> {
+        self.record(match event {
+            PortEvent::Open { socket, .. } => format!("open {socket}"),
+            PortEvent::Probed(socket) => format!("probed {socket}"),
+            other => format!("{:?}", other),
+        });
+        if self.failing {
+            anyhow::bail!("the database is gone");
+        }
+        Ok(())
+    }
+
+    fn host(&mut self, host: &HostReport) -> anyhow::Result<()> {
+        self.record(format!("host {} {:?}", host.ip, host.ports));
+        Ok(())
+    }
+
+    fn finished(&mut self, end: &ScanEnd) -> anyhow::Result<()> {
+        self.record(format!("finished {} {}", end.hosts, end.open_ports));
+        Ok(())
+    }
+}
+
+fn localhost_scanner(ip: IpAddr, ports: (u16, u16)) -> Scanner {
+    Scanner::new(
+        &[ip],
+        1,
+        Duration::from_millis(500),
+        1,
+        true,
+        PortStrategy::pick(
+            &Some(PortRange {
+                ranges: vec![ports],
+            }),
+            None,
+            ScanOrder::Serial,
+        ),
+        true,
+        vec![],
+        false,
+    )
+}
+
+#[test]
+fn sink_gets_the_whole_run_in_order() {
+    let (_listener, port) = common::listener();
+    let open = SocketAddr::new(common::LOCALHOST, port);
+    let ip = open.ip();
+    let closed = SocketAddr::new(ip, open.port() - 1);
+    let (recorder, calls) = Recorder::new(false);
+    let scanner = localhost_scanner(ip, (closed.port(), open.port())).with_sink(recorder);
+
+    let outcome = block_on(scanner.scan());
+    assert_eq!(outcome.open, [open]);
+    let target = Target {
+        ip,
+        hostnames: Vec::new(),
+        sources: vec![ip.to_string()],
+        ports: None,
+    };
+    let report = ScanReport::new(&[target], &outcome.open, HostOrder::Input);
+    let sinks = scanner.sinks();
+    for host in &report.hosts {
+        sinks.host(host);
+    }
+    sinks.finished(&ScanEnd::of(&report));
+
+    assert!(sinks.failures().is_empty());
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [
+            format!("started [({ip}, 2)]"),
+            format!("probed {closed}"),
+            format!("open {open}"),
+            format!("probed {open}"),
+            format!("host {ip} [{}]", open.port()),
+            "finished 1 1".to_owned(),
+        ]
+    );
+}
+
+#[test]
+fn only_critical_sinks_stop_the_scan() {
+    let (_listener, port) = common::listener();
+    let open = SocketAddr::new(common::LOCALHOST, port);
+    let ports = (open.port() - 50, open.port());
+
+    let (recorder, _) = Recorder::new(true);
+    let scanner = localhost_scanner(open.ip(), ports).with_sink(recorder);
+    let outcome = block_on(scanner.scan());
+    assert_eq!(outcome.open, [open]);
+    assert_eq!(outcome.forecast.remaining, 0);
+    let failures = scanner.sinks().failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].count, 52);
+    assert!(!failures[0].critical);
+
+    let (recorder, _) = Recorder::new(true);
+    let scanner = localhost_scanner(open.ip(), ports).with_critical_sink(recorder);
+    let outcome = block_on(scanner.scan());
+    assert!(outcome.open.is_empty());
+    assert!(outcome.forecast.remaining > 0);
+    assert!(scanner.sinks().failed_critically());
+    let failures = scanner.sinks().failures();
+    assert!(failures[0].critical);
+    assert!(
+        failures[0]
+            .to_string()
+            .starts_with("recorder: the database is gone"),
+        "{:?}",
+        failures
+    );
+}
@@ -0,0 +1,103 @@
+/*
+ * Checks the communities of --snmp-probe against a local UDP socket which
+ * answers like an agent of the community "private" would, with a canned
+ * sysDescr, and stays silent to the others. Every community gets a single
+ * packet, and an agent which answers none of them costs one per community.
+ */
+use async_std::task::block_on;
+use rustscan::snmp::{SnmpFinding, SnmpProber, SnmpVersion};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SYS_DESCR: &[u8] = b"Linux router 5.10.0";
+
+/// The answer of an agent to `request`, read as `snmpget` sends it: the
+/// request id is echoed back in a canned GetResponse.
+fn respond(request: &[u8]) -> Vec<u8> {
+    let community_length = usize::from(request[6]);
+    let community = &request[7..7 + community_length];
+    // The PDU tag and length, then the request id.
+    let id_start = 7 + community_length + 2;
+    let id_length = usize::from(request[id_start + 1]);
+    let request_id = &request[id_start..id_start + 2 + id_length];
+
+    let tlv = |tag: u8, value: &[u8]| [&[tag, value.len() as u8][..], value].concat();
+    let oid = [0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00];
+    let varbind = tlv(0x30, &[tlv(0x06, &oid), tlv(0x04, SYS_DESCR)].concat());
+    let pdu = [
+        request_id,
+        &[0x02, 0x01, 0x00, 0x02, 0x01, 0x00],
+        &tlv(0x30, &varbind),
+    ]
+    .concat();
+    let message = [
+        &[0x02, 0x01, request[4]][..],
+        &tlv(0x04, community),
+        &tlv(0xa2, &pdu),
+    ]
+    .concat();
+    tlv(0x30, &message)
+}
+
+/// An agent of `community`, telling the communities it was sent.
+fn agent(community: &'static [u8]) -> (SocketAddr, mpsc::Receiver<Vec<u8>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 1500];
+        while let Ok((read, peer)) = socket.recv_from(&mut buffer) {
+            let request = &buffer[..read];
+            let sent = request[7..7 + usize::from(request[6])].to_vec();
+            let answers = sent == community;
+            if sender.send(sent).is_err() {
+                break;
+            }
+            if answers {
+                socket.send_to(&respond(request), peer).unwrap();
+            }
+        }
+    });
+    (address, received)
+}
+
+#[test]
+fn the_community_which_works_is_found() {
+    let (address, received) = agent(b"private");
+    let prober = SnmpProber::new(Duration::from_millis(300))
+        .with_communities(&["private".to_owned(), "monitoring".to_owned()]);
+    let findings = block_on(prober.probe_all(&[address], 4));
+    assert_eq!(
+        findings.get(&address),
+        Some(&SnmpFinding {
+            version: SnmpVersion::V2c,
+            community: "private".to_owned(),
+            sys_descr: Some("Linux router 5.10.0".to_owned()),
+        })
+    );
+    // public went unanswered, and the probe stopped at private.
+    let sent: Vec<Vec<u8>> = received.try_iter().collect();
+    assert_eq!(sent, [b"public".to_vec(), b"private".to_vec()]);
+}
+
+#[test]
+fn silent_agents_cost_a_packet_per_community() {
+    let (address, received) = agent(b"secret");
+    let prober = SnmpProber::new(Duration::from_millis(200))
+        .with_version(SnmpVersion::V1)
+        .with_communities(&["monitoring".to_owned()]);
+    let started = Instant::now();
+    assert_eq!(block_on(prober.probe(address)), None);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    let sent: Vec<Vec<u8>> = received.try_iter().collect();
+    assert_eq!(
+        sent,
+        [
+            b"public".to_vec(),
+            b"private".to_vec(),
+            b"monitoring".to_vec()
+        ]
+    );
+}
@@ -0,0 +1,53 @@
+/*
+ * Checks that the sockets of the pool are all closed once the scan is over,
+ * and when it's cancelled halfway, by counting the descriptors in
+ * /proc/self/fd. The only test of this binary, so that no other one opens
+ * descriptors meanwhile.
+ */
+
+mod common;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pooled_sockets_are_closed() {
+    use async_std::future::timeout;
+    use async_std::task::block_on;
+    use rustscan::input::{PortRange, ScanOrder};
+    use rustscan::port_strategy::PortStrategy;
+    use rustscan::scanner::Scanner;
+    use std::fs;
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    let descriptors = || fs::read_dir("/proc/self/fd").unwrap().count();
+    let (_listener, port) = common::listener();
+    let scanner = |start: u16, end: u16| {
+        let range = PortRange {
+            ranges: vec![(start, end)],
+        };
+        Scanner::new(
+            &["127.0.0.1".parse::<IpAddr>().unwrap()],
+            200,
+            Duration::from_millis(500),
+            1,
+            true,
+            PortStrategy::pick(&Some(range), None, ScanOrder::Serial),
+            true,
+            vec![],
+            false,
+        )
+    };
+    let start = port.saturating_sub(1000).max(1);
+    // The runtime opens its own descriptors with the first scan.
+    block_on(scanner(start, port).run());
+    let before = descriptors();
+
+    let open = block_on(scanner(start, port).with_socket_pool().run());
+    assert_eq!(open, [SocketAddr::from(([127, 0, 0, 1], port))]);
+    assert_eq!(descriptors(), before);
+
+    let scan = scanner(1, 65535).with_socket_pool();
+    let cancelled = block_on(timeout(Duration::from_millis(20), scan.scan()));
+    assert!(cancelled.is_err(), "the scan is over in 20ms");
+    assert_eq!(descriptors(), before);
+}
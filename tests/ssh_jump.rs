@@ -0,0 +1,91 @@
+/*
+ * Runs --ssh-jump against a stand-in ssh which plays the bastion: it
+ * confirms, refuses or leaves pending the channels by port, and denies the
+ * destinations starting with denied@.
+ */
+
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+fn scan(destination: &str, args: &[&str], log: &Path) -> Output {
+    let shim = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/ssh/bin");
+    let mut path = vec![shim];
+    path.extend(env::split_paths(&env::var_os("PATH").unwrap_or_default()));
+
+    common::rustscan()
+        .args([
+            "--ssh-jump",
+            destination,
+            "--timeout",
+            "500",
+            "--tries",
+            "1",
+        ])
+        .args(args)
+        .env("PATH", env::join_paths(path).unwrap())
+        .env("FAKE_SSH_OPEN", "22")
+        .env("FAKE_SSH_FILTERED", "23")
+        .env("FAKE_SSH_LOG", log)
+        .output()
+        .unwrap()
+}
+
+#[test]
+#[cfg(unix)]
+fn probes_go_through_the_bastion() {
+    let log = env::temp_dir().join(format!("rustscan-ssh-jump-{}.log", std::process::id()));
+    let output = scan(
+        "scan@bastion",
+        &["-g", "-a", "10.9.8.7,2001:db8::7", "-p", "22,23,24"],
+        &log,
+    );
+    let targets = fs::read_to_string(&log).unwrap_or_default();
+    let _ = fs::remove_file(&log);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["10.9.8.7 -> [22]", "2001:db8::7 -> [22]"]);
+
+    // Every socket went to the bastion, IPv6 ones in brackets.
+    let mut targets: Vec<&str> = targets.lines().collect();
+    targets.sort_unstable();
+    assert_eq!(
+        targets,
+        [
+            "10.9.8.7:22",
+            "10.9.8.7:23",
+            "10.9.8.7:24",
+            "[2001:db8::7]:22",
+            "[2001:db8::7]:23",
+            "[2001:db8::7]:24",
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn denied_bastions_abort_the_scan() {
+    let log = env::temp_dir().join(format!("rustscan-ssh-denied-{}.log", std::process::id()));
+    let output = scan(
+        "denied@bastion",
+        &["--accessible", "-a", "10.9.8.7", "-p", "22"],
+        &log,
+    );
+    // Nothing was probed.
+    assert!(!log.exists());
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "Can't connect to denied@bastion: denied@bastion: Permission denied (publickey)."
+        ),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,47 @@
+/*
+ * Checks that the hosts of a --timeout-map group are reported with the
+ * timeout and tries of their group, and that malformed maps are refused.
+ */
+mod common;
+
+#[test]
+fn host_reports_its_timeout_group() {
+    let (_listener, port) = common::listener();
+    let output = common::rustscan()
+        .args([
+            "-n",
+            "--format",
+            "json",
+            "-a",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+        ])
+        .args([
+            "--timeout-map",
+            "lan=10.0.0.0/8:50,lo=127.0.0.0/8:300:2,default:1000",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let host = &report["hosts"][0];
+    assert_eq!(host["ports"], serde_json::json!([port]));
+    assert_eq!(
+        host["timeout"],
+        serde_json::json!({"group": "lo", "timeout_ms": 300, "tries": 2})
+    );
+}
+
+#[test]
+fn malformed_map_is_refused() {
+    let output = common::rustscan()
+        .args(["-n", "-a", "127.0.0.1", "-p", "1"])
+        .args(["--timeout-map", "lan=10.0.0.0/8:300,wan=10.0.0.0/8:900"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("the same network"), "{}", stderr);
+}
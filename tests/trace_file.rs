@@ -0,0 +1,73 @@
+/*
+ * Checks that --trace-file records a small localhost scan: the batch, then
+ * the launch and the resolution of every probe, in order and with timestamps
+ * which never go back, and the last line counting the dropped events.
+ */
+mod common;
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[test]
+fn trace_of_a_localhost_scan() {
+    let (_listener, open) = common::listener();
+    let closed = common::closed_port();
+    let path = std::env::temp_dir().join(format!("rustscan-trace-{}.jsonl", std::process::id()));
+
+    let output = common::rustscan()
+        .args(["-g", "-a", "127.0.0.1", "-p", &format!("{open},{closed}")])
+        .arg("--trace-file")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let trace = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines: Vec<Value> = trace
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let stamps: Vec<u64> = lines
+        .iter()
+        .map(|line| line["ts_us"].as_u64().unwrap())
+        .collect();
+    assert!(
+        stamps.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{}",
+        trace
+    );
+    let position = |event: &str, socket: Option<&str>| {
+        lines
+            .iter()
+            .position(|line| {
+                line["event"] == event && socket.is_none_or(|socket| line["socket"] == socket)
+            })
+            .unwrap_or_else(|| panic!("{}", trace))
+    };
+
+    let started = position("batch-started", None);
+    assert_eq!(lines[started]["hosts"], 1);
+    assert_eq!(lines[started]["sockets"], 2);
+    let finished = position("batch-finished", None);
+    assert_eq!(lines[finished]["open"], 1);
+    let mut states = HashMap::new();
+    for port in [open, closed] {
+        let socket = format!("127.0.0.1:{port}");
+        let launched = position("probe-launched", Some(&socket));
+        let resolved = position("probe-resolved", Some(&socket));
+        assert!(
+            started < launched && launched < resolved && resolved < finished,
+            "{}",
+            trace
+        );
+        assert_eq!(lines[resolved]["tries"], 1);
+        states.insert(port, lines[resolved]["state"].clone());
+    }
+    assert_eq!(states[&open], "open");
+    assert_eq!(states[&closed], "refused");
+
+    let last = lines.last().unwrap();
+    assert_eq!(last["event"], "trace-finished", "{}", trace);
+    assert_eq!(last["dropped"], 0);
+}
@@ -0,0 +1,42 @@
+/*
+ * Checks that --tui refuses to take over a stdout which isn't a terminal,
+ * before anything is probed, and that --output-file writes the JSON report
+ * the dashboard would otherwise leave nowhere to print.
+ */
+mod common;
+
+#[test]
+fn tui_needs_a_terminal() {
+    let (listener, port) = common::listener();
+    let port = port.to_string();
+
+    let output = common::rustscan()
+        .args(["-n", "--tui", "-a", "127.0.0.1", "-p", &port])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+    let printed = String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+    assert!(printed.contains("--output-file"), "{}", printed);
+    assert_eq!(common::probes(&listener), 0, "the port was probed");
+}
+
+#[test]
+fn output_file_gets_the_report() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+    let file = std::env::temp_dir().join(format!("rustscan-report-{}.json", std::process::id()));
+
+    let output = common::rustscan()
+        .args(["-n", "--format", "json", "-a", "127.0.0.1", "-p", &port])
+        .arg("--output-file")
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(report["hosts"][0]["ports"][0].to_string(), port);
+    // The report went to the file, not to stdout.
+    assert!(serde_json::from_slice::<serde_json::Value>(&output.stdout).is_err());
+}
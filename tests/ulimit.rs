@@ -0,0 +1,38 @@
+/*
+ * Checks that a batch size above the soft file limit raises the limit
+ * instead of lowering the batch size.
+ */
+
+mod common;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn soft_limit_raised_for_batch_size() {
+    use rlimit::Resource;
+
+    let (_, hard) = Resource::NOFILE.get().unwrap();
+    if hard < 4_096 {
+        eprintln!("Hard file limit {hard} too low to raise the soft limit, skipping");
+        return;
+    }
+    // Inherited by rustscan, this is the only test of this binary.
+    Resource::NOFILE.set(512, hard).unwrap();
+
+    let (_listener, port) = common::listener();
+    let output = common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-a", "127.0.0.1"])
+        .args(["-p", &port.to_string(), "-b", "2000"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(
+        stderr.contains("Automatically increasing ulimit value to 2"),
+        "{:?}",
+        stderr
+    );
+    assert!(!stderr.contains("Lowered the batch size"), "{:?}", stderr);
+    assert!(stdout.contains(&format!("127.0.0.1 -> [{port}]")));
+}
@@ -0,0 +1,39 @@
+/*
+ * Checks that targets which don't resolve leave the rest of the scan
+ * running: they are listed in the JSON report, and only fail the run with
+ * --strict-resolution.
+ */
+mod common;
+
+use std::process::Output;
+
+fn rustscan(port: &str, extra: &[&str]) -> Output {
+    common::rustscan()
+        .args(["-n", "--format", "json", "-p", port])
+        .args(["-a", "127.0.0.1,nosuch.invalid"])
+        // A run id of its own would tell the runs apart.
+        .args(["--run-id", "unresolved"])
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn unresolved_targets_are_reported() {
+    let (_listener, port) = common::listener();
+    let port = port.to_string();
+
+    let output = rustscan(&port, &[]);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["hosts"][0]["ip"], "127.0.0.1");
+    assert_eq!(report["hosts"][0]["ports"][0].to_string(), port);
+    assert_eq!(report["unresolved"][0]["name"], "nosuch.invalid");
+    assert!(report["unresolved"][0]["error"].is_string(), "{:?}", report);
+
+    // The results are the same, only the exit code changes.
+    let output = rustscan(&port, &["--strict-resolution"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let strict: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(strict, report);
+}
@@ -0,0 +1,100 @@
+/*
+ * Checks the exact output of a small localhost scan at every verbosity level,
+ * the results on stdout and the details on stderr.
+ */
+
+mod common;
+
+use std::process::Output;
+
+fn run_rustscan(port: u16, args: &[&str]) -> Output {
+    common::rustscan()
+        .args(["-n", "--accessible", "--scripts", "none", "-a", "127.0.0.1"])
+        .args(["-p", &port.to_string()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs a scan which doesn't trigger any batch size warning, returns what
+/// it printed on stdout and on stderr.
+fn printed(port: u16, args: &[&str]) -> (String, String) {
+    let (soft, _) = rlimit::Resource::NOFILE.get().unwrap();
+    let ulimit = soft.to_string();
+    let output = run_rustscan(port, &[&["-b", "10", "-u", &ulimit], args].concat());
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// The configuration fingerprint of the scans of `printed`.
+fn fingerprint(port: u16) -> String {
+    let (soft, _) = rlimit::Resource::NOFILE.get().unwrap();
+    let ulimit = soft.to_string();
+    let output = run_rustscan(port, &["-b", "10", "-u", &ulimit, "--dry-run"]);
+    let printed = String::from_utf8(output.stdout).unwrap();
+    let line = printed.lines().last().unwrap();
+    line.strip_prefix("Fingerprint: ").unwrap().to_owned()
+}
+
+#[test]
+fn output_at_each_level() {
+    let (_listener, port) = common::listener();
+    let (soft, _) = rlimit::Resource::NOFILE.get().unwrap();
+
+    let results = format!("Open 127.0.0.1:{port}\n127.0.0.1 -> [{port}]\n");
+    assert_eq!(
+        printed(port, &["--quiet"]),
+        (format!("127.0.0.1 -> [{port}]\n"), String::new())
+    );
+    assert_eq!(
+        printed(port, &[]),
+        (
+            results.clone(),
+            format!("Automatically increasing ulimit value to {soft}.\n")
+        )
+    );
+    assert_eq!(
+        printed(port, &["--verbose"]),
+        (
+            results,
+            format!(
+                "Automatically increasing ulimit value to {soft}.\n\
+                 Scanning 1 host(s) with a batch size of 10\n\
+                 Configuration fingerprint: {}\n",
+                fingerprint(port)
+            )
+        )
+    );
+    assert_eq!(
+        printed(port, &["--quiet", "--no-results"]),
+        (String::new(), String::new())
+    );
+}
+
+#[test]
+fn quiet_warnings_go_to_stderr() {
+    let (_listener, port) = common::listener();
+
+    // A batch size above the file limit always warns.
+    let output = run_rustscan(port, &["--quiet", "-b", "200", "-u", "100"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("127.0.0.1 -> [{port}]\n")
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("File limit is lower than default batch size."));
+
+    let output = run_rustscan(
+        port,
+        &["--quiet", "--silence-warnings", "-b", "200", "-u", "100"],
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("127.0.0.1 -> [{port}]\n")
+    );
+    assert!(output.stderr.is_empty());
+}
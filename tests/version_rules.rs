@@ -0,0 +1,118 @@
+/*
+ * Checks that --probe-rules finds the product and version of fixture
+ * servers emitting canned greetings and answers, and puts them in the
+ * services of the JSON report.
+ */
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Output;
+use std::thread;
+
+/// Serves every connection of a local listener with `serve`, its port
+/// returned.
+fn fixture(serve: fn(TcpStream)) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    port
+}
+
+fn rules_file(name: &str, rules: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rustscan-{name}-{}.toml", std::process::id()));
+    std::fs::write(&path, rules).unwrap();
+    path
+}
+
+fn scan(args: &[&str], rules: &PathBuf) -> Output {
+    let output = common::rustscan()
+        .args(args)
+        .args(["-a", "127.0.0.1", "--probe-rules"])
+        .arg(rules)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(rules);
+    output
+}
+
+#[test]
+fn greetings_and_answers_are_matched() {
+    let greeting = fixture(|mut stream| {
+        let _ = stream.write_all(b"220 fixture.local ESMTP Fixture 2.7.1 ready\r\n");
+        thread::sleep(std::time::Duration::from_millis(500));
+    });
+    let answer = fixture(|mut stream| {
+        let mut request = [0; 64];
+        let read = stream.read(&mut request).unwrap_or(0);
+        if request[..read] == *b"\x00\x01VERSION\r\n" {
+            let _ = stream.write_all(b"+OK cachebox v3.4.0\r\n");
+        }
+    });
+    let silent = fixture(|stream| {
+        thread::sleep(std::time::Duration::from_millis(500));
+        drop(stream);
+    });
+    let rules = rules_file(
+        "version-rules",
+        &format!(
+            r#"
+            [[rule]]
+            name = "fixture-smtp"
+            ports = "{greeting}"
+            match = '^220 \S+ ESMTP (?P<product>\w+) (?P<version>[\d.]+)'
+            service = "smtp"
+            timeout = 500
+
+            [[rule]]
+            name = "cachebox"
+            ports = "{answer},{silent}"
+            probe_hex = "00 01 56 45 52 53 49 4f 4e 0d 0a"
+            match = '^\+OK (?P<product>cachebox) v(?P<version>\S+)'
+            service = "cache"
+            timeout = 300
+            "#
+        ),
+    );
+
+    let ports = format!("{greeting},{answer},{silent}");
+    let output = scan(&["-n", "--format", "json", "-p", &ports], &rules);
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let services = report["hosts"][0]["services"].as_array().unwrap();
+    let service = |port: u16| services.iter().find(|service| service["port"] == port);
+    assert_eq!(services.len(), 2, "{:?}", report);
+    assert_eq!(
+        service(greeting),
+        Some(&serde_json::json!({
+            "port": greeting, "protocol": "tcp", "service": "smtp",
+            "product": "Fixture", "version": "2.7.1"
+        }))
+    );
+    assert_eq!(
+        service(answer),
+        Some(&serde_json::json!({
+            "port": answer, "protocol": "tcp", "service": "cache",
+            "product": "cachebox", "version": "3.4.0"
+        }))
+    );
+    assert_eq!(service(silent), None);
+}
+
+#[test]
+fn broken_rules_abort_the_run() {
+    let rules = rules_file(
+        "broken-rules",
+        "[[rule]]\nname = \"broken\"\nports = \"80\"\nmatch = '(?P<version'\nservice = \"x\"\n",
+    );
+
+    let output = scan(&["--accessible", "-p", "80"], &rules);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("rule \"broken\""), "{}", stderr);
+}